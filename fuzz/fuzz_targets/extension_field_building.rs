@@ -0,0 +1,56 @@
+#![no_main]
+
+use std::{borrow::Cow, io::Cursor};
+
+use libfuzzer_sys::fuzz_target;
+use ntp_proto::{ExtensionField, NoCipher, NtpPacket, PollInterval};
+
+fn serialize(packet: &NtpPacket, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut cursor = Cursor::new(buf);
+    packet.serialize(&mut cursor, &NoCipher, None)?;
+    Ok(cursor.position() as usize)
+}
+
+fuzz_target!(|input: (bool, Vec<(u16, Vec<u8>)>)| {
+    let (v5, fields) = input;
+
+    let (mut packet, _) = if v5 {
+        NtpPacket::poll_message_v5(PollInterval::default())
+    } else {
+        NtpPacket::poll_message(PollInterval::default())
+    };
+
+    for (type_id, data) in fields {
+        packet.push_additional(ExtensionField::Unknown {
+            type_id,
+            data: Cow::Owned(data),
+        });
+    }
+
+    let mut buf = [0u8; 4096 * 4];
+    let mut buf2 = [0u8; 4096 * 4];
+
+    // Building may fail on oversized fields, but must not panic
+    let Ok(used) = serialize(&packet, &mut buf) else {
+        return;
+    };
+    let serialized = &buf[..used];
+
+    // Arbitrary type ids can collide with known fields, which may reject or
+    // normalize their contents. Fields that stay unknown must encode back to
+    // exactly the same bytes.
+    if let Ok((parsed, _)) = NtpPacket::deserialize(serialized, &NoCipher) {
+        // NTPv4 strips the padding of draft identifications, so only the
+        // NTPv5 ones are preserved byte for byte.
+        let only_unknown = parsed.untrusted_extension_fields().all(|ef| match ef {
+            ExtensionField::Unknown { .. } => true,
+            ExtensionField::DraftIdentification(_) => v5,
+            _ => false,
+        });
+
+        if only_unknown {
+            let used = serialize(&parsed, &mut buf2).unwrap();
+            assert_eq!(serialized, &buf2[..used]);
+        }
+    }
+});
@@ -2,9 +2,17 @@ use crate::{packet::NtpLeapIndicator, time_types::NtpDuration};
 
 use super::{SourceSnapshot, config::AlgorithmConfig, source::KalmanState};
 
+/// When some of the selected sources are preferred, the uncertainty of the
+/// other sources is scaled up by this factor before combining. This weights
+/// the preferred sources up, without overstating the accuracy of the result.
+const UNPREFERRED_UNCERTAINTY_SCALE: f64 = 4.0;
+
 pub(super) struct Combine<Index: Copy> {
     pub estimate: KalmanState,
     pub sources: Vec<Index>,
+    /// Share of every source in the combined offset, by the inverse of the
+    /// variance of its offset
+    pub weights: Vec<(Index, f64)>,
     pub delay: NtpDuration,
     pub leap_indicator: Option<NtpLeapIndicator>,
 }
@@ -36,37 +44,103 @@ fn vote_leap<Index: Copy>(selection: &[SourceSnapshot<Index>]) -> Option<NtpLeap
     }
 }
 
+/// Scale up the uncertainty of the estimates that would get more than
+/// `max_weight` of the combined offset, such that they get exactly that.
+/// When there are too few estimates to stay below it, all get equal weight.
+fn cap_weights(estimates: &mut [KalmanState], max_weight: f64) {
+    if estimates.len() < 2 || max_weight >= 1.0 {
+        return;
+    }
+
+    let inverse: Vec<f64> = estimates
+        .iter()
+        .map(|estimate| 1.0 / estimate.offset_variance())
+        .collect();
+    let mut capped = vec![false; estimates.len()];
+    // the total weight the uncapped estimates are divided by, capping an
+    // estimate lowers it, which can push other estimates over the cap
+    let total = loop {
+        let count = capped.iter().filter(|capped| **capped).count();
+        let remaining = 1.0 - count as f64 * max_weight;
+        if count == estimates.len() || remaining <= 0.0 {
+            break None;
+        }
+        let uncapped: f64 = inverse
+            .iter()
+            .zip(&capped)
+            .filter(|(_, capped)| !**capped)
+            .map(|(inverse, _)| inverse)
+            .sum();
+        let total = uncapped / remaining;
+
+        let mut changed = false;
+        for (inverse, capped) in inverse.iter().zip(&mut capped) {
+            if !*capped && inverse / total > max_weight {
+                *capped = true;
+                changed = true;
+            }
+        }
+        if !changed {
+            break Some(total);
+        }
+    };
+
+    let lowest = inverse.iter().copied().fold(f64::INFINITY, f64::min);
+    for ((estimate, inverse), capped) in estimates.iter_mut().zip(&inverse).zip(&capped) {
+        let target = match total {
+            Some(total) if *capped => max_weight * total,
+            Some(_) => continue,
+            None => lowest,
+        };
+        estimate.uncertainty = (inverse / target) * estimate.uncertainty;
+    }
+}
+
 pub(super) fn combine<Index: Copy>(
     selection: &[SourceSnapshot<Index>],
     algo_config: &AlgorithmConfig,
 ) -> Option<Combine<Index>> {
-    selection.first().map(|first| {
-        let mut estimate = first.state;
-        if !algo_config.ignore_server_dispersion {
-            estimate = estimate.add_server_dispersion(first.source_uncertainty.to_seconds())
+    let any_preferred = selection.iter().any(|snapshot| snapshot.prefer);
+    let estimate_of = |snapshot: &SourceSnapshot<Index>| {
+        let mut estimate = if algo_config.ignore_server_dispersion {
+            snapshot.state
+        } else {
+            snapshot
+                .state
+                .add_server_dispersion(snapshot.source_uncertainty.to_seconds())
+        };
+        if any_preferred && !snapshot.prefer {
+            estimate.uncertainty = UNPREFERRED_UNCERTAINTY_SCALE * estimate.uncertainty;
         }
+        estimate
+    };
 
-        let mut used_sources = vec![(first.index, estimate.uncertainty.determinant())];
+    let mut estimates: Vec<_> = selection.iter().map(estimate_of).collect();
+    cap_weights(&mut estimates, algo_config.maximum_source_weight);
 
-        for snapshot in selection.iter().skip(1) {
-            let source_estimate = if algo_config.ignore_server_dispersion {
-                snapshot.state
-            } else {
-                snapshot
-                    .state
-                    .add_server_dispersion(snapshot.source_uncertainty.to_seconds())
-            };
+    selection.first().map(|first| {
+        let mut estimate = estimates[0];
 
+        let mut used_sources = vec![(first.index, estimate.uncertainty.determinant())];
+        let mut weights = vec![(first.index, 1.0 / estimate.offset_variance())];
+
+        for (snapshot, source_estimate) in selection.iter().zip(&estimates).skip(1) {
             used_sources.push((snapshot.index, source_estimate.uncertainty.determinant()));
+            weights.push((snapshot.index, 1.0 / source_estimate.offset_variance()));
 
-            estimate = estimate.merge(&source_estimate);
+            estimate = estimate.merge(source_estimate);
         }
 
         used_sources.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let total_weight: f64 = weights.iter().map(|(_, weight)| weight).sum();
+        for (_, weight) in &mut weights {
+            *weight /= total_weight;
+        }
 
         Combine {
             estimate,
             sources: used_sources.iter().map(|v| v.0).collect(),
+            weights,
             delay: selection
                 .iter()
                 .map(|v| NtpDuration::from_seconds(v.delay) + v.source_delay)
@@ -108,6 +182,9 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.01),
             leap_indicator: NtpLeapIndicator::NoWarning,
             last_update: NtpTimestamp::from_fixed_int(0),
+            trust: false,
+            prefer: false,
+            noselect: false,
         }
     }
 
@@ -220,6 +297,95 @@ mod tests {
         assert_eq!(result.sources, vec![1, 0]);
     }
 
+    #[test]
+    fn test_prefer() {
+        let mut selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([1e-3, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                1e-3,
+            ),
+        ];
+        selected[0].index = 0;
+        selected[1].index = 1;
+
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig).unwrap();
+        assert!((result.estimate.offset() - 0.5e-3).abs() < 1e-9);
+        assert!((result.weights[0].1 - 0.5).abs() < 1e-9);
+
+        selected[1].prefer = true;
+        let result = combine(&selected, &algconfig).unwrap();
+        assert!((result.estimate.offset() - 0.8e-3).abs() < 1e-9);
+        assert_eq!(result.sources, vec![1, 0]);
+        assert_eq!(result.weights.len(), 2);
+        assert!((result.weights[0].1 - 0.2).abs() < 1e-9);
+        assert!((result.weights[1].1 - 0.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_maximum_source_weight() {
+        // an overconfident source far from two others that agree
+        let mut selected = vec![
+            snapshot_for_state(
+                Vector::new_vector([10e-3, 0.0]),
+                Matrix::new([[1e-8, 0.0], [0.0, 1e-12]]),
+                0.0,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                0.0,
+            ),
+            snapshot_for_state(
+                Vector::new_vector([0.0, 0.0]),
+                Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                0.0,
+            ),
+        ];
+        for (index, snapshot) in selected.iter_mut().enumerate() {
+            snapshot.index = index;
+        }
+
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig).unwrap();
+        assert!(result.weights[0].1 > 0.95);
+
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            maximum_source_weight: 0.5,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig).unwrap();
+        assert!((result.estimate.offset() - 5e-3).abs() < 1e-9);
+        assert!((result.weights[0].1 - 0.5).abs() < 1e-9);
+        assert!((result.weights[1].1 - 0.25).abs() < 1e-9);
+        assert!((result.weights[2].1 - 0.25).abs() < 1e-9);
+
+        // with too few sources to stay below the cap, all weigh the same
+        let algconfig = AlgorithmConfig {
+            ignore_server_dispersion: true,
+            maximum_source_weight: 0.25,
+            ..Default::default()
+        };
+        let result = combine(&selected, &algconfig).unwrap();
+        assert!((result.estimate.offset() - 10e-3 / 3.0).abs() < 1e-9);
+        for (_, weight) in &result.weights {
+            assert!((weight - 1.0 / 3.0).abs() < 1e-9);
+        }
+    }
+
     fn snapshot_for_leap(leap: NtpLeapIndicator) -> SourceSnapshot<usize> {
         SourceSnapshot {
             index: 0,
@@ -235,6 +401,9 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.0),
             leap_indicator: leap,
             last_update: NtpTimestamp::from_fixed_int(0),
+            trust: false,
+            prefer: false,
+            noselect: false,
         }
     }
 
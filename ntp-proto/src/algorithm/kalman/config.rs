@@ -1,6 +1,6 @@
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, de};
 
-use crate::time_types::NtpDuration;
+use crate::{duration::deserialize_seconds, time_types::NtpDuration};
 
 #[derive(Debug, Copy, Clone, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
@@ -44,6 +44,43 @@ pub struct AlgorithmConfig {
     /// are rejected. (standard deviations, 0+)
     #[serde(default = "default_delay_outlier_threshold")]
     pub delay_outlier_threshold: f64,
+    /// What to do with measurements whose delay is far above the
+    /// recent minimum delay of their source, before they reach the
+    /// filter.
+    #[serde(default)]
+    pub delay_filter: DelayFilter,
+    /// Ratio between the delay of a measurement and the recent
+    /// minimum delay above which the delay filter acts. (ratio, 1+)
+    #[serde(default = "default_delay_filter_ratio")]
+    pub delay_filter_ratio: f64,
+    /// Number of most recent measurements over which the minimum
+    /// delay is taken. (count, 1+)
+    #[serde(default = "default_delay_filter_window")]
+    pub delay_filter_window: usize,
+    /// Ratio between the minimum delay of the most recent measurements
+    /// of a source and that of its path so far beyond which the path is
+    /// considered to have changed, e.g. by rerouting. (ratio, 1+)
+    #[serde(
+        default = "default_path_change_ratio",
+        deserialize_with = "deserialize_path_change_ratio"
+    )]
+    pub path_change_ratio: f64,
+    /// How long after a change of the path to a source half the change
+    /// in delay is added to the uncertainty of its measurements, as the
+    /// asymmetry of the new path is unknown. 0 disables this. (seconds, 0+)
+    #[serde(default, deserialize_with = "deserialize_seconds")]
+    pub path_change_widening: f64,
+    /// Number of scaled median absolute deviations from the median
+    /// by which the offset of a measurement must differ from the
+    /// prediction of the filter, compared to recent measurements of
+    /// its source, to be rejected as a spike. 0 disables this.
+    /// (deviations, 0+)
+    #[serde(default)]
+    pub spike_filter_threshold: f64,
+    /// Number of most recent measurements the spike filter compares
+    /// a measurement with. (count, 5+)
+    #[serde(default = "default_spike_filter_window")]
+    pub spike_filter_window: usize,
 
     /// Initial estimate of the clock wander of the combination
     /// of our local clock and that of the source. (s/s^2)
@@ -57,7 +94,10 @@ pub struct AlgorithmConfig {
     /// Maximum source uncertainty before we start disregarding it
     /// Note that this is combined uncertainty due to noise and
     /// possible asymmetry error (see also weights below). (seconds)
-    #[serde(default = "default_maximum_source_uncertainty")]
+    #[serde(
+        default = "default_maximum_source_uncertainty",
+        deserialize_with = "deserialize_seconds"
+    )]
     pub maximum_source_uncertainty: f64,
     /// Weight of statistical uncertainty when constructing
     /// overlap ranges. (standard deviations, 0+)
@@ -86,14 +126,35 @@ pub struct AlgorithmConfig {
     pub steer_frequency_leftover: f64,
     /// From what offset should we step the clock instead of
     /// trying to adjust gradually? (seconds, 0+)
-    #[serde(default = "default_step_threshold")]
+    #[serde(
+        default = "default_step_threshold",
+        deserialize_with = "deserialize_seconds"
+    )]
     pub step_threshold: f64,
     /// What is the maximum frequency offset during a slew (s/s)
     #[serde(default = "default_slew_maximum_frequency_offset")]
     pub slew_maximum_frequency_offset: f64,
     /// What is the minimum duration of a slew (s)
-    #[serde(default = "default_slew_minimum_duration")]
+    #[serde(
+        default = "default_slew_minimum_duration",
+        deserialize_with = "deserialize_seconds"
+    )]
     pub slew_minimum_duration: f64,
+    /// Upper bound on the compensation for the delay between the
+    /// intended end of a slew and the kernel applying it (s)
+    #[serde(
+        default = "default_maximum_steer_latency",
+        deserialize_with = "deserialize_seconds"
+    )]
+    pub maximum_steer_latency: f64,
+    /// Shortest time between two updates of the clock. Measurements
+    /// arriving sooner are combined in a single update at the end of
+    /// the interval, 0 updates the clock on every measurement (s)
+    #[serde(
+        default = "default_steer_debounce_interval",
+        deserialize_with = "deserialize_seconds"
+    )]
+    pub steer_debounce_interval: f64,
 
     /// Absolute maximum frequency correction (s/s)
     #[serde(default = "default_maximum_frequency_steer")]
@@ -105,9 +166,38 @@ pub struct AlgorithmConfig {
     #[serde(default)]
     pub ignore_server_dispersion: bool,
 
+    /// Largest share of the combined offset any single source may
+    /// have. The uncertainty of sources that would get more is scaled
+    /// up when combining, limiting the damage a single overconfident
+    /// source can do. 1 disables the cap. (weight, 0-1)
+    #[serde(default = "default_maximum_source_weight")]
+    pub maximum_source_weight: f64,
+
     /// Threshold for detecting external clock meddling
     #[serde(default = "default_meddling_threshold")]
     pub meddling_threshold: NtpDuration,
+
+    /// How long before the current time of the filter of a source a
+    /// measurement may have been taken and still be used, e.g. when
+    /// it was in flight while the clock was steered (seconds, 0+)
+    #[serde(
+        default = "default_stale_measurement_horizon",
+        deserialize_with = "deserialize_stale_measurement_horizon"
+    )]
+    pub stale_measurement_horizon: f64,
+}
+
+/// Treatment of measurements delayed by congestion
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DelayFilter {
+    /// Pass all measurements on to the filter
+    #[default]
+    Off,
+    /// Drop congested measurements
+    Discard,
+    /// Add the excess delay as measurement noise
+    Inflate,
 }
 
 impl Default for AlgorithmConfig {
@@ -124,6 +214,13 @@ impl Default for AlgorithmConfig {
             poll_interval_step_threshold: default_poll_interval_step_threshold(),
 
             delay_outlier_threshold: default_delay_outlier_threshold(),
+            delay_filter: DelayFilter::default(),
+            delay_filter_ratio: default_delay_filter_ratio(),
+            delay_filter_window: default_delay_filter_window(),
+            path_change_ratio: default_path_change_ratio(),
+            path_change_widening: 0.0,
+            spike_filter_threshold: 0.0,
+            spike_filter_window: default_spike_filter_window(),
 
             initial_wander: default_initial_wander(),
             initial_frequency_uncertainty: default_initial_frequency_uncertainty(),
@@ -139,16 +236,25 @@ impl Default for AlgorithmConfig {
             step_threshold: default_step_threshold(),
             slew_maximum_frequency_offset: default_slew_maximum_frequency_offset(),
             slew_minimum_duration: default_slew_minimum_duration(),
+            maximum_steer_latency: default_maximum_steer_latency(),
+            steer_debounce_interval: default_steer_debounce_interval(),
 
             maximum_frequency_steer: default_maximum_frequency_steer(),
 
             ignore_server_dispersion: false,
+            maximum_source_weight: default_maximum_source_weight(),
 
             meddling_threshold: default_meddling_threshold(),
+
+            stale_measurement_horizon: default_stale_measurement_horizon(),
         }
     }
 }
 
+fn default_maximum_source_weight() -> f64 {
+    1.0
+}
+
 fn default_precision_low_probability() -> f64 {
     1. / 3.
 }
@@ -185,6 +291,35 @@ fn default_delay_outlier_threshold() -> f64 {
     5.
 }
 
+fn default_delay_filter_ratio() -> f64 {
+    3.
+}
+
+fn default_delay_filter_window() -> usize {
+    16
+}
+
+fn default_path_change_ratio() -> f64 {
+    1.5
+}
+
+fn deserialize_path_change_ratio<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f64, D::Error> {
+    let ratio = f64::deserialize(deserializer)?;
+    if !(ratio.is_finite() && ratio >= 1.0) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Float(ratio),
+            &"a ratio of at least 1",
+        ));
+    }
+    Ok(ratio)
+}
+
+fn default_spike_filter_window() -> usize {
+    9
+}
+
 fn default_initial_wander() -> f64 {
     1e-8
 }
@@ -237,6 +372,31 @@ fn default_slew_minimum_duration() -> f64 {
     8.0
 }
 
+fn default_maximum_steer_latency() -> f64 {
+    0.1
+}
+
+fn default_steer_debounce_interval() -> f64 {
+    0.0
+}
+
 fn default_meddling_threshold() -> NtpDuration {
     NtpDuration::from_seconds(5.)
 }
+
+fn default_stale_measurement_horizon() -> f64 {
+    1.
+}
+
+fn deserialize_stale_measurement_horizon<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f64, D::Error> {
+    let horizon = deserialize_seconds(deserializer)?;
+    if !(horizon.is_finite() && horizon >= 0.0) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Float(horizon),
+            &"a non-negative number of seconds",
+        ));
+    }
+    Ok(horizon)
+}
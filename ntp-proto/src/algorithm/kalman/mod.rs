@@ -1,20 +1,27 @@
-use std::{collections::HashMap, fmt::Debug, hash::Hash, time::Duration};
+use std::{collections::HashMap, fmt::Debug, hash::Hash, sync::Arc, time::Duration};
 
 pub(crate) use source::AveragingBuffer;
 use source::OneWayKalmanSourceController;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, debug_span, error, info, warn};
 
 use crate::{
     clock::NtpClock,
-    config::{SourceConfig, SynchronizationConfig},
+    config::{SourceConfig, StepPolicy, SynchronizationConfig},
     packet::NtpLeapIndicator,
-    system::TimeSnapshot,
+    system::{ClockAdjustment, TimeSnapshot},
     time_types::{NtpDuration, NtpTimestamp},
 };
 
-use self::{combiner::combine, config::AlgorithmConfig, source::KalmanState};
+use self::{
+    combiner::combine,
+    config::AlgorithmConfig,
+    source::{KalmanState, RetainedState},
+};
 
-use super::{ObservableSourceTimedata, StateUpdate, TimeSyncController};
+use super::{
+    CombinedEstimate, DecisionAction, DecisionCandidate, DecisionRecord, ObservableSourceTimedata,
+    SelectionStatus, StateUpdate, StepSettings, TimeSyncController,
+};
 
 mod combiner;
 pub(super) mod config;
@@ -24,6 +31,14 @@ mod source;
 
 pub use source::{KalmanSourceController, TwoWayKalmanSourceController};
 
+/// Time after a step during which the clock is still considered to be adjusting,
+/// covering requests that were in flight while the clock jumped
+const STEP_SETTLE_TIME: f64 = 1.0;
+
+/// Time after which the filter state left behind by a source is no longer
+/// used for a new source of the same peer
+const RETAINED_STATE_TIMEOUT: f64 = 86400.0;
+
 fn sqr(x: f64) -> f64 {
     x * x
 }
@@ -45,6 +60,11 @@ struct SourceSnapshot<Index: Copy> {
     leap_indicator: NtpLeapIndicator,
 
     last_update: NtpTimestamp,
+
+    // Selection options from the configuration of the source
+    trust: bool,
+    prefer: bool,
+    noselect: bool,
 }
 
 impl<Index: Copy> SourceSnapshot<Index> {
@@ -64,6 +84,11 @@ impl<Index: Copy> SourceSnapshot<Index> {
             remote_delay: self.source_delay,
             remote_uncertainty: self.source_uncertainty,
             last_update: self.last_update,
+            // counted by the filter of the source, not part of its snapshot
+            path_changes: 0,
+            spikes: 0,
+            offset_correction: NtpDuration::ZERO,
+            delay_correction: NtpDuration::ZERO,
         }
     }
 }
@@ -77,6 +102,7 @@ pub struct KalmanControllerMessage {
 enum KalmanControllerMessageInner {
     Step { steer: f64 },
     FreqChange { steer: f64, time: NtpTimestamp },
+    UpdateConfig { algo_config: AlgorithmConfig },
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -94,6 +120,27 @@ pub struct KalmanClockController<C: NtpClock, SourceId: Hash + Eq + Copy + Debug
     timedata: TimeSnapshot,
     desired_freq: f64,
     in_startup: bool,
+    // measurements received per source while in startup
+    startup_measurements: HashMap<SourceId, usize>,
+    steering_suspended: bool,
+    // largest offset to correct at once, only by slewing, while limited
+    steering_limit: Option<f64>,
+    // when the kernel applied the last frequency change
+    last_frequency_update: NtpTimestamp,
+    // start of the current slew as applied by the kernel, with its intended
+    // and requested durations
+    slew: Option<(NtpTimestamp, f64, f64)>,
+    // estimate of how late the kernel ends a slew compared to the requested time
+    steer_latency: f64,
+    // when the clock was last updated
+    last_clock_update: Option<NtpTimestamp>,
+    // time of the newest measurement not yet used to update the clock, while
+    // updates are debounced
+    pending_update: Option<NtpTimestamp>,
+    // outcome of the last source selection
+    selection: HashMap<SourceId, SelectionStatus>,
+    // filter state per peer name, kept across the sources of that peer
+    retained: HashMap<String, RetainedState<NtpDuration, AveragingBuffer>>,
 }
 
 impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, SourceId> {
@@ -113,6 +160,7 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 used_sources: None,
                 time_snapshot: Some(self.timedata),
                 next_update: None,
+                decision: None,
             };
         }
         for (_, (state, _)) in self.sources.iter_mut() {
@@ -124,21 +172,47 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
             }
         }
 
-        let selection = select::select(
-            &self.synchronization_config,
-            &self.algo_config,
-            self.sources
-                .iter()
-                .filter_map(
-                    |(_, (state, usable))| {
-                        if *usable { state.as_ref() } else { None }
-                    },
-                )
-                .cloned()
-                .collect(),
-        );
+        let select::Selection {
+            selected: selection,
+            rejected,
+        } = debug_span!("selection").in_scope(|| {
+            select::select(
+                &self.synchronization_config,
+                &self.algo_config,
+                self.sources
+                    .iter()
+                    .filter_map(
+                        |(_, (state, usable))| {
+                            if *usable { state.as_ref() } else { None }
+                        },
+                    )
+                    .cloned()
+                    .collect(),
+            )
+        });
+
+        self.selection = self
+            .sources
+            .iter()
+            .filter_map(|(id, (state, usable))| match (state, usable) {
+                (None, _) => Some((*id, SelectionStatus::NoMeasurement)),
+                (Some(_), false) => Some((*id, SelectionStatus::Unusable)),
+                (Some(_), true) => None,
+            })
+            .chain(rejected)
+            .collect();
 
         if let Some(combined) = combine(&selection, &self.algo_config) {
+            // the first of the combined sources is the one the system derives its stratum from
+            for (i, id) in combined.sources.iter().enumerate() {
+                let status = if i == 0 {
+                    SelectionStatus::Primary
+                } else {
+                    SelectionStatus::Selected
+                };
+                self.selection.insert(*id, status);
+            }
+
             info!(
                 "Offset: {}+-{}ms, frequency: {}+-{}ppm",
                 combined.estimate.offset() * 1e3,
@@ -157,7 +231,14 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
             let freq_uncertainty = combined.estimate.frequency_variance().sqrt();
             let offset_delta = combined.estimate.offset();
             let offset_uncertainty = combined.estimate.offset_variance().sqrt();
-            let next_update = if self.desired_freq == 0.0
+            let warming_up = self.in_startup && !self.warmup_complete(&selection);
+            let steer_span = debug_span!("steer", warming_up, suspended = self.steering_suspended);
+            let steer_guard = steer_span.enter();
+            let (next_update, action) = if self.steering_suspended {
+                (StateUpdate::default(), DecisionAction::Suspended)
+            } else if warming_up {
+                (StateUpdate::default(), DecisionAction::WarmingUp)
+            } else if self.desired_freq == 0.0
                 && offset_delta.abs() > offset_uncertainty * self.algo_config.steer_offset_threshold
             {
                 // Note: because of threshold effects, offset_delta is likely an extreme estimate
@@ -165,12 +246,13 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 // overcorrecting.
                 // The same does not apply to freq_delta, so if we start slewing
                 // it can be fully corrected without qualms.
-                self.steer_offset(
-                    offset_delta
-                        - offset_uncertainty
-                            * self.algo_config.steer_offset_leftover
-                            * offset_delta.signum(),
-                    freq_delta,
+                let change = offset_delta
+                    - offset_uncertainty
+                        * self.algo_config.steer_offset_leftover
+                        * offset_delta.signum();
+                (
+                    self.steer_offset(change, freq_delta),
+                    DecisionAction::Offset { change },
                 )
             } else if freq_delta.abs()
                 > freq_uncertainty * self.algo_config.steer_frequency_threshold
@@ -178,15 +260,18 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 // Note: because of threshold effects, freq_delta is likely an extreme estimate
                 // at this point. Hence we only correct it partially in order to avoid
                 // overcorrecting.
-                self.steer_frequency(
-                    freq_delta
-                        - freq_uncertainty
-                            * self.algo_config.steer_frequency_leftover
-                            * freq_delta.signum(),
+                let change = freq_delta
+                    - freq_uncertainty
+                        * self.algo_config.steer_frequency_leftover
+                        * freq_delta.signum();
+                (
+                    self.steer_frequency(change),
+                    DecisionAction::Frequency { change },
                 )
             } else {
-                StateUpdate::default()
+                (StateUpdate::default(), DecisionAction::None)
             };
+            drop(steer_guard);
 
             self.timedata.root_delay = combined.delay;
             self.timedata.root_variance_base_time = time;
@@ -212,23 +297,95 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 self.timedata.leap_indicator = leap;
             }
 
-            // After a successful measurement we are out of startup.
-            self.in_startup = false;
+            // After a successful measurement we are out of startup. While steering
+            // is suspended we have not acted on that measurement yet, so stay in
+            // startup to keep its stricter step limits for the first steer.
+            if !self.steering_suspended && !warming_up {
+                self.in_startup = false;
+                self.startup_measurements.clear();
+            }
 
+            let combined_estimate = CombinedEstimate {
+                offset: offset_delta,
+                offset_uncertainty,
+                frequency: combined.estimate.frequency(),
+                frequency_uncertainty: freq_uncertainty,
+            };
             StateUpdate {
                 used_sources: Some(combined.sources),
                 time_snapshot: Some(self.timedata),
+                decision: Some(self.decision(combined.weights, Some(combined_estimate), action)),
                 ..next_update
             }
         } else {
             info!("No consensus on current time");
             StateUpdate {
+                used_sources: Some(vec![]),
                 time_snapshot: Some(self.timedata),
+                decision: Some(self.decision(vec![], None, DecisionAction::NoConsensus)),
                 ..StateUpdate::default()
             }
         }
     }
 
+    /// Record of an update of the clock, with the sources as they were
+    /// considered after selection
+    fn decision(
+        &self,
+        survivors: Vec<(SourceId, f64)>,
+        combined: Option<CombinedEstimate>,
+        action: DecisionAction,
+    ) -> DecisionRecord<SourceId> {
+        DecisionRecord {
+            candidates: self
+                .sources
+                .iter()
+                .filter_map(|(id, (state, _))| {
+                    state.map(|snapshot| DecisionCandidate {
+                        source: *id,
+                        offset: snapshot.offset(),
+                        uncertainty: snapshot.offset_uncertainty(),
+                        delay: snapshot.delay,
+                        status: self.selection_status(*id),
+                    })
+                })
+                .collect(),
+            survivors,
+            combined,
+            action,
+        }
+    }
+
+    /// Whether enough sources have provided enough measurements, agreeing
+    /// with each other, to trust them for the first clock correction.
+    fn warmup_complete(&self, selection: &[SourceSnapshot<SourceId>]) -> bool {
+        let required_sources = self.synchronization_config.warmup_sources.max(1);
+        let mut offsets: Vec<f64> = selection
+            .iter()
+            .filter(|snapshot| {
+                self.startup_measurements
+                    .get(&snapshot.index)
+                    .is_some_and(|count| *count >= self.synchronization_config.warmup_measurements)
+            })
+            .map(|snapshot| snapshot.offset())
+            .collect();
+        offsets.sort_by(f64::total_cmp);
+
+        let agreement = self.synchronization_config.warmup_agreement.to_seconds();
+        let complete = offsets
+            .windows(required_sources)
+            .any(|window| window[window.len() - 1] - window[0] <= agreement);
+
+        if !complete {
+            debug!(
+                warmed_up = offsets.len(),
+                required_sources, "Waiting for warmup before first clock correction"
+            );
+        }
+
+        complete
+    }
+
     fn check_offset_steer(&mut self, change: f64) {
         let change = NtpDuration::from_seconds(change);
         if self.in_startup {
@@ -273,17 +430,36 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
         change: f64,
         freq_delta: f64,
     ) -> StateUpdate<SourceId, KalmanControllerMessage> {
-        if change.abs() > self.algo_config.step_threshold {
+        let step_allowed = match self.synchronization_config.clock_step_policy {
+            StepPolicy::Always => true,
+            StepPolicy::StartupOnly => self.in_startup,
+            StepPolicy::Never => false,
+        };
+        let (change, step_allowed) = match self.steering_limit {
+            Some(limit) => (change.clamp(-limit, limit), false),
+            None => (change, step_allowed),
+        };
+        if change.abs() > self.algo_config.step_threshold && step_allowed {
             // jump
             self.check_offset_steer(change);
-            self.clock
+            let stepped = self
+                .clock
                 .step_clock(NtpDuration::from_seconds(change))
                 .expect("Cannot adjust clock");
+            self.timedata.adjustment = Some(ClockAdjustment {
+                until: stepped + NtpDuration::from_seconds(STEP_SETTLE_TIME),
+                offset: NtpDuration::from_seconds(change),
+            });
             for (state, _) in self.sources.values_mut() {
                 if let Some(state) = state {
                     state.state = state.state.process_offset_steering(change, state.period);
                 }
             }
+            for retained in self.retained.values() {
+                if let Some(state) = retained.lock().expect("Unexpected poisoned mutex").as_mut() {
+                    state.process_offset_steering(change, None);
+                }
+            }
             if self.synchronization_config.warn_on_jump {
                 warn!(
                     "Jumped offset by {}ms. This may cause problems for other software. If this is not a problem for your system, you can reclassify this warning as an informative message through the `synchronization.warn_on_jump` setting in ntp.toml.",
@@ -304,20 +480,72 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 .algo_config
                 .slew_maximum_frequency_offset
                 .min(change.abs() / self.algo_config.slew_minimum_duration);
-            let duration = Duration::from_secs_f64(change.abs() / freq);
-            debug!(
-                "Slewing by {}ms over {}s",
-                change * 1e3,
-                duration.as_secs_f64(),
-            );
+            let intended = change.abs() / freq;
+            if change.abs() > self.algo_config.step_threshold {
+                warn!(
+                    "Offset of {}ms is too large to slew quickly, but the clock step policy does not allow stepping. Slewing over {}s instead.",
+                    change * 1e3,
+                    intended
+                );
+            } else {
+                debug!("Slewing by {}ms over {}s", change * 1e3, intended);
+            }
             let update = self.change_desired_frequency(-freq * change.signum(), freq_delta);
+            if change.abs() > self.algo_config.step_threshold {
+                self.timedata.adjustment = Some(ClockAdjustment {
+                    until: self.last_frequency_update + NtpDuration::from_seconds(intended),
+                    offset: NtpDuration::from_seconds(change),
+                });
+            }
+
+            // Ending the slew gets applied a bit after the timer fires, so ask for
+            // the timer early by the delay we expect.
+            let requested = (intended - self.steer_latency).max(0.0);
+            self.slew = Some((self.last_frequency_update, intended, requested));
             StateUpdate {
-                next_update: Some(duration),
+                next_update: Some(Duration::from_secs_f64(requested)),
                 ..update
             }
         }
     }
 
+    fn end_slew(&mut self) -> StateUpdate<SourceId, KalmanControllerMessage> {
+        let slew_freq = self.desired_freq;
+        let update = self.change_desired_frequency(0.0, 0.0);
+
+        if let Some((start, intended, requested)) = self.slew.take() {
+            let actual = (self.last_frequency_update - start).to_seconds();
+            self.steer_latency = (0.75 * self.steer_latency + 0.25 * (actual - requested))
+                .clamp(0.0, self.algo_config.maximum_steer_latency);
+            debug!(
+                "Slew ended after {}s instead of {}s, error {}ms, expected latency now {}ms",
+                actual,
+                intended,
+                (actual - intended) * slew_freq.abs() * 1e3,
+                self.steer_latency * 1e3,
+            );
+        }
+
+        update
+    }
+
+    /// Time until the earliest of the end of the current slew and the
+    /// deferred update of the clock, if any
+    fn next_timer(&self, now: NtpTimestamp) -> Option<Duration> {
+        let slew_end = self
+            .slew
+            .map(|(start, _, requested)| requested - (now - start).to_seconds());
+        let update = self
+            .pending_update
+            .and(self.last_clock_update)
+            .map(|last| self.algo_config.steer_debounce_interval - (now - last).to_seconds());
+        [slew_end, update]
+            .into_iter()
+            .flatten()
+            .min_by(f64::total_cmp)
+            .map(|remaining| Duration::from_secs_f64(remaining.max(0.0)))
+    }
+
     fn change_desired_frequency(
         &mut self,
         new_freq: f64,
@@ -339,6 +567,7 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
             .clock
             .set_frequency(self.freq_offset)
             .expect("Cannot adjust clock");
+        self.last_frequency_update = freq_update;
         for (state, _) in self.sources.values_mut() {
             if let Some(state) = state {
                 state.state = state.state.process_frequency_steering(
@@ -349,6 +578,11 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug> KalmanClockController<C, S
                 )
             }
         }
+        for retained in self.retained.values() {
+            if let Some(state) = retained.lock().expect("Unexpected poisoned mutex").as_mut() {
+                state.process_frequency_steering(freq_update, actual_change, None);
+            }
+        }
         debug!(
             "Changed frequency, current steer {}ppm, desired freq {}ppm",
             self.freq_offset * 1e6,
@@ -377,6 +611,8 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug + Send + 'static> TimeSyncC
     type NtpSourceController = TwoWayKalmanSourceController<SourceId>;
     type OneWaySourceController = OneWayKalmanSourceController<SourceId>;
 
+    const ALGORITHM: &'static str = "kalman";
+
     fn new(
         clock: C,
         synchronization_config: SynchronizationConfig,
@@ -394,6 +630,16 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug + Send + 'static> TimeSyncC
             desired_freq: 0.0,
             timedata: TimeSnapshot::default(),
             in_startup: true,
+            startup_measurements: HashMap::new(),
+            steering_suspended: false,
+            steering_limit: None,
+            last_frequency_update: NtpTimestamp::default(),
+            slew: None,
+            steer_latency: 0.0,
+            last_clock_update: None,
+            pending_update: None,
+            selection: HashMap::new(),
+            retained: HashMap::new(),
         })
     }
 
@@ -406,9 +652,36 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug + Send + 'static> TimeSyncC
     fn add_source(
         &mut self,
         id: SourceId,
+        peer: &str,
         source_config: SourceConfig,
     ) -> Self::NtpSourceController {
         self.sources.insert(id, (None, false));
+
+        // Sources that are still running hold on to their slot, others are
+        // only kept for as long as their state can be used.
+        let now = self.clock.now().ok();
+        let is_recent = |last_update: Option<NtpTimestamp>| match (now, last_update) {
+            (Some(now), Some(last_update)) => {
+                (now - last_update).to_seconds() < RETAINED_STATE_TIMEOUT
+            }
+            _ => false,
+        };
+        self.retained.retain(|_, retained| {
+            Arc::strong_count(retained) > 1
+                || retained
+                    .lock()
+                    .expect("Unexpected poisoned mutex")
+                    .as_ref()
+                    .is_some_and(|state| is_recent(state.last_update()))
+        });
+
+        let retained = self.retained.entry(peer.to_owned()).or_default().clone();
+        let state = retained
+            .lock()
+            .expect("Unexpected poisoned mutex")
+            .take()
+            .filter(|state| is_recent(state.last_update()));
+
         KalmanSourceController::new(
             id,
             self.algo_config,
@@ -416,6 +689,7 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug + Send + 'static> TimeSyncC
             source_config,
             AveragingBuffer::default(),
         )
+        .retain_in(retained, state)
     }
 
     fn add_one_way_source(
@@ -437,6 +711,8 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug + Send + 'static> TimeSyncC
 
     fn remove_source(&mut self, id: SourceId) {
         self.sources.remove(&id);
+        self.startup_measurements.remove(&id);
+        self.selection.remove(&id);
     }
 
     fn source_update(&mut self, id: SourceId, usable: bool) {
@@ -444,9 +720,101 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug + Send + 'static> TimeSyncC
             state.1 = usable;
         }
     }
+
+    fn set_steering_suspended(&mut self, suspended: bool) {
+        self.steering_suspended = suspended;
+    }
+
+    fn set_steering_limit(&mut self, max_offset: Option<f64>) {
+        self.steering_limit = max_offset;
+    }
+
+    fn step_settings(&self) -> Option<StepSettings> {
+        Some(StepSettings {
+            step_threshold: self.algo_config.step_threshold,
+            slew_maximum_frequency_offset: self.algo_config.slew_maximum_frequency_offset,
+            slew_minimum_duration: self.algo_config.slew_minimum_duration,
+        })
+    }
+
+    fn set_step_settings(&mut self, settings: StepSettings) {
+        // only the clock controller steps and slews, the configuration of the
+        // sources does not need to change
+        self.algo_config.step_threshold = settings.step_threshold;
+        self.algo_config.slew_maximum_frequency_offset = settings.slew_maximum_frequency_offset;
+        self.algo_config.slew_minimum_duration = settings.slew_minimum_duration;
+    }
+
+    fn frequency_estimate(&self) -> Option<f64> {
+        if self.in_startup {
+            return None;
+        }
+        // while slewing, the clock runs at the base frequency adjusted by -desired_freq
+        Some((1.0 + self.freq_offset) / (1.0 - self.desired_freq) - 1.0)
+    }
+
+    fn restore_frequency(
+        &mut self,
+        frequency: f64,
+    ) -> Option<StateUpdate<SourceId, Self::ControllerMessage>> {
+        // The clock is not steered during startup, so there is no slew in progress
+        // that the change would interfere with
+        if !self.in_startup {
+            return None;
+        }
+        let change = (1.0 + frequency) / (1.0 + self.freq_offset) - 1.0;
+        Some(self.steer_frequency(change))
+    }
+
+    fn update_config(
+        &mut self,
+        synchronization_config: SynchronizationConfig,
+        algo_config: Self::AlgorithmConfig,
+    ) -> StateUpdate<SourceId, Self::ControllerMessage> {
+        self.synchronization_config = synchronization_config;
+        self.algo_config = algo_config;
+        StateUpdate {
+            source_message: Some(KalmanControllerMessage {
+                inner: KalmanControllerMessageInner::UpdateConfig { algo_config },
+            }),
+            ..StateUpdate::default()
+        }
+    }
+
+    fn selection_status(&self, id: SourceId) -> SelectionStatus {
+        self.selection.get(&id).copied().unwrap_or_default()
+    }
     fn time_update(&mut self) -> StateUpdate<SourceId, Self::ControllerMessage> {
-        // End slew
-        self.change_desired_frequency(0.0, 0.0)
+        // Without debouncing, the timer is only used to end slews
+        if self.algo_config.steer_debounce_interval <= 0.0 && self.pending_update.is_none() {
+            return self.end_slew();
+        }
+
+        // The timer is shared by the end of a slew and the deferred update of
+        // the clock, handle whichever is due first and ask for the timer again
+        // for the other.
+        let now = self.clock.now().expect("Cannot get current time");
+        let update = if self
+            .slew
+            .is_some_and(|(start, _, requested)| (now - start).to_seconds() >= requested)
+        {
+            self.end_slew()
+        } else if let Some(time) = self.pending_update
+            && self.last_clock_update.is_none_or(|last| {
+                (now - last).to_seconds() >= self.algo_config.steer_debounce_interval
+            })
+        {
+            self.pending_update = None;
+            self.last_clock_update = Some(now);
+            self.update_clock(time)
+        } else {
+            StateUpdate::default()
+        };
+
+        StateUpdate {
+            next_update: update.next_update.or_else(|| self.next_timer(now)),
+            ..update
+        }
     }
 
     fn source_message(
@@ -457,6 +825,34 @@ impl<C: NtpClock, SourceId: Hash + Eq + Copy + Debug + Send + 'static> TimeSyncC
         if let Some(source) = self.sources.get_mut(&id) {
             let time = message.inner.last_update;
             source.0 = Some(message.inner);
+            if self.in_startup {
+                *self.startup_measurements.entry(id).or_default() += 1;
+            }
+
+            // Measurements arriving shortly after the last update of the clock
+            // are combined in a single update at the end of the interval
+            let now = self.clock.now().expect("Cannot get current time");
+            let debounce = self.algo_config.steer_debounce_interval;
+            if debounce > 0.0
+                && let Some(last) = self.last_clock_update
+                && (now - last).to_seconds() < debounce
+            {
+                let first = self.pending_update.is_none();
+                self.pending_update = Some(
+                    self.pending_update
+                        .map_or(time, |pending| pending.max(time)),
+                );
+                if !first {
+                    return StateUpdate::default();
+                }
+                return StateUpdate {
+                    next_update: self.next_timer(now),
+                    ..StateUpdate::default()
+                };
+            }
+
+            self.pending_update = None;
+            self.last_clock_update = Some(now);
             self.update_clock(time)
         } else {
             error!("Internal error: Update from non-existing source");
@@ -544,7 +940,7 @@ mod tests {
         // ignore startup steer of frequency.
         *algo.clock.has_steered.borrow_mut() = false;
 
-        let mut source = algo.add_source(0, source_config);
+        let mut source = algo.add_source(0, "a", source_config);
         algo.source_update(0, true);
 
         assert!(algo.in_startup);
@@ -567,6 +963,7 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             });
             if let Some(message) = message {
                 let actions = algo.source_message(0, message);
@@ -582,6 +979,152 @@ mod tests {
         assert_ne!(algo.timedata.root_variance_base, 0.0);
     }
 
+    #[test]
+    fn test_suspended_steering() {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let algo_config = AlgorithmConfig::default();
+        let source_config = SourceConfig::default();
+        let mut algo = KalmanClockController::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            synchronization_config,
+            algo_config,
+        )
+        .unwrap();
+        let mut cur_instant = NtpInstant::now();
+
+        // ignore startup steer of frequency.
+        *algo.clock.has_steered.borrow_mut() = false;
+
+        let mut source = algo.add_source(0, "a", source_config);
+        algo.source_update(0, true);
+        algo.set_steering_suspended(true);
+
+        let mut noise = 1e-9;
+        let mut measure = |algo: &mut KalmanClockController<TestClock, usize>| {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            noise += 1e-9;
+
+            let message = source.handle_measurement(Measurement {
+                delay: NtpDuration::from_seconds(0.001 + noise),
+                offset: NtpDuration::from_seconds(1700.0 + noise),
+                localtime: algo.clock.current_time,
+                monotime: cur_instant,
+
+                stratum: 0,
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+                after_retransmission: false,
+            });
+            if let Some(message) = message {
+                let actions = algo.source_message(0, message);
+                if let Some(source_message) = actions.source_message {
+                    source.handle_message(source_message);
+                }
+            }
+        };
+
+        for _ in 0..100 {
+            measure(&mut algo);
+        }
+
+        // measurements were processed, but the clock was left alone
+        assert!(!*algo.clock.has_steered.borrow());
+        assert!(algo.in_startup);
+        assert_ne!(algo.timedata.root_delay, NtpDuration::ZERO);
+
+        algo.set_steering_suspended(false);
+        while !*algo.clock.has_steered.borrow() {
+            measure(&mut algo);
+        }
+        assert!(!algo.in_startup);
+    }
+
+    #[test]
+    fn test_peer_keeps_filter_state() {
+        let source_config = SourceConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        algo.set_steering_suspended(true);
+        let mut cur_instant = NtpInstant::now();
+
+        let mut source = algo.add_source(0, "a", source_config);
+        for _ in 0..10 {
+            cur_instant = cur_instant + std::time::Duration::from_secs(1);
+            algo.clock.current_time += NtpDuration::from_seconds(1.0);
+            source.handle_measurement(Measurement {
+                delay: NtpDuration::from_seconds(0.001),
+                offset: NtpDuration::from_seconds(0.5),
+                localtime: algo.clock.current_time,
+                monotime: cur_instant,
+
+                stratum: 0,
+                root_delay: NtpDuration::default(),
+                root_dispersion: NtpDuration::default(),
+                leap: NtpLeapIndicator::NoWarning,
+                precision: 0,
+                after_retransmission: false,
+            });
+        }
+        algo.remove_source(0);
+        drop(source);
+
+        // a new address for the same peer continues where the last one left off
+        let source = algo.add_source(1, "a", source_config);
+        assert!((source.observe().offset.to_seconds() - 0.5).abs() < 1e-3);
+        let other = algo.add_source(2, "b", source_config);
+        assert_eq!(other.observe().uncertainty, NtpDuration::MAX);
+        algo.remove_source(1);
+        drop(source);
+
+        // but not once the state is too old to be of use
+        algo.clock.current_time += NtpDuration::from_seconds(2.0 * RETAINED_STATE_TIMEOUT);
+        let source = algo.add_source(3, "a", source_config);
+        assert_eq!(source.observe().uncertainty, NtpDuration::MAX);
+    }
+
+    #[test]
+    fn test_limited_steering() {
+        let algo_config = AlgorithmConfig::default();
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            algo_config,
+        )
+        .unwrap();
+        algo.in_startup = false;
+
+        // only a small part of a large offset is slewed away
+        algo.set_steering_limit(Some(0.001));
+        algo.steer_offset(1.0, 0.0);
+        assert!(algo.timedata.adjustment.is_none());
+        let (_, intended, _) = algo.slew.unwrap();
+        assert!((intended * algo.desired_freq.abs() - 0.001).abs() < 1e-9);
+
+        // without the limit, it is stepped
+        algo.set_steering_limit(None);
+        algo.steer_offset(1.0, 0.0);
+        assert!(algo.timedata.adjustment.is_some());
+    }
+
     #[test]
     fn slews_dont_accumulate() {
         let synchronization_config = SynchronizationConfig {
@@ -611,6 +1154,279 @@ mod tests {
         assert_eq!(algo.timedata.accumulated_steps, NtpDuration::ZERO);
     }
 
+    #[test]
+    fn test_warmup() {
+        let snapshot = |index: u32, offset: f64| SourceSnapshot {
+            index,
+            state: KalmanState {
+                state: Vector::new_vector([offset, 0.0]),
+                uncertainty: Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                time: NtpTimestamp::from_fixed_int(0),
+            },
+            wander: 0.0,
+            delay: 0.0,
+            period: None,
+            source_uncertainty: NtpDuration::ZERO,
+            source_delay: NtpDuration::ZERO,
+            leap_indicator: NtpLeapIndicator::NoWarning,
+            last_update: NtpTimestamp::from_fixed_int(0),
+            trust: false,
+            prefer: false,
+            noselect: false,
+        };
+
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig {
+                warmup_measurements: 3,
+                warmup_sources: 2,
+                warmup_agreement: NtpDuration::from_seconds(0.1),
+                ..SynchronizationConfig::default()
+            },
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        let selection = [snapshot(0, 10.0), snapshot(1, 10.05), snapshot(2, 12.0)];
+
+        // not enough measurements yet
+        algo.startup_measurements = HashMap::from([(0, 3), (1, 2), (2, 3)]);
+        assert!(!algo.warmup_complete(&selection));
+
+        // two sources with enough measurements, but they disagree
+        algo.startup_measurements = HashMap::from([(0, 3), (1, 1), (2, 5)]);
+        assert!(!algo.warmup_complete(&selection));
+
+        algo.startup_measurements = HashMap::from([(0, 3), (1, 4), (2, 5)]);
+        assert!(algo.warmup_complete(&selection));
+    }
+
+    #[test]
+    fn debounces_updates() {
+        let at =
+            |seconds: f64| NtpTimestamp::from_fixed_int(0) + NtpDuration::from_seconds(seconds);
+        let message = |index: u32, time: NtpTimestamp| KalmanSourceMessage {
+            inner: SourceSnapshot {
+                index,
+                state: KalmanState {
+                    state: Vector::new_vector([0.0, 0.0]),
+                    uncertainty: Matrix::new([[1e-6, 0.0], [0.0, 1e-12]]),
+                    time,
+                },
+                wander: 0.0,
+                delay: 0.0,
+                period: None,
+                source_uncertainty: NtpDuration::ZERO,
+                source_delay: NtpDuration::ZERO,
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                last_update: time,
+                trust: false,
+                prefer: false,
+                noselect: false,
+            },
+        };
+
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: at(0.0),
+            },
+            SynchronizationConfig {
+                minimum_agreeing_sources: 1,
+                ..SynchronizationConfig::default()
+            },
+            AlgorithmConfig {
+                steer_debounce_interval: 1.0,
+                ..AlgorithmConfig::default()
+            },
+        )
+        .unwrap();
+        algo.sources.insert(0, (None, true));
+        algo.sources.insert(1, (None, true));
+
+        assert!(
+            algo.source_message(0, message(0, at(0.0)))
+                .decision
+                .is_some()
+        );
+
+        // measurements shortly after are combined in a single later update
+        algo.clock.current_time = at(0.1);
+        let update = algo.source_message(1, message(1, at(0.1)));
+        assert!(update.decision.is_none());
+        assert!((update.next_update.unwrap().as_secs_f64() - 0.9).abs() < 1e-6);
+
+        algo.clock.current_time = at(0.2);
+        let update = algo.source_message(0, message(0, at(0.2)));
+        assert!(update.decision.is_none());
+        assert!(update.next_update.is_none());
+
+        // the timer may fire late, the interval starts over when the update
+        // is made, not at the time of the measurements it uses
+        algo.clock.current_time = at(1.3);
+        let update = algo.time_update();
+        assert_eq!(update.decision.unwrap().candidates.len(), 2);
+        assert_eq!(algo.last_clock_update, Some(at(1.3)));
+        assert!(update.next_update.is_none());
+
+        algo.clock.current_time = at(1.5);
+        let update = algo.source_message(1, message(1, at(1.5)));
+        assert!(update.decision.is_none());
+        assert!((update.next_update.unwrap().as_secs_f64() - 0.8).abs() < 1e-6);
+        algo.clock.current_time = at(2.4);
+        assert!(algo.time_update().decision.is_some());
+
+        // once the interval has passed, the clock is updated right away
+        algo.clock.current_time = at(3.5);
+        assert!(
+            algo.source_message(0, message(0, at(3.5)))
+                .decision
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn slew_compensates_latency() {
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        algo.in_startup = false;
+
+        let first = algo.steer_offset(0.004, 0.0).next_update.unwrap();
+        assert!((first.as_secs_f64() - 20.0).abs() < 1e-9);
+
+        // the end of the slew gets applied 40ms late
+        algo.clock.current_time += NtpDuration::from_seconds(first.as_secs_f64() + 0.04);
+        algo.time_update();
+        assert!((algo.steer_latency - 0.01).abs() < 1e-6);
+
+        // the next slew is ended early to make up for it
+        let second = algo.steer_offset(0.004, 0.0).next_update.unwrap();
+        assert!((second.as_secs_f64() - 19.99).abs() < 1e-6);
+
+        // a stall is only compensated up to the configured maximum
+        algo.clock.current_time += NtpDuration::from_seconds(100.0);
+        algo.time_update();
+        assert_eq!(algo.steer_latency, algo.algo_config.maximum_steer_latency);
+    }
+
+    #[test]
+    fn frequency_estimate_excludes_slew() {
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(algo.frequency_estimate(), None);
+
+        algo.in_startup = false;
+        algo.steer_frequency(1e-6);
+        assert!((algo.frequency_estimate().unwrap() - 1e-6).abs() < 1e-12);
+
+        algo.change_desired_frequency(-5e-6, 0.0);
+        assert!((algo.freq_offset - 1e-6).abs() > 1e-6);
+        assert!((algo.frequency_estimate().unwrap() - 1e-6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn restore_frequency() {
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        let update = algo.restore_frequency(12e-6).unwrap();
+        assert!(update.source_message.is_some());
+        assert!((algo.freq_offset - 12e-6).abs() < 1e-12);
+
+        // once the controller is out of startup, its own estimate wins
+        algo.in_startup = false;
+        assert!(algo.restore_frequency(-3e-6).is_none());
+        assert!((algo.freq_offset - 12e-6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn update_config() {
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        let update = algo.update_config(
+            SynchronizationConfig {
+                minimum_agreeing_sources: 1,
+                ..SynchronizationConfig::default()
+            },
+            AlgorithmConfig {
+                step_threshold: 0.5,
+                ..AlgorithmConfig::default()
+            },
+        );
+        assert_eq!(algo.synchronization_config.minimum_agreeing_sources, 1);
+        assert_eq!(algo.algo_config.step_threshold, 0.5);
+        // sources are told about the new algorithm configuration
+        assert!(matches!(
+            update.source_message,
+            Some(KalmanControllerMessage {
+                inner: KalmanControllerMessageInner::UpdateConfig { algo_config }
+            }) if algo_config.step_threshold == 0.5
+        ));
+        assert!(!*algo.clock.has_steered.borrow());
+    }
+
+    #[test]
+    fn step_settings() {
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+
+        let settings = StepSettings {
+            step_threshold: 0.5,
+            slew_maximum_frequency_offset: 100e-6,
+            slew_minimum_duration: 4.0,
+        };
+        algo.set_step_settings(settings);
+        assert_eq!(algo.step_settings(), Some(settings));
+        assert_eq!(algo.algo_config.step_threshold, 0.5);
+
+        // a configuration update replaces them
+        algo.update_config(SynchronizationConfig::default(), AlgorithmConfig::default());
+        assert_eq!(
+            algo.step_settings().unwrap().step_threshold,
+            AlgorithmConfig::default().step_threshold
+        );
+    }
+
     #[test]
     #[should_panic]
     fn jumps_add_absolutely() {
@@ -639,6 +1455,78 @@ mod tests {
         algo.steer_offset(-1000.0, 0.0);
     }
 
+    #[test]
+    fn step_policy() {
+        let controller = |clock_step_policy| {
+            KalmanClockController::<_, u32>::new(
+                TestClock {
+                    has_steered: RefCell::new(false),
+                    current_time: NtpTimestamp::from_fixed_int(0),
+                },
+                SynchronizationConfig {
+                    clock_step_policy,
+                    ..SynchronizationConfig::default()
+                },
+                AlgorithmConfig::default(),
+            )
+            .unwrap()
+        };
+
+        // Far beyond the panic threshold, but never stepped so never a reason to exit
+        let mut algo = controller(StepPolicy::Never);
+        algo.in_startup = false;
+        let update = algo.steer_offset(2000.0, 0.0);
+        assert!(update.next_update.is_some());
+        assert_ne!(algo.desired_freq, 0.0);
+        assert_eq!(algo.timedata.accumulated_steps, NtpDuration::ZERO);
+
+        let mut algo = controller(StepPolicy::StartupOnly);
+        let update = algo.steer_offset(1.0, 0.0);
+        assert!(update.next_update.is_none());
+        assert_eq!(algo.desired_freq, 0.0);
+
+        algo.in_startup = false;
+        let update = algo.steer_offset(1.0, 0.0);
+        assert!(update.next_update.is_some());
+        assert_ne!(algo.desired_freq, 0.0);
+    }
+
+    #[test]
+    fn tracks_clock_adjustments() {
+        let mut algo = KalmanClockController::<_, u32>::new(
+            TestClock {
+                has_steered: RefCell::new(false),
+                current_time: NtpTimestamp::from_fixed_int(0),
+            },
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+        )
+        .unwrap();
+        algo.in_startup = false;
+
+        // regular slews are not reported
+        algo.steer_offset(0.004, 0.0);
+        assert_eq!(algo.timedata.adjustment, None);
+
+        algo.steer_offset(-1.0, 0.0);
+        let adjustment = algo.timedata.adjustment.unwrap();
+        assert_eq!(adjustment.offset, NtpDuration::from_seconds(-1.0));
+        assert!(adjustment.in_progress(NtpTimestamp::from_fixed_int(0)));
+        assert!(!adjustment.in_progress(
+            NtpTimestamp::from_fixed_int(0) + NtpDuration::from_seconds(STEP_SETTLE_TIME)
+        ));
+
+        // a slew of an offset too large to correct quickly also counts
+        algo.synchronization_config.clock_step_policy = StepPolicy::Never;
+        algo.steer_offset(1.0, 0.0);
+        let adjustment = algo.timedata.adjustment.unwrap();
+        assert_eq!(adjustment.offset, NtpDuration::from_seconds(1.0));
+        assert!(
+            adjustment
+                .in_progress(NtpTimestamp::from_fixed_int(0) + NtpDuration::from_seconds(10.0))
+        );
+    }
+
     #[test]
     fn test_jumps_update_state() {
         let synchronization_config = SynchronizationConfig::default();
@@ -670,6 +1558,9 @@ mod tests {
                     source_delay: NtpDuration::ZERO,
                     leap_indicator: NtpLeapIndicator::NoWarning,
                     last_update: NtpTimestamp::from_fixed_int(0),
+                    trust: false,
+                    prefer: false,
+                    noselect: false,
                 }),
                 true,
             ),
@@ -692,6 +1583,9 @@ mod tests {
                     source_delay: NtpDuration::ZERO,
                     leap_indicator: NtpLeapIndicator::NoWarning,
                     last_update: NtpTimestamp::from_fixed_int(0),
+                    trust: false,
+                    prefer: false,
+                    noselect: false,
                 }),
                 true,
             ),
@@ -743,6 +1637,9 @@ mod tests {
                     source_delay: NtpDuration::ZERO,
                     leap_indicator: NtpLeapIndicator::NoWarning,
                     last_update: NtpTimestamp::from_fixed_int(0),
+                    trust: false,
+                    prefer: false,
+                    noselect: false,
                 }),
                 true,
             ),
@@ -775,7 +1672,7 @@ mod tests {
         // ignore startup steer of frequency.
         *algo.clock.has_steered.borrow_mut() = false;
 
-        let mut source = algo.add_source(0, source_config);
+        let mut source = algo.add_source(0, "a", source_config);
         algo.source_update(0, true);
 
         let mut noise = 1e-9;
@@ -796,6 +1693,7 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             });
             if let Some(message) = message {
                 let actions = algo.source_message(0, message);
@@ -833,7 +1731,7 @@ mod tests {
         // ignore startup steer of frequency.
         *algo.clock.has_steered.borrow_mut() = false;
 
-        let mut source = algo.add_source(0, source_config);
+        let mut source = algo.add_source(0, "a", source_config);
         algo.source_update(0, true);
 
         let mut noise = 1e-9;
@@ -854,6 +1752,7 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             });
             if let Some(message) = message {
                 let actions = algo.source_message(0, message);
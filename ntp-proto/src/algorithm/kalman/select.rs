@@ -1,6 +1,12 @@
 use crate::config::SynchronizationConfig;
 
-use super::{SourceSnapshot, config::AlgorithmConfig};
+use super::{super::SelectionStatus, SourceSnapshot, config::AlgorithmConfig};
+
+pub(super) struct Selection<Index: Copy> {
+    pub selected: Vec<SourceSnapshot<Index>>,
+    /// Candidates that were not selected, with the reason why
+    pub rejected: Vec<(Index, SelectionStatus)>,
+}
 
 #[derive(Debug)]
 enum BoundType {
@@ -21,11 +27,11 @@ pub(super) fn select<Index: Copy>(
     synchronization_config: &SynchronizationConfig,
     algo_config: &AlgorithmConfig,
     candidates: Vec<SourceSnapshot<Index>>,
-) -> Vec<SourceSnapshot<Index>> {
-    let mut bounds: Vec<(f64, BoundType)> = Vec::with_capacity(2 * candidates.len());
+) -> Selection<Index> {
+    let mut bounds: Vec<(f64, BoundType, bool)> = Vec::with_capacity(2 * candidates.len());
 
     for snapshot in candidates.iter() {
-        if snapshot.period.is_some() {
+        if snapshot.period.is_some() || snapshot.noselect {
             // Do not let periodic sources be part of the vote for correct time
             continue;
         }
@@ -38,25 +44,27 @@ pub(super) fn select<Index: Copy>(
             continue;
         }
 
-        bounds.push((snapshot.offset() - radius, BoundType::Start));
-        bounds.push((snapshot.offset() + radius, BoundType::End));
+        bounds.push((snapshot.offset() - radius, BoundType::Start, snapshot.trust));
+        bounds.push((snapshot.offset() + radius, BoundType::End, snapshot.trust));
     }
 
     bounds.sort_by(|a, b| a.0.total_cmp(&b.0));
 
     // Find the intersection of the confidence intervals of the maximum
     // overlapping set. We need this entire interval to properly integrate
-    // periodic sources
-    let mut maxlow: usize = 0;
-    let mut maxhigh: usize = 0;
+    // periodic sources. Sets containing more trusted sources always win,
+    // so the count of trusted sources is compared first.
+    let mut maxlow: (usize, usize) = (0, 0);
+    let mut maxhigh: (usize, usize) = (0, 0);
     let mut maxtlow: f64 = 0.0;
     let mut maxthigh: f64 = 0.0;
-    let mut cur: usize = 0;
+    let mut cur: (usize, usize) = (0, 0);
 
-    for (time, boundtype) in bounds.iter() {
+    for (time, boundtype, trust) in bounds.iter() {
         match boundtype {
             BoundType::Start => {
-                cur += 1;
+                cur.0 += usize::from(*trust);
+                cur.1 += 1;
                 if cur > maxlow {
                     maxlow = cur;
                     maxtlow = *time;
@@ -67,7 +75,8 @@ pub(super) fn select<Index: Copy>(
                     maxhigh = cur;
                     maxthigh = *time;
                 }
-                cur -= 1;
+                cur.0 -= usize::from(*trust);
+                cur.1 -= 1;
             }
         }
     }
@@ -76,24 +85,38 @@ pub(super) fn select<Index: Copy>(
     // sources are part of the maximum set. If not, something has seriously gone
     // wrong and we shouldn't steer the clock.
     assert_eq!(maxlow, maxhigh);
-    let max = maxlow;
-
-    if max >= synchronization_config.minimum_agreeing_sources && max * 4 > bounds.len() {
-        candidates
-            .iter()
-            .filter(|snapshot| {
-                let radius = snapshot.offset_uncertainty() * algo_config.range_statistical_weight
-                    + snapshot.delay * algo_config.range_delay_weight;
-                radius <= algo_config.maximum_source_uncertainty
-                    && snapshot.offset() - radius <= maxthigh
-                    && snapshot.offset() + radius >= maxtlow
-                    && snapshot.leap_indicator.is_synchronized()
-            })
-            .cloned()
-            .collect()
-    } else {
-        vec![]
+    let (trusted, max) = maxlow;
+
+    // A trusted source stands in for the majority, but not for the minimum
+    // number of agreeing sources
+    let majority = max >= synchronization_config.minimum_agreeing_sources
+        && (trusted > 0 || max * 4 > bounds.len());
+
+    let mut selection = Selection {
+        selected: vec![],
+        rejected: vec![],
+    };
+    for snapshot in candidates {
+        let radius = snapshot.offset_uncertainty() * algo_config.range_statistical_weight
+            + snapshot.delay * algo_config.range_delay_weight;
+        let status = if snapshot.noselect {
+            SelectionStatus::NoSelect
+        } else if !snapshot.leap_indicator.is_synchronized() {
+            SelectionStatus::Unsynchronized
+        } else if radius > algo_config.maximum_source_uncertainty {
+            SelectionStatus::TooUncertain
+        } else if !majority {
+            SelectionStatus::NoMajority
+        } else if snapshot.offset() - radius > maxthigh || snapshot.offset() + radius < maxtlow {
+            SelectionStatus::Falseticker
+        } else {
+            selection.selected.push(snapshot);
+            continue;
+        };
+        selection.rejected.push((snapshot.index, status));
     }
+
+    selection
 }
 
 #[cfg(test)]
@@ -131,6 +154,9 @@ mod tests {
             source_delay: NtpDuration::from_seconds(0.01),
             leap_indicator: NtpLeapIndicator::NoWarning,
             last_update: NtpTimestamp::from_fixed_int(0),
+            trust: false,
+            prefer: false,
+            noselect: false,
         }
     }
 
@@ -156,7 +182,7 @@ mod tests {
             ..Default::default()
         };
 
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).selected;
         assert_eq!(result.len(), 0);
 
         let algconfig = AlgorithmConfig {
@@ -165,7 +191,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).selected;
         assert_eq!(result.len(), 0);
 
         let algconfig = AlgorithmConfig {
@@ -174,7 +200,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).selected;
         assert_eq!(result.len(), 4);
     }
 
@@ -197,7 +223,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).selected;
         assert_eq!(result.len(), 3);
 
         let algconfig = AlgorithmConfig {
@@ -206,7 +232,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).selected;
         assert_eq!(result.len(), 2);
 
         let algconfig = AlgorithmConfig {
@@ -215,7 +241,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).selected;
         assert_eq!(result.len(), 1);
 
         let algconfig = AlgorithmConfig {
@@ -224,7 +250,7 @@ mod tests {
             range_delay_weight: 1.0,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).selected;
         assert_eq!(result.len(), 0);
     }
 
@@ -249,17 +275,73 @@ mod tests {
             minimum_agreeing_sources: 3,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).selected;
         assert_eq!(result.len(), 3);
 
         let sysconfig = SynchronizationConfig {
             minimum_agreeing_sources: 4,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).selected;
         assert_eq!(result.len(), 0);
     }
 
+    #[test]
+    fn test_rejection_reasons() {
+        let mut candidates = vec![
+            snapshot_for_range(0.0, 0.1, 0.1, None),
+            snapshot_for_range(0.0, 0.1, 0.1, None),
+            snapshot_for_range(0.5, 0.1, 0.1, None),
+            snapshot_for_range(0.0, 2.0, 0.1, None),
+            snapshot_for_range(0.0, 0.1, 0.1, None),
+        ];
+        candidates[4].leap_indicator = NtpLeapIndicator::Unsynchronized;
+        for (index, candidate) in candidates.iter_mut().enumerate() {
+            candidate.index = index;
+        }
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 1.0,
+            range_statistical_weight: 1.0,
+            range_delay_weight: 1.0,
+            ..Default::default()
+        };
+
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 2,
+            ..Default::default()
+        };
+        let result = select(&sysconfig, &algconfig, candidates.clone());
+        assert_eq!(
+            result.selected.iter().map(|s| s.index).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(
+            result.rejected,
+            vec![
+                (2, SelectionStatus::Falseticker),
+                (3, SelectionStatus::TooUncertain),
+                (4, SelectionStatus::Unsynchronized),
+            ]
+        );
+
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 3,
+            ..Default::default()
+        };
+        let result = select(&sysconfig, &algconfig, candidates);
+        assert!(result.selected.is_empty());
+        assert_eq!(
+            result.rejected,
+            vec![
+                (0, SelectionStatus::NoMajority),
+                (1, SelectionStatus::NoMajority),
+                (2, SelectionStatus::NoMajority),
+                (3, SelectionStatus::TooUncertain),
+                (4, SelectionStatus::Unsynchronized),
+            ]
+        );
+    }
+
     #[test]
     fn test_tie() {
         // Test that in the case of a tie no group is chosen.
@@ -279,7 +361,7 @@ mod tests {
             minimum_agreeing_sources: 1,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).selected;
         assert_eq!(result.len(), 0);
     }
 
@@ -299,14 +381,96 @@ mod tests {
             minimum_agreeing_sources: 2,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates.clone());
+        let result = select(&sysconfig, &algconfig, candidates.clone()).selected;
         assert_eq!(result.len(), 3);
         assert_eq!(result[0].offset(), 0.5);
         let sysconfig = SynchronizationConfig {
             minimum_agreeing_sources: 3,
             ..Default::default()
         };
-        let result = select(&sysconfig, &algconfig, candidates);
+        let result = select(&sysconfig, &algconfig, candidates).selected;
         assert_eq!(result.len(), 0);
     }
+
+    #[test]
+    fn test_trusted_source_wins() {
+        let mut candidates = vec![
+            snapshot_for_range(0.0, 0.1, 0.1, None),
+            snapshot_for_range(0.0, 0.1, 0.1, None),
+            snapshot_for_range(0.5, 0.1, 0.1, None),
+            snapshot_for_range(0.5, 0.1, 0.1, None),
+            snapshot_for_range(0.5, 0.1, 0.1, None),
+        ];
+        for (index, candidate) in candidates.iter_mut().enumerate() {
+            candidate.index = index;
+        }
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 1.0,
+            range_statistical_weight: 1.0,
+            range_delay_weight: 1.0,
+            ..Default::default()
+        };
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 2,
+            ..Default::default()
+        };
+
+        let result = select(&sysconfig, &algconfig, candidates.clone());
+        assert_eq!(
+            result.selected.iter().map(|s| s.index).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+
+        candidates[0].trust = true;
+        let result = select(&sysconfig, &algconfig, candidates);
+        assert_eq!(
+            result.selected.iter().map(|s| s.index).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(
+            result.rejected,
+            vec![
+                (2, SelectionStatus::Falseticker),
+                (3, SelectionStatus::Falseticker),
+                (4, SelectionStatus::Falseticker),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_noselect_does_not_vote() {
+        let mut candidates = vec![
+            snapshot_for_range(0.0, 0.1, 0.1, None),
+            snapshot_for_range(0.0, 0.1, 0.1, None),
+            snapshot_for_range(0.0, 0.1, 0.1, None),
+        ];
+        for (index, candidate) in candidates.iter_mut().enumerate() {
+            candidate.index = index;
+        }
+        candidates[2].noselect = true;
+        let algconfig = AlgorithmConfig {
+            maximum_source_uncertainty: 1.0,
+            range_statistical_weight: 1.0,
+            range_delay_weight: 1.0,
+            ..Default::default()
+        };
+
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 2,
+            ..Default::default()
+        };
+        let result = select(&sysconfig, &algconfig, candidates.clone());
+        assert_eq!(
+            result.selected.iter().map(|s| s.index).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(result.rejected, vec![(2, SelectionStatus::NoSelect)]);
+
+        let sysconfig = SynchronizationConfig {
+            minimum_agreeing_sources: 3,
+            ..Default::default()
+        };
+        let result = select(&sysconfig, &algconfig, candidates);
+        assert!(result.selected.is_empty());
+    }
 }
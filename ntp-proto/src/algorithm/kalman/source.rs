@@ -73,7 +73,12 @@
 /// If they are often too small, v is quartered, and if they are often too
 /// large, v is quadrupled (note, this corresponds with doubling/halving
 /// the more intuitive standard deviation).
-use tracing::{debug, trace};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{debug, info, trace};
 
 use crate::{
     ObservableSourceTimedata,
@@ -87,7 +92,7 @@ use core::fmt::Debug;
 
 use super::{
     SourceSnapshot,
-    config::AlgorithmConfig,
+    config::{AlgorithmConfig, DelayFilter},
     matrix::{Matrix, Vector},
     sqr,
 };
@@ -308,7 +313,11 @@ pub trait MeasurementNoiseEstimator {
     fn get_noise_estimate(&self) -> f64;
     fn is_outlier(&self, delay: Self::MeasurementDelay, threshold: f64) -> bool;
     fn preprocess(&self, delay: Self::MeasurementDelay) -> Self::MeasurementDelay;
+    /// Remove a fixed part of `correction` seconds from the delay
+    fn correct_delay(delay: Self::MeasurementDelay, correction: f64) -> Self::MeasurementDelay;
     fn reset(&mut self) -> Self;
+    /// Delay in seconds, for measurements that have one
+    fn delay_seconds(delay: Self::MeasurementDelay) -> Option<f64>;
 
     // for SourceSnapshot
     fn get_max_roundtrip(&self, samples: &i32) -> Option<f64>;
@@ -334,10 +343,18 @@ impl MeasurementNoiseEstimator for AveragingBuffer {
         delay.max(MIN_DELAY)
     }
 
+    fn correct_delay(delay: Self::MeasurementDelay, correction: f64) -> Self::MeasurementDelay {
+        delay - NtpDuration::from_seconds(correction)
+    }
+
     fn reset(&mut self) -> Self {
         AveragingBuffer::default()
     }
 
+    fn delay_seconds(delay: Self::MeasurementDelay) -> Option<f64> {
+        Some(delay.to_seconds())
+    }
+
     fn get_max_roundtrip(&self, samples: &i32) -> Option<f64> {
         self.data[..*samples as usize]
             .iter()
@@ -373,10 +390,16 @@ impl MeasurementNoiseEstimator for f64 {
 
     fn preprocess(&self, _delay: Self::MeasurementDelay) -> Self::MeasurementDelay {}
 
+    fn correct_delay(_delay: Self::MeasurementDelay, _correction: f64) -> Self::MeasurementDelay {}
+
     fn reset(&mut self) -> Self {
         *self
     }
 
+    fn delay_seconds(_delay: Self::MeasurementDelay) -> Option<f64> {
+        None
+    }
+
     fn get_max_roundtrip(&self, _samples: &i32) -> Option<f64> {
         Some(1.)
     }
@@ -442,7 +465,9 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
             }
         }
 
-        self.noise_estimator.update(measurement.delay);
+        if !measurement.after_retransmission {
+            self.noise_estimator.update(measurement.delay);
+        }
         self.init_offset.update(offset);
         self.samples += 1;
         self.last_measurement = Some(measurement);
@@ -458,6 +483,146 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
     }
 }
 
+/// Measurements needed before the delay filter trusts its baseline
+const DELAY_BASELINE_MIN_SAMPLES: usize = 4;
+
+/// Measurements over which the minimum delay is compared with that of the
+/// path to a source, to detect that the path changed
+const PATH_CHANGE_SAMPLES: usize = 8;
+
+/// Smallest change of the minimum delay taken as a change of path, smaller
+/// changes cannot be told apart from jitter on local networks (seconds)
+const PATH_CHANGE_MIN_DELTA: f64 = 1e-4;
+
+/// Minimum over the delays of the most recent measurements of a source.
+///
+/// All measurements are recorded, including those that end up discarded, so
+/// that the baseline follows a lasting change in the path to the source.
+#[derive(Debug, Clone, Default)]
+struct DelayBaseline {
+    recent: VecDeque<f64>,
+    /// Minimum delay of the path to the source, as far as it is known
+    path: Option<f64>,
+    path_changes: u32,
+    /// Extra measurement noise after a change of path, and until when
+    widening: Option<(NtpTimestamp, f64)>,
+}
+
+impl DelayBaseline {
+    /// Record the delay of a new measurement, returning how far it exceeds
+    /// the baseline if the delay filter should act on it.
+    fn congestion(&mut self, delay: f64, algo_config: &AlgorithmConfig) -> Option<f64> {
+        let baseline = self.recent.iter().copied().reduce(f64::min);
+        let enough_samples = self.recent.len() >= DELAY_BASELINE_MIN_SAMPLES;
+
+        self.recent.push_back(delay);
+        while self.recent.len() > algo_config.delay_filter_window.max(1) {
+            self.recent.pop_front();
+        }
+
+        let baseline = baseline.filter(|_| enough_samples)?;
+        (algo_config.delay_filter != DelayFilter::Off
+            && delay > algo_config.delay_filter_ratio * baseline)
+            .then_some(delay - baseline)
+    }
+
+    /// Check whether the recent delays are lastingly different from those of
+    /// the path to the source, returning the minimum delays of the old and
+    /// the new path if so.
+    fn path_change(&mut self, algo_config: &AlgorithmConfig) -> Option<(f64, f64)> {
+        if self.recent.len() < DELAY_BASELINE_MIN_SAMPLES {
+            return None;
+        }
+        let recent = self
+            .recent
+            .iter()
+            .rev()
+            .take(PATH_CHANGE_SAMPLES)
+            .copied()
+            .reduce(f64::min)?;
+        let Some(path) = self.path else {
+            self.path = Some(recent);
+            return None;
+        };
+
+        // a longer path only shows once all recent delays are longer, a
+        // shorter one as soon as a single delay is shorter
+        let changed = (recent - path).abs() > PATH_CHANGE_MIN_DELTA
+            && (recent > algo_config.path_change_ratio * path
+                || recent * algo_config.path_change_ratio < path);
+        if changed || recent < path {
+            self.path = Some(recent);
+        }
+        if changed {
+            self.path_changes += 1;
+        }
+        changed.then_some((path, recent))
+    }
+}
+
+/// Measurements needed before the spike filter judges measurements
+const SPIKE_FILTER_MIN_SAMPLES: usize = 5;
+
+/// Ratio between the standard deviation and the median absolute deviation of
+/// normally distributed values
+const MAD_SCALE: f64 = 1.4826;
+
+/// Median absolute deviation of how far the offsets of the most recent
+/// measurements of a source were from the prediction of its filter.
+///
+/// Rejected measurements are recorded too, and only single measurements are
+/// rejected, so that a lasting change of the offset still reaches the filter.
+#[derive(Debug, Clone, Default)]
+struct SpikeFilter {
+    recent: VecDeque<f64>,
+    prev_was_spike: bool,
+    spikes: u32,
+}
+
+impl SpikeFilter {
+    /// Record how far the offset of a new measurement is from the prediction
+    /// of the filter, returning whether it is a spike. Deviations within the
+    /// measurement noise, given as a variance, never are.
+    fn is_spike(&mut self, residual: f64, noise: f64, algo_config: &AlgorithmConfig) -> bool {
+        if algo_config.spike_filter_threshold <= 0.0 {
+            return false;
+        }
+
+        let deviates = self.recent.len() >= SPIKE_FILTER_MIN_SAMPLES && {
+            let center = median(self.recent.iter().copied().collect());
+            let deviation = median(self.recent.iter().map(|r| (r - center).abs()).collect());
+            (residual - center).abs()
+                > algo_config.spike_filter_threshold * (MAD_SCALE * deviation).max(noise.sqrt())
+        };
+
+        self.recent.push_back(residual);
+        while self.recent.len()
+            > algo_config
+                .spike_filter_window
+                .max(SPIKE_FILTER_MIN_SAMPLES)
+        {
+            self.recent.pop_front();
+        }
+
+        let spike = deviates && !self.prev_was_spike;
+        self.prev_was_spike = spike;
+        if spike {
+            self.spikes += 1;
+        }
+        spike
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.
+    } else {
+        values[mid]
+    }
+}
+
 #[derive(Debug, Clone)]
 struct SourceFilter<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>> {
     state: KalmanState,
@@ -471,6 +636,8 @@ struct SourceFilter<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<Measur
 
     last_measurement: Measurement<D>,
     prev_was_outlier: bool,
+    delay_baseline: DelayBaseline,
+    spike_filter: SpikeFilter,
 
     // Last time a packet was processed
     last_iter: NtpTimestamp,
@@ -490,6 +657,7 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
     fn absorb_measurement(
         &mut self,
         measurement: Measurement<D>,
+        extra_noise: f64,
         period: Option<f64>,
     ) -> (f64, f64, f64) {
         // Measurement parameters
@@ -498,7 +666,8 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         // Kalman filter update
         let measurement_vec = Vector::new_vector([measurement.offset.to_seconds()]);
         let measurement_transform = Matrix::new([[1., 0.]]);
-        let measurement_noise = Matrix::new([[self.noise_estimator.get_noise_estimate()]]);
+        let measurement_noise =
+            Matrix::new([[self.noise_estimator.get_noise_estimate() + extra_noise]]);
         let (new_state, stats) = self.state.absorb_measurement(
             measurement_transform,
             measurement_vec,
@@ -612,7 +781,7 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         &mut self,
         source_config: &SourceConfig,
         algo_config: &AlgorithmConfig,
-        measurement: Measurement<D>,
+        mut measurement: Measurement<D>,
         period: Option<f64>,
     ) -> bool {
         // Always update the root_delay, root_dispersion, leap second status and stratum, as they always represent the most accurate state.
@@ -621,30 +790,101 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         self.last_measurement.stratum = measurement.stratum;
         self.last_measurement.leap = measurement.leap;
 
-        if measurement.localtime.is_before(self.state.time) {
-            // Ignore the past
+        // Requests in flight while the clock was steered are answered with
+        // a local time shortly before that of the filter. Those are moved
+        // forward to the time of the filter, anything older is ignored.
+        let lag = (self.state.time - measurement.localtime).to_seconds();
+        if lag > algo_config.stale_measurement_horizon {
+            debug!(lag, "Ignored measurement from before the filter time");
             return false;
         }
+        if lag > 0.0 {
+            measurement.offset += NtpDuration::from_seconds(self.state.frequency() * lag);
+        }
 
         // This was a valid measurement, so no matter what this represents our current iteration time
         // for the purposes of synchronizing
         self.last_iter = measurement.localtime;
 
-        // Filter out one-time outliers (based on delay!)
-        if !self.prev_was_outlier
-            && self
-                .noise_estimator
-                .is_outlier(measurement.delay, algo_config.delay_outlier_threshold)
-        {
+        let congestion = N::delay_seconds(measurement.delay)
+            .and_then(|delay| self.delay_baseline.congestion(delay, algo_config));
+
+        // A lasting change of the delay most likely comes from a change in the
+        // route to the source, which can change the asymmetry of the path by up
+        // to half of the difference in delay
+        if let Some((old_delay, new_delay)) = self.delay_baseline.path_change(algo_config) {
+            info!(old_delay, new_delay, "Path to source changed");
+            if algo_config.path_change_widening > 0.0 {
+                self.delay_baseline.widening = Some((
+                    measurement.localtime
+                        + NtpDuration::from_seconds(algo_config.path_change_widening),
+                    sqr((new_delay - old_delay) / 2.),
+                ));
+            }
+        }
+        let mut extra_noise = match self.delay_baseline.widening {
+            Some((until, noise)) if measurement.localtime < until => noise,
+            _ => 0.0,
+        };
+
+        // Deal with measurements that were held up by congestion, as their offset
+        // is likely skewed by an asymmetric delay
+        if let Some(excess) = congestion {
+            match algo_config.delay_filter {
+                DelayFilter::Off => {}
+                DelayFilter::Discard => {
+                    debug!(excess, "Discarded measurement with congested delay");
+                    return false;
+                }
+                DelayFilter::Inflate => {
+                    // the asymmetry can be as large as the excess delay, which
+                    // shifts the offset by at most half of that
+                    extra_noise += sqr(excess / 2.);
+                }
+            }
+        }
+
+        // Filter out one-time outliers (based on delay!). A late answer to an
+        // earlier request is judged against the delay statistics, but as its
+        // delay is not representative it is not allowed to change them.
+        let is_outlier = self
+            .noise_estimator
+            .is_outlier(measurement.delay, algo_config.delay_outlier_threshold);
+        if measurement.after_retransmission && is_outlier {
+            debug!("Discarded late response to an earlier request");
+            return false;
+        }
+        if !self.prev_was_outlier && is_outlier {
             self.prev_was_outlier = true;
             return false;
         }
 
+        // Filter out single spikes in the offset, judged by how far the
+        // offsets of recent measurements were from the prediction
+        let predicted = self
+            .state
+            .progress_time(measurement.localtime, self.clock_wander, period);
+        let mut residual = measurement.offset.to_seconds() - predicted.offset();
+        if let Some(period) = period {
+            residual -= period * (residual / period).round();
+        }
+        if self.spike_filter.is_spike(
+            residual,
+            self.noise_estimator.get_noise_estimate(),
+            algo_config,
+        ) {
+            debug!(residual, "Discarded spike in the offset");
+            return false;
+        }
+
         // Environment update
         self.progress_filtertime(measurement.localtime, period);
-        self.noise_estimator.update(measurement.delay);
+        if !measurement.after_retransmission {
+            self.noise_estimator.update(measurement.delay);
+        }
 
-        let (p, weight, measurement_period) = self.absorb_measurement(measurement, period);
+        let (p, weight, measurement_period) =
+            self.absorb_measurement(measurement, extra_noise, period);
 
         self.update_wander_estimate(algo_config, p, weight);
         self.update_desired_poll(source_config, algo_config, p, weight, measurement_period);
@@ -695,6 +935,10 @@ pub(super) struct SourceState<
     N: MeasurementNoiseEstimator<MeasurementDelay = D> + Clone,
 >(SourceStateInner<D, N>);
 
+/// Slot in which the filter state of a source is left behind when the source
+/// goes away, for a later source of the same peer to continue from
+pub(super) type RetainedState<D, N> = Arc<Mutex<Option<SourceState<D, N>>>>;
+
 const MIN_DELAY: NtpDuration = NtpDuration::from_exponent(-18);
 
 impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D> + Clone>
@@ -717,7 +961,10 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         mut measurement: Measurement<D>,
         period: Option<f64>,
     ) -> bool {
-        // preprocessing
+        // preprocessing, the configured corrections are applied before the
+        // delay is clamped so a too large correction cannot make it negative
+        measurement.offset += NtpDuration::from_seconds(source_config.offset_correction);
+        measurement.delay = N::correct_delay(measurement.delay, source_config.delay_correction);
         let noise_estimator = match self {
             SourceState(SourceStateInner::Initial(filter)) => &filter.noise_estimator,
             SourceState(SourceStateInner::Stable(filter)) => &filter.noise_estimator,
@@ -755,6 +1002,8 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                         desired_poll_interval: source_config.initial_poll_interval,
                         last_measurement: measurement,
                         prev_was_outlier: false,
+                        delay_baseline: DelayBaseline::default(),
+                        spike_filter: SpikeFilter::default(),
                         last_iter: measurement.localtime,
                     }));
                     debug!("Initial source measurements complete");
@@ -797,6 +1046,7 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
         &self,
         index: Index,
         config: &AlgorithmConfig,
+        source_config: &SourceConfig,
         period: Option<f64>,
     ) -> Option<SourceSnapshot<Index>> {
         match &self.0 {
@@ -831,6 +1081,9 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                         time: last_measurement.localtime,
                     },
                     wander: config.initial_wander,
+                    trust: source_config.trust,
+                    prefer: source_config.prefer,
+                    noselect: source_config.noselect,
                 })
             }
             SourceStateInner::Stable(filter) => Some(SourceSnapshot {
@@ -843,11 +1096,43 @@ impl<D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D>
                 source_delay: filter.last_measurement.root_delay,
                 leap_indicator: filter.last_measurement.leap,
                 last_update: filter.last_iter,
+                trust: source_config.trust,
+                prefer: source_config.prefer,
+                noselect: source_config.noselect,
             }),
             _ => None,
         }
     }
 
+    /// Number of changes of the path to the source detected since the filter
+    /// was last (re)initialized
+    fn path_changes(&self) -> u32 {
+        match &self.0 {
+            SourceStateInner::Initial(_) => 0,
+            SourceStateInner::Stable(filter) => filter.delay_baseline.path_changes,
+        }
+    }
+
+    /// Number of measurements rejected as spikes since the filter was last
+    /// (re)initialized
+    fn spikes(&self) -> u32 {
+        match &self.0 {
+            SourceStateInner::Initial(_) => 0,
+            SourceStateInner::Stable(filter) => filter.spike_filter.spikes,
+        }
+    }
+
+    /// Local time of the last measurement absorbed into the state
+    pub(super) fn last_update(&self) -> Option<NtpTimestamp> {
+        match &self.0 {
+            SourceStateInner::Initial(filter) => filter
+                .last_measurement
+                .as_ref()
+                .map(|measurement| measurement.localtime),
+            SourceStateInner::Stable(filter) => Some(filter.last_iter),
+        }
+    }
+
     pub fn get_desired_poll(&self, limits: &PollIntervalLimits) -> PollInterval {
         match &self.0 {
             SourceStateInner::Initial(_) => limits.min,
@@ -892,6 +1177,7 @@ pub struct KalmanSourceController<
     period: Option<f64>,
     algo_config: AlgorithmConfig,
     source_config: SourceConfig,
+    retained: Option<RetainedState<D, N>>,
 }
 
 pub type TwoWayKalmanSourceController<SourceId> =
@@ -918,6 +1204,32 @@ impl<
             period,
             algo_config,
             source_config,
+            retained: None,
+        }
+    }
+
+    /// Continue from the given state, if any, and leave the state behind in
+    /// the slot once this controller is dropped
+    pub(super) fn retain_in(
+        mut self,
+        retained: RetainedState<D, N>,
+        state: Option<SourceState<D, N>>,
+    ) -> Self {
+        if let Some(state) = state {
+            debug!("Continuing from the filter state of a previous source");
+            self.state = state;
+        }
+        self.retained = Some(retained);
+        self
+    }
+}
+
+impl<SourceId, D: Debug + Copy + Clone, N: MeasurementNoiseEstimator<MeasurementDelay = D> + Clone>
+    Drop for KalmanSourceController<SourceId, D, N>
+{
+    fn drop(&mut self) {
+        if let Some(retained) = &self.retained {
+            *retained.lock().expect("Unexpected poisoned mutex") = Some(self.state.clone());
         }
     }
 }
@@ -940,6 +1252,9 @@ impl<
             super::KalmanControllerMessageInner::FreqChange { steer, time } => self
                 .state
                 .process_frequency_steering(time, steer, self.period),
+            super::KalmanControllerMessageInner::UpdateConfig { algo_config } => {
+                self.algo_config = algo_config;
+            }
         }
     }
 
@@ -954,7 +1269,12 @@ impl<
             self.period,
         ) {
             self.state
-                .snapshot(self.index, &self.algo_config, self.period)
+                .snapshot(
+                    self.index,
+                    &self.algo_config,
+                    &self.source_config,
+                    self.period,
+                )
                 .map(|snapshot| KalmanSourceMessage { inner: snapshot })
         } else {
             None
@@ -967,8 +1287,14 @@ impl<
     }
 
     fn observe(&self) -> super::super::ObservableSourceTimedata {
-        self.state
-            .snapshot(&self.index, &self.algo_config, self.period)
+        let timedata = self
+            .state
+            .snapshot(
+                &self.index,
+                &self.algo_config,
+                &self.source_config,
+                self.period,
+            )
             .map(|snapshot| snapshot.observe())
             .unwrap_or(ObservableSourceTimedata {
                 offset: NtpDuration::ZERO,
@@ -977,7 +1303,18 @@ impl<
                 remote_delay: NtpDuration::MAX,
                 remote_uncertainty: NtpDuration::MAX,
                 last_update: NtpTimestamp::default(),
-            })
+                path_changes: 0,
+                spikes: 0,
+                offset_correction: NtpDuration::ZERO,
+                delay_correction: NtpDuration::ZERO,
+            });
+        ObservableSourceTimedata {
+            path_changes: self.state.path_changes(),
+            spikes: self.state.spikes(),
+            offset_correction: NtpDuration::from_seconds(self.source_config.offset_correction),
+            delay_correction: NtpDuration::from_seconds(self.source_config.delay_correction),
+            ..timedata
+        }
     }
 }
 
@@ -1017,8 +1354,11 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
         source.update_self_using_measurement(
@@ -1035,6 +1375,7 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
@@ -1065,8 +1406,11 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
         source.process_offset_steering(-1800.0, None);
@@ -1084,6 +1428,7 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
@@ -1114,8 +1459,11 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
         source.process_offset_steering(1800.0, None);
@@ -1133,6 +1481,7 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
@@ -1170,15 +1519,23 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
 
         source.process_offset_steering(20e-3, None);
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset()
@@ -1208,15 +1565,23 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
 
         source.process_offset_steering(20e-3, None);
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset()
@@ -1238,6 +1603,7 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
@@ -1245,7 +1611,12 @@ mod tests {
         assert!(
             dbg!(
                 (source
-                    .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                    .snapshot(
+                        0_usize,
+                        &AlgorithmConfig::default(),
+                        &SourceConfig::default(),
+                        None
+                    )
                     .unwrap()
                     .state
                     .offset()
@@ -1255,7 +1626,12 @@ mod tests {
         );
         assert!(
             (source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency()
@@ -1286,15 +1662,23 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
 
         source.process_offset_steering(-20e-3, None);
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset()
@@ -1316,6 +1700,7 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
@@ -1323,7 +1708,12 @@ mod tests {
         assert!(
             dbg!(
                 (source
-                    .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                    .snapshot(
+                        0_usize,
+                        &AlgorithmConfig::default(),
+                        &SourceConfig::default(),
+                        None
+                    )
                     .unwrap()
                     .state
                     .offset()
@@ -1333,7 +1723,12 @@ mod tests {
         );
         assert!(
             (source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency()
@@ -1388,15 +1783,23 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
 
         source.process_offset_steering(-0.2, Some(1.0));
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 + 0.4
@@ -1406,7 +1809,12 @@ mod tests {
         source.process_offset_steering(100.5, Some(1.0));
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 - 0.1
@@ -1453,8 +1861,11 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
 
@@ -1472,12 +1883,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             Some(1.0),
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 + 0.45
@@ -1495,7 +1912,12 @@ mod tests {
         });
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .is_none()
         );
         source.update_self_using_measurement(
@@ -1512,12 +1934,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             Some(1.0),
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 .abs()
@@ -1537,12 +1965,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             Some(1.0),
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 .abs()
@@ -1562,12 +1996,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             Some(1.0),
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 .abs()
@@ -1587,12 +2027,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             Some(1.0),
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 .abs()
@@ -1612,12 +2058,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             Some(1.0),
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 .abs()
@@ -1637,12 +2089,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             Some(1.0),
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 .abs()
@@ -1662,12 +2120,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             Some(1.0),
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 .abs()
@@ -1687,12 +2151,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset()
                 .abs()
@@ -1700,7 +2170,12 @@ mod tests {
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), Some(1.0))
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    Some(1.0)
+                )
                 .unwrap()
                 .offset_uncertainty()
                 < 0.1
@@ -1739,8 +2214,11 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         };
 
@@ -1775,15 +2253,23 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         }));
 
         source.process_frequency_steering(base + NtpDuration::from_seconds(5.0), 200e-6, None);
         assert!(
             (source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency()
@@ -1793,7 +2279,12 @@ mod tests {
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset()
@@ -1803,7 +2294,12 @@ mod tests {
         source.process_frequency_steering(base + NtpDuration::from_seconds(10.0), -200e-6, None);
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency()
@@ -1812,7 +2308,12 @@ mod tests {
         );
         assert!(
             (source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset()
@@ -1834,7 +2335,12 @@ mod tests {
         let mut source = SourceState::new(noise_estimator);
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .is_none()
         );
         source.update_self_using_measurement(
@@ -1851,12 +2357,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -1876,12 +2388,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -1901,12 +2419,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -1926,12 +2450,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -1951,12 +2481,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -1976,12 +2512,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2001,12 +2543,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2026,12 +2574,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             (source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset()
@@ -2041,7 +2595,12 @@ mod tests {
         );
         assert!(
             (source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset_variance()
@@ -2073,7 +2632,12 @@ mod tests {
         let mut source = SourceState::new(AveragingBuffer::default());
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .is_none()
         );
         source.update_self_using_measurement(
@@ -2090,12 +2654,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2115,12 +2685,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2140,12 +2716,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2165,13 +2747,19 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         source.process_offset_steering(4e-3, None);
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2191,12 +2779,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2216,12 +2810,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2241,12 +2841,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .frequency_variance()
@@ -2266,12 +2872,18 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             None,
         );
         assert!(
             (source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset()
@@ -2281,7 +2893,12 @@ mod tests {
         );
         assert!(
             (source
-                .snapshot(0_usize, &AlgorithmConfig::default(), None)
+                .snapshot(
+                    0_usize,
+                    &AlgorithmConfig::default(),
+                    &SourceConfig::default(),
+                    None
+                )
                 .unwrap()
                 .state
                 .offset_variance()
@@ -2325,8 +2942,11 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         };
 
@@ -2451,8 +3071,11 @@ mod tests {
                 root_dispersion: NtpDuration::default(),
                 leap: NtpLeapIndicator::NoWarning,
                 precision: 0,
+                after_retransmission: false,
             },
             prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
             last_iter: base,
         };
 
@@ -2492,4 +3115,230 @@ mod tests {
         assert_eq!(source.precision_score, 0);
         assert!((source.clock_wander - 1e-8).abs() < 1e-12);
     }
+
+    #[test]
+    fn test_stale_measurement_horizon() {
+        let base = NtpTimestamp::from_fixed_int(0);
+        let basei = NtpInstant::now();
+        let measurement = |seconds: f64| Measurement {
+            delay: NtpDuration::from_seconds(0.0),
+            offset: NtpDuration::from_seconds(20e-3),
+            localtime: base + NtpDuration::from_seconds(seconds),
+            monotime: basei + std::time::Duration::from_secs_f64(seconds),
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+            after_retransmission: false,
+        };
+        let filter = SourceFilter {
+            state: KalmanState {
+                state: Vector::new_vector([20e-3, 0.]),
+                uncertainty: Matrix::new([[1e-6, 0.], [0., 1e-8]]),
+                time: base + NtpDuration::from_seconds(10.0),
+            },
+            clock_wander: 1e-8,
+            noise_estimator: AveragingBuffer {
+                data: [0.0, 0.0, 0.0, 0.0, 0.875e-6, 0.875e-6, 0.875e-6, 0.875e-6],
+                next_idx: 0,
+            },
+            precision_score: 0,
+            poll_score: 0,
+            desired_poll_interval: PollIntervalLimits::default().min,
+            last_measurement: measurement(0.0),
+            prev_was_outlier: false,
+            delay_baseline: DelayBaseline::default(),
+            spike_filter: SpikeFilter::default(),
+            last_iter: base,
+        };
+
+        // a measurement taken just before the filter time is still used
+        let algo_config = AlgorithmConfig::default();
+        let mut source = filter.clone();
+        assert!(source.update(
+            &SourceConfig::default(),
+            &algo_config,
+            measurement(9.5),
+            None
+        ));
+        assert_eq!(source.last_iter, base + NtpDuration::from_seconds(9.5));
+
+        // older ones are not
+        let mut source = filter.clone();
+        assert!(!source.update(
+            &SourceConfig::default(),
+            &algo_config,
+            measurement(5.0),
+            None
+        ));
+        assert_eq!(source.last_iter, base);
+
+        let algo_config = AlgorithmConfig {
+            stale_measurement_horizon: 0.0,
+            ..Default::default()
+        };
+        let mut source = filter;
+        assert!(!source.update(
+            &SourceConfig::default(),
+            &algo_config,
+            measurement(9.5),
+            None
+        ));
+        assert!(source.update(
+            &SourceConfig::default(),
+            &algo_config,
+            measurement(10.0),
+            None
+        ));
+    }
+
+    #[test]
+    fn test_delay_baseline() {
+        let algo_config = AlgorithmConfig {
+            delay_filter: DelayFilter::Discard,
+            delay_filter_window: 8,
+            ..Default::default()
+        };
+        let mut baseline = DelayBaseline::default();
+
+        // no judgement until the baseline is established
+        assert_eq!(baseline.congestion(1.0, &algo_config), None);
+        for _ in 0..4 {
+            assert_eq!(baseline.congestion(0.01, &algo_config), None);
+        }
+
+        assert_eq!(baseline.congestion(0.02, &algo_config), None);
+        let excess = baseline.congestion(0.05, &algo_config).unwrap();
+        assert!((excess - 0.04).abs() < 1e-12);
+
+        // a lasting change of path moves the baseline along
+        for _ in 0..8 {
+            baseline.congestion(0.05, &algo_config);
+        }
+        assert_eq!(baseline.congestion(0.06, &algo_config), None);
+
+        assert_eq!(baseline.congestion(1.0, &AlgorithmConfig::default()), None);
+    }
+
+    #[test]
+    fn test_path_change() {
+        let algo_config = AlgorithmConfig::default();
+        let mut baseline = DelayBaseline::default();
+        let mut measure = |delay| {
+            baseline.congestion(delay, &algo_config);
+            baseline.path_change(&algo_config)
+        };
+
+        for delay in [0.011, 0.012, 0.01, 0.013, 0.012, 0.014] {
+            assert_eq!(measure(delay), None);
+        }
+
+        // congestion of a few measurements is not a change of path
+        for _ in 0..4 {
+            assert_eq!(measure(0.05), None);
+        }
+        assert_eq!(measure(0.01), None);
+
+        // but a lasting increase of the delay is
+        for _ in 0..(PATH_CHANGE_SAMPLES - 1) {
+            assert_eq!(measure(0.03), None);
+        }
+        assert_eq!(measure(0.03), Some((0.01, 0.03)));
+        assert_eq!(measure(0.031), None);
+
+        // a shorter path shows immediately
+        assert_eq!(measure(0.015), Some((0.03, 0.015)));
+        assert_eq!(baseline.path_changes, 2);
+
+        // changes on a local network are too small to tell apart from jitter
+        let mut baseline = DelayBaseline::default();
+        for delay in [
+            1e-5, 2e-5, 1e-5, 1e-5, 5e-5, 4e-5, 5e-5, 6e-5, 5e-5, 7e-5, 5e-5, 5e-5,
+        ] {
+            baseline.congestion(delay, &algo_config);
+            assert_eq!(baseline.path_change(&algo_config), None);
+        }
+    }
+
+    #[test]
+    fn test_spike_filter() {
+        let algo_config = AlgorithmConfig {
+            spike_filter_threshold: 5.0,
+            ..Default::default()
+        };
+        let mut filter = SpikeFilter::default();
+
+        // no judgement until there are enough measurements to compare with
+        for residual in [1e-4, -1e-4, 2e-4, 0.0, -2e-4] {
+            assert!(!filter.is_spike(residual, 0.0, &algo_config));
+        }
+        assert!(filter.is_spike(5e-3, 0.0, &algo_config));
+        assert!(!filter.is_spike(1e-4, 0.0, &algo_config));
+        assert_eq!(filter.spikes, 1);
+
+        // only single measurements are rejected
+        assert!(filter.is_spike(5e-3, 0.0, &algo_config));
+        assert!(!filter.is_spike(5e-3, 0.0, &algo_config));
+        assert_eq!(filter.spikes, 2);
+
+        // deviations within the measurement noise are never spikes
+        let mut filter = SpikeFilter::default();
+        for _ in 0..SPIKE_FILTER_MIN_SAMPLES {
+            assert!(!filter.is_spike(0.0, 1e-8, &algo_config));
+        }
+        assert!(!filter.is_spike(3e-4, 1e-8, &algo_config));
+        assert!(filter.is_spike(1e-3, 1e-8, &algo_config));
+
+        assert!(!filter.is_spike(1.0, 0.0, &AlgorithmConfig::default()));
+    }
+
+    #[test]
+    fn test_measurement_corrections() {
+        let source_config = SourceConfig {
+            offset_correction: 0.002,
+            delay_correction: 0.05,
+            ..SourceConfig::default()
+        };
+        let measurement = |delay| Measurement {
+            delay: NtpDuration::from_seconds(delay),
+            offset: NtpDuration::from_seconds(0.01),
+            localtime: NtpTimestamp::from_fixed_int(0),
+            monotime: NtpInstant::now(),
+
+            stratum: 0,
+            root_delay: NtpDuration::default(),
+            root_dispersion: NtpDuration::default(),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: 0,
+            after_retransmission: false,
+        };
+
+        let mut source = SourceState::new(AveragingBuffer::default());
+        source.update_self_using_measurement(
+            &source_config,
+            &AlgorithmConfig::default(),
+            measurement(0.08),
+            None,
+        );
+        let SourceStateInner::Initial(filter) = &source.0 else {
+            panic!("Unexpected source state");
+        };
+        let last = filter.last_measurement.unwrap();
+        assert!((last.offset.to_seconds() - 0.012).abs() < 1e-9);
+        assert!((last.delay.to_seconds() - 0.03).abs() < 1e-9);
+
+        // the delay is never corrected to below the minimum
+        source.update_self_using_measurement(
+            &source_config,
+            &AlgorithmConfig::default(),
+            measurement(0.01),
+            None,
+        );
+        let SourceStateInner::Initial(filter) = &source.0 else {
+            panic!("Unexpected source state");
+        };
+        assert_eq!(filter.last_measurement.unwrap().delay, MIN_DELAY);
+    }
 }
@@ -21,6 +21,142 @@ pub struct ObservableSourceTimedata {
     pub remote_uncertainty: NtpDuration,
 
     pub last_update: NtpTimestamp,
+
+    /// Number of detected changes of the path to the source
+    #[serde(default)]
+    pub path_changes: u32,
+    /// Number of measurements of the source rejected as spikes
+    #[serde(default)]
+    pub spikes: u32,
+
+    /// Configured corrections applied to the measurements of the source
+    #[serde(default)]
+    pub offset_correction: NtpDuration,
+    #[serde(default)]
+    pub delay_correction: NtpDuration,
+}
+
+/// Outcome of the selection of sources used to steer the clock, for a single source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStatus {
+    /// Used to steer the clock, and determines the stratum and reference id of the system
+    Primary,
+    /// Used to steer the clock in combination with other sources
+    Selected,
+    /// There is no measurement from the source yet
+    #[default]
+    NoMeasurement,
+    /// The source was excluded from synchronization, e.g. because it was disabled
+    Unusable,
+    /// The source did not respond to recent polls
+    Unreachable,
+    /// The stratum of the source is not lower than our own stratum
+    Stratum,
+    /// The source uses us as its time reference
+    Loop,
+    /// The root distance of the source is too large
+    Distance,
+    /// The source is not synchronized itself
+    Unsynchronized,
+    /// The uncertainty of the source exceeds the maximum source uncertainty
+    TooUncertain,
+    /// The source disagrees with the majority of the sources
+    Falseticker,
+    /// Not enough sources agree with each other on the time
+    NoMajority,
+    /// The source is configured to be monitored only
+    NoSelect,
+}
+
+/// What the clock controller decided in a single update of the clock, with
+/// the inputs it decided on, for verifying the algorithm offline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DecisionRecord<SourceId> {
+    /// Every source with a measurement, at the time of the update
+    pub candidates: Vec<DecisionCandidate<SourceId>>,
+    /// The sources that survived selection, with their share of the
+    /// combined offset
+    pub survivors: Vec<(SourceId, f64)>,
+    /// The combined estimate, absent when there was no consensus
+    pub combined: Option<CombinedEstimate>,
+    pub action: DecisionAction,
+}
+
+impl<SourceId> DecisionRecord<SourceId> {
+    /// Replace the identifiers of the sources, e.g. by their names
+    pub fn map_sources<T>(self, mut f: impl FnMut(SourceId) -> T) -> DecisionRecord<T> {
+        DecisionRecord {
+            candidates: self
+                .candidates
+                .into_iter()
+                .map(|candidate| DecisionCandidate {
+                    source: f(candidate.source),
+                    offset: candidate.offset,
+                    uncertainty: candidate.uncertainty,
+                    delay: candidate.delay,
+                    status: candidate.status,
+                })
+                .collect(),
+            survivors: self
+                .survivors
+                .into_iter()
+                .map(|(source, weight)| (f(source), weight))
+                .collect(),
+            combined: self.combined,
+            action: self.action,
+        }
+    }
+}
+
+impl<SourceId: Ord> DecisionRecord<SourceId> {
+    /// Order the candidates and survivors by source, the controller keeps
+    /// them in no particular order
+    pub fn sort_sources(&mut self) {
+        self.candidates.sort_by(|a, b| a.source.cmp(&b.source));
+        self.survivors.sort_by(|a, b| a.0.cmp(&b.0));
+    }
+}
+
+/// A source as it was considered for selection, in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DecisionCandidate<SourceId> {
+    pub source: SourceId,
+    pub offset: f64,
+    pub uncertainty: f64,
+    pub delay: f64,
+    pub status: SelectionStatus,
+}
+
+/// The combined estimate of the selected sources, in seconds and seconds per
+/// second
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CombinedEstimate {
+    pub offset: f64,
+    pub offset_uncertainty: f64,
+    pub frequency: f64,
+    pub frequency_uncertainty: f64,
+}
+
+/// The action a clock controller took on its combined estimate
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "action")]
+pub enum DecisionAction {
+    /// The estimate is within its uncertainty of the clock
+    None,
+    /// The sources did not agree on the time
+    NoConsensus,
+    /// Steering is suspended, for maintenance or a blackout
+    Suspended,
+    /// Not enough measurements are in yet for the first correction
+    WarmingUp,
+    /// The offset of the clock is corrected by stepping or slewing
+    Offset { change: f64 },
+    /// The frequency of the clock is corrected
+    Frequency { change: f64 },
 }
 
 #[derive(Debug, Clone)]
@@ -29,10 +165,13 @@ pub struct StateUpdate<SourceId, ControllerMessage> {
     pub source_message: Option<ControllerMessage>,
     // Update to the time snapshot, if any
     pub time_snapshot: Option<TimeSnapshot>,
-    // Update to the used sources, if any
+    // Update to the used sources, if any. An empty list signals that
+    // there is no consensus on the current time.
     pub used_sources: Option<Vec<SourceId>>,
     // Requested timestamp for next non-measurement update
     pub next_update: Option<Duration>,
+    // What was decided in this update, if the clock was updated
+    pub decision: Option<DecisionRecord<SourceId>>,
 }
 
 // Note: this default implementation is necessary since the
@@ -45,10 +184,23 @@ impl<SourceId, ControllerMessage> Default for StateUpdate<SourceId, ControllerMe
             time_snapshot: None,
             used_sources: None,
             next_update: None,
+            decision: None,
         }
     }
 }
 
+/// The thresholds that decide whether an offset is corrected by stepping the
+/// clock or by slewing it, see the algorithm configuration
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StepSettings {
+    /// Offsets larger than this are stepped (s)
+    pub step_threshold: f64,
+    /// Largest frequency offset used for a slew (s/s)
+    pub slew_maximum_frequency_offset: f64,
+    /// Shortest duration of a slew (s)
+    pub slew_minimum_duration: f64,
+}
+
 pub trait TimeSyncController: Sized + Send + 'static {
     type Clock: NtpClock;
     type SourceId;
@@ -66,6 +218,9 @@ pub trait TimeSyncController: Sized + Send + 'static {
             MeasurementDelay = (),
         >;
 
+    /// Identifies the algorithm of the controller, as shown to operators
+    const ALGORITHM: &'static str;
+
     /// Create a new clock controller controlling the given clock
     fn new(
         clock: Self::Clock,
@@ -76,10 +231,14 @@ pub trait TimeSyncController: Sized + Send + 'static {
     /// Take control of the clock (should not be done in new!)
     fn take_control(&mut self) -> Result<(), <Self::Clock as NtpClock>::Error>;
 
-    /// Create a new source with given identity
+    /// Create a new source with given identity. Sources with the same peer
+    /// name are the same logical source, e.g. at a different address after
+    /// the name was resolved again, and continue from what the controller
+    /// learned from the previous source of that peer.
     fn add_source(
         &mut self,
         id: Self::SourceId,
+        peer: &str,
         source_config: SourceConfig,
     ) -> Self::NtpSourceController;
     /// Create a new one way source with given identity (used e.g. with GPS sock sources)
@@ -95,6 +254,39 @@ pub trait TimeSyncController: Sized + Send + 'static {
     /// Notify the controller that the status of a source (whether
     /// or not it is usable for synchronization) has changed.
     fn source_update(&mut self, id: Self::SourceId, usable: bool);
+    /// Suspend or resume steering of the clock. Measurements are
+    /// still processed while steering is suspended.
+    fn set_steering_suspended(&mut self, suspended: bool);
+    /// Only correct offsets by slewing, by at most the given number
+    /// of seconds at a time, until the limit is lifted with None.
+    fn set_steering_limit(&mut self, max_offset: Option<f64>);
+    /// The thresholds currently deciding between stepping and slewing the
+    /// clock, None for controllers that do not slew
+    fn step_settings(&self) -> Option<StepSettings>;
+    /// Change the thresholds deciding between stepping and slewing the clock,
+    /// until the next configuration update
+    fn set_step_settings(&mut self, settings: StepSettings);
+    /// Frequency offset of the clock needed to keep it running at the
+    /// correct rate, excluding any temporary slewing. None while the
+    /// controller has not yet determined it.
+    fn frequency_estimate(&self) -> Option<f64>;
+    /// Start from a frequency offset determined earlier, e.g. by another
+    /// instance. Once the controller has determined the frequency itself
+    /// this is ignored, and None is returned.
+    fn restore_frequency(
+        &mut self,
+        frequency: f64,
+    ) -> Option<StateUpdate<Self::SourceId, Self::ControllerMessage>>;
+    /// Take over a changed configuration, e.g. after the configuration file
+    /// was reloaded. Existing sources take over the new algorithm
+    /// configuration through the returned source message.
+    fn update_config(
+        &mut self,
+        synchronization_config: SynchronizationConfig,
+        algorithm_config: Self::AlgorithmConfig,
+    ) -> StateUpdate<Self::SourceId, Self::ControllerMessage>;
+    /// Outcome of the last selection of sources for the given source
+    fn selection_status(&self, id: Self::SourceId) -> SelectionStatus;
     /// Notify the controller of a new measurement from a source.
     /// The list of SourceIds is used for loop detection, with the
     /// first SourceId given considered the primary source used.
@@ -125,8 +317,14 @@ pub trait SourceController: Sized + Send + 'static {
 }
 
 mod kalman;
+mod replay;
 
 pub use kalman::{
     KalmanClockController, KalmanControllerMessage, KalmanSourceController, KalmanSourceMessage,
-    TwoWayKalmanSourceController, config::AlgorithmConfig,
+    TwoWayKalmanSourceController,
+    config::{AlgorithmConfig, DelayFilter},
+};
+pub use replay::{
+    RecordedDecision, RecordedMeasurement, Replay, ReplayEntry, SimulatedClock, Steering,
+    simulate_leap_second,
 };
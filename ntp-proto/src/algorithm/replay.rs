@@ -0,0 +1,694 @@
+//! Offline replay of recorded measurements through a clock controller, for
+//! tuning the algorithm and catching regressions with real-world traces.
+//!
+//! The simulated clock runs at the rate of the monotonic clock of the recording
+//! host, on top of which it applies the steering of the replayed controller.
+//! Measurements are translated to that clock through the time of the source
+//! they observed, that is the local time plus offset at the moment of recording.
+//! Leap seconds armed by the controller are applied by the simulated clock at
+//! the end of the day, as the kernel would, unless the replay ignores them.
+
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::NtpClock,
+    config::{SourceConfig, SynchronizationConfig},
+    packet::NtpLeapIndicator,
+    source::Measurement,
+    time_types::{NtpDuration, NtpInstant, NtpTimestamp},
+};
+
+use super::{DecisionRecord, SelectionStatus, SourceController, StateUpdate, TimeSyncController};
+
+const SECONDS_PER_DAY: u32 = 86400;
+
+/// A measurement of a source as recorded by the daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RecordedMeasurement {
+    /// Name of the source that was measured
+    pub source: String,
+    /// Seconds since the start of the recording, on the monotonic clock
+    pub elapsed: f64,
+    /// Time of the local clock at the measurement
+    pub localtime: NtpTimestamp,
+    pub offset: f64,
+    pub delay: f64,
+    pub stratum: u8,
+    pub root_delay: f64,
+    pub root_dispersion: f64,
+    pub leap: NtpLeapIndicator,
+    pub precision: i8,
+    #[serde(default)]
+    pub after_retransmission: bool,
+}
+
+impl RecordedMeasurement {
+    /// Record a measurement taken after `start`, the beginning of the recording
+    pub fn new(source: String, start: NtpInstant, measurement: &Measurement<NtpDuration>) -> Self {
+        RecordedMeasurement {
+            source,
+            elapsed: measurement.monotime.abs_diff(start).to_seconds(),
+            localtime: measurement.localtime,
+            offset: measurement.offset.to_seconds(),
+            delay: measurement.delay.to_seconds(),
+            stratum: measurement.stratum,
+            root_delay: measurement.root_delay.to_seconds(),
+            root_dispersion: measurement.root_dispersion.to_seconds(),
+            leap: measurement.leap,
+            precision: measurement.precision,
+            after_retransmission: measurement.after_retransmission,
+        }
+    }
+}
+
+/// A decision of the clock controller as recorded by the daemon, alongside the
+/// measurements it was based on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RecordedDecision {
+    /// Seconds since the start of the recording, on the monotonic clock
+    pub elapsed: f64,
+    pub decision: DecisionRecord<String>,
+}
+
+/// Adjustment of the simulated clock by the replayed controller
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "action")]
+pub enum Steering {
+    Step {
+        elapsed: f64,
+        offset: f64,
+    },
+    Frequency {
+        elapsed: f64,
+        frequency: f64,
+    },
+    /// A leap second applied by the clock itself
+    Leap {
+        elapsed: f64,
+        offset: f64,
+    },
+}
+
+/// What the replayed controller made of a single recorded measurement
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReplayEntry {
+    pub elapsed: f64,
+    pub source: String,
+    /// Offset of the source relative to the simulated clock
+    pub offset: f64,
+    /// Frequency offset the controller estimates for the clock
+    pub frequency_estimate: Option<f64>,
+    pub selection: SelectionStatus,
+    /// Leap second the clock is armed with, if the controller told it about any
+    pub leap_status: Option<NtpLeapIndicator>,
+    /// Steering of the clock since the previous entry
+    pub steering: Vec<Steering>,
+    /// Decisions of the controller since the previous entry, to compare with
+    /// those recorded by the daemon
+    #[serde(default)]
+    pub decisions: Vec<DecisionRecord<String>>,
+}
+
+#[derive(Debug, Default)]
+struct SimulatedClockState {
+    epoch: NtpTimestamp,
+    elapsed: f64,
+    // correction applied up to `since`, after which it grows with `frequency`
+    correction: f64,
+    frequency: f64,
+    since: f64,
+    steering: Vec<Steering>,
+    leap_status: Option<NtpLeapIndicator>,
+    ignore_leaps: bool,
+}
+
+impl SimulatedClockState {
+    fn correction(&self) -> f64 {
+        self.correction + self.frequency * (self.elapsed - self.since)
+    }
+
+    fn now(&self) -> NtpTimestamp {
+        self.epoch + NtpDuration::from_seconds(self.elapsed + self.correction())
+    }
+
+    /// Moment of the armed leap second in the day of `time`, with the change
+    /// it makes to the clock
+    fn leap_second(&self, time: NtpTimestamp) -> Option<(NtpTimestamp, f64)> {
+        let seconds = (u64::from_be_bytes(time.to_bits()) >> 32) as u32;
+        let midnight = NtpTimestamp::from_seconds_nanos_since_ntp_era(seconds, 0)
+            + NtpDuration::from_seconds((SECONDS_PER_DAY - seconds % SECONDS_PER_DAY) as f64);
+        match self.leap_status {
+            // the last second of the day is repeated
+            Some(NtpLeapIndicator::Leap61) => Some((midnight, -1.0)),
+            // the last second of the day is skipped
+            Some(NtpLeapIndicator::Leap59) => {
+                Some((midnight - NtpDuration::from_seconds(1.0), 1.0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Clock whose time is driven by the replay instead of by the system
+#[derive(Debug, Clone, Default)]
+pub struct SimulatedClock {
+    state: Arc<Mutex<SimulatedClockState>>,
+}
+
+impl SimulatedClock {
+    fn state(&self) -> std::sync::MutexGuard<'_, SimulatedClockState> {
+        self.state.lock().expect("Unexpected poisoned mutex")
+    }
+
+    /// Start the clock such that it reads `localtime` at `elapsed`
+    fn start(&self, localtime: NtpTimestamp, elapsed: f64) {
+        let mut state = self.state();
+        state.epoch = localtime - NtpDuration::from_seconds(elapsed);
+        state.elapsed = elapsed;
+        state.since = elapsed;
+    }
+
+    fn advance(&self, elapsed: f64) {
+        let mut state = self.state();
+        let leap_second = state
+            .leap_second(state.now())
+            .filter(|_| !state.ignore_leaps);
+        state.elapsed = elapsed;
+
+        if let Some((at, offset)) = leap_second
+            && !state.now().is_before(at)
+        {
+            state.correction += offset;
+            state.leap_status = Some(NtpLeapIndicator::NoWarning);
+            state.steering.push(Steering::Leap { elapsed, offset });
+        }
+    }
+
+    fn elapsed(&self) -> f64 {
+        self.state().elapsed
+    }
+
+    fn take_steering(&self) -> Vec<Steering> {
+        std::mem::take(&mut self.state().steering)
+    }
+}
+
+impl NtpClock for SimulatedClock {
+    type Error = Infallible;
+
+    fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+        Ok(self.state().now())
+    }
+
+    fn set_frequency(&self, freq: f64) -> Result<NtpTimestamp, Self::Error> {
+        let mut state = self.state();
+        state.correction = state.correction();
+        state.since = state.elapsed;
+        state.frequency = freq;
+        let elapsed = state.elapsed;
+        state.steering.push(Steering::Frequency {
+            elapsed,
+            frequency: freq,
+        });
+        Ok(state.now())
+    }
+
+    fn get_frequency(&self) -> Result<f64, Self::Error> {
+        Ok(self.state().frequency)
+    }
+
+    fn step_clock(&self, offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+        let mut state = self.state();
+        state.correction += offset.to_seconds();
+        let elapsed = state.elapsed;
+        state.steering.push(Steering::Step {
+            elapsed,
+            offset: offset.to_seconds(),
+        });
+        Ok(state.now())
+    }
+
+    fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn error_estimate_update(
+        &self,
+        _est_error: NtpDuration,
+        _max_error: NtpDuration,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn status_update(&self, leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+        self.state().leap_status = Some(leap_status);
+        Ok(())
+    }
+}
+
+/// Feeds recorded measurements through a controller steering a simulated clock.
+/// Sources are created as they first appear in the recording.
+pub struct Replay<Controller: TimeSyncController<Clock = SimulatedClock, SourceId = usize>> {
+    clock: SimulatedClock,
+    controller: Controller,
+    source_config: SourceConfig,
+    sources: Vec<(String, Controller::NtpSourceController)>,
+    start: NtpInstant,
+    next_update: Option<f64>,
+    decisions: Vec<DecisionRecord<String>>,
+}
+
+impl<Controller: TimeSyncController<Clock = SimulatedClock, SourceId = usize>> Replay<Controller> {
+    pub fn new(
+        synchronization_config: SynchronizationConfig,
+        algorithm_config: Controller::AlgorithmConfig,
+        source_config: SourceConfig,
+    ) -> Self {
+        let clock = SimulatedClock::default();
+        let mut controller =
+            match Controller::new(clock.clone(), synchronization_config, algorithm_config) {
+                Ok(controller) => controller,
+                Err(e) => match e {},
+            };
+        if let Err(e) = controller.take_control() {
+            match e {}
+        }
+
+        Replay {
+            clock,
+            controller,
+            source_config,
+            sources: vec![],
+            start: NtpInstant::now(),
+            next_update: None,
+            decisions: vec![],
+        }
+    }
+
+    /// Leave leap seconds to the algorithm, as if the kernel did not apply
+    /// them when they are due
+    pub fn ignore_leap_seconds(&mut self) {
+        self.clock.state().ignore_leaps = true;
+    }
+
+    /// Replay the next measurement of the recording, measurements should be
+    /// replayed in the order in which they were recorded.
+    pub fn measurement(&mut self, recorded: &RecordedMeasurement) -> ReplayEntry {
+        if self.sources.is_empty() {
+            self.clock.start(recorded.localtime, recorded.elapsed);
+        }
+
+        // updates the controller asked for before this measurement
+        while let Some(next_update) = self.next_update
+            && next_update <= recorded.elapsed
+        {
+            self.next_update = None;
+            self.clock.advance(next_update);
+            let update = self.controller.time_update();
+            self.handle_update(update);
+        }
+        self.clock.advance(recorded.elapsed);
+
+        let index = match self
+            .sources
+            .iter()
+            .position(|(name, _)| *name == recorded.source)
+        {
+            Some(index) => index,
+            None => {
+                let index = self.sources.len();
+                let source =
+                    self.controller
+                        .add_source(index, &recorded.source, self.source_config);
+                self.controller.source_update(index, true);
+                self.sources.push((recorded.source.clone(), source));
+                index
+            }
+        };
+
+        let localtime = match self.clock.now() {
+            Ok(now) => now,
+            Err(e) => match e {},
+        };
+        let offset = (recorded.localtime + NtpDuration::from_seconds(recorded.offset)) - localtime;
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(recorded.delay),
+            offset,
+            localtime,
+            monotime: self.start + Duration::from_secs_f64(recorded.elapsed.max(0.0)),
+
+            stratum: recorded.stratum,
+            root_delay: NtpDuration::from_seconds(recorded.root_delay),
+            root_dispersion: NtpDuration::from_seconds(recorded.root_dispersion),
+            leap: recorded.leap,
+            precision: recorded.precision,
+            after_retransmission: recorded.after_retransmission,
+        };
+
+        if let Some(message) = self.sources[index].1.handle_measurement(measurement) {
+            let update = self.controller.source_message(index, message);
+            self.handle_update(update);
+        }
+
+        ReplayEntry {
+            elapsed: recorded.elapsed,
+            source: recorded.source.clone(),
+            offset: offset.to_seconds(),
+            frequency_estimate: self.controller.frequency_estimate(),
+            selection: self.controller.selection_status(index),
+            leap_status: self.clock.state().leap_status,
+            steering: self.clock.take_steering(),
+            decisions: std::mem::take(&mut self.decisions),
+        }
+    }
+
+    fn handle_update(&mut self, update: StateUpdate<usize, Controller::ControllerMessage>) {
+        if let Some(message) = update.source_message {
+            for (_, source) in &mut self.sources {
+                source.handle_message(message.clone());
+            }
+        }
+        if let Some(next_update) = update.next_update {
+            self.next_update = Some(self.clock.elapsed() + next_update.as_secs_f64());
+        }
+        if let Some(decision) = update.decision {
+            let sources = &self.sources;
+            let mut decision = decision.map_sources(|index| sources[index].0.clone());
+            decision.sort_sources();
+            self.decisions.push(decision);
+        }
+    }
+}
+
+/// Measurements of `sources` simulated sources that agree with the clock and
+/// announce `leap` for `before` seconds, after which they apply it and run on
+/// for `after` seconds. The sources take turns every `poll_interval` divided
+/// by `sources` seconds, half a turn away from the leap second.
+///
+/// The leap second is placed at the end of 2016, whether `leap` inserts or
+/// deletes it. Returns the measurements with the seconds from the start of the
+/// recording at which the leap second happens.
+pub fn simulate_leap_second(
+    leap: NtpLeapIndicator,
+    sources: usize,
+    poll_interval: f64,
+    before: f64,
+    after: f64,
+) -> (Vec<RecordedMeasurement>, f64) {
+    // 2017-01-01T00:00:00Z, right after the last leap second
+    const MIDNIGHT: u32 = 3692217600;
+
+    let start = NtpTimestamp::from_seconds_nanos_since_ntp_era(MIDNIGHT, 0)
+        - NtpDuration::from_seconds(before);
+    // a deleted second ends the day a second early
+    let (leap_at, change) = match leap {
+        NtpLeapIndicator::Leap59 => (before - 1.0, 1.0),
+        _ => (before, -1.0),
+    };
+
+    let spacing = poll_interval / sources as f64;
+    let measurements = (0u64..)
+        .map(|i| (i, (i as f64 + 0.5) * spacing))
+        .take_while(|(_, elapsed)| *elapsed < before + after)
+        .map(|(i, elapsed)| {
+            let noise = 1e-5 * ((i * 7919) % 13) as f64 / 13.0;
+            let (leap, offset) = if elapsed < leap_at {
+                (leap, 0.0)
+            } else {
+                (NtpLeapIndicator::NoWarning, change)
+            };
+            RecordedMeasurement {
+                source: format!("simulated-{}", i as usize % sources),
+                elapsed,
+                localtime: start + NtpDuration::from_seconds(elapsed),
+                offset: offset + noise,
+                delay: 1e-3 + noise,
+                stratum: 1,
+                root_delay: 0.0,
+                root_dispersion: 0.0,
+                leap,
+                precision: -20,
+                after_retransmission: false,
+            }
+        })
+        .collect();
+
+    (measurements, leap_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::{AlgorithmConfig, KalmanClockController, leap_smear::LeapSmearState};
+
+    use super::*;
+
+    // 2017-01-01T00:00:00Z, right after the last leap second
+    const LEAP: u32 = 3692217600;
+
+    // a clock that starts 10ms behind its source and runs 20ppm slow
+    fn recording() -> Vec<RecordedMeasurement> {
+        (0..1000)
+            .map(|i| {
+                let elapsed = 16.0 * i as f64;
+                let noise = 1e-5 * ((i * 7919) % 13) as f64 / 13.0;
+                RecordedMeasurement {
+                    source: "ntp.example.com:123".into(),
+                    elapsed,
+                    localtime: NtpTimestamp::from_fixed_int(0) + NtpDuration::from_seconds(elapsed),
+                    offset: 0.01 + 20e-6 * elapsed + noise,
+                    delay: 1e-3 + noise,
+                    stratum: 1,
+                    root_delay: 0.0,
+                    root_dispersion: 0.0,
+                    leap: NtpLeapIndicator::NoWarning,
+                    precision: -20,
+                    after_retransmission: false,
+                }
+            })
+            .collect()
+    }
+
+    fn replay(recording: &[RecordedMeasurement]) -> Vec<ReplayEntry> {
+        let synchronization_config = SynchronizationConfig {
+            minimum_agreeing_sources: 1,
+            ..SynchronizationConfig::default()
+        };
+        let mut replay = Replay::<KalmanClockController<SimulatedClock, usize>>::new(
+            synchronization_config,
+            AlgorithmConfig::default(),
+            SourceConfig::default(),
+        );
+        recording
+            .iter()
+            .map(|measurement| replay.measurement(measurement))
+            .collect()
+    }
+
+    // three sources announcing a leap second for the end of the day two hours
+    // from the start, which they apply when it is due
+    fn leap_recording(leap: NtpLeapIndicator) -> Vec<RecordedMeasurement> {
+        simulate_leap_second(leap, 3, 48.0, 7200.0, 7200.0).0
+    }
+
+    fn leaps(trace: &[ReplayEntry]) -> Vec<f64> {
+        trace
+            .iter()
+            .flat_map(|entry| &entry.steering)
+            .filter_map(|steering| match steering {
+                Steering::Leap { offset, .. } => Some(*offset),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_simulated_clock() {
+        let clock = SimulatedClock::default();
+        clock.start(NtpTimestamp::from_fixed_int(0), 10.0);
+        assert_eq!(clock.now().unwrap(), NtpTimestamp::from_fixed_int(0));
+
+        clock.step_clock(NtpDuration::from_seconds(1.0)).unwrap();
+        clock.set_frequency(1e-3).unwrap();
+        clock.advance(110.0);
+        let expected = NtpTimestamp::from_fixed_int(0) + NtpDuration::from_seconds(100.0 + 1.1);
+        assert!((clock.now().unwrap() - expected).abs() < NtpDuration::from_seconds(1e-6));
+
+        assert_eq!(
+            clock.take_steering(),
+            vec![
+                Steering::Step {
+                    elapsed: 10.0,
+                    offset: 1.0
+                },
+                Steering::Frequency {
+                    elapsed: 10.0,
+                    frequency: 1e-3
+                },
+            ]
+        );
+        assert!(clock.take_steering().is_empty());
+    }
+
+    #[test]
+    fn test_simulated_clock_leap_second() {
+        let clock = SimulatedClock::default();
+        let midnight = NtpTimestamp::from_seconds_nanos_since_ntp_era(LEAP, 0);
+        clock.start(midnight - NtpDuration::from_seconds(10.0), 0.0);
+
+        clock.status_update(NtpLeapIndicator::Leap61).unwrap();
+        clock.advance(5.0);
+        assert!(clock.take_steering().is_empty());
+        clock.advance(15.0);
+        assert_eq!(
+            clock.take_steering(),
+            vec![Steering::Leap {
+                elapsed: 15.0,
+                offset: -1.0
+            }]
+        );
+        assert_eq!(
+            clock.now().unwrap(),
+            midnight + NtpDuration::from_seconds(4.0)
+        );
+        assert_eq!(clock.state().leap_status, Some(NtpLeapIndicator::NoWarning));
+
+        // a clock that ignores leap seconds keeps running
+        let clock = SimulatedClock::default();
+        clock.start(midnight - NtpDuration::from_seconds(10.0), 0.0);
+        clock.state().ignore_leaps = true;
+        clock.status_update(NtpLeapIndicator::Leap59).unwrap();
+        clock.advance(15.0);
+        assert!(clock.take_steering().is_empty());
+        assert_eq!(
+            clock.now().unwrap(),
+            midnight + NtpDuration::from_seconds(5.0)
+        );
+    }
+
+    #[test]
+    fn test_leap_second_simulation() {
+        for (leap, change) in [
+            (NtpLeapIndicator::Leap61, -1.0),
+            (NtpLeapIndicator::Leap59, 1.0),
+        ] {
+            let mut replay = Replay::<KalmanClockController<SimulatedClock, usize>>::new(
+                SynchronizationConfig::default(),
+                AlgorithmConfig::default(),
+                SourceConfig::default(),
+            );
+            let start = Instant::now();
+            let mut smear = LeapSmearState::default();
+            let mut trace = vec![];
+            let mut smear_offsets = vec![];
+            for measurement in &leap_recording(leap) {
+                let entry = replay.measurement(measurement);
+                smear_offsets.push(smear.offset(
+                    Duration::from_secs(3600),
+                    entry.leap_status.unwrap_or(NtpLeapIndicator::Unknown),
+                    start + Duration::from_secs_f64(entry.elapsed),
+                    replay.clock.now().unwrap(),
+                ));
+                trace.push(entry);
+            }
+            let before = trace.iter().filter(|entry| entry.elapsed < 7195.0).count();
+
+            // the sources agree on the leap second, so the clock is armed with it
+            assert!(
+                trace[..before]
+                    .iter()
+                    .any(|entry| entry.leap_status == Some(leap))
+            );
+
+            // and applies it once, as the sources do
+            assert_eq!(leaps(&trace), vec![change]);
+            assert_eq!(leaps(&trace[before..=before]), vec![change]);
+
+            // served time is smeared up to the full leap second, after which
+            // the clock itself has taken it over
+            let last_smear = smear_offsets[before - 1].unwrap().to_seconds();
+            assert!((last_smear - change).abs() < 0.01);
+            assert!(smear_offsets[before..].iter().all(Option::is_none));
+
+            // the filters follow the sources across the leap without steps
+            assert!(
+                trace
+                    .iter()
+                    .flat_map(|entry| &entry.steering)
+                    .all(|steering| !matches!(steering, Steering::Step { .. }))
+            );
+            assert!(
+                trace[before..]
+                    .iter()
+                    .all(|entry| entry.offset.abs() < 1e-3)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ignored_leap_second_simulation() {
+        let mut replay = Replay::<KalmanClockController<SimulatedClock, usize>>::new(
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+            SourceConfig::default(),
+        );
+        replay.ignore_leap_seconds();
+        let trace: Vec<_> = leap_recording(NtpLeapIndicator::Leap61)
+            .iter()
+            .map(|measurement| replay.measurement(measurement))
+            .collect();
+        let before = trace.iter().filter(|entry| entry.elapsed < 7195.0).count();
+
+        // without the kernel applying the leap second, the clock is a second
+        // ahead of the sources, which the algorithm has to correct for itself
+        assert!(leaps(&trace).is_empty());
+        assert!((trace[before].offset + 1.0).abs() < 1e-3);
+        assert!(
+            trace[before..]
+                .iter()
+                .flat_map(|entry| &entry.steering)
+                .any(
+                    |steering| matches!(steering, Steering::Step { offset, .. } if *offset < -0.1)
+                )
+        );
+        assert!(trace.last().unwrap().offset.abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_replay() {
+        let recording = recording();
+        let trace = replay(&recording);
+        assert_eq!(trace.len(), recording.len());
+
+        // replaying the same recording gives the same decisions
+        assert_eq!(trace, replay(&recording));
+
+        // the controller corrects both the offset and the frequency error
+        assert!(trace.iter().any(|entry| !entry.steering.is_empty()));
+        let last = trace.last().unwrap();
+        assert!(last.offset.abs() < 1e-3);
+        assert!(last.frequency_estimate.is_some());
+        assert_eq!(last.selection, SelectionStatus::Primary);
+
+        // with the inputs and outcome of every update of the clock
+        let decision = last.decisions.last().unwrap();
+        assert_eq!(decision.candidates.len(), 1);
+        assert_eq!(decision.candidates[0].source, "ntp.example.com:123");
+        assert_eq!(decision.candidates[0].status, SelectionStatus::Primary);
+        assert_eq!(
+            decision.survivors,
+            vec![("ntp.example.com:123".to_owned(), 1.0)]
+        );
+        assert!(decision.combined.is_some());
+    }
+}
@@ -40,4 +40,10 @@ pub trait NtpClock: Clone + Send + 'static {
     // Change the indicators for upcoming leap seconds and
     // the clocks synchronization status.
     fn status_update(&self, leap_status: NtpLeapIndicator) -> Result<(), Self::Error>;
+
+    // Change the offset of TAI to UTC kept with the clock, in
+    // seconds. Clocks that don't keep it ignore this.
+    fn set_tai_offset(&self, _tai_offset: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
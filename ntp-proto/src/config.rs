@@ -5,7 +5,10 @@ use serde::{
     de::{self, MapAccess, Unexpected, Visitor},
 };
 
-use crate::time_types::{NtpDuration, PollInterval, PollIntervalLimits};
+use crate::{
+    duration::{deserialize_seconds, parse_duration},
+    time_types::{NtpDuration, PollInterval, PollIntervalLimits},
+};
 
 fn deserialize_option_accumulated_step_panic_threshold<'de, D>(
     deserializer: D,
@@ -84,6 +87,19 @@ impl StepThreshold {
     }
 }
 
+/// When the clock may be stepped instead of slewed
+#[derive(Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StepPolicy {
+    /// Step whenever the offset exceeds the step threshold
+    #[default]
+    Always,
+    /// Only step for the first correction after startup, slew afterwards
+    StartupOnly,
+    /// Never step the clock, always slew
+    Never,
+}
+
 #[derive(Debug, Copy, Clone)]
 struct ThresholdPart(Option<NtpDuration>);
 
@@ -98,7 +114,7 @@ impl<'de> Deserialize<'de> for ThresholdPart {
             type Value = ThresholdPart;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("float or \"inf\"")
+                formatter.write_str("float, duration or \"inf\"")
             }
 
             fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
@@ -126,13 +142,16 @@ impl<'de> Deserialize<'de> for ThresholdPart {
             where
                 E: de::Error,
             {
-                if v != "inf" {
-                    return Err(de::Error::invalid_value(
+                if v == "inf" {
+                    return Ok(ThresholdPart(None));
+                }
+                match parse_duration(v) {
+                    Ok(seconds) => self.visit_f64(seconds),
+                    Err(_) => Err(de::Error::invalid_value(
                         de::Unexpected::Str(v),
-                        &"float or \"inf\"",
-                    ));
+                        &"float, duration or \"inf\"",
+                    )),
                 }
-                Ok(ThresholdPart(None))
             }
         }
 
@@ -153,7 +172,7 @@ impl<'de> Deserialize<'de> for StepThreshold {
             type Value = StepThreshold;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("float, map or \"inf\"")
+                formatter.write_str("float, duration, map or \"inf\"")
             }
 
             fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
@@ -194,10 +213,13 @@ impl<'de> Deserialize<'de> for StepThreshold {
                 E: de::Error,
             {
                 if v != "inf" {
-                    return Err(de::Error::invalid_value(
-                        de::Unexpected::Str(v),
-                        &"float, map or \"inf\"",
-                    ));
+                    return match parse_duration(v) {
+                        Ok(seconds) => self.visit_f64(seconds),
+                        Err(_) => Err(de::Error::invalid_value(
+                            de::Unexpected::Str(v),
+                            &"float, duration, map or \"inf\"",
+                        )),
+                    };
                 }
                 Ok(StepThreshold {
                     forward: None,
@@ -245,7 +267,7 @@ impl<'de> Deserialize<'de> for StepThreshold {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct SourceConfig {
     /// Minima and maxima for the poll interval of clients
@@ -255,6 +277,34 @@ pub struct SourceConfig {
     /// Initial poll interval of the system
     #[serde(default = "default_initial_poll_interval")]
     pub initial_poll_interval: PollInterval,
+
+    /// Send a short burst of polls when the source starts, to obtain the
+    /// first measurements quickly
+    #[serde(default)]
+    pub iburst: bool,
+
+    /// Consider this source correct during selection. It is only rejected
+    /// when it disagrees with other trusted sources
+    #[serde(default)]
+    pub trust: bool,
+
+    /// Give this source more weight when combining the selected sources
+    #[serde(default)]
+    pub prefer: bool,
+
+    /// Monitor this source, but never use it to steer the clock
+    #[serde(default)]
+    pub noselect: bool,
+
+    /// Fixed correction in seconds added to the measured offsets, for known
+    /// biases such as an asymmetric path
+    #[serde(default, deserialize_with = "deserialize_seconds")]
+    pub offset_correction: f64,
+
+    /// Fixed part of the measured delays in seconds that does not reflect the
+    /// distance to the source, such as the latency of a serial connection
+    #[serde(default, deserialize_with = "deserialize_seconds")]
+    pub delay_correction: f64,
 }
 
 impl Default for SourceConfig {
@@ -262,6 +312,12 @@ impl Default for SourceConfig {
         Self {
             poll_interval_limits: Default::default(),
             initial_poll_interval: default_initial_poll_interval(),
+            iburst: false,
+            trust: false,
+            prefer: false,
+            noselect: false,
+            offset_correction: 0.0,
+            delay_correction: 0.0,
         }
     }
 }
@@ -300,6 +356,11 @@ pub struct SynchronizationConfig {
     #[serde(default = "default_startup_step_panic_threshold")]
     pub startup_step_panic_threshold: StepThreshold,
 
+    /// When the clock may be stepped. The panic thresholds only apply to
+    /// steps, so they are never hit when stepping is not allowed.
+    #[serde(default)]
+    pub clock_step_policy: StepPolicy,
+
     /// The maximum amount distributed amongst all steps except at startup the
     /// daemon is allowed to step the system clock.
     #[serde(
@@ -314,6 +375,27 @@ pub struct SynchronizationConfig {
     #[serde(default = "default_local_stratum")]
     pub local_stratum: u8,
 
+    /// Stratum to advertise while in holdover, after all sources have been
+    /// lost. When not set, the stratum of the last used source is kept.
+    #[serde(default)]
+    pub orphan_stratum: Option<u8>,
+
+    /// Time in holdover (in seconds) after which the quality of our time is
+    /// considered degraded.
+    #[serde(
+        default = "default_holdover_max_duration",
+        deserialize_with = "deserialize_seconds"
+    )]
+    pub holdover_max_duration: f64,
+
+    /// Root dispersion (in seconds) beyond which the quality of our time in
+    /// holdover is considered degraded.
+    #[serde(
+        default = "default_holdover_max_dispersion",
+        deserialize_with = "deserialize_seconds"
+    )]
+    pub holdover_max_dispersion: f64,
+
     /// Reference ID for clock synchronization. When stratum is 1 this value
     /// is used - the value is left justified, limited to four characters
     /// and zero padded.
@@ -357,6 +439,21 @@ pub struct SynchronizationConfig {
     /// Should a warning be emitted on jumps in the clock
     #[serde(default = "default_warn_on_jump")]
     pub warn_on_jump: bool,
+
+    /// Number of measurements a source needs to have provided before it
+    /// counts towards the warmup that precedes the first clock correction.
+    #[serde(default = "default_warmup_measurements")]
+    pub warmup_measurements: usize,
+
+    /// Number of distinct warmed up sources that must agree with each other
+    /// before the clock is corrected for the first time.
+    #[serde(default = "default_warmup_sources")]
+    pub warmup_sources: usize,
+
+    /// Maximum difference between the offsets of the warmed up sources for
+    /// them to be considered in agreement.
+    #[serde(default = "default_warmup_agreement")]
+    pub warmup_agreement: NtpDuration,
 }
 
 impl Default for SynchronizationConfig {
@@ -366,12 +463,20 @@ impl Default for SynchronizationConfig {
 
             single_step_panic_threshold: default_single_step_panic_threshold(),
             startup_step_panic_threshold: default_startup_step_panic_threshold(),
+            clock_step_policy: StepPolicy::default(),
             accumulated_step_panic_threshold: None,
 
             local_stratum: default_local_stratum(),
+            orphan_stratum: None,
+            holdover_max_duration: default_holdover_max_duration(),
+            holdover_max_dispersion: default_holdover_max_dispersion(),
             reference_id: default_reference_id(),
 
             warn_on_jump: default_warn_on_jump(),
+
+            warmup_measurements: default_warmup_measurements(),
+            warmup_sources: default_warmup_sources(),
+            warmup_agreement: default_warmup_agreement(),
         }
     }
 }
@@ -380,6 +485,15 @@ fn default_minimum_agreeing_sources() -> usize {
     3
 }
 
+fn default_holdover_max_duration() -> f64 {
+    // one day
+    86400.0
+}
+
+fn default_holdover_max_dispersion() -> f64 {
+    0.1
+}
+
 fn default_reference_id() -> ReferenceIdConfig {
     ReferenceIdConfig {
         id: ['X', 'N', 'O', 'N']
@@ -411,3 +525,15 @@ fn default_local_stratum() -> u8 {
 fn default_warn_on_jump() -> bool {
     true
 }
+
+fn default_warmup_measurements() -> usize {
+    1
+}
+
+fn default_warmup_sources() -> usize {
+    1
+}
+
+fn default_warmup_agreement() -> NtpDuration {
+    NtpDuration::from_seconds(1.0)
+}
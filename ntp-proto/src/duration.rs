@@ -0,0 +1,238 @@
+//! Human friendly durations such as `250ms` or `1h30m`, as accepted in the
+//! configuration and on the command line, and as shown to users.
+
+use std::fmt;
+
+use serde::{
+    Deserializer,
+    de::{self, Visitor},
+};
+
+/// Units of a duration, with their length in seconds as a fraction, so the
+/// conversion of whole numbers of sub-second units is exact where possible
+const UNITS: &[(&str, f64, f64)] = &[
+    ("ns", 1.0, 1e9),
+    ("us", 1.0, 1e6),
+    ("µs", 1.0, 1e6),
+    ("ms", 1.0, 1e3),
+    ("s", 1.0, 1.0),
+    ("m", 60.0, 1.0),
+    ("h", 3600.0, 1.0),
+    ("d", 86400.0, 1.0),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDurationError {
+    value: String,
+}
+
+impl fmt::Display for ParseDurationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid duration: {}", self.value)
+    }
+}
+
+impl std::error::Error for ParseDurationError {}
+
+/// Parse a duration into seconds. A duration is a number of seconds, or one
+/// or more numbers each followed by a unit (`ns`, `us`, `ms`, `s`, `m`, `h` or
+/// `d`), such as `250ms`, `1.5s` or `1h30m`. It may start with a sign.
+pub fn parse_duration(value: &str) -> Result<f64, ParseDurationError> {
+    let invalid = || ParseDurationError {
+        value: value.to_owned(),
+    };
+
+    let trimmed = value.trim();
+    let (sign, mut rest) = match trimmed.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+
+    // a plain number is a number of seconds
+    if let Ok(seconds) = rest.parse::<f64>() {
+        return if seconds.is_finite() {
+            Ok(sign * seconds)
+        } else {
+            Err(invalid())
+        };
+    }
+
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0.0;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        let (number, after) = rest.split_at(number_end);
+        let unit_end = after
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(after.len());
+        let (unit, after) = after.split_at(unit_end);
+
+        let number: f64 = number.parse().map_err(|_| invalid())?;
+        let (_, multiplier, divisor) = UNITS
+            .iter()
+            .find(|(name, _, _)| *name == unit)
+            .ok_or_else(invalid)?;
+        seconds += number * multiplier / divisor;
+        rest = after;
+    }
+
+    if seconds.is_finite() {
+        Ok(sign * seconds)
+    } else {
+        Err(invalid())
+    }
+}
+
+/// Format a number of seconds the way [`parse_duration`] reads them, in the
+/// largest units that fit: `250ms`, `1.5s` or `1h30m`.
+pub fn format_duration(seconds: f64) -> String {
+    if !seconds.is_finite() {
+        return format!("{seconds}s");
+    }
+    if seconds < 0.0 {
+        return format!("-{}", format_duration(-seconds));
+    }
+
+    if seconds >= 60.0 {
+        let mut rest = seconds.round() as u64;
+        let mut formatted = String::new();
+        for (unit, unit_seconds) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)] {
+            let count = rest / unit_seconds;
+            rest %= unit_seconds;
+            if count > 0 {
+                formatted.push_str(&format!("{count}{unit}"));
+            }
+        }
+        return formatted;
+    }
+
+    let (value, unit) = match seconds {
+        s if s >= 1.0 || s == 0.0 => (s, "s"),
+        s if s >= 1e-3 => (s * 1e3, "ms"),
+        s if s >= 1e-6 => (s * 1e6, "us"),
+        s => (s * 1e9, "ns"),
+    };
+    let value = format!("{value:.3}");
+    let value = value.trim_end_matches('0').trim_end_matches('.');
+    format!("{value}{unit}")
+}
+
+/// Deserialize a number of seconds, given either as a number or as a
+/// duration string understood by [`parse_duration`]
+pub fn deserialize_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    struct SecondsVisitor;
+
+    impl Visitor<'_> for SecondsVisitor {
+        type Value = f64;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a number of seconds or a duration such as \"250ms\" or \"1h30m\"")
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+            Ok(v)
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+            Ok(v as f64)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            Ok(v as f64)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            parse_duration(v).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(SecondsVisitor)
+}
+
+/// Deserialize a whole, non-negative number of seconds, given either as a
+/// number or as a duration string understood by [`parse_duration`]
+pub fn deserialize_whole_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u64, D::Error> {
+    let seconds = deserialize_seconds(deserializer)?;
+    if seconds < 0.0 || seconds.fract() != 0.0 || seconds > u64::MAX as f64 {
+        return Err(de::Error::custom(format!(
+            "{} is not a whole number of seconds",
+            format_duration(seconds)
+        )));
+    }
+    Ok(seconds as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(90.0));
+        assert_eq!(parse_duration("0.25"), Ok(0.25));
+        assert_eq!(parse_duration("250ms"), Ok(0.25));
+        assert_eq!(parse_duration("1h30m"), Ok(5400.0));
+        assert_eq!(parse_duration("1d"), Ok(86400.0));
+        assert_eq!(parse_duration("1.5s"), Ok(1.5));
+        assert_eq!(parse_duration("-2ms"), Ok(-0.002));
+        assert_eq!(parse_duration("12.5us"), Ok(12.5e-6));
+        assert_eq!(parse_duration("300ns"), Ok(300e-9));
+
+        for invalid in ["", "ms", "30x", "1h30", "1..5s", "s1", "inf", "1 h"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(
+            parse_duration("30x").unwrap_err().to_string(),
+            "invalid duration: 30x"
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.0), "0s");
+        assert_eq!(format_duration(16.0), "16s");
+        assert_eq!(format_duration(1.5), "1.5s");
+        assert_eq!(format_duration(0.25), "250ms");
+        assert_eq!(format_duration(12.5e-6), "12.5us");
+        assert_eq!(format_duration(-0.002), "-2ms");
+        assert_eq!(format_duration(64.0), "1m4s");
+        assert_eq!(format_duration(5400.0), "1h30m");
+        assert_eq!(format_duration(90061.0), "1d1h1m1s");
+
+        for seconds in [0.25, 1.5, 5400.0, -0.002, 12.5e-6] {
+            let parsed = parse_duration(&format_duration(seconds)).unwrap();
+            assert!((parsed - seconds).abs() < 1e-12, "{seconds}");
+        }
+    }
+
+    #[test]
+    fn test_deserialize_seconds() {
+        #[derive(Deserialize)]
+        struct Test {
+            #[serde(deserialize_with = "deserialize_seconds")]
+            seconds: f64,
+            #[serde(deserialize_with = "deserialize_whole_seconds")]
+            whole: u64,
+        }
+
+        let test: Test = serde_json::from_str(r#"{"seconds": 0.5, "whole": 60}"#).unwrap();
+        assert_eq!(test.seconds, 0.5);
+        assert_eq!(test.whole, 60);
+
+        let test: Test = serde_json::from_str(r#"{"seconds": "250ms", "whole": "1h"}"#).unwrap();
+        assert_eq!(test.seconds, 0.25);
+        assert_eq!(test.whole, 3600);
+
+        assert!(serde_json::from_str::<Test>(r#"{"seconds": 1, "whole": "1.5s"}"#).is_err());
+        assert!(serde_json::from_str::<Test>(r#"{"seconds": "1x", "whole": 1}"#).is_err());
+    }
+}
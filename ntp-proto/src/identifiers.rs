@@ -14,6 +14,7 @@ impl ReferenceId {
     pub const NONE: ReferenceId = ReferenceId(u32::from_be_bytes(*b"XNON"));
     pub const SOCK: ReferenceId = ReferenceId(u32::from_be_bytes(*b"SOCK"));
     pub const PPS: ReferenceId = ReferenceId(u32::from_be_bytes(*b"PPS\0"));
+    pub const FILE: ReferenceId = ReferenceId(u32::from_be_bytes(*b"FILE"));
 
     // Network Time Security (NTS) negative-acknowledgment (NAK), from rfc8915
     pub const KISS_NTSN: ReferenceId = ReferenceId(u32::from_be_bytes(*b"NTSN"));
@@ -27,6 +28,19 @@ impl ReferenceId {
         }
     }
 
+    /// Reference id of at most four printable ASCII characters, such as the
+    /// ones identifying the reference clocks of stratum 1 servers. Shorter
+    /// names are padded with zeros.
+    pub fn from_ascii(name: &str) -> Option<ReferenceId> {
+        if name.is_empty() || name.len() > 4 || !name.bytes().all(|b| b.is_ascii_graphic()) {
+            return None;
+        }
+
+        let mut bytes = [0; 4];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Some(ReferenceId::from_bytes(bytes))
+    }
+
     pub(crate) const fn from_int(value: u32) -> ReferenceId {
         ReferenceId(value)
     }
@@ -96,4 +110,20 @@ mod tests {
         // TODO: Generate and add a testcase for ipv6 addresses once
         // we have access to an ipv6 network.
     }
+
+    #[test]
+    fn referenceid_from_ascii() {
+        assert_eq!(
+            ReferenceId::from_ascii("GPS"),
+            Some(ReferenceId::from_bytes(*b"GPS\0"))
+        );
+        assert_eq!(
+            ReferenceId::from_ascii("DCFa"),
+            Some(ReferenceId::from_bytes(*b"DCFa"))
+        );
+        assert_eq!(ReferenceId::from_ascii(""), None);
+        assert_eq!(ReferenceId::from_ascii("GOES1"), None);
+        assert_eq!(ReferenceId::from_ascii("A B"), None);
+        assert_eq!(ReferenceId::from_ascii("GPé"), None);
+    }
 }
@@ -0,0 +1,200 @@
+use std::time::{Duration, Instant};
+
+use crate::{NtpDuration, NtpLeapIndicator, NtpTimestamp};
+
+/// Seconds between the start of the NTP era (1900) and the unix epoch (1970)
+const UNIX_EPOCH_OFFSET: u32 = (70 * 365 + 17) * 86400;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// Tracks the offset that is applied to served time to smear a pending leap second.
+///
+/// The smear follows half a cosine over the configured window, ending exactly at the
+/// moment the system clock applies the leap second. After that the clock itself
+/// carries the full second and the offset returns to zero.
+#[derive(Debug, Default)]
+pub(crate) struct LeapSmearState {
+    // Last observation of the clock, to detect it repeating a second for an
+    // inserted leap second
+    last: Option<(Instant, NtpTimestamp)>,
+    // Moment (in unix seconds) of the leap second the clock already applied
+    applied: Option<u64>,
+}
+
+impl LeapSmearState {
+    /// Offset to add to the served time at `now`, or `None` when not smearing
+    pub(crate) fn offset(
+        &mut self,
+        window: Duration,
+        leap_indicator: NtpLeapIndicator,
+        instant: Instant,
+        now: NtpTimestamp,
+    ) -> Option<NtpDuration> {
+        let direction = match leap_indicator {
+            NtpLeapIndicator::Leap61 => -1.0,
+            NtpLeapIndicator::Leap59 => 1.0,
+            _ => {
+                *self = Self::default();
+                return None;
+            }
+        };
+
+        let bits = u64::from_be_bytes(now.to_bits());
+        let seconds = (bits >> 32) as u32;
+        let fraction = (bits & 0xFFFF_FFFF) as f64 / 4294967296.0;
+        let unix_seconds = seconds.wrapping_sub(UNIX_EPOCH_OFFSET) as u64;
+
+        // Leap seconds happen at the end of the last day of a month
+        let midnight = (unix_seconds / SECONDS_PER_DAY + 1) * SECONDS_PER_DAY;
+        if !is_first_of_month(midnight / SECONDS_PER_DAY) {
+            *self = Self::default();
+            return None;
+        }
+
+        // A clock inserting a leap second shows the final second of the day twice,
+        // which shows up as time going back relative to a monotonic clock.
+        if let Some((last_instant, last_now)) = self.last {
+            let expected = last_now + NtpDuration::from_system_duration(instant - last_instant);
+            if (expected - now).to_seconds() > 0.5 {
+                self.applied = Some(midnight);
+            }
+        }
+        self.last = Some((instant, now));
+
+        if self.applied == Some(midnight) {
+            return None;
+        }
+
+        // A deleted leap second is skipped one second before midnight
+        let leap_at = match leap_indicator {
+            NtpLeapIndicator::Leap59 => midnight - 1,
+            _ => midnight,
+        };
+
+        let window = window.as_secs_f64().min(SECONDS_PER_DAY as f64);
+        let remaining = (leap_at as f64 - unix_seconds as f64 - fraction).max(0.0);
+        if remaining >= window {
+            return None;
+        }
+
+        let progress = 1.0 - remaining / window;
+        let amount = (1.0 - (std::f64::consts::PI * progress).cos()) / 2.0;
+
+        Some(NtpDuration::from_seconds(direction * amount))
+    }
+}
+
+// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn is_first_of_month(days_since_unix_epoch: u64) -> bool {
+    let z = days_since_unix_epoch as i64 + 719468;
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    day == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2017-01-01T00:00:00Z, right after the leap second at the end of 2016
+    const LEAP: u32 = 1483228800;
+
+    const DAY: Duration = Duration::from_secs(86400);
+
+    fn at(unix_seconds: u32, nanos: u32) -> NtpTimestamp {
+        NtpTimestamp::from_seconds_nanos_since_ntp_era(unix_seconds + UNIX_EPOCH_OFFSET, nanos)
+    }
+
+    #[test]
+    fn first_of_month() {
+        assert!(is_first_of_month(0));
+        assert!(is_first_of_month(LEAP as u64 / SECONDS_PER_DAY));
+        assert!(!is_first_of_month(LEAP as u64 / SECONDS_PER_DAY - 1));
+        // 2016-02-29 and 2016-03-01
+        assert!(!is_first_of_month(16860));
+        assert!(is_first_of_month(16861));
+    }
+
+    #[test]
+    fn cosine_smear() {
+        let mut state = LeapSmearState::default();
+        let instant = Instant::now();
+
+        let offset = |state: &mut LeapSmearState, seconds_before: u32| {
+            state
+                .offset(
+                    DAY,
+                    NtpLeapIndicator::Leap61,
+                    instant,
+                    at(LEAP - seconds_before, 0),
+                )
+                .map(|offset| offset.to_seconds())
+        };
+
+        assert_eq!(offset(&mut state, 86401), None);
+        assert!(offset(&mut state, 86399).unwrap().abs() < 1e-6);
+        assert!((offset(&mut state, 43200).unwrap() + 0.5).abs() < 1e-6);
+        assert!((offset(&mut state, 1).unwrap() + 1.0).abs() < 1e-6);
+
+        // deleting a second runs the other way, ending one second early
+        let mut state = LeapSmearState::default();
+        let offset = state
+            .offset(DAY, NtpLeapIndicator::Leap59, instant, at(LEAP - 43201, 0))
+            .unwrap();
+        assert!((offset.to_seconds() - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn no_smear_without_pending_leap() {
+        let mut state = LeapSmearState::default();
+        let instant = Instant::now();
+
+        assert_eq!(
+            state.offset(DAY, NtpLeapIndicator::NoWarning, instant, at(LEAP - 10, 0)),
+            None
+        );
+        // not the end of a month
+        assert_eq!(
+            state.offset(
+                DAY,
+                NtpLeapIndicator::Leap61,
+                instant,
+                at(LEAP - 10 * 86400, 0)
+            ),
+            None
+        );
+        // after the leap second, the next midnight is not the end of a month
+        assert_eq!(
+            state.offset(DAY, NtpLeapIndicator::Leap61, instant, at(LEAP + 10, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn repeated_second_ends_smear() {
+        let mut state = LeapSmearState::default();
+        let instant = Instant::now();
+
+        let before = state
+            .offset(
+                DAY,
+                NtpLeapIndicator::Leap61,
+                instant,
+                at(LEAP - 1, 500_000_000),
+            )
+            .unwrap();
+        assert!((before.to_seconds() + 1.0).abs() < 1e-3);
+
+        // One second later the clock shows the same time again
+        let repeated = state.offset(
+            DAY,
+            NtpLeapIndicator::Leap61,
+            instant + Duration::from_secs(1),
+            at(LEAP - 1, 500_000_000),
+        );
+        assert_eq!(repeated, None);
+    }
+}
@@ -12,14 +12,17 @@ mod algorithm;
 mod clock;
 mod config;
 mod cookiestash;
+mod duration;
 mod identifiers;
 mod io;
 mod ipfilter;
 mod keyset;
+mod leap_smear;
 mod nts;
 mod packet;
 mod server;
 mod source;
+mod symmetric_key;
 mod system;
 mod time_types;
 
@@ -89,12 +92,19 @@ mod generic {
 
 mod exports {
     pub use super::algorithm::{
-        AlgorithmConfig, KalmanClockController, KalmanControllerMessage, KalmanSourceController,
-        KalmanSourceMessage, ObservableSourceTimedata, SourceController, StateUpdate,
-        TimeSyncController, TwoWayKalmanSourceController,
+        AlgorithmConfig, CombinedEstimate, DecisionAction, DecisionCandidate, DecisionRecord,
+        DelayFilter, KalmanClockController, KalmanControllerMessage, KalmanSourceController,
+        KalmanSourceMessage, ObservableSourceTimedata, RecordedDecision, RecordedMeasurement,
+        Replay, ReplayEntry, SelectionStatus, SimulatedClock, SourceController, StateUpdate,
+        Steering, StepSettings, TimeSyncController, TwoWayKalmanSourceController,
+        simulate_leap_second,
     };
     pub use super::clock::NtpClock;
-    pub use super::config::{SourceConfig, StepThreshold, SynchronizationConfig};
+    pub use super::config::{SourceConfig, StepPolicy, StepThreshold, SynchronizationConfig};
+    pub use super::duration::{
+        ParseDurationError, deserialize_seconds, deserialize_whole_seconds, format_duration,
+        parse_duration,
+    };
     pub use super::identifiers::ReferenceId;
     #[cfg(feature = "__internal-fuzz")]
     pub use super::ipfilter::fuzz::fuzz_ipfilter;
@@ -109,20 +119,24 @@ mod exports {
         NtpAssociationMode, NtpLeapIndicator, NtpPacket, PacketParsingError,
     };
     pub use super::server::{
-        FilterAction, FilterList, IpSubnet, Server, ServerAction, ServerConfig, ServerReason,
-        ServerResponse, ServerStatHandler, SubnetParseError,
+        ClockAdjustmentAction, FilterAction, FilterList, IpSubnet, RateLimitAction, Server,
+        ServerAction, ServerConfig, ServerReason, ServerResponse, ServerStatHandler,
+        SubnetParseError,
     };
     #[cfg(feature = "__internal-test")]
     pub use super::source::source_snapshot;
     pub use super::source::{
         AcceptSynchronizationError, Measurement, NtpSource, NtpSourceAction,
         NtpSourceActionIterator, NtpSourceSnapshot, NtpSourceUpdate, ObservableSourceState,
-        OneWaySource, OneWaySourceSnapshot, OneWaySourceUpdate, ProtocolVersion, Reach,
-        SourceNtsData,
+        OneWaySource, OneWaySourceSnapshot, OneWaySourceUpdate, ProtocolVersion, REMEMBERED_TRIES,
+        Reach, SourceNtsData, Transport,
+    };
+    pub use super::symmetric_key::{
+        SymmetricKey, SymmetricKeyError, SymmetricKeySet, SymmetricKeyType,
     };
     pub use super::system::{
-        System, SystemAction, SystemActionIterator, SystemSnapshot, SystemSourceUpdate,
-        TimeSnapshot,
+        ClockAdjustment, ClockQuality, HoldoverLimits, System, SystemAction, SystemActionIterator,
+        SystemSnapshot, SystemSourceUpdate, TimeSnapshot,
     };
 
     #[cfg(feature = "__internal-fuzz")]
@@ -134,8 +148,8 @@ mod exports {
     #[cfg(feature = "__internal-fuzz")]
     pub use super::nts::Request as KeyExchangeRequest;
     pub use super::nts::{
-        KeyExchangeClient, KeyExchangeResult, KeyExchangeServer, NtsClientConfig, NtsError,
-        NtsServerConfig,
+        AeadAlgorithm, KeyExchangeClient, KeyExchangeResult, KeyExchangeServer, NtsClientConfig,
+        NtsError, NtsServerConfig,
     };
     #[cfg(feature = "__internal-fuzz")]
     pub use super::nts::{KeyExchangeResponse, NtsRecord};
@@ -360,6 +360,12 @@ impl KeyExchangeClient {
         })
     }
 
+    /// Only offer the given AEAD algorithms, in order of preference
+    pub fn with_algorithms(mut self, algorithms: &[AeadAlgorithm]) -> Self {
+        self.algorithms = algorithms.into();
+        self
+    }
+
     pub async fn exchange_keys(
         &self,
         io: impl AsyncRead + AsyncWrite + Unpin,
@@ -387,6 +393,11 @@ impl KeyExchangeClient {
 
         let response = KeyExchangeResponse::parse(&mut io).await?;
 
+        // the server must pick one of the algorithms we offered
+        if !self.algorithms.contains(&response.algorithm) {
+            return Err(NtsError::Invalid);
+        }
+
         let keys = NtsKeys::extract_from_connection(
             io.get_ref().1,
             response.protocol,
@@ -475,6 +486,15 @@ impl KeyExchangeServer {
         })
     }
 
+    /// Only accept the given AEAD algorithms
+    pub fn with_algorithms(mut self, algorithms: &[AeadAlgorithm]) -> Self {
+        self.algorithms = algorithms
+            .iter()
+            .filter_map(|algorithm| algorithm.description())
+            .collect();
+        self
+    }
+
     pub async fn handle_longterm<T: AsyncRead + AsyncWrite + Unpin, U: AsRef<KeySet>>(
         &self,
         mut io: tokio_rustls::server::TlsStream<T>,
@@ -664,7 +684,7 @@ impl KeyExchangeServer {
                     .cloned();
                 let algorithm = algorithms
                     .iter()
-                    .find(|v| !matches!(v, AeadAlgorithm::Unknown(_)))
+                    .find(|v| self.algorithms.iter().any(|a| a.id == **v))
                     .cloned();
 
                 let result = match (protocol, algorithm) {
@@ -1160,6 +1180,57 @@ mod tests {
         assert!(matches!(serverresult, Err(NtsError::NoOverlappingProtocol)));
     }
 
+    #[tokio::test]
+    async fn test_keyexchange_roundtrip_no_algorithm_overlap() {
+        let (client, server) = tokio::io::duplex(2048);
+
+        let client = async move {
+            let certificates = tls_utils::pemfile::certs(
+                &mut include_bytes!("../../test-keys/testca.pem").as_slice(),
+            )
+            .collect::<Result<Arc<_>, _>>()
+            .unwrap();
+            let kex = KeyExchangeClient::new(NtsClientConfig {
+                certificates,
+                protocol_version: ProtocolVersion::V4,
+            })
+            .unwrap()
+            .with_algorithms(&[AeadAlgorithm::AeadAesSivCmac512]);
+            kex.exchange_keys(client, "localhost".into(), []).await
+        };
+
+        let server = async move {
+            let certificate_chain = tls_utils::pemfile::certs(
+                &mut include_bytes!("../../test-keys/end.fullchain.pem").as_slice(),
+            )
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+            let private_key = tls_utils::pemfile::private_key(
+                &mut include_bytes!("../../test-keys/end.key").as_slice(),
+            )
+            .unwrap();
+            let kex = KeyExchangeServer::new(NtsServerConfig {
+                certificate_chain,
+                private_key,
+                accepted_versions: vec![NtpVersion::V4],
+                server: None,
+                port: None,
+                pool_authentication_tokens: vec![],
+            })
+            .unwrap()
+            .with_algorithms(&[AeadAlgorithm::AeadAesSivCmac256]);
+            let keyset = KeySet::new();
+            kex.handle_connection(server, &keyset, || None::<()>).await
+        };
+
+        let (kexresult, serverresult) = tokio::join!(client, server);
+        assert!(matches!(kexresult, Err(NtsError::NoOverlappingAlgorithm)));
+        assert!(matches!(
+            serverresult,
+            Err(NtsError::NoOverlappingAlgorithm)
+        ));
+    }
+
     #[tokio::test]
     async fn test_key_exchange_roundtrip_no_cookies() {
         let (client, server) = tokio::io::duplex(2048);
@@ -32,7 +32,7 @@ impl<'a> Mac<'a> {
     pub(super) fn deserialize(
         data: &'a [u8],
     ) -> Result<Mac<'a>, ParsingError<std::convert::Infallible>> {
-        if data.len() < 4 || data.len() >= Self::MAXIMUM_SIZE {
+        if data.len() < 4 || data.len() > Self::MAXIMUM_SIZE {
             return Err(ParsingError::IncorrectLength);
         }
 
@@ -63,4 +63,13 @@ mod tests {
 
         assert_eq!(input, output);
     }
+
+    #[test]
+    fn maximum_size() {
+        let data = [0u8; Mac::MAXIMUM_SIZE];
+        assert!(Mac::deserialize(&data).is_ok());
+
+        let data = [0u8; Mac::MAXIMUM_SIZE + 1];
+        assert!(Mac::deserialize(&data).is_err());
+    }
 }
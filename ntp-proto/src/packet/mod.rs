@@ -221,7 +221,9 @@ impl NtpHeaderV3V4 {
         // In order to increase the entropy of the transmit timestamp
         // it is just a randomly generated timestamp.
         // We then expect to get it back identically from the remote
-        // in the origin field.
+        // in the origin field. As all bits are random, and not just the
+        // low-order ones, no tolerance is needed when matching the origin,
+        // and none is allowed.
         let transmit_timestamp = thread_rng().r#gen();
         packet.transmit_timestamp = transmit_timestamp;
 
@@ -1041,6 +1043,11 @@ impl<'a> NtpPacket<'a> {
         self.header
     }
 
+    /// Key id of the MAC trailing the packet, if any
+    pub fn mac_key_id(&self) -> Option<u32> {
+        self.mac.as_ref().map(|mac| mac.keyid)
+    }
+
     pub fn leap(&self) -> NtpLeapIndicator {
         match self.header {
             NtpHeader::V3(header) => header.leap,
@@ -1743,6 +1750,29 @@ mod tests {
         assert!(!response.valid_server_response(id, true));
     }
 
+    #[test]
+    fn test_origin_validation_is_exact() {
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let mut response = NtpPacket::timestamp_response(
+            &SystemSnapshot::default(),
+            packet,
+            NtpTimestamp::from_fixed_int(0),
+            &TestClock {
+                now: NtpTimestamp::from_fixed_int(2),
+            },
+        );
+        assert!(response.valid_server_response(id, false));
+
+        // A response that differs only in the lowest bit of the origin
+        // timestamp does not answer our request
+        let NtpHeader::V4(header) = &mut response.header else {
+            panic!("wrong version");
+        };
+        let origin = u64::from_be_bytes(header.origin_timestamp.to_bits());
+        header.origin_timestamp = NtpTimestamp::from_bits((origin ^ 1).to_be_bytes());
+        assert!(!response.valid_server_response(id, false));
+    }
+
     #[test]
     fn v5_upgrade_packet() {
         let (packet, _) = NtpPacket::poll_message_upgrade_request(PollInterval::default());
@@ -2382,6 +2412,23 @@ mod tests {
         assert_eq!(p, out);
     }
 
+    #[test]
+    fn push_additional_round_trip_v5() {
+        let (mut p, _) = NtpPacket::poll_message_v5(PollInterval::default());
+        for length in 0..6 {
+            p.push_additional(ExtensionField::Unknown {
+                type_id: 0x4200 + length as u16,
+                data: vec![0xAA; length].into(),
+            });
+        }
+
+        let serialized = p.serialize_without_encryption_vec(None).unwrap();
+        assert_eq!(serialized.len() % 4, 0);
+
+        let (out, _) = NtpPacket::deserialize(&serialized, &NoCipher).unwrap();
+        assert_eq!(p, out);
+    }
+
     #[test]
     fn ef_with_missing_padding_v5() {
         let (packet, _) = NtpPacket::poll_message_v5(PollInterval::default());
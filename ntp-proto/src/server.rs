@@ -10,8 +10,9 @@ use std::{
 use serde::{Deserialize, Deserializer, de};
 
 use crate::{
-    KeySet, NoCipher, NtpClock, NtpPacket, NtpTimestamp, NtpVersion, PacketParsingError,
-    SystemSnapshot, ipfilter::IpFilter,
+    ClockQuality, KeySet, NoCipher, NtpClock, NtpDuration, NtpLeapIndicator, NtpPacket,
+    NtpTimestamp, NtpVersion, PacketParsingError, ReferenceId, SymmetricKeySet, SystemSnapshot,
+    ipfilter::IpFilter, leap_smear::LeapSmearState,
 };
 
 pub enum ServerAction<'a> {
@@ -39,6 +40,8 @@ pub enum ServerResponse {
     NTSNak,
     /// Sent a deny response to client
     Deny,
+    /// Sent a rate limiting (RATE) kiss-o'-death response to client
+    RateLimit,
     /// Only for a conscious choice to not respond, error conditions are separate
     Ignore,
     /// Accepted packet and provided time to requestor
@@ -66,6 +69,30 @@ impl From<FilterAction> for ServerResponse {
     }
 }
 
+/// What to do with requests from clients exceeding the rate limit
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitAction {
+    #[default]
+    Ignore,
+    /// Respond with a RATE kiss-o'-death
+    Kod,
+}
+
+/// How to answer requests while the clock is being stepped or slewed by a
+/// large offset
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClockAdjustmentAction {
+    /// Answer as usual
+    #[default]
+    Answer,
+    /// Don't answer until the adjustment is complete
+    Ignore,
+    /// Answer with the size of the adjustment added to the root dispersion
+    RaiseDispersion,
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Deserialize)]
 pub struct FilterList {
     pub filter: Vec<IpSubnet>,
@@ -77,9 +104,23 @@ pub struct ServerConfig {
     pub denylist: FilterList,
     pub allowlist: FilterList,
     pub rate_limiting_cache_size: usize,
+    /// Average time between requests of a single client
     pub rate_limiting_cutoff: Duration,
+    /// Number of requests a client may send in quick succession
+    pub rate_limiting_burst: u32,
+    pub rate_limiting_action: RateLimitAction,
     pub require_nts: Option<FilterAction>,
     pub accepted_versions: Vec<NtpVersion>,
+    /// Smear pending leap seconds over this window before they happen
+    pub leap_smear_window: Option<Duration>,
+    pub clock_adjustment_action: ClockAdjustmentAction,
+    /// Stratum to advertise at least while in degraded holdover
+    pub degraded_stratum: Option<u8>,
+    /// Added to the root dispersion while in degraded holdover
+    pub degraded_dispersion: Option<Duration>,
+    /// Advertised instead of the reference id of the system while it is a
+    /// synchronized stratum 1 server
+    pub reference_id: Option<ReferenceId>,
 }
 
 pub struct Server<C> {
@@ -87,9 +128,54 @@ pub struct Server<C> {
     clock: C,
     denyfilter: IpFilter,
     allowfilter: IpFilter,
-    client_cache: TimestampedCache<IpAddr>,
+    client_cache: RateLimitCache<IpAddr>,
     system: SystemSnapshot,
     keyset: Arc<KeySet>,
+    symmetric_keys: Arc<SymmetricKeySet>,
+    leap_smear: LeapSmearState,
+}
+
+/// Clock that serves time with a leap smear offset applied
+#[derive(Debug, Clone)]
+struct SmearedClock<C> {
+    inner: C,
+    offset: NtpDuration,
+}
+
+impl<C: NtpClock> NtpClock for SmearedClock<C> {
+    type Error = C::Error;
+
+    fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+        Ok(self.inner.now()? + self.offset)
+    }
+
+    fn set_frequency(&self, freq: f64) -> Result<NtpTimestamp, Self::Error> {
+        self.inner.set_frequency(freq)
+    }
+
+    fn get_frequency(&self) -> Result<f64, Self::Error> {
+        self.inner.get_frequency()
+    }
+
+    fn step_clock(&self, offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+        self.inner.step_clock(offset)
+    }
+
+    fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+        self.inner.disable_ntp_algorithm()
+    }
+
+    fn error_estimate_update(
+        &self,
+        est_error: NtpDuration,
+        max_error: NtpDuration,
+    ) -> Result<(), Self::Error> {
+        self.inner.error_estimate_update(est_error, max_error)
+    }
+
+    fn status_update(&self, leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+        self.inner.status_update(leap_status)
+    }
 }
 
 // Quick estimation of ntp packet message version without doing full parsing
@@ -107,7 +193,7 @@ impl<C> Server<C> {
     ) -> Self {
         let denyfilter = IpFilter::new(&config.denylist.filter);
         let allowfilter = IpFilter::new(&config.allowlist.filter);
-        let client_cache = TimestampedCache::new(config.rate_limiting_cache_size);
+        let client_cache = RateLimitCache::new(config.rate_limiting_cache_size);
         Self {
             config,
             clock,
@@ -116,6 +202,8 @@ impl<C> Server<C> {
             client_cache,
             system,
             keyset,
+            symmetric_keys: Default::default(),
+            leap_smear: Default::default(),
         }
     }
 
@@ -128,7 +216,7 @@ impl<C> Server<C> {
             self.allowfilter = IpFilter::new(&config.allowlist.filter);
         }
         if self.config.rate_limiting_cache_size != config.rate_limiting_cache_size {
-            self.client_cache = TimestampedCache::new(config.rate_limiting_cache_size);
+            self.client_cache = RateLimitCache::new(config.rate_limiting_cache_size);
         }
         self.config = config;
     }
@@ -143,6 +231,11 @@ impl<C> Server<C> {
         self.keyset = keyset;
     }
 
+    /// Provide the server with the symmetric keys used to authenticate clients
+    pub fn update_symmetric_keys(&mut self, symmetric_keys: Arc<SymmetricKeySet>) {
+        self.symmetric_keys = symmetric_keys;
+    }
+
     fn intended_action(&mut self, client_ip: IpAddr) -> (ServerResponse, ServerReason) {
         if self.denyfilter.is_in(&client_ip) {
             // First apply denylist
@@ -154,9 +247,13 @@ impl<C> Server<C> {
             client_ip,
             Instant::now(),
             self.config.rate_limiting_cutoff,
+            self.config.rate_limiting_burst,
         ) {
             // Then ratelimit
-            (ServerResponse::Ignore, ServerReason::RateLimit)
+            match self.config.rate_limiting_action {
+                RateLimitAction::Ignore => (ServerResponse::Ignore, ServerReason::RateLimit),
+                RateLimitAction::Kod => (ServerResponse::RateLimit, ServerReason::RateLimit),
+            }
         } else {
             // Then accept
             (ServerResponse::ProvideTime, ServerReason::Policy)
@@ -178,6 +275,10 @@ impl<C: NtpClock> Server<C> {
         buffer: &'a mut [u8],
         stats_handler: &mut impl ServerStatHandler,
     ) -> ServerAction<'a> {
+        // sockets listening on both IPv4 and IPv6 report IPv4 clients by their
+        // IPv4-mapped address, which the filters and rate limiting would not
+        // recognize as the same client
+        let client_ip = client_ip.to_canonical();
         let (mut action, mut reason) = self.intended_action(client_ip);
 
         if action == ServerResponse::Ignore {
@@ -201,8 +302,8 @@ impl<C: NtpClock> Server<C> {
                 }
             },
             Err(PacketParsingError::DecryptError(packet)) => {
-                // Don't care about decryption errors when denying anyway
-                if action != ServerResponse::Deny {
+                // Don't care about decryption errors when denying or rate limiting anyway
+                if !matches!(action, ServerResponse::Deny | ServerResponse::RateLimit) {
                     action = ServerResponse::NTSNak;
                     reason = ServerReason::InvalidCrypto;
                 }
@@ -235,6 +336,28 @@ impl<C: NtpClock> Server<C> {
 
         let nts = cookie.is_some() || action == ServerResponse::NTSNak;
 
+        // Requests carrying a MAC must be signed with a key we know, and get a signed response
+        let symmetric_key = match packet.mac_key_id() {
+            Some(key_id) if !nts => match self.symmetric_keys.get(key_id) {
+                Some(key) if key.verify(message) => Some(key),
+                _ => {
+                    stats_handler.register(
+                        version.into(),
+                        nts,
+                        ServerReason::InvalidCrypto,
+                        ServerResponse::Ignore,
+                    );
+                    return ServerAction::Ignore;
+                }
+            },
+            _ => None,
+        };
+        let desired_size = message.len()
+            - symmetric_key
+                .as_ref()
+                .map(|key| key.mac_length())
+                .unwrap_or(0);
+
         // ignore non-NTS packets when configured to require NTS
         if let (false, Some(non_nts_action)) = (nts, self.config.require_nts) {
             if non_nts_action == FilterAction::Ignore {
@@ -251,6 +374,72 @@ impl<C: NtpClock> Server<C> {
             }
         }
 
+        // While the clock jumps or runs at an unusual rate, the answers of the server
+        // can be inconsistent with each other.
+        let mut system = self.system;
+        if let Some(adjustment) = system.time_snapshot.adjustment
+            && adjustment.in_progress(recv_timestamp)
+        {
+            match self.config.clock_adjustment_action {
+                ClockAdjustmentAction::Answer => {}
+                ClockAdjustmentAction::Ignore if action == ServerResponse::ProvideTime => {
+                    stats_handler.register(
+                        version.into(),
+                        nts,
+                        ServerReason::Policy,
+                        ServerResponse::Ignore,
+                    );
+                    return ServerAction::Ignore;
+                }
+                ClockAdjustmentAction::Ignore => {}
+                ClockAdjustmentAction::RaiseDispersion => {
+                    let offset = adjustment.offset.to_seconds();
+                    system.time_snapshot.root_variance_base += offset * offset;
+                }
+            }
+        }
+
+        // Clients that select between servers should be able to tell that our
+        // time is no longer to be trusted after a long holdover.
+        if system.clock_quality(recv_timestamp) == ClockQuality::DegradedHoldover {
+            if let Some(stratum) = self.config.degraded_stratum {
+                system.stratum = system.stratum.max(stratum);
+            }
+            if let Some(dispersion) = self.config.degraded_dispersion {
+                let dispersion = dispersion.as_secs_f64();
+                system.time_snapshot.root_variance_base += dispersion * dispersion;
+            }
+        }
+
+        // The reference id of a stratum 1 server names its reference clock, which
+        // means nothing while that clock does not give us the time.
+        if let Some(reference_id) = self.config.reference_id
+            && system.stratum == 1
+            && system.clock_quality(recv_timestamp) != ClockQuality::Unsynchronized
+        {
+            system.reference_id = reference_id;
+        }
+
+        // Clients of a smearing server should never see the leap second itself, so the
+        // smear offset is applied to the served timestamps and the leap is not announced.
+        let mut recv_timestamp = recv_timestamp;
+        let smear = self.config.leap_smear_window.and_then(|window| {
+            self.leap_smear.offset(
+                window,
+                system.time_snapshot.leap_indicator,
+                Instant::now(),
+                recv_timestamp,
+            )
+        });
+        if let Some(offset) = smear {
+            system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+            recv_timestamp += offset;
+        }
+        let clock = SmearedClock {
+            inner: self.clock.clone(),
+            offset: smear.unwrap_or(NtpDuration::ZERO),
+        };
+
         let mut cursor = Cursor::new(buffer);
         let result = match action {
             ServerResponse::NTSNak => {
@@ -267,13 +456,24 @@ impl<C: NtpClock> Server<C> {
                     NtpPacket::deny_response(packet).serialize(&mut cursor, &NoCipher, None)
                 }
             }
+            ServerResponse::RateLimit => {
+                if let Some(cookie) = cookie {
+                    NtpPacket::nts_rate_limit_response(packet).serialize(
+                        &mut cursor,
+                        cookie.s2c.as_ref(),
+                        None,
+                    )
+                } else {
+                    NtpPacket::rate_limit_response(packet).serialize(&mut cursor, &NoCipher, None)
+                }
+            }
             ServerResponse::ProvideTime => {
                 if let Some(cookie) = cookie {
                     NtpPacket::nts_timestamp_response(
-                        &self.system,
+                        &system,
                         packet,
                         recv_timestamp,
-                        &self.clock,
+                        &clock,
                         &cookie,
                         &self.keyset,
                     )
@@ -283,12 +483,16 @@ impl<C: NtpClock> Server<C> {
                         Some(message.len()),
                     )
                 } else {
-                    NtpPacket::timestamp_response(&self.system, packet, recv_timestamp, &self.clock)
-                        .serialize(&mut cursor, &NoCipher, Some(message.len()))
+                    NtpPacket::timestamp_response(&system, packet, recv_timestamp, &clock)
+                        .serialize(&mut cursor, &NoCipher, Some(desired_size))
                 }
             }
             ServerResponse::Ignore => unreachable!(),
         };
+        let result = match (result, symmetric_key) {
+            (Ok(()), Some(key)) => key.sign(&mut cursor),
+            (result, _) => result,
+        };
         match result {
             Ok(_) => {
                 stats_handler.register(version.into(), nts, reason, action);
@@ -311,15 +515,16 @@ impl<C: NtpClock> Server<C> {
     }
 }
 
-/// A size-bounded cache where each entry is timestamped.
+/// A size-bounded cache holding a token bucket for each entry.
 ///
-/// The planned use is in rate limiting: we keep track of when a source last checked in. If it checks
-/// in too often, we issue a rate limiting KISS code.
+/// The planned use is in rate limiting: every request of a source takes a token from its bucket,
+/// which is refilled at a fixed rate up to the burst size. If the bucket is empty, the source checks
+/// in too often and we issue a rate limiting KISS code.
 ///
 /// For this use case we want fast
 ///
-/// - lookups: for each incoming IP we must check when it last checked in
-/// - inserts: for each incoming IP we store that its most recent check-in is now
+/// - lookups: for each incoming IP we must find its bucket
+/// - inserts: for each incoming IP we store its updated bucket
 ///
 /// Hence, this data structure is a vector, and we use a simple hash function to turn the incoming
 /// address into an index. Lookups and inserts are therefore O(1).
@@ -327,12 +532,18 @@ impl<C: NtpClock> Server<C> {
 /// The likelihood of hash collisions can be controlled by changing the size of the cache. Hash collisions
 /// will happen, so this cache should not be relied on if perfect alerting is deemed critical.
 #[derive(Debug)]
-struct TimestampedCache<T> {
+struct RateLimitCache<T> {
     randomstate: RandomState,
-    elements: Vec<Option<(T, Instant)>>,
+    elements: Vec<Option<(T, TokenBucket)>>,
 }
 
-impl<T: std::hash::Hash + Eq> TimestampedCache<T> {
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl<T: std::hash::Hash + Eq> RateLimitCache<T> {
     fn new(length: usize) -> Self {
         Self {
             // looks a bit odd, but prevents a `Clone` constraint
@@ -347,28 +558,38 @@ impl<T: std::hash::Hash + Eq> TimestampedCache<T> {
         self.randomstate.hash_one(item) as usize % self.elements.len()
     }
 
-    fn is_allowed(&mut self, item: T, timestamp: Instant, cutoff: Duration) -> bool {
-        if self.elements.is_empty() {
+    fn is_allowed(&mut self, item: T, timestamp: Instant, cutoff: Duration, burst: u32) -> bool {
+        if self.elements.is_empty() || cutoff.is_zero() {
             // cache disabled, always OK
             return true;
         }
 
+        let burst = burst.max(1) as f64;
         let index = self.index(&item);
+        let slot = &mut self.elements[index];
+
+        // check if the current occupant of this slot is actually the same item, a new
+        // item (or one that was pushed out by a collision) starts with a full bucket
+        if !slot.as_ref().is_some_and(|(v, _)| &item == v) {
+            *slot = Some((
+                item,
+                TokenBucket {
+                    tokens: burst,
+                    last_update: timestamp,
+                },
+            ));
+        }
+        let (_, bucket) = slot.as_mut().expect("slot was filled above");
 
-        // check if the current occupant of this slot is actually the same item
-        let timestamp_if_same = self.elements[index]
-            .as_ref()
-            .and_then(|(v, t)| (&item == v).then_some(t))
-            .copied();
-
-        self.elements[index] = Some((item, timestamp));
+        let elapsed = timestamp.duration_since(bucket.last_update);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() / cutoff.as_secs_f64()).min(burst);
+        bucket.last_update = timestamp;
 
-        if let Some(old_timestamp) = timestamp_if_same {
-            // old and new are the same; check the time
-            timestamp.duration_since(old_timestamp) >= cutoff
-        } else {
-            // old and new are different; this is always OK
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
             true
+        } else {
+            false
         }
     }
 }
@@ -418,7 +639,14 @@ impl std::str::FromStr for IpSubnet {
         if mask > max_mask {
             return Err(SubnetParseError::Mask);
         }
-        Ok(IpSubnet { addr, mask })
+        // clients are matched by their IPv4 address, also when it was mapped
+        match addr.to_canonical() {
+            IpAddr::V4(v4) if addr.is_ipv6() && mask >= 96 => Ok(IpSubnet {
+                addr: IpAddr::V4(v4),
+                mask: mask - 96,
+            }),
+            _ => Ok(IpSubnet { addr, mask }),
+        }
     }
 }
 
@@ -437,8 +665,9 @@ mod tests {
     use std::net::{Ipv4Addr, Ipv6Addr};
 
     use crate::{
-        Cipher, DecodedServerCookie, KeySetProvider, NtpDuration, NtpLeapIndicator,
-        PollIntervalLimits, nts::AeadAlgorithm, packet::AesSivCmac256,
+        Cipher, ClockAdjustment, DecodedServerCookie, KeySetProvider, NtpDuration,
+        NtpLeapIndicator, PollIntervalLimits, TimeSnapshot, nts::AeadAlgorithm,
+        packet::AesSivCmac256,
     };
 
     use super::*;
@@ -535,8 +764,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_secs(1),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -606,8 +842,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_secs(1),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         server.update_config(config);
 
@@ -645,8 +888,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_secs(1),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -711,6 +961,20 @@ mod tests {
         assert!(packet.valid_server_response(id, false));
         assert!(packet.is_kiss_deny());
 
+        // the same client as seen by a socket listening on both IPv4 and IPv6
+        let mut buf = [0; 48];
+        server.handle(
+            "::ffff:128.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((4, false, ServerReason::Policy, ServerResponse::Deny))
+        );
+
         let config = ServerConfig {
             denylist: FilterList {
                 filter: vec!["128.0.0.0/24".parse().unwrap()],
@@ -722,8 +986,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_secs(1),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         server.update_config(config);
 
@@ -755,8 +1026,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 32,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -856,8 +1134,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
 
         server.update_config(config);
@@ -919,6 +1204,82 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_server_rate_limit_kod() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(10),
+            rate_limiting_cache_size: 32,
+            rate_limiting_burst: 2,
+            rate_limiting_action: RateLimitAction::Kod,
+            require_nts: None,
+            accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let mut stats = TestStatHandler::default();
+
+        let mut server = Server::new(
+            config,
+            clock,
+            SystemSnapshot::default(),
+            KeySetProvider::new(1).get(),
+        );
+
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_unencrypted(&packet);
+
+        // the burst allows two requests in quick succession
+        for _ in 0..2 {
+            let mut buf = [0; 48];
+            let response = server.handle(
+                "127.0.0.1".parse().unwrap(),
+                NtpTimestamp::from_fixed_int(100),
+                &serialized,
+                &mut buf,
+                &mut stats,
+            );
+            assert_eq!(
+                stats.last_register.take(),
+                Some((4, false, ServerReason::Policy, ServerResponse::ProvideTime))
+            );
+            assert!(matches!(response, ServerAction::Respond { .. }));
+        }
+
+        let mut buf = [0; 48];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((4, false, ServerReason::RateLimit, ServerResponse::RateLimit))
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+        assert!(packet.is_kiss_rate(PollIntervalLimits::default().min));
+    }
+
     #[test]
     fn test_server_ignores_non_request() {
         let config = ServerConfig {
@@ -932,8 +1293,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -987,8 +1355,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -1051,8 +1426,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         server.update_config(config);
 
@@ -1081,8 +1463,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         server.update_config(config);
 
@@ -1111,8 +1500,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         server.update_config(config);
 
@@ -1141,8 +1537,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         server.update_config(config);
 
@@ -1174,8 +1577,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: Some(FilterAction::Ignore),
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -1265,8 +1675,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_secs(1),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: Some(FilterAction::Ignore),
             accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -1364,8 +1781,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V5],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -1431,6 +1855,223 @@ mod tests {
         assert!(packet.is_kiss_deny());
     }
 
+    #[test]
+    fn test_server_leap_smear() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["127.0.0.0/24".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
+            require_nts: None,
+            accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: Some(Duration::from_secs(86400)),
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
+        };
+        // Noon on 2016-12-31, halfway through the smear for the leap second at the end of the day
+        let noon = NtpTimestamp::from_seconds_nanos_since_ntp_era(3692174400, 0);
+        let clock = TestClock { cur: noon };
+        let mut system = SystemSnapshot::default();
+        system.time_snapshot.leap_indicator = NtpLeapIndicator::Leap61;
+        let mut stats = TestStatHandler::default();
+
+        let mut server = Server::new(config, clock, system, KeySetProvider::new(1).get());
+
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_unencrypted(&packet);
+
+        let mut buf = [0; 48];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            noon,
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        let data = match response {
+            ServerAction::Ignore => panic!("Server ignored packet"),
+            ServerAction::Respond { message } => message,
+        };
+        let packet = NtpPacket::deserialize(data, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+        assert_eq!(packet.leap(), NtpLeapIndicator::NoWarning);
+        assert!(((packet.receive_timestamp() - noon).to_seconds() + 0.5).abs() < 1e-6);
+        assert!(((packet.transmit_timestamp() - noon).to_seconds() + 0.5).abs() < 1e-6);
+    }
+
+    fn test_config() -> ServerConfig {
+        ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
+            require_nts: None,
+            accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
+        }
+    }
+
+    /// Answer a single client poll received at `recv_timestamp`
+    fn poll_response(
+        config: ServerConfig,
+        system: SystemSnapshot,
+        recv_timestamp: NtpTimestamp,
+    ) -> Option<NtpPacket<'static>> {
+        let clock = TestClock {
+            cur: recv_timestamp,
+        };
+        let mut stats = TestStatHandler::default();
+        let mut server = Server::new(config, clock, system, KeySetProvider::new(1).get());
+
+        let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_unencrypted(&packet);
+
+        let mut buf = [0; 48];
+        match server.handle(
+            "127.0.0.1".parse().unwrap(),
+            recv_timestamp,
+            &serialized,
+            &mut buf,
+            &mut stats,
+        ) {
+            ServerAction::Ignore => None,
+            ServerAction::Respond { message } => Some(
+                NtpPacket::deserialize(message, &NoCipher)
+                    .unwrap()
+                    .0
+                    .into_owned(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_server_clock_adjustment() {
+        let now = NtpTimestamp::from_fixed_int(200 << 32);
+        let mut system = SystemSnapshot::default();
+        system.time_snapshot.adjustment = Some(ClockAdjustment {
+            until: now + NtpDuration::from_seconds(1.0),
+            offset: NtpDuration::from_seconds(-2.0),
+        });
+
+        let respond = |clock_adjustment_action, recv_timestamp| {
+            let config = ServerConfig {
+                clock_adjustment_action,
+                ..test_config()
+            };
+            poll_response(config, system, recv_timestamp)
+        };
+
+        let packet = respond(ClockAdjustmentAction::Answer, now).unwrap();
+        assert!(packet.root_dispersion() < NtpDuration::from_seconds(1.0));
+
+        assert!(respond(ClockAdjustmentAction::Ignore, now).is_none());
+        let later = now + NtpDuration::from_seconds(2.0);
+        assert!(respond(ClockAdjustmentAction::Ignore, later).is_some());
+
+        let packet = respond(ClockAdjustmentAction::RaiseDispersion, now).unwrap();
+        assert!(packet.root_dispersion() >= NtpDuration::from_seconds(1.99));
+        let packet = respond(ClockAdjustmentAction::RaiseDispersion, later).unwrap();
+        assert!(packet.root_dispersion() < NtpDuration::from_seconds(1.0));
+    }
+
+    #[test]
+    fn test_server_degraded_holdover() {
+        let now = NtpTimestamp::from_fixed_int(200 << 32);
+        let mut system = SystemSnapshot {
+            stratum: 2,
+            holdover: true,
+            ..Default::default()
+        };
+        system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+        system.time_snapshot.root_variance_base_time = now;
+
+        let respond = |recv_timestamp| {
+            let config = ServerConfig {
+                degraded_stratum: Some(10),
+                degraded_dispersion: Some(Duration::from_secs(2)),
+                ..test_config()
+            };
+            poll_response(config, system, recv_timestamp).expect("Server ignored packet")
+        };
+
+        // shortly after losing our sources, we answer as before
+        let packet = respond(now + NtpDuration::from_seconds(10.0));
+        assert_eq!(packet.stratum(), 2);
+        assert!(packet.root_dispersion() < NtpDuration::from_seconds(1.0));
+
+        // once the holdover lasts too long, clients are told not to trust us
+        let packet = respond(now + NtpDuration::from_seconds(2.0 * 86400.0));
+        assert_eq!(packet.stratum(), 10);
+        assert!(packet.root_dispersion() >= NtpDuration::from_seconds(1.99));
+    }
+
+    #[test]
+    fn test_server_reference_id() {
+        let now = NtpTimestamp::from_fixed_int(200 << 32);
+        let mut system = SystemSnapshot {
+            stratum: 1,
+            reference_id: ReferenceId::NONE,
+            ..Default::default()
+        };
+        system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+
+        let respond = |system, reference_id| {
+            let config = ServerConfig {
+                reference_id,
+                ..test_config()
+            };
+            poll_response(config, system, now)
+                .expect("Server ignored packet")
+                .reference_id()
+        };
+
+        assert_eq!(respond(system, None), ReferenceId::NONE);
+
+        let gps = ReferenceId::from_ascii("GPS").unwrap();
+        assert_eq!(respond(system, Some(gps)), gps);
+
+        // without time from the reference clock, its name is not advertised
+        let unsynchronized = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::Unknown,
+                ..system.time_snapshot
+            },
+            ..system
+        };
+        assert_eq!(respond(unsynchronized, Some(gps)), ReferenceId::NONE);
+
+        // nor is it by a server taking its time from another server
+        let upstream = ReferenceId::from_ascii("UPST").unwrap();
+        let stratum2 = SystemSnapshot {
+            stratum: 2,
+            reference_id: upstream,
+            ..system
+        };
+        assert_eq!(respond(stratum2, Some(gps)), upstream);
+    }
+
     #[test]
     fn test_server_ignore_version() {
         let config = ServerConfig {
@@ -1444,8 +2085,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(1000),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V3, NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         };
         let clock = TestClock {
             cur: NtpTimestamp::from_fixed_int(200),
@@ -1488,8 +2136,15 @@ mod tests {
             },
             rate_limiting_cutoff: Duration::from_millis(100),
             rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
             require_nts: None,
             accepted_versions: vec![NtpVersion::V5],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
         });
 
         let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
@@ -1511,35 +2166,185 @@ mod tests {
         assert!(matches!(response, ServerAction::Ignore));
     }
 
-    // TimestampedCache tests
+    fn serialize_packet_signed(send_packet: &NtpPacket, key: &crate::SymmetricKey) -> Vec<u8> {
+        let mut buf = vec![0; 1024];
+        let mut cursor = Cursor::new(buf.as_mut_slice());
+        send_packet.serialize(&mut cursor, &NoCipher, None).unwrap();
+        key.sign(&mut cursor).unwrap();
+
+        let end = cursor.position() as usize;
+        buf.truncate(end);
+        buf
+    }
+
+    #[test]
+    fn test_server_symmetric_key() {
+        let config = ServerConfig {
+            denylist: FilterList {
+                filter: vec![],
+                action: FilterAction::Deny,
+            },
+            allowlist: FilterList {
+                filter: vec!["0.0.0.0/0".parse().unwrap()],
+                action: FilterAction::Ignore,
+            },
+            rate_limiting_cutoff: Duration::from_secs(1),
+            rate_limiting_cache_size: 0,
+            rate_limiting_burst: 1,
+            rate_limiting_action: RateLimitAction::Ignore,
+            require_nts: None,
+            accepted_versions: vec![NtpVersion::V4],
+            leap_smear_window: None,
+            clock_adjustment_action: ClockAdjustmentAction::Answer,
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
+        };
+        let clock = TestClock {
+            cur: NtpTimestamp::from_fixed_int(200),
+        };
+        let mut stats = TestStatHandler::default();
+
+        let mut server = Server::new(
+            config,
+            clock,
+            SystemSnapshot::default(),
+            KeySetProvider::new(1).get(),
+        );
+        let keys = SymmetricKeySet::parse(
+            "1 AES128CMAC HEX:000102030405060708090a0b0c0d0e0f\n2 SHA1 unknownkey",
+        )
+        .unwrap();
+        let key = keys.get(1).unwrap();
+        let unknown = keys.get(2).unwrap();
+        server.update_symmetric_keys(Arc::new(
+            SymmetricKeySet::parse("1 AES128CMAC HEX:000102030405060708090a0b0c0d0e0f").unwrap(),
+        ));
+
+        // signed request gets a signed response of the same size
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_signed(&packet, &key);
+        let mut buf = [0; 1024];
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((4, false, ServerReason::Policy, ServerResponse::ProvideTime))
+        );
+        let ServerAction::Respond { message } = response else {
+            panic!("Server ignored packet");
+        };
+        assert_eq!(message.len(), serialized.len());
+        assert!(key.verify(message));
+        let packet = NtpPacket::deserialize(message, &NoCipher).unwrap().0;
+        assert!(packet.valid_server_response(id, false));
+        assert_eq!(packet.mac_key_id(), Some(1));
+
+        // tampered request is ignored
+        let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let mut tampered = serialize_packet_signed(&packet, &key);
+        tampered[40] ^= 1;
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &tampered,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((
+                4,
+                false,
+                ServerReason::InvalidCrypto,
+                ServerResponse::Ignore
+            ))
+        );
+        assert!(matches!(response, ServerAction::Ignore));
+
+        // request signed with a key unknown to the server is ignored
+        let (packet, _) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+        let serialized = serialize_packet_signed(&packet, &unknown);
+        let response = server.handle(
+            "127.0.0.1".parse().unwrap(),
+            NtpTimestamp::from_fixed_int(100),
+            &serialized,
+            &mut buf,
+            &mut stats,
+        );
+        assert_eq!(
+            stats.last_register.take(),
+            Some((
+                4,
+                false,
+                ServerReason::InvalidCrypto,
+                ServerResponse::Ignore
+            ))
+        );
+        assert!(matches!(response, ServerAction::Ignore));
+    }
+
+    // RateLimitCache tests
     #[test]
-    fn timestamped_cache() {
+    fn rate_limit_cache() {
         let length = 8u8;
-        let mut cache: TimestampedCache<u8> = TimestampedCache::new(length as usize);
+        let mut cache: RateLimitCache<u8> = RateLimitCache::new(length as usize);
 
         let second = Duration::from_secs(1);
         let instant = Instant::now();
 
-        assert!(cache.is_allowed(0, instant, second));
+        assert!(cache.is_allowed(0, instant, second, 1));
 
-        assert!(!cache.is_allowed(0, instant, second));
+        assert!(!cache.is_allowed(0, instant, second, 1));
 
         let later = instant + 2 * second;
-        assert!(cache.is_allowed(0, later, second));
+        assert!(cache.is_allowed(0, later, second, 1));
 
         // simulate a hash collision
         let even_later = later + 2 * second;
-        assert!(cache.is_allowed(length, even_later, second));
+        assert!(cache.is_allowed(length, even_later, second, 1));
     }
 
     #[test]
-    fn timestamped_cache_size_0() {
-        let mut cache = TimestampedCache::new(0);
+    fn rate_limit_cache_burst() {
+        let mut cache: RateLimitCache<u8> = RateLimitCache::new(8);
 
         let second = Duration::from_secs(1);
         let instant = Instant::now();
 
-        assert!(cache.is_allowed(0, instant, second));
+        assert!(cache.is_allowed(0, instant, second, 3));
+        assert!(cache.is_allowed(0, instant, second, 3));
+        assert!(cache.is_allowed(0, instant, second, 3));
+        assert!(!cache.is_allowed(0, instant, second, 3));
+
+        // tokens come back at the average rate, rejected requests don't use them
+        let later = instant + second / 2;
+        assert!(!cache.is_allowed(0, later, second, 3));
+        let later = instant + second;
+        assert!(cache.is_allowed(0, later, second, 3));
+        assert!(!cache.is_allowed(0, later, second, 3));
+
+        // but the bucket never holds more than the burst
+        let much_later = later + 100 * second;
+        for _ in 0..3 {
+            assert!(cache.is_allowed(0, much_later, second, 3));
+        }
+        assert!(!cache.is_allowed(0, much_later, second, 3));
+    }
+
+    #[test]
+    fn rate_limit_cache_size_0() {
+        let mut cache = RateLimitCache::new(0);
+
+        let second = Duration::from_secs(1);
+        let instant = Instant::now();
+
+        assert!(cache.is_allowed(0, instant, second, 1));
     }
 
     // IpSubnet parsing tests
@@ -1614,6 +2419,13 @@ mod tests {
                 mask: 128
             })
         );
+        assert_eq!(
+            IpSubnet::from_str("::ffff:10.0.0.0/104"),
+            Ok(IpSubnet {
+                addr: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)),
+                mask: 8
+            })
+        );
 
         assert_eq!(
             serde_json::from_str::<IpSubnet>(r#""::/0""#).unwrap(),
@@ -6,11 +6,12 @@ use crate::{
     },
 };
 use crate::{
-    algorithm::{ObservableSourceTimedata, SourceController},
+    algorithm::{ObservableSourceTimedata, SelectionStatus, SourceController},
     config::SourceConfig,
     cookiestash::CookieStash,
     identifiers::ReferenceId,
     packet::{Cipher, NtpAssociationMode, NtpLeapIndicator, NtpPacket, RequestIdentifier},
+    symmetric_key::SymmetricKey,
     system::{SystemSnapshot, SystemSourceUpdate},
     time_types::{NtpDuration, NtpInstant, NtpTimestamp, PollInterval},
 };
@@ -20,6 +21,7 @@ use std::{
     fmt::Debug,
     io::Cursor,
     net::{IpAddr, SocketAddr},
+    sync::Arc,
     time::Duration,
 };
 use tracing::{debug, trace, warn};
@@ -28,6 +30,14 @@ const MAX_STRATUM: u8 = 16;
 const POLL_WINDOW: std::time::Duration = std::time::Duration::from_secs(5);
 const STARTUP_TRIES_THRESHOLD: usize = 3;
 const AFTER_UPGRADE_TRIES_THRESHOLD: u32 = 2;
+// Number of polls, and the time between them, sent at startup with iburst
+const IBURST_POLLS: u8 = 6;
+const IBURST_SPACING: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Number of most recent tries to a source of which an answer is accepted.
+/// When an answer to an earlier try arrives after the next one was sent, it is
+/// paired with the send timestamp of that earlier try.
+pub const REMEMBERED_TRIES: usize = 2;
 
 pub struct SourceNtsData {
     pub(crate) cookies: CookieStash,
@@ -60,6 +70,8 @@ impl std::fmt::Debug for SourceNtsData {
 #[derive(Debug)]
 pub struct NtpSource<Controller: SourceController<MeasurementDelay = NtpDuration>> {
     nts: Option<Box<SourceNtsData>>,
+    // Key used to authenticate packets with a MAC (RFC 5905 symmetric keys)
+    symmetric_key: Option<Arc<SymmetricKey>>,
 
     // Poll interval used when sending last poll message.
     last_poll_interval: PollInterval,
@@ -67,10 +79,11 @@ pub struct NtpSource<Controller: SourceController<MeasurementDelay = NtpDuration
     // Must be increased when the server sends the RATE kiss code.
     remote_min_poll_interval: PollInterval,
 
-    // Identifier of the last request sent to the server. This is correlated
-    // with any received response from the server to guard against replay
-    // attacks and packet reordering.
-    current_request_identifier: Option<(RequestIdentifier, NtpInstant)>,
+    // Identifiers of the last requests sent to the server, newest first. These
+    // are correlated with any received response from the server to guard
+    // against replay attacks and packet reordering, and to tell which request
+    // a response answers.
+    request_identifiers: [Option<(RequestIdentifier, NtpInstant)>; REMEMBERED_TRIES],
 
     // Whether we have seen a DENY/RSTR KISS response since the last succesfull
     // interaction
@@ -83,6 +96,8 @@ pub struct NtpSource<Controller: SourceController<MeasurementDelay = NtpDuration
     source_id: ReferenceId,
     reach: Reach,
     tries: usize,
+    // Polls of the startup burst that still need to be sent
+    burst_remaining: u8,
 
     controller: Controller,
 
@@ -127,6 +142,10 @@ impl<Controller: SourceController<MeasurementDelay = ()>> OneWaySource<Controlle
             unanswered_polls: 0,
             poll_interval: crate::time_types::PollInterval::from_byte(0),
             nts_cookies: None,
+            stratum: 0,
+            reach: Reach::default(),
+            selection: SelectionStatus::default(),
+            transport: Transport::Udp,
             name,
             address,
             id,
@@ -146,6 +165,10 @@ pub struct Measurement<D: Debug + Copy + Clone> {
     pub root_dispersion: NtpDuration,
     pub leap: NtpLeapIndicator,
     pub precision: i8,
+
+    /// Answer to a request after which another was already sent, so the
+    /// delay is most likely not representative for the source
+    pub after_retransmission: bool,
 }
 
 impl Measurement<NtpDuration> {
@@ -169,6 +192,7 @@ impl Measurement<NtpDuration> {
             root_dispersion: packet.root_dispersion(),
             leap: packet.leap(),
             precision: packet.precision(),
+            after_retransmission: false,
         }
     }
 }
@@ -218,6 +242,11 @@ impl Reach {
     pub fn unanswered_polls(&self) -> u32 {
         self.0.trailing_zeros()
     }
+
+    /// The shift register itself, the lowest bit corresponds to the most recent poll
+    pub fn register(&self) -> u8 {
+        self.0
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -246,6 +275,9 @@ pub struct NtpSourceSnapshot {
     pub source_id: ReferenceId,
 
     pub poll_interval: PollInterval,
+    /// Lowest poll interval the source accepts, when its rate limiting raised
+    /// this above the configured minimum
+    pub remote_min_poll_interval: Option<PollInterval>,
     pub reach: Reach,
 
     pub stratum: u8,
@@ -315,6 +347,9 @@ impl NtpSourceSnapshot {
             reference_id: source.reference_id,
             reach: source.reach,
             poll_interval: source.last_poll_interval,
+            remote_min_poll_interval: (source.remote_min_poll_interval
+                > source.source_config.poll_interval_limits.min)
+                .then_some(source.remote_min_poll_interval),
             protocol_version: source.protocol_version,
             bloom_filter: source.bloom_filter.full_filter().copied(),
         }
@@ -336,12 +371,13 @@ pub fn source_snapshot() -> NtpSourceSnapshot {
 
         reach,
         poll_interval: crate::time_types::PollIntervalLimits::default().min,
+        remote_min_poll_interval: None,
         protocol_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
         bloom_filter: None,
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum AcceptSynchronizationError {
     ServerUnreachable,
@@ -350,6 +386,17 @@ pub enum AcceptSynchronizationError {
     Stratum,
 }
 
+impl From<AcceptSynchronizationError> for SelectionStatus {
+    fn from(value: AcceptSynchronizationError) -> Self {
+        match value {
+            AcceptSynchronizationError::ServerUnreachable => SelectionStatus::Unreachable,
+            AcceptSynchronizationError::Loop => SelectionStatus::Loop,
+            AcceptSynchronizationError::Distance => SelectionStatus::Distance,
+            AcceptSynchronizationError::Stratum => SelectionStatus::Stratum,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ProtocolVersion {
     V4,
@@ -383,6 +430,7 @@ impl ProtocolVersion {
 pub struct NtpSourceUpdate<SourceMessage> {
     pub(crate) snapshot: NtpSourceSnapshot,
     pub(crate) message: Option<SourceMessage>,
+    pub(crate) measurement: Option<Measurement<NtpDuration>>,
 }
 
 impl<SourceMessage: Debug> std::fmt::Debug for NtpSourceUpdate<SourceMessage> {
@@ -390,6 +438,7 @@ impl<SourceMessage: Debug> std::fmt::Debug for NtpSourceUpdate<SourceMessage> {
         f.debug_struct("NtpSourceUpdate")
             .field("snapshot", &self.snapshot)
             .field("message", &self.message)
+            .field("measurement", &self.measurement)
             .finish()
     }
 }
@@ -399,16 +448,39 @@ impl<SourceMessage: Clone> Clone for NtpSourceUpdate<SourceMessage> {
         Self {
             snapshot: self.snapshot,
             message: self.message.clone(),
+            measurement: self.measurement,
         }
     }
 }
 
+impl<SourceMessage> NtpSourceUpdate<SourceMessage> {
+    /// Whether the source answered any of its recent polls
+    pub fn is_reachable(&self) -> bool {
+        self.snapshot.reach.is_reachable()
+    }
+
+    pub fn source_addr(&self) -> SocketAddr {
+        self.snapshot.source_addr
+    }
+
+    /// Lowest poll interval the source accepts, when raised by its rate limiting
+    pub fn remote_min_poll_interval(&self) -> Option<PollInterval> {
+        self.snapshot.remote_min_poll_interval
+    }
+
+    /// The measurement this update resulted from, if any
+    pub fn measurement(&self) -> Option<Measurement<NtpDuration>> {
+        self.measurement
+    }
+}
+
 #[cfg(feature = "__internal-test")]
 impl<SourceMessage> NtpSourceUpdate<SourceMessage> {
     pub fn snapshot(snapshot: NtpSourceSnapshot) -> Self {
         NtpSourceUpdate {
             snapshot,
             message: None,
+            measurement: None,
         }
     }
 }
@@ -472,11 +544,34 @@ pub struct ObservableSourceState<SourceId> {
     pub unanswered_polls: u32,
     pub poll_interval: PollInterval,
     pub nts_cookies: Option<usize>,
+    #[serde(default)]
+    pub stratum: u8,
+    #[serde(default)]
+    pub reach: Reach,
+    /// Filled in by the system, as the source itself does not know the outcome
+    #[serde(default)]
+    pub selection: SelectionStatus,
+    /// Filled in by the daemon, which sets up the connection to the source
+    #[serde(default)]
+    pub transport: Transport,
     pub name: String,
     pub address: String,
     pub id: SourceId,
 }
 
+/// Transport over which packets are exchanged with a source
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    #[default]
+    Udp,
+    /// Packets in a TCP connection, for networks that block NTP over UDP.
+    /// Retransmissions and buffering make these measurements less accurate.
+    Tcp,
+    /// Packets in a TLS connection, with the same limitations as TCP
+    Tls,
+}
+
 impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Controller> {
     pub(crate) fn new(
         source_addr: SocketAddr,
@@ -484,21 +579,35 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
         protocol_version: ProtocolVersion,
         controller: Controller,
         nts: Option<Box<SourceNtsData>>,
+        symmetric_key: Option<Arc<SymmetricKey>>,
     ) -> (Self, NtpSourceActionIterator<Controller::SourceMessage>) {
+        // MACs are only defined for NTPv4 packets
+        let protocol_version = if symmetric_key.is_some() {
+            ProtocolVersion::V4
+        } else {
+            protocol_version
+        };
+
         (
             Self {
                 nts,
+                symmetric_key,
 
                 last_poll_interval: source_config.poll_interval_limits.min,
                 remote_min_poll_interval: source_config.poll_interval_limits.min,
 
                 have_deny_rstr_response: false,
 
-                current_request_identifier: None,
+                request_identifiers: Default::default(),
                 source_id: ReferenceId::from_ip(source_addr.ip()),
                 source_addr,
                 reach: Default::default(),
                 tries: 0,
+                burst_remaining: if source_config.iburst {
+                    IBURST_POLLS
+                } else {
+                    0
+                },
 
                 stratum: 16,
                 reference_id: ReferenceId::NONE,
@@ -508,7 +617,7 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
 
                 buffer: [0; 1024],
 
-                protocol_version, // TODO make this configurable
+                protocol_version,
 
                 bloom_filter: RemoteBloomFilter::new(16).expect("16 is a valid chunk size"),
             },
@@ -522,12 +631,35 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
             unanswered_polls: self.reach.unanswered_polls(),
             poll_interval: self.last_poll_interval,
             nts_cookies: self.nts.as_ref().map(|nts| nts.cookies.len()),
+            stratum: self.stratum,
+            reach: self.reach,
+            selection: SelectionStatus::default(),
+            transport: Transport::Udp,
             name,
             address: self.source_addr.to_string(),
             id,
         }
     }
 
+    /// Restore the minimum poll interval the source requested in an earlier run,
+    /// so a restart does not trigger its rate limiting again. This never lowers the
+    /// minimum, and is capped at the maximum of the configured poll interval limits.
+    /// A startup burst would exceed the restored rate limit, so it is skipped.
+    pub fn restore_remote_min_poll_interval(&mut self, interval: PollInterval) {
+        let max = self.source_config.poll_interval_limits.max;
+        if interval > max {
+            warn!(
+                ?interval,
+                ?max,
+                "Remembered rate limit of source exceeds the maximum poll interval, polling at the maximum instead"
+            );
+        }
+        self.remote_min_poll_interval = Ord::max(self.remote_min_poll_interval, interval.min(max));
+        if self.remote_min_poll_interval > self.source_config.poll_interval_limits.min {
+            self.burst_remaining = 0;
+        }
+    }
+
     pub fn current_poll_interval(&self) -> PollInterval {
         self.controller
             .desired_poll_interval()
@@ -596,7 +728,8 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
                 }
             },
         };
-        self.current_request_identifier = Some((identifier, NtpInstant::now() + POLL_WINDOW));
+        self.request_identifiers.rotate_right(1);
+        self.request_identifiers[0] = Some((identifier, NtpInstant::now() + POLL_WINDOW));
 
         if let NtpHeader::V5(header) = packet.header() {
             let req_ef = self.bloom_filter.next_request(header.client_cookie);
@@ -606,6 +739,21 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
         // update the poll interval
         self.last_poll_interval = poll_interval;
 
+        // Only continue a startup burst while the source is answering, an
+        // unreachable source gets a single extra try
+        if self.tries >= 2 && !self.reach.is_reachable() {
+            self.burst_remaining = 0;
+        }
+        self.burst_remaining = self.burst_remaining.saturating_sub(1);
+        let next_poll = if self.burst_remaining > 0 {
+            IBURST_SPACING
+        } else {
+            // randomize the poll interval a little to make it harder to predict poll requests
+            poll_interval
+                .as_system_duration()
+                .mul_f64(thread_rng().gen_range(1.01..=1.05))
+        };
+
         let snapshot = NtpSourceSnapshot::from_source(self);
 
         // Write packet to buffer
@@ -617,6 +765,10 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
                 None,
             )
             .expect("Internal error: could not serialize packet");
+        if let Some(key) = &self.symmetric_key {
+            key.sign(&mut cursor)
+                .expect("Internal error: could not sign packet");
+        }
         let used = cursor.position();
         let result = &cursor.into_inner()[..used as usize];
 
@@ -624,14 +776,10 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
             NtpSourceAction::Send(result.into()),
             NtpSourceAction::UpdateSystem(NtpSourceUpdate {
                 snapshot,
-                message: None
+                message: None,
+                measurement: None,
             }),
-            // randomize the poll interval a little to make it harder to predict poll requests
-            NtpSourceAction::SetTimer(
-                poll_interval
-                    .as_system_duration()
-                    .mul_f64(thread_rng().gen_range(1.01..=1.05))
-            )
+            NtpSourceAction::SetTimer(next_poll)
         )
     }
 
@@ -643,13 +791,24 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
         actions!()
     }
 
+    /// Handle a response of the source.
+    ///
+    /// `send_times` are the times at which the most recent requests were sent,
+    /// newest first and `None` for requests that were not sent.
     pub fn handle_incoming(
         &mut self,
         message: &[u8],
         local_clock_time: NtpInstant,
-        send_time: NtpTimestamp,
+        send_times: &[Option<NtpTimestamp>],
         recv_time: NtpTimestamp,
     ) -> NtpSourceActionIterator<Controller::SourceMessage> {
+        if let Some(key) = &self.symmetric_key
+            && !key.verify(message)
+        {
+            warn!(key_id = key.id(), "received packet without a valid MAC");
+            return actions!();
+        }
+
         let message =
             match NtpPacket::deserialize(message, &self.nts.as_ref().map(|nts| nts.s2c.as_ref())) {
                 Ok((packet, _)) => packet,
@@ -671,44 +830,45 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
             return actions!();
         }
 
-        let request_identifier = match self.current_request_identifier {
-            Some((next_expected_origin, validity)) if validity >= NtpInstant::now() => {
-                next_expected_origin
-            }
-            _ => {
-                debug!("Received old/unexpected packet from source");
-                return actions!();
-            }
+        // Packets should be a response to a recent request from us, if not
+        // just ignore. Note that this might also happen when we reset between
+        // sending the request and receiving the response. We do this before
+        // anything else since accepting even a KISS packet that is not a
+        // response will leave us vulnerable to denial of service attacks.
+        let now = NtpInstant::now();
+        let Some(tries_ago) = self.request_identifiers.iter().position(|request| {
+            matches!(request, Some((identifier, validity))
+                if *validity >= now && message.valid_server_response(*identifier, self.nts.is_some()))
+        }) else {
+            debug!("Received old/unexpected packet from source");
+            return actions!();
         };
 
-        if message.valid_server_response(request_identifier, self.nts.is_some()) {
-            if let ProtocolVersion::V4UpgradingToV5 { tries_left } = self.protocol_version {
-                let tries_left = tries_left.saturating_sub(1);
-                if message.is_upgrade() {
-                    debug!("Received a valid upgrade response, switching to NTPv5!");
-                    self.protocol_version = ProtocolVersion::UpgradedToV5;
-                } else if tries_left == 0 {
-                    debug!("Server does not support NTPv5, stopping the upgrade process");
-                    self.protocol_version = ProtocolVersion::V4;
-                } else {
-                    debug!(tries_left, "Server did not yet respond with upgrade code");
-                    self.protocol_version = ProtocolVersion::V4UpgradingToV5 { tries_left };
-                };
-            } else if let ProtocolVersion::UpgradedToV5 = self.protocol_version {
-                self.protocol_version = ProtocolVersion::V5;
-            }
+        let Some(send_time) = send_times.get(tries_ago).copied().flatten() else {
+            debug!(
+                tries_ago,
+                "received a response to a request that was not sent; discarding"
+            );
+            return actions!();
+        };
+
+        if let ProtocolVersion::V4UpgradingToV5 { tries_left } = self.protocol_version {
+            let tries_left = tries_left.saturating_sub(1);
+            if message.is_upgrade() {
+                debug!("Received a valid upgrade response, switching to NTPv5!");
+                self.protocol_version = ProtocolVersion::UpgradedToV5;
+            } else if tries_left == 0 {
+                debug!("Server does not support NTPv5, stopping the upgrade process");
+                self.protocol_version = ProtocolVersion::V4;
+            } else {
+                debug!(tries_left, "Server did not yet respond with upgrade code");
+                self.protocol_version = ProtocolVersion::V4UpgradingToV5 { tries_left };
+            };
+        } else if let ProtocolVersion::UpgradedToV5 = self.protocol_version {
+            self.protocol_version = ProtocolVersion::V5;
         }
 
-        if !message.valid_server_response(request_identifier, self.nts.is_some()) {
-            // Packets should be a response to a previous request from us,
-            // if not just ignore. Note that this might also happen when
-            // we reset between sending the request and receiving the response.
-            // We do this as the first check since accepting even a KISS
-            // packet that is not a response will leave us vulnerable
-            // to denial of service attacks.
-            debug!("Received old/unexpected packet from source");
-            actions!()
-        } else if message.is_kiss_rate(self.last_poll_interval) {
+        if message.is_kiss_rate(self.last_poll_interval) {
             // KISS packets may not have correct timestamps at all, handle them anyway
             self.remote_min_poll_interval = Ord::max(
                 self.remote_min_poll_interval
@@ -716,11 +876,17 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
                 self.last_poll_interval,
             );
             warn!(?self.remote_min_poll_interval, "Source requested rate limit");
+            self.burst_remaining = 0;
+            if self.remote_min_poll_interval >= self.source_config.poll_interval_limits.max {
+                warn!(
+                    "Source rate limits polling at the maximum poll interval, its poll-interval-limits may be too low for this server"
+                );
+            }
             actions!()
         } else if message.is_kiss_rstr() || message.is_kiss_deny() {
             warn!("Source denied service");
             // Handle the kiss if it was signed, otherwise ignore it
-            if self.nts.is_some() {
+            if self.nts.is_some() || self.symmetric_key.is_some() {
                 actions!(NtpSourceAction::Demobilize)
             } else {
                 // Not signed, so easily faked, but do register it for future reference
@@ -748,7 +914,7 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
             warn!("Received packet with invalid mode");
             actions!()
         } else {
-            self.process_message(message, local_clock_time, send_time, recv_time)
+            self.process_message(message, local_clock_time, send_time, recv_time, tries_ago)
         }
     }
 
@@ -758,6 +924,7 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
         local_clock_time: NtpInstant,
         send_time: NtpTimestamp,
         recv_time: NtpTimestamp,
+        tries_ago: usize,
     ) -> NtpSourceActionIterator<Controller::SourceMessage> {
         trace!("Packet accepted for processing");
         // For reachability, mark that we have had a response
@@ -766,8 +933,11 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
         // Clear received deny/rstr kod
         self.have_deny_rstr_response = false;
 
-        // we received this packet, and don't want to accept future ones with this next_expected_origin
-        self.current_request_identifier = None;
+        // we received this packet, and don't want to accept future ones for this or earlier requests
+        self.request_identifiers[tries_ago..].fill(None);
+        if tries_ago > 0 {
+            debug!(tries_ago, "Received a late response to an earlier request");
+        }
 
         // Update stratum and reference id
         self.stratum = message.stratum();
@@ -815,10 +985,13 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
         }
 
         // generate and handle measurement
-        let measurement =
-            Measurement::from_packet(&message, send_time, recv_time, local_clock_time);
+        let measurement = Measurement {
+            after_retransmission: tries_ago > 0,
+            ..Measurement::from_packet(&message, send_time, recv_time, local_clock_time)
+        };
 
-        let controller_message = self.controller.handle_measurement(measurement);
+        let controller_message = tracing::debug_span!("filter")
+            .in_scope(|| self.controller.handle_measurement(measurement));
 
         // Process new cookies
         if let Some(nts) = self.nts.as_mut() {
@@ -830,6 +1003,7 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
         actions!(NtpSourceAction::UpdateSystem(NtpSourceUpdate {
             snapshot: NtpSourceSnapshot::from_source(self),
             message: controller_message,
+            measurement: Some(measurement),
         }))
     }
 
@@ -839,11 +1013,12 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
 
         NtpSource {
             nts: None,
+            symmetric_key: None,
 
             last_poll_interval: PollInterval::default(),
             remote_min_poll_interval: PollInterval::default(),
 
-            current_request_identifier: None,
+            request_identifiers: Default::default(),
 
             have_deny_rstr_response: false,
 
@@ -851,6 +1026,7 @@ impl<Controller: SourceController<MeasurementDelay = NtpDuration>> NtpSource<Con
             source_id: ReferenceId::from_int(0),
             reach: Reach::default(),
             tries: 0,
+            burst_remaining: 0,
 
             stratum: 0,
             reference_id: ReferenceId::from_int(0),
@@ -1092,6 +1268,56 @@ mod test {
         assert!(source.current_poll_interval() >= source.controller.0);
     }
 
+    #[test]
+    fn restore_remote_min_poll_interval() {
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        let limits = source.source_config.poll_interval_limits;
+        assert_eq!(
+            NtpSourceSnapshot::from_source(&source).remote_min_poll_interval,
+            None
+        );
+
+        source.restore_remote_min_poll_interval(limits.min);
+        assert_eq!(source.remote_min_poll_interval, limits.min);
+
+        source.restore_remote_min_poll_interval(limits.min.inc(limits));
+        assert_eq!(source.remote_min_poll_interval, limits.min.inc(limits));
+
+        // never lowers what was learned this run
+        source.restore_remote_min_poll_interval(limits.min);
+        assert_eq!(source.remote_min_poll_interval, limits.min.inc(limits));
+
+        // capped at the configured maximum
+        source.restore_remote_min_poll_interval(PollInterval::NEVER);
+        assert_eq!(source.remote_min_poll_interval, limits.max);
+        assert_eq!(
+            NtpSourceSnapshot::from_source(&source).remote_min_poll_interval,
+            Some(limits.max)
+        );
+    }
+
+    #[test]
+    fn restored_rate_limit_skips_burst() {
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        let limits = source.source_config.poll_interval_limits;
+
+        // the configured minimum is no rate limit of the source
+        source.burst_remaining = IBURST_POLLS;
+        source.restore_remote_min_poll_interval(limits.min);
+        assert_eq!(source.burst_remaining, IBURST_POLLS);
+
+        source.restore_remote_min_poll_interval(limits.min.inc(limits));
+        assert_eq!(source.burst_remaining, 0);
+
+        let mut timer = None;
+        for action in source.handle_timer() {
+            if let NtpSourceAction::SetTimer(duration) = action {
+                timer = Some(duration);
+            }
+        }
+        assert!(timer.unwrap() > IBURST_SPACING);
+    }
+
     #[test]
     fn test_oversize_cookie_doesnt_crash() {
         let mut source = NtpSource::test_ntp_source(NoopController);
@@ -1137,7 +1363,7 @@ mod test {
         let actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(400),
         );
         for action in actions {
@@ -1152,12 +1378,170 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(500),
         );
         assert!(actions.next().is_none());
     }
 
+    #[test]
+    fn test_late_response_to_earlier_request() {
+        let base = NtpInstant::now();
+        let mut source = NtpSource::test_ntp_source(NoopController);
+
+        let poll = |source: &mut NtpSource<NoopController>| {
+            let outgoing = source
+                .handle_timer()
+                .find_map(|action| match action {
+                    NtpSourceAction::Send(buf) => Some(buf),
+                    _ => None,
+                })
+                .unwrap();
+            let outgoing = NtpPacket::deserialize(&outgoing, &NoCipher).unwrap().0;
+            let mut packet = NtpPacket::test();
+            packet.set_stratum(1);
+            packet.set_mode(NtpAssociationMode::Server);
+            packet.set_origin_timestamp(outgoing.transmit_timestamp());
+            packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(1100));
+            packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(1200));
+            packet.serialize_without_encryption_vec(None).unwrap()
+        };
+        let first = poll(&mut source);
+        let second = poll(&mut source);
+        let send_times = [
+            Some(NtpTimestamp::from_fixed_int(1000)),
+            Some(NtpTimestamp::from_fixed_int(0)),
+        ];
+
+        let respond = |source: &mut NtpSource<NoopController>, response: &[u8]| {
+            source
+                .handle_incoming(
+                    response,
+                    base + Duration::from_secs(1),
+                    &send_times,
+                    NtpTimestamp::from_fixed_int(1400),
+                )
+                .find_map(|action| match action {
+                    NtpSourceAction::UpdateSystem(update) => update.measurement(),
+                    _ => None,
+                })
+        };
+
+        // the answer to the first request is paired with its own send time
+        let measurement = respond(&mut source, &first).unwrap();
+        assert!(measurement.after_retransmission);
+        assert_eq!(measurement.delay, NtpDuration::from_fixed_int(1300));
+        assert!(respond(&mut source, &first).is_none());
+
+        // the newer request can still be answered
+        let measurement = respond(&mut source, &second).unwrap();
+        assert!(!measurement.after_retransmission);
+        assert_eq!(measurement.delay, NtpDuration::from_fixed_int(300));
+    }
+
+    #[test]
+    fn test_handle_incoming_symmetric_key() {
+        let base = NtpInstant::now();
+        let keys =
+            crate::SymmetricKeySet::parse("1 AES128CMAC HEX:000102030405060708090a0b0c0d0e0f")
+                .unwrap();
+        let key = keys.get(1).unwrap();
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        source.symmetric_key = Some(key.clone());
+        source.protocol_version = ProtocolVersion::V4;
+
+        let outgoingbuf = source
+            .handle_timer()
+            .find_map(|action| match action {
+                NtpSourceAction::Send(buf) => Some(buf),
+                _ => None,
+            })
+            .unwrap();
+        assert!(key.verify(&outgoingbuf));
+        let outgoing = NtpPacket::deserialize(&outgoingbuf, &NoCipher).unwrap().0;
+        assert_eq!(outgoing.mac_key_id(), Some(1));
+
+        let mut packet = NtpPacket::test();
+        packet.set_stratum(1);
+        packet.set_mode(NtpAssociationMode::Server);
+        packet.set_origin_timestamp(outgoing.transmit_timestamp());
+        packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+        packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+
+        // a response without MAC is ignored
+        let mut actions = source.handle_incoming(
+            &packet.serialize_without_encryption_vec(None).unwrap(),
+            base + Duration::from_secs(1),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions.next().is_none());
+
+        let mut buf = [0u8; 1024];
+        let mut cursor = Cursor::new(buf.as_mut_slice());
+        packet.serialize(&mut cursor, &NoCipher, None).unwrap();
+        key.sign(&mut cursor).unwrap();
+        let end = cursor.position() as usize;
+
+        let mut actions = source.handle_incoming(
+            &buf[..end],
+            base + Duration::from_secs(1),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
+            NtpTimestamp::from_fixed_int(400),
+        );
+        assert!(actions.any(|action| matches!(action, NtpSourceAction::UpdateSystem(_))));
+    }
+
+    #[test]
+    fn test_iburst() {
+        fn poll(source: &mut NtpSource<NoopController>) -> (NtpPacket<'static>, Duration) {
+            let mut outgoing = None;
+            let mut timer = None;
+            for action in source.handle_timer() {
+                match action {
+                    NtpSourceAction::Send(buf) => {
+                        outgoing = Some(NtpPacket::deserialize(&buf, &NoCipher).unwrap().0)
+                    }
+                    NtpSourceAction::SetTimer(duration) => timer = Some(duration),
+                    _ => {}
+                }
+            }
+            (outgoing.unwrap().into_owned(), timer.unwrap())
+        }
+
+        let base = NtpInstant::now();
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        source.burst_remaining = IBURST_POLLS;
+
+        for i in 0..IBURST_POLLS {
+            let (outgoing, timer) = poll(&mut source);
+            if i + 1 < IBURST_POLLS {
+                assert_eq!(timer, IBURST_SPACING);
+            } else {
+                assert!(timer > IBURST_SPACING);
+            }
+
+            let mut packet = NtpPacket::test();
+            packet.set_stratum(1);
+            packet.set_mode(NtpAssociationMode::Server);
+            packet.set_origin_timestamp(outgoing.transmit_timestamp());
+            packet.set_receive_timestamp(NtpTimestamp::from_fixed_int(100));
+            packet.set_transmit_timestamp(NtpTimestamp::from_fixed_int(200));
+            let _ = source.handle_incoming(
+                &packet.serialize_without_encryption_vec(None).unwrap(),
+                base + Duration::from_secs(1),
+                &[Some(NtpTimestamp::from_fixed_int(0))],
+                NtpTimestamp::from_fixed_int(400),
+            );
+        }
+
+        // an unanswered burst is abandoned after the second poll
+        let mut source = NtpSource::test_ntp_source(NoopController);
+        source.burst_remaining = IBURST_POLLS;
+        assert_eq!(poll(&mut source).1, IBURST_SPACING);
+        assert!(poll(&mut source).1 > IBURST_SPACING);
+    }
+
     #[test]
     fn test_startup_unreachable() {
         let mut source = NtpSource::test_ntp_source(NoopController);
@@ -1213,7 +1597,7 @@ mod test {
         let actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(400),
         );
         for action in actions {
@@ -1313,7 +1697,7 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(500),
         );
         assert!(actions.next().is_none());
@@ -1322,7 +1706,7 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(500),
         );
         assert!(actions.next().is_none());
@@ -1339,7 +1723,7 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(!source.have_deny_rstr_response);
@@ -1365,7 +1749,7 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(source.have_deny_rstr_response);
@@ -1378,7 +1762,7 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(!source.have_deny_rstr_response);
@@ -1404,7 +1788,7 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(source.have_deny_rstr_response);
@@ -1418,7 +1802,7 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(actions.next().is_none());
@@ -1445,7 +1829,7 @@ mod test {
         let mut actions = source.handle_incoming(
             &packet.serialize_without_encryption_vec(None).unwrap(),
             base + Duration::from_secs(1),
-            NtpTimestamp::from_fixed_int(0),
+            &[Some(NtpTimestamp::from_fixed_int(0))],
             NtpTimestamp::from_fixed_int(100),
         );
         assert!(actions.next().is_none());
@@ -1497,7 +1881,7 @@ mod test {
             let actions = source.handle_incoming(
                 &response,
                 NtpInstant::now(),
-                NtpTimestamp::default(),
+                &[Some(NtpTimestamp::default())],
                 NtpTimestamp::default(),
             );
             for action in actions {
@@ -1566,7 +1950,7 @@ mod test {
         let actions = source.handle_incoming(
             &response,
             NtpInstant::now(),
-            NtpTimestamp::default(),
+            &[Some(NtpTimestamp::default())],
             NtpTimestamp::default(),
         );
         for action in actions {
@@ -1610,7 +1994,7 @@ mod test {
         let actions = source.handle_incoming(
             &response,
             NtpInstant::now(),
-            NtpTimestamp::default(),
+            &[Some(NtpTimestamp::default())],
             NtpTimestamp::default(),
         );
         for action in actions {
@@ -1665,7 +2049,7 @@ mod test {
         let actions = source.handle_incoming(
             &response,
             NtpInstant::now(),
-            NtpTimestamp::default(),
+            &[Some(NtpTimestamp::default())],
             NtpTimestamp::default(),
         );
         for action in actions {
@@ -1754,7 +2138,7 @@ mod test {
             let actions = client.handle_incoming(
                 &resp_bytes,
                 NtpInstant::now(),
-                NtpTimestamp::default(),
+                &[Some(NtpTimestamp::default())],
                 NtpTimestamp::default(),
             );
             for action in actions {
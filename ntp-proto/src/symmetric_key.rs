@@ -0,0 +1,412 @@
+use std::{collections::HashMap, fmt::Display, io::Cursor, io::Write, str::FromStr, sync::Arc};
+
+use aes::{Aes128, Aes256};
+use cmac::{Cmac, Mac};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Length of the key identifier that precedes the digest in a MAC
+const KEY_ID_LENGTH: usize = 4;
+
+/// Keys with an ASCII encoding up to this length are read as text, longer keys as hex
+/// (the convention of the classic `ntp.keys` format).
+const MAX_BARE_ASCII_KEY_LENGTH: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymmetricKeyType {
+    /// Legacy RFC 5905 digest: MD5 over key and packet
+    Md5,
+    /// Legacy digest: SHA-1 over key and packet
+    Sha1,
+    /// AES-128-CMAC as recommended by RFC 8573
+    Aes128Cmac,
+    /// AES-256-CMAC
+    Aes256Cmac,
+}
+
+impl SymmetricKeyType {
+    /// Length of the digest produced with this key type
+    pub fn digest_length(self) -> usize {
+        match self {
+            SymmetricKeyType::Md5 => 16,
+            SymmetricKeyType::Sha1 => 20,
+            SymmetricKeyType::Aes128Cmac | SymmetricKeyType::Aes256Cmac => 16,
+        }
+    }
+
+    /// Whether this is one of the legacy digests, which are not considered
+    /// secure anymore
+    pub fn is_legacy(self) -> bool {
+        matches!(self, SymmetricKeyType::Md5 | SymmetricKeyType::Sha1)
+    }
+
+    fn key_length(self) -> Option<usize> {
+        match self {
+            SymmetricKeyType::Md5 | SymmetricKeyType::Sha1 => None,
+            SymmetricKeyType::Aes128Cmac => Some(16),
+            SymmetricKeyType::Aes256Cmac => Some(32),
+        }
+    }
+}
+
+impl FromStr for SymmetricKeyType {
+    type Err = SymmetricKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "M" | "MD5" => Ok(SymmetricKeyType::Md5),
+            "SHA1" | "SHA-1" => Ok(SymmetricKeyType::Sha1),
+            "AES128CMAC" | "AES-128-CMAC" | "AES128" => Ok(SymmetricKeyType::Aes128Cmac),
+            "AES256CMAC" | "AES-256-CMAC" | "AES256" => Ok(SymmetricKeyType::Aes256Cmac),
+            _ => Err(SymmetricKeyError::UnknownType(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymmetricKeyError {
+    UnknownType(String),
+    InvalidId(String),
+    InvalidKey(u32),
+    InvalidKeyLength(u32),
+    DuplicateId(u32),
+    MissingField(usize),
+}
+
+impl Display for SymmetricKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownType(t) => write!(f, "unknown key type '{t}'"),
+            Self::InvalidId(id) => write!(f, "invalid key id '{id}'"),
+            Self::InvalidKey(id) => write!(f, "key {id} is not valid ASCII or hex"),
+            Self::InvalidKeyLength(id) => write!(f, "key {id} has the wrong length for its type"),
+            Self::DuplicateId(id) => write!(f, "key id {id} is defined more than once"),
+            Self::MissingField(line) => write!(f, "line {line} should be `<id> <type> <key>`"),
+        }
+    }
+}
+
+impl std::error::Error for SymmetricKeyError {}
+
+/// A symmetric key used to authenticate NTP packets with a MAC.
+pub struct SymmetricKey {
+    id: u32,
+    key_type: SymmetricKeyType,
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for SymmetricKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SymmetricKey")
+            .field("id", &self.id)
+            .field("key_type", &self.key_type)
+            .finish()
+    }
+}
+
+impl Drop for SymmetricKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SymmetricKey {}
+
+impl SymmetricKey {
+    pub fn new(
+        id: u32,
+        key_type: SymmetricKeyType,
+        key: Vec<u8>,
+    ) -> Result<Self, SymmetricKeyError> {
+        // key id 0 is reserved for the crypto-NAK
+        if id == 0 {
+            return Err(SymmetricKeyError::InvalidId(id.to_string()));
+        }
+
+        match key_type.key_length() {
+            Some(length) if length != key.len() => Err(SymmetricKeyError::InvalidKeyLength(id)),
+            None if key.is_empty() => Err(SymmetricKeyError::InvalidKeyLength(id)),
+            _ => Ok(SymmetricKey { id, key_type, key }),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn key_type(&self) -> SymmetricKeyType {
+        self.key_type
+    }
+
+    /// Length of the MAC (key id and digest) this key appends to a packet
+    pub fn mac_length(&self) -> usize {
+        KEY_ID_LENGTH + self.key_type.digest_length()
+    }
+
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self.key_type {
+            SymmetricKeyType::Md5 => {
+                let mut hasher = Md5::new();
+                hasher.update(&self.key);
+                hasher.update(data);
+                hasher.finalize().to_vec()
+            }
+            SymmetricKeyType::Sha1 => {
+                let mut hasher = Sha1::new();
+                hasher.update(&self.key);
+                hasher.update(data);
+                hasher.finalize().to_vec()
+            }
+            SymmetricKeyType::Aes128Cmac => {
+                let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(&self.key)
+                    .expect("Key length checked on construction");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            SymmetricKeyType::Aes256Cmac => {
+                let mut mac = <Cmac<Aes256> as Mac>::new_from_slice(&self.key)
+                    .expect("Key length checked on construction");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    /// Append a MAC over everything written to the cursor so far
+    pub(crate) fn sign(&self, w: &mut Cursor<&mut [u8]>) -> std::io::Result<()> {
+        let end = w.position() as usize;
+        let digest = self.digest(&w.get_ref()[..end]);
+        w.write_all(&self.id.to_be_bytes())?;
+        w.write_all(&digest)
+    }
+
+    /// Check that a received packet ends in a valid MAC made with this key
+    pub(crate) fn verify(&self, data: &[u8]) -> bool {
+        let Some(body_length) = data.len().checked_sub(self.mac_length()) else {
+            return false;
+        };
+        let (body, mac) = data.split_at(body_length);
+        let (key_id, digest) = mac.split_at(KEY_ID_LENGTH);
+
+        key_id == self.id.to_be_bytes() && constant_time_eq(digest, &self.digest(body))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The symmetric keys known to the daemon, indexed by key id
+#[derive(Debug, Default, Clone)]
+pub struct SymmetricKeySet {
+    keys: HashMap<u32, Arc<SymmetricKey>>,
+}
+
+impl SymmetricKeySet {
+    pub fn get(&self, id: u32) -> Option<Arc<SymmetricKey>> {
+        self.keys.get(&id).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// All keys in the set, in no particular order
+    pub fn keys(&self) -> impl Iterator<Item = &SymmetricKey> {
+        self.keys.values().map(|key| key.as_ref())
+    }
+
+    /// Parse a keys file. Every non-empty line has the form `<id> <type> <key>`,
+    /// where the key is given either as `HEX:<hex>`, `ASCII:<text>`, or bare, in
+    /// which case keys up to 20 characters are read as text and longer ones as hex.
+    /// Everything after a `#` is a comment.
+    pub fn parse(contents: &str) -> Result<Self, SymmetricKeyError> {
+        let mut keys = HashMap::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (Some(id), Some(key_type), Some(key)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(SymmetricKeyError::MissingField(index + 1));
+            };
+
+            let id: u32 = id
+                .parse()
+                .map_err(|_| SymmetricKeyError::InvalidId(id.to_string()))?;
+            let key_type: SymmetricKeyType = key_type.parse()?;
+
+            let key = if let Some(hex) = key.strip_prefix("HEX:") {
+                decode_hex(hex)
+            } else if let Some(ascii) = key.strip_prefix("ASCII:") {
+                Some(ascii.as_bytes().to_vec())
+            } else if key.len() <= MAX_BARE_ASCII_KEY_LENGTH {
+                Some(key.as_bytes().to_vec())
+            } else {
+                decode_hex(key)
+            }
+            .ok_or(SymmetricKeyError::InvalidKey(id))?;
+
+            let key = SymmetricKey::new(id, key_type, key)?;
+            if keys.insert(id, Arc::new(key)).is_some() {
+                return Err(SymmetricKeyError::DuplicateId(id));
+            }
+        }
+
+        Ok(SymmetricKeySet { keys })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign_vec(key: &SymmetricKey, data: &[u8]) -> Vec<u8> {
+        let mut buffer = [0u8; 128];
+        buffer[..data.len()].copy_from_slice(data);
+        let mut cursor = Cursor::new(buffer.as_mut_slice());
+        cursor.set_position(data.len() as u64);
+        key.sign(&mut cursor).unwrap();
+        let length = cursor.position() as usize;
+        buffer[..length].to_vec()
+    }
+
+    #[test]
+    fn parse_keys_file() {
+        let keys = SymmetricKeySet::parse(
+            r#"
+            # id type key
+            1 MD5 secretpassword
+            2 SHA1 HEX:000102030405060708090a0b0c0d0e0f10111213
+            3 AES128CMAC 000102030405060708090a0b0c0d0e0f21222324 # comment
+            4 AES256CMAC HEX:000102030405060708090a0b0c0d0e0f000102030405060708090a0b0c0d0e0f
+            "#,
+        );
+        // the bare key on line 3 is read as hex, but is 20 bytes
+        assert_eq!(keys.unwrap_err(), SymmetricKeyError::InvalidKeyLength(3));
+
+        let keys = SymmetricKeySet::parse(
+            r#"
+            1 MD5 secretpassword
+            2 SHA1 HEX:000102030405060708090a0b0c0d0e0f10111213
+            3 AES128CMAC 000102030405060708090a0b0c0d0e0f
+            4 aes256cmac HEX:000102030405060708090a0b0c0d0e0f000102030405060708090a0b0c0d0e0f
+            "#,
+        )
+        .unwrap();
+        assert_eq!(keys.len(), 4);
+        assert_eq!(keys.get(1).unwrap().key_type(), SymmetricKeyType::Md5);
+        assert_eq!(keys.get(2).unwrap().key_type(), SymmetricKeyType::Sha1);
+        assert_eq!(
+            keys.get(3).unwrap().key_type(),
+            SymmetricKeyType::Aes128Cmac
+        );
+        assert_eq!(
+            keys.get(4).unwrap().key_type(),
+            SymmetricKeyType::Aes256Cmac
+        );
+        assert!(keys.get(5).is_none());
+        assert_eq!(
+            keys.keys().filter(|key| key.key_type().is_legacy()).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            SymmetricKeySet::parse("1 MD5").unwrap_err(),
+            SymmetricKeyError::MissingField(1)
+        );
+        assert_eq!(
+            SymmetricKeySet::parse("x MD5 abc").unwrap_err(),
+            SymmetricKeyError::InvalidId("x".into())
+        );
+        assert_eq!(
+            SymmetricKeySet::parse("0 MD5 abc").unwrap_err(),
+            SymmetricKeyError::InvalidId("0".into())
+        );
+        assert_eq!(
+            SymmetricKeySet::parse("1 RC4 abc").unwrap_err(),
+            SymmetricKeyError::UnknownType("RC4".into())
+        );
+        assert_eq!(
+            SymmetricKeySet::parse("1 SHA1 HEX:0g").unwrap_err(),
+            SymmetricKeyError::InvalidKey(1)
+        );
+        assert_eq!(
+            SymmetricKeySet::parse("1 MD5 abc\n1 SHA1 def").unwrap_err(),
+            SymmetricKeyError::DuplicateId(1)
+        );
+    }
+
+    #[test]
+    fn aes_cmac_rfc4493_vector() {
+        // RFC 4493, example 2
+        let key = SymmetricKey::new(
+            1,
+            SymmetricKeyType::Aes128Cmac,
+            decode_hex("2b7e151628aed2a6abf7158809cf4f3c").unwrap(),
+        )
+        .unwrap();
+        let data = decode_hex("6bc1bee22e409f96e93d7e117393172a").unwrap();
+        assert_eq!(
+            key.digest(&data),
+            decode_hex("070a16b46b4d4144f79bdd9dd04a287c").unwrap()
+        );
+    }
+
+    #[test]
+    fn sign_then_verify() {
+        let keys = SymmetricKeySet::parse(
+            r#"
+            1 MD5 secretpassword
+            2 SHA1 HEX:000102030405060708090a0b0c0d0e0f10111213
+            3 AES128CMAC HEX:000102030405060708090a0b0c0d0e0f
+            "#,
+        )
+        .unwrap();
+        let data = [7u8; 48];
+
+        for id in 1..=3 {
+            let key = keys.get(id).unwrap();
+            let signed = sign_vec(&key, &data);
+            assert_eq!(signed.len(), data.len() + key.mac_length());
+            assert_eq!(signed[48..52], id.to_be_bytes());
+            assert!(key.verify(&signed));
+
+            // tampered data
+            let mut tampered = signed.clone();
+            tampered[3] ^= 1;
+            assert!(!key.verify(&tampered));
+
+            // wrong key
+            let other = keys.get(id % 3 + 1).unwrap();
+            assert!(!other.verify(&signed));
+
+            // missing mac
+            assert!(!key.verify(&data));
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::net::{IpAddr, SocketAddr};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 use std::{fmt::Debug, hash::Hash};
@@ -9,15 +9,24 @@ use crate::packet::v5::server_reference_id::{BloomFilter, ServerId};
 use crate::source::{NtpSourceUpdate, SourceSnapshot};
 use crate::{NtpTimestamp, OneWaySource, OneWaySourceUpdate};
 use crate::{
-    algorithm::{StateUpdate, TimeSyncController},
+    algorithm::{DecisionRecord, SelectionStatus, StateUpdate, StepSettings, TimeSyncController},
     clock::NtpClock,
     config::{SourceConfig, SynchronizationConfig},
     identifiers::ReferenceId,
     packet::NtpLeapIndicator,
-    source::{NtpSource, NtpSourceActionIterator, ProtocolVersion, SourceNtsData},
+    source::{
+        AcceptSynchronizationError, NtpSource, NtpSourceActionIterator, ProtocolVersion,
+        SourceNtsData,
+    },
+    symmetric_key::SymmetricKey,
     time_types::NtpDuration,
 };
 
+/// Frequency tolerance assumed for the local clock while in holdover, as
+/// the PHI constant in RFC 5905. The reported root dispersion grows at
+/// least at this rate from the last synchronization.
+const HOLDOVER_FREQUENCY_TOLERANCE: f64 = 15e-6;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct TimeSnapshot {
     /// Precision of the local clock
@@ -38,6 +47,25 @@ pub struct TimeSnapshot {
     pub leap_indicator: NtpLeapIndicator,
     /// Total amount that the clock has stepped
     pub accumulated_steps: NtpDuration,
+    /// Most recent step or large slew of the clock
+    #[serde(default)]
+    pub adjustment: Option<ClockAdjustment>,
+}
+
+/// A step or large slew of the clock, during which the time served may jump
+/// or run at an unusual rate
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ClockAdjustment {
+    /// Time at which the adjustment is complete
+    pub until: NtpTimestamp,
+    /// Size of the adjustment
+    pub offset: NtpDuration,
+}
+
+impl ClockAdjustment {
+    pub fn in_progress(&self, now: NtpTimestamp) -> bool {
+        now.is_before(self.until)
+    }
 }
 
 impl TimeSnapshot {
@@ -66,6 +94,69 @@ impl Default for TimeSnapshot {
             root_variance_cubic: 0.0,
             leap_indicator: NtpLeapIndicator::Unknown,
             accumulated_steps: NtpDuration::ZERO,
+            adjustment: None,
+        }
+    }
+}
+
+/// Limits within which holdover still provides time of good quality
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct HoldoverLimits {
+    /// Seconds since the last synchronization
+    pub max_duration: f64,
+    /// Root dispersion in seconds
+    pub max_dispersion: f64,
+}
+
+impl HoldoverLimits {
+    fn from_config(config: &SynchronizationConfig) -> Self {
+        HoldoverLimits {
+            max_duration: config.holdover_max_duration,
+            max_dispersion: config.holdover_max_dispersion,
+        }
+    }
+}
+
+impl Default for HoldoverLimits {
+    fn default() -> Self {
+        Self::from_config(&SynchronizationConfig::default())
+    }
+}
+
+/// Discrete quality of the time we have, for downstream consumers that
+/// don't want to interpret the root dispersion themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClockQuality {
+    /// Synchronized to our sources
+    Locked,
+    /// In holdover, within the configured limits
+    Holdover,
+    /// In holdover for longer, or with a larger dispersion, than configured
+    DegradedHoldover,
+    /// Never synchronized
+    Unsynchronized,
+}
+
+impl ClockQuality {
+    /// The PTP clock class (IEEE 1588-2019, table 4) closest to this quality
+    pub fn clock_class(self) -> u8 {
+        match self {
+            ClockQuality::Locked => 6,
+            ClockQuality::Holdover => 7,
+            ClockQuality::DegradedHoldover => 187,
+            ClockQuality::Unsynchronized => 248,
+        }
+    }
+}
+
+impl std::fmt::Display for ClockQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClockQuality::Locked => f.write_str("locked"),
+            ClockQuality::Holdover => f.write_str("holdover"),
+            ClockQuality::DegradedHoldover => f.write_str("degraded holdover"),
+            ClockQuality::Unsynchronized => f.write_str("unsynchronized"),
         }
     }
 }
@@ -87,12 +178,45 @@ pub struct SystemSnapshot {
     /// NTPv5 reference ID for this instance
     #[serde(skip)]
     pub server_id: ServerId,
+    /// All sources were lost after synchronizing, and the clock is running
+    /// on its last estimated frequency
+    #[serde(default)]
+    pub holdover: bool,
+    /// Limits on holdover used to determine the clock quality
+    #[serde(default)]
+    pub holdover_limits: HoldoverLimits,
 }
 
 impl SystemSnapshot {
     pub fn update_timedata(&mut self, timedata: TimeSnapshot, config: &SynchronizationConfig) {
         self.time_snapshot = timedata;
         self.accumulated_steps_threshold = config.accumulated_step_panic_threshold;
+        self.holdover_limits = HoldoverLimits::from_config(config);
+    }
+
+    /// Quality of our time at `now`, based on how long we have been in
+    /// holdover and the dispersion that accumulated since
+    pub fn clock_quality(&self, now: NtpTimestamp) -> ClockQuality {
+        if matches!(
+            self.time_snapshot.leap_indicator,
+            NtpLeapIndicator::Unknown | NtpLeapIndicator::Unsynchronized
+        ) {
+            return ClockQuality::Unsynchronized;
+        }
+
+        if !self.holdover {
+            return ClockQuality::Locked;
+        }
+
+        let duration = (now - self.time_snapshot.root_variance_base_time).to_seconds();
+        let dispersion = self.time_snapshot.root_dispersion(now).to_seconds();
+        if duration > self.holdover_limits.max_duration
+            || dispersion > self.holdover_limits.max_dispersion
+        {
+            ClockQuality::DegradedHoldover
+        } else {
+            ClockQuality::Holdover
+        }
     }
 
     pub fn update_used_sources(&mut self, used_sources: impl Iterator<Item = SourceSnapshot>) {
@@ -130,6 +254,8 @@ impl Default for SystemSnapshot {
             time_snapshot: TimeSnapshot::default(),
             bloom_filter: BloomFilter::new(),
             server_id: ServerId::default(),
+            holdover: false,
+            holdover_limits: HoldoverLimits::default(),
         }
     }
 }
@@ -206,9 +332,19 @@ pub struct System<SourceId, Controller> {
     ip_list: Arc<[IpAddr]>,
 
     sources: HashMap<SourceId, Option<SourceSnapshot>>,
+    usable_sources: HashSet<SourceId>,
+    // why sources were found unfit for synchronization on their last update
+    rejected_sources: HashMap<SourceId, AcceptSynchronizationError>,
+    // whether the controller ever reached consensus, there is nothing to
+    // hold over before that
+    synchronized: bool,
+    // the last decision of the controller that was not taken yet
+    decision: Option<DecisionRecord<SourceId>>,
 
     controller: Controller,
     controller_took_control: bool,
+    // a system that only measures its sources leaves the clock to others
+    clock_control: bool,
 }
 
 impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId = SourceId>>
@@ -223,6 +359,7 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
         // Setup system snapshot
         let mut system = SystemSnapshot {
             stratum: synchronization_config.local_stratum,
+            holdover_limits: HoldoverLimits::from_config(&synchronization_config),
             ..Default::default()
         };
 
@@ -238,8 +375,13 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
             system,
             ip_list,
             sources: Default::default(),
+            usable_sources: Default::default(),
+            rejected_sources: Default::default(),
+            synchronized: false,
+            decision: None,
             controller: Controller::new(clock, synchronization_config, algorithm_config)?,
             controller_took_control: false,
+            clock_control: true,
         })
     }
 
@@ -251,8 +393,14 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
         self.ensure_controller_control()
     }
 
+    /// Never take control of the clock, for a system that measures its
+    /// sources without ever steering
+    pub fn disable_clock_control(&mut self) {
+        self.clock_control = false;
+    }
+
     fn ensure_controller_control(&mut self) -> Result<(), <Controller::Clock as NtpClock>::Error> {
-        if !self.controller_took_control {
+        if self.clock_control && !self.controller_took_control {
             self.controller.take_control()?;
             self.controller_took_control = true;
         }
@@ -301,10 +449,12 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
     pub fn create_ntp_source(
         &mut self,
         id: SourceId,
+        peer: &str,
         source_config: SourceConfig,
         source_addr: SocketAddr,
         protocol_version: ProtocolVersion,
         nts: Option<Box<SourceNtsData>>,
+        symmetric_key: Option<Arc<SymmetricKey>>,
     ) -> Result<
         (
             NtpSource<Controller::NtpSourceController>,
@@ -313,7 +463,7 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
         <Controller::Clock as NtpClock>::Error,
     > {
         self.ensure_controller_control()?;
-        let controller = self.controller.add_source(id, source_config);
+        let controller = self.controller.add_source(id, peer, source_config);
         self.sources.insert(id, None);
         Ok(NtpSource::new(
             source_addr,
@@ -321,6 +471,7 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
             protocol_version,
             controller,
             nts,
+            symmetric_key,
         ))
     }
 
@@ -330,9 +481,82 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
     ) -> Result<(), <Controller::Clock as NtpClock>::Error> {
         self.controller.remove_source(id);
         self.sources.remove(&id);
+        self.rejected_sources.remove(&id);
+        self.set_source_usable(id, false);
         Ok(())
     }
 
+    /// Exclude a source from synchronization until its next update
+    pub fn handle_source_disabled(&mut self, id: SourceId) {
+        self.controller.source_update(id, false);
+        self.set_source_usable(id, false);
+    }
+
+    /// Stop (or resume) steering the clock, while still processing measurements
+    pub fn set_steering_suspended(&mut self, suspended: bool) {
+        self.controller.set_steering_suspended(suspended);
+    }
+
+    /// Only slew the clock, by at most `max_offset` seconds at a time
+    pub fn set_steering_limit(&mut self, max_offset: Option<f64>) {
+        self.controller.set_steering_limit(max_offset);
+    }
+
+    /// The thresholds deciding between stepping and slewing the clock, None
+    /// when the controller does not slew
+    pub fn step_settings(&self) -> Option<StepSettings> {
+        self.controller.step_settings()
+    }
+
+    /// Change the thresholds deciding between stepping and slewing the clock,
+    /// until the configuration is updated
+    pub fn set_step_settings(&mut self, settings: StepSettings) {
+        self.controller.set_step_settings(settings);
+    }
+
+    /// Frequency offset of the clock as currently estimated by the controller
+    pub fn frequency_estimate(&self) -> Option<f64> {
+        self.controller.frequency_estimate()
+    }
+
+    /// Start from a previously determined frequency offset of the clock, this
+    /// returns None when the controller already determined it
+    pub fn restore_frequency(
+        &mut self,
+        frequency: f64,
+    ) -> Option<SystemActionIterator<Controller::ControllerMessage>> {
+        let update = self.controller.restore_frequency(frequency)?;
+        Some(self.handle_algorithm_state_update(update))
+    }
+
+    /// Take over a changed synchronization and algorithm configuration
+    pub fn update_config(
+        &mut self,
+        synchronization_config: SynchronizationConfig,
+        algorithm_config: Controller::AlgorithmConfig,
+    ) -> SystemActionIterator<Controller::ControllerMessage> {
+        self.synchronization_config = synchronization_config;
+        self.system.holdover_limits = HoldoverLimits::from_config(&synchronization_config);
+        let update = self
+            .controller
+            .update_config(synchronization_config, algorithm_config);
+        self.handle_algorithm_state_update(update)
+    }
+
+    /// Outcome of the last source selection for each of the sources
+    pub fn source_selection(&self) -> HashMap<SourceId, SelectionStatus> {
+        self.sources
+            .keys()
+            .map(|id| {
+                let status = match self.rejected_sources.get(id) {
+                    Some(reason) => (*reason).into(),
+                    None => self.controller.selection_status(*id),
+                };
+                (*id, status)
+            })
+            .collect()
+    }
+
     pub fn handle_source_update(
         &mut self,
         id: SourceId,
@@ -341,15 +565,22 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
         SystemActionIterator<Controller::ControllerMessage>,
         <Controller::Clock as NtpClock>::Error,
     > {
-        let usable = update
-            .snapshot
-            .accept_synchronization(
-                self.synchronization_config.local_stratum,
-                self.ip_list.as_ref(),
-                &self.system,
-            )
-            .is_ok();
+        let usable = match update.snapshot.accept_synchronization(
+            self.synchronization_config.local_stratum,
+            self.ip_list.as_ref(),
+            &self.system,
+        ) {
+            Ok(()) => {
+                self.rejected_sources.remove(&id);
+                true
+            }
+            Err(reason) => {
+                self.rejected_sources.insert(id, reason);
+                false
+            }
+        };
         self.controller.source_update(id, usable);
+        self.set_source_usable(id, usable);
         *self.sources.get_mut(&id).unwrap() = Some(SourceSnapshot::Ntp(update.snapshot));
         if let Some(message) = update.message {
             let update = self.controller.source_message(id, message);
@@ -368,6 +599,7 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
         <Controller::Clock as NtpClock>::Error,
     > {
         self.controller.source_update(id, true);
+        self.set_source_usable(id, true);
         *self.sources.get_mut(&id).unwrap() = Some(SourceSnapshot::OneWay(update.snapshot));
         if let Some(message) = update.message {
             let update = self.controller.source_message(id, message);
@@ -383,16 +615,24 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
     ) -> SystemActionIterator<Controller::ControllerMessage> {
         let mut actions = vec![];
         if let Some(ref used_sources) = update.used_sources {
-            self.system
-                .update_used_sources(used_sources.iter().map(|v| {
-                    self.sources.get(v).and_then(|snapshot| *snapshot).expect(
-                    "Critical error: Source used for synchronization that is not known to system",
-                )
-                }));
+            if used_sources.is_empty() {
+                self.start_holdover();
+            } else {
+                self.system
+                    .update_used_sources(used_sources.iter().map(|v| {
+                        self.sources.get(v).and_then(|snapshot| *snapshot).expect(
+                        "Critical error: Source used for synchronization that is not known to system",
+                    )
+                    }));
+                self.end_holdover();
+            }
         }
         if let Some(time_snapshot) = update.time_snapshot {
             self.system
                 .update_timedata(time_snapshot, &self.synchronization_config);
+            if self.system.holdover {
+                self.inflate_holdover_dispersion();
+            }
         }
         if let Some(timeout) = update.next_update {
             actions.push(SystemAction::SetTimer(timeout));
@@ -400,9 +640,18 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
         if let Some(message) = update.source_message {
             actions.push(SystemAction::UpdateSources(SystemSourceUpdate { message }))
         }
+        if update.decision.is_some() {
+            self.decision = update.decision;
+        }
         actions.into()
     }
 
+    /// The decision the controller made in the last update of the clock,
+    /// unless it was taken before
+    pub fn take_decision(&mut self) -> Option<DecisionRecord<SourceId>> {
+        self.decision.take()
+    }
+
     pub fn handle_timer(&mut self) -> SystemActionIterator<Controller::ControllerMessage> {
         tracing::debug!("Timer expired");
         let update = self.controller.time_update();
@@ -412,16 +661,101 @@ impl<SourceId: Hash + Eq + Copy + Debug, Controller: TimeSyncController<SourceId
     pub fn update_ip_list(&mut self, ip_list: Arc<[IpAddr]>) {
         self.ip_list = ip_list;
     }
+
+    fn set_source_usable(&mut self, id: SourceId, usable: bool) {
+        if usable {
+            self.usable_sources.insert(id);
+        } else {
+            self.usable_sources.remove(&id);
+            if self.usable_sources.is_empty() {
+                self.start_holdover();
+            }
+        }
+    }
+
+    fn start_holdover(&mut self) {
+        if !self.synchronized || self.system.holdover {
+            return;
+        }
+
+        tracing::warn!("All sources lost, keeping the clock at its last estimated frequency");
+        self.system.holdover = true;
+        self.inflate_holdover_dispersion();
+
+        if let Some(stratum) = self.synchronization_config.orphan_stratum {
+            tracing::info!(stratum, "Entering orphan mode");
+            self.system.stratum = stratum;
+            // Like other orphan mode implementations, identify as the loopback address
+            self.system.reference_id = ReferenceId::from_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
+    }
+
+    fn end_holdover(&mut self) {
+        self.synchronized = true;
+        if self.system.holdover {
+            tracing::info!("Sources available again, leaving holdover");
+            self.system.holdover = false;
+        }
+    }
+
+    fn inflate_holdover_dispersion(&mut self) {
+        let time_snapshot = &mut self.system.time_snapshot;
+        time_snapshot.root_variance_quadratic = time_snapshot
+            .root_variance_quadratic
+            .max(HOLDOVER_FREQUENCY_TOLERANCE * HOLDOVER_FREQUENCY_TOLERANCE);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::net::{Ipv4Addr, SocketAddr};
 
-    use crate::{NtpSourceSnapshot, time_types::PollIntervalLimits};
+    use crate::{
+        KalmanClockController, NtpSourceSnapshot, algorithm::AlgorithmConfig,
+        time_types::PollIntervalLimits,
+    };
 
     use super::*;
 
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = std::time::SystemTimeError;
+
+        fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn get_frequency(&self) -> Result<f64, Self::Error> {
+            Ok(0.0)
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
     #[test]
     fn test_empty_source_update() {
         let mut system = SystemSnapshot::default();
@@ -443,6 +777,7 @@ mod tests {
                     source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
                     source_id: ReferenceId::KISS_DENY,
                     poll_interval: PollIntervalLimits::default().max,
+                    remote_min_poll_interval: None,
                     reach: Default::default(),
                     stratum: 2,
                     reference_id: ReferenceId::NONE,
@@ -453,6 +788,7 @@ mod tests {
                     source_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
                     source_id: ReferenceId::KISS_RATE,
                     poll_interval: PollIntervalLimits::default().max,
+                    remote_min_poll_interval: None,
                     reach: Default::default(),
                     stratum: 3,
                     reference_id: ReferenceId::NONE,
@@ -493,4 +829,210 @@ mod tests {
             Some(new_accumulated_threshold),
         );
     }
+
+    #[test]
+    fn test_source_selection() {
+        let mut system = System::<usize, KalmanClockController<TestClock, usize>>::new(
+            TestClock {},
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+            Arc::new([]),
+        )
+        .unwrap();
+
+        let source_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 123);
+        for id in 0..2 {
+            system
+                .create_ntp_source(
+                    id,
+                    "192.0.2.1:123",
+                    SourceConfig::default(),
+                    source_addr,
+                    ProtocolVersion::V4,
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+        assert_eq!(
+            system.source_selection(),
+            HashMap::from([
+                (0, SelectionStatus::NoMeasurement),
+                (1, SelectionStatus::NoMeasurement)
+            ])
+        );
+
+        // the reason a source is rejected before selection is reported as well
+        let update = NtpSourceUpdate {
+            snapshot: NtpSourceSnapshot {
+                source_addr,
+                source_id: ReferenceId::from_ip(source_addr.ip()),
+                poll_interval: PollIntervalLimits::default().min,
+                remote_min_poll_interval: None,
+                reach: Default::default(),
+                stratum: 16,
+                reference_id: ReferenceId::NONE,
+                protocol_version: ProtocolVersion::V4,
+                bloom_filter: None,
+            },
+            message: None,
+            measurement: None,
+        };
+        let _ = system.handle_source_update(1, update).unwrap();
+        assert_eq!(
+            system.source_selection().get(&1),
+            Some(&SelectionStatus::Stratum)
+        );
+
+        system.handle_source_remove(1).unwrap();
+        assert_eq!(system.source_selection().len(), 1);
+    }
+
+    #[test]
+    fn test_holdover() {
+        let mut system = System::<usize, KalmanClockController<TestClock, usize>>::new(
+            TestClock {},
+            SynchronizationConfig {
+                orphan_stratum: Some(10),
+                ..Default::default()
+            },
+            AlgorithmConfig::default(),
+            Arc::new([]),
+        )
+        .unwrap();
+
+        // Nothing to hold over before the first synchronization
+        system.set_source_usable(0, true);
+        system.set_source_usable(0, false);
+        assert!(!system.system.holdover);
+
+        system.end_holdover();
+        system.system.stratum = 3;
+        system.set_source_usable(0, true);
+        system.set_source_usable(1, true);
+        system.set_source_usable(0, false);
+        assert!(!system.system.holdover);
+
+        system.set_source_usable(1, false);
+        assert!(system.system.holdover);
+        assert_eq!(system.system.stratum, 10);
+        assert_eq!(
+            system.system.reference_id,
+            ReferenceId::from_ip(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+
+        // Dispersion keeps growing, also with updates from the controller
+        let _ = system.handle_algorithm_state_update(StateUpdate {
+            time_snapshot: Some(TimeSnapshot::default()),
+            ..Default::default()
+        });
+        let later = NtpTimestamp::default() + NtpDuration::from_seconds(1000.0);
+        assert!(
+            system
+                .system
+                .time_snapshot
+                .root_dispersion(later)
+                .to_seconds()
+                >= 1000.0 * HOLDOVER_FREQUENCY_TOLERANCE * 0.99
+        );
+
+        system.end_holdover();
+        assert!(!system.system.holdover);
+    }
+
+    #[test]
+    fn test_clock_quality() {
+        let mut system = SystemSnapshot::default();
+        let now = NtpTimestamp::default();
+        assert_eq!(system.clock_quality(now), ClockQuality::Unsynchronized);
+
+        system.time_snapshot.leap_indicator = NtpLeapIndicator::NoWarning;
+        assert_eq!(system.clock_quality(now), ClockQuality::Locked);
+
+        // at the holdover frequency tolerance, 0.1s of dispersion takes almost two hours
+        system.holdover = true;
+        system.time_snapshot.root_variance_quadratic =
+            HOLDOVER_FREQUENCY_TOLERANCE * HOLDOVER_FREQUENCY_TOLERANCE;
+        let minute = now + NtpDuration::from_seconds(60.0);
+        assert_eq!(system.clock_quality(minute), ClockQuality::Holdover);
+        let two_hours = now + NtpDuration::from_seconds(7200.0);
+        assert_eq!(
+            system.clock_quality(two_hours),
+            ClockQuality::DegradedHoldover
+        );
+
+        // a very stable clock still degrades after the maximum holdover duration
+        system.time_snapshot.root_variance_quadratic = 0.0;
+        assert_eq!(system.clock_quality(two_hours), ClockQuality::Holdover);
+        let two_days = now + NtpDuration::from_seconds(2.0 * 86400.0);
+        assert_eq!(
+            system.clock_quality(two_days),
+            ClockQuality::DegradedHoldover
+        );
+    }
+
+    /// A clock some other daemon steers, the system must leave it alone
+    #[derive(Debug, Clone, Default)]
+    struct ForeignClock {}
+
+    impl NtpClock for ForeignClock {
+        type Error = std::time::SystemTimeError;
+
+        fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn get_frequency(&self) -> Result<f64, Self::Error> {
+            Ok(0.0)
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by system");
+        }
+    }
+
+    #[test]
+    fn test_disabled_clock_control() {
+        let mut system = System::<usize, KalmanClockController<ForeignClock, usize>>::new(
+            ForeignClock {},
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+            Arc::new([]),
+        )
+        .unwrap();
+        system.disable_clock_control();
+
+        system.check_clock_access().unwrap();
+        system
+            .create_ntp_source(
+                0,
+                "192.0.2.1:123",
+                SourceConfig::default(),
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 123),
+                ProtocolVersion::V4,
+                None,
+                None,
+            )
+            .unwrap();
+    }
 }
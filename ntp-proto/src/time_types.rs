@@ -376,7 +376,7 @@ impl<'de> Deserialize<'de> for NtpDuration {
     where
         D: serde::Deserializer<'de>,
     {
-        let seconds: f64 = Deserialize::deserialize(deserializer)?;
+        let seconds = crate::duration::deserialize_seconds(deserializer)?;
 
         if seconds.is_nan() || seconds.is_infinite() {
             return Err(serde::de::Error::invalid_value(
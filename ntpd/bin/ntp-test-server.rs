@@ -0,0 +1,5 @@
+#![forbid(unsafe_code)]
+
+fn main() -> std::io::Result<std::process::ExitCode> {
+    ntpd::test_server_main()
+}
@@ -1,8 +1,20 @@
-use std::{path::PathBuf, process::ExitCode};
+use std::{path::PathBuf, process::ExitCode, time::Duration};
+
+use ntp_proto::{NtpDuration, NtpLeapIndicator, SelectionStatus, Transport, format_duration};
 
 use crate::{
-    daemon::{Config, ObservableState, config::CliArg, tracing::LogLevel},
-    force_sync,
+    daemon::{
+        Config, ObservableState,
+        census::CensusReport,
+        config::CliArg,
+        control::{ControlRequest, ControlResponse, MAX_MAINTENANCE_SECONDS},
+        sockets::SocketLocation,
+        state::DaemonState,
+        state_format,
+        tracing::LogLevel,
+        util::civil_from_days,
+    },
+    force_sync, leap_rehearsal,
 };
 use tokio::runtime::Builder;
 use tracing_subscriber::util::SubscriberInitExt;
@@ -10,14 +22,47 @@ use tracing_subscriber::util::SubscriberInitExt;
 const USAGE_MSG: &str = "\
 usage: ntp-ctl validate [-c PATH]
        ntp-ctl status [-f FORMAT] [-c PATH]
+       ntp-ctl census [-f FORMAT] [-c PATH]
        ntp-ctl force-sync [-c PATH]
+       ntp-ctl disable SOURCE [-c PATH]
+       ntp-ctl enable SOURCE [-c PATH]
+       ntp-ctl maintenance --duration DURATION [-c PATH]
+       ntp-ctl reload [-c PATH]
+       ntp-ctl tune [--step-threshold DURATION] [--slew-maximum-frequency-offset OFFSET]
+                    [--slew-minimum-duration DURATION] [-c PATH]
+       ntp-ctl nts generate-keys --hostname HOSTNAME [--output DIRECTORY]
+       ntp-ctl state export|import FILE [-c PATH]
+       ntp-ctl state verify [-c PATH]
+       ntp-ctl rehearse-leap [delete|insert] [-c PATH]
        ntp-ctl -h | ntp-ctl -v";
 
 const DESCRIPTOR: &str = "ntp-ctl - ntp-daemon monitoring";
 
 const HELP_MSG: &str = "Options:
-  -f, --format=FORMAT                  which format to use for printing statistics [plain, prometheus]
+  -f, --format=FORMAT                  which format to use for printing statistics [plain, prometheus, json]
   -c, --config=CONFIG                  which configuration file to read the socket paths from
+      --duration=DURATION              how long to suspend clock steering (e.g. 90s, 30m, 1h30m)
+      --hostname=HOSTNAME              name under which clients reach the NTS server
+      --output=DIRECTORY               where to write generated keys (default: current directory)
+      --step-threshold=DURATION        offsets above which the clock is stepped instead of slewed
+      --slew-maximum-frequency-offset=OFFSET
+                                       largest frequency offset used to slew the clock (s/s)
+      --slew-minimum-duration=DURATION shortest duration of a slew
+
+Commands:
+  census                               show hourly statistics of the clients of the servers
+  disable SOURCE                       stop polling SOURCE and exclude it from synchronization
+  enable SOURCE                        resume polling SOURCE after it was disabled
+  maintenance                          suspend clock steering for --duration, 0 resumes it
+  reload                               make the daemon read its configuration file again
+  tune                                 show the thresholds deciding between stepping and slewing
+                                       the clock, changing those given until the next reload
+  nts generate-keys                    generate a private CA and server certificate for NTS
+  state export FILE                    write the runtime state of the daemon to FILE
+  state import FILE                    take over the state exported to FILE by another daemon
+  state verify                         check that this version can read the configured state files
+  rehearse-leap [delete|insert]        simulate a leap second with the configured algorithm and
+                                       report what the daemon would do (default: delete)
   -h, --help                           display this help text
   -v, --version                        display version information";
 
@@ -30,6 +75,7 @@ enum Format {
     #[default]
     Plain,
     Prometheus,
+    Json,
 }
 
 #[derive(Debug, Default, PartialEq, Eq)]
@@ -39,7 +85,18 @@ pub enum NtpCtlAction {
     Version,
     Validate,
     Status,
+    Census,
     ForceSync,
+    Disable,
+    Enable,
+    Maintenance,
+    Reload,
+    Tune,
+    GenerateKeys,
+    ExportState,
+    ImportState,
+    VerifyState,
+    RehearseLeap,
 }
 
 #[derive(Debug, Default)]
@@ -50,12 +107,38 @@ pub(crate) struct NtpCtlOptions {
     version: bool,
     validate: bool,
     status: bool,
+    census: bool,
     force_sync: bool,
+    disable: Option<String>,
+    enable: Option<String>,
+    maintenance: bool,
+    duration: Option<Duration>,
+    reload: bool,
+    tune: bool,
+    step_threshold: Option<f64>,
+    slew_maximum_frequency_offset: Option<f64>,
+    slew_minimum_duration: Option<f64>,
+    generate_keys: bool,
+    hostname: Option<String>,
+    output: Option<PathBuf>,
+    export_state: Option<PathBuf>,
+    import_state: Option<PathBuf>,
+    verify_state: bool,
+    rehearse_leap: Option<NtpLeapIndicator>,
     action: NtpCtlAction,
 }
 
 impl NtpCtlOptions {
-    const TAKES_ARGUMENT: &'static [&'static str] = &["--config", "--format"];
+    const TAKES_ARGUMENT: &'static [&'static str] = &[
+        "--config",
+        "--format",
+        "--duration",
+        "--hostname",
+        "--output",
+        "--step-threshold",
+        "--slew-maximum-frequency-offset",
+        "--slew-minimum-duration",
+    ];
     const TAKES_ARGUMENT_SHORT: &'static [char] = &['c', 'f'];
 
     /// parse an iterator over command line arguments
@@ -93,17 +176,52 @@ impl NtpCtlOptions {
                     "-f" | "--format" => match value.as_str() {
                         "plain" => options.format = Format::Plain,
                         "prometheus" => options.format = Format::Prometheus,
+                        "json" => options.format = Format::Json,
                         _ => Err(format!("invalid format option provided: {value}"))?,
                     },
+                    "--duration" => {
+                        let duration = Duration::try_from_secs_f64(parse_duration(&value)?)
+                            .map_err(|_| format!("invalid duration provided: {value}"))?;
+                        if duration.as_secs() > MAX_MAINTENANCE_SECONDS {
+                            Err(format!(
+                                "duration can be at most {MAX_MAINTENANCE_SECONDS} seconds: {value}"
+                            ))?;
+                        }
+                        options.duration = Some(duration);
+                    }
+                    "--hostname" => {
+                        options.hostname = Some(value);
+                    }
+                    "--output" => {
+                        options.output = Some(PathBuf::from(value));
+                    }
+                    "--step-threshold" => {
+                        options.step_threshold = Some(parse_duration(&value)?);
+                    }
+                    "--slew-maximum-frequency-offset" => {
+                        options.slew_maximum_frequency_offset =
+                            Some(value.parse().map_err(|_| {
+                                format!("invalid frequency offset provided: {value}")
+                            })?);
+                    }
+                    "--slew-minimum-duration" => {
+                        options.slew_minimum_duration = Some(parse_duration(&value)?);
+                    }
                     option => {
                         Err(format!("invalid option provided: {option}"))?;
                     }
                 },
                 CliArg::Rest(rest) => {
-                    if rest.len() > 1 {
+                    let expected = match rest.first().map(String::as_str) {
+                        Some("disable" | "enable" | "nts" | "rehearse-leap") => 2,
+                        Some("state") => 3,
+                        _ => 1,
+                    };
+                    if rest.len() > expected {
                         eprintln!("Warning: Too many commands provided.")
                     }
-                    for command in rest {
+                    let mut rest = rest.into_iter();
+                    while let Some(command) = rest.next() {
                         match command.as_str() {
                             "validate" => {
                                 options.validate = true;
@@ -111,9 +229,60 @@ impl NtpCtlOptions {
                             "status" => {
                                 options.status = true;
                             }
+                            "census" => {
+                                options.census = true;
+                            }
                             "force-sync" => {
                                 options.force_sync = true;
                             }
+                            "maintenance" => {
+                                options.maintenance = true;
+                            }
+                            "reload" => {
+                                options.reload = true;
+                            }
+                            "tune" => {
+                                options.tune = true;
+                            }
+                            "nts" => match rest.next().as_deref() {
+                                Some("generate-keys") => options.generate_keys = true,
+                                Some(subcommand) => {
+                                    Err(format!("unknown nts command: {subcommand}"))?
+                                }
+                                None => Err("missing nts command")?,
+                            },
+                            "state" => {
+                                let subcommand = rest.next();
+                                if subcommand.as_deref() == Some("verify") {
+                                    options.verify_state = true;
+                                    continue;
+                                }
+                                let Some(path) = rest.next() else {
+                                    Err("missing file for command: state")?
+                                };
+                                match subcommand.as_deref() {
+                                    Some("export") => options.export_state = Some(path.into()),
+                                    Some("import") => options.import_state = Some(path.into()),
+                                    _ => Err("state requires export, import or verify")?,
+                                }
+                            }
+                            "rehearse-leap" => {
+                                options.rehearse_leap = Some(match rest.next().as_deref() {
+                                    None | Some("delete") => NtpLeapIndicator::Leap59,
+                                    Some("insert") => NtpLeapIndicator::Leap61,
+                                    Some(kind) => Err(format!("unknown leap second: {kind}"))?,
+                                });
+                            }
+                            "disable" | "enable" => {
+                                let Some(source) = rest.next() else {
+                                    Err(format!("missing source for command: {command}"))?
+                                };
+                                if command == "disable" {
+                                    options.disable = Some(source);
+                                } else {
+                                    options.enable = Some(source);
+                                }
+                            }
                             unknown => {
                                 eprintln!("Warning: Unknown command {unknown}");
                             }
@@ -124,7 +293,14 @@ impl NtpCtlOptions {
         }
 
         options.resolve_action();
-        // nothing to validate at the moment
+
+        if options.action == NtpCtlAction::Maintenance && options.duration.is_none() {
+            Err("maintenance requires a --duration")?;
+        }
+
+        if options.action == NtpCtlAction::GenerateKeys && options.hostname.is_none() {
+            Err("nts generate-keys requires a --hostname")?;
+        }
 
         Ok(options)
     }
@@ -139,14 +315,42 @@ impl NtpCtlOptions {
             self.action = NtpCtlAction::Validate;
         } else if self.status {
             self.action = NtpCtlAction::Status;
+        } else if self.census {
+            self.action = NtpCtlAction::Census;
         } else if self.force_sync {
             self.action = NtpCtlAction::ForceSync;
+        } else if self.disable.is_some() {
+            self.action = NtpCtlAction::Disable;
+        } else if self.enable.is_some() {
+            self.action = NtpCtlAction::Enable;
+        } else if self.maintenance {
+            self.action = NtpCtlAction::Maintenance;
+        } else if self.reload {
+            self.action = NtpCtlAction::Reload;
+        } else if self.tune {
+            self.action = NtpCtlAction::Tune;
+        } else if self.generate_keys {
+            self.action = NtpCtlAction::GenerateKeys;
+        } else if self.export_state.is_some() {
+            self.action = NtpCtlAction::ExportState;
+        } else if self.import_state.is_some() {
+            self.action = NtpCtlAction::ImportState;
+        } else if self.verify_state {
+            self.action = NtpCtlAction::VerifyState;
+        } else if self.rehearse_leap.is_some() {
+            self.action = NtpCtlAction::RehearseLeap;
         } else {
             self.action = NtpCtlAction::Help;
         }
     }
 }
 
+/// Parse a duration like `10ms`, `90s` or `1h30m` into seconds, plain numbers
+/// are seconds
+fn parse_duration(value: &str) -> Result<f64, String> {
+    ntp_proto::parse_duration(value).map_err(|_| format!("invalid duration provided: {value}"))
+}
+
 fn validate(config: Option<PathBuf>) -> std::io::Result<ExitCode> {
     // Late completion not needed, so ignore result.
     crate::daemon::tracing::tracing_init(LogLevel::Info, true).init();
@@ -166,6 +370,99 @@ fn validate(config: Option<PathBuf>) -> std::io::Result<ExitCode> {
     }
 }
 
+fn verify_state(config: Option<PathBuf>) -> std::io::Result<ExitCode> {
+    let config = match Config::from_args(config, vec![], vec![]) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: Could not load configuration: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let checks = state_format::verify(&config);
+    if checks.is_empty() {
+        println!("No state files configured");
+    }
+
+    let mut success = true;
+    for check in checks {
+        let name = check.format.name;
+        let path = check.path.display();
+        let current = check.format.current();
+        match check.result {
+            Ok(version) if version == current => println!("{name} {path}: format {version}"),
+            Ok(version) => println!(
+                "{name} {path}: format {version}, migrated to format {current} when next written"
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                println!("{name} {path}: not written yet")
+            }
+            Err(e) => {
+                println!("{name} {path}: {e}");
+                success = false;
+            }
+        }
+    }
+
+    Ok(if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(feature = "nts-server")]
+fn generate_keys(hostname: String, output: PathBuf) -> std::io::Result<ExitCode> {
+    let files = match crate::generate_keys::generate(&hostname, &output) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Error: Could not generate keys: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let absolute = |path: &std::path::Path| std::path::absolute(path).unwrap_or(path.into());
+
+    println!("Generated a private CA and a certificate for {hostname}:");
+    println!("  CA certificate:    {}", files.ca_certificate.display());
+    println!(
+        "  CA key:            {} (keep this secret, it is only needed to issue more certificates)",
+        files.ca_key.display()
+    );
+    println!("  Certificate chain: {}", files.certificate_chain.display());
+    println!("  Server key:        {}", files.private_key.display());
+    println!();
+    println!("Add this to the configuration of the server:");
+    println!();
+    println!("[[nts-ke-server]]");
+    println!("listen = \"[::]:4460\"");
+    println!(
+        "certificate-chain-path = {:?}",
+        absolute(&files.certificate_chain)
+    );
+    println!("private-key-path = {:?}", absolute(&files.private_key));
+    println!();
+    println!(
+        "Copy {} to the clients, and add this to their configuration:",
+        files.ca_certificate.display()
+    );
+    println!();
+    println!("[[source]]");
+    println!("mode = \"nts\"");
+    println!("address = {hostname:?}");
+    println!(
+        "certificate-authority = {:?}",
+        absolute(&files.ca_certificate)
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(feature = "nts-server"))]
+fn generate_keys(_hostname: String, _output: PathBuf) -> std::io::Result<ExitCode> {
+    eprintln!("Error: This build of ntp-ctl does not include NTS server support");
+    Ok(ExitCode::FAILURE)
+}
+
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 pub fn main() -> std::io::Result<ExitCode> {
@@ -184,8 +481,23 @@ pub fn main() -> std::io::Result<ExitCode> {
             Ok(ExitCode::SUCCESS)
         }
         NtpCtlAction::Validate => validate(options.config),
+        NtpCtlAction::VerifyState => verify_state(options.config),
         NtpCtlAction::ForceSync => force_sync::force_sync(options.config),
-        NtpCtlAction::Status => {
+        NtpCtlAction::RehearseLeap => leap_rehearsal::rehearse(
+            options.config,
+            options.rehearse_leap.unwrap_or(NtpLeapIndicator::Leap59),
+        ),
+        NtpCtlAction::GenerateKeys => generate_keys(
+            options.hostname.unwrap_or_default(),
+            options.output.unwrap_or_else(|| PathBuf::from(".")),
+        ),
+        NtpCtlAction::Disable
+        | NtpCtlAction::Enable
+        | NtpCtlAction::Maintenance
+        | NtpCtlAction::Reload
+        | NtpCtlAction::Tune
+        | NtpCtlAction::ExportState
+        | NtpCtlAction::ImportState => {
             let config = Config::from_args(options.config, vec![], vec![]);
 
             if let Err(ref e) = config {
@@ -194,10 +506,73 @@ pub fn main() -> std::io::Result<ExitCode> {
 
             let config = config.unwrap_or_default();
 
-            let observation = config
-                .observability
-                .observation_path
-                .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/observe"));
+            let control = SocketLocation::new(
+                config
+                    .observability
+                    .control_path
+                    .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/control")),
+                config.observability.control_address,
+            );
+
+            let request = match options.action {
+                NtpCtlAction::Reload => ControlRequest::Reload,
+                NtpCtlAction::Tune => ControlRequest::Tune {
+                    step_threshold: options.step_threshold,
+                    slew_maximum_frequency_offset: options.slew_maximum_frequency_offset,
+                    slew_minimum_duration: options.slew_minimum_duration,
+                },
+                NtpCtlAction::ExportState => ControlRequest::ExportState,
+                NtpCtlAction::ImportState => {
+                    let path = options.import_state.unwrap_or_default();
+                    match read_state(&path) {
+                        Ok(state) => ControlRequest::ImportState {
+                            state: Box::new(state),
+                        },
+                        Err(e) => {
+                            eprintln!("Could not read state from {}: {e}", path.display());
+                            return Ok(ExitCode::FAILURE);
+                        }
+                    }
+                }
+                _ => match (options.disable, options.enable, options.duration) {
+                    (Some(source), _, _) => ControlRequest::DisableSource { source },
+                    (None, Some(source), _) => ControlRequest::EnableSource { source },
+                    (None, None, Some(duration)) => ControlRequest::Maintenance {
+                        seconds: duration.as_secs(),
+                    },
+                    (None, None, None) => unreachable!("action requires a source or duration"),
+                },
+            };
+
+            Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(send_control(request, control, options.export_state))
+        }
+        NtpCtlAction::Status | NtpCtlAction::Census => {
+            let config = Config::from_args(options.config, vec![], vec![]);
+
+            // keep stdout clean for machine readable formats
+            if let Err(ref e) = config {
+                eprintln!("Warning: Unable to load configuration file: {e}");
+            }
+
+            let config = config.unwrap_or_default();
+
+            let observation = SocketLocation::new(
+                config
+                    .observability
+                    .observation_path
+                    .unwrap_or_else(|| PathBuf::from("/var/run/ntpd-rs/observe")),
+                config.observability.observation_address,
+            );
+
+            if options.action == NtpCtlAction::Census {
+                return Builder::new_current_thread()
+                    .enable_all()
+                    .build()?
+                    .block_on(print_census(options.format, observation));
+            }
 
             Builder::new_current_thread()
                 .enable_all()
@@ -206,38 +581,408 @@ pub fn main() -> std::io::Result<ExitCode> {
                     match options.format {
                         Format::Plain => print_state(Format::Plain, observation).await,
                         Format::Prometheus => print_state(Format::Prometheus, observation).await,
+                        Format::Json => print_state(Format::Json, observation).await,
                     }
                 })
         }
     }
 }
 
-async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode, std::io::Error> {
-    let mut stream = match tokio::net::UnixStream::connect(&observe_socket).await {
+fn read_state(path: &std::path::Path) -> std::io::Result<DaemonState> {
+    DaemonState::from_json(&std::fs::read_to_string(path)?)
+}
+
+fn write_state(path: &std::path::Path, state: &DaemonState) -> std::io::Result<()> {
+    let mut contents = serde_json::to_string_pretty(state).map_err(std::io::Error::other)?;
+    contents.push('\n');
+    std::fs::write(path, contents)
+}
+
+async fn send_control(
+    request: ControlRequest,
+    control_socket: SocketLocation,
+    export_path: Option<PathBuf>,
+) -> Result<ExitCode, std::io::Error> {
+    let mut stream = match control_socket.connect().await {
         Ok(stream) => stream,
         Err(e) => {
-            eprintln!("Could not open socket at {}: {e}", observe_socket.display(),);
+            eprintln!("Could not open socket at {control_socket}: {e}");
             return Ok(ExitCode::FAILURE);
         }
     };
 
-    let mut msg = Vec::with_capacity(16 * 1024);
-    let mut output =
-        match crate::daemon::sockets::read_json::<ObservableState>(&mut stream, &mut msg).await {
-            Ok(output) => output,
-            Err(e) => {
-                eprintln!("Failed to read state from observation socket: {e}");
+    crate::daemon::sockets::write_json(&mut stream, &request).await?;
 
+    let mut msg = Vec::with_capacity(1024);
+    let response =
+        match crate::daemon::sockets::read_json::<ControlResponse>(&mut stream, &mut msg).await {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("Failed to read response from control socket: {e}");
                 return Ok(ExitCode::FAILURE);
             }
         };
 
+    match (request, response) {
+        (ControlRequest::DisableSource { source }, ControlResponse::Ok { sources }) => {
+            println!("Disabled {source} (matched {sources} sources)");
+            Ok(ExitCode::SUCCESS)
+        }
+        (ControlRequest::EnableSource { source }, ControlResponse::Ok { sources }) => {
+            println!("Enabled {source} (matched {sources} sources)");
+            Ok(ExitCode::SUCCESS)
+        }
+        (
+            ControlRequest::DisableSource { source } | ControlRequest::EnableSource { source },
+            ControlResponse::UnknownSource,
+        ) => {
+            eprintln!("No source named {source}");
+            Ok(ExitCode::FAILURE)
+        }
+        (ControlRequest::Maintenance { .. }, ControlResponse::Maintenance { seconds: 0 }) => {
+            println!("Clock steering resumed");
+            Ok(ExitCode::SUCCESS)
+        }
+        (ControlRequest::Maintenance { .. }, ControlResponse::Maintenance { seconds }) => {
+            println!("Clock steering suspended for {seconds} seconds");
+            Ok(ExitCode::SUCCESS)
+        }
+        (ControlRequest::Reload, ControlResponse::Reloaded { added, removed }) => {
+            println!("Reloaded configuration: {added} sources added, {removed} removed");
+            Ok(ExitCode::SUCCESS)
+        }
+        (ControlRequest::Reload, ControlResponse::ReloadFailed { error }) => {
+            eprintln!("Could not reload configuration, the daemon keeps the current one: {error}");
+            Ok(ExitCode::FAILURE)
+        }
+        (ControlRequest::Tune { .. }, ControlResponse::Tuned { settings }) => {
+            println!(
+                "Step threshold: {}",
+                format_duration(settings.step_threshold)
+            );
+            println!(
+                "Slew maximum frequency offset: {:e} ({} ppm)",
+                settings.slew_maximum_frequency_offset,
+                settings.slew_maximum_frequency_offset * 1e6
+            );
+            println!(
+                "Slew minimum duration: {}",
+                format_duration(settings.slew_minimum_duration)
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+        (ControlRequest::Tune { .. }, ControlResponse::TuneFailed { error }) => {
+            eprintln!("Could not tune the daemon, it keeps the current thresholds: {error}");
+            Ok(ExitCode::FAILURE)
+        }
+        (ControlRequest::ExportState, ControlResponse::State { state }) => {
+            let path = export_path.unwrap_or_default();
+            match write_state(&path, &state) {
+                Ok(()) => {
+                    println!(
+                        "Exported state with {} sources to {}",
+                        state.sources.len(),
+                        path.display()
+                    );
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("Could not write state to {}: {e}", path.display());
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        (
+            ControlRequest::ImportState { .. },
+            ControlResponse::StateImported {
+                frequency,
+                rate_limits,
+                disabled_sources,
+                servers,
+            },
+        ) => {
+            println!(
+                "Imported state: frequency {}, {rate_limits} rate limits, {disabled_sources} disabled sources, statistics of {servers} servers",
+                if frequency {
+                    "restored"
+                } else {
+                    "not restored"
+                },
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+        (_, response) => {
+            eprintln!("Unexpected response from the daemon: {response:?}");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Symbol and description of the outcome of source selection, the symbols follow chrony
+fn describe_selection(status: SelectionStatus) -> (char, &'static str) {
+    match status {
+        SelectionStatus::Primary => ('*', "primary"),
+        SelectionStatus::Selected => ('+', "selected"),
+        SelectionStatus::NoMajority => ('-', "no majority"),
+        SelectionStatus::Falseticker => ('x', "falseticker"),
+        SelectionStatus::TooUncertain => ('~', "too uncertain"),
+        SelectionStatus::NoMeasurement => ('?', "no measurement"),
+        SelectionStatus::Unusable => ('?', "unusable"),
+        SelectionStatus::Unreachable => ('?', "unreachable"),
+        SelectionStatus::Stratum => ('?', "stratum too high"),
+        SelectionStatus::Loop => ('?', "synchronization loop"),
+        SelectionStatus::Distance => ('?', "root distance too large"),
+        SelectionStatus::Unsynchronized => ('?', "unsynchronized"),
+        SelectionStatus::NoSelect => ('-', "noselect"),
+    }
+}
+
+/// The sources as a table, with the details of each source below its row
+fn format_sources(output: &ObservableState) -> String {
+    let rows: Vec<_> = output
+        .sources
+        .iter()
+        .map(|source| {
+            let disabled = output
+                .disabled_sources
+                .iter()
+                .any(|name| *name == source.name || *name == source.address);
+            let (symbol, status) = if disabled {
+                ('?', "disabled")
+            } else {
+                describe_selection(source.selection)
+            };
+            let name = if source.name == source.address {
+                source.name.clone()
+            } else {
+                format!("{}/{}", source.name, source.address)
+            };
+            // measurements over a connection are less accurate
+            let transport = match source.transport {
+                Transport::Udp => "",
+                Transport::Tcp => " [TCP]",
+                Transport::Tls => " [TLS]",
+            };
+            // the offset and delay include configured corrections
+            let corrected = if source.timedata.offset_correction != NtpDuration::ZERO
+                || source.timedata.delay_correction != NtpDuration::ZERO
+            {
+                " [corrected]"
+            } else {
+                ""
+            };
+            [
+                symbol.to_string(),
+                name + source.nts_cookies.map_or("", |_| " [NTS]") + transport + corrected,
+                source.stratum.to_string(),
+                format!("{:03o}", source.reach.register()),
+                format_duration(source.poll_interval.as_duration().to_seconds()),
+                format!(
+                    "{:+.6}±{:.6}s",
+                    source.timedata.offset.to_seconds(),
+                    source.timedata.uncertainty.to_seconds()
+                ),
+                format!("{:.6}s", source.timedata.delay.to_seconds()),
+                status.to_string(),
+            ]
+        })
+        .collect();
+
+    let header = [
+        "S", "Source", "Stratum", "Reach", "Poll", "Offset", "Delay", "Status",
+    ]
+    .map(String::from);
+    let mut widths = header.clone().map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |row: &[String; 8]| {
+        let line: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        line.join(" ").trim_end().to_owned() + "\n"
+    };
+
+    let mut out = format_row(&header);
+    for (row, source) in rows.iter().zip(&output.sources) {
+        out += &format_row(row);
+        out += &format!(
+            "    id: {}, missing polls: {}\n",
+            source.id, source.unanswered_polls
+        );
+        out += &format!(
+            "    root dispersion: {:.6}s, root delay: {:.6}s\n",
+            source.timedata.remote_uncertainty.to_seconds(),
+            source.timedata.remote_delay.to_seconds()
+        );
+        if let Some(nts_cookies) = source.nts_cookies {
+            out += &format!(
+                "    NTS cookies: {}/{} available\n",
+                nts_cookies,
+                ntp_proto::MAX_COOKIES
+            );
+        }
+    }
+    out
+}
+
+fn format_json(output: &ObservableState) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(output)
+}
+
+/// Read the state of the daemon, errors are reported on stderr
+async fn observe(observe_socket: &SocketLocation) -> Option<ObservableState> {
+    let mut stream = match observe_socket.connect().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Could not open socket at {observe_socket}: {e}");
+            return None;
+        }
+    };
+
+    let mut msg = Vec::with_capacity(16 * 1024);
+    match crate::daemon::sockets::read_json::<ObservableState>(&mut stream, &mut msg).await {
+        Ok(output) => Some(output),
+        Err(e) => {
+            eprintln!("Failed to read state from observation socket: {e}");
+            None
+        }
+    }
+}
+
+async fn print_census(
+    print: Format,
+    observe_socket: SocketLocation,
+) -> Result<ExitCode, std::io::Error> {
+    let Some(mut output) = observe(&observe_socket).await else {
+        return Ok(ExitCode::FAILURE);
+    };
+    output.servers.sort_by_key(|s| s.address);
+
+    match print {
+        Format::Json => {
+            let census: Vec<_> = output
+                .servers
+                .iter()
+                .map(|server| {
+                    serde_json::json!({
+                        "address": server.address,
+                        "label": server.label,
+                        "census": server.census,
+                    })
+                })
+                .collect();
+            match serde_json::to_string_pretty(&census) {
+                Ok(json) => println!("{json}"),
+                Err(e) => {
+                    eprintln!("Failed to encode json data: {e}");
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        }
+        Format::Plain | Format::Prometheus => {
+            if print == Format::Prometheus {
+                eprintln!("Warning: the census is only available as plain text or json");
+            }
+            for server in &output.servers {
+                let label = match &server.label {
+                    Some(label) => format!(" ({label})"),
+                    None => String::new(),
+                };
+                println!("{}{label}:", server.address);
+                if server.census.is_empty() {
+                    println!("    no census, enable client-census for this server");
+                }
+                for report in &server.census {
+                    print_census_report(report);
+                }
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_census_report(report: &CensusReport) {
+    let (year, month, day) = civil_from_days(report.start / 86400);
+    let start = format!(
+        "{year:04}-{month:02}-{day:02} {:02}:00 UTC",
+        report.start % 86400 / 3600
+    );
+    let total = report.nts_packets + report.plain_packets;
+    println!(
+        "  {start}{}: {} clients, {total} packets, {:.1}% nts",
+        if report.partial { " (so far)" } else { "" },
+        report.unique_clients,
+        if total == 0 {
+            0.0
+        } else {
+            100.0 * report.nts_packets as f64 / total as f64
+        },
+    );
+    let versions: Vec<_> = report
+        .versions
+        .iter()
+        .map(|(version, packets)| format!("v{version} {packets}"))
+        .collect();
+    if !versions.is_empty() {
+        println!("    versions: {}", versions.join(", "));
+    }
+    if report.untracked_packets > 0 {
+        println!(
+            "    {} packets of clients beyond the tracked number",
+            report.untracked_packets
+        );
+    }
+    let top_talkers: Vec<_> = report
+        .top_talkers
+        .iter()
+        .map(|(client, packets)| format!("{client} {packets}"))
+        .collect();
+    if !top_talkers.is_empty() {
+        println!("    top talkers: {}", top_talkers.join(", "));
+    }
+}
+
+async fn print_state(
+    print: Format,
+    observe_socket: SocketLocation,
+) -> Result<ExitCode, std::io::Error> {
+    let Some(mut output) = observe(&observe_socket).await else {
+        return Ok(ExitCode::FAILURE);
+    };
+
     match print {
         Format::Plain => {
             // Sort sources by address and then id (to deal with pools), servers just by address
             output.sources.sort_by_key(|s| (s.name.clone(), s.id));
             output.servers.sort_by_key(|s| s.address);
 
+            let program = &output.program;
+            if let Some(name) = &program.instance_name {
+                println!("Instance: {name}");
+            }
+            println!(
+                "Daemon: version {} ({}, {})",
+                program.version, program.build_commit, program.build_commit_date
+            );
+            // daemons from before these were published leave them empty
+            if !program.algorithm.is_empty() {
+                let features = if program.features.is_empty() {
+                    "none".to_owned()
+                } else {
+                    program.features.join(", ")
+                };
+                println!("Algorithm: {}, features: {features}", program.algorithm);
+            }
+            if let Some(hash) = &program.config_hash {
+                println!("Configuration hash: {hash}");
+            }
             println!("Synchronization status:");
             println!(
                 "Dispersion: {:.6}s, Delay: {:.6}s",
@@ -248,57 +993,89 @@ async fn print_state(print: Format, observe_socket: PathBuf) -> Result<ExitCode,
                     .to_seconds(),
                 output.system.time_snapshot.root_delay.to_seconds()
             );
-            println!("Stratum: {}", output.system.stratum);
-            println!();
-            println!("Sources:");
-            for source in &output.sources {
+            println!(
+                "Stratum: {}{}",
+                output.system.stratum,
+                if output.system.holdover {
+                    " (holdover)"
+                } else {
+                    ""
+                }
+            );
+            let quality = output.system.clock_quality(output.program.now);
+            println!("Quality: {quality} (clock class {})", quality.clock_class());
+            if let Some(leap_seconds) = output.leap_seconds {
+                let tai_offset = leap_seconds
+                    .tai_offset
+                    .map_or_else(|| "unknown".to_owned(), |offset| format!("{offset}s"));
+                match leap_seconds.next_leap {
+                    Some(leap) => println!(
+                        "TAI offset: {tai_offset}, next leap second in {:.1} days (TAI offset {}s)",
+                        (leap.at - output.program.now).to_seconds() / 86400.0,
+                        leap.tai_offset
+                    ),
+                    None => println!("TAI offset: {tai_offset}, no leap second scheduled"),
+                }
+            }
+            let diversity = &output.diversity;
+            if diversity.sources > 0 {
                 println!(
-                    "{}/{}{} ({}): {:+.6}±{:.6}(±{:.6})s",
-                    source.name,
-                    source.address,
-                    source.nts_cookies.map_or("", |_| " [NTS]"),
-                    source.id,
-                    source.timedata.offset.to_seconds(),
-                    source.timedata.uncertainty.to_seconds(),
-                    source.timedata.delay.to_seconds(),
+                    "Diversity: {:.2} ({} sources, {} networks, {} providers, {} authenticated)",
+                    diversity.score,
+                    diversity.sources,
+                    diversity.networks,
+                    diversity.providers,
+                    diversity.authenticated
                 );
+            }
+            if !diversity.shared.is_empty() {
+                let shared: Vec<_> = diversity.shared.iter().map(ToString::to_string).collect();
                 println!(
-                    "    poll interval: {:.0}s, missing polls: {}",
-                    source.poll_interval.as_duration().to_seconds(),
-                    source.unanswered_polls,
+                    "Warning: all sources steering the clock share {}",
+                    shared.join(" and ")
                 );
+            }
+            println!();
+            println!("Sources:");
+            print!("{}", format_sources(&output));
+            for failure in &output.failed_sources {
                 println!(
-                    "    root dispersion: {:.6}s, root delay:{:.6}s",
-                    source.timedata.remote_uncertainty.to_seconds(),
-                    source.timedata.remote_delay.to_seconds()
+                    "Failed: {}, unreachable on all addresses {} times, backing off {}s",
+                    failure.address, failure.failures, failure.backoff_seconds
                 );
-                if let Some(nts_cookies) = source.nts_cookies {
-                    println!(
-                        "    NTS cookies: {}/{} available",
-                        nts_cookies,
-                        ntp_proto::MAX_COOKIES
-                    )
-                }
             }
             println!();
             println!("Servers:");
             for server in &output.servers {
+                let label = match &server.label {
+                    Some(label) => format!(" ({label})"),
+                    None => String::new(),
+                };
                 println!(
-                    "{}: received {}, accepted {}, errors {}",
+                    "{}{label}: received {}, accepted {}, errors {}",
                     server.address,
                     server.stats.received_packets.get(),
                     server.stats.accepted_packets.get(),
                     server.stats.response_send_errors.get()
                 );
                 println!(
-                    "    denied {}, nts nak {}, rate limited {}, ignored {}",
+                    "    denied {}, nts nak {}, rate limited {} (kod {}), ignored {}",
                     server.stats.denied_packets.get(),
                     server.stats.nts_nak_packets.get(),
                     server.stats.rate_limited_packets.get(),
+                    server.stats.rate_limit_kod_packets.get(),
                     server.stats.ignored_packets.get()
                 );
             }
         }
+        Format::Json => match format_json(&output) {
+            Ok(json) => println!("{json}"),
+            Err(e) => {
+                eprintln!("Failed to encode json data: {e}");
+
+                return Ok(ExitCode::FAILURE);
+            }
+        },
         Format::Prometheus => {
             let mut buf = String::new();
             if let Err(e) = crate::metrics::format_state(&mut buf, &output) {
@@ -319,10 +1096,13 @@ mod tests {
     use std::os::unix::prelude::PermissionsExt;
     use std::path::Path;
 
+    use ntp_proto::{ObservableSourceState, PollIntervalLimits, Reach};
+
     use crate::{
         daemon::{
             config::ObservabilityConfig,
             sockets::{create_unix_socket_with_permissions, write_json},
+            spawn::SourceId,
         },
         test::alloc_port,
     };
@@ -346,7 +1126,7 @@ mod tests {
 
         let sources_listener = create_unix_socket_with_permissions(&path, permissions)?;
 
-        let fut = super::print_state(command, path);
+        let fut = super::print_state(command, SocketLocation::Unix(path));
         let handle = tokio::spawn(fut);
 
         let (mut stream, _addr) = sources_listener.accept().await?;
@@ -364,6 +1144,11 @@ mod tests {
             system: Default::default(),
             sources: vec![],
             servers: vec![],
+            epoch: 0,
+            disabled_sources: vec![],
+            leap_seconds: None,
+            diversity: Default::default(),
+            failed_sources: vec![],
         };
         let result = write_socket_helper(Format::Plain, value).await?;
 
@@ -382,6 +1167,11 @@ mod tests {
             system: Default::default(),
             sources: vec![],
             servers: vec![],
+            epoch: 0,
+            disabled_sources: vec![],
+            leap_seconds: None,
+            diversity: Default::default(),
+            failed_sources: vec![],
         };
         let result = write_socket_helper(Format::Prometheus, value).await?;
 
@@ -393,6 +1183,107 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_control_socket_json() -> std::io::Result<()> {
+        let value = ObservableState {
+            program: Default::default(),
+            system: Default::default(),
+            sources: vec![],
+            servers: vec![],
+            epoch: 0,
+            disabled_sources: vec![],
+            leap_seconds: None,
+            diversity: Default::default(),
+            failed_sources: vec![],
+        };
+        let result = write_socket_helper(Format::Json, value).await?;
+
+        assert_eq!(
+            format!("{:?}", result.unwrap()),
+            format!("{:?}", ExitCode::SUCCESS)
+        );
+
+        Ok(())
+    }
+
+    fn state_with_source() -> ObservableState {
+        let mut source = ObservableSourceState {
+            timedata: Default::default(),
+            unanswered_polls: 3,
+            poll_interval: PollIntervalLimits::default().min,
+            nts_cookies: Some(5),
+            stratum: 2,
+            reach: Reach::default(),
+            selection: SelectionStatus::Primary,
+            transport: Transport::Udp,
+            name: "time.example.com:123".into(),
+            address: "192.0.2.1:123".into(),
+            id: SourceId::new(),
+        };
+        source.timedata.offset = NtpDuration::from_seconds(0.25);
+        source.timedata.remote_uncertainty = NtpDuration::from_seconds(0.5);
+        source.timedata.remote_delay = NtpDuration::from_seconds(0.125);
+
+        ObservableState {
+            program: Default::default(),
+            system: Default::default(),
+            sources: vec![source],
+            servers: vec![],
+            epoch: 7,
+            disabled_sources: vec![],
+            leap_seconds: None,
+            diversity: Default::default(),
+            failed_sources: vec![],
+        }
+    }
+
+    #[test]
+    fn test_format_sources() {
+        let state = state_with_source();
+        let text = format_sources(&state);
+        let lines: Vec<_> = text.lines().collect();
+
+        assert!(lines[0].starts_with("S Source"));
+        assert!(lines[1].starts_with("* time.example.com:123/192.0.2.1:123 [NTS] 2"));
+        assert!(lines[1].contains("+0.250000±"));
+        assert!(lines[1].ends_with("primary"));
+        assert_eq!(
+            lines[2],
+            format!("    id: {}, missing polls: 3", state.sources[0].id)
+        );
+        assert_eq!(
+            lines[3],
+            "    root dispersion: 0.500000s, root delay: 0.125000s"
+        );
+        assert_eq!(
+            lines[4],
+            format!("    NTS cookies: 5/{} available", ntp_proto::MAX_COOKIES)
+        );
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_format_json() {
+        let state = state_with_source();
+        let json = format_json(&state).unwrap();
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["epoch"], 7);
+        assert_eq!(value["sources"][0]["name"], "time.example.com:123");
+        assert_eq!(value["sources"][0]["address"], "192.0.2.1:123");
+        assert_eq!(value["sources"][0]["unanswered_polls"], 3);
+        assert_eq!(value["sources"][0]["nts_cookies"], 5);
+
+        // what ntp-ctl prints is what the daemon publishes
+        let parsed: ObservableState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.sources[0].id, state.sources[0].id);
+        assert_eq!(
+            parsed.sources[0].timedata.offset,
+            state.sources[0].timedata.offset
+        );
+        assert_eq!(parsed.sources[0].selection, SelectionStatus::Primary);
+    }
+
     #[tokio::test]
     async fn test_control_socket_source_invalid_input() -> std::io::Result<()> {
         let value = 42u32;
@@ -428,8 +1319,179 @@ mod tests {
         let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
         assert_eq!(options.format, Format::Prometheus);
 
+        let arguments = &[BINARY, "-f", "json"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.format, Format::Json);
+
         let arguments = &[BINARY, "-f", "yaml"];
         let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
         assert_eq!(err, "invalid format option provided: yaml");
     }
+
+    #[test]
+    fn cli_census() {
+        let arguments = &[BINARY, "census", "-f", "json"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Census);
+        assert_eq!(options.format, Format::Json);
+    }
+
+    #[test]
+    fn cli_disable_enable() {
+        let arguments = &[BINARY, "disable", "ntp.example.com:123"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Disable);
+        assert_eq!(options.disable.as_deref(), Some("ntp.example.com:123"));
+
+        let arguments = &[
+            BINARY,
+            "enable",
+            "ntp.example.com:123",
+            "-c",
+            "/foo/ntp.toml",
+        ];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Enable);
+        assert_eq!(options.enable.as_deref(), Some("ntp.example.com:123"));
+
+        let arguments = &[BINARY, "disable"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "missing source for command: disable");
+    }
+
+    #[test]
+    fn cli_maintenance() {
+        let arguments = &[BINARY, "maintenance", "--duration", "30m"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Maintenance);
+        assert_eq!(options.duration, Some(Duration::from_secs(1800)));
+
+        let arguments = &[BINARY, "maintenance", "--duration=90"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.duration, Some(Duration::from_secs(90)));
+
+        let arguments = &[BINARY, "maintenance", "--duration", "1h30m"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.duration, Some(Duration::from_secs(5400)));
+
+        let arguments = &[BINARY, "maintenance"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "maintenance requires a --duration");
+
+        let arguments = &[BINARY, "maintenance", "--duration", "30x"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "invalid duration provided: 30x");
+
+        let arguments = &[BINARY, "maintenance", "--duration", "2d"];
+        assert!(NtpCtlOptions::try_parse_from(arguments).is_err());
+    }
+
+    #[test]
+    fn cli_reload() {
+        let arguments = &[BINARY, "reload", "-c", "/etc/ntpd-rs/ntp.toml"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Reload);
+        assert_eq!(
+            options.config.as_deref(),
+            Some(Path::new("/etc/ntpd-rs/ntp.toml"))
+        );
+    }
+
+    #[test]
+    fn cli_tune() {
+        let arguments = &[BINARY, "tune"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Tune);
+        assert_eq!(options.step_threshold, None);
+
+        let arguments = &[
+            BINARY,
+            "tune",
+            "--step-threshold",
+            "125ms",
+            "--slew-maximum-frequency-offset=100e-6",
+            "--slew-minimum-duration",
+            "4",
+        ];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::Tune);
+        assert_eq!(options.step_threshold, Some(0.125));
+        assert_eq!(options.slew_maximum_frequency_offset, Some(100e-6));
+        assert_eq!(options.slew_minimum_duration, Some(4.0));
+
+        let arguments = &[BINARY, "tune", "--step-threshold", "fast"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "invalid duration provided: fast");
+    }
+
+    #[test]
+    fn cli_state() {
+        let arguments = &[BINARY, "state", "export", "/tmp/state.json"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::ExportState);
+        assert_eq!(
+            options.export_state.as_deref(),
+            Some(Path::new("/tmp/state.json"))
+        );
+
+        let arguments = &[BINARY, "state", "import", "/tmp/state.json", "-c", "a.toml"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::ImportState);
+        assert_eq!(
+            options.import_state.as_deref(),
+            Some(Path::new("/tmp/state.json"))
+        );
+
+        let arguments = &[BINARY, "state", "export"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "missing file for command: state");
+
+        let arguments = &[BINARY, "state", "dump", "/tmp/state.json"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "state requires export, import or verify");
+
+        let arguments = &[BINARY, "state", "verify", "-c", "a.toml"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::VerifyState);
+    }
+
+    #[test]
+    fn cli_rehearse_leap() {
+        let arguments = &[BINARY, "rehearse-leap", "-c", "/etc/ntpd-rs/ntp.toml"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::RehearseLeap);
+        assert_eq!(options.rehearse_leap, Some(NtpLeapIndicator::Leap59));
+
+        let arguments = &[BINARY, "rehearse-leap", "insert"];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.rehearse_leap, Some(NtpLeapIndicator::Leap61));
+
+        let arguments = &[BINARY, "rehearse-leap", "smear"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "unknown leap second: smear");
+    }
+
+    #[test]
+    fn cli_generate_keys() {
+        let arguments = &[
+            BINARY,
+            "nts",
+            "generate-keys",
+            "--hostname",
+            "time.example.com",
+            "--output=/tmp/keys",
+        ];
+        let options = NtpCtlOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, NtpCtlAction::GenerateKeys);
+        assert_eq!(options.hostname.as_deref(), Some("time.example.com"));
+        assert_eq!(options.output.as_deref(), Some(Path::new("/tmp/keys")));
+
+        let arguments = &[BINARY, "nts", "generate-keys"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "nts generate-keys requires a --hostname");
+
+        let arguments = &[BINARY, "nts", "revoke"];
+        let err = NtpCtlOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "unknown nts command: revoke");
+    }
 }
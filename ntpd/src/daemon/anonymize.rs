@@ -0,0 +1,100 @@
+use std::{
+    fmt::Display,
+    hash::{BuildHasher, RandomState},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::LazyLock,
+};
+
+use serde::Deserialize;
+
+/// Key of the hashes, random for every run of the daemon so that hashes cannot
+/// be reversed by hashing every IPv4 address, but the same for all servers so
+/// a client can be followed from the logs to the census
+static HASH_KEY: LazyLock<RandomState> = LazyLock::new(RandomState::new);
+
+/// How the addresses of clients are recorded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anonymization {
+    /// The full address
+    #[default]
+    None,
+    /// Only the network of the address: the first 24 bits of IPv4 addresses
+    /// and the first 48 bits of IPv6 addresses
+    Truncate,
+    /// A keyed hash of the address
+    Hash,
+}
+
+/// A client address after anonymization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Client {
+    Address(IpAddr),
+    Hash(u64),
+}
+
+impl Display for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Client::Address(ip) => write!(f, "{ip}"),
+            Client::Hash(hash) => write!(f, "{hash:016x}"),
+        }
+    }
+}
+
+impl Anonymization {
+    pub fn client(self, ip: IpAddr) -> Client {
+        let ip = ip.to_canonical();
+        match self {
+            Anonymization::None => Client::Address(ip),
+            Anonymization::Truncate => Client::Address(match ip {
+                IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from_bits(ip.to_bits() & 0xffff_ff00)),
+                IpAddr::V6(ip) => {
+                    IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & !(u128::MAX >> 48)))
+                }
+            }),
+            Anonymization::Hash => Client::Hash(HASH_KEY.hash_one(ip)),
+        }
+    }
+
+    /// The address of a client as it is written to the logs, only including
+    /// the port when the address is not anonymized
+    pub fn log(self, addr: SocketAddr) -> String {
+        match self {
+            Anonymization::None => addr.to_string(),
+            _ => self.client(addr.ip()).to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymize_client() {
+        let v4: IpAddr = "192.0.2.123".parse().unwrap();
+        let mapped: IpAddr = "::ffff:192.0.2.123".parse().unwrap();
+        let v6: IpAddr = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+
+        assert_eq!(Anonymization::None.client(mapped), Client::Address(v4));
+        assert_eq!(
+            Anonymization::Truncate.client(v4),
+            Client::Address("192.0.2.0".parse().unwrap())
+        );
+        assert_eq!(
+            Anonymization::Truncate.client(v6),
+            Client::Address("2001:db8:1::".parse().unwrap())
+        );
+
+        let hash = Anonymization::Hash.client(v4);
+        assert!(matches!(hash, Client::Hash(_)));
+        assert_eq!(Anonymization::Hash.client(mapped), hash);
+        assert_ne!(Anonymization::Hash.client(v6), hash);
+        assert_eq!(hash.to_string().len(), 16);
+
+        let addr = SocketAddr::new(v4, 123);
+        assert_eq!(Anonymization::None.log(addr), "192.0.2.123:123");
+        assert_eq!(Anonymization::Truncate.log(addr), "192.0.2.0");
+    }
+}
@@ -0,0 +1,265 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+use super::config::BlackoutConfig;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// Times at which a blackout window starts, in the format of a crontab entry:
+/// minute, hour, day of the month, month and day of the week, all in UTC.
+///
+/// Every field is `*`, a value, a range `a-b`, any of those followed by a step
+/// `/n`, or a comma separated list of these. As in cron, when both the day of
+/// the month and the day of the week are restricted, either of them matching is
+/// enough.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+#[derive(Debug)]
+pub struct ScheduleError(String);
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid schedule: {}", self.0)
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+/// Parse a single field into a bitmask of the values it matches
+fn parse_field(field: &str, min: u64, max: u64) -> Result<u64, ScheduleError> {
+    let invalid = || ScheduleError(format!("{field:?} is not a list of values in {min}-{max}"));
+    let value = |v: &str| {
+        v.parse::<u64>()
+            .ok()
+            .filter(|v| (min..=max).contains(v))
+            .ok_or_else(invalid)
+    };
+
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u64>().map_err(|_| invalid())?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for v in (start..=end).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+
+    Ok(mask)
+}
+
+impl FromStr for Schedule {
+    type Err = ScheduleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(ScheduleError(format!("{s:?} does not have five fields")));
+        };
+
+        let mut weekday_mask = parse_field(weekdays, 0, 7)?;
+        // both 0 and 7 are sunday
+        if weekday_mask & (1 << 7) != 0 {
+            weekday_mask |= 1;
+        }
+
+        Ok(Schedule {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_mask,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let data: String = Deserialize::deserialize(deserializer)?;
+        data.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Schedule {
+    /// Whether a window starts at the given minute since the unix epoch
+    fn matches(&self, unix_minute: u64) -> bool {
+        let minute = unix_minute % 60;
+        let hour = unix_minute / 60 % 24;
+        let days_since_epoch = unix_minute / (SECONDS_PER_DAY / 60);
+        let (month, day) = month_and_day(days_since_epoch);
+        // the unix epoch was on a thursday
+        let weekday = (days_since_epoch + 4) % 7;
+
+        let day_matches = self.days & (1 << day) != 0;
+        let weekday_matches = self.weekdays & (1 << weekday) != 0;
+        let date_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        };
+
+        self.minutes & (1 << minute) != 0
+            && self.hours & (1 << hour) != 0
+            && self.months & (1 << month) != 0
+            && date_matches
+    }
+}
+
+// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn month_and_day(days_since_unix_epoch: u64) -> (u64, u64) {
+    let z = days_since_unix_epoch as i64 + 719468;
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (month as u64, day as u64)
+}
+
+/// How steering of the clock is restricted during a blackout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Restriction {
+    Suspended,
+    /// Only slews of at most this many seconds
+    Limited(f64),
+}
+
+impl BlackoutConfig {
+    /// Start of the window of this blackout that `unix_seconds` falls in, if any
+    fn active_since(&self, unix_seconds: u64) -> Option<u64> {
+        let now_minute = unix_seconds / 60;
+        (0..=self.duration / 60)
+            .map_while(|back| now_minute.checked_sub(back))
+            .map(|minute| minute * 60)
+            .take_while(|start| start + self.duration > unix_seconds)
+            .find(|start| self.schedule.matches(start / 60))
+    }
+
+    fn restriction(&self) -> Restriction {
+        match self.max_slew {
+            Some(max_slew) => Restriction::Limited(max_slew),
+            None => Restriction::Suspended,
+        }
+    }
+}
+
+/// The restriction of all blackouts combined at `unix_seconds`, and the
+/// number of seconds after which this needs to be checked again
+pub fn restriction(blackouts: &[BlackoutConfig], unix_seconds: u64) -> (Option<Restriction>, u64) {
+    // windows only start on whole minutes
+    let mut next_check = 60 - unix_seconds % 60;
+    let mut combined = None;
+    for blackout in blackouts {
+        let Some(start) = blackout.active_since(unix_seconds) else {
+            continue;
+        };
+        next_check = next_check.min(start + blackout.duration - unix_seconds);
+        combined = Some(match (combined, blackout.restriction()) {
+            (None, restriction) => restriction,
+            (Some(Restriction::Limited(a)), Restriction::Limited(b)) => {
+                Restriction::Limited(f64::min(a, b))
+            }
+            _ => Restriction::Suspended,
+        });
+    }
+
+    (combined, next_check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-15T14:30:00Z, a friday
+    const NOW: u64 = 1710513000;
+
+    fn blackout(schedule: &str, duration: u64, max_slew: Option<f64>) -> BlackoutConfig {
+        BlackoutConfig {
+            schedule: schedule.parse().unwrap(),
+            duration,
+            max_slew,
+        }
+    }
+
+    #[test]
+    fn test_parse_schedule() {
+        assert!("* * * * *".parse::<Schedule>().is_ok());
+        assert!("*/15 9-17 1,15 * 1-5".parse::<Schedule>().is_ok());
+        assert!("0 0 * * 7".parse::<Schedule>().is_ok());
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("* 5-3 * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("* * 0 * *".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn test_schedule_matches() {
+        let minute = NOW / 60;
+        assert!(matches!("30 14 * * *".parse::<Schedule>(), Ok(s) if s.matches(minute)));
+        assert!(matches!("30 14 15 3 5".parse::<Schedule>(), Ok(s) if s.matches(minute)));
+        assert!(matches!("*/10 * * * fri".parse::<Schedule>(), Err(_)));
+        assert!(matches!("*/10 * * * 5".parse::<Schedule>(), Ok(s) if s.matches(minute)));
+        assert!(matches!("30 14 * * 4".parse::<Schedule>(), Ok(s) if !s.matches(minute)));
+        // either the day of the month or the day of the week
+        assert!(matches!("30 14 1 * 5".parse::<Schedule>(), Ok(s) if s.matches(minute)));
+        assert!(matches!("30 14 15 * 1".parse::<Schedule>(), Ok(s) if s.matches(minute)));
+        assert!(matches!("30 14 * 4 *".parse::<Schedule>(), Ok(s) if !s.matches(minute)));
+    }
+
+    #[test]
+    fn test_blackout_restriction() {
+        let blackouts = [
+            blackout("25 14 * * *", 600, Some(0.001)),
+            blackout("29 14 * * *", 120, None),
+        ];
+
+        assert_eq!(restriction(&blackouts, NOW - 600), (None, 60));
+        assert_eq!(
+            restriction(&blackouts, NOW - 300),
+            (Some(Restriction::Limited(0.001)), 60)
+        );
+        assert_eq!(
+            restriction(&blackouts, NOW + 30),
+            (Some(Restriction::Suspended), 30)
+        );
+        assert_eq!(
+            restriction(&blackouts, NOW + 90),
+            (Some(Restriction::Limited(0.001)), 30)
+        );
+        assert_eq!(
+            restriction(&blackouts, NOW + 295),
+            (Some(Restriction::Limited(0.001)), 5)
+        );
+        assert_eq!(restriction(&blackouts, NOW + 300), (None, 60));
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::anonymize::{Anonymization, Client};
+
+const SECONDS_PER_HOUR: u64 = 3600;
+
+/// Number of completed hours for which reports are kept
+const HOURS_KEPT: usize = 24;
+
+/// Number of clients listed as top talkers in a report
+const TOP_TALKERS: usize = 10;
+
+/// Clients tracked per hour, bounding the memory used on busy (pool) servers.
+/// Packets of further clients are still counted, but not per client.
+const MAX_CLIENTS: usize = 100_000;
+
+/// Statistics of the clients served by a server during one hour
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CensusReport {
+    /// Start of the hour, in seconds since the unix epoch
+    pub start: u64,
+    /// Whether the hour is still in progress
+    pub partial: bool,
+    pub unique_clients: usize,
+    /// Packets received per NTP version
+    pub versions: BTreeMap<u8, u64>,
+    pub nts_packets: u64,
+    pub plain_packets: u64,
+    /// Packets of clients beyond the number that is tracked per hour
+    pub untracked_packets: u64,
+    /// The clients that sent the most packets, most packets first, in the
+    /// anonymized form the census keeps them in
+    pub top_talkers: Vec<(String, u64)>,
+}
+
+#[derive(Debug)]
+struct Hour {
+    start: u64,
+    clients: HashMap<Client, u64>,
+    versions: BTreeMap<u8, u64>,
+    nts_packets: u64,
+    plain_packets: u64,
+    untracked_packets: u64,
+}
+
+impl Hour {
+    fn new(unix_seconds: u64) -> Self {
+        Hour {
+            start: unix_seconds - unix_seconds % SECONDS_PER_HOUR,
+            clients: HashMap::new(),
+            versions: BTreeMap::new(),
+            nts_packets: 0,
+            plain_packets: 0,
+            untracked_packets: 0,
+        }
+    }
+
+    fn report(&self, partial: bool) -> CensusReport {
+        let mut top_talkers: Vec<_> = self.clients.iter().collect();
+        top_talkers.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        let top_talkers = top_talkers
+            .into_iter()
+            .take(TOP_TALKERS)
+            .map(|(client, packets)| (client.to_string(), *packets))
+            .collect();
+
+        CensusReport {
+            start: self.start,
+            partial,
+            unique_clients: self.clients.len(),
+            versions: self.versions.clone(),
+            nts_packets: self.nts_packets,
+            plain_packets: self.plain_packets,
+            untracked_packets: self.untracked_packets,
+            top_talkers,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CensusState {
+    anonymization: Anonymization,
+    current: Hour,
+    completed: Vec<CensusReport>,
+}
+
+impl CensusState {
+    /// Move on to the hour `unix_seconds` falls in, if it is a later one
+    fn advance(&mut self, unix_seconds: u64) {
+        if unix_seconds < self.current.start + SECONDS_PER_HOUR {
+            return;
+        }
+
+        let previous = std::mem::replace(&mut self.current, Hour::new(unix_seconds));
+        // hours without any client are left out
+        if !previous.versions.is_empty() {
+            self.completed.push(previous.report(false));
+        }
+        if self.completed.len() > HOURS_KEPT {
+            self.completed.remove(0);
+        }
+    }
+}
+
+/// Hourly statistics of the clients of a server, shared between the server
+/// task and the observer
+#[derive(Debug, Clone)]
+pub struct Census {
+    state: Arc<Mutex<CensusState>>,
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl Census {
+    pub fn new(anonymization: Anonymization) -> Self {
+        Census::starting_at(anonymization, unix_now())
+    }
+
+    fn starting_at(anonymization: Anonymization, unix_seconds: u64) -> Self {
+        Census {
+            state: Arc::new(Mutex::new(CensusState {
+                anonymization,
+                current: Hour::new(unix_seconds),
+                completed: vec![],
+            })),
+        }
+    }
+
+    /// Count a packet received from `client`
+    pub fn record(&self, client: IpAddr, version: u8, nts: bool) {
+        self.record_at(client, version, nts, unix_now())
+    }
+
+    fn record_at(&self, client: IpAddr, version: u8, nts: bool, unix_seconds: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.advance(unix_seconds);
+
+        let client = state.anonymization.client(client);
+        let hour = &mut state.current;
+        *hour.versions.entry(version).or_default() += 1;
+        if nts {
+            hour.nts_packets += 1;
+        } else {
+            hour.plain_packets += 1;
+        }
+
+        if let Some(count) = hour.clients.get_mut(&client) {
+            *count += 1;
+        } else if hour.clients.len() < MAX_CLIENTS {
+            hour.clients.insert(client, 1);
+        } else {
+            hour.untracked_packets += 1;
+        }
+    }
+
+    /// Reports of the last completed hours, followed by one of the current hour
+    pub fn reports(&self) -> Vec<CensusReport> {
+        self.reports_at(unix_now())
+    }
+
+    fn reports_at(&self, unix_seconds: u64) -> Vec<CensusReport> {
+        let mut state = self.state.lock().unwrap();
+        state.advance(unix_seconds);
+
+        let mut reports = state.completed.clone();
+        reports.push(state.current.report(true));
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-15T14:30:00Z
+    const NOW: u64 = 1710513000;
+
+    #[test]
+    fn test_census_report() {
+        let census = Census::starting_at(Anonymization::None, NOW);
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "2001:db8::1".parse().unwrap();
+        let mapped: IpAddr = "::ffff:192.0.2.1".parse().unwrap();
+
+        census.record_at(a, 4, false, NOW);
+        census.record_at(b, 4, true, NOW + 10);
+        census.record_at(mapped, 3, false, NOW + 20);
+
+        let reports = census.reports_at(NOW + 30);
+        assert_eq!(
+            reports,
+            vec![CensusReport {
+                start: NOW - 1800,
+                partial: true,
+                unique_clients: 2,
+                versions: BTreeMap::from([(3, 1), (4, 2)]),
+                nts_packets: 1,
+                plain_packets: 2,
+                untracked_packets: 0,
+                top_talkers: vec![(a.to_string(), 2), (b.to_string(), 1)],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_census_anonymization() {
+        let census = Census::starting_at(Anonymization::Truncate, NOW);
+        census.record_at("192.0.2.1".parse().unwrap(), 4, false, NOW);
+        census.record_at("192.0.2.2".parse().unwrap(), 4, false, NOW);
+
+        let reports = census.reports_at(NOW);
+        assert_eq!(reports[0].unique_clients, 1);
+        assert_eq!(reports[0].top_talkers, vec![("192.0.2.0".to_owned(), 2)]);
+    }
+
+    #[test]
+    fn test_census_hours() {
+        let census = Census::starting_at(Anonymization::None, NOW);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+
+        census.record_at(client, 4, false, NOW);
+        // nothing is received in the hour after
+        census.record_at(client, 4, false, NOW + 2 * SECONDS_PER_HOUR);
+
+        let reports = census.reports_at(NOW + 2 * SECONDS_PER_HOUR);
+        assert_eq!(reports.len(), 2);
+        assert!(!reports[0].partial);
+        assert_eq!(reports[0].start, NOW - 1800);
+        assert!(reports[1].partial);
+        assert_eq!(reports[1].start, NOW - 1800 + 2 * SECONDS_PER_HOUR);
+
+        for hour in 3..(HOURS_KEPT as u64 + 10) {
+            census.record_at(client, 4, false, NOW + hour * SECONDS_PER_HOUR);
+        }
+        let reports = census.reports_at(NOW + 100 * SECONDS_PER_HOUR);
+        assert_eq!(reports.len(), HOURS_KEPT + 1);
+        assert_eq!(reports[HOURS_KEPT].unique_clients, 0);
+    }
+}
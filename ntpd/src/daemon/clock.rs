@@ -3,6 +3,14 @@ use ntp_proto::NtpClock;
 
 use super::util::convert_clock_timestamp;
 
+/// The clock steered by the daemon.
+///
+/// The platform specific parts live behind the [`Clock`] trait of the
+/// `clock-steering` crate: on Linux the clock is steered with `clock_adjtime`,
+/// which also allows steering PTP hardware clocks, while on FreeBSD and macOS
+/// only the system clock can be steered, through `ntp_adjtime` and
+/// `clock_settime`. This wrapper only translates between the types of that
+/// crate and those of `ntp-proto`.
 #[derive(Debug, Clone, Copy)]
 pub struct NtpClockWrapper(UnixClock);
 
@@ -10,6 +18,12 @@ impl NtpClockWrapper {
     pub fn new(clock: UnixClock) -> Self {
         NtpClockWrapper(clock)
     }
+
+    /// Offset in seconds of TAI to UTC as known by the kernel, zero when it
+    /// has not been set
+    pub fn tai_offset(&self) -> Result<i32, <UnixClock as Clock>::Error> {
+        self.0.get_tai()
+    }
 }
 
 impl Default for NtpClockWrapper {
@@ -72,4 +86,8 @@ impl NtpClock for NtpClockWrapper {
             ntp_proto::NtpLeapIndicator::Unsynchronized => clock_steering::LeapIndicator::Unknown,
         })
     }
+
+    fn set_tai_offset(&self, tai_offset: i32) -> Result<(), Self::Error> {
+        self.0.set_tai(tai_offset)
+    }
 }
@@ -0,0 +1,201 @@
+use std::{path::PathBuf, time::Duration};
+
+use ntp_proto::{NtpClock, NtpDuration};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span, debug, info, instrument, warn};
+
+use super::{clock::NtpClockWrapper, config::Timescale};
+
+/// How often the followed clock is compared to the steered clock
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+// Offsets beyond this are corrected with a step instead of a frequency change,
+// this happens when the steered clock itself was stepped.
+const STEP_THRESHOLD: f64 = 0.1;
+
+// On the first comparison even small offsets are stepped away, as slewing them
+// out would take a while.
+const FIRST_STEP_THRESHOLD: f64 = 20e-6;
+
+// Gains of the servo for a FOLLOW_INTERVAL of a second, these match those that
+// phc2sys uses for hardware clocks.
+const PROPORTIONAL_GAIN: f64 = 0.7;
+const INTEGRAL_GAIN: f64 = 0.3;
+
+const MAX_FREQUENCY: f64 = 500e-6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ServoAction {
+    Step(f64),
+    Frequency(f64),
+}
+
+/// A PI controller that turns the measured offset of the follower relative
+/// to the steered clock into a correction of the follower
+#[derive(Debug)]
+struct Servo {
+    frequency: f64,
+    first: bool,
+}
+
+impl Servo {
+    fn new(frequency: f64) -> Self {
+        Servo {
+            frequency,
+            first: true,
+        }
+    }
+
+    fn update(&mut self, offset: f64) -> ServoAction {
+        let threshold = if self.first {
+            FIRST_STEP_THRESHOLD
+        } else {
+            STEP_THRESHOLD
+        };
+        self.first = false;
+
+        if offset.abs() > threshold {
+            return ServoAction::Step(-offset);
+        }
+
+        let seconds = FOLLOW_INTERVAL.as_secs_f64();
+        self.frequency = (self.frequency - INTEGRAL_GAIN * offset / seconds)
+            .clamp(-MAX_FREQUENCY, MAX_FREQUENCY);
+        ServoAction::Frequency(
+            (self.frequency - PROPORTIONAL_GAIN * offset / seconds)
+                .clamp(-MAX_FREQUENCY, MAX_FREQUENCY),
+        )
+    }
+}
+
+/// Keep the clock at `path`, which keeps `timescale`, in step with the clock
+/// steered by the daemon
+#[instrument(level = tracing::Level::ERROR, skip(steered), name = "Follow clock")]
+pub fn spawn(steered: NtpClockWrapper, path: PathBuf, timescale: Timescale) -> JoinHandle<()> {
+    tokio::spawn(
+        (async move {
+            let follower = match open(&path) {
+                Ok(follower) => follower,
+                Err(e) => {
+                    warn!(error = ?e, "Could not open clock to follow");
+                    return;
+                }
+            };
+
+            if let Err(e) = follow(steered, follower, timescale).await {
+                warn!(error = ?e, "Stopped following the steered clock");
+            }
+        })
+        .instrument(Span::current()),
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn open(path: &std::path::Path) -> std::io::Result<NtpClockWrapper> {
+    let clock = clock_steering::unix::UnixClock::open(path)?;
+    Ok(NtpClockWrapper::new(clock))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open(_path: &std::path::Path) -> std::io::Result<NtpClockWrapper> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "following clocks is not supported on this platform",
+    ))
+}
+
+/// Seconds the follower should be ahead of the steered clock, which keeps
+/// UTC, or `None` while that is not known
+fn timescale_offset(timescale: Timescale, tai_offset: i32) -> Option<f64> {
+    match timescale {
+        Timescale::Utc => Some(0.0),
+        Timescale::Tai if tai_offset > 0 => Some(tai_offset.into()),
+        Timescale::Tai => None,
+    }
+}
+
+async fn follow(
+    steered: NtpClockWrapper,
+    follower: NtpClockWrapper,
+    timescale: Timescale,
+) -> Result<(), <NtpClockWrapper as NtpClock>::Error> {
+    follower.disable_ntp_algorithm()?;
+    let mut servo = Servo::new(follower.get_frequency()?);
+    let mut waiting = false;
+
+    let mut interval = tokio::time::interval(FOLLOW_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        // The TAI offset changes with leap seconds, so it is read every time
+        let Some(timescale_offset) = timescale_offset(timescale, steered.tai_offset()?) else {
+            if !waiting {
+                info!("waiting for the TAI offset to be known before following the clock");
+                waiting = true;
+            }
+            continue;
+        };
+        waiting = false;
+
+        // Reading the steered clock on both sides of the follower
+        // cancels out most of the time the reads take
+        let before = steered.now()?;
+        let follower_now = follower.now()?;
+        let after = steered.now()?;
+        let steered_now = before + (after - before) / 2;
+        let offset = (follower_now - steered_now).to_seconds() - timescale_offset;
+
+        match servo.update(offset) {
+            ServoAction::Step(correction) => {
+                info!(offset, "stepping followed clock");
+                follower.step_clock(NtpDuration::from_seconds(correction))?;
+            }
+            ServoAction::Frequency(frequency) => {
+                debug!(offset, ppm = frequency * 1e6, "adjusting followed clock");
+                follower.set_frequency(frequency)?;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timescale_offset() {
+        assert_eq!(timescale_offset(Timescale::Utc, 0), Some(0.0));
+        assert_eq!(timescale_offset(Timescale::Utc, 37), Some(0.0));
+        assert_eq!(timescale_offset(Timescale::Tai, 37), Some(37.0));
+        // an unset offset would step a TAI clock to UTC
+        assert_eq!(timescale_offset(Timescale::Tai, 0), None);
+    }
+
+    #[test]
+    fn test_servo() {
+        let mut servo = Servo::new(0.0);
+
+        // any noticeable initial offset is stepped away
+        assert_eq!(servo.update(1e-3), ServoAction::Step(-1e-3));
+
+        // afterwards, a follower that runs ahead is slowed down
+        let ServoAction::Frequency(frequency) = servo.update(1e-6) else {
+            panic!("expected a frequency adjustment");
+        };
+        assert!((frequency + 1e-6).abs() < 1e-12);
+
+        // and an offset without error leaves the learned frequency
+        let ServoAction::Frequency(frequency) = servo.update(0.0) else {
+            panic!("expected a frequency adjustment");
+        };
+        assert!((frequency + 0.3e-6).abs() < 1e-12);
+
+        // large offsets, such as after the steered clock steps, are stepped
+        assert_eq!(servo.update(-0.5), ServoAction::Step(0.5));
+
+        // the frequency is kept within what a clock can plausibly need
+        assert_eq!(servo.update(0.05), ServoAction::Frequency(-MAX_FREQUENCY));
+    }
+}
@@ -0,0 +1,260 @@
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use ntp_proto::deserialize_whole_seconds;
+use serde::{Deserialize, Deserializer, de};
+
+/// Sources fetched from a central server, see the fleet module
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FleetConfig {
+    /// Where the list of sources is fetched from
+    pub url: HttpsUrl,
+    /// Where the minisign signature of the list is fetched from, by default
+    /// the URL of the list with `.minisig` appended
+    #[serde(default)]
+    pub signature_url: Option<HttpsUrl>,
+    /// Key the list must be signed with
+    pub public_key: MinisignPublicKey,
+    /// Seconds between fetches of the list
+    #[serde(
+        default = "default_refresh_interval",
+        deserialize_with = "deserialize_refresh_interval"
+    )]
+    pub refresh_interval: u64,
+    /// File keeping the last list and its signature, used at startup
+    #[serde(default)]
+    pub cache_path: Option<PathBuf>,
+}
+
+impl FleetConfig {
+    pub fn signature_url(&self) -> HttpsUrl {
+        self.signature_url
+            .clone()
+            .unwrap_or_else(|| self.url.with_suffix(".minisig"))
+    }
+}
+
+fn default_refresh_interval() -> u64 {
+    3600
+}
+
+fn deserialize_refresh_interval<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match deserialize_whole_seconds(deserializer)? {
+        0 => Err(de::Error::custom(
+            "the refresh interval must be at least 1 second",
+        )),
+        seconds => Ok(seconds),
+    }
+}
+
+/// An `https://` URL without user information
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct HttpsUrl {
+    pub host: String,
+    pub port: u16,
+    /// Path and query
+    pub path: String,
+}
+
+impl HttpsUrl {
+    /// The host and, when not the default, the port, as sent in the `Host`
+    /// header
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match self.port {
+            443 => host,
+            port => format!("{host}:{port}"),
+        }
+    }
+
+    /// This URL with `suffix` appended to its path, before any query
+    fn with_suffix(&self, suffix: &str) -> HttpsUrl {
+        let path = match self.path.split_once('?') {
+            Some((path, query)) => format!("{path}{suffix}?{query}"),
+            None => format!("{}{suffix}", self.path),
+        };
+        HttpsUrl {
+            path,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FleetParseError {
+    Url,
+    PublicKey,
+}
+
+impl std::error::Error for FleetParseError {}
+
+impl Display for FleetParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Url => write!(f, "Invalid URL, expected https://host[:port]/path"),
+            Self::PublicKey => write!(f, "Invalid minisign public key"),
+        }
+    }
+}
+
+impl FromStr for HttpsUrl {
+    type Err = FleetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s.strip_prefix("https://").ok_or(FleetParseError::Url)?;
+        let rest = rest.split_once('#').map_or(rest, |(rest, _)| rest);
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('/') => (&rest[..i], rest[i..].to_owned()),
+            Some(i) => (&rest[..i], format!("/{}", &rest[i..])),
+            None => (rest, "/".to_owned()),
+        };
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(authority) => {
+                let (host, port) = authority.split_once(']').ok_or(FleetParseError::Url)?;
+                (host, port.strip_prefix(':'))
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| FleetParseError::Url)?,
+            None => 443,
+        };
+        if host.is_empty() || host.contains(['@', ' ']) {
+            return Err(FleetParseError::Url);
+        }
+
+        Ok(HttpsUrl {
+            host: host.to_owned(),
+            port,
+            path,
+        })
+    }
+}
+
+impl Display for HttpsUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "https://{}{}", self.authority(), self.path)
+    }
+}
+
+impl<'de> Deserialize<'de> for HttpsUrl {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// An Ed25519 public key in the minisign format, as on the second line of a
+/// minisign public key file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MinisignPublicKey(pub minisign_verify::PublicKey);
+
+impl FromStr for MinisignPublicKey {
+    type Err = FleetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        minisign_verify::PublicKey::from_base64(s.trim())
+            .map(MinisignPublicKey)
+            .map_err(|_| FleetParseError::PublicKey)
+    }
+}
+
+impl<'de> Deserialize<'de> for MinisignPublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        let url: HttpsUrl = "https://config.example.com/ntp/sources.toml"
+            .parse()
+            .unwrap();
+        assert_eq!(url.host, "config.example.com");
+        assert_eq!(url.port, 443);
+        assert_eq!(url.path, "/ntp/sources.toml");
+        assert_eq!(
+            url.to_string(),
+            "https://config.example.com/ntp/sources.toml"
+        );
+
+        let url: HttpsUrl = "https://[2001:db8::1]:8443?fleet=a".parse().unwrap();
+        assert_eq!(url.host, "2001:db8::1");
+        assert_eq!(url.port, 8443);
+        assert_eq!(url.path, "/?fleet=a");
+        assert_eq!(url.authority(), "[2001:db8::1]:8443");
+        assert_eq!(
+            url.with_suffix(".minisig").to_string(),
+            "https://[2001:db8::1]:8443/.minisig?fleet=a"
+        );
+
+        assert!("http://config.example.com/".parse::<HttpsUrl>().is_err());
+        assert!(
+            "https://user@config.example.com/"
+                .parse::<HttpsUrl>()
+                .is_err()
+        );
+        assert!(
+            "https://config.example.com:https/"
+                .parse::<HttpsUrl>()
+                .is_err()
+        );
+        assert!("https:///sources.toml".parse::<HttpsUrl>().is_err());
+    }
+
+    #[test]
+    fn test_deserialize_fleet() {
+        let fleet: FleetConfig = toml::from_str(
+            r#"
+            url = "https://config.example.com/sources.toml"
+            public-key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(fleet.refresh_interval, 3600);
+        assert_eq!(fleet.cache_path, None);
+        assert_eq!(
+            fleet.signature_url().to_string(),
+            "https://config.example.com/sources.toml.minisig"
+        );
+
+        let fleet: Result<FleetConfig, _> = toml::from_str(
+            r#"
+            url = "https://config.example.com/sources.toml"
+            public-key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0Q"
+            "#,
+        );
+        assert!(fleet.is_err());
+
+        let fleet: Result<FleetConfig, _> = toml::from_str(
+            r#"
+            url = "https://config.example.com/sources.toml"
+            public-key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"
+            refresh-interval = 0
+            "#,
+        );
+        assert!(fleet.is_err());
+    }
+}
@@ -1,9 +1,12 @@
+mod fleet;
 mod ntp_source;
 mod server;
 
 use clock_steering::unix::UnixClock;
+pub use fleet::*;
 use ntp_proto::{
-    AlgorithmConfig, NtpVersion, ProtocolVersion, SourceConfig, SynchronizationConfig,
+    AlgorithmConfig, NtpVersion, ProtocolVersion, SourceConfig, SynchronizationConfig, Transport,
+    deserialize_seconds, deserialize_whole_seconds,
 };
 pub use ntp_source::*;
 use serde::{Deserialize, Deserializer};
@@ -20,7 +23,7 @@ use std::{
 use timestamped_socket::interface::InterfaceName;
 use tracing::{info, warn};
 
-use super::{clock::NtpClockWrapper, tracing::LogLevel};
+use super::{blackout::Schedule, clock::NtpClockWrapper, nat64::Nat64Prefix, tracing::LogLevel};
 
 const USAGE_MSG: &str = "\
 usage: ntp-daemon [-c PATH] [-l LOG_LEVEL]
@@ -223,7 +226,9 @@ where
         ));
 
         #[cfg(not(target_os = "linux"))]
-        panic!("Custom clock paths not supported on this platform");
+        return Err(serde::de::Error::custom(format!(
+            "cannot use clock {path:?}, only the system clock can be steered on this platform"
+        )));
     } else {
         tracing::debug!("using REALTIME clock");
         Ok(NtpClockWrapper::new(UnixClock::CLOCK_REALTIME))
@@ -261,6 +266,17 @@ pub enum TimestampMode {
     Hardware,
 }
 
+/// Timescale of a clock the daemon does not steer directly
+#[derive(Default, Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Timescale {
+    /// Coordinated universal time, like the system clock
+    #[default]
+    Utc,
+    /// International atomic time, which PTP uses
+    Tai,
+}
+
 impl TimestampMode {
     #[cfg(target_os = "linux")]
     pub(crate) fn as_interface_mode(self) -> timestamped_socket::socket::InterfaceTimestampMode {
@@ -290,7 +306,7 @@ impl TimestampMode {
     }
 }
 
-#[derive(Deserialize, Debug, Copy, Clone, Default)]
+#[derive(Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct ClockConfig {
     #[serde(deserialize_with = "deserialize_ntp_clock", default)]
@@ -298,6 +314,44 @@ pub struct ClockConfig {
     #[serde(deserialize_with = "deserialize_interface", default)]
     pub interface: Option<InterfaceName>,
     pub timestamp_mode: TimestampMode,
+    /// Clocks (such as PTP hardware clocks) that are kept in step with `clock`
+    #[serde(default)]
+    pub follow_clocks: Vec<PathBuf>,
+    /// Timescale the clocks in `follow_clocks` keep
+    #[serde(default)]
+    pub follow_clocks_timescale: Timescale,
+    /// Pulse per second output aligned to the seconds of `clock`
+    #[serde(default)]
+    pub pps_output: Option<PpsOutputConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PpsOutputConfig {
+    /// File that is set to `1` at the start of each pulse and `0` at its end,
+    /// such as the value of a GPIO line
+    pub path: PathBuf,
+    /// Length of the pulse in seconds
+    #[serde(
+        default = "default_pps_output_pulse_width",
+        deserialize_with = "deserialize_pulse_width"
+    )]
+    pub pulse_width: f64,
+}
+
+fn default_pps_output_pulse_width() -> f64 {
+    0.1
+}
+
+fn deserialize_pulse_width<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let pulse_width = deserialize_seconds(deserializer)?;
+    if pulse_width > 0.0 && pulse_width < 1.0 {
+        Ok(pulse_width)
+    } else {
+        Err(serde::de::Error::custom(
+            "pulse-width must be more than 0 and less than 1 second",
+        ))
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -311,8 +365,23 @@ pub struct ObservabilityConfig {
     pub observation_path: Option<PathBuf>,
     #[serde(default = "default_observation_permissions")]
     pub observation_permissions: u32,
+    /// Loopback address on which the observation socket listens instead of
+    /// a Unix socket, `observation_path` then holds the token of clients
+    #[serde(default)]
+    pub observation_address: Option<SocketAddr>,
+    #[serde(default)]
+    pub control_path: Option<PathBuf>,
+    #[serde(default = "default_control_permissions")]
+    pub control_permissions: u32,
+    /// Loopback address on which the control socket listens instead of a
+    /// Unix socket, `control_path` then holds the token of clients
+    #[serde(default)]
+    pub control_address: Option<SocketAddr>,
     #[serde(default = "default_metrics_exporter_listen")]
     pub metrics_exporter_listen: SocketAddr,
+    /// Name of this daemon instance, added to all metrics and the observability output
+    #[serde(default)]
+    pub instance_name: Option<String>,
 }
 
 impl Default for ObservabilityConfig {
@@ -322,7 +391,12 @@ impl Default for ObservabilityConfig {
             ansi_colors: default_ansi_colors(),
             observation_path: Default::default(),
             observation_permissions: default_observation_permissions(),
+            observation_address: None,
+            control_path: Default::default(),
+            control_permissions: default_control_permissions(),
+            control_address: None,
             metrics_exporter_listen: default_metrics_exporter_listen(),
+            instance_name: None,
         }
     }
 }
@@ -335,10 +409,129 @@ const fn default_observation_permissions() -> u32 {
     0o666
 }
 
+const fn default_control_permissions() -> u32 {
+    0o600
+}
+
 fn default_metrics_exporter_listen() -> SocketAddr {
     "127.0.0.1:9975".parse().unwrap()
 }
 
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SchedulingConfig {
+    /// Steer the clock from a thread with SCHED_FIFO scheduling at this
+    /// priority (1-99)
+    #[serde(default)]
+    pub realtime_priority: Option<u8>,
+    /// Restrict the thread that steers the clock to these cpus
+    #[serde(default)]
+    pub cpu_affinity: Vec<usize>,
+}
+
+impl SchedulingConfig {
+    /// Whether the clock can be steered from any thread of the runtime
+    pub fn is_default(&self) -> bool {
+        self.realtime_priority.is_none() && self.cpu_affinity.is_empty()
+    }
+}
+
+/// How far the daemon confines itself once it has set up its clock
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SandboxLevel {
+    /// Keep running with the privileges the daemon was started with
+    #[default]
+    Off,
+    /// Switch to an unprivileged user, keeping only the capability to steer the clock
+    DropPrivileges,
+    /// Also restrict the daemon to the system calls it needs
+    Seccomp,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SandboxConfig {
+    #[serde(default)]
+    pub level: SandboxLevel,
+    /// User to switch to when dropping privileges
+    #[serde(default = "default_sandbox_user")]
+    pub user: String,
+    /// Group to switch to, the primary group of the user when not set
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            level: SandboxLevel::default(),
+            user: default_sandbox_user(),
+            group: None,
+        }
+    }
+}
+
+fn default_sandbox_user() -> String {
+    "ntpd-rs".into()
+}
+
+/// A recurring window during which the clock is not steered, or only slewed
+/// by small amounts
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BlackoutConfig {
+    /// When the windows start
+    pub schedule: Schedule,
+    /// Length of every window in seconds
+    #[serde(deserialize_with = "deserialize_blackout_duration")]
+    pub duration: u64,
+    /// Largest slew allowed during the window in seconds, steering is
+    /// suspended entirely when not set
+    #[serde(default, deserialize_with = "deserialize_max_slew")]
+    pub max_slew: Option<f64>,
+}
+
+// Longer windows are unlikely to be intended, and would keep the clock
+// unsteered for most of the time.
+const MAX_BLACKOUT_DURATION: u64 = 7 * 86400;
+
+fn deserialize_blackout_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u64, D::Error> {
+    let duration = deserialize_whole_seconds(deserializer)?;
+    if duration == 0 || duration > MAX_BLACKOUT_DURATION {
+        return Err(serde::de::Error::custom(format!(
+            "blackout duration must be between 1 and {MAX_BLACKOUT_DURATION} seconds"
+        )));
+    }
+    Ok(duration)
+}
+
+fn deserialize_max_slew<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    let max_slew = f64::deserialize(deserializer)?;
+    if !(max_slew.is_finite() && max_slew > 0.0) {
+        return Err(serde::de::Error::custom(
+            "max-slew must be a positive number of seconds",
+        ));
+    }
+    Ok(Some(max_slew))
+}
+
+/// What to do on startup when the name of a source cannot be resolved
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnresolvableSources {
+    /// Start with the sources that can be resolved, and keep resolving the
+    /// others in the background
+    #[default]
+    Retry,
+    /// Refuse to start
+    Fail,
+}
+
 #[derive(Deserialize, Debug, Default)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct DaemonSynchronizationConfig {
@@ -347,6 +540,57 @@ pub struct DaemonSynchronizationConfig {
 
     #[serde(default)]
     pub algorithm: AlgorithmConfig,
+
+    /// File in which the frequency offset of the clock is kept across restarts
+    #[serde(default)]
+    pub drift_file: Option<PathBuf>,
+
+    /// File in which poll intervals requested by rate limiting servers are kept
+    /// across restarts
+    #[serde(default)]
+    pub kod_history_file: Option<PathBuf>,
+
+    /// File to which all measurements are recorded, for replaying them
+    /// through the algorithm with ntp-replay
+    #[serde(default)]
+    pub measurement_record_file: Option<PathBuf>,
+
+    /// Also record every decision of the clock algorithm to the measurement
+    /// record file
+    #[serde(default)]
+    pub record_decisions: bool,
+
+    /// The `leap-seconds.list` file published by the IERS, to announce leap
+    /// seconds and set the offset of TAI to UTC
+    #[serde(default)]
+    pub leap_seconds_file: Option<PathBuf>,
+
+    /// Recurring windows during which steering of the clock is restricted
+    #[serde(rename = "blackout", default)]
+    pub blackouts: Vec<BlackoutConfig>,
+
+    /// Number of sockets, each with a task of its own, that sources polled
+    /// over UDP share instead of opening their own. Disabled when zero.
+    #[serde(default)]
+    pub shared_sockets: usize,
+
+    /// Keep the socket of a source polled over UDP across polls, rather than
+    /// opening one on a new port for every poll
+    #[serde(default)]
+    pub keep_sockets: bool,
+
+    /// Only measure the sources, without ever steering the clock
+    #[serde(default)]
+    pub prober: bool,
+
+    /// What to do on startup when the name of a source cannot be resolved
+    #[serde(default)]
+    pub unresolvable_sources: UnresolvableSources,
+
+    /// Prefix of the NAT64 gateway through which IPv4 addresses of sources
+    /// are reached from an IPv6-only network
+    #[serde(default)]
+    pub nat64_prefix: Option<Nat64Prefix>,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -367,8 +611,29 @@ pub struct Config {
     #[serde(default)]
     pub keyset: KeysetConfig,
     #[serde(default)]
+    pub crypto_policy: CryptoPolicyConfig,
+    #[serde(default)]
+    pub fleet: Option<FleetConfig>,
+    #[serde(default)]
+    pub scheduling: SchedulingConfig,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    #[serde(default)]
     #[cfg(feature = "hardware-timestamping")]
     pub clock: ClockConfig,
+    /// Hash of the configuration file this was read from, if any
+    #[serde(skip)]
+    pub hash: Option<String>,
+}
+
+/// Hash of the contents of a configuration file, so operators can tell which
+/// configuration a daemon runs with. This is 64-bit FNV-1a, it is not meant to
+/// withstand deliberately crafted collisions.
+fn hash_config(contents: &str) -> String {
+    let hash = contents.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
 }
 
 impl Config {
@@ -381,7 +646,9 @@ impl Config {
         }
 
         let contents = std::fs::read_to_string(file)?;
-        Ok(toml::de::from_str(&contents)?)
+        let mut config: Config = toml::de::from_str(&contents)?;
+        config.hash = Some(hash_config(&contents));
+        Ok(config)
     }
 
     fn from_first_file(file: Option<impl AsRef<Path>>) -> Result<Config, ConfigError> {
@@ -442,7 +709,9 @@ impl Config {
                 NtpSourceConfig::Nts(_) => count += 1,
                 NtpSourceConfig::Pool(config) => count += config.first.count,
                 NtpSourceConfig::NtsPool(config) => count += config.first.count,
+                NtpSourceConfig::Service(config) => count += config.first.count,
                 NtpSourceConfig::Sock(_) => count += 1,
+                NtpSourceConfig::File(_) => count += 1,
                 #[cfg(feature = "pps")]
                 NtpSourceConfig::Pps(_) => {} // PPS sources don't count
             }
@@ -450,6 +719,37 @@ impl Config {
         count
     }
 
+    /// Resolve the names of all sources once, reporting the names that do
+    /// not resolve to any address as an error
+    pub async fn check_resolvable(&self) -> io::Result<()> {
+        let mut unresolvable = vec![];
+        for source in &self.sources {
+            let address: &NormalizedAddress = match source {
+                NtpSourceConfig::Standard(config) => &config.first.address,
+                NtpSourceConfig::Nts(config) => &config.first.address,
+                NtpSourceConfig::Pool(config) => &config.first.addr,
+                NtpSourceConfig::NtsPool(config) => &config.first.addr,
+                _ => continue,
+            };
+            let resolved = match address.lookup_host().await {
+                Ok(mut addresses) => addresses.next().is_some(),
+                Err(_) => false,
+            };
+            if !resolved {
+                unresolvable.push(address.to_string());
+            }
+        }
+
+        if unresolvable.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("could not resolve sources: {}", unresolvable.join(", ")),
+            ))
+        }
+    }
+
     /// Check that the config is reasonable. This function may panic if the
     /// configuration is egregious, although it doesn't do so currently.
     pub fn check(&self) -> bool {
@@ -463,7 +763,8 @@ impl Config {
             info!("No sources configured. Daemon will not change system time.");
         }
 
-        if !self.sources.is_empty()
+        if !self.synchronization.prober
+            && !self.sources.is_empty()
             && self.count_sources()
                 < self
                     .synchronization
@@ -476,8 +777,26 @@ impl Config {
             ok = false;
         }
 
+        if self.synchronization.record_decisions
+            && self.synchronization.measurement_record_file.is_none()
+        {
+            warn!(
+                "Decisions are only recorded together with the measurements, set a measurement-record-file."
+            );
+        }
+
+        if self
+            .synchronization
+            .synchronization_base
+            .orphan_stratum
+            .is_some_and(|stratum| !(1..16).contains(&stratum))
+        {
+            warn!("The orphan stratum should be between 1 and 15.");
+            ok = false;
+        }
+
         if self.sources.iter().any(|config| match config {
-            NtpSourceConfig::Sock(_) => false,
+            NtpSourceConfig::Sock(_) | NtpSourceConfig::File(_) => false,
             #[cfg(feature = "pps")]
             NtpSourceConfig::Pps(_) => false,
             NtpSourceConfig::Standard(config) => {
@@ -492,6 +811,9 @@ impl Config {
             NtpSourceConfig::NtsPool(config) => {
                 matches!(config.first.ntp_version, ProtocolVersion::V5)
             }
+            NtpSourceConfig::Service(config) => {
+                matches!(config.first.ntp_version, ProtocolVersion::V5)
+            }
         }) {
             warn!(
                 "Forcing a source into NTPv5, which is still a draft. There is no guarantee that the server will remain compatible with this or future versions of ntpd-rs."
@@ -499,6 +821,78 @@ impl Config {
             ok = false;
         }
 
+        if self.sources.iter().any(|config| {
+            matches!(config, NtpSourceConfig::Standard(config) if config.first.transport != Transport::Udp)
+        }) {
+            warn!(
+                "Some sources are polled over TCP or TLS. Their measurements are less accurate than over UDP, so this should only be used when UDP is blocked."
+            );
+        }
+
+        for server in &self.servers {
+            if server.tls_certificate_chain_path.is_some() != server.tls_private_key_path.is_some()
+            {
+                warn!(
+                    "A server has a TLS certificate chain or private key, but not both. Both are needed to use TLS."
+                );
+                ok = false;
+            }
+
+            if server.tcp_listen.is_none() && server.tls_certificate_chain_path.is_some() {
+                warn!(
+                    "A server has a TLS certificate chain, but no `tcp-listen` address to use it on."
+                );
+                ok = false;
+            }
+        }
+
+        let source_configs = std::iter::once(self.source_defaults).chain(
+            self.sources
+                .iter()
+                .filter_map(|config| match config {
+                    NtpSourceConfig::Standard(config) => Some(&config.second),
+                    NtpSourceConfig::Nts(config) => Some(&config.second),
+                    NtpSourceConfig::Pool(config) => Some(&config.second),
+                    NtpSourceConfig::NtsPool(config) => Some(&config.second),
+                    NtpSourceConfig::Service(config) => Some(&config.second),
+                    _ => None,
+                })
+                .map(|partial| partial.clone().with_defaults(self.source_defaults)),
+        );
+        for source_config in source_configs {
+            let limits = source_config.poll_interval_limits;
+            if limits.min > limits.max {
+                warn!(
+                    "The poll interval limits of a source have a minimum of {} above their maximum of {}.",
+                    limits.min.as_log(),
+                    limits.max.as_log()
+                );
+                ok = false;
+            } else if !(limits.min..=limits.max).contains(&source_config.initial_poll_interval) {
+                warn!(
+                    "The initial poll interval {} of a source is outside its poll interval limits.",
+                    source_config.initial_poll_interval.as_log()
+                );
+                ok = false;
+            }
+        }
+
+        #[cfg(not(feature = "fleet"))]
+        if self.fleet.is_some() {
+            warn!(
+                "Fleet sources are configured, but this build does not include fleet support. They will not be fetched."
+            );
+            ok = false;
+        }
+
+        #[cfg(not(feature = "nts-server"))]
+        if !self.nts_ke.is_empty() {
+            warn!(
+                "NTS key exchange servers are configured, but this build does not include NTS server support. They will not be started."
+            );
+            ok = false;
+        }
+
         // Check that the NTS configuration is consistent with the NTP configuration
         for ke_server in self
             .nts_ke
@@ -532,6 +926,15 @@ impl Config {
             }
         }
 
+        if self
+            .scheduling
+            .realtime_priority
+            .is_some_and(|priority| !(1..=99).contains(&priority))
+        {
+            warn!("The realtime priority should be between 1 and 99.");
+            ok = false;
+        }
+
         ok
     }
 }
@@ -567,7 +970,7 @@ impl From<toml::de::Error> for ConfigError {
 
 #[cfg(test)]
 mod tests {
-    use ntp_proto::{NtpDuration, ProtocolVersion, StepThreshold};
+    use ntp_proto::{NtpDuration, ProtocolVersion, StepPolicy, StepThreshold};
 
     use super::*;
 
@@ -581,6 +984,13 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_unchecked("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key: None,
+                    interface: None,
+                    source_address: None,
+                    prefer_ip: None,
+                    force_ip: None,
+                    transport: Default::default(),
+                    nat_keepalive: None,
                 },
                 second: Default::default()
             })]
@@ -598,6 +1008,13 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_unchecked("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key: None,
+                    interface: None,
+                    source_address: None,
+                    prefer_ip: None,
+                    force_ip: None,
+                    transport: Default::default(),
+                    nat_keepalive: None,
                 },
                 second: Default::default()
             })]
@@ -613,6 +1030,13 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_unchecked("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key: None,
+                    interface: None,
+                    source_address: None,
+                    prefer_ip: None,
+                    force_ip: None,
+                    transport: Default::default(),
+                    nat_keepalive: None,
                 },
                 second: Default::default()
             })]
@@ -644,6 +1068,13 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_unchecked("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key: None,
+                    interface: None,
+                    source_address: None,
+                    prefer_ip: None,
+                    force_ip: None,
+                    transport: Default::default(),
+                    nat_keepalive: None,
                 },
                 second: Default::default()
             })]
@@ -694,6 +1125,13 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_unchecked("example.com", 123).into(),
                     ntp_version: ProtocolVersion::V4,
+                    key: None,
+                    interface: None,
+                    source_address: None,
+                    prefer_ip: None,
+                    force_ip: None,
+                    transport: Default::default(),
+                    nat_keepalive: None,
                 },
                 second: Default::default()
             })]
@@ -715,6 +1153,13 @@ mod tests {
                 first: StandardSource {
                     address: NormalizedAddress::new_unchecked("example.com", 123).into(),
                     ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                    key: None,
+                    interface: None,
+                    source_address: None,
+                    prefer_ip: None,
+                    force_ip: None,
+                    transport: Default::default(),
+                    nat_keepalive: None,
                 },
                 second: Default::default()
             })]
@@ -833,6 +1278,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn system_config_clock_step_policy() {
+        let config: SynchronizationConfig = toml::from_str("").unwrap();
+        assert_eq!(config.clock_step_policy, StepPolicy::Always);
+
+        let config: SynchronizationConfig = toml::from_str(
+            r#"
+            clock-step-policy = "startup-only"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.clock_step_policy, StepPolicy::StartupOnly);
+
+        let config: SynchronizationConfig = toml::from_str(
+            r#"
+            clock-step-policy = "never"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.clock_step_policy, StepPolicy::Never);
+
+        let config: Result<SynchronizationConfig, _> = toml::from_str(
+            r#"
+            clock-step-policy = "sometimes"
+            "#,
+        );
+        assert!(config.is_err());
+    }
+
     #[test]
     fn duration_not_nan() {
         #[derive(Debug, Deserialize)]
@@ -887,6 +1361,10 @@ mod tests {
             r#"
             interface = "enp0s31f6"
             timestamp-mode = "software"
+            follow-clocks = ["/dev/ptp0"]
+
+            [pps-output]
+            path = "/sys/class/gpio/gpio18/value"
             "#,
         );
 
@@ -896,6 +1374,151 @@ mod tests {
         assert_eq!(config.interface, Some(expected));
 
         assert_eq!(config.timestamp_mode, TimestampMode::Software);
+        assert_eq!(config.follow_clocks, vec![PathBuf::from("/dev/ptp0")]);
+        assert_eq!(config.follow_clocks_timescale, Timescale::Utc);
+        assert_eq!(
+            config.pps_output,
+            Some(PpsOutputConfig {
+                path: PathBuf::from("/sys/class/gpio/gpio18/value"),
+                pulse_width: 0.1,
+            })
+        );
+    }
+
+    #[test]
+    fn pps_output_pulse_width() {
+        let config: PpsOutputConfig = toml::from_str(
+            r#"
+            path = "/sys/class/gpio/gpio18/value"
+            pulse-width = "200ms"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.pulse_width, 0.2);
+
+        for pulse_width in ["0", "-0.1", "1", "2.5", "nan", "inf"] {
+            let config: Result<PpsOutputConfig, _> = toml::from_str(&format!(
+                r#"
+                path = "/sys/class/gpio/gpio18/value"
+                pulse-width = {pulse_width}
+                "#
+            ));
+            let error = config.unwrap_err();
+            assert!(
+                error.to_string().contains("pulse-width must be"),
+                "{pulse_width}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn follow_clocks_timescale() {
+        let config: ClockConfig = toml::from_str(
+            r#"
+            follow-clocks = ["/dev/ptp0"]
+            follow-clocks-timescale = "tai"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.follow_clocks_timescale, Timescale::Tai);
+
+        let config: Result<ClockConfig, _> = toml::from_str(
+            r#"
+            follow-clocks-timescale = "gps"
+            "#,
+        );
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn scheduling_config() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.scheduling.realtime_priority, None);
+        assert!(config.scheduling.cpu_affinity.is_empty());
+
+        let config: Config = toml::from_str(
+            r#"
+            [scheduling]
+            realtime-priority = 10
+            cpu-affinity = [2, 3]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.scheduling.realtime_priority, Some(10));
+        assert_eq!(config.scheduling.cpu_affinity, vec![2, 3]);
+    }
+
+    #[test]
+    fn sandbox_config() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.sandbox.level, SandboxLevel::Off);
+        assert_eq!(config.sandbox.user, "ntpd-rs");
+        assert_eq!(config.sandbox.group, None);
+
+        let config: Config = toml::from_str(
+            r#"
+            [sandbox]
+            level = "seccomp"
+            user = "ntp"
+            group = "time"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sandbox.level, SandboxLevel::Seccomp);
+        assert_eq!(config.sandbox.user, "ntp");
+        assert_eq!(config.sandbox.group.as_deref(), Some("time"));
+
+        let config: Result<Config, _> = toml::from_str(
+            r#"
+            [sandbox]
+            level = "chroot"
+            "#,
+        );
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn stale_measurement_horizon() {
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            [algorithm]
+            stale-measurement-horizon = "500ms"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.algorithm.stale_measurement_horizon, 0.5);
+
+        for horizon in ["-1", "nan", "inf"] {
+            let config: Result<DaemonSynchronizationConfig, _> = toml::from_str(&format!(
+                r#"
+                [algorithm]
+                stale-measurement-horizon = {horizon}
+                "#
+            ));
+            assert!(config.is_err(), "{horizon}");
+        }
+    }
+
+    #[test]
+    fn path_change_ratio() {
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            [algorithm]
+            path-change-ratio = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.algorithm.path_change_ratio, 2.0);
+
+        for ratio in ["-1.5", "0.5", "nan", "inf"] {
+            let config: Result<DaemonSynchronizationConfig, _> = toml::from_str(&format!(
+                r#"
+                [algorithm]
+                path-change-ratio = {ratio}
+                "#
+            ));
+            assert!(config.is_err(), "{ratio}");
+        }
     }
 
     #[test]
@@ -920,5 +1543,140 @@ mod tests {
         let config = config.unwrap();
         assert_eq!(config.synchronization_base.minimum_agreeing_sources, 2);
         assert_eq!(config.algorithm.initial_wander, 1e-7);
+        assert_eq!(config.drift_file, None);
+        assert_eq!(config.kod_history_file, None);
+        assert_eq!(config.measurement_record_file, None);
+        assert_eq!(config.leap_seconds_file, None);
+
+        let config: DaemonSynchronizationConfig = toml::from_str(
+            r#"
+            minimum-agreeing-sources = 2
+            drift-file = "/var/lib/ntpd-rs/drift"
+            kod-history-file = "/var/lib/ntpd-rs/kod-history"
+            measurement-record-file = "/tmp/measurements.jsonl"
+            leap-seconds-file = "/usr/share/zoneinfo/leap-seconds.list"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.synchronization_base.minimum_agreeing_sources, 2);
+        assert_eq!(
+            config.drift_file,
+            Some(PathBuf::from("/var/lib/ntpd-rs/drift"))
+        );
+        assert_eq!(
+            config.kod_history_file,
+            Some(PathBuf::from("/var/lib/ntpd-rs/kod-history"))
+        );
+        assert_eq!(
+            config.measurement_record_file,
+            Some(PathBuf::from("/tmp/measurements.jsonl"))
+        );
+        assert_eq!(
+            config.leap_seconds_file,
+            Some(PathBuf::from("/usr/share/zoneinfo/leap-seconds.list"))
+        );
+    }
+
+    #[test]
+    fn blackout_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [[synchronization.blackout]]
+            schedule = "25 14 * * 1-5"
+            duration = 600
+
+            [[synchronization.blackout]]
+            schedule = "0 3 * * 0"
+            duration = "1h"
+            max-slew = 0.0005
+            "#,
+        )
+        .unwrap();
+        let blackouts = &config.synchronization.blackouts;
+        assert_eq!(blackouts.len(), 2);
+        assert_eq!(
+            blackouts[0].schedule,
+            "25 14 * * 1-5".parse::<Schedule>().unwrap()
+        );
+        assert_eq!(blackouts[0].duration, 600);
+        assert_eq!(blackouts[1].duration, 3600);
+        assert_eq!(blackouts[0].max_slew, None);
+        assert_eq!(blackouts[1].max_slew, Some(0.0005));
+
+        for invalid in [
+            "schedule = \"25 14 * *\"\nduration = 600",
+            "schedule = \"25 14 * * *\"\nduration = 0",
+            "schedule = \"25 14 * * *\"\nduration = \"1.5s\"",
+            "schedule = \"25 14 * * *\"\nduration = 600\nmax-slew = -1.0",
+        ] {
+            let config: Result<Config, _> =
+                toml::from_str(&format!("[[synchronization.blackout]]\n{invalid}"));
+            assert!(config.is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_nat64_prefix() {
+        let config: Config =
+            toml::from_str("[synchronization]\nnat64-prefix = \"64:ff9b::/96\"").unwrap();
+        assert_eq!(
+            config.synchronization.nat64_prefix,
+            Some(super::super::nat64::WELL_KNOWN_PREFIX)
+        );
+
+        let config: Result<Config, _> =
+            toml::from_str("[synchronization]\nnat64-prefix = \"64:ff9b::/80\"");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_hash_config() {
+        assert_eq!(hash_config(""), "cbf29ce484222325");
+        assert_eq!(hash_config("a"), "af63dc4c8601ec8c");
+        assert_ne!(
+            hash_config("[observability]\nlog-level = \"info\"\n"),
+            hash_config("[observability]\nlog-level = \"warn\"\n")
+        );
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.hash, None);
+    }
+
+    #[tokio::test]
+    async fn test_check_resolvable() {
+        let source = |name: &str, addresses: Vec<SocketAddr>| {
+            NtpSourceConfig::Standard(FlattenedPair {
+                first: StandardSource {
+                    address: NormalizedAddress::with_hardcoded_dns(name, 123, addresses).into(),
+                    ntp_version: ProtocolVersion::V4,
+                    key: None,
+                    interface: None,
+                    source_address: None,
+                    prefer_ip: None,
+                    force_ip: None,
+                    transport: Default::default(),
+                    nat_keepalive: None,
+                },
+                second: Default::default(),
+            })
+        };
+
+        let mut config: Config =
+            toml::from_str("[synchronization]\nunresolvable-sources = \"fail\"").unwrap();
+        assert_eq!(
+            config.synchronization.unresolvable_sources,
+            UnresolvableSources::Fail
+        );
+
+        config.sources = vec![source(
+            "example.com",
+            vec!["192.0.2.1:123".parse().unwrap()],
+        )];
+        assert!(config.check_resolvable().await.is_ok());
+
+        config.sources.push(source("unknown.example", vec![]));
+        let err = config.check_resolvable().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains("unknown.example:123"));
     }
 }
@@ -2,20 +2,26 @@
 use std::sync::Mutex;
 use std::{
     fmt,
+    io::{BufRead, BufReader},
     net::{IpAddr, SocketAddr},
     ops::Deref,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
-use ntp_proto::{PollInterval, PollIntervalLimits, SourceConfig};
+use ntp_proto::{
+    PollInterval, PollIntervalLimits, SourceConfig, Transport, deserialize_seconds,
+    deserialize_whole_seconds,
+};
 use ntp_proto::{ProtocolVersion, tls_utils::Certificate};
 use serde::{
     Deserialize, Deserializer,
     de::{self, Visitor},
 };
+use timestamped_socket::interface::InterfaceName;
 
-use super::super::keyexchange::certificates_from_file;
+use crate::daemon::util::canonical_addr;
 
 fn deserialize_ntp_version<'de, D>(deserializer: D) -> Result<ProtocolVersion, D::Error>
 where
@@ -75,6 +81,56 @@ pub struct StandardSource {
         deserialize_with = "deserialize_ntp_version"
     )]
     pub ntp_version: ProtocolVersion,
+    /// Id of the symmetric key used to authenticate packets exchanged with this source
+    #[serde(default)]
+    pub key: Option<u32>,
+    /// Network interface to bind the socket used for this source to
+    #[serde(default)]
+    pub interface: Option<InterfaceName>,
+    /// Local address to send the packets for this source from
+    #[serde(default)]
+    pub source_address: Option<IpAddr>,
+    /// Address family to try first when the name resolves to both
+    #[serde(default)]
+    pub prefer_ip: Option<AddressFamily>,
+    /// Only use addresses of this family
+    #[serde(default)]
+    pub force_ip: Option<AddressFamily>,
+    /// Exchange packets over a TCP or TLS connection instead of UDP
+    #[serde(default)]
+    pub transport: Transport,
+    /// Send a keepalive datagram this often between polls
+    #[serde(default, deserialize_with = "deserialize_nat_keepalive")]
+    pub nat_keepalive: Option<Duration>,
+}
+
+fn deserialize_nat_keepalive<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match deserialize_whole_seconds(deserializer)? {
+        0 => Err(de::Error::custom(
+            "the nat keepalive interval must be at least 1 second",
+        )),
+        seconds => Ok(Some(Duration::from_secs(seconds))),
+    }
+}
+
+/// IP version of the addresses a source name resolves to
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddressFamily {
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub fn of(addr: &SocketAddr) -> AddressFamily {
+        match addr {
+            SocketAddr::V4(_) => AddressFamily::V4,
+            SocketAddr::V6(_) => AddressFamily::V6,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq, Clone)]
@@ -92,6 +148,23 @@ pub struct NtsSourceConfig {
         deserialize_with = "deserialize_ntp_version"
     )]
     pub ntp_version: ProtocolVersion,
+    #[serde(default)]
+    pub interface: Option<InterfaceName>,
+    #[serde(default)]
+    pub source_address: Option<IpAddr>,
+}
+
+fn certificates_from_file(path: &Path) -> std::io::Result<Vec<Certificate>> {
+    let file = std::fs::File::open(path)?;
+    let reader = BufReader::new(file);
+
+    certificates_from_bufread(reader)
+}
+
+pub(crate) fn certificates_from_bufread(
+    mut reader: impl BufRead,
+) -> std::io::Result<Vec<Certificate>> {
+    ntp_proto::tls_utils::pemfile::certs(&mut reader).collect()
 }
 
 fn deserialize_certificate_authorities<'de, D>(
@@ -129,6 +202,16 @@ pub struct PoolSourceConfig {
         deserialize_with = "deserialize_ntp_version"
     )]
     pub ntp_version: ProtocolVersion,
+    #[serde(default)]
+    pub interface: Option<InterfaceName>,
+    #[serde(default)]
+    pub source_address: Option<IpAddr>,
+    #[serde(default)]
+    pub prefer_ip: Option<AddressFamily>,
+    #[serde(default)]
+    pub force_ip: Option<AddressFamily>,
+    #[serde(default, deserialize_with = "deserialize_nat_keepalive")]
+    pub nat_keepalive: Option<Duration>,
 }
 
 fn max_sources_default() -> usize {
@@ -153,6 +236,42 @@ pub struct NtsPoolSourceConfig {
         deserialize_with = "deserialize_ntp_version"
     )]
     pub ntp_version: ProtocolVersion,
+    #[serde(default)]
+    pub interface: Option<InterfaceName>,
+    #[serde(default)]
+    pub source_address: Option<IpAddr>,
+}
+
+/// Servers found through DNS SRV records or multicast DNS
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ServiceSourceConfig {
+    /// Domain with the `_ntp._udp` SRV records, by default the search domains
+    /// of the system
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Also use the servers advertised with multicast DNS
+    #[serde(default)]
+    pub mdns: bool,
+    #[serde(default = "max_sources_default")]
+    pub count: usize,
+    #[serde(default)]
+    pub ignore: Vec<IpAddr>,
+    #[serde(
+        default = "default_ntp_version",
+        deserialize_with = "deserialize_ntp_version"
+    )]
+    pub ntp_version: ProtocolVersion,
+    #[serde(default)]
+    pub interface: Option<InterfaceName>,
+    #[serde(default)]
+    pub source_address: Option<IpAddr>,
+    #[serde(default)]
+    pub prefer_ip: Option<AddressFamily>,
+    #[serde(default)]
+    pub force_ip: Option<AddressFamily>,
+    #[serde(default, deserialize_with = "deserialize_nat_keepalive")]
+    pub nat_keepalive: Option<Duration>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -243,6 +362,29 @@ impl<'de> Deserialize<'de> for SockSourceConfig {
     }
 }
 
+/// A reference clock that writes its measurements as lines of text to a file
+/// or FIFO, see the documentation of the `file` source for their format
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FileSourceConfig {
+    pub path: PathBuf,
+    /// Standard deviation of the offsets measured by the reference (s)
+    #[serde(deserialize_with = "deserialize_precision")]
+    pub precision: f64,
+}
+
+fn deserialize_precision<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let precision = deserialize_seconds(deserializer)?;
+    if precision > 0.0 {
+        Ok(precision)
+    } else {
+        Err(de::Error::invalid_value(
+            de::Unexpected::Float(precision),
+            &"a positive precision",
+        ))
+    }
+}
+
 #[derive(Deserialize, Debug, PartialEq, Eq, Clone, Default)]
 #[serde(deny_unknown_fields)]
 pub struct PartialPollIntervalLimits {
@@ -250,7 +392,7 @@ pub struct PartialPollIntervalLimits {
     pub max: Option<PollInterval>,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[derive(Deserialize, Debug, PartialEq, Clone, Default)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct PartialSourceConfig {
     /// Minima and maxima for the poll interval of clients
@@ -259,6 +401,32 @@ pub struct PartialSourceConfig {
 
     /// Initial poll interval of the system
     pub initial_poll_interval: Option<PollInterval>,
+
+    /// Send a short burst of polls when the source starts
+    pub iburst: Option<bool>,
+
+    /// Consider this source correct during selection
+    pub trust: Option<bool>,
+
+    /// Give this source more weight when combining the selected sources
+    pub prefer: Option<bool>,
+
+    /// Monitor this source, but never use it to steer the clock
+    pub noselect: Option<bool>,
+
+    /// Fixed correction in seconds added to the measured offsets
+    #[serde(default, deserialize_with = "deserialize_optional_seconds")]
+    pub offset_correction: Option<f64>,
+
+    /// Fixed part of the measured delays in seconds
+    #[serde(default, deserialize_with = "deserialize_optional_seconds")]
+    pub delay_correction: Option<f64>,
+}
+
+fn deserialize_optional_seconds<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    deserialize_seconds(deserializer).map(Some)
 }
 
 impl PartialSourceConfig {
@@ -277,6 +445,12 @@ impl PartialSourceConfig {
             initial_poll_interval: self
                 .initial_poll_interval
                 .unwrap_or(defaults.initial_poll_interval),
+            iburst: self.iburst.unwrap_or(defaults.iburst),
+            trust: self.trust.unwrap_or(defaults.trust),
+            prefer: self.prefer.unwrap_or(defaults.prefer),
+            noselect: self.noselect.unwrap_or(defaults.noselect),
+            offset_correction: self.offset_correction.unwrap_or(defaults.offset_correction),
+            delay_correction: self.delay_correction.unwrap_or(defaults.delay_correction),
         }
     }
 }
@@ -410,8 +584,12 @@ pub enum NtpSourceConfig {
     Pool(FlattenedPair<PoolSourceConfig, PartialSourceConfig>),
     #[serde(rename = "nts-pool")]
     NtsPool(FlattenedPair<NtsPoolSourceConfig, PartialSourceConfig>),
+    #[serde(rename = "srv")]
+    Service(FlattenedPair<ServiceSourceConfig, PartialSourceConfig>),
     #[serde(rename = "sock")]
     Sock(SockSourceConfig),
+    #[serde(rename = "file")]
+    File(FileSourceConfig),
     #[cfg(feature = "pps")]
     #[serde(rename = "pps")]
     Pps(PpsSourceConfig),
@@ -573,7 +751,6 @@ impl NormalizedAddress {
         }
     }
 
-    #[cfg(test)]
     pub(crate) fn new_unchecked(server_name: &str, port: u16) -> Self {
         Self {
             server_name: server_name.to_string(),
@@ -599,7 +776,11 @@ impl NormalizedAddress {
 
     #[cfg(not(test))]
     pub async fn lookup_host(&self) -> std::io::Result<impl Iterator<Item = SocketAddr> + '_> {
-        tokio::net::lookup_host((self.server_name.as_str(), self.port)).await
+        Ok(
+            tokio::net::lookup_host((self.server_name.as_str(), self.port))
+                .await?
+                .map(canonical_addr),
+        )
     }
 
     #[cfg(test)]
@@ -614,7 +795,7 @@ impl NormalizedAddress {
 
         let addresses = addresses.to_vec();
 
-        Ok(addresses.into_iter())
+        Ok(addresses.into_iter().map(canonical_addr))
     }
 }
 
@@ -635,6 +816,13 @@ impl TryFrom<&str> for StandardSource {
         Ok(Self {
             address: NormalizedAddress::from_string_ntp(value.to_string())?.into(),
             ntp_version: default_ntp_version(),
+            key: None,
+            interface: None,
+            source_address: None,
+            prefer_ip: None,
+            force_ip: None,
+            transport: Transport::Udp,
+            nat_keepalive: None,
         })
     }
 }
@@ -654,6 +842,8 @@ impl<'a> TryFrom<&'a str> for NtpSourceConfig {
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
     use super::*;
 
     #[derive(Deserialize, Debug)]
@@ -661,13 +851,31 @@ mod tests {
         source: NtpSourceConfig,
     }
 
+    #[test]
+    fn nos_nl_pem() {
+        let input = include_bytes!("../../../testdata/certificates/nos-nl.pem");
+        let certificates = certificates_from_bufread(input.as_slice()).unwrap();
+
+        assert_eq!(certificates.len(), 1);
+    }
+
+    #[test]
+    fn nos_nl_chain_pem() {
+        let input = include_bytes!("../../../testdata/certificates/nos-nl-chain.pem");
+        let certificates = certificates_from_bufread(input.as_slice()).unwrap();
+
+        assert_eq!(certificates.len(), 3);
+    }
+
     fn source_addr(config: &NtpSourceConfig) -> String {
         match config {
             NtpSourceConfig::Standard(c) => c.first.address.to_string(),
             NtpSourceConfig::Nts(c) => c.first.address.to_string(),
             NtpSourceConfig::Pool(c) => c.first.addr.to_string(),
             NtpSourceConfig::NtsPool(c) => c.first.addr.to_string(),
+            NtpSourceConfig::Service(c) => c.first.domain.clone().unwrap_or_default(),
             NtpSourceConfig::Sock(_c) => "".to_string(),
+            NtpSourceConfig::File(_c) => "".to_string(),
             #[cfg(feature = "pps")]
             NtpSourceConfig::Pps(_c) => "".to_string(),
         }
@@ -760,6 +968,37 @@ mod tests {
             assert!(matches!(test.source, NtpSourceConfig::NtsPool(_)));
             assert_eq!(source_addr(&test.source), "example.com:4460");
         }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "srv"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Service(config) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(config.first.domain, None);
+        assert!(!config.first.mdns);
+        assert_eq!(config.first.count, 4);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            mode = "srv"
+            domain = "example.com"
+            mdns = true
+            count = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(source_addr(&test.source), "example.com");
+        let NtpSourceConfig::Service(config) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert!(config.first.mdns);
+        assert_eq!(config.first.count, 2);
     }
 
     #[test]
@@ -823,6 +1062,184 @@ mod tests {
         assert_eq!(source.first.ntp_version, ProtocolVersion::V4);
     }
 
+    #[test]
+    fn test_symmetric_key() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            key = 5
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.key, Some(5));
+    }
+
+    #[test]
+    fn test_socket_binding() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            interface = "eth1"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(
+            source.first.interface,
+            Some(InterfaceName::from_str("eth1").unwrap())
+        );
+        assert_eq!(source.first.source_address, None);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "pool.example.com"
+            mode = "pool"
+            source-address = "192.0.2.7"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Pool(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.interface, None);
+        assert_eq!(
+            source.first.source_address,
+            Some("192.0.2.7".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_address_family() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            prefer-ip = "v6"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.prefer_ip, Some(AddressFamily::V6));
+        assert_eq!(source.first.force_ip, None);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "pool.example.com"
+            mode = "pool"
+            force-ip = "v4"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Pool(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.force_ip, Some(AddressFamily::V4));
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            force-ip = "v5"
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_source_transport() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.transport, Transport::Udp);
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            transport = "tls"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.transport, Transport::Tls);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            transport = "quic"
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
+    #[test]
+    fn test_source_nat_keepalive() {
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            nat-keepalive = 25
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.nat_keepalive, Some(Duration::from_secs(25)));
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [source]
+            address = "pool.example.com"
+            mode = "pool"
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Pool(source) = test.source else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(source.first.nat_keepalive, None);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+            [source]
+            address = "example.com"
+            mode = "server"
+            nat-keepalive = 0
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
     #[test]
     fn test_deserialize_source_pem_certificate() {
         let contents = include_bytes!("../../../testdata/certificates/nos-nl.pem");
@@ -862,9 +1279,29 @@ mod tests {
                 mode = "server"
                 address = "example.com"
                 initial-poll-interval = 7
+                iburst = true
+                prefer = true
+                noselect = false
+                offset-correction = -0.0015
+                delay-correction = "20ms"
             "#,
         );
-        assert!(test.is_ok());
+        let Ok(TestConfig {
+            source: NtpSourceConfig::Standard(test),
+        }) = test
+        else {
+            panic!("Unexpected source type");
+        };
+        let config = test.second.with_defaults(SourceConfig::default());
+        assert_eq!(config.initial_poll_interval.as_log(), 7);
+        assert!(config.iburst);
+        assert!(!SourceConfig::default().iburst);
+        assert!(config.prefer);
+        assert!(!config.trust);
+        assert!(!config.noselect);
+        assert_eq!(config.offset_correction, -0.0015);
+        assert_eq!(config.delay_correction, 0.02);
+        assert_eq!(SourceConfig::default().delay_correction, 0.0);
 
         let test2: Result<TestConfig, _> = toml::from_str(
             r#"
@@ -877,6 +1314,52 @@ mod tests {
         assert!(test2.is_err());
     }
 
+    #[test]
+    fn test_source_poll_interval_limits() {
+        let defaults = SourceConfig {
+            poll_interval_limits: PollIntervalLimits {
+                min: PollInterval::from_byte(6),
+                max: PollInterval::from_byte(10),
+            },
+            ..SourceConfig::default()
+        };
+
+        // a nearby server that may be polled every 16 seconds
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "server"
+                address = "192.168.1.10"
+                poll-interval-limits = { min = 4, max = 4 }
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Standard(local) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let config = local.second.with_defaults(defaults);
+        assert_eq!(config.poll_interval_limits.min.as_log(), 4);
+        assert_eq!(config.poll_interval_limits.max.as_log(), 4);
+
+        // a public pool keeps the defaults for the bound that is not given
+        let test: TestConfig = toml::from_str(
+            r#"
+                [source]
+                mode = "pool"
+                address = "pool.ntp.org"
+                count = 4
+                poll-interval-limits = { min = 10 }
+            "#,
+        )
+        .unwrap();
+        let NtpSourceConfig::Pool(pool) = test.source else {
+            panic!("Unexpected source type");
+        };
+        let config = pool.second.with_defaults(defaults);
+        assert_eq!(config.poll_interval_limits.min.as_log(), 10);
+        assert_eq!(config.poll_interval_limits.max.as_log(), 10);
+    }
+
     #[test]
     fn test_sock_config_parsing() {
         let TestConfig {
@@ -992,6 +1475,56 @@ mod tests {
         assert!(test.is_err());
     }
 
+    #[test]
+    fn test_file_config_parsing() {
+        let TestConfig {
+            source: NtpSourceConfig::File(test),
+        } = toml::from_str(
+            r#"
+                [source]
+                mode = "file"
+                path = "/run/refclock"
+                precision = 1e-7
+            "#,
+        )
+        .unwrap()
+        else {
+            panic!("Invalid source type");
+        };
+        assert_eq!(test.path, PathBuf::from("/run/refclock"));
+        assert_eq!(test.precision, 1e-7);
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "file"
+                path = "/run/refclock"
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "file"
+                path = "/run/refclock"
+                precision = -1e-7
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test: Result<TestConfig, _> = toml::from_str(
+            r#"
+                [source]
+                mode = "file"
+                path = "/run/refclock"
+                precision = 1e-7
+                unknown_field = 5
+            "#,
+        );
+        assert!(test.is_err());
+    }
+
     #[cfg(feature = "pps")]
     #[test]
     fn test_pps_config_parsing() {
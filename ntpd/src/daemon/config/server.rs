@@ -1,12 +1,20 @@
 use std::{
     net::{AddrParseError, SocketAddr},
+    os::unix::fs::PermissionsExt,
     path::PathBuf,
     str::FromStr,
     time::Duration,
 };
 
-use ntp_proto::{FilterAction, FilterList, NtpVersion};
+use ntp_proto::{
+    AeadAlgorithm, ClockAdjustmentAction, FilterAction, FilterList, NtpVersion, RateLimitAction,
+    ReferenceId, SymmetricKeySet, deserialize_seconds, deserialize_whole_seconds,
+};
 use serde::{Deserialize, Deserializer};
+use timestamped_socket::interface::InterfaceName;
+use tracing::warn;
+
+use crate::daemon::anonymize::Anonymization;
 
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
@@ -19,6 +27,9 @@ pub struct KeysetConfig {
     pub key_rotation_interval: usize,
     #[serde(default)]
     pub key_storage_path: Option<String>,
+    /// File with the symmetric keys used to authenticate NTP packets
+    #[serde(default)]
+    pub symmetric_keys_path: Option<PathBuf>,
 }
 
 impl Default for KeysetConfig {
@@ -27,10 +38,124 @@ impl Default for KeysetConfig {
             stale_key_count: default_stale_key_count(),
             key_rotation_interval: default_key_rotation_interval(),
             key_storage_path: None,
+            symmetric_keys_path: None,
+        }
+    }
+}
+
+impl KeysetConfig {
+    /// Read the configured symmetric keys file, if any, refusing keys the
+    /// crypto policy does not allow
+    pub fn symmetric_keys(
+        &self,
+        crypto_policy: &CryptoPolicyConfig,
+    ) -> std::io::Result<SymmetricKeySet> {
+        let Some(path) = &self.symmetric_keys_path else {
+            return Ok(SymmetricKeySet::default());
+        };
+
+        let meta = std::fs::metadata(path)?;
+        if meta.permissions().mode() as libc::mode_t & libc::S_IROTH != 0 {
+            warn!(
+                ?path,
+                "Unrestricted symmetric keys file permissions: Others can read."
+            );
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let keys = SymmetricKeySet::parse(&contents)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+        if !crypto_policy.allow_legacy_mac
+            && let Some(id) = keys
+                .keys()
+                .filter(|key| key.key_type().is_legacy())
+                .map(|key| key.id())
+                .min()
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "key {id} uses a legacy MD5 or SHA-1 digest, which the crypto policy does not allow"
+                ),
+            ));
+        }
+
+        Ok(keys)
+    }
+}
+
+/// The minimum cryptography the daemon accepts
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CryptoPolicyConfig {
+    /// Oldest TLS version used for NTS key exchange
+    #[serde(default)]
+    pub min_tls_version: TlsVersion,
+    /// AEAD algorithms offered and accepted in NTS key exchange, in order of
+    /// preference
+    #[serde(
+        default = "default_aead_algorithms",
+        deserialize_with = "deserialize_aead_algorithms"
+    )]
+    pub aead_algorithms: Vec<AeadAlgorithm>,
+    /// Whether symmetric keys with an MD5 or SHA-1 digest may be used
+    #[serde(default = "default_allow_legacy_mac")]
+    pub allow_legacy_mac: bool,
+}
+
+impl Default for CryptoPolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_tls_version: TlsVersion::default(),
+            aead_algorithms: default_aead_algorithms(),
+            allow_legacy_mac: default_allow_legacy_mac(),
         }
     }
 }
 
+/// TLS versions that can be required for NTS key exchange. NTS itself
+/// requires TLS 1.3 (RFC 8915), so that is the only one there is.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Deserialize)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+fn default_aead_algorithms() -> Vec<AeadAlgorithm> {
+    vec![
+        AeadAlgorithm::AeadAesSivCmac512,
+        AeadAlgorithm::AeadAesSivCmac256,
+    ]
+}
+
+fn default_allow_legacy_mac() -> bool {
+    true
+}
+
+fn deserialize_aead_algorithms<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<AeadAlgorithm>, D::Error> {
+    const ALGORITHMS: &[&str] = &["aes-siv-cmac-256", "aes-siv-cmac-512"];
+
+    let names = Vec::<String>::deserialize(deserializer)?;
+    if names.is_empty() {
+        return Err(serde::de::Error::custom(
+            "at least one AEAD algorithm must be allowed",
+        ));
+    }
+
+    names
+        .iter()
+        .map(|name| match name.as_str() {
+            "aes-siv-cmac-256" => Ok(AeadAlgorithm::AeadAesSivCmac256),
+            "aes-siv-cmac-512" => Ok(AeadAlgorithm::AeadAesSivCmac512),
+            _ => Err(serde::de::Error::unknown_variant(name, ALGORITHMS)),
+        })
+        .collect()
+}
+
 fn default_key_rotation_interval() -> usize {
     // 1 day in seconds
     86400
@@ -45,6 +170,12 @@ fn default_stale_key_count() -> usize {
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct ServerConfig {
     pub listen: SocketAddr,
+    /// Identifies this listener in metrics, logs and observability output
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Only answer requests arriving on this network interface
+    #[serde(default)]
+    pub interface: Option<InterfaceName>,
     #[serde(default = "default_denylist")]
     pub denylist: FilterList,
     #[serde(default = "default_allowlist")]
@@ -57,6 +188,10 @@ pub struct ServerConfig {
         deserialize_with = "deserialize_rate_limiting_cutoff"
     )]
     pub rate_limiting_cutoff: Duration,
+    #[serde(default = "default_rate_limiting_burst")]
+    pub rate_limiting_burst: u32,
+    #[serde(default)]
+    pub rate_limiting_action: RateLimitAction,
     #[serde(default, deserialize_with = "deserialize_require_nts")]
     pub require_nts: Option<FilterAction>,
     #[serde(
@@ -64,6 +199,46 @@ pub struct ServerConfig {
         deserialize_with = "deserialize_accepted_ntp_versions"
     )]
     pub accept_ntp_versions: Vec<NtpVersion>,
+    /// Window (in seconds) over which a pending leap second is smeared
+    #[serde(default, deserialize_with = "deserialize_leap_smear_window")]
+    pub leap_smear_window: Option<Duration>,
+    /// How to answer while the clock is stepped or slewed by a large offset
+    #[serde(default)]
+    pub clock_adjustment_action: ClockAdjustmentAction,
+    /// Minimum stratum to advertise while in degraded holdover
+    #[serde(default, deserialize_with = "deserialize_degraded_stratum")]
+    pub degraded_stratum: Option<u8>,
+    /// Dispersion (in seconds) added to the root dispersion while in degraded holdover
+    #[serde(default, deserialize_with = "deserialize_degraded_dispersion")]
+    pub degraded_dispersion: Option<Duration>,
+    /// Reference id to advertise as a synchronized stratum 1 server, an IP
+    /// address or up to four ASCII characters
+    #[serde(default, deserialize_with = "deserialize_reference_id")]
+    pub reference_id: Option<ReferenceId>,
+    /// Keep hourly statistics of the clients of this server
+    #[serde(default)]
+    pub client_census: bool,
+    /// How the census records the addresses of clients
+    #[serde(default)]
+    pub census_anonymization: Anonymization,
+    /// How the addresses of clients appear in the logs
+    #[serde(default)]
+    pub log_anonymization: Anonymization,
+    /// Also answer requests sent over TCP connections to this address
+    #[serde(default)]
+    pub tcp_listen: Option<SocketAddr>,
+    /// Use TLS on the connections, with this certificate chain
+    #[serde(default)]
+    pub tls_certificate_chain_path: Option<PathBuf>,
+    #[serde(default)]
+    pub tls_private_key_path: Option<PathBuf>,
+    /// Advertise this server on the local network with multicast DNS
+    #[serde(default)]
+    pub mdns_advertise: bool,
+}
+
+fn default_rate_limiting_burst() -> u32 {
+    1
 }
 
 fn default_accepted_ntp_versions() -> Vec<NtpVersion> {
@@ -147,18 +322,81 @@ fn deserialize_rate_limiting_cutoff<'de, D: Deserializer<'de>>(
     Ok(Duration::from_millis(u64::deserialize(deserializer)?))
 }
 
+fn deserialize_leap_smear_window<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    match deserialize_whole_seconds(deserializer)? {
+        window @ 1..=86400 => Ok(Some(Duration::from_secs(window))),
+        window => Err(serde::de::Error::custom(format!(
+            "{window} is not a valid leap smear window, it must be between 1 and 86400 seconds"
+        ))),
+    }
+}
+
+fn deserialize_degraded_stratum<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u8>, D::Error> {
+    match u8::deserialize(deserializer)? {
+        stratum @ 1..=16 => Ok(Some(stratum)),
+        stratum => Err(serde::de::Error::custom(format!(
+            "{stratum} is not a valid stratum, it must be between 1 and 16"
+        ))),
+    }
+}
+
+fn deserialize_degraded_dispersion<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    let seconds = deserialize_seconds(deserializer)?;
+    Duration::try_from_secs_f64(seconds).map(Some).map_err(|_| {
+        serde::de::Error::custom(format!(
+            "{seconds} is not a valid dispersion, it must be a positive number of seconds"
+        ))
+    })
+}
+
+fn deserialize_reference_id<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ReferenceId>, D::Error> {
+    let reference_id = String::deserialize(deserializer)?;
+    match reference_id.parse() {
+        Ok(ip) => Ok(Some(ReferenceId::from_ip(ip))),
+        Err(_) => ReferenceId::from_ascii(&reference_id).map(Some).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "{reference_id:?} is not a valid reference id, it must be an IP address or at most four ASCII characters"
+            ))
+        }),
+    }
+}
+
 impl TryFrom<&str> for ServerConfig {
     type Error = AddrParseError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         Ok(ServerConfig {
             listen: SocketAddr::from_str(value)?,
+            label: None,
+            interface: None,
             denylist: default_denylist(),
             allowlist: default_allowlist(),
             rate_limiting_cache_size: Default::default(),
             rate_limiting_cutoff: Default::default(),
+            rate_limiting_burst: default_rate_limiting_burst(),
+            rate_limiting_action: Default::default(),
             require_nts: None,
             accept_ntp_versions: default_accepted_ntp_versions(),
+            leap_smear_window: None,
+            clock_adjustment_action: Default::default(),
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
+            client_census: false,
+            census_anonymization: Anonymization::None,
+            log_anonymization: Anonymization::None,
+            tcp_listen: None,
+            tls_certificate_chain_path: None,
+            tls_private_key_path: None,
+            mdns_advertise: false,
         })
     }
 }
@@ -168,12 +406,28 @@ impl From<SocketAddr> for ServerConfig {
     fn from(listen: SocketAddr) -> Self {
         ServerConfig {
             listen,
+            label: None,
+            interface: None,
             denylist: default_denylist(),
             allowlist: default_allowlist(),
             rate_limiting_cache_size: Default::default(),
             rate_limiting_cutoff: Default::default(),
+            rate_limiting_burst: default_rate_limiting_burst(),
+            rate_limiting_action: Default::default(),
             require_nts: None,
             accept_ntp_versions: default_accepted_ntp_versions(),
+            leap_smear_window: None,
+            clock_adjustment_action: Default::default(),
+            degraded_stratum: None,
+            degraded_dispersion: None,
+            reference_id: None,
+            client_census: false,
+            census_anonymization: Anonymization::None,
+            log_anonymization: Anonymization::None,
+            tcp_listen: None,
+            tls_certificate_chain_path: None,
+            tls_private_key_path: None,
+            mdns_advertise: false,
         }
     }
 }
@@ -185,8 +439,15 @@ impl From<ServerConfig> for ntp_proto::ServerConfig {
             allowlist: value.allowlist,
             rate_limiting_cache_size: value.rate_limiting_cache_size,
             rate_limiting_cutoff: value.rate_limiting_cutoff,
+            rate_limiting_burst: value.rate_limiting_burst,
+            rate_limiting_action: value.rate_limiting_action,
             require_nts: value.require_nts,
             accepted_versions: value.accept_ntp_versions,
+            leap_smear_window: value.leap_smear_window,
+            clock_adjustment_action: value.clock_adjustment_action,
+            degraded_stratum: value.degraded_stratum,
+            degraded_dispersion: value.degraded_dispersion,
+            reference_id: value.reference_id,
         }
     }
 }
@@ -203,6 +464,10 @@ pub struct NtsKeConfig {
     pub ntp_port: Option<u16>,
     pub ntp_server: Option<String>,
     pub accept_ntp_versions: Vec<NtpVersion>,
+    /// How the addresses of clients appear in the logs
+    pub log_anonymization: Anonymization,
+    /// Advertise this server on the local network with multicast DNS
+    pub mdns_advertise: bool,
 }
 
 impl<'de> Deserialize<'de> for NtsKeConfig {
@@ -231,6 +496,10 @@ impl<'de> Deserialize<'de> for NtsKeConfig {
                 deserialize_with = "deserialize_accepted_ntp_versions_for_nts"
             )]
             accept_ntp_versions: Vec<NtpVersion>,
+            #[serde(default)]
+            log_anonymization: Anonymization,
+            #[serde(default)]
+            mdns_advertise: bool,
         }
 
         let raw = NtsKeConfigRaw::deserialize(deserializer)?;
@@ -247,6 +516,8 @@ impl<'de> Deserialize<'de> for NtsKeConfig {
             ntp_port: raw.ntp_port,
             ntp_server: raw.ntp_server,
             accept_ntp_versions: raw.accept_ntp_versions,
+            log_anonymization: raw.log_anonymization,
+            mdns_advertise: raw.mdns_advertise,
         })
     }
 }
@@ -285,6 +556,7 @@ fn default_concurrent_connections() -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test::alloc_port;
 
     #[test]
     fn test_deserialize_server() {
@@ -307,6 +579,8 @@ mod tests {
             ntp_proto::FilterAction::Ignore
         );
         assert_eq!(test.server.denylist.action, ntp_proto::FilterAction::Deny);
+        assert_eq!(test.server.rate_limiting_burst, 1);
+        assert_eq!(test.server.rate_limiting_action, RateLimitAction::Ignore);
 
         let test: TestConfig = toml::from_str(
             r#"
@@ -314,6 +588,8 @@ mod tests {
             listen = "127.0.0.1:123"
             rate-limiting-cutoff-ms = 1000
             rate-limiting-cache-size = 32
+            rate-limiting-burst = 4
+            rate-limiting-action = "kod"
             "#,
         )
         .unwrap();
@@ -323,6 +599,8 @@ mod tests {
             test.server.rate_limiting_cutoff,
             Duration::from_millis(1000)
         );
+        assert_eq!(test.server.rate_limiting_burst, 4);
+        assert_eq!(test.server.rate_limiting_action, RateLimitAction::Kod);
         assert_eq!(
             test.server.accept_ntp_versions,
             vec![NtpVersion::V3, NtpVersion::V4]
@@ -385,6 +663,155 @@ mod tests {
             "#,
         );
         assert!(test.is_err());
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            leap-smear-window = 86400
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.server.leap_smear_window,
+            Some(Duration::from_secs(86400))
+        );
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            leap-smear-window = 0
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "[::]:123"
+            interface = "eth1"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.server.interface,
+            Some(InterfaceName::from_str("eth1").unwrap())
+        );
+        assert_eq!(
+            test.server.clock_adjustment_action,
+            ClockAdjustmentAction::Answer
+        );
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            clock-adjustment-action = "raise-dispersion"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.server.clock_adjustment_action,
+            ClockAdjustmentAction::RaiseDispersion
+        );
+        assert_eq!(test.server.label, None);
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            label = "tenant-a"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.server.label.as_deref(), Some("tenant-a"));
+        assert_eq!(test.server.degraded_stratum, None);
+        assert_eq!(test.server.reference_id, None);
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            degraded-stratum = 12
+            degraded-dispersion = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.server.degraded_stratum, Some(12));
+        assert_eq!(
+            test.server.degraded_dispersion,
+            Some(Duration::from_millis(500))
+        );
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            degraded-stratum = 17
+            "#,
+        );
+        assert!(test.is_err());
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            reference-id = "GPS"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.server.reference_id, ReferenceId::from_ascii("GPS"));
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            reference-id = "192.0.2.1"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            test.server.reference_id,
+            Some(ReferenceId::from_ip("192.0.2.1".parse().unwrap()))
+        );
+
+        for invalid in ["", "GALILEO", "A B", "192.0.2"] {
+            let test = toml::from_str::<TestConfig>(&format!(
+                "[server]\nlisten = \"127.0.0.1:123\"\nreference-id = {invalid:?}\n"
+            ));
+            assert!(test.is_err(), "{invalid}");
+        }
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "127.0.0.1:123"
+            client-census = true
+            census-anonymization = "truncate"
+            log-anonymization = "hash"
+            "#,
+        )
+        .unwrap();
+        assert!(test.server.client_census);
+        assert_eq!(test.server.census_anonymization, Anonymization::Truncate);
+        assert_eq!(test.server.log_anonymization, Anonymization::Hash);
+
+        let test = toml::from_str::<TestConfig>(
+            r#"
+            [server]
+            listen = "[::]:123"
+            tcp-listen = "[::]:123"
+            tls-certificate-chain-path = "/etc/ntpd-rs/server.crt"
+            tls-private-key-path = "/etc/ntpd-rs/server.key"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.server.tcp_listen, Some("[::]:123".parse().unwrap()));
+        assert_eq!(
+            test.server.tls_certificate_chain_path,
+            Some(PathBuf::from("/etc/ntpd-rs/server.crt"))
+        );
     }
 
     #[test]
@@ -408,6 +835,73 @@ mod tests {
         assert_ne!(test.keyset, KeysetConfig::default())
     }
 
+    #[test]
+    fn test_deserialize_crypto_policy() {
+        #[derive(Deserialize, Debug)]
+        #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+        struct TestConfig {
+            crypto_policy: CryptoPolicyConfig,
+        }
+
+        let test: TestConfig = toml::from_str(
+            r#"
+            [crypto-policy]
+            min-tls-version = "1.3"
+            aead-algorithms = ["aes-siv-cmac-256"]
+            allow-legacy-mac = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(test.crypto_policy.min_tls_version, TlsVersion::Tls13);
+        assert_eq!(
+            test.crypto_policy.aead_algorithms,
+            [AeadAlgorithm::AeadAesSivCmac256]
+        );
+        assert!(!test.crypto_policy.allow_legacy_mac);
+
+        let test: TestConfig = toml::from_str("[crypto-policy]").unwrap();
+        assert_eq!(test.crypto_policy, CryptoPolicyConfig::default());
+
+        for policy in [
+            "min-tls-version = \"1.2\"",
+            "aead-algorithms = []",
+            "aead-algorithms = [\"aes-128-gcm\"]",
+        ] {
+            let test: Result<TestConfig, _> = toml::from_str(&format!("[crypto-policy]\n{policy}"));
+            assert!(test.is_err(), "{policy}");
+        }
+    }
+
+    #[test]
+    fn test_symmetric_keys_crypto_policy() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join(format!("ntp-test-keys-{}", alloc_port()));
+        std::fs::write(
+            &path,
+            "1 AES128CMAC HEX:000102030405060708090a0b0c0d0e0f\n2 MD5 secret\n",
+        )
+        .unwrap();
+        let keyset = KeysetConfig {
+            symmetric_keys_path: Some(path.clone()),
+            ..KeysetConfig::default()
+        };
+
+        let keys = keyset
+            .symmetric_keys(&CryptoPolicyConfig::default())
+            .unwrap();
+        assert_eq!(keys.len(), 2);
+
+        let strict = CryptoPolicyConfig {
+            allow_legacy_mac: false,
+            ..CryptoPolicyConfig::default()
+        };
+        let err = keyset.symmetric_keys(&strict).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("key 2"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_deserialize_nts_ke() {
         #[derive(Deserialize, Debug)]
@@ -0,0 +1,337 @@
+use std::os::unix::fs::PermissionsExt;
+
+use libc::{ECONNABORTED, EMFILE, ENFILE, ENOBUFS, ENOMEM};
+use ntp_proto::StepSettings;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{Instrument, Span, debug, error, info, instrument, trace, warn};
+
+use super::{
+    sockets::{Listener, SocketLocation, read_json, write_json},
+    state::DaemonState,
+};
+
+/// Longest maintenance window that can be requested, so steering always resumes eventually
+pub const MAX_MAINTENANCE_SECONDS: u64 = 86400;
+
+/// Largest frequency offset a slew can be tuned to, the kernel does not adjust
+/// the frequency of the clock by more than 500 ppm
+pub const MAX_SLEW_FREQUENCY_OFFSET: f64 = 500e-6;
+
+/// A command sent to the daemon over the control socket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "command")]
+pub enum ControlRequest {
+    /// Stop polling the matching sources and exclude them from synchronization
+    DisableSource { source: String },
+    /// Resume polling the matching sources
+    EnableSource { source: String },
+    /// Suspend clock steering for the given number of seconds, zero resumes immediately
+    Maintenance { seconds: u64 },
+    /// Return the runtime state of the daemon
+    ExportState,
+    /// Take over the parts of an exported state that carry over to this daemon
+    ImportState { state: Box<DaemonState> },
+    /// Read the configuration file again and apply changes to the sources
+    Reload,
+    /// Start polling an additional server, given as `host:port`, until the
+    /// configuration is reloaded
+    AddSource { address: String },
+    /// Stop the configured sources that created the matching sources
+    RemoveSource { source: String },
+    /// Return the thresholds that decide between stepping and slewing the
+    /// clock, after changing those that are given until the configuration is
+    /// reloaded
+    Tune {
+        #[serde(default)]
+        step_threshold: Option<f64>,
+        #[serde(default)]
+        slew_maximum_frequency_offset: Option<f64>,
+        #[serde(default)]
+        slew_minimum_duration: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "result")]
+pub enum ControlResponse {
+    Ok {
+        /// Number of sources matching the name in the request
+        sources: usize,
+    },
+    UnknownSource,
+    /// Clock steering is suspended for this many more seconds
+    Maintenance {
+        seconds: u64,
+    },
+    State {
+        state: Box<DaemonState>,
+    },
+    /// What was taken over from an imported state
+    StateImported {
+        frequency: bool,
+        rate_limits: usize,
+        disabled_sources: usize,
+        servers: usize,
+    },
+    /// Number of configured sources that were started and stopped
+    Reloaded {
+        added: usize,
+        removed: usize,
+    },
+    /// The configuration could not be used, the daemon keeps running as before
+    ReloadFailed {
+        error: String,
+    },
+    /// The thresholds deciding between stepping and slewing the clock
+    Tuned {
+        settings: StepSettings,
+    },
+    /// The requested thresholds were rejected, the current ones are kept
+    TuneFailed {
+        error: String,
+    },
+}
+
+/// Reject thresholds with which the clock would not be steered sensibly
+pub fn check_step_settings(settings: &StepSettings) -> Result<(), String> {
+    if !(settings.step_threshold.is_finite() && settings.step_threshold >= 0.0) {
+        return Err(format!(
+            "step threshold must be a non-negative number of seconds, not {}",
+            settings.step_threshold
+        ));
+    }
+    if !(settings.slew_maximum_frequency_offset > 0.0
+        && settings.slew_maximum_frequency_offset <= MAX_SLEW_FREQUENCY_OFFSET)
+    {
+        return Err(format!(
+            "slew maximum frequency offset must be above 0 and at most {MAX_SLEW_FREQUENCY_OFFSET}, not {}",
+            settings.slew_maximum_frequency_offset
+        ));
+    }
+    if !(settings.slew_minimum_duration.is_finite() && settings.slew_minimum_duration > 0.0) {
+        return Err(format!(
+            "slew minimum duration must be a positive number of seconds, not {}",
+            settings.slew_minimum_duration
+        ));
+    }
+    Ok(())
+}
+
+/// A request together with the channel on which the system answers it
+#[derive(Debug)]
+pub struct ControlMessage {
+    pub request: ControlRequest,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+#[instrument(level = tracing::Level::ERROR, skip_all, name = "Control", fields(path = debug(config.control_path.clone())))]
+pub fn spawn(
+    config: &super::config::ObservabilityConfig,
+    control_sender: mpsc::Sender<ControlMessage>,
+) -> JoinHandle<std::io::Result<()>> {
+    let config = config.clone();
+    tokio::spawn(
+        (async move {
+            let result = control(config, control_sender).await;
+            if let Err(ref e) = result {
+                warn!("Abnormal termination of the control socket: {e}");
+                warn!("Runtime control of the daemon will not be available");
+            }
+            result
+        })
+        .instrument(Span::current()),
+    )
+}
+
+/// Reload the configuration whenever the daemon receives SIGHUP
+pub fn reload_on_hangup(control_sender: mpsc::Sender<ControlMessage>) -> std::io::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading configuration");
+            let (reply, response) = oneshot::channel();
+            let request = ControlRequest::Reload;
+            if control_sender
+                .send(ControlMessage { request, reply })
+                .await
+                .is_err()
+            {
+                break;
+            }
+            // the system task logs the outcome
+            let _ = response.await;
+        }
+    });
+    Ok(())
+}
+
+async fn control(
+    config: super::config::ObservabilityConfig,
+    control_sender: mpsc::Sender<ControlMessage>,
+) -> std::io::Result<()> {
+    let timeout = std::time::Duration::from_millis(500);
+
+    let location = match config.control_path {
+        Some(path) => SocketLocation::new(path, config.control_address),
+        None => return Ok(()),
+    };
+
+    let permissions: std::fs::Permissions = PermissionsExt::from_mode(config.control_permissions);
+
+    let control_listener = Listener::bind(&location, permissions).await?;
+
+    loop {
+        let connection = match control_listener.accept().await {
+            Ok(a) => a,
+            Err(e) if matches!(e.raw_os_error(), Some(ECONNABORTED)) => {
+                debug!("Unexpectedly closed unix socket: {e}");
+                continue;
+            }
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(ENFILE) | Some(EMFILE) | Some(ENOMEM) | Some(ENOBUFS)
+                ) =>
+            {
+                error!("Not enough resources available to accept incoming control socket: {e}");
+                tokio::time::sleep(timeout).await;
+                continue;
+            }
+            Err(e) => {
+                error!("Could not accept connection due to unexpected problem: {e}");
+                return Err(e);
+            }
+        };
+
+        // Commands are handled one at a time, so they are applied in the order they arrive
+        let handled = async {
+            let mut stream = connection.authenticate().await?;
+            handle_connection(&mut stream, &control_sender).await
+        };
+        match tokio::time::timeout(timeout, handled).await {
+            Err(_) => debug!("Handling control command timed out"),
+            Ok(Err(err)) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                warn!("Rejected connection: {err}")
+            }
+            Ok(Err(err)) => warn!("error handling control connection: {err}"),
+            Ok(Ok(())) => trace!("Handled control command"),
+        }
+    }
+}
+
+async fn handle_connection(
+    stream: &mut (impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin),
+    control_sender: &mpsc::Sender<ControlMessage>,
+) -> std::io::Result<()> {
+    let mut msg = Vec::with_capacity(1024);
+    let request: ControlRequest = read_json(stream, &mut msg).await?;
+
+    let (reply, response) = oneshot::channel();
+    control_sender
+        .send(ControlMessage { request, reply })
+        .await
+        .map_err(|_| std::io::Error::other("system task is not running"))?;
+    let response = response
+        .await
+        .map_err(|_| std::io::Error::other("system task did not answer"))?;
+
+    write_json(stream, &response).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::UnixStream;
+
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_control_roundtrip() {
+        // be careful with copying: tests run concurrently and should use a unique socket name!
+        let path = std::env::temp_dir().join(format!("ntp-test-stream-{}", alloc_port()));
+        let config = super::super::config::ObservabilityConfig {
+            control_path: Some(path.clone()),
+            control_permissions: 0o700,
+            ..Default::default()
+        };
+
+        let (control_sender, mut control_receiver) = mpsc::channel(1);
+
+        let handle = tokio::spawn(async move {
+            control(config, control_sender).await.unwrap();
+        });
+
+        let system = tokio::spawn(async move {
+            let msg: ControlMessage = control_receiver.recv().await.unwrap();
+            assert_eq!(
+                msg.request,
+                ControlRequest::DisableSource {
+                    source: "example.com:123".into()
+                }
+            );
+            msg.reply.send(ControlResponse::Ok { sources: 2 }).unwrap();
+        });
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let mut stream = UnixStream::connect(path).await.unwrap();
+        write_json(
+            &mut stream,
+            &ControlRequest::DisableSource {
+                source: "example.com:123".into(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut buf = vec![];
+        let response: ControlResponse = read_json(&mut stream, &mut buf).await.unwrap();
+        assert_eq!(response, ControlResponse::Ok { sources: 2 });
+
+        system.await.unwrap();
+        handle.abort();
+    }
+
+    #[test]
+    fn test_check_step_settings() {
+        let settings = StepSettings {
+            step_threshold: 0.01,
+            slew_maximum_frequency_offset: 200e-6,
+            slew_minimum_duration: 8.0,
+        };
+        assert_eq!(check_step_settings(&settings), Ok(()));
+
+        for invalid in [
+            StepSettings {
+                step_threshold: -1.0,
+                ..settings
+            },
+            StepSettings {
+                step_threshold: f64::NAN,
+                ..settings
+            },
+            StepSettings {
+                slew_maximum_frequency_offset: 0.0,
+                ..settings
+            },
+            StepSettings {
+                slew_maximum_frequency_offset: 1e-3,
+                ..settings
+            },
+            StepSettings {
+                slew_minimum_duration: f64::INFINITY,
+                ..settings
+            },
+        ] {
+            assert!(check_step_settings(&invalid).is_err(), "{invalid:?}");
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Diversity of the sources steering the clock.
+//!
+//! However many sources steer the clock, they can all fail at once when they
+//! share a network or are run by the same provider. The daemon warns when all
+//! sources steering the clock share such a failure domain, and scores how
+//! independent they are, including how many of them are authenticated. It
+//! does not know the autonomous system of a source, the network the source is
+//! in stands in for it.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{
+    config::NtpSourceConfig,
+    nat64::{Nat64Prefix, WELL_KNOWN_PREFIX},
+};
+
+/// How long sources must share a failure domain before this is logged, so the
+/// selection settling after startup or a lost source does not cause warnings
+pub const DIVERSITY_WARNING_DELAY: Duration = Duration::from_secs(600);
+
+/// Where a source steering the clock gets its time from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceDomain {
+    /// Network of the source, none for a local reference clock
+    network: Option<String>,
+    /// Who runs the source, when known
+    provider: Option<String>,
+    /// Whether the source is reached over the network without authentication
+    unauthenticated: bool,
+}
+
+impl SourceDomain {
+    /// The domain of a source with the given name and address, created for
+    /// the configured source `config`. Addresses in the NAT64 prefix are in
+    /// the network of the IPv4 address they reach.
+    pub fn new(
+        config: &NtpSourceConfig,
+        name: &str,
+        address: &str,
+        nat64: Option<Nat64Prefix>,
+    ) -> SourceDomain {
+        let network = address.parse::<SocketAddr>().ok().map(|addr| {
+            let ip = nat64
+                .unwrap_or(WELL_KNOWN_PREFIX)
+                .extract(addr.ip())
+                .map_or(addr.ip(), IpAddr::V4);
+            network_of(ip)
+        });
+        let (provider, authenticated) = match config {
+            NtpSourceConfig::Standard(cfg) => (provider_of(name), cfg.first.key.is_some()),
+            NtpSourceConfig::Nts(_) => (provider_of(name), true),
+            // the servers of a pool or a service are run by many providers
+            NtpSourceConfig::Pool(_) | NtpSourceConfig::Service(_) => (None, false),
+            NtpSourceConfig::NtsPool(_) => (None, true),
+            NtpSourceConfig::Sock(_) | NtpSourceConfig::File(_) => (None, true),
+            #[cfg(feature = "pps")]
+            NtpSourceConfig::Pps(_) => (None, true),
+        };
+
+        SourceDomain {
+            unauthenticated: network.is_some() && !authenticated,
+            network,
+            provider,
+        }
+    }
+}
+
+/// The /24 network of an IPv4 address, or the /48 network of an IPv6 address
+fn network_of(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => format!("{}/24", Ipv4Addr::from_bits(ip.to_bits() & !0xff)),
+        IpAddr::V6(ip) => format!(
+            "{}/48",
+            Ipv6Addr::from_bits(ip.to_bits() & !((1u128 << 80) - 1))
+        ),
+    }
+}
+
+/// Second-level labels under which country code domains register names, as
+/// in `example.co.uk`
+const SECOND_LEVEL_LABELS: &[&str] = &["ac", "co", "com", "edu", "gov", "ne", "net", "or", "org"];
+
+/// The registered domain in the host name of a source, such as `example.com`
+/// for `time.example.com:123`, or none when the source is named by its address.
+///
+/// Without the public suffix list, this is the last two labels, or three for
+/// names registered under a common second level of a country code domain,
+/// such as `example.co.uk`. Other suffixes under which anyone can register a
+/// name, like those of hosting providers, are taken for a single provider.
+fn provider_of(name: &str) -> Option<String> {
+    if name.parse::<SocketAddr>().is_ok() || name.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    let host = name.rsplit_once(':').map_or(name, |(host, _)| host);
+    let labels: Vec<String> = host
+        .trim_end_matches('.')
+        .split('.')
+        .map(str::to_ascii_lowercase)
+        .collect();
+    let count = match &labels[..] {
+        [.., second, top] if top.len() == 2 && SECOND_LEVEL_LABELS.contains(&second.as_str()) => 3,
+        _ => 2,
+    };
+    Some(labels[labels.len().saturating_sub(count)..].join("."))
+}
+
+/// A failure domain shared by all sources steering the clock
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SharedDomain {
+    /// All sources are in this network
+    Network(String),
+    /// All sources are run by this provider
+    Provider(String),
+}
+
+impl fmt::Display for SharedDomain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SharedDomain::Network(network) => write!(f, "network {network}"),
+            SharedDomain::Provider(provider) => write!(f, "provider {provider}"),
+        }
+    }
+}
+
+/// How independent the sources steering the clock are, as shown on the
+/// observation socket
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Diversity {
+    /// Number of sources steering the clock
+    pub sources: usize,
+    /// Number of networks they are in, local reference clocks each count as
+    /// a network of their own
+    pub networks: usize,
+    /// Number of providers that run them, sources of which the provider is
+    /// not known each count as a provider of their own
+    pub providers: usize,
+    /// Number of them that cannot be tampered with on the network: local
+    /// reference clocks and sources using NTS or a symmetric key
+    pub authenticated: usize,
+    /// From 0, when all share a network and a provider and none is
+    /// authenticated, to 1, when every source is in a network of its own, with
+    /// a provider of its own, and authenticated
+    pub score: f64,
+    /// The failure domains all of them share
+    pub shared: Vec<SharedDomain>,
+}
+
+impl Diversity {
+    pub fn assess(sources: &[SourceDomain]) -> Diversity {
+        if sources.is_empty() {
+            return Diversity::default();
+        }
+
+        let networks = count_distinct(sources.iter().map(|source| &source.network));
+        let providers = count_distinct(sources.iter().map(|source| &source.provider));
+        let authenticated = sources
+            .iter()
+            .filter(|source| !source.unauthenticated)
+            .count();
+
+        // how far the sources are spread out, from 0 when they are all the
+        // same to 1 when they all differ
+        let spread = |distinct: usize| {
+            if sources.len() > 1 {
+                (distinct - 1) as f64 / (sources.len() - 1) as f64
+            } else {
+                0.0
+            }
+        };
+        let score =
+            (spread(networks) + spread(providers) + authenticated as f64 / sources.len() as f64)
+                / 3.0;
+
+        let mut shared = vec![];
+        if let Some(network) = common(sources.iter().map(|source| &source.network)) {
+            shared.push(SharedDomain::Network(network));
+        }
+        if let Some(provider) = common(sources.iter().map(|source| &source.provider)) {
+            shared.push(SharedDomain::Provider(provider));
+        }
+
+        Diversity {
+            sources: sources.len(),
+            networks,
+            providers,
+            authenticated,
+            score,
+            shared,
+        }
+    }
+}
+
+/// Number of distinct values, where every unknown value is distinct
+fn count_distinct<'a>(values: impl Iterator<Item = &'a Option<String>>) -> usize {
+    let mut known = vec![];
+    let mut unknown = 0;
+    for value in values {
+        match value {
+            Some(value) if !known.contains(&value) => known.push(value),
+            Some(_) => {}
+            None => unknown += 1,
+        }
+    }
+    known.len() + unknown
+}
+
+/// The value all are known to have in common, if any
+fn common<'a>(mut values: impl Iterator<Item = &'a Option<String>>) -> Option<String> {
+    let first = values.next()?.as_ref()?;
+    values
+        .all(|value| value.as_ref() == Some(first))
+        .then(|| first.clone())
+}
+
+/// Logs when the sources steering the clock start or stop sharing a failure
+/// domain, once that has lasted for [`DIVERSITY_WARNING_DELAY`]
+#[derive(Debug)]
+pub struct DiversityMonitor {
+    shared: Vec<SharedDomain>,
+    since: tokio::time::Instant,
+    /// The failure domains last logged about
+    reported: Vec<SharedDomain>,
+}
+
+impl DiversityMonitor {
+    pub fn new(now: tokio::time::Instant) -> DiversityMonitor {
+        DiversityMonitor {
+            shared: vec![],
+            since: now,
+            reported: vec![],
+        }
+    }
+
+    pub fn update(&mut self, diversity: &Diversity, now: tokio::time::Instant) {
+        if diversity.shared != self.shared {
+            self.shared = diversity.shared.clone();
+            self.since = now;
+        }
+        if self.shared == self.reported || now - self.since < DIVERSITY_WARNING_DELAY {
+            return;
+        }
+
+        if self.shared.is_empty() {
+            info!("sources steering the clock no longer share a failure domain");
+        } else {
+            let shared = self
+                .shared
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            warn!(
+                sources = diversity.sources,
+                %shared,
+                "all sources steering the clock share a failure domain, consider adding independent sources"
+            );
+        }
+        self.reported = self.shared.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(config: &str, name: &str, address: &str) -> SourceDomain {
+        let config: NtpSourceConfig = toml::from_str(config).unwrap();
+        SourceDomain::new(&config, name, address, None)
+    }
+
+    fn server(name: &str, address: &str) -> SourceDomain {
+        domain(
+            &format!("mode = \"server\"\naddress = \"{name}\""),
+            name,
+            address,
+        )
+    }
+
+    #[test]
+    fn test_source_domain() {
+        let source = server("time.Example.com:123", "192.0.2.17:123");
+        assert_eq!(source.network.as_deref(), Some("192.0.2.0/24"));
+        assert_eq!(source.provider.as_deref(), Some("example.com"));
+        assert!(source.unauthenticated);
+
+        let source = server("ntp1.Example.CO.uk:123", "192.0.2.17:123");
+        assert_eq!(source.provider.as_deref(), Some("example.co.uk"));
+        let source = server("time.example.de:123", "192.0.2.17:123");
+        assert_eq!(source.provider.as_deref(), Some("example.de"));
+        let source = server("co.uk:123", "192.0.2.17:123");
+        assert_eq!(source.provider.as_deref(), Some("co.uk"));
+
+        let source = server("[2001:db8:1:2::1]:123", "[2001:db8:1:2::1]:123");
+        assert_eq!(source.network.as_deref(), Some("2001:db8:1::/48"));
+        assert_eq!(source.provider, None);
+
+        let source = server("192.0.2.17:123", "[64:ff9b::192.0.2.17]:123");
+        assert_eq!(source.network.as_deref(), Some("192.0.2.0/24"));
+
+        let source = domain(
+            "mode = \"pool\"\naddress = \"pool.example.com\"",
+            "pool.example.com:123",
+            "192.0.2.1:123",
+        );
+        assert_eq!(source.provider, None);
+        assert!(source.unauthenticated);
+
+        let source = domain(
+            "mode = \"sock\"\npath = \"/run/gps.sock\"\nprecision = 1e-6",
+            "/run/gps.sock",
+            "/run/gps.sock",
+        );
+        assert_eq!(source.network, None);
+        assert!(!source.unauthenticated);
+    }
+
+    #[test]
+    fn test_shared_failure_domain() {
+        let diversity = Diversity::assess(&[
+            server("a.example.com:123", "192.0.2.1:123"),
+            server("b.example.com:123", "192.0.2.2:123"),
+        ]);
+        assert_eq!(diversity.sources, 2);
+        assert_eq!(diversity.networks, 1);
+        assert_eq!(diversity.providers, 1);
+        assert_eq!(diversity.authenticated, 0);
+        assert_eq!(diversity.score, 0.0);
+        assert_eq!(
+            diversity.shared,
+            [
+                SharedDomain::Network("192.0.2.0/24".to_owned()),
+                SharedDomain::Provider("example.com".to_owned()),
+            ]
+        );
+
+        let diversity = Diversity::assess(&[
+            server("a.example.com:123", "192.0.2.1:123"),
+            server("b.example.org:123", "198.51.100.1:123"),
+        ]);
+        assert!(diversity.shared.is_empty());
+        assert!((diversity.score - 2.0 / 3.0).abs() < 1e-9);
+
+        assert_eq!(Diversity::assess(&[]), Diversity::default());
+    }
+
+    #[test]
+    fn test_monitor_waits_for_selection_to_settle() {
+        let start = tokio::time::Instant::now();
+        let mut monitor = DiversityMonitor::new(start);
+        let single = Diversity::assess(&[server("a.example.com:123", "192.0.2.1:123")]);
+        let diverse = Diversity::default();
+
+        monitor.update(&single, start);
+        monitor.update(&diverse, start + Duration::from_secs(60));
+        monitor.update(&diverse, start + DIVERSITY_WARNING_DELAY);
+        assert!(monitor.reported.is_empty());
+
+        monitor.update(&single, start + DIVERSITY_WARNING_DELAY);
+        monitor.update(&single, start + 2 * DIVERSITY_WARNING_DELAY);
+        assert_eq!(monitor.reported, single.shared);
+    }
+}
@@ -0,0 +1,1555 @@
+//! Discovery of NTP servers through DNS service records: the `_ntp._udp` SRV
+//! records of a domain looked up at the resolvers of the system (RFC 2782),
+//! and the servers advertised with multicast DNS on the local network
+//! (RFC 6762, RFC 6763). The servers of this daemon can be advertised the
+//! same way.
+//!
+//! Only the small part of DNS needed for this is implemented here: a single
+//! question, the SRV, PTR, TXT and address records in the answers, and the
+//! resolvers and search domains of `/etc/resolv.conf`. The names of the
+//! servers found are resolved as usual, by the system. A resolver crate would
+//! add a large dependency tree for caching, DNSSEC and encrypted transports
+//! none of which is used here, on top of which the responder advertising our
+//! own servers would still be needed, as resolvers do not answer queries.
+
+use std::{
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use rand::Rng;
+use timestamped_socket::interface::interfaces;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::{Instant, timeout, timeout_at},
+};
+use tracing::{debug, warn};
+
+use super::util::canonical_addr;
+
+/// Service name under which NTP servers are listed
+pub const NTP_SERVICE: &str = "_ntp._udp";
+/// Service name of NTS key exchange servers
+#[cfg(feature = "nts-server")]
+pub const NTS_KE_SERVICE: &str = "_ntske._tcp";
+/// Name listing the services offered on the local network (RFC 6763, section 9)
+const SERVICES_NAME: &str = "_services._dns-sd._udp.local";
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const DNS_PORT: u16 = 53;
+const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_ADDRESS: SocketAddr = SocketAddr::new(IpAddr::V4(MDNS_GROUP), MDNS_PORT);
+
+/// How long to wait for the answer of a resolver
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to collect the answers of multicast DNS responders
+const MDNS_WAIT: Duration = Duration::from_secs(1);
+/// Largest multicast DNS message (RFC 6762, section 17)
+const MDNS_MAX_MESSAGE: usize = 9000;
+/// Compression pointers followed in a single name, to stop on loops
+const MAX_POINTERS: usize = 16;
+/// Time to live of the records naming the host, and of the other records
+/// (RFC 6762, section 10)
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+/// Longest time to live in answers to queries that are not from port 5353
+/// (RFC 6762, section 6.7)
+const LEGACY_UNICAST_TTL: u32 = 10;
+/// Probes sent before claiming the host name, and the time between them
+/// (RFC 6762, section 8.1)
+const PROBES: usize = 3;
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the loser of simultaneous probes waits before probing again
+/// (RFC 6762, section 8.2)
+const PROBE_DEFER: Duration = Duration::from_secs(1);
+/// After this many conflicts, the host name is only probed for every few
+/// seconds (RFC 6762, section 8.1)
+const MAX_CONFLICTS: usize = 15;
+const PROBE_THROTTLE: Duration = Duration::from_secs(5);
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+pub const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NAME_ERROR: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub record_type: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Txt(Vec<String>),
+    /// A record of a type that is not used here, its data is skipped
+    Other(u16),
+}
+
+impl RecordData {
+    fn record_type(&self) -> u16 {
+        match self {
+            RecordData::A(_) => TYPE_A,
+            RecordData::Aaaa(_) => TYPE_AAAA,
+            RecordData::Ptr(_) => TYPE_PTR,
+            RecordData::Srv { .. } => TYPE_SRV,
+            RecordData::Txt(_) => TYPE_TXT,
+            RecordData::Other(record_type) => *record_type,
+        }
+    }
+
+    /// The name this record points to
+    fn target(&self) -> Option<&str> {
+        match self {
+            RecordData::Ptr(name) | RecordData::Srv { target: name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+/// A DNS message
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Message {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    /// Records a multicast DNS probe proposes for the names it asks about
+    /// (RFC 6762, section 8.2)
+    pub authority: Vec<Record>,
+    pub additional: Vec<Record>,
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "malformed DNS message")
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(ErrorKind::TimedOut, "no answer from the DNS resolver")
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) -> io::Result<()> {
+    let name = name.trim_end_matches('.');
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid DNS name: {name}"),
+                ));
+            }
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+    }
+    out.push(0);
+    Ok(())
+}
+
+fn write_record(out: &mut Vec<u8>, record: &Record) -> io::Result<()> {
+    write_name(out, &record.name)?;
+    out.extend_from_slice(&record.data.record_type().to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out.extend_from_slice(&record.ttl.to_be_bytes());
+
+    let length_at = out.len();
+    out.extend_from_slice(&[0, 0]);
+    match &record.data {
+        RecordData::A(ip) => out.extend_from_slice(&ip.octets()),
+        RecordData::Aaaa(ip) => out.extend_from_slice(&ip.octets()),
+        RecordData::Ptr(name) => write_name(out, name)?,
+        RecordData::Srv {
+            priority,
+            weight,
+            port,
+            target,
+        } => {
+            out.extend_from_slice(&priority.to_be_bytes());
+            out.extend_from_slice(&weight.to_be_bytes());
+            out.extend_from_slice(&port.to_be_bytes());
+            write_name(out, target)?;
+        }
+        RecordData::Txt(strings) if strings.is_empty() => {
+            // a TXT record holds at least one, possibly empty, string
+            out.push(0);
+        }
+        RecordData::Txt(strings) => {
+            for string in strings {
+                let length = u8::try_from(string.len()).map_err(|_| {
+                    io::Error::new(ErrorKind::InvalidInput, "TXT string is too long")
+                })?;
+                out.push(length);
+                out.extend_from_slice(string.as_bytes());
+            }
+        }
+        RecordData::Other(_) => {}
+    }
+    let length = (out.len() - length_at - 2) as u16;
+    out[length_at..length_at + 2].copy_from_slice(&length.to_be_bytes());
+    Ok(())
+}
+
+/// Read the (possibly compressed) name at `pos` of `data`, returning the name
+/// and the position right after it
+fn read_name(data: &[u8], mut pos: usize) -> io::Result<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let length = *data.get(pos).ok_or_else(malformed)? as usize;
+        match length {
+            0 => {
+                let end = *end.get_or_insert(pos + 1);
+                return Ok((labels.join("."), end));
+            }
+            1..=63 => {
+                let label = data.get(pos + 1..pos + 1 + length).ok_or_else(malformed)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + length;
+            }
+            0xc0.. => {
+                let low = *data.get(pos + 1).ok_or_else(malformed)? as usize;
+                end.get_or_insert(pos + 2);
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return Err(malformed());
+                }
+                pos = ((length & 0x3f) << 8) | low;
+            }
+            _ => return Err(malformed()),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + length)
+            .ok_or_else(malformed)?;
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn name(&mut self) -> io::Result<String> {
+        let (name, end) = read_name(self.data, self.pos)?;
+        self.pos = end;
+        Ok(name)
+    }
+
+    fn question(&mut self) -> io::Result<Question> {
+        let name = self.name()?;
+        let record_type = self.u16()?;
+        let _class = self.u16()?;
+        Ok(Question { name, record_type })
+    }
+
+    fn record(&mut self) -> io::Result<Record> {
+        let name = self.name()?;
+        let record_type = self.u16()?;
+        // only the top bit of the class differs in multicast DNS
+        let _class = self.u16()?;
+        let ttl = self.u32()?;
+        let length = self.u16()? as usize;
+        let start = self.pos;
+        let rdata = self.bytes(length)?;
+
+        let data = match record_type {
+            TYPE_A => RecordData::A(Ipv4Addr::from(
+                <[u8; 4]>::try_from(rdata).map_err(|_| malformed())?,
+            )),
+            TYPE_AAAA => RecordData::Aaaa(Ipv6Addr::from(
+                <[u8; 16]>::try_from(rdata).map_err(|_| malformed())?,
+            )),
+            // names in the data may point anywhere in the message
+            TYPE_PTR => RecordData::Ptr(read_name(self.data, start)?.0),
+            TYPE_SRV => {
+                let mut srv = Reader {
+                    data: self.data,
+                    pos: start,
+                };
+                RecordData::Srv {
+                    priority: srv.u16()?,
+                    weight: srv.u16()?,
+                    port: srv.u16()?,
+                    target: srv.name()?,
+                }
+            }
+            TYPE_TXT => {
+                let mut txt = Reader {
+                    data: rdata,
+                    pos: 0,
+                };
+                let mut strings = vec![];
+                while txt.pos < rdata.len() {
+                    let length = txt.bytes(1)?[0] as usize;
+                    strings.push(String::from_utf8_lossy(txt.bytes(length)?).into_owned());
+                }
+                // a single empty string stands for a record without strings
+                if strings == [""] {
+                    strings.clear();
+                }
+                RecordData::Txt(strings)
+            }
+            other => RecordData::Other(other),
+        };
+
+        Ok(Record { name, ttl, data })
+    }
+}
+
+impl Message {
+    pub fn serialize(&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(512);
+        out.extend_from_slice(&self.id.to_be_bytes());
+        out.extend_from_slice(&self.flags.to_be_bytes());
+        for count in [
+            self.questions.len(),
+            self.answers.len(),
+            self.authority.len(),
+            self.additional.len(),
+        ] {
+            let count = u16::try_from(count).map_err(|_| malformed())?;
+            out.extend_from_slice(&count.to_be_bytes());
+        }
+
+        for question in &self.questions {
+            write_name(&mut out, &question.name)?;
+            out.extend_from_slice(&question.record_type.to_be_bytes());
+            out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        }
+        for record in self
+            .answers
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            write_record(&mut out, record)?;
+        }
+
+        Ok(out)
+    }
+
+    pub fn deserialize(data: &[u8]) -> io::Result<Message> {
+        let mut reader = Reader { data, pos: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let questions = reader.u16()?;
+        let answers = reader.u16()?;
+        let authority = reader.u16()?;
+        let additional = reader.u16()?;
+
+        let mut message = Message {
+            id,
+            flags,
+            ..Default::default()
+        };
+        for _ in 0..questions {
+            message.questions.push(reader.question()?);
+        }
+        for _ in 0..answers {
+            message.answers.push(reader.record()?);
+        }
+        for _ in 0..authority {
+            message.authority.push(reader.record()?);
+        }
+        for _ in 0..additional {
+            message.additional.push(reader.record()?);
+        }
+
+        Ok(message)
+    }
+
+    fn into_records(self) -> impl Iterator<Item = Record> {
+        self.answers
+            .into_iter()
+            .chain(self.authority)
+            .chain(self.additional)
+    }
+}
+
+/// The resolvers and search domains of the system
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ResolverConfig {
+    nameservers: Vec<SocketAddr>,
+    search: Vec<String>,
+}
+
+impl ResolverConfig {
+    fn system() -> io::Result<ResolverConfig> {
+        std::fs::read_to_string(RESOLV_CONF)
+            .map(|contents| ResolverConfig::parse(&contents))
+            .map_err(|e| io::Error::new(e.kind(), format!("could not read {RESOLV_CONF}: {e}")))
+    }
+
+    fn parse(contents: &str) -> ResolverConfig {
+        let mut config = ResolverConfig::default();
+        for line in contents.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(Ok(ip)) = words.next().map(str::parse::<IpAddr>) {
+                        config.nameservers.push(SocketAddr::new(ip, DNS_PORT));
+                    }
+                }
+                // the last of these lines is the one that counts
+                Some("domain" | "search") => {
+                    config.search = words
+                        .map(|domain| domain.trim_end_matches('.').to_owned())
+                        .filter(|domain| !domain.is_empty())
+                        .collect();
+                }
+                _ => {}
+            }
+        }
+        config
+    }
+}
+
+/// Ask the resolver at `nameserver` a single question, over TCP when the
+/// answer does not fit in a datagram
+async fn query(nameserver: SocketAddr, question: &Question) -> io::Result<Message> {
+    let id = rand::random();
+    let request = Message {
+        id,
+        flags: FLAG_RECURSION_DESIRED,
+        questions: vec![question.clone()],
+        ..Default::default()
+    }
+    .serialize()?;
+
+    let local: SocketAddr = match nameserver {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(nameserver).await?;
+    socket.send(&request).await?;
+
+    let mut buf = vec![0; 4096];
+    let mut response = timeout(QUERY_TIMEOUT, async {
+        loop {
+            let length = socket.recv(&mut buf).await?;
+            match Message::deserialize(&buf[..length]) {
+                Ok(message) if message.id == id && message.flags & FLAG_RESPONSE != 0 => {
+                    return Ok::<_, io::Error>(message);
+                }
+                _ => continue,
+            }
+        }
+    })
+    .await
+    .map_err(|_| timed_out())??;
+
+    if response.flags & FLAG_TRUNCATED != 0 {
+        response = timeout(QUERY_TIMEOUT, query_tcp(nameserver, &request))
+            .await
+            .map_err(|_| timed_out())??;
+    }
+
+    match response.flags & RCODE_MASK {
+        0 | RCODE_NAME_ERROR => Ok(response),
+        rcode => Err(io::Error::other(format!(
+            "DNS resolver {nameserver} answered with error code {rcode}"
+        ))),
+    }
+}
+
+async fn query_tcp(nameserver: SocketAddr, request: &[u8]) -> io::Result<Message> {
+    let mut stream = TcpStream::connect(nameserver).await?;
+    stream
+        .write_all(&(request.len() as u16).to_be_bytes())
+        .await?;
+    stream.write_all(request).await?;
+
+    let length = stream.read_u16().await?;
+    let mut buf = vec![0; length as usize];
+    stream.read_exact(&mut buf).await?;
+    Message::deserialize(&buf)
+}
+
+/// Ask the resolvers in turn, until one of them answers
+async fn lookup(question: Question, nameservers: &[SocketAddr]) -> io::Result<Message> {
+    let mut last_error = io::Error::new(ErrorKind::NotFound, "no DNS resolvers configured");
+    for nameserver in nameservers {
+        match query(*nameserver, &question).await {
+            Ok(response) => return Ok(response),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Ask the multicast DNS responders on the local network, collecting their
+/// answers for a while. As the query is not sent from port 5353, responders
+/// answer it directly (RFC 6762, section 6.7).
+async fn query_multicast(questions: Vec<Question>) -> io::Result<Vec<Record>> {
+    let id = rand::random();
+    let request = Message {
+        id,
+        questions,
+        ..Default::default()
+    }
+    .serialize()?;
+
+    let subnets = local_subnets()?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.send_to(&request, MDNS_ADDRESS).await?;
+
+    let deadline = Instant::now() + MDNS_WAIT;
+    let mut buf = vec![0; MDNS_MAX_MESSAGE];
+    let mut records = vec![];
+    while let Ok(received) = timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (length, sender) = received?;
+        // responders answer from the multicast DNS port, and are on the
+        // local network (RFC 6762, sections 6 and 11)
+        if sender.port() != MDNS_PORT || !on_link(&subnets, sender.ip()) {
+            debug!(%sender, "ignoring multicast DNS answer from outside the local network");
+            continue;
+        }
+        if let Ok(message) = Message::deserialize(&buf[..length])
+            && message.id == id
+            && message.flags & FLAG_RESPONSE != 0
+        {
+            records.extend(message.into_records());
+        }
+    }
+
+    Ok(records)
+}
+
+/// The IPv4 subnets the host is on, as its addresses with their netmask
+fn local_subnets() -> io::Result<Vec<(Ipv4Addr, Ipv4Addr)>> {
+    Ok(nix::ifaddrs::getifaddrs()?
+        .filter_map(|ifaddr| {
+            let address = ifaddr.address?.as_sockaddr_in()?.ip();
+            let netmask = ifaddr.netmask?.as_sockaddr_in()?.ip();
+            Some((address, netmask))
+        })
+        .collect())
+}
+
+/// Whether `ip` is on one of the `subnets` of the host, or a link-local
+/// address, which is on the local link by definition
+fn on_link(subnets: &[(Ipv4Addr, Ipv4Addr)], ip: IpAddr) -> bool {
+    let IpAddr::V4(ip) = ip else {
+        return false;
+    };
+    ip.is_link_local()
+        || subnets.iter().any(|(address, netmask)| {
+            ip.to_bits() & netmask.to_bits() == address.to_bits() & netmask.to_bits()
+        })
+}
+
+fn has_record(records: &[Record], name: &str, matches: impl Fn(&RecordData) -> bool) -> bool {
+    records
+        .iter()
+        .any(|record| record.name.eq_ignore_ascii_case(name) && matches(&record.data))
+}
+
+/// Browse the NTP servers advertised on the local network. Responders
+/// usually include the service and address records of the instances with
+/// their answer, whatever is missing is asked for once more.
+async fn browse_mdns() -> io::Result<Vec<Record>> {
+    let service = format!("{NTP_SERVICE}.local");
+    let mut records = query_multicast(vec![Question {
+        name: service.clone(),
+        record_type: TYPE_PTR,
+    }])
+    .await?;
+
+    let mut questions = vec![];
+    for record in &records {
+        match &record.data {
+            RecordData::Ptr(instance)
+                if record.name.eq_ignore_ascii_case(&service)
+                    && !has_record(&records, instance, |data| {
+                        matches!(data, RecordData::Srv { .. })
+                    }) =>
+            {
+                questions.push(Question {
+                    name: instance.clone(),
+                    record_type: TYPE_SRV,
+                });
+            }
+            RecordData::Srv { target, .. }
+                if !has_record(&records, target, |data| {
+                    matches!(data, RecordData::A(_) | RecordData::Aaaa(_))
+                }) =>
+            {
+                for record_type in [TYPE_A, TYPE_AAAA] {
+                    questions.push(Question {
+                        name: target.clone(),
+                        record_type,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !questions.is_empty() {
+        records.extend(query_multicast(questions).await?);
+    }
+
+    Ok(records)
+}
+
+/// An address of a server found through service discovery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    /// Name of the server from its service record
+    pub name: String,
+    pub addr: SocketAddr,
+    pub priority: u16,
+    pub weight: u16,
+}
+
+/// The servers of the service records among `answers`, at the addresses given
+/// along with them, or else the addresses their names resolve to. Each answer
+/// only gives addresses for its own service records, so a resolver or
+/// responder cannot redirect the servers found through another.
+async fn servers_of(answers: &[Vec<Record>]) -> Vec<DiscoveredServer> {
+    let mut servers = vec![];
+    for records in answers {
+        servers.extend(servers_of_answer(records).await);
+    }
+    servers
+}
+
+async fn servers_of_answer(records: &[Record]) -> Vec<DiscoveredServer> {
+    let mut servers = vec![];
+    for record in records {
+        let RecordData::Srv {
+            priority,
+            weight,
+            port,
+            target,
+        } = &record.data
+        else {
+            continue;
+        };
+        // a target of "." means that the service is not offered (RFC 2782)
+        if target.is_empty() {
+            continue;
+        }
+
+        let mut addresses: Vec<SocketAddr> = records
+            .iter()
+            .filter(|record| record.name.eq_ignore_ascii_case(target))
+            .filter_map(|record| match record.data {
+                RecordData::A(ip) => Some(SocketAddr::new(ip.into(), *port)),
+                RecordData::Aaaa(ip) => Some(SocketAddr::new(ip.into(), *port)),
+                _ => None,
+            })
+            .collect();
+        if addresses.is_empty() {
+            match tokio::net::lookup_host((target.as_str(), *port)).await {
+                Ok(resolved) => addresses.extend(resolved),
+                Err(e) => debug!(%target, error = ?e, "could not resolve a discovered server"),
+            }
+        }
+
+        servers.extend(addresses.into_iter().map(|addr| DiscoveredServer {
+            name: target.clone(),
+            addr: canonical_addr(addr),
+            priority: *priority,
+            weight: *weight,
+        }));
+    }
+    servers
+}
+
+/// Order servers the way RFC 2782 asks clients to try them: by priority, and
+/// within a priority at random, with a chance proportional to their weight
+fn order_servers(mut servers: Vec<DiscoveredServer>, rng: &mut impl Rng) -> Vec<DiscoveredServer> {
+    // servers without weight go first, they are only picked when no other is
+    servers.sort_by_key(|server| (server.priority, server.weight != 0));
+
+    let mut ordered = Vec::with_capacity(servers.len());
+    for group in servers.chunk_by(|a, b| a.priority == b.priority) {
+        let mut group = group.to_vec();
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|server| u32::from(server.weight)).sum();
+            let mut pick = rng.gen_range(0..=total);
+            let index = group
+                .iter()
+                .position(|server| {
+                    let weight = u32::from(server.weight);
+                    if pick <= weight {
+                        true
+                    } else {
+                        pick -= weight;
+                        false
+                    }
+                })
+                .unwrap_or(0);
+            ordered.push(group.remove(index));
+        }
+    }
+    ordered
+}
+
+/// Find the NTP servers listed in the SRV records of `domain`, or of the
+/// search domains of the system when no domain is given, together with those
+/// advertised on the local network when `mdns` is set. The servers are in the
+/// order in which they should be used.
+// tests use servers given by the test instead
+#[cfg_attr(test, allow(dead_code))]
+pub async fn discover(domain: Option<&str>, mdns: bool) -> io::Result<Vec<DiscoveredServer>> {
+    let resolver = ResolverConfig::system();
+    let domains = match domain {
+        Some(domain) => vec![domain.to_owned()],
+        None => resolver
+            .as_ref()
+            .map(|resolver| resolver.search.clone())
+            .unwrap_or_default(),
+    };
+    if domains.is_empty() && !mdns {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            "no domain to look for NTP servers in",
+        ));
+    }
+
+    let mut answers = vec![];
+    let mut last_error = None;
+    for domain in &domains {
+        let question = Question {
+            name: format!("{NTP_SERVICE}.{domain}"),
+            record_type: TYPE_SRV,
+        };
+        let response = match &resolver {
+            Ok(resolver) => lookup(question, &resolver.nameservers).await,
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        };
+        match response {
+            Ok(response) => answers.push(response.into_records().collect()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    if mdns {
+        match browse_mdns().await {
+            Ok(found) => answers.push(found),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    let servers = servers_of(&answers).await;
+    if servers.is_empty() {
+        return Err(last_error
+            .unwrap_or_else(|| io::Error::new(ErrorKind::NotFound, "no NTP servers found")));
+    }
+    Ok(order_servers(servers, &mut rand::thread_rng()))
+}
+
+/// A service of this daemon to advertise with multicast DNS
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdvertisedService {
+    /// Service name, such as [`NTP_SERVICE`]
+    pub service: &'static str,
+    /// Address the service listens on, an unspecified address stands for all
+    /// addresses of the host
+    pub addr: SocketAddr,
+}
+
+/// Name of the host, without its domain
+fn hostname() -> String {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .and_then(|name| {
+            name.trim()
+                .split('.')
+                .next()
+                .filter(|label| !label.is_empty())
+                .map(str::to_owned)
+        })
+        .unwrap_or_else(|| "ntpd-rs".to_owned())
+}
+
+/// Whether `ip` can only be reached on the local network. Public addresses
+/// are not advertised, as multicast DNS is about the local network only.
+fn is_local_address(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => ip.is_unique_local() || ip.is_unicast_link_local(),
+    }
+}
+
+/// The local addresses the services are offered on
+fn host_addresses(services: &[AdvertisedService]) -> Vec<IpAddr> {
+    let mut ips: Vec<IpAddr> = services
+        .iter()
+        .map(|service| service.addr.ip())
+        .filter(|ip| !ip.is_unspecified())
+        .collect();
+    if services
+        .iter()
+        .any(|service| service.addr.ip().is_unspecified())
+    {
+        match interfaces() {
+            Ok(interfaces) => {
+                ips.extend(interfaces.iter().flat_map(|(_, interface)| interface.ips()))
+            }
+            Err(e) => warn!(error = ?e, "could not list the addresses of the host"),
+        }
+    }
+    ips.retain(is_local_address);
+    ips.sort();
+    ips.dedup();
+    ips
+}
+
+/// The records describing `services` of the host named `host` at `ips`
+fn service_records(services: &[AdvertisedService], host: &str, ips: &[IpAddr]) -> Vec<Record> {
+    let host_name = format!("{host}.local");
+    let mut records: Vec<Record> = ips
+        .iter()
+        .map(|ip| Record {
+            name: host_name.clone(),
+            ttl: HOST_TTL,
+            data: match ip {
+                IpAddr::V4(ip) => RecordData::A(*ip),
+                IpAddr::V6(ip) => RecordData::Aaaa(*ip),
+            },
+        })
+        .collect();
+
+    for (index, service) in services.iter().enumerate() {
+        let service_name = format!("{}.local", service.service);
+        // instances of the same service are told apart by their port
+        let instance = if services[..index]
+            .iter()
+            .any(|other| other.service == service.service)
+        {
+            format!("{host} ({}).{service_name}", service.addr.port())
+        } else {
+            format!("{host}.{service_name}")
+        };
+
+        let listing = Record {
+            name: SERVICES_NAME.to_owned(),
+            ttl: SERVICE_TTL,
+            data: RecordData::Ptr(service_name.clone()),
+        };
+        if !records.contains(&listing) {
+            records.push(listing);
+        }
+        records.extend([
+            Record {
+                name: service_name,
+                ttl: SERVICE_TTL,
+                data: RecordData::Ptr(instance.clone()),
+            },
+            Record {
+                name: instance.clone(),
+                ttl: HOST_TTL,
+                data: RecordData::Srv {
+                    priority: 0,
+                    weight: 0,
+                    port: service.addr.port(),
+                    target: host_name.clone(),
+                },
+            },
+            Record {
+                name: instance,
+                ttl: SERVICE_TTL,
+                data: RecordData::Txt(vec![]),
+            },
+        ]);
+    }
+
+    records
+}
+
+/// The answer to `query` from `records`, if any of them is asked for. The
+/// records a client needs to use the services it asked for are added to the
+/// answer (RFC 6763, section 12).
+fn respond(query: &Message, records: &[Record], legacy_unicast: bool) -> Option<Message> {
+    let mut answers: Vec<Record> = vec![];
+    for question in &query.questions {
+        for record in records {
+            if record.name.eq_ignore_ascii_case(&question.name)
+                && (question.record_type == TYPE_ANY
+                    || question.record_type == record.data.record_type())
+                && !answers.contains(record)
+            {
+                answers.push(record.clone());
+            }
+        }
+    }
+    if answers.is_empty() {
+        return None;
+    }
+
+    let mut additional: Vec<Record> = vec![];
+    let mut targets: Vec<String> = answers
+        .iter()
+        .filter_map(|record| record.data.target())
+        .map(str::to_owned)
+        .collect();
+    while let Some(target) = targets.pop() {
+        for record in records {
+            if record.name.eq_ignore_ascii_case(&target)
+                && !answers.contains(record)
+                && !additional.contains(record)
+            {
+                targets.extend(record.data.target().map(str::to_owned));
+                additional.push(record.clone());
+            }
+        }
+    }
+
+    let mut response = Message {
+        id: 0,
+        flags: FLAG_RESPONSE | FLAG_AUTHORITATIVE,
+        questions: vec![],
+        answers,
+        authority: vec![],
+        additional,
+    };
+    if legacy_unicast {
+        // such a client expects an ordinary DNS answer
+        response.id = query.id;
+        response.questions = query.questions.clone();
+        for record in response.answers.iter_mut().chain(&mut response.additional) {
+            record.ttl = record.ttl.min(LEGACY_UNICAST_TTL);
+        }
+    }
+    Some(response)
+}
+
+/// Open the multicast DNS port, shared with other responders on the host
+fn bind_mdns_socket() -> io::Result<UdpSocket> {
+    use nix::sys::socket::{
+        AddressFamily, SockFlag, SockType, SockaddrIn, bind, setsockopt, socket, sockopt,
+    };
+    use std::os::fd::AsRawFd;
+
+    let fd = socket(
+        AddressFamily::Inet,
+        SockType::Datagram,
+        SockFlag::empty(),
+        None,
+    )?;
+    setsockopt(&fd, sockopt::ReuseAddr, &true)?;
+    setsockopt(&fd, sockopt::ReusePort, &true)?;
+    let address = std::net::SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT);
+    bind(fd.as_raw_fd(), &SockaddrIn::from(address))?;
+
+    let socket = std::net::UdpSocket::from(fd);
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
+/// Receive the next multicast DNS message from a host on the local network,
+/// others are dropped (RFC 6762, section 11)
+async fn recv_message(
+    socket: &UdpSocket,
+    subnets: &[(Ipv4Addr, Ipv4Addr)],
+    buf: &mut [u8],
+) -> io::Result<(Message, SocketAddr)> {
+    loop {
+        let (length, sender) = socket.recv_from(buf).await?;
+        if !on_link(subnets, sender.ip()) {
+            debug!(%sender, "ignoring multicast DNS message from outside the local network");
+            continue;
+        }
+        if let Ok(message) = Message::deserialize(&buf[..length]) {
+            return Ok((message, sender));
+        }
+    }
+}
+
+/// Whether `records` claim `name` with other data than `ours`
+fn claims_other(records: &[Record], name: &str, ours: &[Record]) -> bool {
+    records
+        .iter()
+        .any(|record| record.name.eq_ignore_ascii_case(name) && !ours.contains(record))
+}
+
+/// The records for `name` in the order in which simultaneous probes are
+/// compared: by type, then by their data (RFC 6762, section 8.2)
+fn tiebreak_order(records: &[Record], name: &str) -> io::Result<Vec<(u16, Vec<u8>)>> {
+    let mut ordered = vec![];
+    for record in records {
+        if record.name.eq_ignore_ascii_case(name) {
+            let mut data = vec![];
+            write_record(
+                &mut data,
+                &Record {
+                    name: String::new(),
+                    ttl: 0,
+                    data: record.data.clone(),
+                },
+            )?;
+            // skip the empty name, type, class, time to live and length
+            ordered.push((record.data.record_type(), data.split_off(11)));
+        }
+    }
+    ordered.sort();
+    Ok(ordered)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ProbeOutcome {
+    /// The name is ours
+    Claimed,
+    /// Another host uses the name
+    Conflict,
+    /// Another host probes for the name at the same time, and goes first
+    Deferred,
+}
+
+/// Probe whether another host on the local network uses `host_name`, before
+/// claiming it with `ours` (RFC 6762, section 8.1)
+async fn probe(
+    socket: &UdpSocket,
+    subnets: &[(Ipv4Addr, Ipv4Addr)],
+    host_name: &str,
+    ours: &[Record],
+) -> io::Result<ProbeOutcome> {
+    let query = Message {
+        questions: vec![Question {
+            name: host_name.to_owned(),
+            record_type: TYPE_ANY,
+        }],
+        authority: ours.to_vec(),
+        ..Default::default()
+    }
+    .serialize()?;
+    let our_order = tiebreak_order(ours, host_name)?;
+
+    // hosts starting at the same moment do not probe in lockstep
+    let delay = rand::thread_rng().gen_range(0..PROBE_INTERVAL.as_millis() as u64);
+    tokio::time::sleep(Duration::from_millis(delay)).await;
+
+    let mut buf = vec![0; MDNS_MAX_MESSAGE];
+    for _ in 0..PROBES {
+        socket.send_to(&query, MDNS_ADDRESS).await?;
+
+        let deadline = Instant::now() + PROBE_INTERVAL;
+        while let Ok(received) = timeout_at(deadline, recv_message(socket, subnets, &mut buf)).await
+        {
+            let (message, _) = received?;
+            if message.flags & FLAG_RESPONSE != 0 {
+                if claims_other(&message.answers, host_name, ours) {
+                    return Ok(ProbeOutcome::Conflict);
+                }
+            } else if message
+                .questions
+                .iter()
+                .any(|question| question.name.eq_ignore_ascii_case(host_name))
+            {
+                // our own probes come back with the same records
+                let their_order = tiebreak_order(&message.authority, host_name)?;
+                if !their_order.is_empty() && their_order > our_order {
+                    return Ok(ProbeOutcome::Deferred);
+                }
+            }
+        }
+    }
+
+    Ok(ProbeOutcome::Claimed)
+}
+
+/// Announce `records`, and answer the multicast DNS queries for them, until
+/// another host claims `host_name`
+async fn respond_to_queries(
+    socket: &UdpSocket,
+    subnets: &[(Ipv4Addr, Ipv4Addr)],
+    host_name: &str,
+    records: &[Record],
+) -> io::Result<()> {
+    let announcement = Message {
+        flags: FLAG_RESPONSE | FLAG_AUTHORITATIVE,
+        answers: records.to_vec(),
+        ..Default::default()
+    }
+    .serialize()?;
+
+    // announced twice, a second apart (RFC 6762, section 8.3)
+    socket.send_to(&announcement, MDNS_ADDRESS).await?;
+    let announce_again = tokio::time::sleep(Duration::from_secs(1));
+    tokio::pin!(announce_again);
+    let mut announced = false;
+
+    let mut buf = vec![0; MDNS_MAX_MESSAGE];
+    loop {
+        let (message, sender) = tokio::select! {
+            () = &mut announce_again, if !announced => {
+                announced = true;
+                socket.send_to(&announcement, MDNS_ADDRESS).await?;
+                continue;
+            }
+            received = recv_message(socket, subnets, &mut buf) => received?,
+        };
+
+        if message.flags & FLAG_RESPONSE != 0 {
+            // our own answers come back with the same records
+            if claims_other(&message.answers, host_name, records) {
+                return Ok(());
+            }
+            continue;
+        }
+
+        let legacy_unicast = sender.port() != MDNS_PORT;
+        let Some(response) = respond(&message, records, legacy_unicast) else {
+            continue;
+        };
+        let destination = if legacy_unicast { sender } else { MDNS_ADDRESS };
+        if let Err(e) = socket.send_to(&response.serialize()?, destination).await {
+            debug!(error = ?e, %destination, "could not send multicast DNS answer");
+        }
+    }
+}
+
+/// Advertise `services` on the local network: claim a host name, announce the
+/// services, and answer the multicast DNS queries for them until the task is
+/// stopped. When another host claims the same name, the services move to a
+/// new one, with a number added (RFC 6762, section 9). The port is shared
+/// with other responders on the host.
+pub async fn advertise(services: Vec<AdvertisedService>) -> io::Result<()> {
+    let socket = bind_mdns_socket()?;
+    socket.join_multicast_v4(MDNS_GROUP, Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(255)?;
+
+    let ips = host_addresses(&services);
+    if ips.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            "no address on the local network to advertise",
+        ));
+    }
+
+    let base = hostname();
+    let mut host = base.clone();
+    let mut conflicts = 0;
+    loop {
+        if conflicts >= MAX_CONFLICTS {
+            tokio::time::sleep(PROBE_THROTTLE).await;
+        }
+
+        let records = service_records(&services, &host, &ips);
+        let host_name = format!("{host}.local");
+        let address_records: Vec<Record> = records
+            .iter()
+            .filter(|record| record.name == host_name)
+            .cloned()
+            .collect();
+        let subnets = local_subnets()?;
+
+        match probe(&socket, &subnets, &host_name, &address_records).await? {
+            ProbeOutcome::Claimed => {
+                debug!(%host_name, "advertising the servers with multicast DNS");
+                respond_to_queries(&socket, &subnets, &host_name, &records).await?;
+            }
+            ProbeOutcome::Deferred => {
+                tokio::time::sleep(PROBE_DEFER).await;
+                continue;
+            }
+            ProbeOutcome::Conflict => {}
+        }
+
+        conflicts += 1;
+        host = format!("{base}-{}", conflicts + 1);
+        warn!(%host_name, new_name = %host, "another host uses the multicast DNS host name");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn server(name: &str, priority: u16, weight: u16) -> DiscoveredServer {
+        DiscoveredServer {
+            name: name.to_owned(),
+            addr: "192.0.2.1:123".parse().unwrap(),
+            priority,
+            weight,
+        }
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let message = Message {
+            id: 0x1234,
+            flags: FLAG_RESPONSE,
+            questions: vec![Question {
+                name: "_ntp._udp.example.com".to_owned(),
+                record_type: TYPE_SRV,
+            }],
+            answers: vec![Record {
+                name: "_ntp._udp.example.com".to_owned(),
+                ttl: 300,
+                data: RecordData::Srv {
+                    priority: 10,
+                    weight: 5,
+                    port: 123,
+                    target: "time.example.com".to_owned(),
+                },
+            }],
+            authority: vec![Record {
+                name: "example.com".to_owned(),
+                ttl: 300,
+                data: RecordData::Ptr("ns.example.com".to_owned()),
+            }],
+            additional: vec![
+                Record {
+                    name: "time.example.com".to_owned(),
+                    ttl: 300,
+                    data: RecordData::A("192.0.2.1".parse().unwrap()),
+                },
+                Record {
+                    name: "time.example.com".to_owned(),
+                    ttl: 300,
+                    data: RecordData::Aaaa("2001:db8::1".parse().unwrap()),
+                },
+                Record {
+                    name: "time.example.com".to_owned(),
+                    ttl: 300,
+                    data: RecordData::Txt(vec!["path=/".to_owned()]),
+                },
+                Record {
+                    name: "time.example.com".to_owned(),
+                    ttl: 300,
+                    data: RecordData::Txt(vec![]),
+                },
+            ],
+        };
+
+        let bytes = message.serialize().unwrap();
+        assert_eq!(Message::deserialize(&bytes).unwrap(), message);
+        assert!(Message::deserialize(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_compressed_names() {
+        #[rustfmt::skip]
+        let bytes = [
+            0, 1, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0,
+            // _ntp._udp.local PTR, with the name of the instance pointing back
+            4, b'_', b'n', b't', b'p', 4, b'_', b'u', b'd', b'p', 5, b'l', b'o', b'c', b'a', b'l', 0,
+            0, 12, 0x80, 1, 0, 0, 0, 120, 0, 7,
+            4, b'h', b'o', b's', b't', 0xc0, 12,
+        ];
+
+        let message = Message::deserialize(&bytes).unwrap();
+        assert_eq!(message.answers.len(), 1);
+        assert_eq!(message.answers[0].name, "_ntp._udp.local");
+        assert_eq!(
+            message.answers[0].data,
+            RecordData::Ptr("host._ntp._udp.local".to_owned())
+        );
+
+        // a pointer to itself never ends
+        let mut looping = bytes;
+        looping[12..14].copy_from_slice(&[0xc0, 12]);
+        assert!(Message::deserialize(&looping).is_err());
+    }
+
+    #[test]
+    fn test_resolv_conf() {
+        let config = ResolverConfig::parse(
+            "# comment\nnameserver 127.0.0.53\nnameserver ::1\nnameserver fe80::1%eth0\n\
+             domain example.org\nsearch example.com. lan\noptions edns0\n",
+        );
+        assert_eq!(
+            config,
+            ResolverConfig {
+                nameservers: vec![
+                    "127.0.0.53:53".parse().unwrap(),
+                    "[::1]:53".parse().unwrap()
+                ],
+                search: vec!["example.com".to_owned(), "lan".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_order_servers() {
+        let servers = vec![
+            server("backup", 20, 0),
+            server("a", 10, 60),
+            server("b", 10, 40),
+            server("c", 10, 0),
+        ];
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let ordered = order_servers(servers.clone(), &mut rng);
+            assert_eq!(ordered.len(), 4);
+            assert_eq!(ordered[3].name, "backup");
+        }
+
+        // with a weight of zero, a server is only rarely picked first
+        let first_c = (0..1000)
+            .filter(|_| order_servers(servers.clone(), &mut rng)[0].name == "c")
+            .count();
+        assert!(first_c < 50);
+    }
+
+    #[tokio::test]
+    async fn test_servers_of() {
+        let records = vec![
+            Record {
+                name: "_ntp._udp.example.com".to_owned(),
+                ttl: 300,
+                data: RecordData::Srv {
+                    priority: 1,
+                    weight: 1,
+                    port: 1123,
+                    target: "Time.example.com".to_owned(),
+                },
+            },
+            Record {
+                name: "_ntp._udp.example.com".to_owned(),
+                ttl: 300,
+                data: RecordData::Srv {
+                    priority: 1,
+                    weight: 1,
+                    port: 123,
+                    target: String::new(),
+                },
+            },
+            Record {
+                name: "time.example.com".to_owned(),
+                ttl: 300,
+                data: RecordData::A("192.0.2.1".parse().unwrap()),
+            },
+        ];
+
+        assert_eq!(
+            servers_of(&[records]).await,
+            vec![DiscoveredServer {
+                name: "Time.example.com".to_owned(),
+                addr: "192.0.2.1:1123".parse().unwrap(),
+                priority: 1,
+                weight: 1,
+            }]
+        );
+
+        // the addresses in one answer do not count for the servers of another
+        let answers = [
+            vec![Record {
+                name: "_ntp._udp.local".to_owned(),
+                ttl: 120,
+                data: RecordData::Srv {
+                    priority: 0,
+                    weight: 0,
+                    port: 123,
+                    target: "localhost".to_owned(),
+                },
+            }],
+            vec![Record {
+                name: "localhost".to_owned(),
+                ttl: 120,
+                data: RecordData::A("192.0.2.1".parse().unwrap()),
+            }],
+        ];
+        let servers = servers_of(&answers).await;
+        assert!(!servers.is_empty());
+        assert!(servers.iter().all(|server| server.addr.ip().is_loopback()));
+    }
+
+    #[test]
+    fn test_on_link() {
+        let subnets = [(
+            "192.0.2.10".parse().unwrap(),
+            "255.255.255.0".parse().unwrap(),
+        )];
+        assert!(on_link(&subnets, "192.0.2.200".parse().unwrap()));
+        assert!(on_link(&subnets, "169.254.1.1".parse().unwrap()));
+        assert!(!on_link(&subnets, "198.51.100.1".parse().unwrap()));
+        assert!(!on_link(&subnets, "2001:db8::1".parse().unwrap()));
+    }
+
+    fn advertised(service: &'static str, addr: &str) -> AdvertisedService {
+        AdvertisedService {
+            service,
+            addr: addr.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_service_records() {
+        let services = [
+            advertised(NTP_SERVICE, "0.0.0.0:123"),
+            advertised(NTP_SERVICE, "0.0.0.0:1123"),
+        ];
+        let records = service_records(&services, "clock", &["192.0.2.1".parse().unwrap()]);
+
+        assert!(records.contains(&Record {
+            name: "clock.local".to_owned(),
+            ttl: HOST_TTL,
+            data: RecordData::A("192.0.2.1".parse().unwrap()),
+        }));
+        assert_eq!(
+            records
+                .iter()
+                .filter(|record| record.name == SERVICES_NAME)
+                .count(),
+            1
+        );
+
+        let instances: Vec<_> = records
+            .iter()
+            .filter(|record| record.name == "_ntp._udp.local")
+            .filter_map(|record| record.data.target())
+            .collect();
+        assert_eq!(
+            instances,
+            ["clock._ntp._udp.local", "clock (1123)._ntp._udp.local"]
+        );
+        assert!(records.contains(&Record {
+            name: "clock (1123)._ntp._udp.local".to_owned(),
+            ttl: HOST_TTL,
+            data: RecordData::Srv {
+                priority: 0,
+                weight: 0,
+                port: 1123,
+                target: "clock.local".to_owned(),
+            },
+        }));
+    }
+
+    #[test]
+    fn test_tiebreak_order() {
+        let record = |ip: &str| Record {
+            name: "clock.local".to_owned(),
+            ttl: HOST_TTL,
+            data: RecordData::A(ip.parse().unwrap()),
+        };
+        let ours = [record("192.0.2.20")];
+        let order = |records: &[Record]| tiebreak_order(records, "Clock.local").unwrap();
+
+        assert!(order(&[record("192.0.2.100")]) > order(&ours));
+        assert!(order(&[record("192.0.2.3")]) < order(&ours));
+        // with the same first record, more records go first
+        assert!(order(&[record("192.0.2.20"), record("192.0.2.21")]) > order(&ours));
+        assert_eq!(order(&ours), order(&ours));
+    }
+
+    #[test]
+    fn test_local_addresses() {
+        for ip in [
+            "192.168.1.2",
+            "10.0.0.1",
+            "169.254.3.4",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(is_local_address(&ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["192.0.2.1", "8.8.8.8", "2001:db8::1", "127.0.0.1", "::1"] {
+            assert!(!is_local_address(&ip.parse().unwrap()), "{ip}");
+        }
+
+        let services = [
+            advertised(NTP_SERVICE, "192.168.1.2:123"),
+            advertised(NTP_SERVICE, "198.51.100.1:123"),
+        ];
+        assert_eq!(
+            host_addresses(&services),
+            ["192.168.1.2".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_respond() {
+        let services = [advertised(NTP_SERVICE, "192.0.2.1:123")];
+        let records = service_records(&services, "clock", &["192.0.2.1".parse().unwrap()]);
+        let query = Message {
+            id: 0x1234,
+            questions: vec![Question {
+                name: "_NTP._udp.local".to_owned(),
+                record_type: TYPE_PTR,
+            }],
+            ..Default::default()
+        };
+
+        let response = respond(&query, &records, true).unwrap();
+        assert_eq!(response.id, 0x1234);
+        assert_eq!(response.questions, query.questions);
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            response.answers[0].data,
+            RecordData::Ptr("clock._ntp._udp.local".to_owned())
+        );
+        // the service and address records of the instance come along
+        let mut additional: Vec<_> = response
+            .additional
+            .iter()
+            .map(|record| record.data.record_type())
+            .collect();
+        additional.sort();
+        assert_eq!(additional, [TYPE_A, TYPE_TXT, TYPE_SRV]);
+        assert!(
+            response
+                .answers
+                .iter()
+                .chain(&response.additional)
+                .all(|record| record.ttl <= LEGACY_UNICAST_TTL)
+        );
+
+        // an ordinary multicast DNS answer does not repeat the question
+        let response = respond(&query, &records, false).unwrap();
+        assert_eq!(response.id, 0);
+        assert!(response.questions.is_empty());
+        assert_eq!(response.answers[0].ttl, SERVICE_TTL);
+
+        let query = Message {
+            questions: vec![Question {
+                name: "_ntp._udp.example.com".to_owned(),
+                record_type: TYPE_PTR,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(respond(&query, &records, false), None);
+    }
+}
@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use super::state_format::DRIFT_FILE;
+
+/// How often the frequency estimate is written to the drift file
+pub const DRIFT_FILE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+// Anything beyond this is not a plausible frequency error for a working clock,
+// and more likely a corrupted file.
+pub const MAX_FREQUENCY_PPM: f64 = 500.0;
+
+/// Read the frequency offset (as a fraction, not ppm) stored in a drift file
+pub fn load(path: &Path) -> std::io::Result<f64> {
+    let contents = DRIFT_FILE.read(path)?;
+    let ppm: f64 = contents.trim().parse().map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "drift file does not contain a frequency",
+        )
+    })?;
+
+    if !ppm.is_finite() || ppm.abs() > MAX_FREQUENCY_PPM {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("implausible frequency in drift file: {ppm}ppm"),
+        ));
+    }
+
+    Ok(ppm * 1e-6)
+}
+
+/// Write the frequency offset to the drift file, replacing it atomically so
+/// a crash halfway through never leaves a truncated file behind
+pub fn store(path: &Path, frequency: f64) -> std::io::Result<()> {
+    DRIFT_FILE.write(path, &format!("{:.6}\n", frequency * 1e6))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    #[test]
+    fn test_drift_file_roundtrip() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join(format!("ntp-test-drift-{}", alloc_port()));
+
+        store(&path, 12.5e-6).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# ntpd-rs drift-file format 1\n12.500000\n"
+        );
+        assert!((load(&path).unwrap() - 12.5e-6).abs() < 1e-12);
+
+        // drift files written before the format was versioned
+        std::fs::write(&path, "-3.250000\n").unwrap();
+        assert!((load(&path).unwrap() + 3.25e-6).abs() < 1e-12);
+
+        std::fs::write(&path, "not a number\n").unwrap();
+        assert!(load(&path).is_err());
+
+        std::fs::write(&path, "-1000\n").unwrap();
+        assert!(load(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(load(&path).is_err());
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    fmt::Display,
+    io::SeekFrom,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ntp_proto::{
+    Measurement, NtpClock, NtpDuration, NtpInstant, NtpLeapIndicator, NtpTimestamp, OneWaySource,
+    OneWaySourceSnapshot, OneWaySourceUpdate, ReferenceId, SourceController, SystemSourceUpdate,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+    net::unix::pipe,
+    sync::mpsc,
+};
+use tracing::{Instrument, Span, debug, error, instrument, warn};
+
+use crate::daemon::{exitcode, ntp_source::MsgForSystem, util::EPOCH_OFFSET};
+
+use super::{ntp_source::SourceChannels, spawn::SourceId};
+
+/// How often a regular file is checked for new samples
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before opening a file that could not be read again
+const REOPEN_DELAY: Duration = Duration::from_secs(10);
+
+/// Samples read more than this long after they were taken are ignored, as
+/// they no longer describe the clock (s)
+const MAX_SAMPLE_AGE: f64 = 2.0;
+
+/// Lines read from the file that are not yet processed
+const LINE_BUFFER_SIZE: usize = 16;
+
+/// A single line written by the reference:
+/// `<unix time> <offset> [none|insert|delete]`
+#[derive(Debug, PartialEq)]
+struct FileSample {
+    /// Time of the system clock at which the offset was measured
+    time: NtpTimestamp,
+    /// How far the reference is ahead of the system clock (s)
+    offset: f64,
+    leap: NtpLeapIndicator,
+}
+
+#[derive(Debug, PartialEq)]
+enum SampleError {
+    InvalidTime(String),
+    MissingOffset,
+    InvalidOffset(String),
+    InvalidLeap(String),
+    TrailingData(String),
+}
+
+impl Display for SampleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SampleError::InvalidTime(t) => write!(f, "Invalid time {t}"),
+            SampleError::MissingOffset => f.write_str("Missing offset"),
+            SampleError::InvalidOffset(o) => write!(f, "Invalid offset {o}"),
+            SampleError::InvalidLeap(l) => write!(f, "Invalid leap indicator {l}"),
+            SampleError::TrailingData(d) => write!(f, "Unexpected {d} after the sample"),
+        }
+    }
+}
+
+/// Parse a line of the file, which is either a sample or empty apart from a
+/// comment starting with `#`
+fn parse_sample(line: &str) -> Result<Option<FileSample>, SampleError> {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut fields = line.split_whitespace();
+    let Some(time) = fields.next() else {
+        return Ok(None);
+    };
+
+    let time = parse_unix_time(time).ok_or_else(|| SampleError::InvalidTime(time.to_owned()))?;
+    let offset = fields.next().ok_or(SampleError::MissingOffset)?;
+    let offset = offset
+        .parse::<f64>()
+        .ok()
+        .filter(|offset| offset.is_finite())
+        .ok_or_else(|| SampleError::InvalidOffset(offset.to_owned()))?;
+    let leap = match fields.next() {
+        None | Some("none") => NtpLeapIndicator::NoWarning,
+        Some("insert") => NtpLeapIndicator::Leap61,
+        Some("delete") => NtpLeapIndicator::Leap59,
+        Some(leap) => return Err(SampleError::InvalidLeap(leap.to_owned())),
+    };
+    if let Some(field) = fields.next() {
+        return Err(SampleError::TrailingData(field.to_owned()));
+    }
+
+    Ok(Some(FileSample { time, offset, leap }))
+}
+
+/// Seconds since the Unix epoch, with a fraction of up to nanoseconds
+fn parse_unix_time(time: &str) -> Option<NtpTimestamp> {
+    let (seconds, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let seconds: u64 = seconds.parse().ok()?;
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let fraction = &fraction[..fraction.len().min(9)];
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        format!("{fraction:0<9}").parse().ok()?
+    };
+
+    Some(NtpTimestamp::from_seconds_nanos_since_ntp_era(
+        EPOCH_OFFSET.wrapping_add(seconds as u32),
+        nanos,
+    ))
+}
+
+/// Send the lines written to the file or FIFO at `path` after it was opened,
+/// until `lines` is closed
+async fn read_lines(path: PathBuf, lines: mpsc::Sender<String>) {
+    while !lines.is_closed() {
+        if let Err(e) = tail(&path, &lines).await {
+            warn!(error = ?e, path = %path.display(), "Could not read file source, retrying");
+            tokio::time::sleep(REOPEN_DELAY).await;
+        }
+    }
+}
+
+async fn tail(path: &Path, lines: &mpsc::Sender<String>) -> std::io::Result<()> {
+    if tokio::fs::metadata(path).await?.file_type().is_fifo() {
+        tail_fifo(path, lines).await
+    } else {
+        tail_file(path, lines).await
+    }
+}
+
+async fn tail_file(path: &Path, lines: &mpsc::Sender<String>) -> std::io::Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(SeekFrom::End(0)).await?;
+    debug!("Opened file source");
+
+    let mut reader = BufReader::new(file);
+    let mut line = vec![];
+    loop {
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            if lines.is_closed() {
+                return Ok(());
+            }
+            tokio::time::sleep(FILE_POLL_INTERVAL).await;
+
+            // a truncated file is read again from the start
+            let position = reader.stream_position().await?;
+            if reader.get_ref().metadata().await?.len() < position {
+                reader.seek(SeekFrom::Start(0)).await?;
+                line.clear();
+            }
+            continue;
+        }
+
+        // the rest of the line may not have been written yet
+        if line.ends_with(b"\n") && !send_line(&mut line, lines).await {
+            return Ok(());
+        }
+    }
+}
+
+async fn tail_fifo(path: &Path, lines: &mpsc::Sender<String>) -> std::io::Result<()> {
+    let mut options = pipe::OpenOptions::new();
+    // holding the write end as well, reading does not end when the writer
+    // goes away, but waits for the next one
+    #[cfg(target_os = "linux")]
+    options.read_write(true);
+    let receiver = options.open_receiver(path)?;
+    debug!("Opened FIFO file source");
+
+    let mut reader = BufReader::new(receiver);
+    let mut line = vec![];
+    loop {
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            // all writers are gone, wait for the next one
+            if lines.is_closed() {
+                return Ok(());
+            }
+            tokio::time::sleep(FILE_POLL_INTERVAL).await;
+            continue;
+        }
+
+        if line.ends_with(b"\n") && !send_line(&mut line, lines).await {
+            return Ok(());
+        }
+    }
+}
+
+/// Pass on a complete line, returns false when nobody is listening anymore
+async fn send_line(line: &mut Vec<u8>, lines: &mpsc::Sender<String>) -> bool {
+    let text = String::from_utf8_lossy(line).into_owned();
+    line.clear();
+    lines.send(text).await.is_ok()
+}
+
+pub(crate) struct FileSourceTask<
+    C: 'static + NtpClock + Send,
+    Controller: SourceController<MeasurementDelay = ()>,
+> {
+    index: SourceId,
+    lines: mpsc::Receiver<String>,
+    clock: C,
+    path: PathBuf,
+    channels: SourceChannels<Controller::ControllerMessage, Controller::SourceMessage>,
+    source: OneWaySource<Controller>,
+}
+
+impl<C, Controller: SourceController<MeasurementDelay = ()>> FileSourceTask<C, Controller>
+where
+    C: 'static + NtpClock + Send + Sync,
+{
+    async fn run(&mut self) {
+        loop {
+            enum SelectResult<Controller: SourceController> {
+                Line(Option<String>),
+                SystemUpdate(
+                    Result<
+                        SystemSourceUpdate<Controller::ControllerMessage>,
+                        tokio::sync::broadcast::error::RecvError,
+                    >,
+                ),
+            }
+
+            let selected: SelectResult<Controller> = tokio::select! {
+                line = self.lines.recv() => {
+                    SelectResult::Line(line)
+                },
+                result = self.channels.system_update_receiver.recv() => {
+                    SelectResult::SystemUpdate(result)
+                }
+            };
+
+            match selected {
+                SelectResult::Line(Some(line)) => match parse_sample(&line) {
+                    Ok(Some(sample)) => self.handle_sample(sample).await,
+                    Ok(None) => {}
+                    Err(e) => warn!("Ignored invalid sample: {}", e),
+                },
+                // the reader only stops once this task is gone
+                SelectResult::Line(None) => return,
+                SelectResult::SystemUpdate(result) => match result {
+                    Ok(update) => {
+                        self.source.handle_message(update.message);
+                    }
+                    Err(e) => {
+                        error!("Error receiving system update: {:?}", e)
+                    }
+                },
+            }
+        }
+    }
+
+    async fn handle_sample(&mut self, sample: FileSample) {
+        debug!("received {:?}", sample);
+
+        let now = match self.clock.now() {
+            Ok(time) => time,
+            Err(e) => {
+                error!(error = ?e, "There was an error retrieving the current time");
+                std::process::exit(exitcode::NOPERM);
+            }
+        };
+        let age = (now - sample.time).to_seconds();
+        if !(0.0..=MAX_SAMPLE_AGE).contains(&age) {
+            warn!(
+                age,
+                "Ignored sample that was not taken just before it was read"
+            );
+            return;
+        }
+
+        let measurement = Measurement {
+            delay: (),
+            offset: NtpDuration::from_seconds(sample.offset),
+            localtime: sample.time,
+            monotime: NtpInstant::now(),
+
+            stratum: 0,
+            root_delay: NtpDuration::ZERO,
+            root_dispersion: NtpDuration::ZERO,
+            leap: sample.leap,
+            precision: 0,
+            after_retransmission: false,
+        };
+
+        let controller_message = self.source.handle_measurement(measurement);
+
+        let update = OneWaySourceUpdate {
+            snapshot: OneWaySourceSnapshot {
+                source_id: ReferenceId::FILE,
+                stratum: 0,
+            },
+            message: controller_message,
+        };
+        self.channels
+            .msg_for_system_sender
+            .send(MsgForSystem::OneWaySourceUpdate(self.index, update))
+            .await
+            .ok();
+
+        self.channels
+            .observations
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(
+                self.index,
+                self.source.observe(
+                    "File source".to_string(),
+                    self.path.display().to_string(),
+                    self.index,
+                ),
+            );
+    }
+
+    #[instrument(level = tracing::Level::ERROR, name = "File Source", skip(clock, channels, source))]
+    pub fn spawn(
+        index: SourceId,
+        path: PathBuf,
+        clock: C,
+        channels: SourceChannels<Controller::ControllerMessage, Controller::SourceMessage>,
+        source: OneWaySource<Controller>,
+    ) -> tokio::task::JoinHandle<()> {
+        let (lines_sender, lines) = mpsc::channel(LINE_BUFFER_SIZE);
+        tokio::spawn(
+            (async move {
+                let reader = read_lines(path.clone(), lines_sender);
+                let mut process = FileSourceTask {
+                    index,
+                    lines,
+                    clock,
+                    path,
+                    channels,
+                    source,
+                };
+
+                // the reader is part of this task, so it stops with the source
+                tokio::select! {
+                    () = process.run() => {}
+                    () = reader => {}
+                }
+            })
+            .instrument(Span::current()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_sample() {
+        let time = |seconds: u32, nanos: u32| {
+            NtpTimestamp::from_seconds_nanos_since_ntp_era(EPOCH_OFFSET + seconds, nanos)
+        };
+
+        assert_eq!(
+            parse_sample("1700000000.25 -1.5e-6\n"),
+            Ok(Some(FileSample {
+                time: time(1700000000, 250_000_000),
+                offset: -1.5e-6,
+                leap: NtpLeapIndicator::NoWarning,
+            }))
+        );
+        assert_eq!(
+            parse_sample("1700000000.1234567891 0.001 insert # leap second at midnight"),
+            Ok(Some(FileSample {
+                time: time(1700000000, 123_456_789),
+                offset: 0.001,
+                leap: NtpLeapIndicator::Leap61,
+            }))
+        );
+        assert_eq!(
+            parse_sample("1700000000 0 delete").unwrap().unwrap().leap,
+            NtpLeapIndicator::Leap59
+        );
+
+        assert_eq!(parse_sample(""), Ok(None));
+        assert_eq!(parse_sample("  # a comment\n"), Ok(None));
+
+        assert!(matches!(
+            parse_sample("1700000000.-5 0"),
+            Err(SampleError::InvalidTime(_))
+        ));
+        assert!(matches!(
+            parse_sample("-1 0"),
+            Err(SampleError::InvalidTime(_))
+        ));
+        assert_eq!(parse_sample("1700000000"), Err(SampleError::MissingOffset));
+        assert!(matches!(
+            parse_sample("1700000000 NaN"),
+            Err(SampleError::InvalidOffset(_))
+        ));
+        assert!(matches!(
+            parse_sample("1700000000 0 soon"),
+            Err(SampleError::InvalidLeap(_))
+        ));
+        assert!(matches!(
+            parse_sample("1700000000 0 none 1"),
+            Err(SampleError::TrailingData(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_lines() {
+        let path = std::env::temp_dir().join(format!("ntp-test-file-source-{}", alloc_port()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(file, "1 0 # written before the file is opened").unwrap();
+
+        let (lines_sender, mut lines) = mpsc::channel(LINE_BUFFER_SIZE);
+        let reader = tokio::spawn(read_lines(path.clone(), lines_sender));
+
+        // only lines written after the file is opened are read, and a line is
+        // only passed on once it is complete
+        let read = async {
+            loop {
+                write!(file, "2 0").unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                writeln!(file, ".5").unwrap();
+                if let Ok(line) = tokio::time::timeout(2 * FILE_POLL_INTERVAL, lines.recv()).await
+                    && line.unwrap() == "2 0.5\n"
+                {
+                    break;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), read)
+            .await
+            .unwrap();
+
+        drop(lines);
+        reader.abort();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_fifo() {
+        let path = std::env::temp_dir().join(format!("ntp-test-fifo-source-{}", alloc_port()));
+        let status = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let (lines_sender, mut lines) = mpsc::channel(LINE_BUFFER_SIZE);
+        let reader = tokio::spawn(read_lines(path.clone(), lines_sender));
+
+        // opening the FIFO for writing blocks until the reader has opened it,
+        // and the reader keeps reading after a writer goes away
+        let writer_path = path.clone();
+        let writer = tokio::task::spawn_blocking(move || {
+            for line in ["1 0", "2 0"] {
+                let mut fifo = std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&writer_path)
+                    .unwrap();
+                writeln!(fifo, "{line}").unwrap();
+            }
+        });
+
+        for expected in ["1 0\n", "2 0\n"] {
+            let line = tokio::time::timeout(Duration::from_secs(10), lines.recv())
+                .await
+                .unwrap();
+            assert_eq!(line.unwrap(), expected);
+        }
+        writer.await.unwrap();
+
+        drop(lines);
+        reader.abort();
+        std::fs::remove_file(path).unwrap();
+    }
+}
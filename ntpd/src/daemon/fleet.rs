@@ -0,0 +1,432 @@
+//! Sources managed centrally for a fleet of daemons.
+//!
+//! The daemon fetches a list of sources from an HTTPS server together with a
+//! detached minisign signature, and only uses a list signed with the
+//! configured Ed25519 key. The list is fetched again periodically, and is
+//! kept in a cache file when configured, so the sources are known at startup
+//! even when the server cannot be reached. A list signed before the one in
+//! use, according to the timestamp in the trusted comment of its signature,
+//! is refused, so an attacker cannot bring back an old list.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use minisign_verify::Signature;
+use ntp_proto::tls_utils::{self, ServerName, TLS13};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::watch,
+    task::JoinHandle,
+};
+use tokio_rustls::TlsConnector;
+use tracing::{debug, info, warn};
+
+use super::config::{FleetConfig, HttpsUrl, MinisignPublicKey, NtpSourceConfig};
+
+/// Largest list of sources or signature that is accepted
+const MAX_RESPONSE_SIZE: usize = 1 << 20;
+
+/// Time allowed for fetching the list and its signature
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn invalid(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+/// Read the list of sources from the cache file, if it is there and signed
+/// with the configured key
+pub async fn load_cache(config: &FleetConfig) -> Vec<NtpSourceConfig> {
+    let Some(path) = &config.cache_path else {
+        return vec![];
+    };
+
+    match read_cache(path, config).await {
+        Ok((sources, _)) => {
+            debug!(
+                ?path,
+                sources = sources.len(),
+                "loaded cached fleet sources"
+            );
+            sources
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+        Err(e) => {
+            warn!(?path, error = %e, "ignoring the cached fleet sources");
+            vec![]
+        }
+    }
+}
+
+/// The cached list of sources and the time it was signed
+async fn read_cache(
+    path: &Path,
+    config: &FleetConfig,
+) -> std::io::Result<(Vec<NtpSourceConfig>, u64)> {
+    let list = tokio::fs::read(path).await?;
+    let signature = tokio::fs::read_to_string(signature_path(path)).await?;
+    let signed_at = verify(&config.public_key, &list, &signature)?;
+    Ok((parse_sources(&list)?, signed_at))
+}
+
+/// Store the list and its signature in the cache. Each file is replaced
+/// atomically, a crash in between leaves a signature that does not match the
+/// list, so the cache is ignored instead of trusted.
+async fn write_cache(path: &Path, list: &[u8], signature: &str) -> std::io::Result<()> {
+    write_atomic(path, list).await?;
+    write_atomic(&signature_path(path), signature.as_bytes()).await
+}
+
+async fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".tmp");
+
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+
+    tokio::fs::rename(tmp, path).await
+}
+
+/// Fetch the list of sources every refresh interval, and send it whenever it
+/// changed and is signed with the configured key
+pub fn spawn(config: FleetConfig, sources: watch::Sender<Vec<NtpSourceConfig>>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let connector = match tls_connector() {
+            Ok(connector) => connector,
+            Err(e) => {
+                warn!(error = %e, "could not set up TLS to fetch the fleet sources");
+                return;
+            }
+        };
+
+        // lists signed before the cached one are refused, also after a restart
+        let mut newest = match &config.cache_path {
+            Some(path) => read_cache(path, &config)
+                .await
+                .ok()
+                .map(|(_, signed_at)| signed_at),
+            None => None,
+        };
+
+        let mut interval = tokio::time::interval(Duration::from_secs(config.refresh_interval));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+
+            let fetched = tokio::time::timeout(FETCH_TIMEOUT, fetch_verified(&connector, &config))
+                .await
+                .unwrap_or_else(|_| Err(std::io::Error::from(ErrorKind::TimedOut)))
+                .and_then(|(list, signature, signed_at)| {
+                    check_newer(signed_at, newest)?;
+                    Ok((parse_sources(&list)?, list, signature, signed_at))
+                });
+            let (new_sources, list, signature, signed_at) = match fetched {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    warn!(url = %config.url, error = %e, "could not fetch the fleet sources, keeping the current ones");
+                    continue;
+                }
+            };
+            newest = Some(signed_at);
+
+            if let Some(path) = &config.cache_path
+                && let Err(e) = write_cache(path, &list, &signature).await
+            {
+                warn!(?path, error = %e, "could not cache the fleet sources");
+            }
+
+            let changed = sources.send_if_modified(|current| {
+                let changed = *current != new_sources;
+                *current = new_sources;
+                changed
+            });
+            if changed {
+                info!(url = %config.url, "fetched changed fleet sources");
+            }
+        }
+    })
+}
+
+/// Refuse a list signed before the newest one seen, which may be replayed
+/// to bring back sources that were removed
+fn check_newer(signed_at: u64, newest: Option<u64>) -> std::io::Result<()> {
+    match newest {
+        Some(newest) if signed_at < newest => Err(invalid(format!(
+            "list was signed at {signed_at}, before the current one signed at {newest}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The signature of the cached list is kept next to it
+fn signature_path(path: &Path) -> PathBuf {
+    let mut signature = path.as_os_str().to_owned();
+    signature.push(".minisig");
+    signature.into()
+}
+
+/// Parse a list of sources, written like the `[[source]]` sections of the
+/// configuration. Only sources reached over the network can be listed.
+fn parse_sources(list: &[u8]) -> std::io::Result<Vec<NtpSourceConfig>> {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct SourceList {
+        #[serde(rename = "source", default)]
+        sources: Vec<NtpSourceConfig>,
+    }
+
+    let list = std::str::from_utf8(list).map_err(|_| invalid("list of sources is not UTF-8"))?;
+    let list: SourceList = toml::from_str(list).map_err(|e| invalid(e.to_string()))?;
+    for source in &list.sources {
+        if !matches!(
+            source,
+            NtpSourceConfig::Standard(_)
+                | NtpSourceConfig::Nts(_)
+                | NtpSourceConfig::Pool(_)
+                | NtpSourceConfig::NtsPool(_)
+                | NtpSourceConfig::Service(_)
+        ) {
+            return Err(invalid(
+                "only sources reached over the network can be listed",
+            ));
+        }
+    }
+    Ok(list.sources)
+}
+
+/// Fetch the list of sources and its signature, and verify the signature.
+/// Also returns the time the list was signed.
+async fn fetch_verified(
+    connector: &TlsConnector,
+    config: &FleetConfig,
+) -> std::io::Result<(Vec<u8>, String, u64)> {
+    let list = fetch(connector, &config.url).await?;
+    let signature = fetch(connector, &config.signature_url()).await?;
+    let signature = String::from_utf8(signature).map_err(|_| invalid("signature is not text"))?;
+    let signed_at = verify(&config.public_key, &list, &signature)?;
+    Ok((list, signature, signed_at))
+}
+
+fn tls_connector() -> std::io::Result<TlsConnector> {
+    let builder = tls_utils::client_config_builder_with_protocol_versions(&[&TLS13]);
+    let verifier = tls_utils::PlatformVerifier::new_with_extra_roots(std::iter::empty())
+        .map_err(std::io::Error::other)?
+        .with_provider(builder.crypto_provider().clone());
+    let config = builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Fetch `url` with an HTTP/1.0 GET request, which servers answer without
+/// chunked encoding. Redirects are not followed.
+async fn fetch(connector: &TlsConnector, url: &HttpsUrl) -> std::io::Result<Vec<u8>> {
+    let io = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    let server_name = ServerName::try_from(url.host.clone()).map_err(std::io::Error::other)?;
+    let mut io = connector.connect(server_name, io).await?;
+
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: ntpd-rs/{}\r\n\r\n",
+        url.path,
+        url.authority(),
+        env!("CARGO_PKG_VERSION"),
+    );
+    io.write_all(request.as_bytes()).await?;
+    io.flush().await?;
+
+    // Many servers close the connection without a TLS close_notify. A
+    // truncated list is caught by the signature.
+    let mut response = vec![];
+    match (&mut io)
+        .take(MAX_RESPONSE_SIZE as u64 + 1)
+        .read_to_end(&mut response)
+        .await
+    {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => {}
+        Err(e) => return Err(e),
+    }
+    if response.len() > MAX_RESPONSE_SIZE {
+        return Err(invalid(format!("response from {url} is too large")));
+    }
+
+    parse_response(&response).map(<[u8]>::to_vec)
+}
+
+/// The body of an HTTP response, when its status is 200
+fn parse_response(response: &[u8]) -> std::io::Result<&[u8]> {
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("incomplete HTTP response"))?;
+    let head =
+        std::str::from_utf8(&response[..end]).map_err(|_| invalid("invalid HTTP response"))?;
+    let body = &response[end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("200") {
+        return Err(invalid(format!("server responded with {status}")));
+    }
+
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            let length: usize = value
+                .trim()
+                .parse()
+                .map_err(|_| invalid("invalid Content-Length"))?;
+            return body
+                .get(..length)
+                .ok_or_else(|| invalid("truncated HTTP response"));
+        }
+    }
+    Ok(body)
+}
+
+/// Verify a minisign signature over `data`, both the signature itself and
+/// the global signature over its trusted comment, and return the time it was
+/// signed
+fn verify(public_key: &MinisignPublicKey, data: &[u8], signature: &str) -> std::io::Result<u64> {
+    let signature =
+        Signature::decode(signature).map_err(|e| invalid(format!("invalid signature: {e}")))?;
+    // the legacy variant signs the data itself instead of its hash
+    public_key
+        .0
+        .verify(data, &signature, true)
+        .map_err(|e| invalid(format!("signature does not match: {e}")))?;
+    signed_at(signature.trusted_comment())
+        .ok_or_else(|| invalid("trusted comment of the signature has no timestamp"))
+}
+
+/// The Unix time in the trusted comment minisign writes by default, such as
+/// `timestamp:1760000000\tfile:sources.toml\thashed`
+fn signed_at(trusted_comment: &str) -> Option<u64> {
+    trusted_comment
+        .split('\t')
+        .find_map(|field| field.strip_prefix("timestamp:"))?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::alloc_port;
+
+    const LIST: &[u8] = b"[[source]]\nmode = \"server\"\naddress = \"ntp.example.com\"\n";
+
+    // made with the Ed25519 key with secret 00 01 .. 1f
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RURaHg8rPE1ufwsi0fp9IFokhYE+ui1RP1LN0j2OiNAEV+CywVzQ8NTzKA4Kk+Zz2bVo6sgHQmBOuTbL5QdYhL98d4lnIgSVjw0=
+trusted comment: timestamp:1760000000\tfile:sources.toml\thashed
+f5NQeXYQWzwyneMNuI3RshAzQvrPhtocjwc17p8G12a6qYBUUnuhNELzw80TwEDxAPp+3PGMwd3o4A49LoooAw==
+";
+
+    fn public_key() -> MinisignPublicKey {
+        "RWRaHg8rPE1ufwOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4"
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_verify() {
+        assert_eq!(verify(&public_key(), LIST, SIGNATURE).unwrap(), 1760000000);
+
+        // the legacy variant, signing the data itself
+        let legacy = "untrusted comment: legacy
+RWRaHg8rPE1uf9vS4M9oDHSVvdtoyutGrxixJygBTrTf1+IFnaPIpxhB5En7+uH3vhX02PqFazGaUZf07Mskcc8ilK8RpitNVQY=
+trusted comment: timestamp:1750000000\tfile:sources.toml
+taRbcYyVyLl1YP4E+se/bqxHqPgdTeeHuVc47yOsmLMy5x3KAHbjA9R44qpTMSemerG/Q1Xbh5XogTG95IeLDQ==
+";
+        assert_eq!(verify(&public_key(), LIST, legacy).unwrap(), 1750000000);
+
+        // a custom trusted comment without the time it was signed
+        let no_timestamp = "untrusted comment: signature from minisign secret key
+RURaHg8rPE1ufwsi0fp9IFokhYE+ui1RP1LN0j2OiNAEV+CywVzQ8NTzKA4Kk+Zz2bVo6sgHQmBOuTbL5QdYhL98d4lnIgSVjw0=
+trusted comment: no timestamp
+CRwn0tyKdG6F/CVOGReQWAhXkPD/mOify1E6swRV87ooMAcJtlbHoaFb6uvz0IYzEdaH/TFCh7SKCwyW+5rcDw==
+";
+        assert!(verify(&public_key(), LIST, no_timestamp).is_err());
+
+        assert!(verify(&public_key(), b"[[source]]\n", SIGNATURE).is_err());
+        let tampered = SIGNATURE.replace("1760000000", "1760000001");
+        assert!(verify(&public_key(), LIST, &tampered).is_err());
+        let other_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"
+            .parse()
+            .unwrap();
+        assert!(verify(&other_key, LIST, SIGNATURE).is_err());
+        assert!(verify(&public_key(), LIST, "untrusted comment: nothing\n").is_err());
+    }
+
+    #[test]
+    fn test_check_newer() {
+        check_newer(1760000000, None).unwrap();
+        check_newer(1760000000, Some(1750000000)).unwrap();
+        check_newer(1760000000, Some(1760000000)).unwrap();
+        assert!(check_newer(1750000000, Some(1760000000)).is_err());
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        let sources = parse_sources(LIST).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert!(matches!(sources[0], NtpSourceConfig::Standard(_)));
+
+        assert!(parse_sources(b"").unwrap().is_empty());
+        assert!(parse_sources(b"[synchronization]\nminimum-agreeing-sources = 1\n").is_err());
+        assert!(
+            parse_sources(
+                b"[[source]]\nmode = \"sock\"\npath = \"/run/gps.sock\"\nprecision = 1e-6\n"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response =
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\ncontent-length: 5\r\n\r\nhello, world";
+        assert_eq!(parse_response(response).unwrap(), b"hello");
+
+        let response = b"HTTP/1.0 200 OK\r\n\r\nhello";
+        assert_eq!(parse_response(response).unwrap(), b"hello");
+
+        assert!(parse_response(b"HTTP/1.1 404 Not Found\r\n\r\n").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello").is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_cache() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join(format!("ntp-test-fleet-{}", alloc_port()));
+        let config = FleetConfig {
+            url: "https://config.example.com/sources.toml".parse().unwrap(),
+            signature_url: None,
+            public_key: public_key(),
+            refresh_interval: 3600,
+            cache_path: Some(path.clone()),
+        };
+        assert!(load_cache(&config).await.is_empty());
+
+        write_cache(&path, LIST, SIGNATURE).await.unwrap();
+        assert_eq!(load_cache(&config).await.len(), 1);
+        assert_eq!(read_cache(&path, &config).await.unwrap().1, 1760000000);
+
+        std::fs::write(&path, b"").unwrap();
+        assert!(load_cache(&config).await.is_empty());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(signature_path(&path)).unwrap();
+    }
+}
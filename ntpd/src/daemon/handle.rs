@@ -0,0 +1,330 @@
+//! Running the daemon inside another application.
+//!
+//! [`spawn`] starts everything the `ntp-daemon` binary starts for a
+//! configuration, on the tokio runtime of the caller, and returns a [`Handle`]
+//! to observe and control it. Process wide settings such as the sandbox,
+//! logging and reloading on `SIGHUP` are left to the application.
+
+use std::{sync::Arc, time::Instant};
+
+use ntp_proto::{AlgorithmConfig, KalmanClockController, NtpClock};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::{AbortHandle, JoinHandle},
+};
+
+#[cfg(feature = "fleet")]
+use super::fleet;
+use super::{
+    Config, ObservableState,
+    clock::NtpClockWrapper,
+    clock_follower,
+    config::UnresolvableSources,
+    control::{self, ControlMessage, ControlRequest, ControlResponse},
+    dns_sd::{self, AdvertisedService},
+    nts_key_provider, observer, pps_output,
+    system::{self, ConfigReload, ServerData, SpawnOptions},
+};
+
+/// A running daemon, see [`spawn`]
+pub struct Handle {
+    main_loop: JoinHandle<std::io::Result<()>>,
+    tasks: Vec<AbortHandle>,
+    key_rotation: nts_key_provider::KeyRotation,
+    pps_output: Option<pps_output::PpsOutput>,
+    control_sender: mpsc::Sender<ControlMessage>,
+    observations: Arc<std::sync::RwLock<observer::Observations>>,
+    server_data_receiver: tokio::sync::watch::Receiver<Vec<ServerData>>,
+    disabled_sources_receiver: tokio::sync::watch::Receiver<Vec<String>>,
+    instance_name: Option<String>,
+    clock: NtpClockWrapper,
+    start_time: Instant,
+}
+
+/// Start the daemon with `config` on the current tokio runtime.
+///
+/// The observation and control sockets are only created when their paths are
+/// configured, everything they offer is also available on the [`Handle`].
+pub async fn spawn(config: Config) -> std::io::Result<Handle> {
+    start(config, None).await
+}
+
+pub(super) async fn start(
+    config: Config,
+    config_reload: Option<ConfigReload<AlgorithmConfig>>,
+) -> std::io::Result<Handle> {
+    // Warn/error if the config is unreasonable
+    config.check();
+
+    if config.synchronization.unresolvable_sources == UnresolvableSources::Fail {
+        config.check_resolvable().await.inspect_err(|e| {
+            ::tracing::error!("Not starting: {}", e);
+        })?;
+    }
+
+    let advertised = advertised_services(&config);
+    let config_hash = config.hash.clone();
+
+    let symmetric_keys = config
+        .keyset
+        .symmetric_keys(&config.crypto_policy)
+        .map_err(|e| {
+            ::tracing::error!("Could not load symmetric keys: {}", e);
+            e
+        })?;
+
+    // we always generate the keyset (even if NTS is not used)
+    let (keyset, key_rotation) = nts_key_provider::spawn(config.keyset).await;
+
+    #[cfg(feature = "hardware-timestamping")]
+    let clock_config = config.clock;
+
+    #[cfg(not(feature = "hardware-timestamping"))]
+    let clock_config = super::config::ClockConfig::default();
+
+    ::tracing::debug!("Configuration loaded, spawning daemon jobs");
+    let clock = clock_config.clock;
+
+    // start with the cached fleet sources, until they are fetched
+    #[cfg(feature = "fleet")]
+    let (fleet_sender, fleet_sources) = tokio::sync::watch::channel(match &config.fleet {
+        Some(fleet_config) => fleet::load_cache(fleet_config).await,
+        None => vec![],
+    });
+    #[cfg(not(feature = "fleet"))]
+    let fleet_sources = tokio::sync::watch::channel(vec![]).1;
+
+    let follow_clocks = clock_config.follow_clocks.clone();
+    let follow_clocks_timescale = clock_config.follow_clocks_timescale;
+    let pps_output = clock_config.pps_output.clone();
+    let (main_loop, channels) = system::spawn::<KalmanClockController<_, _>>(
+        config_reload,
+        &config.synchronization,
+        config.synchronization.algorithm,
+        SpawnOptions {
+            source_defaults: config.source_defaults,
+            clock: clock_config,
+            sources: &config.sources,
+            servers: &config.servers,
+            keyset: keyset.clone(),
+            symmetric_keys: Arc::new(symmetric_keys),
+            crypto_policy: config.crypto_policy.clone(),
+            fleet_sources,
+            scheduling: &config.scheduling,
+        },
+    )
+    .await?;
+    channels
+        .observations
+        .write()
+        .expect("Unexpected poisoned mutex")
+        .set_config_hash(config_hash);
+
+    let mut tasks = vec![];
+
+    if !advertised.is_empty() {
+        tasks.push(
+            tokio::spawn(async move {
+                if let Err(e) = dns_sd::advertise(advertised).await {
+                    ::tracing::warn!(error = ?e, "Could not advertise the servers with multicast DNS");
+                }
+            })
+            .abort_handle(),
+        );
+    }
+
+    #[cfg(feature = "nts-server")]
+    for nts_ke_config in config.nts_ke {
+        tasks.push(
+            super::keyexchange::spawn(
+                nts_ke_config,
+                keyset.clone(),
+                config.crypto_policy.aead_algorithms.clone(),
+            )
+            .abort_handle(),
+        );
+    }
+
+    #[cfg(feature = "fleet")]
+    if let Some(fleet_config) = config.fleet {
+        tasks.push(fleet::spawn(fleet_config, fleet_sender).abort_handle());
+    }
+
+    for path in follow_clocks {
+        tasks.push(clock_follower::spawn(clock, path, follow_clocks_timescale).abort_handle());
+    }
+
+    let pps_output = pps_output.and_then(|pps_output| {
+        pps_output::spawn(clock, pps_output, channels.observations.clone())
+            .inspect_err(|e| ::tracing::warn!(error = ?e, "Could not start PPS output"))
+            .ok()
+    });
+
+    tasks.push(
+        observer::spawn(
+            &config.observability,
+            channels.observations.clone(),
+            channels.server_data_receiver.clone(),
+            channels.disabled_sources_receiver.clone(),
+            clock,
+        )
+        .abort_handle(),
+    );
+    tasks.push(
+        control::spawn(&config.observability, channels.control_sender.clone()).abort_handle(),
+    );
+
+    Ok(Handle {
+        main_loop,
+        tasks,
+        key_rotation,
+        pps_output,
+        control_sender: channels.control_sender,
+        observations: channels.observations,
+        server_data_receiver: channels.server_data_receiver,
+        disabled_sources_receiver: channels.disabled_sources_receiver,
+        instance_name: config.observability.instance_name,
+        clock,
+        start_time: Instant::now(),
+    })
+}
+
+impl Handle {
+    /// The state of the daemon, the same as shown by `ntp-ctl status`
+    pub fn status(&self) -> ObservableState {
+        observer::observe(
+            self.start_time,
+            &self.observations,
+            &self.server_data_receiver,
+            &self.disabled_sources_receiver,
+            self.instance_name.clone(),
+            self.clock.now().expect("Unable to get current time"),
+        )
+    }
+
+    /// Start polling the server at `address`, given as `host` or `host:port`,
+    /// with the source defaults of the configuration. Like sources added
+    /// through the configuration file, it is stopped by a reload of a
+    /// configuration that does not contain it.
+    pub async fn add_source(&self, address: &str) -> std::io::Result<()> {
+        let request = ControlRequest::AddSource {
+            address: address.to_owned(),
+        };
+        match self.request(request).await? {
+            ControlResponse::Reloaded { .. } => Ok(()),
+            ControlResponse::ReloadFailed { error } => {
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, error))
+            }
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Stop the sources with the given name or address, together with the
+    /// pool they are part of. Returns how many configured sources were
+    /// stopped, none is reported as [`std::io::ErrorKind::NotFound`].
+    pub async fn remove_source(&self, source: &str) -> std::io::Result<usize> {
+        let request = ControlRequest::RemoveSource {
+            source: source.to_owned(),
+        };
+        match self.request(request).await? {
+            ControlResponse::Reloaded { removed, .. } => Ok(removed),
+            ControlResponse::UnknownSource => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("no source named {source}"),
+            )),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Stop the daemon with all of its sources, servers, the rotation of its
+    /// NTS keys and its PPS output
+    pub async fn shutdown(self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        self.key_rotation.stop();
+        if let Some(pps_output) = self.pps_output {
+            pps_output.stop().await;
+        }
+        self.main_loop.abort();
+        // the system task stops its sources and servers when it is dropped
+        let _ = self.main_loop.await;
+    }
+
+    /// Wait for the daemon to stop, which it only does on an error
+    pub async fn join(self) -> std::io::Result<()> {
+        let result = self.main_loop.await;
+        for task in &self.tasks {
+            task.abort();
+        }
+        self.key_rotation.stop();
+        if let Some(pps_output) = self.pps_output {
+            pps_output.stop().await;
+        }
+        result?
+    }
+
+    pub(super) fn control_sender(&self) -> mpsc::Sender<ControlMessage> {
+        self.control_sender.clone()
+    }
+
+    async fn request(&self, request: ControlRequest) -> std::io::Result<ControlResponse> {
+        let (reply, response) = oneshot::channel();
+        self.control_sender
+            .send(ControlMessage { request, reply })
+            .await
+            .map_err(|_| std::io::Error::other("system task is not running"))?;
+        response
+            .await
+            .map_err(|_| std::io::Error::other("system task did not answer"))
+    }
+}
+
+/// The servers of `config` to advertise on the local network
+fn advertised_services(config: &Config) -> Vec<AdvertisedService> {
+    let services = config
+        .servers
+        .iter()
+        .filter(|server| server.mdns_advertise)
+        .map(|server| AdvertisedService {
+            service: dns_sd::NTP_SERVICE,
+            addr: server.listen,
+        });
+
+    #[cfg(feature = "nts-server")]
+    let services = services.chain(
+        config
+            .nts_ke
+            .iter()
+            .filter(|nts_ke| nts_ke.mdns_advertise)
+            .map(|nts_ke| AdvertisedService {
+                service: dns_sd::NTS_KE_SERVICE,
+                addr: nts_ke.listen,
+            }),
+    );
+
+    services.collect()
+}
+
+fn unexpected(response: ControlResponse) -> std::io::Error {
+    std::io::Error::other(format!("unexpected response from the daemon: {response:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_embedded_daemon() {
+        let handle = spawn(Config::default()).await.unwrap();
+        assert!(handle.status().sources.is_empty());
+
+        let err = handle.add_source("a:b:c").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        let err = handle.remove_source("example.com:123").await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
+        handle.shutdown().await;
+    }
+}
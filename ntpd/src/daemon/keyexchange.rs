@@ -1,26 +1,30 @@
-use std::{
-    io::{BufRead, BufReader},
-    path::Path,
-    sync::Arc,
-};
+use std::sync::Arc;
 
 use libc::{ECONNABORTED, EMFILE, ENFILE, ENOBUFS, ENOMEM};
-use ntp_proto::{KeyExchangeServer, KeySet};
+use ntp_proto::{AeadAlgorithm, KeyExchangeServer, KeySet};
 use ntp_proto::{NtsServerConfig, tls_utils::Certificate};
 use tokio::{net::TcpListener, task::JoinHandle};
 use tracing::{Instrument, Span, debug, error, instrument};
 
 use super::config::NtsKeConfig;
 use super::exitcode;
+use super::sockets::bind_tcp_listener;
 
 #[instrument(level = tracing::Level::ERROR, name = "Nts Server", skip_all, fields(address = debug(nts_ke_config.listen)))]
 pub fn spawn(
     nts_ke_config: NtsKeConfig,
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    algorithms: Vec<AeadAlgorithm>,
 ) -> JoinHandle<std::io::Result<()>> {
+    // the listener exists once the daemon has started, after which the
+    // privileges to open it may be dropped
+    let listener = bind_tcp_listener(nts_ke_config.listen, None)
+        .inspect_err(|e| error!("Could not open network port for KE server: {}", e))
+        .ok();
+
     tokio::spawn(
         (async move {
-            let result = run_nts_ke(nts_ke_config, keyset).await;
+            let result = run_nts_ke(nts_ke_config, keyset, algorithms, listener).await;
 
             match result {
                 Ok(v) => Ok(v),
@@ -41,6 +45,8 @@ fn io_error(msg: &str) -> std::io::Error {
 async fn run_nts_ke(
     nts_ke_config: NtsKeConfig,
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    algorithms: Vec<AeadAlgorithm>,
+    listener: Option<TcpListener>,
 ) -> std::io::Result<()> {
     let certificate_chain_file = std::fs::File::open(&nts_ke_config.certificate_chain_path)
         .map_err(|e| {
@@ -72,15 +78,17 @@ async fn run_nts_ke(
         port: nts_ke_config.ntp_port,
         pool_authentication_tokens: nts_ke_config.accepted_pool_authentication_tokens.clone(),
     })
-    .map_err(std::io::Error::other)?;
+    .map_err(std::io::Error::other)?
+    .with_algorithms(&algorithms);
 
-    run_key_exchange_server(keyset, key_exchange_server, nts_ke_config).await
+    run_key_exchange_server(keyset, key_exchange_server, nts_ke_config, listener).await
 }
 
 async fn run_key_exchange_server(
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
     key_exchange_server: KeyExchangeServer,
     ke_config: NtsKeConfig,
+    mut listener: Option<TcpListener>,
 ) -> std::io::Result<()> {
     let timeout = std::time::Duration::from_millis(ke_config.key_exchange_timeout_ms);
     let key_exchange_server = Arc::new(key_exchange_server);
@@ -91,13 +99,16 @@ async fn run_key_exchange_server(
     let longlivedpermits = Arc::new(tokio::sync::Semaphore::new(ke_config.longlived_connections));
 
     loop {
-        let listener = match TcpListener::bind(&ke_config.listen).await {
-            Ok(listener) => listener,
-            Err(e) => {
-                error!("Could not open network port for KE server: {}", e);
-                tokio::time::sleep(timeout).await;
-                continue;
-            }
+        let listener = match listener.take() {
+            Some(listener) => listener,
+            None => match bind_tcp_listener(ke_config.listen, None) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    error!("Could not open network port for KE server: {}", e);
+                    tokio::time::sleep(timeout).await;
+                    continue;
+                }
+            },
         };
 
         // Ensure we do not make too many connections. We can reinitialize here because any error path recreating the socket
@@ -141,6 +152,7 @@ async fn run_key_exchange_server(
                     break;
                 }
             };
+            let source_addr = ke_config.log_anonymization.log(source_addr);
             let keyset = keyset.borrow().clone();
             let key_exchange_server = key_exchange_server.clone();
             let longlivedpermits = longlivedpermits.clone();
@@ -157,15 +169,15 @@ async fn run_key_exchange_server(
 
             tokio::spawn(async move {
                 match tokio::time::timeout(timeout, fut).await {
-                    Err(_) => tracing::debug!(?source_addr, "NTS KE timed out"),
-                    Ok(Err(err)) => tracing::debug!(?err, ?source_addr, "NTS KE failed"),
-                    Ok(Ok(None)) => tracing::debug!(?source_addr, "NTS KE completed"),
+                    Err(_) => tracing::debug!(%source_addr, "NTS KE timed out"),
+                    Ok(Err(err)) => tracing::debug!(?err, %source_addr, "NTS KE failed"),
+                    Ok(Ok(None)) => tracing::debug!(%source_addr, "NTS KE completed"),
                     Ok(Ok(Some(((longlived_permit, io), (key_exchange_server, keyset))))) => {
                         if let Err(err) = key_exchange_server
                             .handle_longterm(io, || keyset.clone())
                             .await
                         {
-                            tracing::debug!(?err, ?source_addr, "Long term NTS KE failed");
+                            tracing::debug!(?err, %source_addr, "Long term NTS KE failed");
                         } else {
                             tracing::debug!("Long lived connection closed by remote");
                         }
@@ -178,46 +190,24 @@ async fn run_key_exchange_server(
     }
 }
 
-pub(crate) fn certificates_from_file(path: &Path) -> std::io::Result<Vec<Certificate>> {
-    let file = std::fs::File::open(path)?;
-    let reader = BufReader::new(file);
-
-    certificates_from_bufread(reader)
-}
-
-fn certificates_from_bufread(mut reader: impl BufRead) -> std::io::Result<Vec<Certificate>> {
-    ntp_proto::tls_utils::pemfile::certs(&mut reader).collect()
-}
-
 #[cfg(test)]
 mod tests {
-    use std::{io::Cursor, net::SocketAddr, path::PathBuf};
+    use std::{
+        io::{BufReader, Cursor},
+        net::SocketAddr,
+        path::PathBuf,
+    };
 
     use ntp_proto::KeySetProvider;
     use ntp_proto::{KeyExchangeClient, NtpVersion, NtsClientConfig};
     use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::TcpStream;
 
+    use crate::daemon::config::{CryptoPolicyConfig, certificates_from_bufread};
     use crate::test::alloc_port;
 
     use super::*;
 
-    #[test]
-    fn nos_nl_pem() {
-        let input = include_bytes!("../../testdata/certificates/nos-nl.pem");
-        let certificates = certificates_from_bufread(input.as_slice()).unwrap();
-
-        assert_eq!(certificates.len(), 1);
-    }
-
-    #[test]
-    fn nos_nl_chain_pem() {
-        let input = include_bytes!("../../testdata/certificates/nos-nl-chain.pem");
-        let certificates = certificates_from_bufread(input.as_slice()).unwrap();
-
-        assert_eq!(certificates.len(), 3);
-    }
-
     #[test]
     fn parse_private_keys() {
         let input = include_bytes!("../../test-keys/end.key");
@@ -259,9 +249,15 @@ mod tests {
             ntp_port: None,
             ntp_server: None,
             accept_ntp_versions: vec![NtpVersion::V4],
+            log_anonymization: Default::default(),
+            mdns_advertise: false,
         };
 
-        let _join_handle = spawn(nts_ke_config, keyset);
+        let _join_handle = spawn(
+            nts_ke_config,
+            keyset,
+            CryptoPolicyConfig::default().aead_algorithms,
+        );
 
         // give the server some time to make the port available
         tokio::time::sleep(std::time::Duration::from_millis(20)).await;
@@ -342,9 +338,15 @@ mod tests {
             ntp_port: Some(568),
             ntp_server: Some("jantje".into()),
             accept_ntp_versions: vec![NtpVersion::V4],
+            log_anonymization: Default::default(),
+            mdns_advertise: false,
         };
 
-        let _join_handle = spawn(nts_ke_config, keyset);
+        let _join_handle = spawn(
+            nts_ke_config,
+            keyset,
+            CryptoPolicyConfig::default().aead_algorithms,
+        );
 
         // give the server some time to make the port available
         tokio::time::sleep(std::time::Duration::from_millis(20)).await;
@@ -0,0 +1,89 @@
+use std::{collections::HashMap, net::SocketAddr, path::Path};
+
+use ntp_proto::PollInterval;
+
+use super::{state_format::KOD_HISTORY_FILE, util::canonical_addr};
+
+/// Read the minimum poll intervals that servers requested through rate limiting.
+///
+/// Every line holds a server address and the log2 of its minimum poll interval
+/// in seconds, separated by whitespace.
+pub fn load(path: &Path) -> std::io::Result<HashMap<SocketAddr, PollInterval>> {
+    let contents = KOD_HISTORY_FILE.read(path)?;
+    let invalid = |line: &str| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid line in kod history file: {line:?}"),
+        )
+    };
+
+    let mut history = HashMap::new();
+    for line in contents.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let mut parts = line.split_whitespace();
+        let (Some(addr), Some(interval), None) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid(line));
+        };
+        let addr: SocketAddr = addr.parse().map_err(|_| invalid(line))?;
+        let interval: u8 = interval.parse().map_err(|_| invalid(line))?;
+        // beyond 2^17 seconds (~36h) polling is pointless
+        if interval > 17 {
+            return Err(invalid(line));
+        }
+        history.insert(canonical_addr(addr), PollInterval::from_byte(interval));
+    }
+
+    Ok(history)
+}
+
+/// Write the minimum poll intervals to the history file, replacing it atomically
+pub fn store(path: &Path, history: &HashMap<SocketAddr, PollInterval>) -> std::io::Result<()> {
+    let mut entries: Vec<_> = history.iter().collect();
+    entries.sort();
+
+    let contents: String = entries
+        .into_iter()
+        .map(|(addr, interval)| format!("{addr} {}\n", interval.as_log()))
+        .collect();
+
+    KOD_HISTORY_FILE.write(path, &contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    #[test]
+    fn test_kod_history_roundtrip() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join(format!("ntp-test-kod-history-{}", alloc_port()));
+
+        let mut history = HashMap::new();
+        history.insert("192.0.2.1:123".parse().unwrap(), PollInterval::from_byte(7));
+        history.insert(
+            "[2001:db8::1]:123".parse().unwrap(),
+            PollInterval::from_byte(9),
+        );
+
+        store(&path, &history).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# ntpd-rs kod-history-file format 1\n192.0.2.1:123 7\n[2001:db8::1]:123 9\n"
+        );
+        assert_eq!(load(&path).unwrap(), history);
+
+        // history files written before the format was versioned
+        std::fs::write(&path, "192.0.2.1:123 7\n[2001:db8::1]:123 9\n").unwrap();
+        assert_eq!(load(&path).unwrap(), history);
+
+        std::fs::write(&path, "192.0.2.1:123\n").unwrap();
+        assert!(load(&path).is_err());
+
+        std::fs::write(&path, "192.0.2.1:123 40\n").unwrap();
+        assert!(load(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(load(&path).is_err());
+    }
+}
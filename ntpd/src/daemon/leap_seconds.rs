@@ -0,0 +1,262 @@
+use std::path::Path;
+
+use ntp_proto::{NtpDuration, NtpLeapIndicator, NtpTimestamp};
+use serde::{Deserialize, Serialize};
+
+/// How often the leap seconds file is read again, to pick up updates
+pub const LEAP_SECONDS_FILE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+// Same as other NTP implementations: clients are told about a leap second in
+// the four weeks before it, so they all have heard of it by the time it happens.
+const ANNOUNCE_BEFORE: f64 = 28.0 * 86400.0;
+
+// The kernel applies a pending leap second at the end of the current day.
+const ARM_BEFORE: f64 = 86400.0;
+
+/// Moment from which a new offset of TAI to UTC applies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeapSecond {
+    pub at: NtpTimestamp,
+    pub tai_offset: i32,
+}
+
+/// The leap seconds published by the IERS, in the format of `leap-seconds.list`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeapSecondsList {
+    expires: NtpTimestamp,
+    leaps: Vec<LeapSecond>,
+}
+
+/// What the daemon knows about leap seconds, as shown on the observation socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeapSecondsStatus {
+    /// Current offset of TAI to UTC in seconds
+    pub tai_offset: Option<i32>,
+    /// The next leap second in the list, if any
+    pub next_leap: Option<LeapSecond>,
+    /// After this the list can no longer be used
+    pub expires: NtpTimestamp,
+}
+
+impl LeapSecondsList {
+    /// Read a leap seconds list from a file
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse the contents of a leap seconds list.
+    ///
+    /// Every data line holds the NTP timestamp from which an offset applies and
+    /// that offset, followed by a comment. The expiry is on the line starting
+    /// with `#@`, all other lines starting with `#` are comments.
+    pub fn parse(contents: &str) -> std::io::Result<Self> {
+        let invalid = |message: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid leap seconds list: {message}"),
+            )
+        };
+
+        let mut expires = None;
+        let mut leaps: Vec<LeapSecond> = vec![];
+        for line in contents.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            if let Some(expiry) = line.strip_prefix("#@") {
+                let seconds = expiry
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid expiry {line:?}")))?;
+                expires = Some(NtpTimestamp::from_seconds_nanos_since_ntp_era(seconds, 0));
+                continue;
+            }
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let data = line.split_once('#').map_or(line, |(data, _comment)| data);
+            let mut parts = data.split_whitespace();
+            let (Some(at), Some(tai_offset), None) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(invalid(format!("invalid line {line:?}")));
+            };
+            let leap = LeapSecond {
+                at: NtpTimestamp::from_seconds_nanos_since_ntp_era(
+                    at.parse()
+                        .map_err(|_| invalid(format!("invalid line {line:?}")))?,
+                    0,
+                ),
+                tai_offset: tai_offset
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid line {line:?}")))?,
+            };
+
+            // every entry after the first is a single leap second
+            if let Some(previous) = leaps.last()
+                && (!previous.at.is_before(leap.at)
+                    || (leap.tai_offset - previous.tai_offset).abs() != 1)
+            {
+                return Err(invalid(format!(
+                    "entry does not follow the previous {line:?}"
+                )));
+            }
+            leaps.push(leap);
+        }
+
+        let expires = expires.ok_or_else(|| invalid("no expiry date".into()))?;
+        if leaps.is_empty() {
+            return Err(invalid("no entries".into()));
+        }
+
+        Ok(LeapSecondsList { expires, leaps })
+    }
+
+    pub fn expires(&self) -> NtpTimestamp {
+        self.expires
+    }
+
+    pub fn is_expired(&self, now: NtpTimestamp) -> bool {
+        !now.is_before(self.expires)
+    }
+
+    /// Offset of TAI to UTC at `now`, if the list covers that moment
+    pub fn tai_offset(&self, now: NtpTimestamp) -> Option<i32> {
+        self.leaps
+            .iter()
+            .take_while(|leap| !now.is_before(leap.at))
+            .last()
+            .map(|leap| leap.tai_offset)
+    }
+
+    pub fn next_leap(&self, now: NtpTimestamp) -> Option<LeapSecond> {
+        self.leaps
+            .iter()
+            .find(|leap| now.is_before(leap.at))
+            .copied()
+    }
+
+    /// Leap indicator to announce at `now`, given the leap second is at most
+    /// `before` seconds away
+    fn leap_indicator(&self, now: NtpTimestamp, before: f64) -> NtpLeapIndicator {
+        let Some(next) = self.next_leap(now) else {
+            return NtpLeapIndicator::NoWarning;
+        };
+        let current = self.tai_offset(now).unwrap_or(next.tai_offset);
+        if (next.at - now) > NtpDuration::from_seconds(before) {
+            NtpLeapIndicator::NoWarning
+        } else if next.tai_offset > current {
+            NtpLeapIndicator::Leap61
+        } else {
+            NtpLeapIndicator::Leap59
+        }
+    }
+
+    /// Leap indicator to announce to clients at `now`
+    pub fn announcement(&self, now: NtpTimestamp) -> NtpLeapIndicator {
+        self.leap_indicator(now, ANNOUNCE_BEFORE)
+    }
+
+    /// Leap indicator to pass to the kernel at `now`
+    pub fn kernel_status(&self, now: NtpTimestamp) -> NtpLeapIndicator {
+        self.leap_indicator(now, ARM_BEFORE)
+    }
+
+    pub fn status(&self, now: NtpTimestamp) -> LeapSecondsStatus {
+        LeapSecondsStatus {
+            tai_offset: self.tai_offset(now),
+            next_leap: self.next_leap(now),
+            expires: self.expires,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    const LIST: &str = "\
+#	Updated through IERS Bulletin C
+#$	 3676924800
+#@	3928521600
+#
+3644697600	36	# 1 Jul 2015
+3692217600	37	# 1 Jan 2017
+#h	16edd0f0 3666784f 37db6bdd e74ced87 59af48f1
+";
+
+    fn at(seconds: u32) -> NtpTimestamp {
+        NtpTimestamp::from_seconds_nanos_since_ntp_era(seconds, 0)
+    }
+
+    #[test]
+    fn test_parse_leap_seconds_list() {
+        let list = LeapSecondsList::parse(LIST).unwrap();
+        assert_eq!(list.expires(), at(3928521600));
+        assert!(!list.is_expired(at(3928521599)));
+        assert!(list.is_expired(at(3928521600)));
+
+        assert_eq!(list.tai_offset(at(3644697599)), None);
+        assert_eq!(list.tai_offset(at(3644697600)), Some(36));
+        assert_eq!(list.tai_offset(at(3692217600)), Some(37));
+        assert_eq!(list.next_leap(at(3692217600)), None);
+        assert_eq!(
+            list.next_leap(at(3692217599)),
+            Some(LeapSecond {
+                at: at(3692217600),
+                tai_offset: 37
+            })
+        );
+
+        assert!(LeapSecondsList::parse("2272060800 10\n").is_err());
+        assert!(LeapSecondsList::parse("#@ 3928521600\n").is_err());
+        assert!(LeapSecondsList::parse("#@ 3928521600\n2272060800\n").is_err());
+        assert!(LeapSecondsList::parse("#@ 3928521600\n2287785600 11\n2272060800 12\n").is_err());
+        assert!(LeapSecondsList::parse("#@ 3928521600\n2272060800 10\n2287785600 12\n").is_err());
+    }
+
+    #[test]
+    fn test_leap_second_announcement() {
+        let list = LeapSecondsList::parse(LIST).unwrap();
+        let leap = 3692217600;
+
+        assert_eq!(
+            list.announcement(at(leap - 29 * 86400)),
+            NtpLeapIndicator::NoWarning
+        );
+        assert_eq!(
+            list.announcement(at(leap - 27 * 86400)),
+            NtpLeapIndicator::Leap61
+        );
+        assert_eq!(
+            list.kernel_status(at(leap - 27 * 86400)),
+            NtpLeapIndicator::NoWarning
+        );
+        assert_eq!(
+            list.kernel_status(at(leap - 3600)),
+            NtpLeapIndicator::Leap61
+        );
+        assert_eq!(list.announcement(at(leap)), NtpLeapIndicator::NoWarning);
+
+        let negative =
+            LeapSecondsList::parse("#@ 3928521600\n3692217600 37\n3881174400 36\n").unwrap();
+        assert_eq!(
+            negative.announcement(at(3881174400 - 86400)),
+            NtpLeapIndicator::Leap59
+        );
+    }
+
+    #[test]
+    fn test_load_leap_seconds_file() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join(format!("ntp-test-leap-seconds-{}", alloc_port()));
+
+        std::fs::write(&path, LIST).unwrap();
+        assert_eq!(
+            LeapSecondsList::load(&path).unwrap(),
+            LeapSecondsList::parse(LIST).unwrap()
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(LeapSecondsList::load(&path).is_err());
+    }
+}
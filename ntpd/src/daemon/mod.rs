@@ -1,27 +1,51 @@
-mod clock;
+pub mod anonymize;
+mod blackout;
+pub mod census;
+pub(crate) mod clock;
+mod clock_follower;
 pub mod config;
+pub mod control;
+pub mod diversity;
+mod dns_sd;
+mod drift;
+mod file_source;
+#[cfg(feature = "fleet")]
+mod fleet;
+pub mod handle;
+#[cfg(feature = "nts-server")]
 pub mod keyexchange;
+mod kod_history;
+pub mod leap_seconds;
 mod local_ip_provider;
+pub mod nat64;
 mod ntp_source;
 pub mod nts_key_provider;
 pub mod observer;
+mod pps_output;
 #[cfg(feature = "pps")]
 mod pps_source;
+pub mod recorder;
+mod sandbox;
+mod scheduling;
 mod server;
+mod shared_socket;
 mod sock_source;
 pub mod sockets;
 pub mod spawn;
+pub mod state;
+pub mod state_format;
+mod stream;
 mod system;
 pub mod tracing;
-mod util;
+pub(crate) mod util;
 
 use std::{error::Error, path::PathBuf};
 
 use ::tracing::info;
 pub use config::Config;
-use ntp_proto::KalmanClockController;
 pub use observer::ObservableState;
-pub use system::spawn;
+use system::ConfigReload;
+pub use system::{SpawnOptions, spawn};
 use tokio::runtime::Builder;
 use tracing_subscriber::util::SubscriberInitExt;
 
@@ -81,13 +105,27 @@ pub(crate) fn initialize_logging_parse_config(
 }
 
 fn run(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
-    let config = initialize_logging_parse_config(options.log_level, options.config);
-
-    let runtime = if config.servers.is_empty() && config.nts_ke.is_empty() {
-        Builder::new_current_thread().enable_all().build()?
+    let config = initialize_logging_parse_config(options.log_level, options.config.clone());
+
+    // The clock is opened while parsing the configuration, and the runtime threads
+    // should only run as the configured user
+    let sandbox = config.sandbox.clone();
+    let socket_privileges = sandbox::SocketPrivileges::of(&config);
+    let realtime_scheduling = config.scheduling.realtime_priority.is_some();
+    sandbox::drop_privileges(&sandbox, socket_privileges, realtime_scheduling).map_err(|e| {
+        ::tracing::error!("Could not set up the sandbox: {}", e);
+        e
+    })?;
+
+    let mut builder = if config.servers.is_empty() && config.nts_ke.is_empty() {
+        Builder::new_current_thread()
     } else {
-        Builder::new_multi_thread().enable_all().build()?
+        Builder::new_multi_thread()
     };
+    let runtime = builder
+        .enable_all()
+        .on_thread_start(sandbox::restrict_thread)
+        .build()?;
 
     runtime.block_on(async {
         // give the user a warning that we use the command line option
@@ -95,45 +133,21 @@ fn run(options: NtpDaemonOptions) -> Result<(), Box<dyn Error>> {
             info!("Log level override from command line arguments is active");
         }
 
-        // Warn/error if the config is unreasonable. We do this after finishing
-        // tracing setup to ensure logging is fully configured.
-        config.check();
-
-        // we always generate the keyset (even if NTS is not used)
-        let keyset = nts_key_provider::spawn(config.keyset).await;
-
-        #[cfg(feature = "hardware-timestamping")]
-        let clock_config = config.clock;
-
-        #[cfg(not(feature = "hardware-timestamping"))]
-        let clock_config = config::ClockConfig::default();
-
-        ::tracing::debug!("Configuration loaded, spawning daemon jobs");
-        let clock = clock_config.clock;
-        let (main_loop_handle, channels) = spawn::<KalmanClockController<_, _>>(
-            config.synchronization.synchronization_base,
-            config.synchronization.algorithm,
-            config.source_defaults,
-            clock_config,
-            &config.sources,
-            &config.servers,
-            keyset.clone(),
-        )
-        .await?;
-
-        for nts_ke_config in config.nts_ke {
-            let _join_handle = keyexchange::spawn(nts_ke_config, keyset.clone());
-        }
+        let config_reload = ConfigReload {
+            path: options.config,
+            algorithm: |config| config.synchronization.algorithm,
+        };
+        let handle = handle::start(config, Some(config_reload)).await?;
+
+        control::reload_on_hangup(handle.control_sender())?;
 
-        observer::spawn(
-            &config.observability,
-            channels.source_snapshots,
-            channels.server_data_receiver,
-            channels.system_snapshot_receiver,
-            clock,
-        );
+        // the main thread has opened the listeners of servers by now
+        sandbox::confine(&sandbox, socket_privileges, realtime_scheduling).map_err(|e| {
+            ::tracing::error!("Could not set up the sandbox: {}", e);
+            e
+        })?;
 
-        Ok(main_loop_handle.await??)
+        Ok(handle.join().await?)
     })
 }
 
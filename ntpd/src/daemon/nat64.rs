@@ -0,0 +1,187 @@
+//! Reaching IPv4 sources from an IPv6-only network through NAT64.
+//!
+//! A NAT64 gateway translates packets to the IPv6 addresses in its prefix to
+//! the IPv4 address embedded in them (RFC 6052). Names of sources are usually
+//! translated as well, by a DNS64 resolver. Addresses that are not, such as
+//! IPv4 literals in the configuration or sent by an NTS key exchange server,
+//! are embedded in the configured prefix by the daemon itself.
+
+use std::{
+    fmt::Display,
+    net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use serde::{Deserialize, Deserializer, de};
+
+/// The well-known prefix of RFC 6052
+pub const WELL_KNOWN_PREFIX: Nat64Prefix = Nat64Prefix {
+    addr: Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0),
+    len: 96,
+};
+
+/// IPv6 prefix under which a NAT64 gateway reaches IPv4 addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nat64Prefix {
+    addr: Ipv6Addr,
+    len: u8,
+}
+
+impl Nat64Prefix {
+    /// Positions of the IPv4 address in an address of the prefix, skipping
+    /// bits 64 to 71, which must be zero
+    fn positions(&self) -> impl Iterator<Item = usize> {
+        (usize::from(self.len / 8)..16).filter(|&i| i != 8).take(4)
+    }
+
+    /// The address under which `addr` is reached, when it is an IPv4 address
+    pub fn synthesize(&self, addr: SocketAddr) -> SocketAddr {
+        let IpAddr::V4(ip) = addr.ip().to_canonical() else {
+            return addr;
+        };
+
+        let mut octets = self.addr.octets();
+        for (i, octet) in self.positions().zip(ip.octets()) {
+            octets[i] = octet;
+        }
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), addr.port())
+    }
+
+    /// The IPv4 address embedded in `addr`, when it is in this prefix
+    pub fn extract(&self, addr: IpAddr) -> Option<Ipv4Addr> {
+        let IpAddr::V6(ip) = addr else {
+            return None;
+        };
+        let octets = ip.octets();
+        let prefix = usize::from(self.len / 8);
+        if octets[..prefix] != self.addr.octets()[..prefix] || octets[8] != 0 {
+            return None;
+        }
+
+        let mut embedded = [0; 4];
+        for (octet, i) in embedded.iter_mut().zip(self.positions()) {
+            *octet = octets[i];
+        }
+        Some(Ipv4Addr::from(embedded))
+    }
+}
+
+#[derive(Debug)]
+pub enum Nat64PrefixParseError {
+    Prefix,
+    Ip(AddrParseError),
+    Length,
+    HostBits,
+}
+
+impl std::error::Error for Nat64PrefixParseError {}
+
+impl Display for Nat64PrefixParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Prefix => write!(f, "Invalid prefix syntax"),
+            Self::Ip(e) => write!(f, "{e} in NAT64 prefix"),
+            Self::Length => write!(f, "NAT64 prefix length must be 32, 40, 48, 56, 64 or 96"),
+            Self::HostBits => write!(f, "NAT64 prefix has bits set after its length"),
+        }
+    }
+}
+
+impl From<AddrParseError> for Nat64PrefixParseError {
+    fn from(value: AddrParseError) -> Self {
+        Self::Ip(value)
+    }
+}
+
+impl std::str::FromStr for Nat64Prefix {
+    type Err = Nat64PrefixParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = s.split_once('/').ok_or(Nat64PrefixParseError::Prefix)?;
+        let addr: Ipv6Addr = addr.parse()?;
+        let len: u8 = len.parse().map_err(|_| Nat64PrefixParseError::Length)?;
+        if ![32, 40, 48, 56, 64, 96].contains(&len) {
+            return Err(Nat64PrefixParseError::Length);
+        }
+        if addr.to_bits() & (u128::MAX >> len) != 0 {
+            return Err(Nat64PrefixParseError::HostBits);
+        }
+        Ok(Nat64Prefix { addr, len })
+    }
+}
+
+impl Display for Nat64Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+impl<'de> Deserialize<'de> for Nat64Prefix {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        std::str::FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prefix() {
+        assert_eq!(
+            "64:ff9b::/96".parse::<Nat64Prefix>().unwrap(),
+            WELL_KNOWN_PREFIX
+        );
+        assert_eq!(
+            "2001:db8:100::/40"
+                .parse::<Nat64Prefix>()
+                .unwrap()
+                .to_string(),
+            "2001:db8:100::/40"
+        );
+
+        assert!("64:ff9b::".parse::<Nat64Prefix>().is_err());
+        assert!("192.0.2.0/96".parse::<Nat64Prefix>().is_err());
+        assert!("64:ff9b::/80".parse::<Nat64Prefix>().is_err());
+        assert!("64:ff9b::1/96".parse::<Nat64Prefix>().is_err());
+    }
+
+    #[test]
+    fn test_synthesize() {
+        // the examples of RFC 6052 section 2.4
+        let addr: SocketAddr = "192.0.2.33:123".parse().unwrap();
+        for (prefix, synthesized) in [
+            ("2001:db8::/32", "[2001:db8:c000:221::]:123"),
+            ("2001:db8:100::/40", "[2001:db8:1c0:2:21::]:123"),
+            ("2001:db8:122::/48", "[2001:db8:122:c000:2:2100::]:123"),
+            ("2001:db8:122:300::/56", "[2001:db8:122:3c0:0:221::]:123"),
+            (
+                "2001:db8:122:344::/64",
+                "[2001:db8:122:344:c0:2:2100:0]:123",
+            ),
+            ("2001:db8:122:344::/96", "[2001:db8:122:344::c000:221]:123"),
+        ] {
+            let prefix: Nat64Prefix = prefix.parse().unwrap();
+            let synthesized: SocketAddr = synthesized.parse().unwrap();
+            assert_eq!(prefix.synthesize(addr), synthesized);
+            assert_eq!(
+                prefix.extract(synthesized.ip()),
+                Some(Ipv4Addr::new(192, 0, 2, 33))
+            );
+        }
+
+        // IPv6 addresses are reached directly
+        let addr: SocketAddr = "[2001:db8::1]:123".parse().unwrap();
+        assert_eq!(WELL_KNOWN_PREFIX.synthesize(addr), addr);
+        assert_eq!(WELL_KNOWN_PREFIX.extract(addr.ip()), None);
+
+        let mapped: SocketAddr = "[::ffff:192.0.2.33]:123".parse().unwrap();
+        assert_eq!(
+            WELL_KNOWN_PREFIX.synthesize(mapped),
+            "[64:ff9b::192.0.2.33]:123".parse().unwrap()
+        );
+    }
+}
@@ -1,22 +1,26 @@
-use std::{
-    collections::HashMap, future::Future, marker::PhantomData, net::SocketAddr, pin::Pin, sync::Arc,
-};
+use std::{future::Future, marker::PhantomData, net::SocketAddr, pin::Pin, sync::Arc};
 
 use ntp_proto::{
     NtpClock, NtpDuration, NtpInstant, NtpSource, NtpSourceActionIterator, NtpSourceUpdate,
-    NtpTimestamp, ObservableSourceState, OneWaySourceUpdate, SourceController, SystemSourceUpdate,
+    NtpTimestamp, ObservableSourceState, OneWaySourceUpdate, REMEMBERED_TRIES, SourceController,
+    SystemSourceUpdate,
 };
 #[cfg(target_os = "linux")]
 use timestamped_socket::socket::open_interface_udp;
-use timestamped_socket::{
-    interface::InterfaceName,
-    socket::{Connected, RecvResult, Socket, connect_address},
-};
-use tracing::{Instrument, Span, debug, error, instrument, warn};
+use timestamped_socket::socket::{Connected, RecvResult, Socket, connect_address, open_ip};
+use tracing::{Instrument, Span, debug, error, info_span, instrument, warn};
 
 use tokio::time::{Instant, Sleep};
 
-use super::{config::TimestampMode, exitcode, spawn::SourceId, util::convert_net_timestamp};
+use super::{
+    config::TimestampMode,
+    exitcode,
+    observer::Observations,
+    shared_socket::SharedSocketClient,
+    spawn::{SocketBinding, SourceId},
+    stream::StreamClient,
+    util::convert_net_timestamp,
+};
 
 /// Trait needed to allow injecting of futures other than `tokio::time::Sleep` for testing
 pub trait Wait: Future<Output = ()> {
@@ -38,8 +42,8 @@ pub enum MsgForSystem<SourceMessage> {
     NetworkIssue(SourceId),
     /// Source is unreachable, and should be restarted with new resolved addr.
     Unreachable(SourceId),
-    /// Update from source
-    SourceUpdate(SourceId, NtpSourceUpdate<SourceMessage>),
+    /// Update from source, with the span of the poll exchange that produced it
+    SourceUpdate(SourceId, NtpSourceUpdate<SourceMessage>, Span),
     /// Update from sock source
     OneWaySourceUpdate(SourceId, OneWaySourceUpdate<SourceMessage>),
 }
@@ -49,8 +53,7 @@ pub struct SourceChannels<ControllerMessage, SourceMessage> {
     pub msg_for_system_sender: tokio::sync::mpsc::Sender<MsgForSystem<SourceMessage>>,
     pub system_update_receiver:
         tokio::sync::broadcast::Receiver<SystemSourceUpdate<ControllerMessage>>,
-    pub source_snapshots:
-        Arc<std::sync::RwLock<HashMap<SourceId, ObservableSourceState<SourceId>>>>,
+    pub observations: Arc<std::sync::RwLock<Observations>>,
 }
 
 pub(crate) struct SourceTask<
@@ -61,21 +64,41 @@ pub(crate) struct SourceTask<
     _wait: PhantomData<T>,
     index: SourceId,
     clock: C,
-    interface: Option<InterfaceName>,
+    binding: SocketBinding,
     timestamp_mode: TimestampMode,
     name: String,
     source_addr: SocketAddr,
     socket: Option<Socket<SocketAddr, Connected>>,
+    /// Socket of the previous poll, kept open so a late answer to it still arrives
+    previous_socket: Option<Socket<SocketAddr, Connected>>,
+    /// Used instead of the socket when the source is polled over TCP or TLS
+    stream: Option<StreamClient>,
+    /// Used instead of the socket when the source is on a shared socket
+    shared: Option<SharedSocketClient>,
+    /// Keep the socket across polls instead of opening one on a new port for
+    /// every poll
+    keep_socket: bool,
+    /// When the next datagram to keep the NAT mapping of the socket alive is due
+    next_keepalive: Option<Instant>,
     channels: SourceChannels<Controller::ControllerMessage, Controller::SourceMessage>,
 
+    // polling is paused while the source is administratively disabled
+    enabled: tokio::sync::watch::Receiver<bool>,
+
     source: NtpSource<Controller>,
 
     // we don't store the real origin timestamp in the packet, because that would leak our
     // system time to the network (and could make attacks easier). So instead there is some
     // garbage data in the origin_timestamp field, and we need to track and pass along the
     // actual origin timestamp ourselves.
-    /// Timestamp of the last packet that we sent
-    last_send_timestamp: Option<NtpTimestamp>,
+    /// Timestamps of the last packets that we sent, newest first
+    send_timestamps: [Option<NtpTimestamp>; REMEMBERED_TRIES],
+
+    /// Number of poll exchanges started with this source
+    exchange: u64,
+    /// Span covering the current poll exchange, from sending the request up to the
+    /// steering decision the system makes based on the response
+    poll_span: Span,
 }
 
 #[derive(Debug)]
@@ -91,17 +114,26 @@ where
     T: Wait,
 {
     async fn setup_socket(&mut self) -> SocketResult {
-        let socket_res = match self.interface {
+        let socket_res = match self.binding {
             #[cfg(target_os = "linux")]
-            Some(interface) => {
-                open_interface_udp(
-                    interface,
-                    0, /*lets os choose*/
-                    self.timestamp_mode.as_interface_mode(),
-                    None,
-                )
-                .and_then(|socket| socket.connect(self.source_addr))
-            }
+            SocketBinding {
+                interface: Some(interface),
+                ..
+            } => open_interface_udp(
+                interface,
+                0, /*lets os choose*/
+                self.timestamp_mode.as_interface_mode(),
+                None,
+            )
+            .and_then(|socket| socket.connect(self.source_addr)),
+            SocketBinding {
+                source_address: Some(source_address),
+                ..
+            } => open_ip(
+                SocketAddr::new(source_address, 0),
+                self.timestamp_mode.as_general_mode(),
+            )
+            .and_then(|socket| socket.connect(self.source_addr)),
             _ => connect_address(self.source_addr, self.timestamp_mode.as_general_mode()),
         };
 
@@ -116,14 +148,56 @@ where
         SocketResult::Ok
     }
 
+    /// Postpone the next NAT keepalive to a full interval from now
+    fn schedule_keepalive(&mut self) {
+        self.next_keepalive = self
+            .binding
+            .nat_keepalive
+            .and_then(|interval| Instant::now().checked_add(interval));
+    }
+
+    fn observe(&self) -> ObservableSourceState<SourceId> {
+        ObservableSourceState {
+            transport: self.binding.transport,
+            ..self.source.observe(self.name.clone(), self.index)
+        }
+    }
+
+    fn handle_response(
+        &mut self,
+        packet: &[u8],
+        recv_timestamp: NtpTimestamp,
+    ) -> NtpSourceActionIterator<Controller::SourceMessage> {
+        let actions = self.poll_span.in_scope(|| {
+            self.source.handle_incoming(
+                packet,
+                NtpInstant::now(),
+                &self.send_timestamps,
+                recv_timestamp,
+            )
+        });
+        self.channels
+            .observations
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .insert(self.index, self.observe());
+        actions
+    }
+
     async fn run(&mut self, mut poll_wait: Pin<&mut T>) {
         loop {
             let mut buf = [0_u8; 1024];
+            let mut previous_buf = [0_u8; 1024];
 
             #[allow(clippy::large_enum_variant)]
             enum SelectResult<Controller: SourceController> {
                 Timer,
+                EnabledChanged,
+                Keepalive,
                 Recv(Result<RecvResult<SocketAddr>, std::io::Error>),
+                PreviousRecv(Result<RecvResult<SocketAddr>, std::io::Error>),
+                StreamRecv(Result<(Vec<u8>, NtpTimestamp), std::io::Error>),
+                SharedRecv(Option<(Vec<u8>, NtpTimestamp)>),
                 SystemUpdate(
                     Result<
                         SystemSourceUpdate<Controller::ControllerMessage>,
@@ -132,16 +206,34 @@ where
                 ),
             }
 
+            let enabled = *self.enabled.borrow();
+            let clock = &self.clock;
+            let next_keepalive = self.next_keepalive;
             let selected: SelectResult<Controller> = tokio::select! {
-                () = &mut poll_wait => {
+                () = &mut poll_wait, if enabled => {
                     SelectResult::Timer
                 },
+                Ok(()) = self.enabled.changed() => {
+                    SelectResult::EnabledChanged
+                },
+                () = async { if let Some(deadline) = next_keepalive { tokio::time::sleep_until(deadline).await } else { std::future::pending().await }}, if enabled => {
+                    SelectResult::Keepalive
+                },
                 result = self.channels.system_update_receiver.recv() => {
                     SelectResult::SystemUpdate(result)
                 },
                 result = async { if let Some(ref mut socket) = self.socket { socket.recv(&mut buf).await } else { std::future::pending().await }} => {
                     SelectResult::Recv(result)
                 },
+                result = async { if let Some(ref mut socket) = self.previous_socket { socket.recv(&mut previous_buf).await } else { std::future::pending().await }} => {
+                    SelectResult::PreviousRecv(result)
+                },
+                result = async { if let Some(ref mut stream) = self.stream { stream.recv(clock).await } else { std::future::pending().await }} => {
+                    SelectResult::StreamRecv(result)
+                },
+                result = async { if let Some(ref mut shared) = self.shared { shared.recv().await } else { std::future::pending().await }} => {
+                    SelectResult::SharedRecv(result)
+                },
             };
 
             let actions = match selected {
@@ -149,30 +241,7 @@ where
                     tracing::debug!("accept packet");
                     match accept_packet(result, &buf, &self.clock) {
                         AcceptResult::Accept(packet, recv_timestamp) => {
-                            let send_timestamp = match self.last_send_timestamp {
-                                Some(ts) => ts,
-                                None => {
-                                    debug!(
-                                        "we received a message without having sent one; discarding"
-                                    );
-                                    continue;
-                                }
-                            };
-                            let actions = self.source.handle_incoming(
-                                packet,
-                                NtpInstant::now(),
-                                send_timestamp,
-                                recv_timestamp,
-                            );
-                            self.channels
-                                .source_snapshots
-                                .write()
-                                .expect("Unexpected poisoned mutex")
-                                .insert(
-                                    self.index,
-                                    self.source.observe(self.name.clone(), self.index),
-                                );
-                            actions
+                            self.handle_response(packet, recv_timestamp)
                         }
                         AcceptResult::NetworkGone => {
                             self.channels
@@ -181,7 +250,7 @@ where
                                 .await
                                 .ok();
                             self.channels
-                                .source_snapshots
+                                .observations
                                 .write()
                                 .expect("Unexpected poisoned mutex")
                                 .remove(&self.index);
@@ -190,30 +259,86 @@ where
                         AcceptResult::Ignore => NtpSourceActionIterator::default(),
                     }
                 }
+                SelectResult::PreviousRecv(result) => {
+                    match accept_packet(result, &previous_buf, &self.clock) {
+                        AcceptResult::Accept(packet, recv_timestamp) => {
+                            self.handle_response(packet, recv_timestamp)
+                        }
+                        AcceptResult::NetworkGone => {
+                            // the current socket notices this as well
+                            self.previous_socket = None;
+                            NtpSourceActionIterator::default()
+                        }
+                        AcceptResult::Ignore => NtpSourceActionIterator::default(),
+                    }
+                }
+                SelectResult::StreamRecv(result) => match result {
+                    Ok((packet, recv_timestamp)) if packet.len() >= 48 => {
+                        self.handle_response(&packet, recv_timestamp)
+                    }
+                    Ok((packet, _)) => {
+                        debug!(
+                            expected = 48,
+                            actual = packet.len(),
+                            "received packet is too small"
+                        );
+                        NtpSourceActionIterator::default()
+                    }
+                    Err(error) => {
+                        warn!(?error, "connection to source lost");
+                        NtpSourceActionIterator::default()
+                    }
+                },
+                SelectResult::SharedRecv(Some((packet, recv_timestamp))) => {
+                    self.handle_response(&packet, recv_timestamp)
+                }
+                SelectResult::SharedRecv(None) => {
+                    warn!("shared socket of the source is gone");
+                    self.channels
+                        .msg_for_system_sender
+                        .send(MsgForSystem::NetworkIssue(self.index))
+                        .await
+                        .ok();
+                    self.channels
+                        .observations
+                        .write()
+                        .expect("Unexpected poisoned mutex")
+                        .remove(&self.index);
+                    return;
+                }
                 SelectResult::Timer => {
                     tracing::debug!("wait completed");
                     let actions = self.source.handle_timer();
                     self.channels
-                        .source_snapshots
+                        .observations
                         .write()
                         .expect("Unexpected poisoned mutex")
-                        .insert(
-                            self.index,
-                            self.source.observe(self.name.clone(), self.index),
-                        );
+                        .insert(self.index, self.observe());
                     actions
                 }
+                SelectResult::EnabledChanged => {
+                    debug!(enabled = *self.enabled.borrow(), "source toggled");
+                    NtpSourceActionIterator::default()
+                }
+                SelectResult::Keepalive => {
+                    // A single byte is too short to be taken for an NTP packet,
+                    // servers drop it without a response.
+                    if let Some(socket) = &mut self.socket
+                        && let Err(error) = socket.send(&[0]).await
+                    {
+                        debug!(?error, "nat keepalive could not be sent");
+                    }
+                    self.schedule_keepalive();
+                    NtpSourceActionIterator::default()
+                }
                 SelectResult::SystemUpdate(result) => match result {
                     Ok(update) => {
                         let actions = self.source.handle_system_update(update);
                         self.channels
-                            .source_snapshots
+                            .observations
                             .write()
                             .expect("Unexpected poisoned mutex")
-                            .insert(
-                                self.index,
-                                self.source.observe(self.name.clone(), self.index),
-                            );
+                            .insert(self.index, self.observe());
                         actions
                     }
                     Err(_) => NtpSourceActionIterator::default(),
@@ -223,14 +348,60 @@ where
             for action in actions {
                 match action {
                     ntp_proto::NtpSourceAction::Send(packet) => {
-                        if matches!(self.setup_socket().await, SocketResult::Abort) {
+                        self.exchange += 1;
+                        self.poll_span = info_span!("poll", exchange = self.exchange);
+                        // the source remembers its requests the same way
+                        self.send_timestamps.rotate_right(1);
+                        self.send_timestamps[0] = None;
+
+                        if let Some(stream) = &mut self.stream {
+                            let send_result = stream
+                                .send(self.source_addr, self.binding, &packet, &self.clock)
+                                .instrument(self.poll_span.clone())
+                                .await;
+                            match send_result {
+                                Ok(send_timestamp) => {
+                                    self.send_timestamps[0] = Some(send_timestamp)
+                                }
+                                Err(error) => {
+                                    // the poll remains unanswered, the next one connects again
+                                    warn!(parent: &self.poll_span, ?error, "poll message could not be sent");
+                                }
+                            }
+                            continue;
+                        }
+
+                        if let Some(shared) = &mut self.shared {
+                            let send_result = shared
+                                .send(&packet)
+                                .instrument(self.poll_span.clone())
+                                .await;
+                            match send_result {
+                                Ok(send_timestamp) => {
+                                    self.send_timestamps[0] = Some(send_timestamp)
+                                }
+                                Err(error) => {
+                                    warn!(parent: &self.poll_span, ?error, "poll message could not be sent");
+                                }
+                            }
+                            continue;
+                        }
+
+                        // the socket is only kept across polls when configured, or to
+                        // keep its NAT mapping alive
+                        if !self.keep_socket && self.binding.nat_keepalive.is_none() {
+                            self.previous_socket = self.socket.take();
+                        }
+                        if self.socket.is_none()
+                            && matches!(self.setup_socket().await, SocketResult::Abort)
+                        {
                             self.channels
                                 .msg_for_system_sender
                                 .send(MsgForSystem::NetworkIssue(self.index))
                                 .await
                                 .ok();
                             self.channels
-                                .source_snapshots
+                                .observations
                                 .write()
                                 .expect("Unexpected poisoned mutex")
                                 .remove(&self.index);
@@ -246,26 +417,34 @@ where
                                 std::process::exit(exitcode::NOPERM);
                             }
                             Ok(ts) => {
-                                self.last_send_timestamp = Some(ts);
+                                self.send_timestamps[0] = Some(ts);
                             }
                         }
 
-                        match self.socket.as_mut().unwrap().send(&packet).await {
+                        let send_result = self
+                            .socket
+                            .as_mut()
+                            .unwrap()
+                            .send(&packet)
+                            .instrument(self.poll_span.clone())
+                            .await;
+                        match send_result {
                             Err(error) => {
-                                warn!(?error, "poll message could not be sent");
+                                warn!(parent: &self.poll_span, ?error, "poll message could not be sent");
 
                                 match error.raw_os_error() {
                                     Some(libc::EHOSTDOWN)
                                     | Some(libc::EHOSTUNREACH)
                                     | Some(libc::ENETDOWN)
-                                    | Some(libc::ENETUNREACH) => {
+                                    | Some(libc::ENETUNREACH)
+                                    | Some(libc::ENODEV) => {
                                         self.channels
                                             .msg_for_system_sender
                                             .send(MsgForSystem::NetworkIssue(self.index))
                                             .await
                                             .ok();
                                         self.channels
-                                            .source_snapshots
+                                            .observations
                                             .write()
                                             .expect("Unexpected poisoned mutex")
                                             .remove(&self.index);
@@ -275,17 +454,23 @@ where
                                 }
                             }
                             Ok(opt_send_timestamp) => {
-                                // update the last_send_timestamp with the one given by the kernel, if available
-                                self.last_send_timestamp = opt_send_timestamp
+                                // update the send timestamp with the one given by the kernel, if available
+                                self.send_timestamps[0] = opt_send_timestamp
                                     .map(convert_net_timestamp)
-                                    .or(self.last_send_timestamp);
+                                    .or(self.send_timestamps[0]);
                             }
                         }
+
+                        self.schedule_keepalive();
                     }
                     ntp_proto::NtpSourceAction::UpdateSystem(update) => {
                         self.channels
                             .msg_for_system_sender
-                            .send(MsgForSystem::SourceUpdate(self.index, update))
+                            .send(MsgForSystem::SourceUpdate(
+                                self.index,
+                                update,
+                                self.poll_span.clone(),
+                            ))
                             .await
                             .ok();
                     }
@@ -302,7 +487,7 @@ where
                             .await
                             .ok();
                         self.channels
-                            .source_snapshots
+                            .observations
                             .write()
                             .expect("Unexpected poisoned mutex")
                             .remove(&self.index);
@@ -315,7 +500,7 @@ where
                             .await
                             .ok();
                         self.channels
-                            .source_snapshots
+                            .observations
                             .write()
                             .expect("Unexpected poisoned mutex")
                             .remove(&self.index);
@@ -333,15 +518,19 @@ where
     C: 'static + NtpClock + Send + Sync,
 {
     #[allow(clippy::too_many_arguments)]
-    #[instrument(level = tracing::Level::ERROR, name = "Ntp Source", skip(timestamp_mode, clock, channels, source, initial_actions))]
+    #[instrument(level = tracing::Level::ERROR, name = "Ntp Source", skip(timestamp_mode, clock, stream, shared, channels, enabled, source, initial_actions))]
     pub fn spawn(
         index: SourceId,
         name: String,
         source_addr: SocketAddr,
-        interface: Option<InterfaceName>,
+        binding: SocketBinding,
         clock: C,
         timestamp_mode: TimestampMode,
+        stream: Option<StreamClient>,
+        shared: Option<SharedSocketClient>,
+        keep_socket: bool,
         channels: SourceChannels<Controller::ControllerMessage, Controller::SourceMessage>,
+        enabled: tokio::sync::watch::Receiver<bool>,
         source: NtpSource<Controller>,
         initial_actions: NtpSourceActionIterator<Controller::SourceMessage>,
     ) -> tokio::task::JoinHandle<()> {
@@ -370,18 +559,33 @@ where
                     }
                 }
 
+                if let Some(stream) = &stream {
+                    warn!(
+                        transport = ?stream.transport(),
+                        "Polling the source over a connection instead of UDP, its measurements are less accurate"
+                    );
+                }
+
                 let mut process = SourceTask {
                     _wait: PhantomData,
                     index,
                     name,
                     clock,
                     channels,
-                    interface,
+                    binding,
                     timestamp_mode,
                     source_addr,
                     socket: None,
+                    previous_socket: None,
+                    stream,
+                    shared,
+                    keep_socket,
+                    next_keepalive: None,
+                    enabled,
                     source,
-                    last_send_timestamp: None,
+                    send_timestamps: Default::default(),
+                    exchange: 0,
+                    poll_span: Span::none(),
                 };
 
                 process.run(poll_wait).await;
@@ -441,7 +645,8 @@ fn accept_packet<'a, C: NtpClock>(
                 Some(libc::EHOSTDOWN)
                 | Some(libc::EHOSTUNREACH)
                 | Some(libc::ENETDOWN)
-                | Some(libc::ENETUNREACH) => AcceptResult::NetworkGone,
+                | Some(libc::ENETUNREACH)
+                | Some(libc::ENODEV) => AcceptResult::NetworkGone,
                 _ => AcceptResult::Ignore,
             }
         }
@@ -465,7 +670,10 @@ mod tests {
     use timestamped_socket::socket::{GeneralTimestampMode, Open, open_ip};
     use tokio::sync::{broadcast, mpsc};
 
-    use crate::{daemon::util::EPOCH_OFFSET, test::alloc_port};
+    use crate::{
+        daemon::{shared_socket::SharedSockets, util::EPOCH_OFFSET},
+        test::alloc_port,
+    };
 
     use super::*;
 
@@ -613,10 +821,12 @@ mod tests {
 
         let Ok((source, _)) = system.create_ntp_source(
             index,
+            "test",
             SourceConfig::default(),
             SocketAddr::from((Ipv4Addr::LOCALHOST, port_base)),
             ProtocolVersion::V4,
             None,
+            None,
         ) else {
             panic!("Could not create test source");
         };
@@ -629,14 +839,22 @@ mod tests {
             channels: SourceChannels {
                 msg_for_system_sender,
                 system_update_receiver,
-                source_snapshots: Arc::new(RwLock::new(HashMap::new())),
+                observations: Arc::new(RwLock::new(Observations::default())),
             },
             source_addr: SocketAddr::from((Ipv4Addr::LOCALHOST, port_base)),
-            interface: None,
+            binding: SocketBinding::default(),
             timestamp_mode: TimestampMode::KernelRecv,
             socket: None,
+            previous_socket: None,
+            stream: None,
+            shared: None,
+            keep_socket: false,
+            next_keepalive: None,
+            enabled: tokio::sync::watch::channel(true).1,
             source,
-            last_send_timestamp: None,
+            send_timestamps: Default::default(),
+            exchange: 0,
+            poll_span: Span::none(),
         };
 
         (
@@ -668,6 +886,59 @@ mod tests {
         handle.abort();
     }
 
+    #[tokio::test]
+    async fn test_kept_socket_is_reused() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _, _system_update_sender) = test_startup().await;
+        process.keep_socket = true;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        let mut buf = [0; 48];
+        poll_send.notify();
+        let first = socket.recv(&mut buf).await.unwrap();
+        poll_send.notify();
+        let second = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(first.remote_addr, second.remote_addr);
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_disabled_source_does_not_poll() {
+        // Note: Ports must be unique among tests to deal with parallelism
+        let (mut process, socket, _, _system_update_sender) = test_startup().await;
+
+        let (enabled_sender, enabled) = tokio::sync::watch::channel(false);
+        process.enabled = enabled;
+
+        let (poll_wait, poll_send) = TestWait::new();
+
+        let handle = tokio::spawn(async move {
+            tokio::pin!(poll_wait);
+            process.run(poll_wait).await;
+        });
+
+        poll_send.notify();
+
+        let mut buf = [0; 48];
+        let timeout =
+            tokio::time::timeout(std::time::Duration::from_millis(50), socket.recv(&mut buf)).await;
+        assert!(timeout.is_err());
+
+        // the pending poll goes out as soon as the source is enabled again
+        enabled_sender.send(true).unwrap();
+        let network = socket.recv(&mut buf).await.unwrap();
+        assert_eq!(network.bytes_read, 48);
+
+        handle.abort();
+    }
+
     fn serialize_packet_unencrypted(send_packet: &NtpPacket) -> [u8; 48] {
         let mut buf = [0; 48];
         let mut cursor = Cursor::new(buf.as_mut_slice());
@@ -722,7 +993,7 @@ mod tests {
         socket.send_to(&serialized, remote_addr).await.unwrap();
 
         let msg = msg_recv.recv().await.unwrap();
-        assert!(matches!(msg, MsgForSystem::SourceUpdate(_, _)));
+        assert!(matches!(msg, MsgForSystem::SourceUpdate(_, _, _)));
 
         handle.abort();
     }
@@ -779,4 +1050,148 @@ mod tests {
 
         handle.abort();
     }
+
+    /// Answer every request as a server, until receiving fails
+    async fn serve(mut socket: Socket<SocketAddr, Open>) {
+        let system = SystemSnapshot {
+            time_snapshot: TimeSnapshot {
+                leap_indicator: NtpLeapIndicator::NoWarning,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let clock = TestClock {};
+
+        let mut buf = [0; 48];
+        while let Ok(RecvResult {
+            bytes_read,
+            timestamp,
+            remote_addr,
+        }) = socket.recv(&mut buf).await
+        {
+            let Ok((request, _)) = NtpPacket::deserialize(&buf[..bytes_read], &NoCipher) else {
+                continue;
+            };
+            let recv_timestamp = match timestamp {
+                Some(timestamp) => convert_net_timestamp(timestamp),
+                None => clock.now().unwrap(),
+            };
+            let response = NtpPacket::timestamp_response(&system, request, recv_timestamp, &clock);
+            let serialized = serialize_packet_unencrypted(&response);
+            let _ = socket.send_to(&serialized, remote_addr).await;
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored bench_` to check that this
+    /// many sources on shared sockets get their polls answered in time
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark"]
+    async fn bench_shared_socket_sources() {
+        const SOURCES: usize = 5000;
+        const POLLS: usize = 10;
+
+        let server_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, alloc_port()));
+        let server = open_ip(server_addr, GeneralTimestampMode::SoftwareRecv).unwrap();
+        tokio::spawn(serve(server));
+
+        let (system_update_sender, _) = broadcast::channel(1);
+        let (msg_for_system_sender, mut msg_recv) = mpsc::channel(SOURCES);
+        let observations = Arc::new(RwLock::new(Observations::default()));
+        let mut system: ntp_proto::System<_, KalmanClockController<_, _>> = ntp_proto::System::new(
+            TestClock {},
+            SynchronizationConfig::default(),
+            AlgorithmConfig::default(),
+            Arc::new([]),
+        )
+        .unwrap();
+        let mut shared_sockets = SharedSockets::new(4, TimestampMode::KernelRecv);
+
+        let mut polls = vec![];
+        let mut handles = vec![];
+        for _ in 0..SOURCES {
+            let index = SourceId::new();
+            let Ok((source, _)) = system.create_ntp_source(
+                index,
+                "bench",
+                SourceConfig::default(),
+                server_addr,
+                ProtocolVersion::V4,
+                None,
+                None,
+            ) else {
+                panic!("Could not create bench source");
+            };
+
+            let mut process: SourceTask<_, TwoWayKalmanSourceController<SourceId>, _> =
+                SourceTask {
+                    _wait: PhantomData,
+                    index,
+                    name: "bench".into(),
+                    clock: TestClock {},
+                    channels: SourceChannels {
+                        msg_for_system_sender: msg_for_system_sender.clone(),
+                        system_update_receiver: system_update_sender.subscribe(),
+                        observations: observations.clone(),
+                    },
+                    source_addr: server_addr,
+                    binding: SocketBinding::default(),
+                    timestamp_mode: TimestampMode::KernelRecv,
+                    socket: None,
+                    previous_socket: None,
+                    stream: None,
+                    shared: shared_sockets.client(
+                        server_addr,
+                        SocketBinding::default(),
+                        TestClock {},
+                    ),
+                    keep_socket: false,
+                    next_keepalive: None,
+                    enabled: tokio::sync::watch::channel(true).1,
+                    source,
+                    send_timestamps: Default::default(),
+                    exchange: 0,
+                    poll_span: Span::none(),
+                };
+            assert!(process.shared.is_some());
+
+            let (poll_wait, poll_send) = TestWait::new();
+            handles.push(tokio::spawn(async move {
+                tokio::pin!(poll_wait);
+                process.run(poll_wait).await;
+            }));
+            polls.push(poll_send);
+        }
+
+        let start = std::time::Instant::now();
+        let mut answered = 0;
+        for _ in 0..POLLS {
+            for poll in &polls {
+                poll.notify();
+            }
+
+            // a poll round ends when every source has its answer, or when
+            // nothing arrives for a second
+            let mut round = 0;
+            while round < SOURCES {
+                match tokio::time::timeout(Duration::from_secs(1), msg_recv.recv()).await {
+                    Ok(Some(MsgForSystem::SourceUpdate(..))) => round += 1,
+                    Ok(Some(_)) => {}
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            answered += round;
+        }
+        let elapsed = start.elapsed();
+
+        for handle in handles {
+            handle.abort();
+        }
+
+        assert!(
+            answered >= SOURCES * POLLS * 99 / 100,
+            "{answered} of {} polls answered in {elapsed:?} ({:.0} polls/s)",
+            SOURCES * POLLS,
+            answered as f64 / elapsed.as_secs_f64()
+        );
+    }
 }
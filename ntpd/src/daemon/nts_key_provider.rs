@@ -1,7 +1,7 @@
 use std::{
     fs::{File, OpenOptions},
     os::unix::prelude::{OpenOptionsExt, PermissionsExt},
-    sync::Arc,
+    sync::{Arc, mpsc},
 };
 
 use ntp_proto::{KeySet, KeySetProvider};
@@ -10,8 +10,21 @@ use tracing::{Span, instrument, warn};
 
 use super::config::KeysetConfig;
 
+/// Rotates the keys of the key provider until it is stopped or dropped
+#[derive(Debug)]
+pub struct KeyRotation {
+    stop: mpsc::Sender<()>,
+}
+
+impl KeyRotation {
+    /// Stop rotating and storing the keys, the current keys stay valid
+    pub fn stop(&self) {
+        let _ = self.stop.send(());
+    }
+}
+
 #[instrument(level = tracing::Level::ERROR, name = "KeySet Provider", skip_all, fields(path = debug(config.key_storage_path.clone())))]
-pub async fn spawn(config: KeysetConfig) -> watch::Receiver<Arc<KeySet>> {
+pub async fn spawn(config: KeysetConfig) -> (watch::Receiver<Arc<KeySet>>, KeyRotation) {
     let (mut provider, mut next_interval) = match &config.key_storage_path {
         Some(path) => {
             let path = path.to_owned();
@@ -58,6 +71,7 @@ pub async fn spawn(config: KeysetConfig) -> watch::Receiver<Arc<KeySet>> {
         ),
     };
     let (tx, rx) = watch::channel(provider.get());
+    let (stop, stopped) = mpsc::channel();
     let span = Span::current();
     tokio::task::spawn_blocking(move || {
         let _enter = span.enter();
@@ -85,10 +99,15 @@ pub async fn spawn(config: KeysetConfig) -> watch::Receiver<Arc<KeySet>> {
             if tx.send(provider.get()).is_err() {
                 break;
             }
-            std::thread::sleep(next_interval);
+            // the blocking thread would otherwise keep the runtime from
+            // shutting down for as long as a key lasts
+            match stopped.recv_timeout(next_interval) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
             next_interval = std::time::Duration::from_secs(config.key_rotation_interval as _);
             provider.rotate();
         }
     });
-    rx
+    (rx, KeyRotation { stop })
 }
@@ -1,9 +1,12 @@
+use super::census::CensusReport;
+use super::diversity::Diversity;
+use super::leap_seconds::LeapSecondsStatus;
 use super::server::ServerStats;
-use super::sockets::create_unix_socket_with_permissions;
-use super::spawn::SourceId;
+use super::sockets::{Listener, SocketLocation};
+use super::spawn::{SourceFailure, SourceId, SpawnerId};
 use super::system::ServerData;
 use libc::{ECONNABORTED, EMFILE, ENFILE, ENOBUFS, ENOMEM};
-use ntp_proto::{NtpClock, NtpTimestamp, ObservableSourceState, SystemSnapshot};
+use ntp_proto::{NtpClock, NtpTimestamp, ObservableSourceState, SelectionStatus, SystemSnapshot};
 use std::collections::HashMap;
 use std::os::unix::fs::PermissionsExt;
 use std::sync::Arc;
@@ -19,6 +22,105 @@ pub struct ObservableState {
     pub system: SystemSnapshot,
     pub sources: Vec<ObservableSourceState<SourceId>>,
     pub servers: Vec<ObservableServerState>,
+    /// Epoch of the observations the sources and system state were taken from
+    #[serde(default)]
+    pub epoch: u64,
+    /// Names of the sources that were disabled at runtime
+    #[serde(default)]
+    pub disabled_sources: Vec<String>,
+    /// Leap seconds from the leap seconds file, when one is configured
+    #[serde(default)]
+    pub leap_seconds: Option<LeapSecondsStatus>,
+    /// How independent the sources steering the clock are
+    #[serde(default)]
+    pub diversity: Diversity,
+    /// Sources that stayed unreachable on all of their addresses
+    #[serde(default)]
+    pub failed_sources: Vec<SourceFailure>,
+}
+
+/// Latest state of all sources and the system, shared with the observer.
+///
+/// Everything in here is updated under a single lock, so a reader always sees
+/// source and system state that belong together. Every change bumps the epoch.
+#[derive(Debug, Default)]
+pub struct Observations {
+    epoch: u64,
+    sources: HashMap<SourceId, ObservableSourceState<SourceId>>,
+    system: SystemSnapshot,
+    selection: HashMap<SourceId, SelectionStatus>,
+    leap_seconds: Option<LeapSecondsStatus>,
+    diversity: Diversity,
+    algorithm: &'static str,
+    config_hash: Option<String>,
+    failed_sources: HashMap<SpawnerId, SourceFailure>,
+}
+
+impl Observations {
+    pub fn new(system: SystemSnapshot, algorithm: &'static str) -> Self {
+        Observations {
+            system,
+            algorithm,
+            ..Default::default()
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn insert(&mut self, id: SourceId, state: ObservableSourceState<SourceId>) {
+        self.epoch += 1;
+        self.sources.insert(id, state);
+    }
+
+    pub fn remove(&mut self, id: &SourceId) {
+        self.epoch += 1;
+        self.sources.remove(id);
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = &ObservableSourceState<SourceId>> {
+        self.sources.values()
+    }
+
+    pub fn system(&self) -> &SystemSnapshot {
+        &self.system
+    }
+
+    pub fn update_system(
+        &mut self,
+        system: SystemSnapshot,
+        selection: HashMap<SourceId, SelectionStatus>,
+    ) {
+        self.epoch += 1;
+        self.system = system;
+        self.selection = selection;
+    }
+
+    pub fn set_leap_seconds(&mut self, leap_seconds: Option<LeapSecondsStatus>) {
+        self.epoch += 1;
+        self.leap_seconds = leap_seconds;
+    }
+
+    pub fn set_diversity(&mut self, diversity: Diversity) {
+        self.epoch += 1;
+        self.diversity = diversity;
+    }
+
+    pub fn set_config_hash(&mut self, config_hash: Option<String>) {
+        self.epoch += 1;
+        self.config_hash = config_hash;
+    }
+
+    /// Record that recovering the source of a spawner failed, or clear that
+    /// once it is reachable again
+    pub fn set_source_failure(&mut self, spawner: SpawnerId, failure: Option<SourceFailure>) {
+        self.epoch += 1;
+        match failure {
+            Some(failure) => self.failed_sources.insert(spawner, failure),
+            None => self.failed_sources.remove(&spawner),
+        };
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,6 +130,19 @@ pub struct ProgramData {
     pub build_commit_date: String,
     pub uptime_seconds: f64,
     pub now: NtpTimestamp,
+    /// Name of the daemon instance from the configuration
+    #[serde(default)]
+    pub instance_name: Option<String>,
+    /// Optional features the daemon was built with
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Algorithm steering the clock
+    #[serde(default)]
+    pub algorithm: String,
+    /// Hash of the configuration file in use, none without a configuration
+    /// file
+    #[serde(default)]
+    pub config_hash: Option<String>,
 }
 
 impl ProgramData {
@@ -48,21 +163,50 @@ impl Default for ProgramData {
             build_commit_date: env!("NTPD_RS_GIT_DATE").to_owned(),
             uptime_seconds: 0.0,
             now: NtpTimestamp::default(),
+            instance_name: None,
+            features: FEATURES.iter().map(|&feature| feature.to_owned()).collect(),
+            algorithm: String::new(),
+            config_hash: None,
         }
     }
 }
 
+/// Optional features of this build
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "hardware-timestamping")]
+    "hardware-timestamping",
+    #[cfg(feature = "pps")]
+    "pps",
+    #[cfg(feature = "nts-server")]
+    "nts-server",
+    #[cfg(feature = "metrics-exporter")]
+    "metrics-exporter",
+    #[cfg(feature = "fleet")]
+    "fleet",
+];
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ObservableServerState {
     pub address: SocketAddr,
+    #[serde(default)]
+    pub label: Option<String>,
     pub stats: ServerStats,
+    /// Hourly client statistics, empty unless `client-census` is enabled
+    #[serde(default)]
+    pub census: Vec<CensusReport>,
 }
 
 impl From<&ServerData> for ObservableServerState {
     fn from(data: &ServerData) -> Self {
         ObservableServerState {
             address: data.config.listen,
+            label: data.config.label.clone(),
             stats: data.stats.clone(),
+            census: data
+                .census
+                .as_ref()
+                .map(|census| census.reports())
+                .unwrap_or_default(),
         }
     }
 }
@@ -70,16 +214,16 @@ impl From<&ServerData> for ObservableServerState {
 #[instrument(level = tracing::Level::ERROR, skip_all, name = "Observer", fields(path = debug(config.observation_path.clone())))]
 pub fn spawn<C: 'static + NtpClock + Send>(
     config: &super::config::ObservabilityConfig,
-    sources_reader: Arc<std::sync::RwLock<HashMap<SourceId, ObservableSourceState<SourceId>>>>,
+    observations: Arc<std::sync::RwLock<Observations>>,
     server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
-    system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
+    disabled_reader: tokio::sync::watch::Receiver<Vec<String>>,
     clock: C,
 ) -> JoinHandle<std::io::Result<()>> {
     let config = config.clone();
     tokio::spawn(
         (async move {
             let result =
-                observer(config, sources_reader, server_reader, system_reader, clock).await;
+                observer(config, observations, server_reader, disabled_reader, clock).await;
             if let Err(ref e) = result {
                 warn!("Abnormal termination of the state observer: {e}");
                 warn!("The state observer will not be available");
@@ -92,16 +236,16 @@ pub fn spawn<C: 'static + NtpClock + Send>(
 
 async fn observer<C: 'static + NtpClock + Send>(
     config: super::config::ObservabilityConfig,
-    sources_reader: Arc<std::sync::RwLock<HashMap<SourceId, ObservableSourceState<SourceId>>>>,
+    observations: Arc<std::sync::RwLock<Observations>>,
     server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
-    system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
+    disabled_reader: tokio::sync::watch::Receiver<Vec<String>>,
     clock: C,
 ) -> std::io::Result<()> {
     let start_time = Instant::now();
     let timeout = std::time::Duration::from_millis(500);
 
-    let path = match config.observation_path {
-        Some(path) => path,
+    let location = match config.observation_path {
+        Some(path) => SocketLocation::new(path, config.observation_address),
         None => return Ok(()),
     };
 
@@ -111,7 +255,7 @@ async fn observer<C: 'static + NtpClock + Send>(
     let permissions: std::fs::Permissions =
         PermissionsExt::from_mode(config.observation_permissions);
 
-    let observe_listener = create_unix_socket_with_permissions(&path, permissions)?;
+    let observe_listener = Listener::bind(&location, permissions).await?;
     let observe_permits = Arc::new(tokio::sync::Semaphore::new(8));
 
     loop {
@@ -120,7 +264,7 @@ async fn observer<C: 'static + NtpClock + Send>(
             .acquire_owned()
             .await
             .expect("Semaphore for observability was unexpectedly closed");
-        let (mut stream, _addr) = match observe_listener.accept().await {
+        let connection = match observe_listener.accept().await {
             Ok(a) => a,
             Err(e) if matches!(e.raw_os_error(), Some(ECONNABORTED)) => {
                 debug!("Unexpectedly closed unix socket: {e}");
@@ -143,18 +287,21 @@ async fn observer<C: 'static + NtpClock + Send>(
                 return Err(e);
             }
         };
-        let sources_reader = sources_reader.clone();
+        let observations = observations.clone();
         let server_reader = server_reader.clone();
-        let system_reader = system_reader.clone();
+        let disabled_reader = disabled_reader.clone();
+        let instance_name = config.instance_name.clone();
 
         let now = clock.now().expect("Unable to get current time");
         let fut = async move {
+            let mut stream = connection.authenticate().await?;
             handle_connection(
                 &mut stream,
                 start_time,
-                &sources_reader,
+                &observations,
                 server_reader,
-                system_reader,
+                disabled_reader,
+                instance_name,
                 now,
             )
             .await
@@ -163,6 +310,9 @@ async fn observer<C: 'static + NtpClock + Send>(
         tokio::spawn(async move {
             match tokio::time::timeout(timeout, fut).await {
                 Err(_) => debug!("Returning observability records timed out"),
+                Ok(Err(err)) if err.kind() == std::io::ErrorKind::PermissionDenied => {
+                    warn!("Rejected connection: {err}")
+                }
                 Ok(Err(err)) => warn!("error handling connection: {err}"),
                 Ok(_) => trace!("Returned observability records to connection"),
             }
@@ -174,36 +324,101 @@ async fn observer<C: 'static + NtpClock + Send>(
 async fn handle_connection(
     stream: &mut (impl tokio::io::AsyncWrite + Unpin),
     start_time: Instant,
-    sources_reader: &std::sync::RwLock<HashMap<SourceId, ObservableSourceState<SourceId>>>,
+    observations: &std::sync::RwLock<Observations>,
     server_reader: tokio::sync::watch::Receiver<Vec<ServerData>>,
-    system_reader: tokio::sync::watch::Receiver<SystemSnapshot>,
+    disabled_reader: tokio::sync::watch::Receiver<Vec<String>>,
+    instance_name: Option<String>,
     now: NtpTimestamp,
 ) -> std::io::Result<()> {
-    let observe = ObservableState {
-        program: ProgramData::with_dynamics(start_time.elapsed().as_secs_f64(), now),
-        sources: sources_reader
-            .read()
-            .expect("Unexpected poisoned mutex")
-            .values()
-            .cloned()
-            .collect(),
-        system: *system_reader.borrow(),
-        servers: server_reader.borrow().iter().map(|s| s.into()).collect(),
-    };
+    let observe = observe(
+        start_time,
+        observations,
+        &server_reader,
+        &disabled_reader,
+        instance_name,
+        now,
+    );
 
     super::sockets::write_json(stream, &observe).await?;
 
     Ok(())
 }
 
+/// The state of the daemon as shown on the observation socket
+pub(super) fn observe(
+    start_time: Instant,
+    observations: &std::sync::RwLock<Observations>,
+    server_reader: &tokio::sync::watch::Receiver<Vec<ServerData>>,
+    disabled_reader: &tokio::sync::watch::Receiver<Vec<String>>,
+    instance_name: Option<String>,
+    now: NtpTimestamp,
+) -> ObservableState {
+    // Copy everything out under a single read lock, so sources and system
+    // state are consistent with each other, and the lock is released before
+    // writing to the (possibly slow) client.
+    let (
+        sources,
+        system,
+        epoch,
+        leap_seconds,
+        diversity,
+        algorithm,
+        config_hash,
+        mut failed_sources,
+    ) = {
+        let observations = observations.read().expect("Unexpected poisoned mutex");
+        let sources = observations
+            .sources
+            .values()
+            .map(|source| ObservableSourceState {
+                selection: observations
+                    .selection
+                    .get(&source.id)
+                    .copied()
+                    .unwrap_or_default(),
+                ..source.clone()
+            })
+            .collect();
+        (
+            sources,
+            observations.system,
+            observations.epoch(),
+            observations.leap_seconds,
+            observations.diversity.clone(),
+            observations.algorithm,
+            observations.config_hash.clone(),
+            observations.failed_sources.values().cloned().collect(),
+        )
+    };
+
+    failed_sources.sort_by(|a: &SourceFailure, b| a.address.cmp(&b.address));
+
+    ObservableState {
+        program: ProgramData {
+            instance_name,
+            algorithm: algorithm.to_owned(),
+            config_hash,
+            ..ProgramData::with_dynamics(start_time.elapsed().as_secs_f64(), now)
+        },
+        sources,
+        system,
+        servers: server_reader.borrow().iter().map(|s| s.into()).collect(),
+        epoch,
+        disabled_sources: disabled_reader.borrow().clone(),
+        leap_seconds,
+        diversity,
+        failed_sources,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{borrow::BorrowMut, time::Duration};
 
     use ntp_proto::v5::{BloomFilter, ServerId};
     use ntp_proto::{
-        NtpDuration, NtpLeapIndicator, NtpTimestamp, PollIntervalLimits, Reach, ReferenceId,
-        TimeSnapshot,
+        HoldoverLimits, NtpDuration, NtpLeapIndicator, NtpTimestamp, PollIntervalLimits, Reach,
+        ReferenceId, TimeSnapshot,
     };
     use tokio::{io::AsyncReadExt, net::UnixStream};
 
@@ -258,53 +473,75 @@ mod tests {
             log_level: None,
             observation_path: Some(path.clone()),
             observation_permissions: 0o700,
+            instance_name: Some("tenant-a".into()),
             ..Default::default()
         };
 
-        let mut source_snapshots = HashMap::new();
+        let mut observations = Observations::default();
         let id = SourceId::new();
-        source_snapshots.insert(
+        observations.insert(
             id,
             ObservableSourceState {
                 timedata: Default::default(),
                 unanswered_polls: Reach::default().unanswered_polls(),
                 poll_interval: PollIntervalLimits::default().min,
                 nts_cookies: None,
+                stratum: 2,
+                reach: Reach::default(),
+                selection: Default::default(),
+                transport: Default::default(),
                 name: "127.0.0.3:123".into(),
                 address: "127.0.0.3:123".into(),
                 id,
             },
         );
 
-        let source_snapshots = Arc::new(std::sync::RwLock::new(source_snapshots));
+        observations.update_system(
+            SystemSnapshot {
+                stratum: 1,
+                reference_id: ReferenceId::NONE,
+                accumulated_steps_threshold: None,
+                time_snapshot: TimeSnapshot {
+                    precision: NtpDuration::from_seconds(1e-3),
+                    root_delay: NtpDuration::ZERO,
+                    root_variance_base_time: NtpTimestamp::default(),
+                    root_variance_base: 0.0,
+                    root_variance_linear: 0.0,
+                    root_variance_quadratic: 0.0,
+                    root_variance_cubic: 0.0,
+                    leap_indicator: NtpLeapIndicator::Leap59,
+                    accumulated_steps: NtpDuration::ZERO,
+                    adjustment: None,
+                },
+                bloom_filter: BloomFilter::new(),
+                server_id: ServerId::default(),
+                holdover: false,
+                holdover_limits: HoldoverLimits::default(),
+            },
+            HashMap::from([(id, SelectionStatus::Primary)]),
+        );
+        observations.set_config_hash(Some("0123456789abcdef".into()));
+        let failure = SourceFailure {
+            address: "example.com:123".into(),
+            failures: 2,
+            backoff_seconds: 8,
+        };
+        let failed_spawner = SpawnerId::new();
+        observations.set_source_failure(failed_spawner, Some(failure.clone()));
+        observations.set_source_failure(SpawnerId::new(), None);
+        let epoch = observations.epoch();
 
-        let (_, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let observations = Arc::new(std::sync::RwLock::new(observations));
 
-        let (_, system_reader) = tokio::sync::watch::channel(SystemSnapshot {
-            stratum: 1,
-            reference_id: ReferenceId::NONE,
-            accumulated_steps_threshold: None,
-            time_snapshot: TimeSnapshot {
-                precision: NtpDuration::from_seconds(1e-3),
-                root_delay: NtpDuration::ZERO,
-                root_variance_base_time: NtpTimestamp::default(),
-                root_variance_base: 0.0,
-                root_variance_linear: 0.0,
-                root_variance_quadratic: 0.0,
-                root_variance_cubic: 0.0,
-                leap_indicator: NtpLeapIndicator::Leap59,
-                accumulated_steps: NtpDuration::ZERO,
-            },
-            bloom_filter: BloomFilter::new(),
-            server_id: ServerId::default(),
-        });
+        let (_, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_, disabled_reader) = tokio::sync::watch::channel(vec![]);
 
         let handle = tokio::spawn(async move {
             observer(
                 config,
-                source_snapshots,
+                observations,
                 servers_reader,
-                system_reader,
+                disabled_reader,
                 TestClock,
             )
             .await
@@ -321,6 +558,16 @@ mod tests {
 
         // Deal with randomized order
         assert_eq!(result.sources.len(), 1);
+        assert_eq!(result.sources[0].selection, SelectionStatus::Primary);
+        assert_eq!(result.system.stratum, 1);
+        assert_eq!(result.epoch, epoch);
+        assert_eq!(result.program.instance_name.as_deref(), Some("tenant-a"));
+        assert_eq!(
+            result.program.config_hash.as_deref(),
+            Some("0123456789abcdef")
+        );
+        assert_eq!(result.program.features, ProgramData::default().features);
+        assert_eq!(result.failed_sources, vec![failure]);
 
         handle.abort();
     }
@@ -336,51 +583,62 @@ mod tests {
             ..Default::default()
         };
 
-        let mut source_snapshots = HashMap::new();
+        let mut observations = Observations::default();
         let id = SourceId::new();
-        source_snapshots.insert(
+        observations.insert(
             id,
             ObservableSourceState {
                 timedata: Default::default(),
                 unanswered_polls: Reach::default().unanswered_polls(),
                 poll_interval: PollIntervalLimits::default().min,
                 nts_cookies: None,
+                stratum: 2,
+                reach: Reach::default(),
+                selection: Default::default(),
+                transport: Default::default(),
                 name: "127.0.0.3:123".into(),
                 address: "127.0.0.3:123".into(),
                 id,
             },
         );
 
-        let source_snapshots = Arc::new(std::sync::RwLock::new(source_snapshots));
-        let source_snapshots_clone = source_snapshots.clone();
+        observations.update_system(
+            SystemSnapshot {
+                stratum: 1,
+                reference_id: ReferenceId::NONE,
+                accumulated_steps_threshold: None,
+                time_snapshot: TimeSnapshot {
+                    precision: NtpDuration::from_seconds(1e-3),
+                    root_delay: NtpDuration::ZERO,
+                    root_variance_base_time: NtpTimestamp::default(),
+                    root_variance_base: 0.0,
+                    root_variance_linear: 0.0,
+                    root_variance_quadratic: 0.0,
+                    root_variance_cubic: 0.0,
+                    leap_indicator: NtpLeapIndicator::Leap59,
+                    accumulated_steps: NtpDuration::ZERO,
+                    adjustment: None,
+                },
+                bloom_filter: BloomFilter::new(),
+                server_id: ServerId::default(),
+                holdover: false,
+                holdover_limits: HoldoverLimits::default(),
+            },
+            HashMap::new(),
+        );
 
-        let (mut server_writer, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let observations = Arc::new(std::sync::RwLock::new(observations));
+        let observations_clone = observations.clone();
 
-        let (mut system_writer, system_reader) = tokio::sync::watch::channel(SystemSnapshot {
-            stratum: 1,
-            reference_id: ReferenceId::NONE,
-            accumulated_steps_threshold: None,
-            time_snapshot: TimeSnapshot {
-                precision: NtpDuration::from_seconds(1e-3),
-                root_delay: NtpDuration::ZERO,
-                root_variance_base_time: NtpTimestamp::default(),
-                root_variance_base: 0.0,
-                root_variance_linear: 0.0,
-                root_variance_quadratic: 0.0,
-                root_variance_cubic: 0.0,
-                leap_indicator: NtpLeapIndicator::Leap59,
-                accumulated_steps: NtpDuration::ZERO,
-            },
-            bloom_filter: BloomFilter::new(),
-            server_id: ServerId::default(),
-        });
+        let (mut server_writer, servers_reader) = tokio::sync::watch::channel(vec![]);
+        let (_, disabled_reader) = tokio::sync::watch::channel(vec![]);
 
         let handle = tokio::spawn(async move {
             observer(
                 config,
-                source_snapshots,
+                observations,
                 servers_reader,
-                system_reader,
+                disabled_reader,
                 TestClock,
             )
             .await
@@ -399,11 +657,10 @@ mod tests {
         reader.read_buf(&mut bufref).await.unwrap();
 
         // Ensure none of the locks is held long term
-        let _ = system_writer.borrow_mut();
-        let _ = source_snapshots_clone
+        let _ = observations_clone
             .write()
             .expect("Unexpected poisoned mutex")
-            .len();
+            .epoch();
         let _ = server_writer.borrow_mut();
 
         handle.abort();
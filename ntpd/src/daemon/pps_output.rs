@@ -0,0 +1,239 @@
+use std::{
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use ntp_proto::{NtpClock, NtpDuration, NtpLeapIndicator, NtpTimestamp};
+use tracing::{Span, debug, info, instrument, trace, warn};
+
+use super::{clock::NtpClockWrapper, config::PpsOutputConfig, observer::Observations, sandbox};
+
+// Sleeping is only accurate to tens of microseconds, so we wake up a bit before
+// the second boundary and wait for the last part by reading the clock.
+const SPIN_MARGIN: Duration = Duration::from_micros(500);
+
+/// Something that can be driven high or low, such as a GPIO line
+trait PulseOutput {
+    fn set(&mut self, high: bool) -> std::io::Result<()>;
+}
+
+/// A file to which the level is written as `1` or `0`, as done for the value
+/// of GPIO lines exported through sysfs
+struct ValueFile(std::fs::File);
+
+impl PulseOutput for ValueFile {
+    fn set(&mut self, high: bool) -> std::io::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        let value: &[u8] = if high { b"1" } else { b"0" };
+        self.0.write_all_at(value, 0)
+    }
+}
+
+/// The thread generating the PPS output
+pub struct PpsOutput {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl PpsOutput {
+    /// Stop generating pulses and wait for the thread to finish, which takes
+    /// up to a second
+    pub async fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let thread = self.thread;
+        let _ = tokio::task::spawn_blocking(move || thread.join()).await;
+    }
+}
+
+/// Generate a pulse at the start of every second of the steered clock
+///
+/// This runs on a separate thread, as the timing of the tokio runtime is too
+/// coarse to place the edges of the pulses.
+#[instrument(level = tracing::Level::ERROR, skip_all, fields(path = ?config.path), name = "PPS output")]
+pub fn spawn(
+    clock: NtpClockWrapper,
+    config: PpsOutputConfig,
+    observations: Arc<RwLock<Observations>>,
+) -> std::io::Result<PpsOutput> {
+    // the configuration only accepts widths below a second, but it may have
+    // been constructed by an application embedding the daemon
+    let pulse_width = Duration::try_from_secs_f64(config.pulse_width)
+        .ok()
+        .filter(|pulse_width| !pulse_width.is_zero() && *pulse_width < Duration::from_secs(1))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "pulse width of PPS output must be more than 0 and less than 1 second",
+            )
+        })?;
+
+    let span = Span::current();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let thread = std::thread::Builder::new()
+        .name("pps-output".into())
+        .spawn(move || {
+            sandbox::restrict_thread();
+            let _enter = span.enter();
+
+            let output = match std::fs::OpenOptions::new().write(true).open(&config.path) {
+                Ok(file) => ValueFile(file),
+                Err(e) => {
+                    warn!(error = ?e, "Could not open PPS output");
+                    return;
+                }
+            };
+
+            if let Err(e) = generate(
+                clock,
+                output,
+                pulse_width,
+                || is_synchronized(&observations),
+                &stopped,
+            ) {
+                warn!(error = %e, "Stopped generating PPS output");
+            }
+        })?;
+    Ok(PpsOutput { stop, thread })
+}
+
+fn is_synchronized(observations: &RwLock<Observations>) -> bool {
+    let leap_indicator = observations
+        .read()
+        .expect("Unexpected poisoned mutex")
+        .system()
+        .time_snapshot
+        .leap_indicator;
+
+    !matches!(
+        leap_indicator,
+        NtpLeapIndicator::Unknown | NtpLeapIndicator::Unsynchronized
+    )
+}
+
+fn generate(
+    clock: NtpClockWrapper,
+    mut output: impl PulseOutput,
+    pulse_width: Duration,
+    synchronized: impl Fn() -> bool,
+    stop: &AtomicBool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    output.set(false)?;
+
+    let mut was_synchronized = false;
+    'pulses: loop {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let now = clock.now()?;
+        let second = now.truncated_second_bits(0) + NtpDuration::from_seconds(1.0);
+        std::thread::sleep(until_next_second(now).saturating_sub(SPIN_MARGIN));
+        loop {
+            let remaining = second - clock.now()?;
+            if remaining <= NtpDuration::ZERO {
+                break;
+            }
+            if remaining > NtpDuration::from_system_duration(SPIN_MARGIN * 2) {
+                // the clock was stepped back while we were sleeping
+                continue 'pulses;
+            }
+            std::hint::spin_loop();
+        }
+
+        // Downstream devices should not lock to pulses that are not aligned to
+        // the actual time, so there are no pulses until the clock is synchronized
+        let in_sync = synchronized();
+        if in_sync != was_synchronized {
+            info!(synchronized = in_sync, "PPS output toggled");
+            was_synchronized = in_sync;
+        }
+        if !in_sync {
+            continue;
+        }
+
+        output.set(true)?;
+        let edge = clock.now()?;
+        trace!(error = edge_error(edge).to_seconds(), "PPS output pulse");
+
+        std::thread::sleep(pulse_width);
+        output.set(false)?;
+        debug!("PPS output pulse ended");
+    }
+}
+
+/// Time left until the next whole second
+fn until_next_second(now: NtpTimestamp) -> Duration {
+    let into_second = (now - now.truncated_second_bits(0)).to_seconds();
+    Duration::from_secs_f64((1.0 - into_second).clamp(0.0, 1.0))
+}
+
+/// Distance of the edge to the nearest whole second
+fn edge_error(edge: NtpTimestamp) -> NtpDuration {
+    let error = edge - edge.truncated_second_bits(0);
+    if error > NtpDuration::from_seconds(0.5) {
+        error - NtpDuration::from_seconds(1.0)
+    } else {
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountedPulses(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl PulseOutput for CountedPulses {
+        fn set(&mut self, high: bool) -> std::io::Result<()> {
+            if high {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stop() {
+        let pulses = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::spawn({
+            let pulses = CountedPulses(pulses.clone());
+            let stop = stop.clone();
+            move || {
+                generate(
+                    NtpClockWrapper::default(),
+                    pulses,
+                    Duration::from_millis(1),
+                    || true,
+                    &stop,
+                )
+                .map_err(|e| e.to_string())
+            }
+        });
+
+        std::thread::sleep(Duration::from_millis(10));
+        stop.store(true, Ordering::Relaxed);
+        // stops within a second, after at most the pulse it was waiting for
+        thread.join().unwrap().unwrap();
+        assert!(pulses.load(Ordering::Relaxed) <= 1);
+    }
+
+    #[test]
+    fn test_second_boundaries() {
+        let now = NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 250_000_000);
+        let delay = until_next_second(now);
+        assert!((delay.as_secs_f64() - 0.75).abs() < 1e-6);
+
+        let late = NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 1_000);
+        assert!((edge_error(late).to_seconds() - 1e-6).abs() < 1e-9);
+
+        let early = NtpTimestamp::from_seconds_nanos_since_ntp_era(99, 999_999_000);
+        assert!((edge_error(early).to_seconds() + 1e-6).abs() < 1e-9);
+    }
+}
@@ -92,6 +92,7 @@ where
                             root_dispersion: NtpDuration::ZERO,
                             leap: NtpLeapIndicator::NoWarning,
                             precision: 0,
+                            after_retransmission: false,
                         };
 
                         let controller_message = self.source.handle_measurement(measurement);
@@ -111,7 +112,7 @@ where
                             .ok();
 
                         self.channels
-                            .source_snapshots
+                            .observations
                             .write()
                             .expect("Unexpected poisoned mutex")
                             .insert(
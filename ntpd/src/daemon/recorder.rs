@@ -0,0 +1,124 @@
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+};
+
+use ntp_proto::{
+    DecisionRecord, Measurement, NtpDuration, NtpInstant, RecordedDecision, RecordedMeasurement,
+};
+use serde::Deserialize;
+
+/// Records the measurements of all sources, one JSON object per line, such that
+/// `ntp-replay` can feed them through the algorithm later on. Decisions of the
+/// algorithm can be recorded in between, to compare the replay with.
+#[derive(Debug)]
+pub struct MeasurementRecorder {
+    file: LineWriter<File>,
+    start: NtpInstant,
+}
+
+impl MeasurementRecorder {
+    /// Start a new recording, replacing any earlier recording at `path`
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(MeasurementRecorder {
+            file: LineWriter::new(File::create(path)?),
+            start: NtpInstant::now(),
+        })
+    }
+
+    pub fn record(
+        &mut self,
+        source: &str,
+        measurement: &Measurement<NtpDuration>,
+    ) -> std::io::Result<()> {
+        let recorded = RecordedMeasurement::new(source.to_owned(), self.start, measurement);
+        serde_json::to_writer(&mut self.file, &recorded)?;
+        self.file.write_all(b"\n")
+    }
+
+    pub fn record_decision(&mut self, decision: DecisionRecord<String>) -> std::io::Result<()> {
+        let recorded = RecordedDecision {
+            elapsed: NtpInstant::now().abs_diff(self.start).to_seconds(),
+            decision,
+        };
+        serde_json::to_writer(&mut self.file, &recorded)?;
+        self.file.write_all(b"\n")
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecordedLine {
+    Decision(RecordedDecision),
+    Measurement(RecordedMeasurement),
+}
+
+/// Read the measurements of a recording made by [`MeasurementRecorder`],
+/// skipping any recorded decisions
+pub fn load(path: &Path) -> std::io::Result<Vec<RecordedMeasurement>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut measurements = vec![];
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line).map_err(std::io::Error::from)? {
+            RecordedLine::Decision(_) => {}
+            RecordedLine::Measurement(measurement) => measurements.push(measurement),
+        }
+    }
+    Ok(measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::{DecisionAction, NtpLeapIndicator, NtpTimestamp};
+
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    #[test]
+    fn test_record_and_load() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join(format!("ntp-test-recording-{}", alloc_port()));
+
+        let mut recorder = MeasurementRecorder::create(&path).unwrap();
+        let measurement = Measurement {
+            delay: NtpDuration::from_seconds(0.002),
+            offset: NtpDuration::from_seconds(-0.5),
+            localtime: NtpTimestamp::from_seconds_nanos_since_ntp_era(3_900_000_000, 0),
+            monotime: recorder.start + std::time::Duration::from_secs(16),
+
+            stratum: 2,
+            root_delay: NtpDuration::from_seconds(0.01),
+            root_dispersion: NtpDuration::from_seconds(0.02),
+            leap: NtpLeapIndicator::NoWarning,
+            precision: -20,
+            after_retransmission: false,
+        };
+        recorder
+            .record("ntp.example.com:123", &measurement)
+            .unwrap();
+        recorder
+            .record_decision(DecisionRecord {
+                candidates: vec![],
+                survivors: vec![],
+                combined: None,
+                action: DecisionAction::NoConsensus,
+            })
+            .unwrap();
+        recorder.record("192.0.2.1:123", &measurement).unwrap();
+
+        let recording = load(&path).unwrap();
+        assert_eq!(recording.len(), 2);
+        assert_eq!(recording[0].source, "ntp.example.com:123");
+        assert_eq!(recording[1].source, "192.0.2.1:123");
+        assert!((recording[0].elapsed - 16.0).abs() < 1e-6);
+        assert!((recording[0].offset + 0.5).abs() < 1e-9);
+        assert_eq!(recording[0].localtime, measurement.localtime);
+
+        std::fs::write(&path, "{}\n").unwrap();
+        assert!(load(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
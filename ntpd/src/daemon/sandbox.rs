@@ -0,0 +1,481 @@
+use tracing::info;
+
+use super::config::{Config, NtpSourceConfig, SandboxConfig, SandboxLevel};
+
+/// What the sockets of the daemon need beyond what an unprivileged user may
+/// do. The capabilities for the listeners of servers are only kept until these
+/// are open, those for sources for as long as the daemon runs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub struct SocketPrivileges {
+    /// A server or NTS key exchange server listens on a port below 1024,
+    /// which needs `CAP_NET_BIND_SERVICE`
+    pub privileged_ports: bool,
+    /// A server binds its listener to an interface, which needs `CAP_NET_RAW`
+    /// on kernels before Linux 5.7
+    pub listen_on_interface: bool,
+    /// A source or the clock binds its sockets to an interface, which needs
+    /// `CAP_NET_RAW` on kernels before Linux 5.7
+    pub bind_to_interface: bool,
+}
+
+impl SocketPrivileges {
+    pub fn of(config: &Config) -> Self {
+        let privileged_ports = config
+            .servers
+            .iter()
+            .flat_map(|server| std::iter::once(server.listen).chain(server.tcp_listen))
+            .map(|listen| listen.port())
+            .chain(config.nts_ke.iter().map(|nts_ke| nts_ke.listen.port()))
+            .any(|port| port < 1024);
+
+        let listen_on_interface = config
+            .servers
+            .iter()
+            .any(|server| server.interface.is_some());
+
+        #[cfg(feature = "hardware-timestamping")]
+        let clock_interface = config.clock.interface.is_some();
+        #[cfg(not(feature = "hardware-timestamping"))]
+        let clock_interface = false;
+
+        let bind_to_interface = clock_interface
+            || config.sources.iter().any(|source| match source {
+                NtpSourceConfig::Standard(cfg) => cfg.first.interface.is_some(),
+                NtpSourceConfig::Nts(cfg) => cfg.first.interface.is_some(),
+                NtpSourceConfig::Pool(cfg) => cfg.first.interface.is_some(),
+                NtpSourceConfig::NtsPool(cfg) => cfg.first.interface.is_some(),
+                NtpSourceConfig::Service(cfg) => cfg.first.interface.is_some(),
+                NtpSourceConfig::Sock(_) | NtpSourceConfig::File(_) => false,
+                #[cfg(feature = "pps")]
+                NtpSourceConfig::Pps(_) => false,
+            });
+
+        SocketPrivileges {
+            privileged_ports,
+            listen_on_interface,
+            bind_to_interface,
+        }
+    }
+}
+
+/// Switch to the configured user, keeping only the capabilities to steer the
+/// clock and to open the sockets of the daemon.
+///
+/// This must be called on the main thread, after the clock has been opened
+/// and before the runtime is started, so that all threads of the daemon run as
+/// the configured user. Threads started afterwards drop the capabilities to
+/// open listeners with [`restrict_thread`]. The thread that steers the clock
+/// only sets its realtime priority later, so `CAP_SYS_NICE` is kept for that
+/// when `realtime_scheduling` is set. Unlike the scheduling settings, failing
+/// to set up the sandbox is an error, as the daemon would otherwise silently
+/// run with more privileges than intended.
+pub fn drop_privileges(
+    config: &SandboxConfig,
+    sockets: SocketPrivileges,
+    realtime_scheduling: bool,
+) -> std::io::Result<()> {
+    match config.level {
+        SandboxLevel::Off => Ok(()),
+        SandboxLevel::DropPrivileges | SandboxLevel::Seccomp => {
+            switch_user(config, sockets, realtime_scheduling)
+        }
+    }
+}
+
+/// Finish confining the daemon once it has started and the main thread has
+/// opened the listeners of servers: drop the capabilities that were only
+/// needed for those, and with [`SandboxLevel::Seccomp`] restrict the system
+/// calls of all threads.
+pub fn confine(
+    config: &SandboxConfig,
+    sockets: SocketPrivileges,
+    realtime_scheduling: bool,
+) -> std::io::Result<()> {
+    match config.level {
+        SandboxLevel::Off => Ok(()),
+        SandboxLevel::DropPrivileges => drop_listener_capabilities(sockets, realtime_scheduling),
+        SandboxLevel::Seccomp => {
+            drop_listener_capabilities(sockets, realtime_scheduling)?;
+            install_syscall_filter()
+        }
+    }
+}
+
+/// The capabilities to keep, limited to those the daemon has
+#[cfg(target_os = "linux")]
+fn capabilities(
+    sockets: SocketPrivileges,
+    realtime_scheduling: bool,
+    listeners: bool,
+) -> std::io::Result<caps::CapsHashSet> {
+    use caps::{CapSet, Capability, CapsHashSet};
+
+    let mut keep = CapsHashSet::from([Capability::CAP_SYS_TIME]);
+    if listeners && sockets.privileged_ports {
+        keep.insert(Capability::CAP_NET_BIND_SERVICE);
+    }
+    if (listeners && sockets.listen_on_interface) || sockets.bind_to_interface {
+        keep.insert(Capability::CAP_NET_RAW);
+    }
+    if realtime_scheduling {
+        keep.insert(Capability::CAP_SYS_NICE);
+    }
+
+    // a daemon that was started without some of these can not gain them
+    let permitted = caps::read(None, CapSet::Permitted).map_err(std::io::Error::other)?;
+    Ok(keep.intersection(&permitted).copied().collect())
+}
+
+#[cfg(target_os = "linux")]
+fn switch_user(
+    config: &SandboxConfig,
+    sockets: SocketPrivileges,
+    realtime_scheduling: bool,
+) -> std::io::Result<()> {
+    use nix::unistd::{Group, User, geteuid, setgid, setgroups, setuid};
+
+    let user = User::from_name(&config.user)?.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("user {} does not exist", config.user),
+        )
+    })?;
+    let gid = match &config.group {
+        Some(name) => {
+            Group::from_name(name)?
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("group {name} does not exist"),
+                    )
+                })?
+                .gid
+        }
+        None => user.gid,
+    };
+
+    let keep = capabilities(sockets, realtime_scheduling, true)?;
+    if !keep.contains(&caps::Capability::CAP_SYS_TIME) {
+        tracing::warn!("The daemon does not have CAP_SYS_TIME, and can not steer the clock");
+    }
+
+    if geteuid() != user.uid {
+        // without this, switching away from root clears all capabilities
+        nix::sys::prctl::set_keepcaps(true)?;
+        setgroups(&[gid])?;
+        setgid(gid)?;
+        setuid(user.uid)?;
+        nix::sys::prctl::set_keepcaps(false)?;
+    }
+
+    set_thread_capabilities(&keep)?;
+    let _ = THREAD_CAPABILITIES.set(capabilities(sockets, realtime_scheduling, false)?);
+
+    info!(user = %config.user, gid = gid.as_raw(), capabilities = ?keep, "dropped privileges");
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn drop_listener_capabilities(
+    sockets: SocketPrivileges,
+    realtime_scheduling: bool,
+) -> std::io::Result<()> {
+    let keep = capabilities(sockets, realtime_scheduling, false)?;
+    set_thread_capabilities(&keep)?;
+    info!(capabilities = ?keep, "dropped the capabilities to open listeners");
+    Ok(())
+}
+
+/// Capabilities that threads restrict themselves to when they start
+#[cfg(target_os = "linux")]
+static THREAD_CAPABILITIES: std::sync::OnceLock<caps::CapsHashSet> = std::sync::OnceLock::new();
+
+/// Restrict the calling thread to the capabilities it needs once the daemon
+/// has started, when the daemon drops its privileges.
+///
+/// Capabilities belong to a thread rather than the process. The main thread
+/// opens the listeners of servers while the daemon starts, and keeps the
+/// capabilities for that until [`confine`]. Every other thread, among which
+/// the threads of the runtime, calls this when it starts.
+pub fn restrict_thread() {
+    #[cfg(target_os = "linux")]
+    if let Some(keep) = THREAD_CAPABILITIES.get()
+        && let Err(e) = set_thread_capabilities(keep)
+    {
+        // running on with more privileges than intended is not an option
+        tracing::error!("Could not restrict the capabilities of a thread: {}", e);
+        std::process::exit(super::exitcode::NOPERM);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_thread_capabilities(keep: &caps::CapsHashSet) -> std::io::Result<()> {
+    use caps::CapSet;
+
+    caps::set(None, CapSet::Effective, keep).map_err(std::io::Error::other)?;
+    caps::set(None, CapSet::Permitted, keep).map_err(std::io::Error::other)?;
+    caps::clear(None, CapSet::Inheritable).map_err(std::io::Error::other)
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn install_syscall_filter() -> std::io::Result<()> {
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+
+    let arch = TargetArch::try_from(std::env::consts::ARCH).map_err(std::io::Error::other)?;
+
+    let rules = ALLOWED_SYSCALLS
+        .iter()
+        .map(|&syscall| (syscall, vec![]))
+        .collect();
+
+    // Refusing unexpected system calls, rather than killing the daemon, leaves
+    // room to report them through the usual error handling.
+    let filter = SeccompFilter::new(
+        rules,
+        SeccompAction::Errno(libc::EPERM as u32),
+        SeccompAction::Allow,
+        arch,
+    )
+    .map_err(std::io::Error::other)?;
+    let program = BpfProgram::try_from(filter).map_err(std::io::Error::other)?;
+    // the runtime is running by now, so the filter is installed in all threads
+    seccompiler::apply_filter_all_threads(&program).map_err(std::io::Error::other)?;
+
+    info!(
+        syscalls = ALLOWED_SYSCALLS.len(),
+        "restricted the system calls of the daemon"
+    );
+    Ok(())
+}
+
+/// System calls used by the runtime, the sockets of sources and servers, the
+/// observability and control sockets, the files of the daemon and steering
+/// the clock
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+const ALLOWED_SYSCALLS: &[i64] = &[
+    // memory and threads
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_futex,
+    libc::SYS_clone,
+    libc::SYS_clone3,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_sched_yield,
+    libc::SYS_sched_getaffinity,
+    // scheduling of the thread that steers the clock
+    libc::SYS_sched_setaffinity,
+    libc::SYS_sched_setscheduler,
+    libc::SYS_sched_getscheduler,
+    libc::SYS_sched_getparam,
+    libc::SYS_sched_get_priority_min,
+    libc::SYS_sched_get_priority_max,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+    libc::SYS_getpid,
+    libc::SYS_gettid,
+    libc::SYS_tgkill,
+    libc::SYS_prctl,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    // signals
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    // event loop
+    libc::SYS_epoll_create1,
+    libc::SYS_epoll_ctl,
+    libc::SYS_epoll_pwait,
+    libc::SYS_eventfd2,
+    libc::SYS_pipe2,
+    libc::SYS_ppoll,
+    // files
+    libc::SYS_openat,
+    libc::SYS_close,
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    libc::SYS_pread64,
+    libc::SYS_pwrite64,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_ftruncate,
+    libc::SYS_fchmod,
+    libc::SYS_fchmodat,
+    libc::SYS_mkdirat,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat2,
+    libc::SYS_readlinkat,
+    libc::SYS_getdents64,
+    // sockets
+    libc::SYS_socket,
+    libc::SYS_connect,
+    libc::SYS_bind,
+    libc::SYS_listen,
+    libc::SYS_accept4,
+    libc::SYS_shutdown,
+    libc::SYS_getsockname,
+    libc::SYS_getpeername,
+    libc::SYS_getsockopt,
+    libc::SYS_setsockopt,
+    libc::SYS_sendto,
+    libc::SYS_recvfrom,
+    libc::SYS_sendmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_recvmsg,
+    libc::SYS_socketpair,
+    // time
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_getres,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_adjtime,
+    libc::SYS_clock_settime,
+    libc::SYS_adjtimex,
+    // legacy variants still used by the C library on x86_64
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_lstat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_epoll_wait,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_unlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_mkdir,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_chmod,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_readlink,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_arch_prctl,
+];
+
+#[cfg(not(target_os = "linux"))]
+fn switch_user(
+    _config: &SandboxConfig,
+    _sockets: SocketPrivileges,
+    _realtime_scheduling: bool,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "dropping privileges is not supported on this platform",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_listener_capabilities(
+    _sockets: SocketPrivileges,
+    _realtime_scheduling: bool,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "dropping privileges is not supported on this platform",
+    ))
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn install_syscall_filter() -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "system call filtering is only supported on Linux on x86_64 and aarch64",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_privileges_follow_the_configuration() {
+        let config: Config = toml::from_str(
+            r#"
+            [[source]]
+            mode = "server"
+            address = "example.com"
+
+            [[server]]
+            listen = "[::]:1123"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(SocketPrivileges::of(&config), SocketPrivileges::default());
+
+        let config: Config = toml::from_str(
+            r#"
+            [[server]]
+            listen = "[::]:123"
+            "#,
+        )
+        .unwrap();
+        assert!(SocketPrivileges::of(&config).privileged_ports);
+        assert!(!SocketPrivileges::of(&config).bind_to_interface);
+
+        let config: Config = toml::from_str(
+            r#"
+            [[source]]
+            mode = "pool"
+            address = "pool.ntp.org"
+            count = 4
+            interface = "eth1"
+            "#,
+        )
+        .unwrap();
+        assert!(!SocketPrivileges::of(&config).privileged_ports);
+        assert!(SocketPrivileges::of(&config).bind_to_interface);
+
+        let config: Config = toml::from_str(
+            r#"
+            [[server]]
+            listen = "[::]:1123"
+            interface = "eth1"
+            "#,
+        )
+        .unwrap();
+        assert!(SocketPrivileges::of(&config).listen_on_interface);
+        assert!(!SocketPrivileges::of(&config).bind_to_interface);
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[test]
+    fn allowed_syscalls_are_unique() {
+        let unique: std::collections::HashSet<_> = ALLOWED_SYSCALLS.iter().collect();
+        assert_eq!(unique.len(), ALLOWED_SYSCALLS.len());
+    }
+}
@@ -0,0 +1,80 @@
+use tracing::{info, warn};
+
+use super::config::SchedulingConfig;
+
+/// Apply the configured scheduling policy and CPU affinity to the current thread.
+///
+/// This is called on the thread that steers the clock, which spawns no other
+/// threads, such that the rest of the daemon keeps the default scheduling.
+/// Failures are logged, after which the thread continues with the default
+/// scheduling.
+pub fn apply(config: &SchedulingConfig) {
+    if let Some(priority) = config.realtime_priority {
+        match set_realtime_priority(priority) {
+            Ok(()) => info!(priority, "using realtime (SCHED_FIFO) scheduling"),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => warn!(
+                "Could not enable realtime scheduling, this requires the CAP_SYS_NICE capability or a sufficient RLIMIT_RTPRIO"
+            ),
+            Err(e) => warn!(error = ?e, "Could not enable realtime scheduling"),
+        }
+    }
+
+    if !config.cpu_affinity.is_empty() {
+        match set_cpu_affinity(&config.cpu_affinity) {
+            Ok(()) => info!(cpus = ?config.cpu_affinity, "restricted clock steering to cpus"),
+            Err(e) => warn!(error = ?e, "Could not set cpu affinity"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_realtime_priority(priority: u8) -> std::io::Result<()> {
+    use thread_priority::{
+        RealtimeThreadSchedulePolicy, ThreadPriority, ThreadPriorityValue, ThreadSchedulePolicy,
+    };
+
+    let priority = ThreadPriorityValue::try_from(priority)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    thread_priority::set_thread_priority_and_policy(
+        thread_priority::thread_native_id(),
+        ThreadPriority::Crossplatform(priority),
+        ThreadSchedulePolicy::Realtime(RealtimeThreadSchedulePolicy::Fifo),
+    )
+    .map_err(|e| match e {
+        thread_priority::Error::OS(errno) => std::io::Error::from_raw_os_error(errno),
+        e => std::io::Error::other(format!("{e:?}")),
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cpus: &[usize]) -> std::io::Result<()> {
+    use nix::{
+        sched::{CpuSet, sched_setaffinity},
+        unistd::Pid,
+    };
+
+    let mut set = CpuSet::new();
+    for &cpu in cpus {
+        set.set(cpu)?;
+    }
+
+    // pid 0 is the calling thread
+    Ok(sched_setaffinity(Pid::from_raw(0), &set)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_realtime_priority(_priority: u8) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "realtime scheduling is not supported on this platform",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_cpu_affinity(_cpus: &[usize]) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "cpu affinity is not supported on this platform",
+    ))
+}
@@ -1,4 +1,5 @@
 use std::{
+    net::{IpAddr, SocketAddr},
     sync::{
         Arc,
         atomic::{AtomicU64, Ordering},
@@ -7,25 +8,35 @@ use std::{
 };
 
 use ntp_proto::{
-    KeySet, NtpClock, Server, ServerReason, ServerResponse, ServerStatHandler, SystemSnapshot,
+    KeySet, NtpClock, Server, ServerReason, ServerResponse, ServerStatHandler, SymmetricKeySet,
+    SystemSnapshot,
 };
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use timestamped_socket::socket::{RecvResult, open_ip};
-use tokio::task::JoinHandle;
+#[cfg(target_os = "linux")]
+use timestamped_socket::socket::open_interface_udp;
+use timestamped_socket::socket::{Open, RecvResult, Socket, open_ip};
+use tokio::{sync::mpsc, task::JoinHandle};
 use tracing::{Instrument, Span, debug, instrument, warn};
 
-use super::{config::ServerConfig, util::convert_net_timestamp};
+use super::{
+    census::Census,
+    config::ServerConfig,
+    sockets::bind_tcp_listener,
+    stream::{self, StreamRequest},
+    util::convert_net_timestamp,
+};
 
 // Maximum size of udp packet we handle
 const MAX_PACKET_SIZE: usize = 1024;
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ServerStats {
     pub received_packets: Counter,
     pub accepted_packets: Counter,
     pub denied_packets: Counter,
     pub ignored_packets: Counter,
     pub rate_limited_packets: Counter,
+    pub rate_limit_kod_packets: Counter,
     pub response_send_errors: Counter,
     pub nts_received_packets: Counter,
     pub nts_accepted_packets: Counter,
@@ -34,6 +45,26 @@ pub struct ServerStats {
     pub nts_nak_packets: Counter,
 }
 
+impl ServerStats {
+    /// Add counts from elsewhere, such as those of an instance whose state was imported
+    pub fn add(&self, other: &ServerStats) {
+        self.received_packets.add(&other.received_packets);
+        self.accepted_packets.add(&other.accepted_packets);
+        self.denied_packets.add(&other.denied_packets);
+        self.ignored_packets.add(&other.ignored_packets);
+        self.rate_limited_packets.add(&other.rate_limited_packets);
+        self.rate_limit_kod_packets
+            .add(&other.rate_limit_kod_packets);
+        self.response_send_errors.add(&other.response_send_errors);
+        self.nts_received_packets.add(&other.nts_received_packets);
+        self.nts_accepted_packets.add(&other.nts_accepted_packets);
+        self.nts_denied_packets.add(&other.nts_denied_packets);
+        self.nts_rate_limited_packets
+            .add(&other.nts_rate_limited_packets);
+        self.nts_nak_packets.add(&other.nts_nak_packets);
+    }
+}
+
 impl ServerStatHandler for ServerStats {
     fn register(
         &mut self,
@@ -47,6 +78,10 @@ impl ServerStatHandler for ServerStats {
         match (response, reason) {
             (ServerResponse::ProvideTime, _) => self.accepted_packets.inc(),
             (ServerResponse::Ignore, ServerReason::RateLimit) => self.rate_limited_packets.inc(),
+            (ServerResponse::RateLimit, _) => {
+                self.rate_limited_packets.inc();
+                self.rate_limit_kod_packets.inc();
+            }
             (ServerResponse::Ignore, _) => self.ignored_packets.inc(),
             (ServerResponse::Deny, _) => self.denied_packets.inc(),
             (ServerResponse::NTSNak, _) => self.nts_nak_packets.inc(),
@@ -57,9 +92,8 @@ impl ServerStatHandler for ServerStats {
             match (response, reason) {
                 (ServerResponse::ProvideTime, _) => self.nts_accepted_packets.inc(),
                 (ServerResponse::Deny, _) => self.nts_denied_packets.inc(),
-                (ServerResponse::Ignore, ServerReason::RateLimit) => {
-                    self.nts_rate_limited_packets.inc()
-                }
+                (ServerResponse::Ignore, ServerReason::RateLimit)
+                | (ServerResponse::RateLimit, _) => self.nts_rate_limited_packets.inc(),
                 _ => { /* counted above */ }
             }
         }
@@ -79,6 +113,16 @@ impl Counter {
     pub fn get(&self) -> u64 {
         self.value.as_ref().load(Ordering::Relaxed)
     }
+
+    fn add(&self, other: &Counter) {
+        self.value.fetch_add(other.get(), Ordering::Relaxed);
+    }
+}
+
+impl PartialEq for Counter {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
 }
 
 impl Serialize for Counter {
@@ -100,6 +144,23 @@ impl<'de> Deserialize<'de> for Counter {
     }
 }
 
+/// Registers packets both in the statistics and, when enabled, the census of
+/// the server, which unlike the statistics is kept per client
+struct Registration<'a> {
+    stats: &'a mut ServerStats,
+    census: Option<&'a Census>,
+    client: IpAddr,
+}
+
+impl ServerStatHandler for Registration<'_> {
+    fn register(&mut self, version: u8, nts: bool, reason: ServerReason, response: ServerResponse) {
+        self.stats.register(version, nts, reason, response);
+        if let Some(census) = self.census {
+            census.record(self.client, version, nts);
+        }
+    }
+}
+
 pub struct ServerTask<C: 'static + NtpClock + Send> {
     config: ServerConfig,
     network_wait_period: std::time::Duration,
@@ -107,26 +168,110 @@ pub struct ServerTask<C: 'static + NtpClock + Send> {
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
     server: Server<C>,
     stats: ServerStats,
+    census: Option<Census>,
+    /// Requests received over TCP or TLS connections
+    stream_requests: Option<mpsc::Receiver<StreamRequest>>,
+}
+
+fn open_socket(config: &ServerConfig) -> std::io::Result<Socket<SocketAddr, Open>> {
+    match config.interface {
+        #[cfg(target_os = "linux")]
+        Some(interface) => open_interface_udp(
+            interface,
+            config.listen.port(),
+            timestamped_socket::socket::InterfaceTimestampMode::SoftwareRecv,
+            None,
+        ),
+        _ => open_ip(
+            config.listen,
+            timestamped_socket::socket::GeneralTimestampMode::SoftwareRecv,
+        ),
+    }
+}
+
+/// Start accepting requests over TCP or TLS connections, when configured
+fn listen_stream<C: 'static + NtpClock + Send + Sync>(
+    config: &ServerConfig,
+    clock: C,
+    network_wait_period: Duration,
+) -> Option<mpsc::Receiver<StreamRequest>> {
+    let listen = config.tcp_listen?;
+    let tls = match (
+        &config.tls_certificate_chain_path,
+        &config.tls_private_key_path,
+    ) {
+        (Some(certificate_chain_path), Some(private_key_path)) => {
+            match stream::tls_acceptor(certificate_chain_path, private_key_path) {
+                Ok(tls) => Some(tls),
+                Err(error) => {
+                    // falling back to plain TCP would silently drop the protection of TLS
+                    warn!(
+                        ?error,
+                        "Could not set up TLS, not accepting requests over connections"
+                    );
+                    return None;
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // like the socket for requests over UDP, the listener exists once the
+    // daemon has started
+    let listener = bind_tcp_listener(listen, config.interface)
+        .inspect_err(|error| warn!(?error, ?listen, "Could not open server connection socket"))
+        .ok();
+
+    let (sender, receiver) = mpsc::channel(64);
+    tokio::spawn(
+        stream::serve(
+            listen,
+            config.interface,
+            listener,
+            tls,
+            clock,
+            sender,
+            network_wait_period,
+            config.log_anonymization,
+        )
+        .instrument(Span::current()),
+    );
+    Some(receiver)
 }
 
 impl<C: 'static + NtpClock + Send> ServerTask<C> {
-    #[instrument(level = tracing::Level::ERROR, name = "Ntp Server", skip_all, fields(address = debug(config.listen)))]
+    #[instrument(level = tracing::Level::ERROR, name = "Ntp Server", skip_all, fields(address = debug(config.listen), label = config.label.as_deref()))]
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         config: ServerConfig,
         stats: ServerStats,
+        census: Option<Census>,
         mut system_receiver: tokio::sync::watch::Receiver<SystemSnapshot>,
         mut keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+        symmetric_keys: Arc<SymmetricKeySet>,
         clock: C,
         network_wait_period: Duration,
-    ) -> JoinHandle<()> {
+    ) -> JoinHandle<()>
+    where
+        C: Sync,
+    {
+        // The sockets are opened right away, so that they exist once the
+        // daemon has started, after which the privileges to open them may be
+        // dropped. Should that fail, opening them is retried.
+        let socket = open_socket(&config)
+            .inspect_err(|error| warn!(?error, ?config.listen, "Could not open server socket"))
+            .ok();
+        let stream_requests = listen_stream(&config, clock.clone(), network_wait_period);
+
         tokio::spawn(
             (async move {
-                let server = Server::new(
+                let mut server = Server::new(
                     config.clone().into(),
                     clock,
                     *system_receiver.borrow_and_update(),
                     keyset.borrow_and_update().clone(),
                 );
+                server.update_symmetric_keys(symmetric_keys);
 
                 let mut process = ServerTask {
                     config,
@@ -135,28 +280,25 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                     keyset,
                     server,
                     stats,
+                    census,
+                    stream_requests,
                 };
 
-                process.serve().await;
+                process.serve(socket).await;
             })
             .instrument(Span::current()),
         )
     }
 
-    async fn serve(&mut self) {
-        let mut cur_socket = None;
+    async fn serve(&mut self, socket: Option<Socket<SocketAddr, Open>>) {
+        let mut cur_socket = socket;
         loop {
             // open socket if it is not already open
             let socket = match &mut cur_socket {
                 Some(socket) => socket,
                 None => {
                     let new_socket = loop {
-                        let socket_res = open_ip(
-                            self.config.listen,
-                            timestamped_socket::socket::GeneralTimestampMode::SoftwareRecv,
-                        );
-
-                        match socket_res {
+                        match open_socket(&self.config) {
                             Ok(socket) => break socket,
                             Err(error) => {
                                 warn!(?error, ?self.config.listen, "Could not open server socket");
@@ -185,7 +327,12 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                             timestamp: Some(timestamp),
                         }) => {
                             let mut send_buf = [0u8; MAX_PACKET_SIZE];
-                            match self.server.handle(source_addr.ip(), convert_net_timestamp(timestamp), &buf[..length], &mut send_buf[..length], &mut self.stats) {
+                            let mut registration = Registration {
+                                stats: &mut self.stats,
+                                census: self.census.as_ref(),
+                                client: source_addr.ip(),
+                            };
+                            match self.server.handle(source_addr.ip(), convert_net_timestamp(timestamp), &buf[..length], &mut send_buf[..length], &mut registration) {
                                 ntp_proto::ServerAction::Ignore => { /* explicitly do nothing */ },
                                 ntp_proto::ServerAction::Respond { message } => {
                                     if let Err(send_err) = socket.send_to(message, source_addr).await {
@@ -209,13 +356,34 @@ impl<C: 'static + NtpClock + Send> ServerTask<C> {
                             // remote network/host, which is not relevant for a server).
                             // Furthermore, they can conceivably be triggered by a
                             // malicious third party, and triggering restart on them
-                            // would then result in a denial-of-service.
-                            if matches!(receive_error.raw_os_error(), Some(libc::ENETDOWN)) {
+                            // would then result in a denial-of-service. ENODEV
+                            // means the interface we are bound to went away.
+                            if matches!(
+                                receive_error.raw_os_error(),
+                                Some(libc::ENETDOWN) | Some(libc::ENODEV)
+                            ) {
                                 cur_socket = None;
                             }
                         }
                     }
                 },
+                Some(request) = async { if let Some(ref mut requests) = self.stream_requests { requests.recv().await } else { std::future::pending().await }} => {
+                    let mut send_buf = [0u8; MAX_PACKET_SIZE];
+                    let length = request.packet.len();
+                    let mut registration = Registration {
+                        stats: &mut self.stats,
+                        census: self.census.as_ref(),
+                        client: request.remote.ip(),
+                    };
+                    match self.server.handle(request.remote.ip(), request.recv_timestamp, &request.packet, &mut send_buf[..length], &mut registration) {
+                        ntp_proto::ServerAction::Ignore => { /* dropping the response channel leaves the request unanswered */ },
+                        ntp_proto::ServerAction::Respond { message } => {
+                            if request.response.send(message.to_vec()).is_err() {
+                                self.stats.response_send_errors.inc();
+                            }
+                        },
+                    }
+                },
                 _ = self.system_receiver.changed(), if self.system_receiver.has_changed().is_ok() => {
                     self.server.update_system(*self.system_receiver.borrow_and_update());
                 }
@@ -306,8 +474,10 @@ mod tests {
         let join = ServerTask::spawn(
             config,
             Default::default(),
+            None,
             system_snapshots,
             keyset,
+            Default::default(),
             clock,
             Duration::from_secs(0),
         );
@@ -336,4 +506,61 @@ mod tests {
 
         join.abort();
     }
+
+    #[tokio::test]
+    async fn test_server_serves_tcp() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let tcp_listen = SocketAddr::new("127.0.0.1".parse().unwrap(), alloc_port());
+        let config = ServerConfig {
+            tcp_listen: Some(tcp_listen),
+            ..ServerConfig::from(SocketAddr::new("127.0.0.1".parse().unwrap(), alloc_port()))
+        };
+
+        let clock = TestClock {
+            time: NtpTimestamp::from_seconds_nanos_since_ntp_era(0, 1000),
+        };
+        let (_, system_snapshots) = tokio::sync::watch::channel(SystemSnapshot::default());
+        let (_, keyset) = tokio::sync::watch::channel(KeySetProvider::new(1).get());
+
+        let join = ServerTask::spawn(
+            config,
+            Default::default(),
+            None,
+            system_snapshots,
+            keyset,
+            Default::default(),
+            clock,
+            Duration::from_secs(0),
+        );
+
+        // the listener is opened by the server task
+        let mut stream = loop {
+            match tokio::net::TcpStream::connect(tcp_listen).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(1)).await,
+            }
+        };
+        let (packet, id) = NtpPacket::poll_message(PollIntervalLimits::default().min);
+
+        let serialized = serialize_packet_unencrypted(&packet);
+        stream
+            .write_all(&(serialized.len() as u16).to_be_bytes())
+            .await
+            .unwrap();
+        stream.write_all(&serialized).await.unwrap();
+
+        let length = tokio::time::timeout(Duration::from_millis(100), stream.read_u16())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(length, 48);
+        let mut buf = [0; 48];
+        stream.read_exact(&mut buf).await.unwrap();
+        let packet = NtpPacket::deserialize(&buf, &NoCipher).unwrap().0;
+        assert_ne!(packet.stratum(), 0);
+        assert!(packet.valid_server_response(id, false));
+
+        join.abort();
+    }
 }
@@ -0,0 +1,584 @@
+//! Polling many sources over a few shared UDP sockets.
+//!
+//! Normally every source opens a connected socket for every poll. When
+//! monitoring thousands of servers that means thousands of open sockets and
+//! a wakeup of every source task for every datagram. Instead, the sources can
+//! be spread over a fixed number of shards, each a task that owns a single
+//! unconnected socket. A shard sends the requests of its sources and hands
+//! every response to the source whose request it answers, recognized by the
+//! address of the server and the random origin timestamp (or client cookie)
+//! it echoes. Responses that answer no outstanding request are dropped.
+//!
+//! The polling timers of the sources are left to tokio, whose timer is already
+//! a hierarchical timer wheel, so thousands of them are cheap.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use ntp_proto::{NtpClock, NtpTimestamp, REMEMBERED_TRIES};
+#[cfg(target_os = "linux")]
+use timestamped_socket::socket::open_interface_udp;
+use timestamped_socket::{
+    interface::InterfaceName,
+    socket::{Open, RecvResult, Socket, open_ip},
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+use tracing::{debug, warn};
+
+use super::{config::TimestampMode, spawn::SocketBinding, util::convert_net_timestamp};
+
+/// Responses a source has not picked up yet, further ones are dropped
+const RESPONSE_BUFFER: usize = 4;
+
+/// How long a shard stops receiving after an error, so an error that
+/// persists does not keep it spinning
+const RECV_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The bytes of a request that a server echoes at offset 24 of its response
+type Cookie = [u8; 8];
+
+fn request_cookie(packet: &[u8]) -> Option<Cookie> {
+    let version = (packet.first()? >> 3) & 0b111;
+    // NTPv5 echoes the client cookie in place, earlier versions copy the
+    // transmit timestamp to the origin timestamp
+    let range = if version == 5 { 24..32 } else { 40..48 };
+    packet.get(range)?.try_into().ok()
+}
+
+fn response_cookie(packet: &[u8]) -> Option<Cookie> {
+    packet.get(24..32)?.try_into().ok()
+}
+
+enum Command {
+    Register {
+        client: u64,
+        responses: mpsc::Sender<(Vec<u8>, NtpTimestamp)>,
+    },
+    Send {
+        client: u64,
+        remote: SocketAddr,
+        packet: Vec<u8>,
+        sent: oneshot::Sender<std::io::Result<NtpTimestamp>>,
+    },
+    Remove {
+        client: u64,
+    },
+}
+
+/// A source on a shard
+struct ShardClient {
+    responses: mpsc::Sender<(Vec<u8>, NtpTimestamp)>,
+    /// Requests that may still be answered, newest last
+    outstanding: VecDeque<(SocketAddr, Cookie)>,
+}
+
+struct Shard<C> {
+    socket: Socket<SocketAddr, Open>,
+    clock: C,
+    commands: mpsc::UnboundedReceiver<Command>,
+    clients: HashMap<u64, ShardClient>,
+    outstanding: HashMap<(SocketAddr, Cookie), u64>,
+}
+
+impl<C: NtpClock> Shard<C> {
+    async fn run(mut self) {
+        let mut buf = [0u8; 1024];
+        let mut resume_recv = None;
+        loop {
+            let resume = resume_recv;
+            tokio::select! {
+                result = async {
+                    if let Some(deadline) = resume {
+                        tokio::time::sleep_until(deadline).await;
+                    }
+                    self.socket.recv(&mut buf).await
+                } => match result {
+                    Ok(result) => {
+                        resume_recv = None;
+                        self.handle_recv(result, &buf);
+                    }
+                    Err(receive_error) => {
+                        warn!(?receive_error, "could not receive packet on shared socket");
+                        match receive_error.raw_os_error() {
+                            // the sources see the shard stop and are restarted,
+                            // which opens a new socket
+                            Some(libc::EHOSTDOWN)
+                            | Some(libc::EHOSTUNREACH)
+                            | Some(libc::ENETDOWN)
+                            | Some(libc::ENETUNREACH)
+                            | Some(libc::ENODEV) => return,
+                            _ => resume_recv = Some(Instant::now() + RECV_ERROR_BACKOFF),
+                        }
+                    }
+                },
+                command = self.commands.recv() => match command {
+                    Some(command) => {
+                        self.handle_command(command).await;
+                        // sources polled at the same moment are sent to in
+                        // one go, without waking up for each of them
+                        while let Ok(command) = self.commands.try_recv() {
+                            self.handle_command(command).await;
+                        }
+                    }
+                    // the daemon is stopping
+                    None => return,
+                },
+            }
+        }
+    }
+
+    fn handle_recv(&mut self, result: RecvResult<SocketAddr>, buf: &[u8]) {
+        let RecvResult {
+            bytes_read,
+            remote_addr,
+            timestamp,
+            ..
+        } = result;
+
+        let recv_timestamp = match timestamp.map(convert_net_timestamp) {
+            Some(timestamp) => timestamp,
+            None => match self.clock.now() {
+                Ok(now) => now,
+                Err(error) => {
+                    warn!(?error, "could not read the clock, dropping packet");
+                    return;
+                }
+            },
+        };
+
+        // messages of fewer than 48 bytes are skipped entirely, like on the
+        // sockets of the sources themselves
+        let packet = &buf[..bytes_read];
+        let Some(cookie) = response_cookie(packet).filter(|_| bytes_read >= 48) else {
+            debug!(
+                expected = 48,
+                actual = bytes_read,
+                "received packet is too small"
+            );
+            return;
+        };
+
+        let Some(client) = self.outstanding.remove(&(remote_addr, cookie)) else {
+            debug!(%remote_addr, "received packet that answers no outstanding request");
+            return;
+        };
+        if let Some(shard_client) = self.clients.get(&client) {
+            match shard_client
+                .responses
+                .try_send((packet.to_vec(), recv_timestamp))
+            {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    debug!(%remote_addr, "source is not keeping up, dropping packet");
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => self.remove(client),
+            }
+        }
+    }
+
+    async fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Register { client, responses } => {
+                self.clients.insert(
+                    client,
+                    ShardClient {
+                        responses,
+                        outstanding: VecDeque::new(),
+                    },
+                );
+            }
+            Command::Send {
+                client,
+                remote,
+                packet,
+                sent,
+            } => {
+                let result = self.send(client, remote, &packet).await;
+                // the source may have stopped waiting for the result
+                let _ = sent.send(result);
+            }
+            Command::Remove { client } => self.remove(client),
+        }
+    }
+
+    async fn send(
+        &mut self,
+        client: u64,
+        remote: SocketAddr,
+        packet: &[u8],
+    ) -> std::io::Result<NtpTimestamp> {
+        let Some(cookie) = request_cookie(packet) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "request is too small",
+            ));
+        };
+        let Some(shard_client) = self.clients.get_mut(&client) else {
+            return Err(std::io::Error::other("source is not registered"));
+        };
+
+        shard_client.outstanding.push_back((remote, cookie));
+        if shard_client.outstanding.len() > REMEMBERED_TRIES
+            && let Some(expired) = shard_client.outstanding.pop_front()
+        {
+            self.outstanding.remove(&expired);
+        }
+        self.outstanding.insert((remote, cookie), client);
+
+        let now = self
+            .clock
+            .now()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        let send_timestamp = self.socket.send_to(packet, remote).await?;
+        // use the timestamp given by the kernel, if available
+        Ok(send_timestamp.map(convert_net_timestamp).unwrap_or(now))
+    }
+
+    fn remove(&mut self, client: u64) {
+        if let Some(shard_client) = self.clients.remove(&client) {
+            for request in shard_client.outstanding {
+                self.outstanding.remove(&request);
+            }
+        }
+    }
+}
+
+/// The handle of a source on a shard. Dropping it removes the source from
+/// the shard.
+#[derive(Debug)]
+pub struct SharedSocketClient {
+    id: u64,
+    remote: SocketAddr,
+    commands: mpsc::UnboundedSender<Command>,
+    responses: mpsc::Receiver<(Vec<u8>, NtpTimestamp)>,
+}
+
+impl SharedSocketClient {
+    /// Send a request to the source, returning the time at which it was sent
+    pub async fn send(&mut self, packet: &[u8]) -> std::io::Result<NtpTimestamp> {
+        let (sent, result) = oneshot::channel();
+        self.commands
+            .send(Command::Send {
+                client: self.id,
+                remote: self.remote,
+                packet: packet.to_vec(),
+                sent,
+            })
+            .map_err(|_| std::io::Error::other("shard is not running"))?;
+        result
+            .await
+            .map_err(|_| std::io::Error::other("shard is not running"))?
+    }
+
+    /// Wait for the next response of the source and the time at which it
+    /// was received. Returns `None` when the shard stopped.
+    ///
+    /// This is cancel safe.
+    pub async fn recv(&mut self) -> Option<(Vec<u8>, NtpTimestamp)> {
+        self.responses.recv().await
+    }
+}
+
+impl Drop for SharedSocketClient {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Remove { client: self.id });
+    }
+}
+
+/// The shards with a socket bound the same way
+#[derive(Debug)]
+struct ShardGroup {
+    interface: Option<InterfaceName>,
+    source_address: Option<IpAddr>,
+    ipv4: bool,
+    shards: Vec<mpsc::UnboundedSender<Command>>,
+}
+
+/// The shards over which sources polled over UDP are spread, opened on first
+/// use for every local binding and address family
+#[derive(Debug, Default)]
+pub struct SharedSockets {
+    /// Number of shards per binding, sharing is disabled when zero
+    shard_count: usize,
+    timestamp_mode: TimestampMode,
+    groups: Vec<ShardGroup>,
+    next_client: u64,
+}
+
+impl SharedSockets {
+    pub fn new(shard_count: usize, timestamp_mode: TimestampMode) -> Self {
+        SharedSockets {
+            shard_count,
+            timestamp_mode,
+            groups: vec![],
+            next_client: 0,
+        }
+    }
+
+    fn open_socket(
+        &self,
+        binding: SocketBinding,
+        ipv4: bool,
+    ) -> std::io::Result<Socket<SocketAddr, Open>> {
+        match binding {
+            #[cfg(target_os = "linux")]
+            SocketBinding {
+                interface: Some(interface),
+                ..
+            } => open_interface_udp(
+                interface,
+                0, /*lets os choose*/
+                self.timestamp_mode.as_interface_mode(),
+                None,
+            ),
+            SocketBinding {
+                source_address: Some(source_address),
+                ..
+            } => open_ip(
+                SocketAddr::new(source_address, 0),
+                self.timestamp_mode.as_general_mode(),
+            ),
+            _ => {
+                let unspecified = if ipv4 {
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+                } else {
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+                };
+                open_ip(
+                    SocketAddr::new(unspecified, 0),
+                    self.timestamp_mode.as_general_mode(),
+                )
+            }
+        }
+    }
+
+    /// Put the source at `remote` on one of the shards. Returns `None` when
+    /// sharing is disabled or the socket of the shard could not be opened,
+    /// the source then uses sockets of its own.
+    pub fn client<C: NtpClock + Sync>(
+        &mut self,
+        remote: SocketAddr,
+        binding: SocketBinding,
+        clock: C,
+    ) -> Option<SharedSocketClient> {
+        if self.shard_count == 0 {
+            return None;
+        }
+
+        let ipv4 = remote.is_ipv4();
+        let id = self.next_client;
+        self.next_client += 1;
+
+        let group_index = match self.groups.iter().position(|group| {
+            group.interface == binding.interface
+                && group.source_address == binding.source_address
+                && group.ipv4 == ipv4
+        }) {
+            Some(index) => index,
+            None => {
+                self.groups.push(ShardGroup {
+                    interface: binding.interface,
+                    source_address: binding.source_address,
+                    ipv4,
+                    shards: vec![],
+                });
+                self.groups.len() - 1
+            }
+        };
+
+        // sources are handed out to the shards in turn
+        let shard_index = (id % self.shard_count as u64) as usize;
+        let shards = &self.groups[group_index].shards;
+        let commands = match shards.get(shard_index) {
+            Some(commands) if !commands.is_closed() => commands.clone(),
+            _ => {
+                let socket = match self.open_socket(binding, ipv4) {
+                    Ok(socket) => socket,
+                    Err(error) => {
+                        warn!(?error, "Could not open shared socket");
+                        return None;
+                    }
+                };
+                let (commands, receiver) = mpsc::unbounded_channel();
+                tokio::spawn(
+                    Shard {
+                        socket,
+                        clock,
+                        commands: receiver,
+                        clients: HashMap::new(),
+                        outstanding: HashMap::new(),
+                    }
+                    .run(),
+                );
+
+                let shards = &mut self.groups[group_index].shards;
+                if shard_index < shards.len() {
+                    shards[shard_index] = commands.clone();
+                } else {
+                    shards.push(commands.clone());
+                }
+                commands
+            }
+        };
+
+        let (responses, receiver) = mpsc::channel(RESPONSE_BUFFER);
+        commands
+            .send(Command::Register {
+                client: id,
+                responses,
+            })
+            .ok()?;
+
+        Some(SharedSocketClient {
+            id,
+            remote,
+            commands,
+            responses: receiver,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use ntp_proto::{NtpDuration, NtpLeapIndicator};
+    use timestamped_socket::socket::GeneralTimestampMode;
+
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Default)]
+    struct TestClock {}
+
+    impl NtpClock for TestClock {
+        type Error = Infallible;
+
+        fn now(&self) -> std::result::Result<NtpTimestamp, Self::Error> {
+            Ok(NtpTimestamp::default())
+        }
+
+        fn set_frequency(&self, _freq: f64) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by shard");
+        }
+
+        fn get_frequency(&self) -> Result<f64, Self::Error> {
+            Ok(0.0)
+        }
+
+        fn step_clock(&self, _offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+            panic!("Shouldn't be called by shard");
+        }
+
+        fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by shard");
+        }
+
+        fn error_estimate_update(
+            &self,
+            _est_error: NtpDuration,
+            _max_error: NtpDuration,
+        ) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by shard");
+        }
+
+        fn status_update(&self, _leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+            panic!("Shouldn't be called by shard");
+        }
+    }
+
+    fn request(transmit: u64) -> [u8; 48] {
+        let mut packet = [0u8; 48];
+        // version 4, client mode
+        packet[0] = 0x23;
+        packet[40..48].copy_from_slice(&transmit.to_be_bytes());
+        packet
+    }
+
+    /// Answer every request by echoing its transmit timestamp
+    async fn respond(mut server: Socket<SocketAddr, Open>) {
+        let mut buf = [0u8; 48];
+        while let Ok(RecvResult {
+            bytes_read: 48,
+            remote_addr,
+            ..
+        }) = server.recv(&mut buf).await
+        {
+            let mut response = [0u8; 48];
+            response[0] = 0x24;
+            response[24..32].copy_from_slice(&buf[40..48]);
+            let _ = server.send_to(&response, remote_addr).await;
+        }
+    }
+
+    fn server() -> (Socket<SocketAddr, Open>, SocketAddr) {
+        let addr = SocketAddr::new("127.0.0.1".parse().unwrap(), alloc_port());
+        let socket = open_ip(addr, GeneralTimestampMode::SoftwareRecv).unwrap();
+        (socket, addr)
+    }
+
+    #[test]
+    fn test_cookies() {
+        let request = request(0x0102030405060708);
+        assert_eq!(request_cookie(&request), Some([1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(request_cookie(&request[..40]), None);
+
+        let mut v5 = [0u8; 48];
+        v5[0] = 0x2b;
+        v5[24..32].copy_from_slice(&[8; 8]);
+        assert_eq!(request_cookie(&v5), Some([8; 8]));
+    }
+
+    #[tokio::test]
+    async fn test_shared_socket_demultiplexing() {
+        let (server, addr) = server();
+        tokio::spawn(respond(server));
+
+        let mut sockets = SharedSockets::new(1, TimestampMode::default());
+        let mut a = sockets
+            .client(addr, SocketBinding::default(), TestClock {})
+            .unwrap();
+        let mut b = sockets
+            .client(addr, SocketBinding::default(), TestClock {})
+            .unwrap();
+
+        a.send(&request(1)).await.unwrap();
+        b.send(&request(2)).await.unwrap();
+
+        let (packet, _) = a.recv().await.unwrap();
+        assert_eq!(response_cookie(&packet), Some(1u64.to_be_bytes()));
+        let (packet, _) = b.recv().await.unwrap();
+        assert_eq!(response_cookie(&packet), Some(2u64.to_be_bytes()));
+
+        // each request is answered only once
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), a.recv())
+                .await
+                .is_err()
+        );
+
+        // a dropped source no longer receives answers, the others still do
+        drop(a);
+        b.send(&request(3)).await.unwrap();
+        let (packet, _) = b.recv().await.unwrap();
+        assert_eq!(response_cookie(&packet), Some(3u64.to_be_bytes()));
+    }
+
+    #[tokio::test]
+    async fn test_shared_sockets_disabled() {
+        let mut sockets = SharedSockets::default();
+        let addr = SocketAddr::new("127.0.0.1".parse().unwrap(), alloc_port());
+        assert!(
+            sockets
+                .client(addr, SocketBinding::default(), TestClock {})
+                .is_none()
+        );
+    }
+}
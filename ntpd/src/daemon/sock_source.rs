@@ -159,6 +159,7 @@ where
                             root_dispersion: NtpDuration::ZERO,
                             leap,
                             precision: 0, // TODO: compute on startup?
+                            after_retransmission: false,
                         };
 
                         let controller_message = self.source.handle_measurement(measurement);
@@ -177,7 +178,7 @@ where
                             .ok();
 
                         self.channels
-                            .source_snapshots
+                            .observations
                             .write()
                             .expect("Unexpected poisoned mutex")
                             .insert(
@@ -237,7 +238,6 @@ where
 #[cfg(test)]
 mod tests {
     use std::{
-        collections::HashMap,
         os::unix::net::UnixDatagram,
         sync::{Arc, RwLock},
     };
@@ -251,6 +251,7 @@ mod tests {
     use crate::{
         daemon::{
             ntp_source::{MsgForSystem, SourceChannels},
+            observer::Observations,
             sock_source::{SOCK_MAGIC, SampleError, SockSourceTask, create_socket},
             spawn::SourceId,
             util::EPOCH_OFFSET,
@@ -333,7 +334,7 @@ mod tests {
             SourceChannels {
                 msg_for_system_sender,
                 system_update_receiver,
-                source_snapshots: Arc::new(RwLock::new(HashMap::new())),
+                observations: Arc::new(RwLock::new(Observations::default())),
             },
             system
                 .create_sock_source(index, SourceConfig::default(), 0.001)
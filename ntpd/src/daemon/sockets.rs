@@ -1,7 +1,216 @@
 use std::fs::Permissions;
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+use subtle::ConstantTimeEq;
+
+use timestamped_socket::interface::InterfaceName;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UnixListener, UnixStream};
+
+/// Length of the token that clients of a TCP socket start with, in hex digits
+const TOKEN_LENGTH: usize = 32;
+
+/// How long a client of a TCP socket gets to send its token
+const TOKEN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A connection to the observation or control socket of the daemon
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Where clients reach the observation or control socket of the daemon.
+///
+/// By default this is a Unix socket, of which the permissions determine who
+/// may connect. On platforms or in setups where those are not available, it
+/// can be a TCP socket on the loopback interface instead. As anyone on the
+/// host can connect to that, the daemon writes a random token to the file at
+/// the configured path, with the configured permissions, and only serves
+/// clients that start by sending it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketLocation {
+    Unix(PathBuf),
+    Tcp {
+        address: SocketAddr,
+        token_path: PathBuf,
+    },
+}
+
+impl SocketLocation {
+    pub fn new(path: PathBuf, address: Option<SocketAddr>) -> Self {
+        match address {
+            Some(address) => SocketLocation::Tcp {
+                address,
+                token_path: path,
+            },
+            None => SocketLocation::Unix(path),
+        }
+    }
+
+    pub async fn connect(&self) -> std::io::Result<Box<dyn Stream>> {
+        match self {
+            SocketLocation::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+            SocketLocation::Tcp {
+                address,
+                token_path,
+            } => {
+                let token = std::fs::read_to_string(token_path)?;
+                let mut stream = TcpStream::connect(address).await?;
+                stream.write_all(token.trim().as_bytes()).await?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SocketLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketLocation::Unix(path) => write!(f, "{}", path.display()),
+            SocketLocation::Tcp { address, .. } => write!(f, "{address}"),
+        }
+    }
+}
+
+/// Listen for TCP connections at `address` right away, with the same options
+/// as [`TcpListener::bind`]. Listeners of servers are opened this way while
+/// the daemon starts, as the privileges to open them may be dropped after.
+///
+/// With an `interface`, only connections arriving on that interface are
+/// accepted. This is only supported on Linux, elsewhere it is ignored.
+pub fn bind_tcp_listener(
+    address: SocketAddr,
+    interface: Option<InterfaceName>,
+) -> std::io::Result<TcpListener> {
+    let socket = match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    #[cfg(target_os = "linux")]
+    if let Some(interface) = interface {
+        socket.bind_device(Some(interface.to_string().as_bytes()))?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = interface;
+    socket.set_reuseaddr(true)?;
+    socket.bind(address)?;
+    socket.listen(1024)
+}
+
+/// The daemon side of a [`SocketLocation`]
+pub enum Listener {
+    Unix(UnixListener),
+    Tcp {
+        listener: TcpListener,
+        token: Arc<str>,
+    },
+}
+
+/// A client accepted by a [`Listener`], which has yet to show it may connect
+pub struct Connection {
+    stream: Box<dyn Stream>,
+    /// The token a TCP client must send first, and its address
+    token: Option<(Arc<str>, SocketAddr)>,
+}
+
+impl Connection {
+    /// Wait for a client of a TCP socket to send the token, which is reported
+    /// as [`std::io::ErrorKind::PermissionDenied`] when it does not. Clients
+    /// of a Unix socket are let through right away.
+    pub async fn authenticate(self) -> std::io::Result<Box<dyn Stream>> {
+        let Connection { mut stream, token } = self;
+        let Some((token, addr)) = token else {
+            return Ok(stream);
+        };
+
+        let mut received = [0; TOKEN_LENGTH];
+        match tokio::time::timeout(TOKEN_TIMEOUT, stream.read_exact(&mut received)).await {
+            // the comparison takes the same time however much of the token
+            // a client guessed right
+            Ok(Ok(_)) if bool::from(received.ct_eq(token.as_bytes())) => Ok(stream),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("client at {addr} did not send a valid token"),
+            )),
+        }
+    }
+}
+
+impl Listener {
+    /// Listen at `location`, creating the socket or token file with the
+    /// given permissions
+    pub async fn bind(
+        location: &SocketLocation,
+        permissions: Permissions,
+    ) -> std::io::Result<Self> {
+        match location {
+            SocketLocation::Unix(path) => Ok(Listener::Unix(create_unix_socket_with_permissions(
+                path,
+                permissions,
+            )?)),
+            SocketLocation::Tcp {
+                address,
+                token_path,
+            } => {
+                if !address.ip().is_loopback() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{address} is not a loopback address"),
+                    ));
+                }
+                let token = create_token_file(token_path, permissions)?;
+                Ok(Listener::Tcp {
+                    listener: TcpListener::bind(address).await?,
+                    token: token.into(),
+                })
+            }
+        }
+    }
+
+    /// Accept the next client. Reading the token of a client of a TCP socket
+    /// is left to [`Connection::authenticate`], so a slow client does not hold
+    /// up the others.
+    pub async fn accept(&self) -> std::io::Result<Connection> {
+        match self {
+            Listener::Unix(listener) => Ok(Connection {
+                stream: Box::new(listener.accept().await?.0),
+                token: None,
+            }),
+            Listener::Tcp { listener, token } => {
+                let (stream, addr) = listener.accept().await?;
+                Ok(Connection {
+                    stream: Box::new(stream),
+                    token: Some((token.clone(), addr)),
+                })
+            }
+        }
+    }
+}
+
+fn create_token_file(path: &Path, permissions: Permissions) -> std::io::Result<String> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let token: String = rand::random::<[u8; TOKEN_LENGTH / 2]>()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(permissions.mode())
+        .open(path)?;
+    // an existing file keeps its permissions when opened
+    std::fs::set_permissions(path, permissions)?;
+    file.write_all(token.as_bytes())?;
+
+    Ok(token)
+}
 
 pub async fn write_json<T>(stream: &mut (impl AsyncWrite + Unpin), value: &T) -> std::io::Result<()>
 where
@@ -84,7 +293,7 @@ fn create_unix_socket(path: &Path) -> std::io::Result<tokio::net::UnixListener>
 
 #[cfg(test)]
 mod tests {
-    use tokio::net::{UnixListener, UnixStream};
+    use std::os::unix::fs::PermissionsExt;
 
     use crate::test::alloc_port;
 
@@ -142,4 +351,64 @@ mod tests {
         // the logic will automatically grow the buffer to the required size
         assert!(!buf.is_empty());
     }
+
+    #[tokio::test]
+    async fn tcp_socket_requires_token() {
+        let token_path = std::env::temp_dir().join(format!("ntp-test-token-{}", alloc_port()));
+        let location = SocketLocation::new(
+            token_path.clone(),
+            Some(format!("127.0.0.1:{}", alloc_port()).parse().unwrap()),
+        );
+        let listener = Listener::bind(&location, PermissionsExt::from_mode(0o600))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::metadata(&token_path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let mut client = location.connect().await.unwrap();
+        let mut server = listener
+            .accept()
+            .await
+            .unwrap()
+            .authenticate()
+            .await
+            .unwrap();
+        write_json(&mut server, &42u64).await.unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(read_json::<u64>(&mut client, &mut buf).await.unwrap(), 42);
+
+        let SocketLocation::Tcp { address, .. } = location else {
+            unreachable!()
+        };
+        let mut intruder = TcpStream::connect(address).await.unwrap();
+        intruder.write_all(&[b'0'; TOKEN_LENGTH]).await.unwrap();
+        let connection = listener.accept().await.unwrap();
+        let err = connection.authenticate().await.err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+        // a client that does not send anything does not keep others out
+        let _silent = TcpStream::connect(address).await.unwrap();
+        let silent = listener.accept().await.unwrap();
+        let mut client = location.connect().await.unwrap();
+        let mut server = listener
+            .accept()
+            .await
+            .unwrap()
+            .authenticate()
+            .await
+            .unwrap();
+        write_json(&mut server, &43u64).await.unwrap();
+        assert_eq!(read_json::<u64>(&mut client, &mut buf).await.unwrap(), 43);
+        let err = silent.authenticate().await.err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+        let location = SocketLocation::new(token_path, Some("192.0.2.1:9976".parse().unwrap()));
+        let err = Listener::bind(&location, PermissionsExt::from_mode(0o600))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
 }
@@ -0,0 +1,122 @@
+use ntp_proto::SourceConfig;
+use tokio::sync::mpsc;
+
+use crate::daemon::config::FileSourceConfig;
+
+use super::{
+    FileSourceCreateParameters, SourceCreateParameters, SourceId, SourceRemovalReason,
+    SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId, standard::StandardSpawnError,
+};
+
+pub struct FileSpawner {
+    config: FileSourceConfig,
+    source_config: SourceConfig,
+    id: SpawnerId,
+    has_spawned: bool,
+}
+
+impl FileSpawner {
+    pub fn new(config: FileSourceConfig, source_config: SourceConfig) -> FileSpawner {
+        FileSpawner {
+            config,
+            source_config,
+            id: Default::default(),
+            has_spawned: false,
+        }
+    }
+}
+
+impl Spawner for FileSpawner {
+    type Error = StandardSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), StandardSpawnError> {
+        action_tx
+            .send(SpawnEvent::new(
+                self.id,
+                SpawnAction::Create(SourceCreateParameters::File(FileSourceCreateParameters {
+                    id: SourceId::new(),
+                    path: self.config.path.clone(),
+                    config: self.source_config,
+                    noise_estimate: self.config.precision.powi(2),
+                })),
+            ))
+            .await?;
+        self.has_spawned = true;
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.has_spawned
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), StandardSpawnError> {
+        if removed_source.reason != SourceRemovalReason::Demobilized {
+            self.has_spawned = false;
+        }
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        self.config.path.display().to_string()
+    }
+
+    fn get_description(&self) -> &str {
+        "file"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::SourceConfig;
+    use tokio::sync::mpsc;
+
+    use crate::daemon::{
+        config::FileSourceConfig,
+        spawn::{SourceCreateParameters, SpawnAction, Spawner, file::FileSpawner},
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    #[tokio::test]
+    async fn creates_a_source() {
+        let path = std::env::temp_dir().join("ntp-test-file-source");
+        let precision = 1e-7;
+        let mut spawner = FileSpawner::new(
+            FileSourceConfig {
+                path: path.clone(),
+                precision,
+            },
+            SourceConfig::default(),
+        );
+        let spawner_id = spawner.get_id();
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap();
+        assert_eq!(res.id, spawner_id);
+
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("expected a source to be created");
+        };
+        assert_eq!(create_params.get_addr(), path.display().to_string());
+
+        let SourceCreateParameters::File(params) = create_params else {
+            panic!("did not receive file source create parameters!");
+        };
+        assert_eq!(params.path, path);
+        assert!((params.noise_estimate - precision.powi(2)).abs() < 1e-20);
+
+        // Should be complete after spawning
+        assert!(spawner.is_complete());
+    }
+}
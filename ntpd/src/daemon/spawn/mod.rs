@@ -1,7 +1,14 @@
-use std::{future::Future, net::SocketAddr, path::PathBuf, sync::atomic::AtomicU64};
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, atomic::AtomicU64},
+    time::Duration,
+};
 
-use ntp_proto::{ProtocolVersion, SourceConfig, SourceNtsData};
+use ntp_proto::{ProtocolVersion, SourceConfig, SourceNtsData, SymmetricKey, Transport};
 use serde::{Deserialize, Serialize};
+use timestamped_socket::interface::InterfaceName;
 use tokio::{
     sync::mpsc,
     time::{Instant, timeout},
@@ -9,16 +16,40 @@ use tokio::{
 
 use super::{config::NormalizedAddress, system::NETWORK_WAIT_PERIOD};
 
+pub mod file;
 pub mod nts;
 pub mod nts_pool;
 pub mod pool;
 #[cfg(feature = "pps")]
 pub mod pps;
+pub mod service;
 pub mod sock;
 pub mod standard;
 
 const NTS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
+/// Delay before the first retry after a spawner reported a failed attempt.
+/// It doubles with every consecutive failed attempt.
+const RETRY_BACKOFF: Duration = Duration::from_secs(4);
+
+/// Longest delay between retries
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(1024);
+
+fn retry_backoff(failed_attempts: usize) -> Duration {
+    let doublings = failed_attempts.saturating_sub(1).min(16) as u32;
+    RETRY_BACKOFF
+        .saturating_mul(1 << doublings)
+        .min(MAX_RETRY_BACKOFF)
+}
+
+/// Time the basic spawner waits before calling `try_spawn` again
+fn retry_wait(failed_attempts: usize) -> Duration {
+    match failed_attempts {
+        0 => NETWORK_WAIT_PERIOD,
+        _ => retry_backoff(failed_attempts),
+    }
+}
+
 /// Unique identifier for a spawner.
 /// This is used to identify which spawner was used to create a source
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
@@ -84,6 +115,7 @@ impl SpawnEvent {
 pub enum SystemEvent {
     SourceRemoved(SourceRemovedEvent),
     SourceRegistered(SourceCreateParameters),
+    SourceReachable(SourceId),
     Idle,
 }
 
@@ -108,29 +140,49 @@ pub enum SourceRemovalReason {
 }
 
 /// The kind of action that the spawner requests to the system.
-/// Currently a spawner can only create sources
+/// Currently a spawner can create sources and report that it failed to keep
+/// its source reachable
 #[derive(Debug)]
 pub enum SpawnAction {
     Create(SourceCreateParameters),
+    Failed(SourceFailure),
     // Remove(()),
 }
 
+/// Reported by a spawner when every step of recovering its source failed,
+/// before it starts over after a backoff
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceFailure {
+    pub address: String,
+    /// Consecutive times recovering the source failed
+    pub failures: usize,
+    /// How long the spawner waits before starting over
+    pub backoff_seconds: u64,
+}
+
 impl SpawnAction {
+    #[allow(clippy::too_many_arguments)]
     pub fn create_ntp(
         id: SourceId,
         addr: SocketAddr,
         normalized_addr: NormalizedAddress,
+        peer: String,
         protocol_version: ProtocolVersion,
         config: SourceConfig,
         nts: Option<Box<SourceNtsData>>,
+        symmetric_key: Option<Arc<SymmetricKey>>,
+        binding: SocketBinding,
     ) -> SpawnAction {
         SpawnAction::Create(SourceCreateParameters::Ntp(NtpSourceCreateParameters {
             id,
             addr,
             normalized_addr,
+            peer,
             protocol_version,
             config,
             nts,
+            symmetric_key,
+            binding,
         }))
     }
 }
@@ -139,6 +191,7 @@ impl SpawnAction {
 pub enum SourceCreateParameters {
     Ntp(NtpSourceCreateParameters),
     Sock(SockSourceCreateParameters),
+    File(FileSourceCreateParameters),
     #[cfg(feature = "pps")]
     Pps(PpsSourceCreateParameters),
 }
@@ -148,15 +201,28 @@ impl SourceCreateParameters {
         match self {
             Self::Ntp(params) => params.id,
             Self::Sock(params) => params.id,
+            Self::File(params) => params.id,
             #[cfg(feature = "pps")]
             Self::Pps(params) => params.id,
         }
     }
 
+    /// Name by which the source can be referred to in control commands
+    pub fn get_name(&self) -> String {
+        match self {
+            Self::Ntp(params) => params.normalized_addr.to_string(),
+            Self::Sock(params) => params.path.display().to_string(),
+            Self::File(params) => params.path.display().to_string(),
+            #[cfg(feature = "pps")]
+            Self::Pps(params) => params.path.display().to_string(),
+        }
+    }
+
     pub fn get_addr(&self) -> String {
         match self {
             Self::Ntp(params) => params.addr.to_string(),
             Self::Sock(params) => params.path.display().to_string(),
+            Self::File(params) => params.path.display().to_string(),
             #[cfg(feature = "pps")]
             Self::Pps(params) => params.path.display().to_string(),
         }
@@ -168,9 +234,30 @@ pub struct NtpSourceCreateParameters {
     pub id: SourceId,
     pub addr: SocketAddr,
     pub normalized_addr: NormalizedAddress,
+    /// Name of the logical peer this source is for. A source replacing an
+    /// earlier one of the same peer, e.g. after the name of the server
+    /// resolved to another address, continues from its filter state.
+    pub peer: String,
     pub protocol_version: ProtocolVersion,
     pub config: SourceConfig,
     pub nts: Option<Box<SourceNtsData>>,
+    pub symmetric_key: Option<Arc<SymmetricKey>>,
+    pub binding: SocketBinding,
+}
+
+/// Local side of the socket over which an NTP source is polled
+///
+/// When both are given, the interface takes precedence on Linux. Other
+/// platforms do not support binding to an interface and only use the address.
+/// The transport determines whether this is a UDP socket or a connection.
+/// With a NAT keepalive interval, a UDP socket is kept open across polls and
+/// a one byte datagram is sent over it whenever it has been idle that long.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SocketBinding {
+    pub interface: Option<InterfaceName>,
+    pub source_address: Option<IpAddr>,
+    pub transport: Transport,
+    pub nat_keepalive: Option<std::time::Duration>,
 }
 
 #[derive(Debug)]
@@ -181,6 +268,14 @@ pub struct SockSourceCreateParameters {
     pub noise_estimate: f64,
 }
 
+#[derive(Debug)]
+pub struct FileSourceCreateParameters {
+    pub id: SourceId,
+    pub path: PathBuf,
+    pub config: SourceConfig,
+    pub noise_estimate: f64,
+}
+
 #[cfg(feature = "pps")]
 #[derive(Debug)]
 pub struct PpsSourceCreateParameters {
@@ -198,7 +293,8 @@ pub trait Spawner {
     ///
     /// It is ok for this function to use some time when spawning a new client.
     /// However, it should not implement it's own retry or backoff feature, but
-    /// rather rely on that provided by the basic spawner.
+    /// rather rely on that provided by the basic spawner. A spawner that wants
+    /// to be retried less often reports so through `failed_attempts`.
     fn try_spawn(
         &mut self,
         action_tx: &mpsc::Sender<SpawnEvent>,
@@ -236,6 +332,30 @@ pub trait Spawner {
         async { Ok(()) }
     }
 
+    /// Event handler for when a source first answers its polls
+    ///
+    /// This is called at most once for every spawned source, as soon as the
+    /// system sees that it has become reachable.
+    fn handle_source_reachable(
+        &mut self,
+        _id: SourceId,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Number of consecutive failed attempts to spawn a working source
+    ///
+    /// The basic spawner waits longer before the next call to `try_spawn`
+    /// with every failed attempt. Zero retries at the normal rate.
+    fn failed_attempts(&self) -> usize {
+        0
+    }
+
+    /// Failure to report to the system, taken after every event
+    fn take_failure(&mut self) -> Option<SourceFailure> {
+        None
+    }
+
     /// Get the id of the spawner
     fn get_id(&self) -> SpawnerId;
 
@@ -255,7 +375,8 @@ pub async fn spawner_task<S: Spawner + Send + 'static>(
     let mut last_ticket_time = Instant::now();
 
     loop {
-        if last_ticket_time.elapsed() >= NETWORK_WAIT_PERIOD {
+        let wait = retry_wait(spawner.failed_attempts());
+        if last_ticket_time.elapsed() >= wait {
             has_ticket = true;
         }
 
@@ -269,7 +390,7 @@ pub async fn spawner_task<S: Spawner + Send + 'static>(
             system_notify.recv().await
         } else {
             timeout(
-                NETWORK_WAIT_PERIOD - last_ticket_time.elapsed(),
+                wait.saturating_sub(last_ticket_time.elapsed()),
                 system_notify.recv(),
             )
             .await
@@ -287,8 +408,18 @@ pub async fn spawner_task<S: Spawner + Send + 'static>(
             SystemEvent::SourceRemoved(removed_source) => {
                 spawner.handle_source_removed(removed_source).await?;
             }
+            SystemEvent::SourceReachable(id) => {
+                spawner.handle_source_reachable(id).await?;
+            }
             SystemEvent::Idle => {}
         }
+
+        if let Some(failure) = spawner.take_failure() {
+            let event = SpawnEvent::new(spawner.get_id(), SpawnAction::Failed(failure));
+            if action_tx.send(event).await.is_err() {
+                break;
+            }
+        }
     }
 
     Ok(())
@@ -296,7 +427,12 @@ pub async fn spawner_task<S: Spawner + Send + 'static>(
 
 #[cfg(test)]
 mod tests {
-    use super::{NtpSourceCreateParameters, SourceCreateParameters, SpawnAction, SpawnEvent};
+    use std::time::Duration;
+
+    use super::{
+        NETWORK_WAIT_PERIOD, NtpSourceCreateParameters, SourceCreateParameters, SpawnAction,
+        SpawnEvent, retry_wait,
+    };
 
     pub fn get_ntp_create_params(res: SpawnEvent) -> Option<NtpSourceCreateParameters> {
         let SpawnAction::Create(SourceCreateParameters::Ntp(params)) = res.action else {
@@ -304,4 +440,14 @@ mod tests {
         };
         Some(params)
     }
+
+    #[test]
+    fn retry_wait_doubles_up_to_a_limit() {
+        assert_eq!(retry_wait(0), NETWORK_WAIT_PERIOD);
+        assert_eq!(retry_wait(1), Duration::from_secs(4));
+        assert_eq!(retry_wait(2), Duration::from_secs(8));
+        assert_eq!(retry_wait(5), Duration::from_secs(64));
+        assert_eq!(retry_wait(9), Duration::from_secs(1024));
+        assert_eq!(retry_wait(usize::MAX), Duration::from_secs(1024));
+    }
 }
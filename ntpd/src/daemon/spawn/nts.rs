@@ -2,14 +2,18 @@ use std::fmt::Display;
 use std::net::SocketAddr;
 use std::ops::Deref;
 
-use ntp_proto::{KeyExchangeClient, NtsClientConfig, NtsError, SourceConfig};
+use ntp_proto::{
+    AeadAlgorithm, KeyExchangeClient, NtsClientConfig, NtsError, SourceConfig, Transport,
+};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tracing::warn;
 
-use super::super::config::NtsSourceConfig;
+use super::super::{config::NtsSourceConfig, util::canonical_addr};
 
-use super::{SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId};
+use super::{
+    SocketBinding, SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+};
 
 pub struct NtsSpawner {
     config: NtsSourceConfig,
@@ -43,7 +47,7 @@ impl From<mpsc::error::SendError<SpawnEvent>> for NtsSpawnError {
 pub(super) async fn resolve_addr(address: (&str, u16)) -> Option<SocketAddr> {
     match tokio::net::lookup_host(address).await {
         Ok(mut addresses) => match addresses.next() {
-            Some(address) => Some(address),
+            Some(address) => Some(canonical_addr(address)),
             None => {
                 warn!("received unknown domain name from NTS-ke");
                 None
@@ -74,6 +78,13 @@ impl NtsSpawner {
             has_spawned: false,
         })
     }
+
+    /// Only offer the given AEAD algorithms in the key exchange, in order of
+    /// preference
+    pub fn with_algorithms(mut self, algorithms: &[AeadAlgorithm]) -> Self {
+        self.key_exchange_client = self.key_exchange_client.with_algorithms(algorithms);
+        self
+    }
 }
 
 impl Spawner for NtsSpawner {
@@ -112,9 +123,17 @@ impl Spawner for NtsSpawner {
                                 SourceId::new(),
                                 address,
                                 self.config.address.deref().clone(),
+                                self.config.address.to_string(),
                                 ke.protocol_version,
                                 self.source_config,
                                 Some(ke.nts),
+                                None,
+                                SocketBinding {
+                                    interface: self.config.interface,
+                                    source_address: self.config.source_address,
+                                    transport: Transport::Udp,
+                                    nat_keepalive: None,
+                                },
                             ),
                         ))
                         .await?;
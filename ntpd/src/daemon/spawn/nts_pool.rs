@@ -6,11 +6,15 @@ use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tracing::warn;
 
-use ntp_proto::{KeyExchangeClient, NtsClientConfig, NtsError, SourceConfig};
+use ntp_proto::{
+    AeadAlgorithm, KeyExchangeClient, NtsClientConfig, NtsError, SourceConfig, Transport,
+};
 
 use super::super::config::NtsPoolSourceConfig;
 
-use super::{SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId};
+use super::{
+    SocketBinding, SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+};
 
 use super::nts::resolve_addr;
 
@@ -67,6 +71,13 @@ impl NtsPoolSpawner {
         })
     }
 
+    /// Only offer the given AEAD algorithms in the key exchange, in order of
+    /// preference
+    pub fn with_algorithms(mut self, algorithms: &[AeadAlgorithm]) -> Self {
+        self.key_exchange_client = self.key_exchange_client.with_algorithms(algorithms);
+        self
+    }
+
     fn contains_source(&self, domain: &str) -> bool {
         self.current_sources
             .iter()
@@ -121,9 +132,17 @@ impl Spawner for NtsPoolSpawner {
                                     id,
                                     address,
                                     self.config.addr.deref().clone(),
+                                    address.to_string(),
                                     ke.protocol_version,
                                     self.source_config,
                                     Some(ke.nts),
+                                    None,
+                                    SocketBinding {
+                                        interface: self.config.interface,
+                                        source_address: self.config.source_address,
+                                        transport: Transport::Udp,
+                                        nat_keepalive: None,
+                                    },
                                 ),
                             ))
                             .await?;
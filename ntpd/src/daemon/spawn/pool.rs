@@ -1,13 +1,15 @@
 use std::fmt::Display;
-use std::{net::SocketAddr, ops::Deref};
+use std::{collections::HashSet, net::SocketAddr, ops::Deref};
 
-use ntp_proto::SourceConfig;
+use ntp_proto::{SourceConfig, Transport};
 use tokio::sync::mpsc;
-use tracing::warn;
+use tracing::{info, warn};
 
-use super::super::config::PoolSourceConfig;
+use super::super::config::{AddressFamily, PoolSourceConfig};
 
-use super::{SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId};
+use super::{
+    SocketBinding, SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+};
 
 struct PoolSource {
     id: SourceId,
@@ -20,6 +22,8 @@ pub struct PoolSpawner {
     id: SpawnerId,
     current_sources: Vec<PoolSource>,
     known_ips: Vec<SocketAddr>,
+    /// Consecutive attempts to resolve the address of the pool that failed
+    failed_resolutions: usize,
 }
 
 #[derive(Debug)]
@@ -41,6 +45,7 @@ impl PoolSpawner {
             id: Default::default(),
             current_sources: Default::default(),
             known_ips: Default::default(),
+            failed_resolutions: 0,
         }
     }
 }
@@ -60,15 +65,40 @@ impl Spawner for PoolSpawner {
         if self.known_ips.len() < self.config.count - self.current_sources.len() {
             match self.config.addr.lookup_host().await {
                 Ok(addresses) => {
+                    if self.failed_resolutions > 0 {
+                        info!(
+                            address = %self.config.addr.deref(),
+                            failed_attempts = self.failed_resolutions,
+                            "pool address resolved, adding sources"
+                        );
+                        self.failed_resolutions = 0;
+                    }
                     // add the addresses looked up to our list of known ips
                     self.known_ips.append(&mut addresses.collect());
-                    // remove known ips that we are already connected to or that we want to ignore
+                    // remove known ips that we already know or are already connected to, or
+                    // that we want to ignore
+                    let mut seen = HashSet::new();
                     self.known_ips.retain(|ip| {
-                        !self.current_sources.iter().any(|p| p.addr == *ip)
-                            && !self.config.ignore.iter().any(|ign| *ign == ip.ip())
+                        seen.insert(*ip)
+                            && !self.current_sources.iter().any(|p| p.addr == *ip)
+                            && !self
+                                .config
+                                .ignore
+                                .iter()
+                                .any(|ign| ign.to_canonical() == ip.ip())
+                            && self
+                                .config
+                                .force_ip
+                                .is_none_or(|family| AddressFamily::of(ip) == family)
                     });
+                    // sources are taken from the back, so move the preferred family there
+                    if let Some(family) = self.config.prefer_ip {
+                        self.known_ips
+                            .sort_by_key(|ip| AddressFamily::of(ip) == family);
+                    }
                 }
                 Err(e) => {
+                    self.failed_resolutions += 1;
                     warn!(error = ?e, "error while resolving source address, retrying");
                     return Ok(());
                 }
@@ -84,9 +114,17 @@ impl Spawner for PoolSpawner {
                     id,
                     addr,
                     self.config.addr.deref().clone(),
+                    addr.to_string(),
                     self.config.ntp_version,
                     self.source_config,
                     None,
+                    None,
+                    SocketBinding {
+                        interface: self.config.interface,
+                        source_address: self.config.source_address,
+                        transport: Transport::Udp,
+                        nat_keepalive: self.config.nat_keepalive,
+                    },
                 );
                 tracing::debug!(?action, "intending to spawn new pool source at");
 
@@ -135,7 +173,7 @@ mod tests {
     use tokio::sync::mpsc::{self, error::TryRecvError};
 
     use crate::daemon::{
-        config::{NormalizedAddress, PoolSourceConfig},
+        config::{AddressFamily, NormalizedAddress, PoolSourceConfig},
         spawn::{
             SourceRemovalReason, SourceRemovedEvent, Spawner, pool::PoolSpawner,
             tests::get_ntp_create_params,
@@ -155,6 +193,11 @@ mod tests {
                 count: 2,
                 ignore: vec![],
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -190,6 +233,46 @@ mod tests {
         assert!(pool.is_complete());
     }
 
+    #[tokio::test]
+    async fn mapped_addresses_are_one_source() {
+        let address_strings = ["127.0.0.1:123", "[::ffff:127.0.0.1]:123", "127.0.0.2:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 3,
+                ignore: vec![],
+                ntp_version: ProtocolVersion::V4,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                nat_keepalive: None,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let addr1 = get_ntp_create_params(action_rx.try_recv().unwrap())
+            .unwrap()
+            .addr;
+        let addr2 = get_ntp_create_params(action_rx.try_recv().unwrap())
+            .unwrap()
+            .addr;
+        assert_ne!(addr1, addr2);
+        assert!(addresses[0] == addr1 || addresses[0] == addr2);
+        assert!(addresses[2] == addr1 || addresses[2] == addr2);
+
+        // a new lookup does not bring back the same server under another address
+        pool.try_spawn(&action_tx).await.unwrap();
+        let res = action_rx.try_recv().unwrap_err();
+        assert_eq!(res, TryRecvError::Empty);
+        assert!(!pool.is_complete());
+    }
+
     #[tokio::test]
     async fn respects_ntp_version_force_v5() {
         let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
@@ -202,6 +285,11 @@ mod tests {
                 count: 2,
                 ignore: vec![],
                 ntp_version: ProtocolVersion::V5,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -243,6 +331,11 @@ mod tests {
                 count: 2,
                 ignore: vec![],
                 ntp_version: ProtocolVersion::V4,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -285,6 +378,11 @@ mod tests {
                 count: 2,
                 ignore: ignores.clone(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -326,6 +424,11 @@ mod tests {
                 count: 2,
                 ignore: vec![],
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -371,6 +474,11 @@ mod tests {
                 count: 2,
                 ignore: vec![],
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -381,4 +489,36 @@ mod tests {
         assert_eq!(res, TryRecvError::Empty);
         assert!(!pool.is_complete());
     }
+
+    #[tokio::test]
+    async fn respects_force_ip() {
+        let address_strings = ["192.0.2.1:123", "[2001:db8::1]:123", "[2001:db8::2]:123"];
+        let addresses = address_strings.map(|addr| addr.parse().unwrap());
+
+        let mut pool = PoolSpawner::new(
+            PoolSourceConfig {
+                addr: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses.to_vec())
+                    .into(),
+                count: 2,
+                ignore: vec![],
+                ntp_version: ProtocolVersion::V4,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: Some(AddressFamily::V4),
+                nat_keepalive: None,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        pool.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert_eq!(params.addr, addresses[0]);
+
+        // the IPv6 addresses are never used
+        let res = action_rx.try_recv().unwrap_err();
+        assert_eq!(res, TryRecvError::Empty);
+        assert!(!pool.is_complete());
+    }
 }
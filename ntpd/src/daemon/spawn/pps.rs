@@ -110,7 +110,9 @@ mod tests {
         let res = action_rx.try_recv().unwrap();
         assert_eq!(res.id, spawner_id);
 
-        let SpawnAction::Create(create_params) = res.action;
+        let SpawnAction::Create(create_params) = res.action else {
+            panic!("expected a source to be created");
+        };
         assert_eq!(create_params.get_addr(), socket_path.display().to_string());
 
         let SourceCreateParameters::Pps(params) = create_params else {
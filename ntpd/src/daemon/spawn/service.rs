@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::net::SocketAddr;
+
+use ntp_proto::{SourceConfig, Transport};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use super::super::{
+    config::{AddressFamily, NormalizedAddress, ServiceSourceConfig},
+    dns_sd::{self, DiscoveredServer},
+};
+
+use super::{
+    SocketBinding, SourceId, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+};
+
+struct ServiceSource {
+    id: SourceId,
+    addr: SocketAddr,
+}
+
+/// Spawns sources for the servers found through DNS service discovery, like a
+/// pool does for the addresses of its name
+pub struct ServiceSpawner {
+    config: ServiceSourceConfig,
+    source_config: SourceConfig,
+    id: SpawnerId,
+    current_sources: Vec<ServiceSource>,
+    /// Servers not yet used, the next one to use at the back
+    known_servers: Vec<DiscoveredServer>,
+    /// Consecutive attempts to discover servers that failed
+    failed_discoveries: usize,
+
+    /// Used instead of the servers found on the network
+    #[cfg(test)]
+    hardcoded_servers: Vec<DiscoveredServer>,
+}
+
+#[derive(Debug)]
+pub enum ServiceSpawnError {}
+
+impl Display for ServiceSpawnError {
+    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        unreachable!()
+    }
+}
+
+impl std::error::Error for ServiceSpawnError {}
+
+impl ServiceSpawner {
+    pub fn new(config: ServiceSourceConfig, source_config: SourceConfig) -> ServiceSpawner {
+        ServiceSpawner {
+            config,
+            source_config,
+            id: Default::default(),
+            current_sources: Default::default(),
+            known_servers: Default::default(),
+            failed_discoveries: 0,
+            #[cfg(test)]
+            hardcoded_servers: vec![],
+        }
+    }
+
+    #[cfg(not(test))]
+    async fn discover(&self) -> std::io::Result<Vec<DiscoveredServer>> {
+        dns_sd::discover(self.config.domain.as_deref(), self.config.mdns).await
+    }
+
+    #[cfg(test)]
+    async fn discover(&self) -> std::io::Result<Vec<DiscoveredServer>> {
+        Ok(self.hardcoded_servers.clone())
+    }
+}
+
+impl Spawner for ServiceSpawner {
+    type Error = ServiceSpawnError;
+
+    async fn try_spawn(
+        &mut self,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+    ) -> Result<(), ServiceSpawnError> {
+        // early return if there is nothing to do
+        if self.current_sources.len() >= self.config.count {
+            return Ok(());
+        }
+
+        if self.known_servers.len() < self.config.count - self.current_sources.len() {
+            match self.discover().await {
+                Ok(servers) => {
+                    if self.failed_discoveries > 0 {
+                        info!(
+                            service = %self.get_addr_description(),
+                            failed_attempts = self.failed_discoveries,
+                            "NTP servers discovered, adding sources"
+                        );
+                        self.failed_discoveries = 0;
+                    }
+                    // a new discovery replaces the servers left over from the
+                    // previous one, in the order given by the service records
+                    self.known_servers = servers.into_iter().rev().collect();
+                    let mut seen = HashSet::new();
+                    self.known_servers.retain(|server| {
+                        seen.insert(server.addr)
+                            && !self.current_sources.iter().any(|s| s.addr == server.addr)
+                            && !self
+                                .config
+                                .ignore
+                                .iter()
+                                .any(|ign| ign.to_canonical() == server.addr.ip())
+                            && self
+                                .config
+                                .force_ip
+                                .is_none_or(|family| AddressFamily::of(&server.addr) == family)
+                    });
+                    if let Some(family) = self.config.prefer_ip {
+                        self.known_servers
+                            .sort_by_key(|server| AddressFamily::of(&server.addr) == family);
+                    }
+                }
+                Err(e) => {
+                    self.failed_discoveries += 1;
+                    warn!(error = ?e, "error while discovering NTP servers, retrying");
+                    return Ok(());
+                }
+            }
+        }
+
+        while self.current_sources.len() < self.config.count {
+            let Some(server) = self.known_servers.pop() else {
+                break;
+            };
+            let id = SourceId::new();
+            self.current_sources.push(ServiceSource {
+                id,
+                addr: server.addr,
+            });
+            let action = SpawnAction::create_ntp(
+                id,
+                server.addr,
+                NormalizedAddress::new_unchecked(&server.name, server.addr.port()),
+                server.addr.to_string(),
+                self.config.ntp_version,
+                self.source_config,
+                None,
+                None,
+                SocketBinding {
+                    interface: self.config.interface,
+                    source_address: self.config.source_address,
+                    transport: Transport::Udp,
+                    nat_keepalive: self.config.nat_keepalive,
+                },
+            );
+            tracing::debug!(?action, "intending to spawn new discovered source at");
+
+            action_tx
+                .send(SpawnEvent::new(self.id, action))
+                .await
+                .expect("Channel was no longer connected");
+        }
+
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.current_sources.len() >= self.config.count
+    }
+
+    async fn handle_source_removed(
+        &mut self,
+        removed_source: SourceRemovedEvent,
+    ) -> Result<(), ServiceSpawnError> {
+        self.current_sources.retain(|s| s.id != removed_source.id);
+        Ok(())
+    }
+
+    fn get_id(&self) -> SpawnerId {
+        self.id
+    }
+
+    fn get_addr_description(&self) -> String {
+        let service = match &self.config.domain {
+            Some(domain) => format!("{}.{domain}", dns_sd::NTP_SERVICE),
+            None => dns_sd::NTP_SERVICE.to_owned(),
+        };
+        if self.config.mdns {
+            format!("{service} and mDNS ({})", self.config.count)
+        } else {
+            format!("{service} ({})", self.config.count)
+        }
+    }
+
+    fn get_description(&self) -> &str {
+        "srv"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntp_proto::{ProtocolVersion, SourceConfig};
+    use tokio::sync::mpsc::{self, error::TryRecvError};
+
+    use crate::daemon::{
+        config::{AddressFamily, ServiceSourceConfig},
+        dns_sd::DiscoveredServer,
+        spawn::{
+            SourceRemovalReason, SourceRemovedEvent, Spawner, service::ServiceSpawner,
+            tests::get_ntp_create_params,
+        },
+        system::MESSAGE_BUFFER_SIZE,
+    };
+
+    fn config(count: usize) -> ServiceSourceConfig {
+        ServiceSourceConfig {
+            domain: Some("example.com".to_owned()),
+            mdns: false,
+            count,
+            ignore: vec![],
+            ntp_version: ProtocolVersion::V4,
+            interface: None,
+            source_address: None,
+            prefer_ip: None,
+            force_ip: None,
+            nat_keepalive: None,
+        }
+    }
+
+    fn server(name: &str, addr: &str) -> DiscoveredServer {
+        DiscoveredServer {
+            name: name.to_owned(),
+            addr: addr.parse().unwrap(),
+            priority: 10,
+            weight: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn spawns_discovered_servers_in_order() {
+        let mut spawner = ServiceSpawner::new(config(2), SourceConfig::default());
+        spawner.hardcoded_servers = vec![
+            server("a.example.com", "192.0.2.1:123"),
+            server("b.example.com", "192.0.2.2:1123"),
+            server("c.example.com", "192.0.2.3:123"),
+        ];
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let first = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert_eq!(first.addr, "192.0.2.1:123".parse().unwrap());
+        assert_eq!(first.normalized_addr.to_string(), "a.example.com:123");
+        let second = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert_eq!(second.addr, "192.0.2.2:1123".parse().unwrap());
+        assert_eq!(second.normalized_addr.to_string(), "b.example.com:1123");
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert!(spawner.is_complete());
+
+        // a lost source is replaced by the next server, not one still in use
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: first.id,
+                reason: SourceRemovalReason::Unreachable,
+            })
+            .await
+            .unwrap();
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let third = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert_eq!(third.addr, "192.0.2.3:123".parse().unwrap());
+        assert!(spawner.is_complete());
+    }
+
+    #[tokio::test]
+    async fn respects_ignore_and_force_ip() {
+        let mut spawner = ServiceSpawner::new(
+            ServiceSourceConfig {
+                ignore: vec!["192.0.2.1".parse().unwrap()],
+                force_ip: Some(AddressFamily::V4),
+                ..config(3)
+            },
+            SourceConfig::default(),
+        );
+        spawner.hardcoded_servers = vec![
+            server("a.example.com", "192.0.2.1:123"),
+            server("b.example.com", "[2001:db8::2]:123"),
+            server("c.example.com", "192.0.2.3:123"),
+        ];
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert_eq!(params.addr, "192.0.2.3:123".parse().unwrap());
+        assert_eq!(action_rx.try_recv().unwrap_err(), TryRecvError::Empty);
+        assert!(!spawner.is_complete());
+    }
+}
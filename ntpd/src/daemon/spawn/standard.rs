@@ -1,24 +1,61 @@
 use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{net::SocketAddr, ops::Deref};
 
-use ntp_proto::SourceConfig;
+use ntp_proto::{SourceConfig, SymmetricKey};
 use tokio::sync::mpsc;
-use tracing::warn;
+use tracing::{info, warn};
 
-use super::super::config::StandardSource;
+use super::super::config::{AddressFamily, StandardSource};
 
 use super::{
-    SourceId, SourceRemovalReason, SourceRemovedEvent, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+    SocketBinding, SourceFailure, SourceId, SourceRemovalReason, SourceRemovedEvent, SpawnAction,
+    SpawnEvent, Spawner, SpawnerId,
 };
 
 pub struct StandardSpawner {
     id: SpawnerId,
     config: StandardSource,
     source_config: SourceConfig,
-    resolved: Option<SocketAddr>,
+    symmetric_key: Option<Arc<SymmetricKey>>,
+    resolved: Vec<SocketAddr>,
+    resolved_at: Option<Instant>,
+    current: usize,
+    unreachable_count: usize,
+    /// Consecutive attempts to resolve the address that gave nothing
+    failed_resolutions: usize,
+    /// Consecutive times the recovery ladder ran out without the source
+    /// becoming reachable
+    failures: usize,
+    /// Failed attempts the basic spawner backs off for before the next
+    /// attempt to spawn, zero after a source was created
+    failed_attempts: usize,
+    /// Failure the basic spawner reports to the system
+    pending_failure: Option<SourceFailure>,
     has_spawned: bool,
 }
 
+/// How long the addresses a name resolved to are used before the name is
+/// resolved again on the next respawn of the source.
+const RESOLUTION_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// The steps taken when a source keeps getting reported as unreachable. Every
+/// consecutive report moves one step further down the ladder, until a source
+/// becomes reachable again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    /// Create the source anew (and with it its socket) on the same address
+    Reconnect,
+    /// Resolve the configured name again
+    Reresolve,
+    /// Try the next address from the most recent resolution
+    Alternate,
+    /// Nothing left to try, report the failure and start over after a
+    /// backoff
+    Failed,
+}
+
 #[derive(Debug)]
 pub enum StandardSpawnError {
     SendError(mpsc::error::SendError<SpawnEvent>),
@@ -46,33 +83,166 @@ impl StandardSpawner {
             id: Default::default(),
             config,
             source_config,
-            resolved: None,
+            symmetric_key: None,
+            resolved: vec![],
+            resolved_at: None,
+            current: 0,
+            unreachable_count: 0,
+            failed_resolutions: 0,
+            failures: 0,
+            failed_attempts: 0,
+            pending_failure: None,
             has_spawned: false,
         }
     }
 
+    /// Authenticate the packets of the spawned sources with the given key
+    pub fn with_symmetric_key(mut self, symmetric_key: Option<Arc<SymmetricKey>>) -> Self {
+        self.symmetric_key = symmetric_key;
+        self
+    }
+
     async fn do_resolve(&mut self, force_resolve: bool) -> Option<SocketAddr> {
-        if let (false, Some(addr)) = (force_resolve, self.resolved) {
-            Some(addr)
+        let expired = self
+            .resolved_at
+            .is_none_or(|at| at.elapsed() >= RESOLUTION_LIFETIME);
+        if let (false, false, Some(addr)) =
+            (force_resolve, expired, self.resolved.get(self.current))
+        {
+            Some(*addr)
         } else {
             match self.config.address.lookup_host().await {
-                Ok(mut addresses) => match addresses.next() {
-                    None => {
+                Ok(addresses) => {
+                    self.resolved = order_by_family(
+                        addresses.collect(),
+                        self.config.prefer_ip,
+                        self.config.force_ip,
+                    );
+                    self.resolved_at = Some(Instant::now());
+                    self.current = 0;
+                    if self.resolved.is_empty() {
+                        self.failed_resolution();
                         warn!("Could not resolve source address, retrying");
-                        None
-                    }
-                    Some(first) => {
-                        self.resolved = Some(first);
-                        self.resolved
+                    } else if self.failed_resolutions > 0 {
+                        info!(
+                            address = %self.config.address.deref(),
+                            failed_attempts = self.failed_resolutions,
+                            "source address resolved, adding the source"
+                        );
+                        self.failed_resolutions = 0;
                     }
-                },
+                    self.resolved.first().copied()
+                }
                 Err(e) => {
+                    self.failed_resolution();
                     warn!(error = ?e, "error while resolving source address, retrying");
                     None
                 }
             }
         }
     }
+
+    /// Have the basic spawner wait longer with every consecutive failure to
+    /// resolve the address
+    fn failed_resolution(&mut self) {
+        self.failed_resolutions += 1;
+        self.failed_attempts = self.failed_resolutions;
+    }
+
+    /// Move on to the next address after the source could not use the
+    /// network, which is of the other family if the name has both.
+    fn fall_back(&mut self) {
+        if self.resolved.len() > 1 {
+            self.current = (self.current + 1) % self.resolved.len();
+            info!(
+                address = %self.config.address.deref(),
+                alternate = %self.resolved[self.current],
+                "network issue with source, trying an alternate address"
+            );
+        }
+    }
+
+    fn next_recovery(&self) -> Recovery {
+        match self.unreachable_count {
+            ..=1 => Recovery::Reconnect,
+            2 => Recovery::Reresolve,
+            _ if self.current + 1 < self.resolved.len() => Recovery::Alternate,
+            _ => Recovery::Failed,
+        }
+    }
+
+    fn escalate(&mut self) {
+        self.unreachable_count += 1;
+        let address = self.config.address.deref();
+        match self.next_recovery() {
+            Recovery::Reconnect => {
+                info!(%address, "source unreachable, recreating it");
+            }
+            Recovery::Reresolve => {
+                info!(%address, "source still unreachable, resolving its address again");
+                self.resolved.clear();
+            }
+            Recovery::Alternate => {
+                self.current += 1;
+                info!(
+                    %address,
+                    alternate = %self.resolved[self.current],
+                    "source still unreachable, trying an alternate address"
+                );
+            }
+            Recovery::Failed => {
+                self.failures += 1;
+                let backoff = super::retry_backoff(self.failures);
+                warn!(
+                    %address,
+                    attempts = self.unreachable_count,
+                    failures = self.failures,
+                    ?backoff,
+                    "source failed: unreachable on all of its addresses, starting over"
+                );
+                self.pending_failure = Some(SourceFailure {
+                    address: address.to_string(),
+                    failures: self.failures,
+                    backoff_seconds: backoff.as_secs(),
+                });
+                self.failed_attempts = self.failures;
+                self.unreachable_count = 0;
+                self.resolved.clear();
+            }
+        }
+    }
+}
+
+/// Order resolved addresses such that the families alternate, starting with
+/// the preferred family, or otherwise the family the resolver returned first.
+/// Addresses within a family keep the order of the resolver.
+fn order_by_family(
+    addresses: Vec<SocketAddr>,
+    prefer: Option<AddressFamily>,
+    force: Option<AddressFamily>,
+) -> Vec<SocketAddr> {
+    let addresses: Vec<_> = addresses
+        .into_iter()
+        .filter(|addr| force.is_none_or(|family| AddressFamily::of(addr) == family))
+        .collect();
+    let Some(first) = prefer.or_else(|| addresses.first().map(AddressFamily::of)) else {
+        return addresses;
+    };
+
+    let (preferred, other): (Vec<_>, Vec<_>) = addresses
+        .into_iter()
+        .partition(|addr| AddressFamily::of(addr) == first);
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+
+    let mut ordered = vec![];
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
 }
 
 impl Spawner for StandardSpawner {
@@ -92,13 +262,22 @@ impl Spawner for StandardSpawner {
                     SourceId::new(),
                     addr,
                     self.config.address.deref().clone(),
+                    self.config.address.to_string(),
                     self.config.ntp_version,
                     self.source_config,
                     None,
+                    self.symmetric_key.clone(),
+                    SocketBinding {
+                        interface: self.config.interface,
+                        source_address: self.config.source_address,
+                        transport: self.config.transport,
+                        nat_keepalive: self.config.nat_keepalive,
+                    },
                 ),
             ))
             .await?;
         self.has_spawned = true;
+        self.failed_attempts = 0;
         Ok(())
     }
 
@@ -110,9 +289,10 @@ impl Spawner for StandardSpawner {
         &mut self,
         removed_source: SourceRemovedEvent,
     ) -> Result<(), StandardSpawnError> {
-        if removed_source.reason == SourceRemovalReason::Unreachable {
-            // force new resolution
-            self.resolved = None;
+        match removed_source.reason {
+            SourceRemovalReason::Unreachable => self.escalate(),
+            SourceRemovalReason::NetworkIssue => self.fall_back(),
+            SourceRemovalReason::Demobilized => {}
         }
         if removed_source.reason != SourceRemovalReason::Demobilized {
             self.has_spawned = false;
@@ -120,6 +300,20 @@ impl Spawner for StandardSpawner {
         Ok(())
     }
 
+    async fn handle_source_reachable(&mut self, _id: SourceId) -> Result<(), StandardSpawnError> {
+        self.unreachable_count = 0;
+        self.failures = 0;
+        Ok(())
+    }
+
+    fn failed_attempts(&self) -> usize {
+        self.failed_attempts
+    }
+
+    fn take_failure(&mut self) -> Option<SourceFailure> {
+        self.pending_failure.take()
+    }
+
     fn get_id(&self) -> SpawnerId {
         self.id
     }
@@ -141,10 +335,12 @@ mod tests {
     use tokio::sync::mpsc::{self, error::TryRecvError};
 
     use crate::daemon::{
-        config::{NormalizedAddress, StandardSource},
+        config::{AddressFamily, NormalizedAddress, StandardSource},
         spawn::{
-            SourceRemovalReason, SourceRemovedEvent, SpawnAction, Spawner,
-            standard::StandardSpawner, tests::get_ntp_create_params,
+            NtpSourceCreateParameters, SourceId, SourceRemovalReason, SourceRemovedEvent,
+            SpawnAction, SpawnEvent, Spawner,
+            standard::{StandardSpawner, order_by_family},
+            tests::get_ntp_create_params,
         },
         system::MESSAGE_BUFFER_SIZE,
     };
@@ -160,6 +356,13 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -170,7 +373,9 @@ mod tests {
         spawner.try_spawn(&action_tx).await.unwrap();
         let res = action_rx.try_recv().unwrap();
         assert_eq!(res.id, spawner_id);
-        let SpawnAction::Create(create_params) = &res.action;
+        let SpawnAction::Create(create_params) = &res.action else {
+            panic!("expected a source to be created");
+        };
         assert_eq!(create_params.get_addr(), "127.0.0.1:123");
         let params = get_ntp_create_params(res).unwrap();
         assert_eq!(params.addr.to_string(), "127.0.0.1:123");
@@ -194,6 +399,13 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::V5,
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -223,6 +435,13 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::V4,
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -252,6 +471,13 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -293,6 +519,13 @@ mod tests {
                 )
                 .into(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -306,9 +539,10 @@ mod tests {
         assert!(spawner.is_complete());
 
         // We repeat multiple times and check at least one is different to be less
-        // sensitive to dns resolver giving the same pool ip.
+        // sensitive to dns resolver giving the same pool ip. The fifth time the
+        // recovery fails and the spawner backs off.
         let mut seen_addresses = vec![];
-        for _ in 0..5 {
+        for _ in 0..4 {
             spawner
                 .handle_source_removed(SourceRemovedEvent {
                     id: params.id,
@@ -346,6 +580,13 @@ mod tests {
                 address: NormalizedAddress::with_hardcoded_dns("does.not.resolve", 123, vec![])
                     .into(),
                 ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
             },
             SourceConfig::default(),
         );
@@ -356,4 +597,298 @@ mod tests {
         let res = action_rx.try_recv().unwrap_err();
         assert_eq!(res, TryRecvError::Empty);
     }
+
+    #[tokio::test]
+    async fn backs_off_resolution() {
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns("does.not.resolve", 123, vec![])
+                    .into(),
+                ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, _action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        assert_eq!(spawner.failed_attempts(), 0);
+        spawner.try_spawn(&action_tx).await.unwrap();
+        assert_eq!(spawner.failed_attempts(), 1);
+        spawner.try_spawn(&action_tx).await.unwrap();
+        assert_eq!(spawner.failed_attempts(), 2);
+        // a failure to resolve is not a failure of the source
+        assert_eq!(spawner.take_failure(), None);
+    }
+
+    async fn spawn_after_unreachable(
+        spawner: &mut StandardSpawner,
+        action_tx: &mpsc::Sender<SpawnEvent>,
+        action_rx: &mut mpsc::Receiver<SpawnEvent>,
+        id: SourceId,
+    ) -> NtpSourceCreateParameters {
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id,
+                reason: SourceRemovalReason::Unreachable,
+            })
+            .await
+            .unwrap();
+        assert!(!spawner.is_complete());
+        spawner.try_spawn(action_tx).await.unwrap();
+        get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn escalates_recovery_on_repeated_unreachable() {
+        let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
+        let addresses: [std::net::SocketAddr; 3] =
+            address_strings.map(|addr| addr.parse().unwrap());
+        let [a, b, c] = addresses;
+
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns(
+                    "example.com",
+                    123,
+                    addresses.to_vec(),
+                )
+                .into(),
+                ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let mut params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        let mut seen = vec![params.addr];
+
+        // initial, reconnect, re-resolve, two alternates
+        for _ in 0..4 {
+            params =
+                spawn_after_unreachable(&mut spawner, &action_tx, &mut action_rx, params.id).await;
+            seen.push(params.addr);
+        }
+        assert_eq!(seen, vec![c, c, b, c, a]);
+
+        // the ladder is exhausted: report it and back off
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::Unreachable,
+            })
+            .await
+            .unwrap();
+        let failure = spawner.take_failure().unwrap();
+        assert_eq!(failure.address, a.to_string());
+        assert_eq!(failure.failures, 1);
+        assert_eq!(failure.backoff_seconds, 4);
+        assert_eq!(spawner.take_failure(), None);
+        assert_eq!(spawner.failed_attempts(), 1);
+        assert!(!spawner.is_complete());
+
+        // after the backoff, start over from a fresh resolution
+        spawner.try_spawn(&action_tx).await.unwrap();
+        assert_eq!(spawner.failed_attempts(), 0);
+        params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        seen.push(params.addr);
+        for _ in 0..2 {
+            params =
+                spawn_after_unreachable(&mut spawner, &action_tx, &mut action_rx, params.id).await;
+            seen.push(params.addr);
+        }
+
+        // then the ladder starts over with reconnect and re-resolve
+        assert_eq!(seen, vec![c, c, b, c, a, a, a, c]);
+        assert_eq!(spawner.failures, 1);
+    }
+
+    #[tokio::test]
+    async fn backs_off_longer_on_repeated_failure() {
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns(
+                    "example.com",
+                    123,
+                    vec!["127.0.0.1:123".parse().unwrap()],
+                )
+                .into(),
+                ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        let mut backoffs = vec![];
+        for _ in 0..3 {
+            spawner.try_spawn(&action_tx).await.unwrap();
+            let mut params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+            // reconnect and re-resolve, a single address leaves nothing to alternate
+            for _ in 0..2 {
+                params =
+                    spawn_after_unreachable(&mut spawner, &action_tx, &mut action_rx, params.id)
+                        .await;
+            }
+            spawner
+                .handle_source_removed(SourceRemovedEvent {
+                    id: params.id,
+                    reason: SourceRemovalReason::Unreachable,
+                })
+                .await
+                .unwrap();
+            let failure = spawner.take_failure().unwrap();
+            assert_eq!(spawner.failed_attempts(), failure.failures);
+            backoffs.push(failure.backoff_seconds);
+        }
+        assert_eq!(backoffs, vec![4, 8, 16]);
+
+        spawner
+            .handle_source_reachable(SourceId::new())
+            .await
+            .unwrap();
+        assert_eq!(spawner.failures, 0);
+    }
+
+    #[tokio::test]
+    async fn reachable_source_resets_recovery() {
+        let address_strings = ["127.0.0.1:123", "127.0.0.2:123", "127.0.0.3:123"];
+        let addresses: [std::net::SocketAddr; 3] =
+            address_strings.map(|addr| addr.parse().unwrap());
+        let [_, b, c] = addresses;
+
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns(
+                    "example.com",
+                    123,
+                    addresses.to_vec(),
+                )
+                .into(),
+                ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: None,
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert_eq!(params.addr, c);
+
+        let params =
+            spawn_after_unreachable(&mut spawner, &action_tx, &mut action_rx, params.id).await;
+        assert_eq!(params.addr, c);
+        let params =
+            spawn_after_unreachable(&mut spawner, &action_tx, &mut action_rx, params.id).await;
+        assert_eq!(params.addr, b);
+
+        spawner.handle_source_reachable(params.id).await.unwrap();
+
+        // back at the first step: keep the address we had
+        let params =
+            spawn_after_unreachable(&mut spawner, &action_tx, &mut action_rx, params.id).await;
+        assert_eq!(params.addr, b);
+    }
+
+    #[test]
+    fn alternates_address_families() {
+        let addresses: Vec<std::net::SocketAddr> = [
+            "[2001:db8::1]:123",
+            "[2001:db8::2]:123",
+            "192.0.2.1:123",
+            "[2001:db8::3]:123",
+            "192.0.2.2:123",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        let [v6a, v6b, v4a, v6c, v4b] = addresses.clone().try_into().unwrap();
+
+        assert_eq!(
+            order_by_family(addresses.clone(), None, None),
+            vec![v6a, v4a, v6b, v4b, v6c]
+        );
+        assert_eq!(
+            order_by_family(addresses.clone(), Some(AddressFamily::V4), None),
+            vec![v4a, v6a, v4b, v6b, v6c]
+        );
+        assert_eq!(
+            order_by_family(
+                addresses.clone(),
+                Some(AddressFamily::V4),
+                Some(AddressFamily::V6)
+            ),
+            vec![v6a, v6b, v6c]
+        );
+        assert_eq!(
+            order_by_family(addresses, None, Some(AddressFamily::V4)),
+            vec![v4a, v4b]
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_on_network_issue() {
+        let addresses: Vec<std::net::SocketAddr> = vec![
+            "192.0.2.1:123".parse().unwrap(),
+            "[2001:db8::1]:123".parse().unwrap(),
+        ];
+
+        let mut spawner = StandardSpawner::new(
+            StandardSource {
+                address: NormalizedAddress::with_hardcoded_dns("example.com", 123, addresses)
+                    .into(),
+                ntp_version: ProtocolVersion::v4_upgrading_to_v5_with_default_tries(),
+                key: None,
+                interface: None,
+                source_address: None,
+                prefer_ip: Some(AddressFamily::V6),
+                force_ip: None,
+                transport: Default::default(),
+                nat_keepalive: None,
+            },
+            SourceConfig::default(),
+        );
+        let (action_tx, mut action_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert!(params.addr.is_ipv6());
+
+        spawner
+            .handle_source_removed(SourceRemovedEvent {
+                id: params.id,
+                reason: SourceRemovalReason::NetworkIssue,
+            })
+            .await
+            .unwrap();
+        spawner.try_spawn(&action_tx).await.unwrap();
+        let params = get_ntp_create_params(action_rx.try_recv().unwrap()).unwrap();
+        assert!(params.addr.is_ipv4());
+    }
 }
@@ -0,0 +1,164 @@
+use std::{collections::BTreeMap, net::SocketAddr};
+
+use ntp_proto::{ObservableSourceState, PollInterval, SelectionStatus};
+use serde::{Deserialize, Serialize};
+
+use super::{server::ServerStats, spawn::SourceId, state_format::STATE_EXPORT};
+
+/// Runtime state of the daemon, exported through the control socket for
+/// debugging, and to give another instance (such as a standby host) a head start
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DaemonState {
+    /// Version of the format of the state, exports from before it was
+    /// versioned have none
+    #[serde(default)]
+    pub format: u32,
+    /// Version of ntpd-rs that exported the state
+    pub version: String,
+    /// Frequency offset of the clock, once the daemon has determined it
+    pub frequency_ppm: Option<f64>,
+    pub stratum: u8,
+    #[serde(default)]
+    pub sources: Vec<SourceState>,
+    #[serde(default)]
+    pub servers: Vec<ServerState>,
+    #[serde(default)]
+    pub disabled_sources: Vec<String>,
+    /// Minimum poll intervals that servers requested through rate limiting
+    #[serde(default)]
+    pub rate_limits: BTreeMap<SocketAddr, PollInterval>,
+}
+
+impl DaemonState {
+    /// Read a state exported in any format this version supports, migrated to
+    /// the current format
+    pub fn from_json(contents: &str) -> std::io::Result<DaemonState> {
+        #[derive(Deserialize)]
+        struct Format {
+            #[serde(default)]
+            format: u64,
+        }
+
+        let invalid = |e| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let Format { format } = serde_json::from_str(contents).map_err(invalid)?;
+        let format = u32::try_from(format).unwrap_or(u32::MAX);
+        let contents = STATE_EXPORT.migrate(format, contents.to_owned())?;
+        serde_json::from_str(&contents).map_err(invalid)
+    }
+}
+
+/// A source as seen by the daemon, its measurement filter is described by the
+/// offset, uncertainty and delay it currently estimates
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SourceState {
+    pub name: String,
+    pub address: String,
+    pub stratum: u8,
+    pub reach: u8,
+    pub poll_interval: PollInterval,
+    pub offset: f64,
+    pub uncertainty: f64,
+    pub delay: f64,
+    pub remote_delay: f64,
+    pub remote_uncertainty: f64,
+    pub nts_cookies: Option<usize>,
+    pub selection: SelectionStatus,
+}
+
+impl From<&ObservableSourceState<SourceId>> for SourceState {
+    fn from(source: &ObservableSourceState<SourceId>) -> Self {
+        SourceState {
+            name: source.name.clone(),
+            address: source.address.clone(),
+            stratum: source.stratum,
+            reach: source.reach.register(),
+            poll_interval: source.poll_interval,
+            offset: source.timedata.offset.to_seconds(),
+            uncertainty: source.timedata.uncertainty.to_seconds(),
+            delay: source.timedata.delay.to_seconds(),
+            remote_delay: source.timedata.remote_delay.to_seconds(),
+            remote_uncertainty: source.timedata.remote_uncertainty.to_seconds(),
+            nts_cookies: source.nts_cookies,
+            selection: source.selection,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ServerState {
+    pub listen: SocketAddr,
+    pub stats: ServerStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_roundtrip() {
+        let state = DaemonState {
+            format: STATE_EXPORT.current(),
+            version: "1.2.3".into(),
+            frequency_ppm: Some(-12.5),
+            stratum: 3,
+            sources: vec![SourceState {
+                name: "ntp.example.com:123".into(),
+                address: "192.0.2.1:123".into(),
+                stratum: 2,
+                reach: 0o377,
+                poll_interval: PollInterval::from_byte(6),
+                offset: 1e-4,
+                uncertainty: 2e-5,
+                delay: 3e-3,
+                remote_delay: 1e-3,
+                remote_uncertainty: 4e-4,
+                nts_cookies: Some(8),
+                selection: SelectionStatus::Primary,
+            }],
+            servers: vec![],
+            disabled_sources: vec!["ntp.example.com:123".into()],
+            rate_limits: BTreeMap::from([(
+                "192.0.2.1:123".parse().unwrap(),
+                PollInterval::from_byte(8),
+            )]),
+        };
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json.contains("\"frequency-ppm\":-12.5"));
+        assert!(json.contains("\"rate-limits\":{\"192.0.2.1:123\":8}"));
+        assert_eq!(serde_json::from_str::<DaemonState>(&json).unwrap(), state);
+
+        // an older export without the optional lists can still be imported
+        let state: DaemonState =
+            serde_json::from_str(r#"{"version":"1.2.3","frequency-ppm":null,"stratum":16}"#)
+                .unwrap();
+        assert!(state.sources.is_empty());
+        assert!(state.rate_limits.is_empty());
+    }
+
+    #[test]
+    fn test_state_format() {
+        let state = DaemonState::from_json(
+            r#"{"format":1,"version":"1.2.3","frequency-ppm":-12.5,"stratum":3}"#,
+        )
+        .unwrap();
+        assert_eq!(state.frequency_ppm, Some(-12.5));
+
+        // exports from before the format was versioned are migrated
+        let state =
+            DaemonState::from_json(r#"{"version":"1.2.3","frequency-ppm":null,"stratum":16}"#)
+                .unwrap();
+        assert_eq!(state.stratum, 16);
+
+        let err = DaemonState::from_json(
+            r#"{"format":99,"version":"9.0.0","frequency-ppm":null,"stratum":16}"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+        assert!(DaemonState::from_json("not json").is_err());
+    }
+}
@@ -0,0 +1,281 @@
+//! Versioned formats of the state the daemon keeps on disk.
+//!
+//! State files start with a header naming their format and its version, such
+//! as `# ntpd-rs drift-file format 1`. Files written before the formats were
+//! versioned have no header and are read as version 0. A file in an older
+//! version is migrated to the current version when it is read, one version at
+//! a time, and is written in the current version when it is next stored. A
+//! file in a newer version is neither read nor replaced, so running an older
+//! daemon for a while does not throw away the state of a newer one.
+
+use std::{
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{config::Config, drift, kod_history};
+
+const HEADER_PREFIX: &str = "# ntpd-rs ";
+
+/// Converts the contents of a state file from one version of its format to
+/// the next
+type Migration = fn(String) -> std::io::Result<String>;
+
+/// A format of state on disk, and the migrations to its current version
+#[derive(Debug)]
+pub struct StateFormat {
+    pub name: &'static str,
+    /// Migration `i` converts version `i` to version `i + 1`, so the number of
+    /// migrations is the current version
+    migrations: &'static [Migration],
+}
+
+/// The frequency offset of the clock, see `drift-file`
+pub const DRIFT_FILE: StateFormat = StateFormat {
+    name: "drift-file",
+    migrations: &[unchanged],
+};
+
+/// The minimum poll intervals requested by servers, see `kod-history-file`
+pub const KOD_HISTORY_FILE: StateFormat = StateFormat {
+    name: "kod-history-file",
+    migrations: &[unchanged],
+};
+
+/// The runtime state written by `ntp-ctl state export`, which keeps its
+/// version in the `format` field instead of a header
+pub const STATE_EXPORT: StateFormat = StateFormat {
+    name: "state-export",
+    migrations: &[unchanged],
+};
+
+/// Version 1 only added the version, the contents did not change
+fn unchanged(contents: String) -> std::io::Result<String> {
+    Ok(contents)
+}
+
+impl StateFormat {
+    pub const fn current(&self) -> u32 {
+        self.migrations.len() as u32
+    }
+
+    /// `version` when this version of the daemon can read it
+    pub fn check(&self, version: u32) -> std::io::Result<u32> {
+        if version > self.current() {
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "{} format {version} was written by a newer version of ntpd-rs, this version reads up to format {}",
+                    self.name,
+                    self.current()
+                ),
+            ));
+        }
+        Ok(version)
+    }
+
+    /// Convert `contents` in `version` to the current version
+    pub fn migrate(&self, version: u32, contents: String) -> std::io::Result<String> {
+        let version = self.check(version)?;
+        self.migrations[version as usize..]
+            .iter()
+            .try_fold(contents, |contents, migration| migration(contents))
+    }
+
+    /// The version of a state file and the contents after its header
+    fn split_header<'a>(&self, contents: &'a str) -> std::io::Result<(u32, &'a str)> {
+        let Some(rest) = contents.strip_prefix(HEADER_PREFIX) else {
+            return Ok((0, contents));
+        };
+        let (header, body) = rest.split_once('\n').unwrap_or((rest, ""));
+
+        let version = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [name, "format", version] if name == self.name => version.parse().ok(),
+            _ => None,
+        };
+        let version = version.ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("not a {} header: {header:?}", self.name),
+            )
+        })?;
+        Ok((version, body))
+    }
+
+    /// The version of the state file with `contents`, when this version of the
+    /// daemon can read it
+    pub fn version_of(&self, contents: &str) -> std::io::Result<u32> {
+        self.check(self.split_header(contents)?.0)
+    }
+
+    /// The contents of a state file after its header, in the current version
+    pub fn read(&self, path: &Path) -> std::io::Result<String> {
+        let contents = std::fs::read_to_string(path)?;
+        let (version, body) = self.split_header(&contents)?;
+        self.migrate(version, body.to_owned())
+    }
+
+    /// Write `body` with a header for the current version, replacing the file
+    /// atomically so a crash halfway through never leaves a truncated file
+    /// behind. A file in a newer version is left alone, one that cannot be
+    /// read at all is replaced.
+    pub fn write(&self, path: &Path, body: &str) -> std::io::Result<()> {
+        if let Ok(contents) = std::fs::read_to_string(path)
+            && let Err(e) = self.version_of(&contents)
+            && e.kind() == ErrorKind::Unsupported
+        {
+            return Err(e);
+        }
+
+        let mut tmp = PathBuf::from(path);
+        tmp.as_mut_os_string().push(".tmp");
+
+        let mut file = std::fs::File::create(&tmp)?;
+        write!(
+            file,
+            "{HEADER_PREFIX}{} format {}\n{body}",
+            self.name,
+            self.current()
+        )?;
+        file.sync_all()?;
+
+        std::fs::rename(tmp, path)
+    }
+}
+
+/// A state file checked by `ntp-ctl state verify`
+#[derive(Debug)]
+pub struct StateFileCheck {
+    pub format: &'static StateFormat,
+    pub path: PathBuf,
+    /// The version the file is in, when it can be read
+    pub result: std::io::Result<u32>,
+}
+
+/// Check whether the state files of `config` can be read by this version of
+/// the daemon
+pub fn verify(config: &Config) -> Vec<StateFileCheck> {
+    fn check(
+        format: &'static StateFormat,
+        path: &Path,
+        load: impl FnOnce(&Path) -> std::io::Result<()>,
+    ) -> StateFileCheck {
+        let result = std::fs::read_to_string(path)
+            .and_then(|contents| format.version_of(&contents))
+            .and_then(|version| load(path).map(|()| version));
+        StateFileCheck {
+            format,
+            path: path.to_owned(),
+            result,
+        }
+    }
+
+    let mut checks = vec![];
+    if let Some(path) = &config.synchronization.drift_file {
+        checks.push(check(&DRIFT_FILE, path, |path| {
+            drift::load(path).map(|_| ())
+        }));
+    }
+    if let Some(path) = &config.synchronization.kod_history_file {
+        checks.push(check(&KOD_HISTORY_FILE, path, |path| {
+            kod_history::load(path).map(|_| ())
+        }));
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    const TEST_FORMAT: StateFormat = StateFormat {
+        name: "test",
+        migrations: &[unchanged, rename],
+    };
+
+    fn rename(contents: String) -> std::io::Result<String> {
+        Ok(contents.replace("old", "new"))
+    }
+
+    #[test]
+    fn test_migrate() {
+        assert_eq!(TEST_FORMAT.current(), 2);
+        assert_eq!(TEST_FORMAT.migrate(0, "old\n".into()).unwrap(), "new\n");
+        assert_eq!(TEST_FORMAT.migrate(2, "old\n".into()).unwrap(), "old\n");
+
+        let err = TEST_FORMAT.migrate(3, "old\n".into()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_header() {
+        assert_eq!(TEST_FORMAT.split_header("old\n").unwrap(), (0, "old\n"));
+        assert_eq!(
+            TEST_FORMAT
+                .split_header("# ntpd-rs test format 2\nnew\n")
+                .unwrap(),
+            (2, "new\n")
+        );
+        assert_eq!(
+            TEST_FORMAT
+                .version_of("# ntpd-rs test format 7\n")
+                .unwrap_err()
+                .kind(),
+            ErrorKind::Unsupported
+        );
+        assert!(
+            TEST_FORMAT
+                .split_header("# ntpd-rs other format 1\n")
+                .is_err()
+        );
+        assert!(TEST_FORMAT.split_header("# ntpd-rs test format\n").is_err());
+    }
+
+    #[test]
+    fn test_write_keeps_newer_file() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join(format!("ntp-test-state-format-{}", alloc_port()));
+
+        std::fs::write(&path, "old\n").unwrap();
+        assert_eq!(TEST_FORMAT.read(&path).unwrap(), "new\n");
+
+        TEST_FORMAT.write(&path, "new\n").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# ntpd-rs test format 2\nnew\n"
+        );
+
+        std::fs::write(&path, "# ntpd-rs test format 3\nnewer\n").unwrap();
+        let err = TEST_FORMAT.write(&path, "new\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+        assert_eq!(
+            TEST_FORMAT.read(&path).unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "# ntpd-rs test format 3\nnewer\n"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_replaces_corrupt_file() {
+        // be careful with copying: tests run concurrently and should use a unique file name!
+        let path = std::env::temp_dir().join(format!("ntp-test-state-format-{}", alloc_port()));
+
+        std::fs::write(&path, "# ntpd-rs test format two\nnew\n").unwrap();
+        assert_eq!(
+            TEST_FORMAT.read(&path).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+
+        TEST_FORMAT.write(&path, "new\n").unwrap();
+        assert_eq!(TEST_FORMAT.read(&path).unwrap(), "new\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,530 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{BufReader, Error, ErrorKind},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ntp_proto::{
+    NtpClock, NtpTimestamp, Transport,
+    tls_utils::{self, Certificate, ServerName, TLS13},
+};
+use timestamped_socket::interface::InterfaceName;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpSocket, TcpStream},
+    sync::{Semaphore, mpsc, oneshot},
+    time::timeout,
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::{Instrument, Span, debug, warn};
+
+use super::{anonymize::Anonymization, sockets::bind_tcp_listener, spawn::SocketBinding};
+
+/// Largest packet exchanged over a connection, the same as for UDP
+pub const MAX_FRAME_SIZE: usize = 1024;
+
+/// Time allowed for setting up a connection, including the TLS handshake
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Servers close connections on which nothing was received for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Connections a server keeps open at the same time, further ones are closed
+const MAX_CONNECTIONS: usize = 512;
+
+/// Connections a server keeps open from a single client at the same time. An
+/// IPv6 client counts as its /64 network, as it usually has all of those
+/// addresses.
+const MAX_CLIENT_CONNECTIONS: usize = 8;
+
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Packets received over a connection, each preceded by its length as a
+/// 16 bit big endian number
+#[derive(Debug, Default)]
+struct FrameBuffer {
+    data: Vec<u8>,
+    /// When each chunk of `data` was read, with the offset at which it starts.
+    /// The first chunk always starts at the start of `data`.
+    reads: VecDeque<(usize, NtpTimestamp)>,
+}
+
+impl FrameBuffer {
+    /// Take the first frame out of the buffer, once it was received completely
+    fn pop(&mut self) -> Result<Option<(Vec<u8>, NtpTimestamp)>, Error> {
+        let Some(&[high, low]) = self.data.get(..2) else {
+            return Ok(None);
+        };
+        let length = u16::from_be_bytes([high, low]) as usize;
+        if length > MAX_FRAME_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("frame of {length} bytes is too large"),
+            ));
+        }
+        let end = 2 + length;
+        if self.data.len() < end {
+            return Ok(None);
+        }
+
+        let started = self
+            .reads
+            .front()
+            .map(|&(_, read)| read)
+            .unwrap_or_default();
+        let packet = self.data.drain(..end).skip(2).collect();
+
+        // the next frame starts in the last chunk that started before its start
+        while self.reads.get(1).is_some_and(|&(start, _)| start <= end) {
+            self.reads.pop_front();
+        }
+        if self.data.is_empty() {
+            self.reads.clear();
+        }
+        for (start, _) in &mut self.reads {
+            *start = start.saturating_sub(end);
+        }
+
+        Ok(Some((packet, started)))
+    }
+
+    /// Read until a frame was received completely, and return its packet with
+    /// the time at which its first bytes were read. A client may send its next
+    /// frames before receiving an answer, and those get the time at which their
+    /// own first bytes were read. Frames are much smaller
+    /// than a segment, so when the rest of a frame arrives later, that is due
+    /// to buffering in the stream and not part of the network delay.
+    ///
+    /// This is cancel safe: everything read so far stays in the buffer.
+    async fn read_frame<C: NtpClock>(
+        &mut self,
+        stream: &mut (impl AsyncRead + Unpin),
+        clock: &C,
+    ) -> Result<(Vec<u8>, NtpTimestamp), Error> {
+        loop {
+            if let Some(frame) = self.pop()? {
+                return Ok(frame);
+            }
+
+            let mut chunk = [0; MAX_FRAME_SIZE + 2];
+            let read = stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            let now = clock.now().map_err(|e| Error::other(e.to_string()))?;
+            self.reads.push_back((self.data.len(), now));
+            self.data.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+async fn write_frame(stream: &mut (impl AsyncWrite + Unpin), packet: &[u8]) -> Result<(), Error> {
+    if packet.len() > MAX_FRAME_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("packet of {} bytes is too large", packet.len()),
+        ));
+    }
+
+    // a single write, so the frame is not split over multiple segments
+    let mut frame = Vec::with_capacity(2 + packet.len());
+    frame.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+    frame.extend_from_slice(packet);
+    stream.write_all(&frame).await?;
+    stream.flush().await
+}
+
+/// Connection over which a source is polled when UDP cannot be used
+///
+/// The connection is kept open between polls, and set up again on the next
+/// poll after it was lost. Measurements over it are less accurate than those
+/// over UDP, as a lost segment delays all data behind it until it has been
+/// retransmitted.
+pub struct StreamClient {
+    transport: Transport,
+    server_name: String,
+    tls: Option<TlsConnector>,
+    connection: Option<Box<dyn Connection>>,
+    frames: FrameBuffer,
+}
+
+impl StreamClient {
+    /// Returns `None` for sources polled over UDP
+    pub fn new(transport: Transport, server_name: String) -> Option<StreamClient> {
+        match transport {
+            Transport::Udp => None,
+            Transport::Tcp | Transport::Tls => Some(StreamClient {
+                transport,
+                server_name,
+                tls: None,
+                connection: None,
+                frames: FrameBuffer::default(),
+            }),
+        }
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.transport
+    }
+
+    async fn connect(
+        &mut self,
+        addr: SocketAddr,
+        binding: SocketBinding,
+    ) -> Result<Box<dyn Connection>, Error> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        match binding {
+            #[cfg(target_os = "linux")]
+            SocketBinding {
+                interface: Some(interface),
+                ..
+            } => socket.bind_device(Some(interface.to_string().as_bytes()))?,
+            SocketBinding {
+                source_address: Some(source_address),
+                ..
+            } => socket.bind(SocketAddr::new(source_address, 0))?,
+            _ => {}
+        }
+
+        let stream = timeout(CONNECT_TIMEOUT, socket.connect(addr)).await??;
+        // waiting for more data to fill a segment only delays the packets
+        stream.set_nodelay(true)?;
+
+        match self.transport {
+            Transport::Tls => {
+                let connector = match self.tls.clone() {
+                    Some(connector) => connector,
+                    None => {
+                        let connector = tls_connector()?;
+                        self.tls = Some(connector.clone());
+                        connector
+                    }
+                };
+                let server_name = ServerName::try_from(self.server_name.clone())
+                    .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+                let stream =
+                    timeout(CONNECT_TIMEOUT, connector.connect(server_name, stream)).await??;
+                Ok(Box::new(stream))
+            }
+            Transport::Tcp | Transport::Udp => Ok(Box::new(stream)),
+        }
+    }
+
+    /// Send a packet, first connecting when there is no open connection.
+    ///
+    /// Returns the time at which the packet was handed to the kernel, so that
+    /// framing and encrypting it is not counted as network delay.
+    pub async fn send<C: NtpClock>(
+        &mut self,
+        addr: SocketAddr,
+        binding: SocketBinding,
+        packet: &[u8],
+        clock: &C,
+    ) -> Result<NtpTimestamp, Error> {
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => {
+                self.frames = FrameBuffer::default();
+                self.connect(addr, binding).await?
+            }
+        };
+
+        write_frame(&mut connection, packet).await?;
+        self.connection = Some(connection);
+        clock.now().map_err(|e| Error::other(e.to_string()))
+    }
+
+    /// Wait for the next packet and the time at which it started to arrive.
+    /// Without an open connection, this never completes.
+    ///
+    /// This is cancel safe.
+    pub async fn recv<C: NtpClock>(&mut self, clock: &C) -> Result<(Vec<u8>, NtpTimestamp), Error> {
+        let Some(connection) = &mut self.connection else {
+            return std::future::pending().await;
+        };
+
+        let result = self.frames.read_frame(connection, clock).await;
+        if result.is_err() {
+            // the next poll connects again
+            self.connection = None;
+        }
+        result
+    }
+}
+
+fn tls_connector() -> Result<TlsConnector, Error> {
+    let builder = tls_utils::client_config_builder_with_protocol_versions(&[&TLS13]);
+    let verifier = tls_utils::PlatformVerifier::new_with_extra_roots(std::iter::empty())
+        .map_err(Error::other)?
+        .with_provider(builder.crypto_provider().clone());
+    let config = builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Set up TLS for the connections to a server from PEM files
+pub fn tls_acceptor(
+    certificate_chain_path: &Path,
+    private_key_path: &Path,
+) -> Result<TlsAcceptor, Error> {
+    let mut certificate_chain_file = BufReader::new(File::open(certificate_chain_path)?);
+    let certificate_chain = tls_utils::pemfile::certs(&mut certificate_chain_file)
+        .collect::<Result<Vec<Certificate>, Error>>()?;
+    let private_key =
+        tls_utils::pemfile::private_key(&mut BufReader::new(File::open(private_key_path)?))?;
+
+    let config = tls_utils::server_config_builder_with_protocol_versions(&[&TLS13])
+        .with_no_client_auth()
+        .with_single_cert(certificate_chain, private_key)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Packet received by a server over a connection
+#[derive(Debug)]
+pub struct StreamRequest {
+    pub remote: SocketAddr,
+    pub packet: Vec<u8>,
+    pub recv_timestamp: NtpTimestamp,
+    /// Dropped without an answer when the request is ignored
+    pub response: oneshot::Sender<Vec<u8>>,
+}
+
+/// Open connections, by the client they are from
+#[derive(Debug, Default, Clone)]
+struct ClientConnections(Arc<Mutex<HashMap<IpAddr, usize>>>);
+
+impl ClientConnections {
+    /// Count a connection from `remote`, unless it has too many open already.
+    /// The connection counts until the returned guard is dropped.
+    fn try_open(&self, remote: IpAddr) -> Option<ClientConnection> {
+        let client = match remote.to_canonical() {
+            IpAddr::V4(ip) => IpAddr::V4(ip),
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from_bits(ip.to_bits() & !u128::from(u64::MAX))),
+        };
+
+        let mut connections = self.0.lock().expect("Unexpected poisoned mutex");
+        let count = connections.entry(client).or_default();
+        if *count >= MAX_CLIENT_CONNECTIONS {
+            return None;
+        }
+        *count += 1;
+
+        Some(ClientConnection {
+            connections: self.clone(),
+            client,
+        })
+    }
+}
+
+struct ClientConnection {
+    connections: ClientConnections,
+    client: IpAddr,
+}
+
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        let mut connections = self
+            .connections
+            .0
+            .lock()
+            .expect("Unexpected poisoned mutex");
+        if let Some(count) = connections.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Accept connections on `listen`, and pass the packets received over them on
+/// to the server task through `requests`. This stops once the server task is
+/// gone.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve<C: NtpClock + Sync>(
+    listen: SocketAddr,
+    interface: Option<InterfaceName>,
+    listener: Option<TcpListener>,
+    tls: Option<TlsAcceptor>,
+    clock: C,
+    requests: mpsc::Sender<StreamRequest>,
+    network_wait_period: Duration,
+    log_anonymization: Anonymization,
+) {
+    let listener = match listener {
+        Some(listener) => listener,
+        None => loop {
+            match bind_tcp_listener(listen, interface) {
+                Ok(listener) => break listener,
+                Err(error) => {
+                    warn!(?error, ?listen, "Could not open server connection socket");
+                    tokio::time::sleep(network_wait_period).await;
+                }
+            }
+        },
+    };
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    let client_connections = ClientConnections::default();
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = requests.closed() => return,
+        };
+        let (stream, remote) = match accepted {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                warn!(?error, "Could not accept connection");
+                continue;
+            }
+        };
+        let Ok(permit) = connections.clone().try_acquire_owned() else {
+            debug!(
+                remote = %log_anonymization.log(remote),
+                "too many open connections, closing new connection"
+            );
+            continue;
+        };
+        let Some(client_permit) = client_connections.try_open(remote.ip()) else {
+            debug!(
+                remote = %log_anonymization.log(remote),
+                "too many open connections from client, closing new connection"
+            );
+            continue;
+        };
+
+        let (tls, clock, requests) = (tls.clone(), clock.clone(), requests.clone());
+        tokio::spawn(
+            async move {
+                if let Err(error) = handle_connection(stream, remote, tls, clock, requests).await {
+                    debug!(?error, remote = %log_anonymization.log(remote), "connection closed");
+                }
+                drop((permit, client_permit));
+            }
+            .instrument(Span::current()),
+        );
+    }
+}
+
+async fn handle_connection<C: NtpClock + Sync>(
+    stream: TcpStream,
+    remote: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    clock: C,
+    requests: mpsc::Sender<StreamRequest>,
+) -> Result<(), Error> {
+    stream.set_nodelay(true)?;
+    let mut connection: Box<dyn Connection> = match tls {
+        Some(tls) => Box::new(timeout(CONNECT_TIMEOUT, tls.accept(stream)).await??),
+        None => Box::new(stream),
+    };
+
+    let mut frames = FrameBuffer::default();
+    loop {
+        let (packet, recv_timestamp) =
+            timeout(IDLE_TIMEOUT, frames.read_frame(&mut connection, &clock)).await??;
+
+        let (response, answer) = oneshot::channel();
+        let request = StreamRequest {
+            remote,
+            packet,
+            recv_timestamp,
+            response,
+        };
+        if requests.send(request).await.is_err() {
+            return Ok(());
+        }
+
+        // an ignored request leaves the connection open for later ones
+        if let Ok(message) = answer.await {
+            write_frame(&mut connection, &message).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frames() {
+        let mut written = vec![];
+        write_frame(&mut written, &[1; 48]).await.unwrap();
+        write_frame(&mut written, &[2; 68]).await.unwrap();
+        assert!(
+            write_frame(&mut written, &[0; MAX_FRAME_SIZE + 1])
+                .await
+                .is_err()
+        );
+        assert_eq!(written.len(), 2 + 48 + 2 + 68);
+
+        let first = NtpTimestamp::from_seconds_nanos_since_ntp_era(100, 0);
+        let second = NtpTimestamp::from_seconds_nanos_since_ntp_era(101, 0);
+        let mut frames = FrameBuffer {
+            data: written[..30].to_vec(),
+            reads: VecDeque::from([(0, first)]),
+        };
+        assert_eq!(frames.pop().unwrap(), None);
+        frames.reads.push_back((30, second));
+        frames.data.extend_from_slice(&written[30..]);
+        assert_eq!(frames.pop().unwrap(), Some((vec![1; 48], first)));
+        assert_eq!(frames.pop().unwrap(), Some((vec![2; 68], second)));
+        assert_eq!(frames.pop().unwrap(), None);
+        assert!(frames.reads.is_empty());
+
+        // a frame that starts in the middle of a chunk
+        let mut frames = FrameBuffer {
+            data: written[..60].to_vec(),
+            reads: VecDeque::from([(0, first), (40, second)]),
+        };
+        assert_eq!(frames.pop().unwrap(), Some((vec![1; 48], first)));
+        assert_eq!(frames.reads, [(0, second)]);
+        frames.data.extend_from_slice(&written[60..]);
+        assert_eq!(frames.pop().unwrap(), Some((vec![2; 68], second)));
+
+        let mut frames = FrameBuffer {
+            data: vec![0xff, 0xff, 0],
+            reads: VecDeque::from([(0, first)]),
+        };
+        assert!(frames.pop().is_err());
+    }
+
+    #[test]
+    fn test_client_connections() {
+        let connections = ClientConnections::default();
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+        let neighbour: IpAddr = "2001:db8::2".parse().unwrap();
+
+        let mut open: Vec<_> = (0..MAX_CLIENT_CONNECTIONS)
+            .map(|_| connections.try_open(client).unwrap())
+            .collect();
+        assert!(connections.try_open(client).is_none());
+        assert!(connections.try_open(neighbour).is_none());
+        assert!(
+            connections
+                .try_open("2001:db8:0:1::1".parse().unwrap())
+                .is_some()
+        );
+        assert!(connections.try_open("192.0.2.1".parse().unwrap()).is_some());
+
+        open.pop();
+        assert!(connections.try_open(neighbour).is_some());
+
+        open.clear();
+        assert!(connections.0.lock().unwrap().is_empty());
+    }
+}
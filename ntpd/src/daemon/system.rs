@@ -1,41 +1,70 @@
 #[cfg(feature = "pps")]
 use crate::daemon::pps_source::PpsSourceTask;
 use crate::daemon::{
+    file_source::FileSourceTask,
     sock_source::SockSourceTask,
     spawn::{SourceCreateParameters, spawner_task},
 };
 
 use super::spawn::nts_pool::NtsPoolSpawner;
 use super::{
+    blackout::{self, Restriction},
+    census::Census,
     clock::NtpClockWrapper,
-    config::{ClockConfig, NtpSourceConfig, ServerConfig, TimestampMode},
+    config::{
+        AddressFamily, BlackoutConfig, ClockConfig, Config, CryptoPolicyConfig,
+        DaemonSynchronizationConfig, NtpSourceConfig, SchedulingConfig, ServerConfig,
+        TimestampMode,
+    },
+    control::{
+        ControlMessage, ControlRequest, ControlResponse, MAX_MAINTENANCE_SECONDS,
+        check_step_settings,
+    },
+    diversity::{Diversity, DiversityMonitor, SourceDomain},
+    drift::{self, DRIFT_FILE_INTERVAL},
+    kod_history,
+    leap_seconds::{LEAP_SECONDS_FILE_INTERVAL, LeapSecondsList},
+    nat64::Nat64Prefix,
     ntp_source::{MsgForSystem, SourceChannels, SourceTask, Wait},
+    observer::Observations,
+    recorder::MeasurementRecorder,
+    sandbox, scheduling,
     server::{ServerStats, ServerTask},
+    shared_socket::SharedSockets,
     spawn::{
-        SourceId, SourceRemovalReason, SpawnAction, SpawnEvent, Spawner, SpawnerId, SystemEvent,
-        nts::NtsSpawner, pool::PoolSpawner, sock::SockSpawner, standard::StandardSpawner,
+        SocketBinding, SourceId, SourceRemovalReason, SpawnAction, SpawnEvent, Spawner, SpawnerId,
+        SystemEvent, file::FileSpawner, nts::NtsSpawner, pool::PoolSpawner,
+        service::ServiceSpawner, sock::SockSpawner, standard::StandardSpawner,
     },
+    state::{self, DaemonState, ServerState},
+    state_format::STATE_EXPORT,
+    stream::StreamClient,
 };
 
 #[cfg(feature = "pps")]
 use super::spawn::pps::PpsSpawner;
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     future::Future,
     marker::PhantomData,
-    net::IpAddr,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
     pin::Pin,
     sync::{Arc, RwLock},
 };
 
 use ntp_proto::{
-    KeySet, NtpClock, ObservableSourceState, SourceConfig, SynchronizationConfig, System,
-    SystemActionIterator, SystemSnapshot, SystemSourceUpdate, TimeSyncController,
+    KeySet, Measurement, NtpClock, NtpDuration, NtpLeapIndicator, PollInterval, SelectionStatus,
+    SourceConfig, StepSettings, SymmetricKeySet, System, SystemActionIterator, SystemSnapshot,
+    SystemSourceUpdate, TimeSyncController, Transport,
 };
 use timestamped_socket::interface::InterfaceName;
-use tokio::{sync::mpsc, task::JoinHandle};
-use tracing::{debug, info};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
 
 pub const NETWORK_WAIT_PERIOD: std::time::Duration = std::time::Duration::from_secs(1);
 
@@ -85,98 +114,228 @@ impl<T: Wait> Wait for SingleshotSleep<T> {
 }
 
 pub struct DaemonChannels {
-    pub source_snapshots:
-        Arc<std::sync::RwLock<HashMap<SourceId, ObservableSourceState<SourceId>>>>,
+    pub observations: Arc<std::sync::RwLock<Observations>>,
     pub server_data_receiver: tokio::sync::watch::Receiver<Vec<ServerData>>,
-    pub system_snapshot_receiver: tokio::sync::watch::Receiver<SystemSnapshot>,
+    pub disabled_sources_receiver: tokio::sync::watch::Receiver<Vec<String>>,
+    pub control_sender: mpsc::Sender<ControlMessage>,
+}
+
+/// How the configuration is read again when the daemon is asked to reload it
+pub struct ConfigReload<A> {
+    pub path: Option<PathBuf>,
+    /// Takes the settings for the algorithm from the configuration
+    pub algorithm: fn(&Config) -> A,
+}
+
+/// What the daemon synchronizes with and serves, next to the settings of the
+/// `[synchronization]` section
+pub struct SpawnOptions<'a> {
+    pub source_defaults: SourceConfig,
+    pub clock: ClockConfig,
+    pub sources: &'a [NtpSourceConfig],
+    pub servers: &'a [ServerConfig],
+    pub keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    pub symmetric_keys: Arc<SymmetricKeySet>,
+    pub crypto_policy: CryptoPolicyConfig,
+    /// The latest list of sources fetched for the fleet
+    pub fleet_sources: tokio::sync::watch::Receiver<Vec<NtpSourceConfig>>,
+    /// Scheduling of the thread that steers the clock
+    pub scheduling: &'a SchedulingConfig,
 }
 
 /// Spawn the NTP daemon
 pub async fn spawn<Controller: TimeSyncController<Clock = NtpClockWrapper, SourceId = SourceId>>(
-    synchronization_config: SynchronizationConfig,
+    config_reload: Option<ConfigReload<Controller::AlgorithmConfig>>,
+    synchronization: &DaemonSynchronizationConfig,
     algorithm_config: Controller::AlgorithmConfig,
-    source_defaults_config: SourceConfig,
-    clock_config: ClockConfig,
-    source_configs: &[NtpSourceConfig],
-    server_configs: &[ServerConfig],
-    keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    options: SpawnOptions<'_>,
 ) -> std::io::Result<(JoinHandle<std::io::Result<()>>, DaemonChannels)> {
     let ip_list = super::local_ip_provider::spawn()?;
 
     let (mut system, channels) = SystemTask::<_, Controller, _>::new(
-        clock_config.clock,
-        clock_config.interface,
-        clock_config.timestamp_mode,
-        synchronization_config,
+        config_reload,
+        options.clock.clock,
+        synchronization,
         algorithm_config,
-        keyset,
+        &options,
         ip_list,
-        !source_configs.is_empty(),
     );
+    if system.prober {
+        info!("running as a prober, the sources are measured but the clock is never steered");
+        system.apply_steering_restrictions();
+    }
+
+    for source_config in options.sources {
+        let spawner = ConfiguredSpawner::new(
+            source_config,
+            options.source_defaults,
+            &options.symmetric_keys,
+            &options.crypto_policy,
+            synchronization.nat64_prefix,
+        )
+        .inspect_err(|e| tracing::error!("Could not spawn source: {}", e))?;
+        system.start_spawner(source_config.clone(), spawner, false);
+    }
+    system.update_fleet_sources();
+
+    for server_config in options.servers.iter() {
+        system.add_server(server_config.to_owned()).await;
+    }
+
+    let run = async move {
+        let sleep =
+            SingleshotSleep::new_disabled(tokio::time::sleep_until(tokio::time::Instant::now()));
+        tokio::pin!(sleep);
+        system.run(sleep).await
+    };
+
+    let handle = if options.scheduling.is_default() {
+        tokio::spawn(run)
+    } else {
+        spawn_clock_thread(options.scheduling.clone(), run)?
+    };
+
+    Ok((handle, channels))
+}
+
+/// Run the system task, which steers the clock, on a thread of its own with
+/// the configured scheduling. The other tasks, among which the sources and
+/// servers it spawns, stay on the runtime with the default scheduling.
+///
+/// Aborting the returned task stops the thread and drops the system task.
+fn spawn_clock_thread(
+    config: SchedulingConfig,
+    run: impl Future<Output = std::io::Result<()>> + Send + 'static,
+) -> std::io::Result<JoinHandle<std::io::Result<()>>> {
+    let runtime = tokio::runtime::Handle::current();
+    let (mut result_tx, result_rx) = oneshot::channel();
+
+    std::thread::Builder::new()
+        .name("ntp-clock".into())
+        .spawn(move || {
+            sandbox::restrict_thread();
+            scheduling::apply(&config);
+            // the runtime drives the timers and sockets of the system task
+            // from its own threads, this thread only polls it
+            let result = runtime.block_on(async {
+                tokio::select! {
+                    result = run => Some(result),
+                    () = result_tx.closed() => None,
+                }
+            });
+            if let Some(result) = result {
+                let _ = result_tx.send(result);
+            }
+        })?;
+
+    Ok(tokio::spawn(async move {
+        result_rx
+            .await
+            .unwrap_or_else(|_| Err(std::io::Error::other("the clock thread stopped")))
+    }))
+}
+
+struct SystemSpawnerData {
+    id: SpawnerId,
+    notify_tx: mpsc::Sender<SystemEvent>,
+    // the configured source the spawner creates sources for
+    config: NtpSourceConfig,
+    // whether that source is one of the fleet sources rather than configured
+    fleet: bool,
+    task: tokio::task::AbortHandle,
+}
 
-    for source_config in source_configs {
-        match source_config {
+/// Spawner for a configured source. These are created before any of them is
+/// started, such that a configuration with an unusable source is rejected as a
+/// whole.
+enum ConfiguredSpawner {
+    Standard(StandardSpawner),
+    Nts(NtsSpawner),
+    Pool(PoolSpawner),
+    NtsPool(NtsPoolSpawner),
+    Service(ServiceSpawner),
+    Sock(SockSpawner),
+    File(FileSpawner),
+    #[cfg(feature = "pps")]
+    Pps(PpsSpawner),
+}
+
+impl ConfiguredSpawner {
+    fn new(
+        config: &NtpSourceConfig,
+        source_defaults_config: SourceConfig,
+        symmetric_keys: &SymmetricKeySet,
+        crypto_policy: &CryptoPolicyConfig,
+        nat64_prefix: Option<Nat64Prefix>,
+    ) -> std::io::Result<Self> {
+        // behind a NAT64 gateway, IPv4 addresses are only reached through an
+        // address synthesized from them, so IPv6 addresses are tried first
+        let prefer_ip = |configured: Option<AddressFamily>| {
+            configured.or(nat64_prefix.map(|_| AddressFamily::V6))
+        };
+
+        Ok(match config {
             NtpSourceConfig::Standard(cfg) => {
-                system.add_spawner(StandardSpawner::new(
-                    cfg.first.clone(),
-                    cfg.second.clone().with_defaults(source_defaults_config),
-                ));
+                let symmetric_key = match cfg.first.key {
+                    None => None,
+                    Some(id) => Some(symmetric_keys.get(id).ok_or_else(|| {
+                        std::io::Error::other(format!("unknown symmetric key {id}"))
+                    })?),
+                };
+                let mut first = cfg.first.clone();
+                first.prefer_ip = prefer_ip(first.prefer_ip);
+                ConfiguredSpawner::Standard(
+                    StandardSpawner::new(
+                        first,
+                        cfg.second.clone().with_defaults(source_defaults_config),
+                    )
+                    .with_symmetric_key(symmetric_key),
+                )
             }
-            NtpSourceConfig::Nts(cfg) => {
+            NtpSourceConfig::Nts(cfg) => ConfiguredSpawner::Nts(
                 NtsSpawner::new(
                     cfg.first.clone(),
                     cfg.second.clone().with_defaults(source_defaults_config),
                 )
-                .map(|spawner| system.add_spawner(spawner))
-                .map_err(|e| {
-                    tracing::error!("Could not spawn source: {}", e);
-                    std::io::Error::other(e)
-                })?;
-            }
+                .map_err(std::io::Error::other)?
+                .with_algorithms(&crypto_policy.aead_algorithms),
+            ),
             NtpSourceConfig::Pool(cfg) => {
-                system.add_spawner(PoolSpawner::new(
-                    cfg.first.clone(),
+                let mut first = cfg.first.clone();
+                first.prefer_ip = prefer_ip(first.prefer_ip);
+                ConfiguredSpawner::Pool(PoolSpawner::new(
+                    first,
                     cfg.second.clone().with_defaults(source_defaults_config),
-                ));
+                ))
             }
-            NtpSourceConfig::NtsPool(cfg) => {
+            NtpSourceConfig::NtsPool(cfg) => ConfiguredSpawner::NtsPool(
                 NtsPoolSpawner::new(
                     cfg.first.clone(),
                     cfg.second.clone().with_defaults(source_defaults_config),
                 )
-                .map(|spawner| system.add_spawner(spawner))
-                .map_err(|e| {
-                    tracing::error!("Could not spawn source: {}", e);
-                    std::io::Error::other(e)
-                })?;
+                .map_err(std::io::Error::other)?
+                .with_algorithms(&crypto_policy.aead_algorithms),
+            ),
+            NtpSourceConfig::Service(cfg) => {
+                let mut first = cfg.first.clone();
+                first.prefer_ip = prefer_ip(first.prefer_ip);
+                ConfiguredSpawner::Service(ServiceSpawner::new(
+                    first,
+                    cfg.second.clone().with_defaults(source_defaults_config),
+                ))
             }
             NtpSourceConfig::Sock(cfg) => {
-                system.add_spawner(SockSpawner::new(cfg.clone(), source_defaults_config));
+                ConfiguredSpawner::Sock(SockSpawner::new(cfg.clone(), source_defaults_config))
+            }
+            NtpSourceConfig::File(cfg) => {
+                ConfiguredSpawner::File(FileSpawner::new(cfg.clone(), source_defaults_config))
             }
             #[cfg(feature = "pps")]
             NtpSourceConfig::Pps(cfg) => {
-                system.add_spawner(PpsSpawner::new(cfg.clone(), source_defaults_config));
+                ConfiguredSpawner::Pps(PpsSpawner::new(cfg.clone(), source_defaults_config))
             }
-        }
-    }
-
-    for server_config in server_configs.iter() {
-        system.add_server(server_config.to_owned()).await;
+        })
     }
-
-    let handle = tokio::spawn(async move {
-        let sleep =
-            SingleshotSleep::new_disabled(tokio::time::sleep_until(tokio::time::Instant::now()));
-        tokio::pin!(sleep);
-        system.run(sleep).await
-    });
-
-    Ok((handle, channels))
-}
-
-struct SystemSpawnerData {
-    id: SpawnerId,
-    notify_tx: mpsc::Sender<SystemEvent>,
 }
 
 struct SystemTask<
@@ -190,18 +349,60 @@ struct SystemTask<
     system_snapshot_sender: tokio::sync::watch::Sender<SystemSnapshot>,
     system_update_sender:
         tokio::sync::broadcast::Sender<SystemSourceUpdate<Controller::ControllerMessage>>,
-    source_snapshots: Arc<std::sync::RwLock<HashMap<SourceId, ObservableSourceState<SourceId>>>>,
+    observations: Arc<std::sync::RwLock<Observations>>,
     server_data_sender: tokio::sync::watch::Sender<Vec<ServerData>>,
     keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+    symmetric_keys: Arc<SymmetricKeySet>,
     ip_list: tokio::sync::watch::Receiver<Arc<[IpAddr]>>,
 
     msg_for_system_rx: mpsc::Receiver<MsgForSystem<Controller::SourceMessage>>,
     msg_for_system_tx: mpsc::Sender<MsgForSystem<Controller::SourceMessage>>,
     spawn_tx: mpsc::Sender<SpawnEvent>,
     spawn_rx: mpsc::Receiver<SpawnEvent>,
+    control_rx: mpsc::Receiver<ControlMessage>,
+
+    // names of sources disabled through the control socket, these survive the
+    // sources being respawned
+    disabled_sources: HashSet<String>,
+    disabled_sources_sender: tokio::sync::watch::Sender<Vec<String>>,
+    // end of the maintenance window requested through the control socket,
+    // clock steering is suspended until then
+    maintenance_until: Option<tokio::time::Instant>,
+    // configured blackout windows, the restriction currently in effect and
+    // when to check them again
+    blackouts: Vec<BlackoutConfig>,
+    blackout: Option<Restriction>,
+    blackout_check: tokio::time::Instant,
+
+    drift_file: Option<PathBuf>,
+
+    // minimum poll interval each server asked for through rate limiting,
+    // kept across restarts when a file is configured
+    kod_history_file: Option<PathBuf>,
+    kod_history: HashMap<SocketAddr, PollInterval>,
+
+    // measurements of all sources are written here when configured
+    recorder: Option<MeasurementRecorder>,
+
+    // leap seconds from the configured file, read again periodically
+    leap_seconds_file: Option<PathBuf>,
+    leap_seconds: Option<LeapSecondsList>,
+
+    // logs when the sources steering the clock share a failure domain
+    diversity: DiversityMonitor,
+
+    // how to read the configuration again on reload, with the source
+    // defaults that the running spawners were created with
+    config_reload: Option<ConfigReload<Controller::AlgorithmConfig>>,
+    source_defaults: SourceConfig,
+    // the crypto policy the running spawners were created with
+    crypto_policy: CryptoPolicyConfig,
+    // the latest list of sources fetched for the fleet
+    fleet_sources: tokio::sync::watch::Receiver<Vec<NtpSourceConfig>>,
 
     sources: HashMap<SourceId, SourceState>,
     servers: Vec<ServerData>,
+    server_tasks: Vec<tokio::task::AbortHandle>,
     spawners: Vec<SystemSpawnerData>,
 
     clock: C,
@@ -212,25 +413,100 @@ struct SystemTask<
     // bind the socket to a specific interface. This is relevant for hardware timestamping,
     // because the interface determines which clock is used to produce the timestamps.
     interface: Option<InterfaceName>,
+
+    // shards over which sources polled over UDP share their sockets, if enabled
+    shared_sockets: SharedSockets,
+
+    // keep the socket of a source that is not on a shared socket across polls
+    keep_sockets: bool,
+
+    // IPv4 addresses of sources are reached through this NAT64 prefix
+    nat64_prefix: Option<Nat64Prefix>,
+
+    // only measure the sources, without ever steering the clock
+    prober: bool,
+
+    // also record the decisions of the controller in the measurement recording
+    record_decisions: bool,
 }
 
 impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = SourceId>, T: Wait>
     SystemTask<C, Controller, T>
 {
-    #[allow(clippy::too_many_arguments)]
     fn new(
+        config_reload: Option<ConfigReload<Controller::AlgorithmConfig>>,
         clock: C,
-        interface: Option<InterfaceName>,
-        timestamp_mode: TimestampMode,
-        synchronization_config: SynchronizationConfig,
+        synchronization: &DaemonSynchronizationConfig,
         algorithm_config: Controller::AlgorithmConfig,
-        keyset: tokio::sync::watch::Receiver<Arc<KeySet>>,
+        options: &SpawnOptions<'_>,
         ip_list: tokio::sync::watch::Receiver<Arc<[IpAddr]>>,
-        have_sources: bool,
     ) -> (Self, DaemonChannels) {
+        let prober = synchronization.prober;
+        let have_sources =
+            (!options.sources.is_empty() || !options.fleet_sources.borrow().is_empty()) && !prober;
+
+        // a prober leaves the clock and its frequency alone, so it needs neither
+        // access to the clock nor a drift file
+        let drift_file = synchronization.drift_file.clone().filter(|_| !prober);
+        let shared_sockets = match synchronization.shared_sockets {
+            0 if prober => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            shared_sockets => shared_sockets,
+        };
+
+        // Start from the frequency of the previous run, so the controller
+        // does not need to learn it from scratch
+        if have_sources && let Some(path) = &drift_file {
+            match drift::load(path) {
+                Ok(frequency) => match clock.set_frequency(frequency) {
+                    Ok(_) => info!(?path, ppm = frequency * 1e6, "restored clock frequency"),
+                    Err(e) => warn!(error = ?e, "could not restore clock frequency"),
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    info!(
+                        ?path,
+                        "no drift file yet, learning clock frequency from scratch"
+                    );
+                }
+                Err(e) => warn!(?path, error = ?e, "could not read drift file"),
+            }
+        }
+
+        let kod_history = match &synchronization.kod_history_file {
+            None => HashMap::new(),
+            Some(path) => match kod_history::load(path) {
+                Ok(history) => {
+                    info!(
+                        ?path,
+                        servers = history.len(),
+                        "restored server rate limits"
+                    );
+                    history
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+                Err(e) => {
+                    warn!(?path, error = ?e, "could not read kod history file");
+                    HashMap::new()
+                }
+            },
+        };
+
+        let recorder = synchronization
+            .measurement_record_file
+            .as_ref()
+            .and_then(|path| match MeasurementRecorder::create(path) {
+                Ok(recorder) => {
+                    info!(?path, "recording measurements");
+                    Some(recorder)
+                }
+                Err(e) => {
+                    warn!(?path, error = ?e, "could not create measurement recording");
+                    None
+                }
+            });
+
         let Ok(mut system) = System::new(
             clock.clone(),
-            synchronization_config,
+            synchronization.synchronization_base,
             algorithm_config,
             ip_list.borrow().clone(),
         ) else {
@@ -238,20 +514,31 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
             std::process::exit(70);
         };
 
+        // a prober creates its sources without taking control of the clock,
+        // so another daemon can keep steering it
+        if prober {
+            system.disable_clock_control();
+        }
+
         if have_sources && let Err(e) = system.check_clock_access() {
             tracing::error!("Could not control clock: {}", e);
             std::process::exit(70);
         }
 
         // Create communication channels
-        let (system_snapshot_sender, system_snapshot_receiver) =
-            tokio::sync::watch::channel(system.system_snapshot());
-        let source_snapshots = Arc::new(RwLock::new(HashMap::new()));
+        let (system_snapshot_sender, _) = tokio::sync::watch::channel(system.system_snapshot());
+        let observations = Arc::new(RwLock::new(Observations::new(
+            system.system_snapshot(),
+            Controller::ALGORITHM,
+        )));
         let (server_data_sender, server_data_receiver) = tokio::sync::watch::channel(vec![]);
         let (msg_for_system_sender, msg_for_system_receiver) =
             tokio::sync::mpsc::channel(MESSAGE_BUFFER_SIZE);
         let (system_update_sender, _) = tokio::sync::broadcast::channel(MESSAGE_BUFFER_SIZE);
         let (spawn_tx, spawn_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let (control_sender, control_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
+        let (disabled_sources_sender, disabled_sources_receiver) =
+            tokio::sync::watch::channel(vec![]);
 
         // Build System and its channels
         (
@@ -261,46 +548,155 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
 
                 system_snapshot_sender,
                 system_update_sender,
-                source_snapshots: source_snapshots.clone(),
+                observations: observations.clone(),
                 server_data_sender,
-                keyset: keyset.clone(),
+                keyset: options.keyset.clone(),
+                symmetric_keys: options.symmetric_keys.clone(),
                 ip_list,
 
                 msg_for_system_rx: msg_for_system_receiver,
                 msg_for_system_tx: msg_for_system_sender,
                 spawn_rx,
                 spawn_tx,
+                control_rx,
+
+                disabled_sources: Default::default(),
+                disabled_sources_sender,
+                maintenance_until: None,
+                blackouts: synchronization.blackouts.clone(),
+                blackout: None,
+                blackout_check: tokio::time::Instant::now(),
+
+                drift_file,
+
+                kod_history_file: synchronization.kod_history_file.clone(),
+                kod_history,
+
+                recorder,
+
+                leap_seconds_file: synchronization.leap_seconds_file.clone(),
+                leap_seconds: None,
+
+                diversity: DiversityMonitor::new(tokio::time::Instant::now()),
+
+                config_reload,
+                source_defaults: options.source_defaults,
+                crypto_policy: options.crypto_policy.clone(),
+                fleet_sources: options.fleet_sources.clone(),
 
                 sources: Default::default(),
                 servers: Default::default(),
+                server_tasks: Default::default(),
                 spawners: Default::default(),
                 clock,
-                timestamp_mode,
-                interface,
+                timestamp_mode: options.clock.timestamp_mode,
+                interface: options.clock.interface.clone(),
+                shared_sockets: SharedSockets::new(shared_sockets, options.clock.timestamp_mode),
+                keep_sockets: synchronization.keep_sockets,
+                nat64_prefix: synchronization.nat64_prefix,
+                prober,
+                record_decisions: synchronization.record_decisions,
             },
             DaemonChannels {
-                source_snapshots,
+                observations,
                 server_data_receiver,
-                system_snapshot_receiver,
+                disabled_sources_receiver,
+                control_sender,
             },
         )
     }
 
-    fn add_spawner(&mut self, spawner: impl Spawner + Send + Sync + 'static) -> SpawnerId {
+    fn start_spawner(
+        &mut self,
+        config: NtpSourceConfig,
+        spawner: ConfiguredSpawner,
+        fleet: bool,
+    ) -> SpawnerId {
+        match spawner {
+            ConfiguredSpawner::Standard(spawner) => self.add_spawner(spawner, config, fleet),
+            ConfiguredSpawner::Nts(spawner) => self.add_spawner(spawner, config, fleet),
+            ConfiguredSpawner::Pool(spawner) => self.add_spawner(spawner, config, fleet),
+            ConfiguredSpawner::NtsPool(spawner) => self.add_spawner(spawner, config, fleet),
+            ConfiguredSpawner::Service(spawner) => self.add_spawner(spawner, config, fleet),
+            ConfiguredSpawner::Sock(spawner) => self.add_spawner(spawner, config, fleet),
+            ConfiguredSpawner::File(spawner) => self.add_spawner(spawner, config, fleet),
+            #[cfg(feature = "pps")]
+            ConfiguredSpawner::Pps(spawner) => self.add_spawner(spawner, config, fleet),
+        }
+    }
+
+    fn add_spawner(
+        &mut self,
+        spawner: impl Spawner + Send + Sync + 'static,
+        config: NtpSourceConfig,
+        fleet: bool,
+    ) -> SpawnerId {
         let (notify_tx, notify_rx) = mpsc::channel(MESSAGE_BUFFER_SIZE);
         let id = spawner.get_id();
-        let spawner_data = SystemSpawnerData { id, notify_tx };
-        debug!(id=?spawner_data.id, ty=spawner.get_description(), addr=spawner.get_addr_description(), "Running spawner");
-        self.spawners.push(spawner_data);
+        debug!(id=?id, ty=spawner.get_description(), addr=spawner.get_addr_description(), "Running spawner");
         let spawn_tx = self.spawn_tx.clone();
-        // tokio::spawn(async move { spawner.run(spawn_tx, notify_rx).await });
-        tokio::spawn(spawner_task(spawner, spawn_tx, notify_rx));
+        let task = tokio::spawn(spawner_task(spawner, spawn_tx, notify_rx)).abort_handle();
+        self.spawners.push(SystemSpawnerData {
+            id,
+            notify_tx,
+            config,
+            fleet,
+            task,
+        });
         id
     }
 
+    /// Stop a spawner together with all sources it created
+    fn remove_spawner(&mut self, id: SpawnerId) {
+        self.spawners.retain(|spawner| {
+            if spawner.id == id {
+                spawner.task.abort();
+            }
+            spawner.id != id
+        });
+        self.observations
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .set_source_failure(id, None);
+
+        let sources: Vec<_> = self
+            .sources
+            .iter()
+            .filter(|(_, state)| state.spawner_id == id)
+            .map(|(index, _)| *index)
+            .collect();
+        for index in sources {
+            if let Some(state) = self.sources.remove(&index) {
+                state.task.abort();
+            }
+            if let Err(e) = self.system.handle_source_remove(index) {
+                warn!(error = ?e, "could not remove source");
+            }
+            self.observations
+                .write()
+                .expect("Unexpected poisoned mutex")
+                .remove(&index);
+        }
+    }
+
     async fn run(&mut self, mut wait: Pin<&mut SingleshotSleep<T>>) -> std::io::Result<()> {
+        let mut drift_interval = tokio::time::interval(DRIFT_FILE_INTERVAL);
+        drift_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut leap_seconds_interval = tokio::time::interval(LEAP_SECONDS_FILE_INTERVAL);
+        leap_seconds_interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
         loop {
+            let maintenance_end = self
+                .maintenance_until
+                .unwrap_or_else(tokio::time::Instant::now);
             tokio::select! {
+                _ = drift_interval.tick(), if self.drift_file.is_some() => {
+                    self.store_drift();
+                }
+                _ = leap_seconds_interval.tick(), if self.leap_seconds_file.is_some() => {
+                    self.load_leap_seconds();
+                    self.handle_state_update(SystemActionIterator::default(), &mut wait);
+                }
                 opt_msg_for_system = self.msg_for_system_rx.recv() => {
                     match opt_msg_for_system {
                         None => {
@@ -326,9 +722,21 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
                         }
                     }
                 }
+                Some(control_msg) = self.control_rx.recv() => {
+                    self.handle_control(control_msg, &mut wait);
+                }
+                () = tokio::time::sleep_until(maintenance_end), if self.maintenance_until.is_some() => {
+                    self.end_maintenance();
+                }
+                () = tokio::time::sleep_until(self.blackout_check), if !self.blackouts.is_empty() => {
+                    self.update_blackout();
+                }
                 _ = self.ip_list.changed(), if self.ip_list.has_changed().is_ok() => {
                     self.system.update_ip_list(self.ip_list.borrow_and_update().clone());
                 }
+                _ = self.fleet_sources.changed(), if self.fleet_sources.has_changed().is_ok() => {
+                    self.update_fleet_sources();
+                }
                 () = &mut wait => {
                     let timer = self.system.handle_timer();
                     self.handle_state_update(timer, &mut wait);
@@ -340,15 +748,155 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
         Ok(())
     }
 
+    fn store_drift(&self) {
+        let (Some(path), Some(frequency)) = (&self.drift_file, self.system.frequency_estimate())
+        else {
+            return;
+        };
+
+        match drift::store(path, frequency) {
+            Ok(()) => debug!(?path, ppm = frequency * 1e6, "stored clock frequency"),
+            Err(e) => warn!(?path, error = ?e, "could not store clock frequency"),
+        }
+    }
+
+    fn load_leap_seconds(&mut self) {
+        let Some(path) = &self.leap_seconds_file else {
+            return;
+        };
+        let now = match self.clock.now() {
+            Ok(now) => now,
+            Err(e) => {
+                warn!(error = ?e, "could not read clock to check leap seconds");
+                return;
+            }
+        };
+
+        match LeapSecondsList::load(path) {
+            Ok(list) if list.is_expired(now) => {
+                warn!(?path, "leap seconds file has expired, ignoring it");
+                self.leap_seconds = None;
+            }
+            Ok(list) => {
+                if self.leap_seconds.as_ref() != Some(&list) {
+                    info!(?path, expires = ?list.expires(), "loaded leap seconds");
+                }
+                if let Some(tai_offset) = list.tai_offset(now)
+                    && let Err(e) = self.clock.set_tai_offset(tai_offset)
+                {
+                    warn!(error = ?e, tai_offset, "could not set TAI offset");
+                }
+                self.leap_seconds = Some(list);
+            }
+            // an earlier version of the file remains usable until it expires
+            Err(e) => warn!(?path, error = ?e, "could not read leap seconds file"),
+        }
+
+        self.observations
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .set_leap_seconds(self.leap_seconds.as_ref().map(|list| list.status(now)));
+    }
+
+    /// Announce leap seconds from the leap seconds file when our sources don't
+    fn announce_leap_seconds(&self, snapshot: &mut SystemSnapshot) {
+        let Some(list) = &self.leap_seconds else {
+            return;
+        };
+        if snapshot.time_snapshot.leap_indicator != NtpLeapIndicator::NoWarning {
+            return;
+        }
+        let Ok(now) = self.clock.now() else {
+            return;
+        };
+        if list.is_expired(now) {
+            return;
+        }
+
+        snapshot.time_snapshot.leap_indicator = list.announcement(now);
+        // The controller passes on the leap indicator of our sources, so on
+        // the day of the leap second this overrides it for the kernel too.
+        let kernel_status = list.kernel_status(now);
+        if kernel_status != NtpLeapIndicator::NoWarning
+            && let Err(e) = self.clock.status_update(kernel_status)
+        {
+            warn!(error = ?e, "could not announce leap second to the kernel");
+        }
+    }
+
+    fn record_remote_min_poll_interval(&mut self, addr: SocketAddr, interval: PollInterval) {
+        let Some(path) = &self.kod_history_file else {
+            return;
+        };
+
+        if self
+            .kod_history
+            .get(&addr)
+            .is_some_and(|previous| *previous >= interval)
+        {
+            return;
+        }
+
+        self.kod_history.insert(addr, interval);
+        match kod_history::store(path, &self.kod_history) {
+            Ok(()) => debug!(?path, ?addr, ?interval, "stored server rate limit"),
+            Err(e) => warn!(?path, error = ?e, "could not store kod history"),
+        }
+    }
+
+    fn record_measurement(&mut self, index: SourceId, measurement: &Measurement<NtpDuration>) {
+        let (Some(recorder), Some(source)) = (&mut self.recorder, self.sources.get(&index)) else {
+            return;
+        };
+
+        if let Err(e) = recorder.record(&source.name, measurement) {
+            warn!(error = ?e, "could not record measurement, stopping recording");
+            self.recorder = None;
+        }
+    }
+
+    /// Record what the controller decided in its last update of the clock,
+    /// if asked for
+    fn record_decision(&mut self) {
+        let Some(decision) = self.system.take_decision() else {
+            return;
+        };
+        let (true, Some(recorder)) = (self.record_decisions, &mut self.recorder) else {
+            return;
+        };
+
+        let sources = &self.sources;
+        let mut decision = decision.map_sources(|index| match sources.get(&index) {
+            Some(source) => source.name.clone(),
+            None => format!("{index:?}"),
+        });
+        decision.sort_sources();
+        if let Err(e) = recorder.record_decision(decision) {
+            warn!(error = ?e, "could not record decision, stopping recording");
+            self.recorder = None;
+        }
+    }
+
     fn handle_state_update(
         &mut self,
         actions: SystemActionIterator<Controller::ControllerMessage>,
         wait: &mut Pin<&mut SingleshotSleep<T>>,
     ) {
-        // Don't care if there is no receiver.
-        let _ = self
-            .system_snapshot_sender
-            .send(self.system.system_snapshot());
+        self.record_decision();
+        let mut system_snapshot = self.system.system_snapshot();
+        self.announce_leap_seconds(&mut system_snapshot);
+        let selection = self.system.source_selection();
+        let diversity = self.assess_diversity(&selection);
+        {
+            let mut observations = self
+                .observations
+                .write()
+                .expect("Unexpected poisoned mutex");
+            observations.update_system(system_snapshot, selection);
+            observations.set_diversity(diversity);
+        }
+        // Servers subscribe later on, so keep the value even without receivers.
+        self.system_snapshot_sender.send_replace(system_snapshot);
 
         for action in actions {
             match action {
@@ -365,6 +913,34 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
         }
     }
 
+    /// How independent the sources steering the clock are, logging when they
+    /// all share a failure domain
+    fn assess_diversity(&mut self, selection: &HashMap<SourceId, SelectionStatus>) -> Diversity {
+        let domains: Vec<_> = selection
+            .iter()
+            .filter(|(_, status)| {
+                matches!(status, SelectionStatus::Primary | SelectionStatus::Selected)
+            })
+            .filter_map(|(id, _)| {
+                let source = self.sources.get(id)?;
+                let spawner = self
+                    .spawners
+                    .iter()
+                    .find(|spawner| spawner.id == source.spawner_id)?;
+                Some(SourceDomain::new(
+                    &spawner.config,
+                    &source.name,
+                    &source.address,
+                    self.nat64_prefix,
+                ))
+            })
+            .collect();
+        let diversity = Diversity::assess(&domains);
+        self.diversity
+            .update(&diversity, tokio::time::Instant::now());
+        diversity
+    }
+
     async fn handle_source_update(
         &mut self,
         msg: MsgForSystem<Controller::SourceMessage>,
@@ -373,17 +949,58 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
         tracing::debug!(?msg, "updating source");
 
         match msg {
+            MsgForSystem::SourceUpdate(index, _, _)
+            | MsgForSystem::OneWaySourceUpdate(index, _)
+            | MsgForSystem::MustDemobilize(index)
+            | MsgForSystem::NetworkIssue(index)
+            | MsgForSystem::Unreachable(index)
+                if !self.sources.contains_key(&index) =>
+            {
+                // the source was removed on a configuration reload, but had
+                // already sent this message
+                debug!(source_id=?index, "ignoring message from removed source");
+            }
+            MsgForSystem::SourceUpdate(index, _, _)
+            | MsgForSystem::OneWaySourceUpdate(index, _)
+                if self
+                    .sources
+                    .get(&index)
+                    .is_some_and(|s| !*s.enabled.borrow()) =>
+            {
+                debug!(source_id=?index, "ignoring update from disabled source");
+            }
             MsgForSystem::MustDemobilize(index) => {
                 if let Err(e) = self.handle_source_demobilize(index).await {
                     unreachable!("Could not demobilize source: {}", e);
                 };
             }
-            MsgForSystem::SourceUpdate(index, update) => {
-                match self.system.handle_source_update(index, update) {
+            MsgForSystem::SourceUpdate(index, update, poll_span) => {
+                let reachable = update.is_reachable();
+                if let Some(interval) = update.remote_min_poll_interval() {
+                    self.record_remote_min_poll_interval(update.source_addr(), interval);
+                }
+                if let Some(measurement) = update.measurement() {
+                    self.record_measurement(index, &measurement);
+                }
+                // a prober keeps no state for the sources beyond their own
+                // measurements, which are exported through the observations
+                if self.prober {
+                    if reachable {
+                        self.handle_source_reachable(index).await;
+                    }
+                    return Ok(());
+                }
+                // selection and steering happen as part of the poll exchange that
+                // delivered this measurement
+                poll_span.in_scope(|| match self.system.handle_source_update(index, update) {
                     Err(e) => unreachable!("Could not process source measurement: {}", e),
                     Ok(timer) => self.handle_state_update(timer, wait),
+                });
+                if reachable {
+                    self.handle_source_reachable(index).await;
                 }
             }
+            MsgForSystem::OneWaySourceUpdate(_, _) if self.prober => {}
             MsgForSystem::OneWaySourceUpdate(index, update) => {
                 match self.system.handle_one_way_source_update(index, update) {
                     Err(e) => unreachable!("Could not process source measurement: {}", e),
@@ -401,6 +1018,547 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
         Ok(())
     }
 
+    async fn handle_source_reachable(&mut self, index: SourceId) {
+        let Some(state) = self.sources.get_mut(&index) else {
+            return;
+        };
+        if state.reachable {
+            return;
+        }
+        state.reachable = true;
+
+        let spawner_id = state.spawner_id;
+        let source_id = state.source_id;
+        self.observations
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .set_source_failure(spawner_id, None);
+        let opt_spawner = self.spawners.iter().find(|s| s.id == spawner_id);
+        if let Some(spawner) = opt_spawner {
+            spawner
+                .notify_tx
+                .send(SystemEvent::SourceReachable(source_id))
+                .await
+                .expect("Could not notify spawner");
+        }
+    }
+
+    fn handle_control(&mut self, msg: ControlMessage, wait: &mut Pin<&mut SingleshotSleep<T>>) {
+        let response = match msg.request {
+            ControlRequest::DisableSource { source } => self.set_source_enabled(source, false),
+            ControlRequest::EnableSource { source } => self.set_source_enabled(source, true),
+            ControlRequest::Maintenance { seconds } => self.set_maintenance(seconds),
+            ControlRequest::ExportState => ControlResponse::State {
+                state: Box::new(self.export_state()),
+            },
+            ControlRequest::ImportState { state } => self.import_state(*state, wait),
+            ControlRequest::Reload => self.reload(wait),
+            ControlRequest::AddSource { address } => self.add_source(address),
+            ControlRequest::RemoveSource { source } => self.remove_source(source),
+            ControlRequest::Tune {
+                step_threshold,
+                slew_maximum_frequency_offset,
+                slew_minimum_duration,
+            } => self.tune(
+                step_threshold,
+                slew_maximum_frequency_offset,
+                slew_minimum_duration,
+            ),
+        };
+
+        // The requester may already have given up
+        let _ = msg.reply.send(response);
+    }
+
+    fn set_source_enabled(&mut self, source: String, enable: bool) -> ControlResponse {
+        let mut matched = 0;
+        for (id, state) in &self.sources {
+            if state.name != source && state.address != source {
+                continue;
+            }
+            matched += 1;
+            state.enabled.send_replace(enable);
+            if !enable {
+                self.system.handle_source_disabled(*id);
+            }
+        }
+
+        let response = if matched == 0 && !self.disabled_sources.contains(&source) {
+            ControlResponse::UnknownSource
+        } else {
+            if enable {
+                info!(%source, "source enabled");
+                self.disabled_sources.remove(&source);
+            } else {
+                info!(%source, "source disabled");
+                self.disabled_sources.insert(source);
+            }
+            ControlResponse::Ok { sources: matched }
+        };
+
+        let mut disabled: Vec<_> = self.disabled_sources.iter().cloned().collect();
+        disabled.sort();
+        let _ = self.disabled_sources_sender.send(disabled);
+
+        response
+    }
+
+    /// Start a source that is not in the configuration, a reload stops it
+    /// again like any other source that is not configured
+    fn add_source(&mut self, address: String) -> ControlResponse {
+        let spawner = NtpSourceConfig::try_from(address.as_str()).and_then(|config| {
+            ConfiguredSpawner::new(
+                &config,
+                self.source_defaults,
+                &self.symmetric_keys,
+                &self.crypto_policy,
+                self.nat64_prefix,
+            )
+            .map(|spawner| (config, spawner))
+        });
+
+        match spawner {
+            Ok((config, spawner)) => {
+                info!(%address, "source added");
+                self.start_spawner(config, spawner, false);
+                ControlResponse::Reloaded {
+                    added: 1,
+                    removed: 0,
+                }
+            }
+            Err(e) => {
+                warn!(%address, error = %e, "could not add source");
+                ControlResponse::ReloadFailed {
+                    error: e.to_string(),
+                }
+            }
+        }
+    }
+
+    /// Stop the spawners of all sources with the given name or address, and
+    /// of the configured servers with that address that have no sources yet
+    fn remove_source(&mut self, source: String) -> ControlResponse {
+        let mut spawners = vec![];
+        let by_source = self
+            .sources
+            .values()
+            .filter(|state| state.name == source || state.address == source)
+            .map(|state| state.spawner_id);
+        let by_config = self
+            .spawners
+            .iter()
+            .filter(|spawner| match &spawner.config {
+                NtpSourceConfig::Standard(cfg) => cfg.first.address.to_string() == source,
+                _ => false,
+            })
+            .map(|spawner| spawner.id);
+        for id in by_source.chain(by_config) {
+            if !spawners.contains(&id) {
+                spawners.push(id);
+            }
+        }
+
+        if spawners.is_empty() {
+            return ControlResponse::UnknownSource;
+        }
+        for id in &spawners {
+            self.remove_spawner(*id);
+        }
+
+        info!(%source, removed = spawners.len(), "source removed");
+        ControlResponse::Reloaded {
+            added: 0,
+            removed: spawners.len(),
+        }
+    }
+
+    /// Change the given thresholds deciding between stepping and slewing the
+    /// clock, until the configuration is reloaded
+    fn tune(
+        &mut self,
+        step_threshold: Option<f64>,
+        slew_maximum_frequency_offset: Option<f64>,
+        slew_minimum_duration: Option<f64>,
+    ) -> ControlResponse {
+        let Some(current) = self.system.step_settings() else {
+            return ControlResponse::TuneFailed {
+                error: "the clock controller has no thresholds to tune".to_owned(),
+            };
+        };
+        let settings = StepSettings {
+            step_threshold: step_threshold.unwrap_or(current.step_threshold),
+            slew_maximum_frequency_offset: slew_maximum_frequency_offset
+                .unwrap_or(current.slew_maximum_frequency_offset),
+            slew_minimum_duration: slew_minimum_duration.unwrap_or(current.slew_minimum_duration),
+        };
+        if let Err(error) = check_step_settings(&settings) {
+            warn!(%error, "rejected tuning of the clock controller");
+            return ControlResponse::TuneFailed { error };
+        }
+
+        for (setting, old, new) in [
+            (
+                "step-threshold",
+                current.step_threshold,
+                settings.step_threshold,
+            ),
+            (
+                "slew-maximum-frequency-offset",
+                current.slew_maximum_frequency_offset,
+                settings.slew_maximum_frequency_offset,
+            ),
+            (
+                "slew-minimum-duration",
+                current.slew_minimum_duration,
+                settings.slew_minimum_duration,
+            ),
+        ] {
+            if old != new {
+                info!(
+                    setting,
+                    old, new, "clock controller tuned through the control socket"
+                );
+            }
+        }
+        self.system.set_step_settings(settings);
+
+        ControlResponse::Tuned { settings }
+    }
+
+    fn set_maintenance(&mut self, seconds: u64) -> ControlResponse {
+        let seconds = seconds.min(MAX_MAINTENANCE_SECONDS);
+        if seconds == 0 {
+            self.end_maintenance();
+        } else {
+            info!(seconds, "clock steering suspended for maintenance");
+            self.maintenance_until =
+                Some(tokio::time::Instant::now() + std::time::Duration::from_secs(seconds));
+            self.apply_steering_restrictions();
+        }
+
+        ControlResponse::Maintenance { seconds }
+    }
+
+    fn export_state(&self) -> DaemonState {
+        let selection = self.system.source_selection();
+        let mut sources: Vec<_> = self
+            .observations
+            .read()
+            .expect("Unexpected poisoned mutex")
+            .sources()
+            .map(|source| state::SourceState {
+                selection: selection.get(&source.id).copied().unwrap_or_default(),
+                ..state::SourceState::from(source)
+            })
+            .collect();
+        sources.sort_by(|a, b| (&a.name, &a.address).cmp(&(&b.name, &b.address)));
+
+        let mut disabled_sources: Vec<_> = self.disabled_sources.iter().cloned().collect();
+        disabled_sources.sort();
+
+        DaemonState {
+            format: STATE_EXPORT.current(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            frequency_ppm: self.system.frequency_estimate().map(|f| f * 1e6),
+            stratum: self.system.system_snapshot().stratum,
+            sources,
+            servers: self
+                .servers
+                .iter()
+                .map(|server| ServerState {
+                    listen: server.config.listen,
+                    stats: server.stats.clone(),
+                })
+                .collect(),
+            disabled_sources,
+            rate_limits: self.kod_history.iter().map(|(a, i)| (*a, *i)).collect(),
+        }
+    }
+
+    /// Take over what carries over from the state of another instance. The
+    /// measurement filters of sources are not, as they depend on the network
+    /// path from this host and converge again within a few polls.
+    fn import_state(
+        &mut self,
+        state: DaemonState,
+        wait: &mut Pin<&mut SingleshotSleep<T>>,
+    ) -> ControlResponse {
+        let frequency = match state.frequency_ppm {
+            Some(ppm) if ppm.is_finite() && ppm.abs() <= drift::MAX_FREQUENCY_PPM => {
+                match self.system.restore_frequency(ppm * 1e-6) {
+                    Some(actions) => {
+                        info!(ppm, "restored clock frequency from imported state");
+                        self.handle_state_update(actions, wait);
+                        true
+                    }
+                    None => {
+                        info!("clock frequency already determined, ignoring imported frequency");
+                        false
+                    }
+                }
+            }
+            Some(ppm) => {
+                warn!(ppm, "ignoring implausible frequency in imported state");
+                false
+            }
+            None => false,
+        };
+
+        for (addr, interval) in &state.rate_limits {
+            let known = self.kod_history.entry(*addr).or_insert(*interval);
+            *known = Ord::max(*known, *interval);
+        }
+        if let Some(path) = &self.kod_history_file
+            && !state.rate_limits.is_empty()
+            && let Err(e) = kod_history::store(path, &self.kod_history)
+        {
+            warn!(?path, error = ?e, "could not store kod history");
+        }
+
+        let disabled_sources = state
+            .disabled_sources
+            .into_iter()
+            .filter(|source| {
+                matches!(
+                    self.set_source_enabled(source.clone(), false),
+                    ControlResponse::Ok { .. }
+                )
+            })
+            .count();
+
+        let mut servers = 0;
+        for imported in &state.servers {
+            if let Some(server) = self
+                .servers
+                .iter()
+                .find(|server| server.config.listen == imported.listen)
+            {
+                server.stats.add(&imported.stats);
+                servers += 1;
+            }
+        }
+
+        info!(
+            version = state.version,
+            frequency,
+            rate_limits = state.rate_limits.len(),
+            disabled_sources,
+            servers,
+            "imported daemon state"
+        );
+
+        ControlResponse::StateImported {
+            frequency,
+            rate_limits: state.rate_limits.len(),
+            disabled_sources,
+            servers,
+        }
+    }
+
+    /// Read the configuration file again and apply the changes to the sources
+    /// and synchronization. Other sections only take effect on a restart. A
+    /// configuration that cannot be used leaves everything as it is.
+    fn reload(&mut self, wait: &mut Pin<&mut SingleshotSleep<T>>) -> ControlResponse {
+        fn reload_failed(error: impl std::fmt::Display) -> ControlResponse {
+            warn!(%error, "could not reload configuration, keeping the current one");
+            ControlResponse::ReloadFailed {
+                error: error.to_string(),
+            }
+        }
+
+        let Some(config_reload) = &self.config_reload else {
+            return reload_failed("reloading is not supported in this mode");
+        };
+
+        let config = match Config::from_args(config_reload.path.as_ref(), vec![], vec![]) {
+            Ok(config) => config,
+            Err(e) => return reload_failed(e),
+        };
+        config.check();
+        let algorithm_config = (config_reload.algorithm)(&config);
+
+        let symmetric_keys = match config.keyset.symmetric_keys(&config.crypto_policy) {
+            Ok(keys) => keys,
+            Err(e) => return reload_failed(e),
+        };
+
+        // All sources are recreated when their defaults or the crypto policy
+        // changed
+        let recreate = config.source_defaults != self.source_defaults
+            || config.crypto_policy != self.crypto_policy;
+        let (added_count, removed_count) = match self.replace_spawners(
+            &config.sources,
+            false,
+            recreate,
+            config.source_defaults,
+            &symmetric_keys,
+            &config.crypto_policy,
+        ) {
+            Ok(counts) => counts,
+            Err(e) => return reload_failed(e),
+        };
+        if recreate {
+            let fleet_sources = self.fleet_sources.borrow().clone();
+            if let Err(e) = self.replace_spawners(
+                &fleet_sources,
+                true,
+                true,
+                config.source_defaults,
+                &symmetric_keys,
+                &config.crypto_policy,
+            ) {
+                warn!(error = %e, "could not recreate the fleet sources, keeping the current ones");
+            }
+        }
+        self.source_defaults = config.source_defaults;
+        self.symmetric_keys = Arc::new(symmetric_keys);
+        self.crypto_policy = config.crypto_policy.clone();
+        self.blackouts = config.synchronization.blackouts.clone();
+        self.update_blackout();
+
+        let actions = self.system.update_config(
+            config.synchronization.synchronization_base,
+            algorithm_config,
+        );
+        self.handle_state_update(actions, wait);
+        self.observations
+            .write()
+            .expect("Unexpected poisoned mutex")
+            .set_config_hash(config.hash);
+
+        info!(
+            added = added_count,
+            removed = removed_count,
+            "reloaded configuration"
+        );
+        ControlResponse::Reloaded {
+            added: added_count,
+            removed: removed_count,
+        }
+    }
+
+    /// Make the running spawners of the configured sources, or of the fleet
+    /// sources, match `sources`. Spawners for a source that is still there
+    /// are kept unless `recreate`. When a new spawner cannot be created,
+    /// nothing changes. Returns how many spawners were added and removed.
+    fn replace_spawners(
+        &mut self,
+        sources: &[NtpSourceConfig],
+        fleet: bool,
+        recreate: bool,
+        source_defaults: SourceConfig,
+        symmetric_keys: &SymmetricKeySet,
+        crypto_policy: &CryptoPolicyConfig,
+    ) -> std::io::Result<(usize, usize)> {
+        // Pair every source with a running spawner for the same
+        // configuration, spawners of the other kind are left alone
+        let mut kept: Vec<_> = self
+            .spawners
+            .iter()
+            .map(|spawner| spawner.fleet != fleet)
+            .collect();
+        let mut added = vec![];
+        for source_config in sources {
+            let running = (0..self.spawners.len())
+                .find(|&i| !recreate && !kept[i] && self.spawners[i].config == *source_config);
+            match running {
+                Some(i) => kept[i] = true,
+                None => {
+                    let spawner = ConfiguredSpawner::new(
+                        source_config,
+                        source_defaults,
+                        symmetric_keys,
+                        crypto_policy,
+                        self.nat64_prefix,
+                    )?;
+                    added.push((source_config.clone(), spawner));
+                }
+            }
+        }
+
+        let removed: Vec<_> = self
+            .spawners
+            .iter()
+            .zip(kept)
+            .filter(|(_, kept)| !kept)
+            .map(|(spawner, _)| spawner.id)
+            .collect();
+        for id in &removed {
+            self.remove_spawner(*id);
+        }
+        let added_count = added.len();
+        for (source_config, spawner) in added {
+            self.start_spawner(source_config, spawner, fleet);
+        }
+        Ok((added_count, removed.len()))
+    }
+
+    /// Start and stop sources to match the latest list of fleet sources
+    fn update_fleet_sources(&mut self) {
+        let sources = self.fleet_sources.borrow_and_update().clone();
+        let symmetric_keys = self.symmetric_keys.clone();
+        let crypto_policy = self.crypto_policy.clone();
+        match self.replace_spawners(
+            &sources,
+            true,
+            false,
+            self.source_defaults,
+            &symmetric_keys,
+            &crypto_policy,
+        ) {
+            Ok((0, 0)) => {}
+            Ok((added, removed)) => info!(added, removed, "updated fleet sources"),
+            Err(e) => {
+                warn!(error = %e, "could not use the fleet sources, keeping the current ones")
+            }
+        }
+    }
+
+    fn end_maintenance(&mut self) {
+        if self.maintenance_until.take().is_some() {
+            info!("maintenance ended, resuming clock steering");
+        }
+        self.apply_steering_restrictions();
+    }
+
+    fn update_blackout(&mut self) {
+        let unix_seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        let (restriction, next_check) = blackout::restriction(&self.blackouts, unix_seconds);
+        self.blackout_check =
+            tokio::time::Instant::now() + std::time::Duration::from_secs(next_check);
+
+        if restriction != self.blackout {
+            match restriction {
+                Some(Restriction::Suspended) => info!("blackout started, clock steering suspended"),
+                Some(Restriction::Limited(max_slew)) => {
+                    info!(
+                        max_slew,
+                        "blackout started, clock steering limited to small slews"
+                    )
+                }
+                None => info!("blackout ended, resuming clock steering"),
+            }
+            self.blackout = restriction;
+            self.apply_steering_restrictions();
+        }
+    }
+
+    /// Restrict steering of the clock as needed for maintenance, blackouts
+    /// and probing
+    fn apply_steering_restrictions(&mut self) {
+        let suspended = self.prober
+            || self.maintenance_until.is_some()
+            || self.blackout == Some(Restriction::Suspended);
+        self.system.set_steering_suspended(suspended);
+        self.system.set_steering_limit(match self.blackout {
+            Some(Restriction::Limited(max_slew)) => Some(max_slew),
+            _ => None,
+        });
+    }
+
     async fn handle_source_network_issue(&mut self, index: SourceId) -> std::io::Result<()> {
         self.system
             .handle_source_remove(index)
@@ -477,39 +1635,77 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
     ) -> Result<SourceId, C::Error> {
         let source_id = params.get_id();
         info!(source_id=?source_id, addr=?params.get_addr(), spawner=?spawner_id, "new source");
-        self.sources.insert(
-            source_id,
-            SourceState {
-                source_id,
-                spawner_id,
-            },
-        );
+        let name = params.get_name();
+        let address = params.get_addr();
+        let enabled =
+            !self.disabled_sources.contains(&name) && !self.disabled_sources.contains(&address);
+        let (enabled_sender, enabled_receiver) = tokio::sync::watch::channel(enabled);
 
-        match params {
+        let task = match params {
             SourceCreateParameters::Ntp(ref mut params) => {
-                let (source, initial_actions) = self.system.create_ntp_source(
+                if let Some(prefix) = self.nat64_prefix {
+                    params.addr = prefix.synthesize(params.addr);
+                }
+                let (mut source, initial_actions) = self.system.create_ntp_source(
                     source_id,
+                    &params.peer,
                     params.config,
                     params.addr,
                     params.protocol_version,
                     params.nts.take(),
+                    params.symmetric_key.clone(),
                 )?;
 
+                if let Some(interval) = self.kod_history.get(&params.addr) {
+                    source.restore_remote_min_poll_interval(*interval);
+                }
+
+                // a source bound to its own interface or address does not use
+                // the interface configured for timestamping
+                let binding = match params.binding {
+                    SocketBinding {
+                        interface: None,
+                        source_address: None,
+                        ..
+                    } => SocketBinding {
+                        interface: self.interface,
+                        ..params.binding
+                    },
+                    binding => binding,
+                };
+
                 SourceTask::spawn(
                     source_id,
                     params.normalized_addr.to_string(),
                     params.addr,
-                    self.interface,
+                    binding,
                     self.clock.clone(),
                     self.timestamp_mode,
+                    StreamClient::new(
+                        binding.transport,
+                        params.normalized_addr.server_name.clone(),
+                    ),
+                    // a socket kept alive for a NAT mapping is not shared
+                    match binding {
+                        SocketBinding {
+                            transport: Transport::Udp,
+                            nat_keepalive: None,
+                            ..
+                        } => self
+                            .shared_sockets
+                            .client(params.addr, binding, self.clock.clone()),
+                        _ => None,
+                    },
+                    self.keep_sockets,
                     SourceChannels {
                         msg_for_system_sender: self.msg_for_system_tx.clone(),
                         system_update_receiver: self.system_update_sender.subscribe(),
-                        source_snapshots: self.source_snapshots.clone(),
+                        observations: self.observations.clone(),
                     },
+                    enabled_receiver,
                     source,
                     initial_actions,
-                );
+                )
             }
             SourceCreateParameters::Sock(ref params) => {
                 let source = self.system.create_sock_source(
@@ -524,10 +1720,28 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
                     SourceChannels {
                         msg_for_system_sender: self.msg_for_system_tx.clone(),
                         system_update_receiver: self.system_update_sender.subscribe(),
-                        source_snapshots: self.source_snapshots.clone(),
+                        observations: self.observations.clone(),
                     },
                     source,
-                );
+                )
+            }
+            SourceCreateParameters::File(ref params) => {
+                let source = self.system.create_sock_source(
+                    source_id,
+                    params.config,
+                    params.noise_estimate,
+                )?;
+                FileSourceTask::spawn(
+                    source_id,
+                    params.path.clone(),
+                    self.clock.clone(),
+                    SourceChannels {
+                        msg_for_system_sender: self.msg_for_system_tx.clone(),
+                        system_update_receiver: self.system_update_sender.subscribe(),
+                        observations: self.observations.clone(),
+                    },
+                    source,
+                )
             }
             #[cfg(feature = "pps")]
             SourceCreateParameters::Pps(ref params) => {
@@ -544,13 +1758,26 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
                     SourceChannels {
                         msg_for_system_sender: self.msg_for_system_tx.clone(),
                         system_update_receiver: self.system_update_sender.subscribe(),
-                        source_snapshots: self.source_snapshots.clone(),
+                        observations: self.observations.clone(),
                     },
                     source,
-                );
+                )
             }
         };
 
+        self.sources.insert(
+            source_id,
+            SourceState {
+                source_id,
+                spawner_id,
+                reachable: false,
+                name,
+                address,
+                enabled: enabled_sender,
+                task: task.abort_handle(),
+            },
+        );
+
         // Try and find a related spawner and notify that spawner.
         // This makes sure that the spawner that initially sent the create event
         // is now aware that the source was added to the system.
@@ -565,40 +1792,84 @@ impl<C: NtpClock + Sync, Controller: TimeSyncController<Clock = C, SourceId = So
     }
 
     async fn handle_spawn_event(&mut self, event: SpawnEvent) -> Result<(), C::Error> {
+        if !self.spawners.iter().any(|s| s.id == event.id) {
+            // the spawner was stopped on a configuration reload
+            debug!(spawner=?event.id, "ignoring source from removed spawner");
+            return Ok(());
+        }
+
         match event.action {
             SpawnAction::Create(params) => {
                 self.create_source(event.id, params).await?;
             }
+            SpawnAction::Failed(failure) => {
+                self.observations
+                    .write()
+                    .expect("Unexpected poisoned mutex")
+                    .set_source_failure(event.id, Some(failure));
+            }
         }
         Ok(())
     }
 
     async fn add_server(&mut self, config: ServerConfig) {
         let stats = ServerStats::default();
+        let census = config
+            .client_census
+            .then(|| Census::new(config.census_anonymization));
         self.servers.push(ServerData {
             stats: stats.clone(),
+            census: census.clone(),
             config: config.clone(),
         });
-        ServerTask::spawn(
+        let task = ServerTask::spawn(
             config,
             stats,
+            census,
             self.system_snapshot_sender.subscribe(),
             self.keyset.clone(),
+            self.symmetric_keys.clone(),
             self.clock.clone(),
             NETWORK_WAIT_PERIOD,
         );
+        self.server_tasks.push(task.abort_handle());
         let _ = self.server_data_sender.send(self.servers.clone());
     }
 }
 
+// The tasks of spawners, sources and servers would otherwise keep running
+// when the system task is aborted, as happens when an embedding application
+// shuts the daemon down.
+impl<C: NtpClock, Controller: TimeSyncController<SourceId = SourceId, Clock = C>, T: Wait> Drop
+    for SystemTask<C, Controller, T>
+{
+    fn drop(&mut self) {
+        for spawner in &self.spawners {
+            spawner.task.abort();
+        }
+        for source in self.sources.values() {
+            source.task.abort();
+        }
+        for task in &self.server_tasks {
+            task.abort();
+        }
+    }
+}
+
 #[derive(Debug)]
 struct SourceState {
     spawner_id: SpawnerId,
     source_id: SourceId,
+    reachable: bool,
+    name: String,
+    address: String,
+    enabled: tokio::sync::watch::Sender<bool>,
+    task: tokio::task::AbortHandle,
 }
 
 #[derive(Debug, Clone)]
 pub struct ServerData {
     pub stats: ServerStats,
+    pub census: Option<Census>,
     pub config: ServerConfig,
 }
@@ -1,3 +1,5 @@
+use std::net::{IpAddr, SocketAddr};
+
 use ntp_proto::NtpTimestamp;
 
 // Epoch offset between NTP and UNIX timescales
@@ -16,3 +18,28 @@ pub(crate) fn convert_clock_timestamp(ts: clock_steering::Timestamp) -> NtpTimes
         ts.nanos,
     )
 }
+
+/// Replace an IPv4-mapped IPv6 address by the IPv4 address itself, so that a
+/// server keeps the same identity however its address was obtained
+pub(crate) fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr.ip().to_canonical() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V4(ip), addr.port()),
+        // keep the scope of link-local addresses
+        IpAddr::V6(_) => addr,
+    }
+}
+
+/// The year, month and day of a day counted from the unix epoch
+// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(crate) fn civil_from_days(days_since_unix_epoch: u64) -> (u64, u64, u64) {
+    let z = days_since_unix_epoch + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
@@ -2,8 +2,8 @@ use std::fmt::Debug;
 use std::{collections::HashMap, marker::PhantomData};
 
 use ntp_proto::{
-    Measurement, NtpClock, NtpDuration, PollInterval, SourceConfig, SourceController,
-    TimeSyncController,
+    Measurement, NtpClock, NtpDuration, PollInterval, SelectionStatus, SourceConfig,
+    SourceController, TimeSyncController,
 };
 use serde::Deserialize;
 
@@ -130,6 +130,8 @@ impl<C: NtpClock> TimeSyncController for SingleShotController<C> {
     type NtpSourceController = SingleShotSourceController<NtpDuration>;
     type OneWaySourceController = SingleShotSourceController<()>;
 
+    const ALGORITHM: &'static str = "single-shot";
+
     fn new(
         clock: Self::Clock,
         synchronization_config: ntp_proto::SynchronizationConfig,
@@ -152,6 +154,7 @@ impl<C: NtpClock> TimeSyncController for SingleShotController<C> {
     fn add_source(
         &mut self,
         _id: Self::SourceId,
+        _peer: &str,
         config: SourceConfig,
     ) -> Self::NtpSourceController {
         SingleShotSourceController::<NtpDuration> {
@@ -187,6 +190,45 @@ impl<C: NtpClock> TimeSyncController for SingleShotController<C> {
         }
     }
 
+    fn set_steering_suspended(&mut self, _suspended: bool) {
+        // force-sync only ever does a single explicit step
+    }
+
+    fn set_steering_limit(&mut self, _max_offset: Option<f64>) {}
+
+    fn step_settings(&self) -> Option<ntp_proto::StepSettings> {
+        // force-sync only ever does a single explicit step
+        None
+    }
+
+    fn set_step_settings(&mut self, _settings: ntp_proto::StepSettings) {}
+
+    fn frequency_estimate(&self) -> Option<f64> {
+        None
+    }
+
+    fn restore_frequency(
+        &mut self,
+        _frequency: f64,
+    ) -> Option<ntp_proto::StateUpdate<Self::SourceId, Self::ControllerMessage>> {
+        // force-sync only ever steps the clock
+        None
+    }
+
+    fn update_config(
+        &mut self,
+        _synchronization_config: ntp_proto::SynchronizationConfig,
+        _algorithm_config: Self::AlgorithmConfig,
+    ) -> ntp_proto::StateUpdate<Self::SourceId, Self::ControllerMessage> {
+        // force-sync runs once, there is no configuration to reload
+        ntp_proto::StateUpdate::default()
+    }
+
+    fn selection_status(&self, _id: Self::SourceId) -> SelectionStatus {
+        // the process exits as soon as enough sources agree, so this is never observed
+        SelectionStatus::NoMeasurement
+    }
+
     fn source_message(
         &mut self,
         id: Self::SourceId,
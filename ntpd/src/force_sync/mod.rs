@@ -10,7 +10,8 @@ use ntp_proto::{NtpClock, NtpDuration};
 use tokio::runtime::Builder;
 
 use crate::daemon::{
-    config, initialize_logging_parse_config, nts_key_provider, spawn, tracing::LogLevel,
+    SpawnOptions, config, initialize_logging_parse_config, nts_key_provider, spawn,
+    tracing::LogLevel,
 };
 
 mod algorithm;
@@ -133,16 +134,20 @@ pub(crate) fn force_sync(config: Option<PathBuf>) -> std::io::Result<ExitCode> {
                 match source {
                     config::NtpSourceConfig::Standard(_)
                     | config::NtpSourceConfig::Nts(_)
-                    | config::NtpSourceConfig::Sock(_) => total_sources += 1,
+                    | config::NtpSourceConfig::Sock(_)
+                    | config::NtpSourceConfig::File(_) => total_sources += 1,
                     #[cfg(feature = "pps")]
                     config::NtpSourceConfig::Pps(_) => {} // PPS sources don't count
                     config::NtpSourceConfig::Pool(cfg) => total_sources += cfg.first.count,
                     config::NtpSourceConfig::NtsPool(cfg) => total_sources += cfg.first.count,
+                    config::NtpSourceConfig::Service(cfg) => total_sources += cfg.first.count,
                 }
             }
 
+            let symmetric_keys = config.keyset.symmetric_keys(&config.crypto_policy)?;
+
             // We will need to have a keyset for the daemon
-            let keyset = nts_key_provider::spawn(config.keyset).await;
+            let (keyset, _key_rotation) = nts_key_provider::spawn(config.keyset).await;
 
             #[cfg(feature = "hardware-timestamping")]
             let clock_config = config.clock;
@@ -151,16 +156,33 @@ pub(crate) fn force_sync(config: Option<PathBuf>) -> std::io::Result<ExitCode> {
             let clock_config = config::ClockConfig::default();
 
             ::tracing::debug!("Configuration loaded, spawning daemon jobs");
+            // A single sync keeps how sources are polled, but should not
+            // touch the drift file or any other state of the daemon, and
+            // blackouts only concern the daemon steering the clock
+            let synchronization = config::DaemonSynchronizationConfig {
+                synchronization_base: config.synchronization.synchronization_base,
+                shared_sockets: config.synchronization.shared_sockets,
+                keep_sockets: config.synchronization.keep_sockets,
+                nat64_prefix: config.synchronization.nat64_prefix,
+                ..Default::default()
+            };
             let (main_loop_handle, _) = spawn::<SingleShotController<_>>(
-                config.synchronization.synchronization_base,
+                None,
+                &synchronization,
                 SingleShotControllerConfig {
                     expected_sources: total_sources,
                 },
-                config.source_defaults,
-                clock_config,
-                &config.sources,
-                &[], // No serving when operating in force sync mode
-                keyset.clone(),
+                SpawnOptions {
+                    source_defaults: config.source_defaults,
+                    clock: clock_config,
+                    sources: &config.sources,
+                    servers: &[], // No serving when operating in force sync mode
+                    keyset: keyset.clone(),
+                    symmetric_keys: std::sync::Arc::new(symmetric_keys),
+                    crypto_policy: config.crypto_policy,
+                    fleet_sources: tokio::sync::watch::channel(vec![]).1, // Fleet sources are only fetched by the daemon
+                    scheduling: &Default::default(),
+                },
             )
             .await?;
 
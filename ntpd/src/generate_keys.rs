@@ -0,0 +1,167 @@
+//! Generation of a private certificate authority and server certificate for NTS.
+
+use std::{
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use rcgen::{
+    BasicConstraints, CertificateParams, CertifiedIssuer, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+
+/// How long the generated certificates are valid, about five years
+const VALIDITY: Duration = Duration::from_secs(1825 * 86400);
+
+// Allow for some error in the clocks of the machines using the certificates
+const BACKDATE: Duration = Duration::from_secs(86400);
+
+/// Paths of the files written by [`generate`]
+#[derive(Debug)]
+pub struct GeneratedFiles {
+    pub ca_certificate: PathBuf,
+    pub ca_key: PathBuf,
+    pub certificate_chain: PathBuf,
+    pub private_key: PathBuf,
+}
+
+/// Generate a CA and a certificate for `hostname` signed by it, and write
+/// them to `directory`. Existing files are never overwritten.
+pub fn generate(hostname: &str, directory: &Path) -> std::io::Result<GeneratedFiles> {
+    let now = SystemTime::now();
+
+    let ca_key = KeyPair::generate().map_err(std::io::Error::other)?;
+    let ca_key_pem = ca_key.serialize_pem();
+    let mut ca_params = params(&format!("ntpd-rs private CA for {hostname}"), now);
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let ca = CertifiedIssuer::self_signed(ca_params, ca_key).map_err(std::io::Error::other)?;
+
+    let server_key = KeyPair::generate().map_err(std::io::Error::other)?;
+    let mut server_params = params(hostname, now);
+    // The subject alternative name is an IP address when the hostname is one
+    server_params.subject_alt_names = CertificateParams::new(vec![hostname.to_owned()])
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+        .subject_alt_names;
+    server_params.is_ca = IsCa::ExplicitNoCa;
+    server_params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let server = server_params
+        .signed_by(&server_key, &ca)
+        .map_err(std::io::Error::other)?;
+
+    let files = GeneratedFiles {
+        ca_certificate: directory.join("ca.pem"),
+        ca_key: directory.join("ca.key"),
+        certificate_chain: directory.join(format!("{hostname}.chain.pem")),
+        private_key: directory.join(format!("{hostname}.key")),
+    };
+
+    write_new(&files.ca_certificate, ca.pem().as_bytes(), 0o644)?;
+    write_new(&files.ca_key, ca_key_pem.as_bytes(), 0o600)?;
+    // The chain starts with the server certificate, followed by the CA
+    let chain = server.pem() + &ca.pem();
+    write_new(&files.certificate_chain, chain.as_bytes(), 0o644)?;
+    write_new(
+        &files.private_key,
+        server_key.serialize_pem().as_bytes(),
+        0o600,
+    )?;
+
+    Ok(files)
+}
+
+/// Parameters of a certificate for `common_name`, valid from shortly before `now`
+fn params(common_name: &str, now: SystemTime) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, common_name);
+    params.not_before = (now - BACKDATE).into();
+    params.not_after = (now + VALIDITY).into();
+    params
+}
+
+fn write_new(path: &Path, contents: &[u8], mode: u32) -> std::io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display())))?;
+    file.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ntp_proto::tls_utils::{self, ClientConnection, RootCertStore, ServerConnection};
+
+    use crate::test::alloc_port;
+
+    use super::*;
+
+    #[test]
+    fn test_generated_certificates_handshake() {
+        // be careful with copying: tests run concurrently and should use a unique directory!
+        let directory = std::env::temp_dir().join(format!("ntp-test-keys-{}", alloc_port()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let files = generate("ntpd-rs.test", &directory).unwrap();
+        // existing keys are never overwritten
+        assert!(generate("ntpd-rs.test", &directory).is_err());
+
+        let read = |path: &Path| std::io::BufReader::new(std::fs::File::open(path).unwrap());
+        let chain = tls_utils::pemfile::certs(&mut read(&files.certificate_chain))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(chain.len(), 2);
+        let key = tls_utils::pemfile::private_key(&mut read(&files.private_key)).unwrap();
+
+        let mut roots = RootCertStore::empty();
+        for ca in tls_utils::pemfile::certs(&mut read(&files.ca_certificate)) {
+            roots.add(ca.unwrap()).unwrap();
+        }
+
+        let server_config = tls_utils::server_config_builder()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        let client_config = tls_utils::client_config_builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+        let mut client = ClientConnection::new(
+            Arc::new(client_config),
+            tls_utils::ServerName::try_from("ntpd-rs.test").unwrap(),
+        )
+        .unwrap();
+
+        while client.is_handshaking() || server.is_handshaking() {
+            let mut buf = vec![];
+            while client.wants_write() {
+                client.write_tls(&mut buf).unwrap();
+            }
+            if !buf.is_empty() {
+                server.read_tls(&mut buf.as_slice()).unwrap();
+                server.process_new_packets().unwrap();
+            }
+
+            let mut buf = vec![];
+            while server.wants_write() {
+                server.write_tls(&mut buf).unwrap();
+            }
+            if !buf.is_empty() {
+                client.read_tls(&mut buf.as_slice()).unwrap();
+                client.process_new_packets().unwrap();
+            }
+        }
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+//! Rehearsal of a leap second against the configuration of the daemon.
+//!
+//! The clock algorithm is run as configured on simulated sources that announce
+//! a leap second and apply it at midnight, steering a simulated clock that
+//! applies the leap second when it is armed as the kernel would. Nothing is
+//! sent to the running daemon and the system clock is left alone, so this can
+//! be used on a production machine to check what would happen.
+
+use std::{path::PathBuf, process::ExitCode};
+
+use ntp_proto::{
+    KalmanClockController, NtpLeapIndicator, Replay, ReplayEntry, SimulatedClock, Steering,
+    simulate_leap_second,
+};
+
+use crate::daemon::{Config, config::NtpSourceConfig};
+
+/// How long the rehearsal runs before and after the leap second, at the least
+const MARGIN: f64 = 3.0 * 3600.0;
+
+/// What the daemon would have done around the leap second
+#[derive(Debug, Default, PartialEq)]
+struct Outcome {
+    /// Seconds before the leap second at which the clock was armed with it
+    armed_before: Option<f64>,
+    /// Leap seconds applied by the clock, as seconds after the leap second
+    /// and the change they made to the clock
+    applied: Vec<(f64, f64)>,
+    /// Steps of the clock from the leap second on
+    steps: Vec<f64>,
+    /// Largest offset of the sources to the clock from the leap second on
+    max_offset: f64,
+}
+
+fn analyze(trace: &[ReplayEntry], leap: NtpLeapIndicator, leap_at: f64) -> Outcome {
+    let mut outcome = Outcome {
+        armed_before: trace
+            .iter()
+            .find(|entry| entry.leap_status == Some(leap))
+            .map(|entry| leap_at - entry.elapsed),
+        ..Default::default()
+    };
+
+    for entry in trace {
+        for steering in &entry.steering {
+            match *steering {
+                Steering::Leap { elapsed, offset } => {
+                    outcome.applied.push((elapsed - leap_at, offset))
+                }
+                Steering::Step { elapsed, offset } if elapsed >= leap_at => {
+                    outcome.steps.push(offset)
+                }
+                _ => {}
+            }
+        }
+        if entry.elapsed >= leap_at {
+            outcome.max_offset = outcome.max_offset.max(entry.offset.abs());
+        }
+    }
+
+    outcome
+}
+
+/// Run the clock algorithm configured in `config` through `leap`
+fn rehearse_config(config: &Config, leap: NtpLeapIndicator) -> Outcome {
+    let configured: usize = config
+        .sources
+        .iter()
+        .map(|source| match source {
+            NtpSourceConfig::Standard(_)
+            | NtpSourceConfig::Nts(_)
+            | NtpSourceConfig::Sock(_)
+            | NtpSourceConfig::File(_) => 1,
+            #[cfg(feature = "pps")]
+            NtpSourceConfig::Pps(_) => 0,
+            NtpSourceConfig::Pool(cfg) => cfg.first.count,
+            NtpSourceConfig::NtsPool(cfg) => cfg.first.count,
+            NtpSourceConfig::Service(cfg) => cfg.first.count,
+        })
+        .sum();
+    let sources = configured
+        .max(
+            config
+                .synchronization
+                .synchronization_base
+                .minimum_agreeing_sources,
+        )
+        .max(1);
+    let poll_interval = config
+        .source_defaults
+        .poll_interval_limits
+        .min
+        .as_duration()
+        .to_seconds();
+    // servers start smearing before the leap second, which should be covered
+    let before = config
+        .servers
+        .iter()
+        .filter_map(|server| server.leap_smear_window)
+        .map(|window| window.as_secs_f64() + 3600.0)
+        .fold(MARGIN, f64::max);
+
+    let (measurements, leap_at) =
+        simulate_leap_second(leap, sources, poll_interval, before, MARGIN);
+
+    let mut replay = Replay::<KalmanClockController<SimulatedClock, usize>>::new(
+        config.synchronization.synchronization_base,
+        config.synchronization.algorithm,
+        config.source_defaults,
+    );
+    let trace: Vec<_> = measurements
+        .iter()
+        .map(|measurement| replay.measurement(measurement))
+        .collect();
+
+    analyze(&trace, leap, leap_at)
+}
+
+/// Rehearse a leap second with the configuration of the daemon, and report
+/// what the daemon would have done
+pub(crate) fn rehearse(
+    config: Option<PathBuf>,
+    leap: NtpLeapIndicator,
+) -> std::io::Result<ExitCode> {
+    let config = match Config::from_args(config, vec![], vec![]) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: Could not load configuration: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let kind = match leap {
+        NtpLeapIndicator::Leap59 => "deleted",
+        _ => "inserted",
+    };
+    println!("Rehearsing a {kind} leap second, the clock and the daemon are left alone");
+
+    let outcome = rehearse_config(&config, leap);
+
+    match outcome.armed_before {
+        Some(before) => println!("  the clock is armed {before:.0}s before the leap second"),
+        None => println!("  the clock is never armed, the leap second is missed"),
+    }
+    for (after, offset) in &outcome.applied {
+        println!("  the kernel applies the leap second {after:.0}s after it is due ({offset:+}s)");
+    }
+    for offset in &outcome.steps {
+        println!("  the clock is stepped by {offset:+.6}s after the leap second");
+    }
+    println!(
+        "  after the leap second the clock stays within {:.3}ms of the sources",
+        outcome.max_offset * 1e3
+    );
+    for server in &config.servers {
+        if let Some(window) = server.leap_smear_window {
+            println!(
+                "  the server on {} smears the leap second over the {}s before it",
+                server.listen,
+                window.as_secs()
+            );
+        }
+    }
+
+    if outcome.armed_before.is_some() && outcome.applied.len() == 1 && outcome.steps.is_empty() {
+        println!("The leap second is handled without disruption");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("The leap second is not handled as it should be");
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rehearse_deleted_leap_second() {
+        let outcome = rehearse_config(&Config::default(), NtpLeapIndicator::Leap59);
+
+        assert!(outcome.armed_before.is_some_and(|before| before > 3600.0));
+        let [(after, offset)] = outcome.applied[..] else {
+            panic!("leap second applied {} times", outcome.applied.len());
+        };
+        assert!((0.0..60.0).contains(&after));
+        assert_eq!(offset, 1.0);
+        assert!(outcome.steps.is_empty());
+        assert!(outcome.max_offset < 1e-3);
+    }
+
+    #[test]
+    fn test_rehearse_inserted_leap_second() {
+        let outcome = rehearse_config(&Config::default(), NtpLeapIndicator::Leap61);
+
+        assert!(outcome.armed_before.is_some());
+        assert_eq!(
+            outcome
+                .applied
+                .iter()
+                .map(|(_, offset)| *offset)
+                .collect::<Vec<_>>(),
+            vec![-1.0]
+        );
+        assert!(outcome.steps.is_empty());
+    }
+
+    #[test]
+    fn test_missed_leap_second() {
+        let (measurements, leap_at) =
+            simulate_leap_second(NtpLeapIndicator::Leap59, 3, 16.0, MARGIN, MARGIN);
+        let config = Config::default();
+        let mut replay = Replay::<KalmanClockController<SimulatedClock, usize>>::new(
+            config.synchronization.synchronization_base,
+            config.synchronization.algorithm,
+            config.source_defaults,
+        );
+        replay.ignore_leap_seconds();
+        let trace: Vec<_> = measurements
+            .iter()
+            .map(|measurement| replay.measurement(measurement))
+            .collect();
+
+        let outcome = analyze(&trace, NtpLeapIndicator::Leap59, leap_at);
+        assert!(outcome.applied.is_empty());
+        assert!(outcome.max_offset > 0.5);
+    }
+}
@@ -3,11 +3,23 @@
 mod ctl;
 mod daemon;
 mod force_sync;
+#[cfg(feature = "nts-server")]
+mod generate_keys;
+mod leap_rehearsal;
 mod metrics;
+mod replay;
+mod test_server;
 
 pub use ctl::main as ctl_main;
 pub use daemon::main as daemon_main;
+pub use daemon::{
+    Config, ObservableState,
+    handle::{Handle, spawn},
+};
+#[cfg(feature = "metrics-exporter")]
 pub use metrics::exporter::main as metrics_exporter_main;
+pub use replay::main as replay_main;
+pub use test_server::main as test_server_main;
 
 #[cfg(test)]
 mod test {
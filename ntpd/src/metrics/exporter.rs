@@ -5,13 +5,11 @@ use tokio::net::TcpListener;
 use tokio::runtime::Builder;
 use tracing::{debug, error, trace, warn};
 
-use std::{
-    fmt::Write,
-    path::{Path, PathBuf},
-    sync::Arc,
-};
+use std::{fmt::Write, path::PathBuf, sync::Arc};
 
-use crate::daemon::{ObservableState, config::CliArg, initialize_logging_parse_config};
+use crate::daemon::{
+    ObservableState, config::CliArg, initialize_logging_parse_config, sockets::SocketLocation,
+};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -132,7 +130,10 @@ fn run(options: NtpMetricsExporterOptions) -> Result<(), Box<dyn std::error::Err
         let timeout = std::time::Duration::from_millis(1000);
 
         let observation_socket_path = match config.observability.observation_path {
-            Some(path) => Arc::new(path),
+            Some(path) => Arc::new(SocketLocation::new(
+                path,
+                config.observability.observation_address,
+            )),
             None => {
                 eprintln!("An observation socket path must be configured using the observation-path option in the [observability] section of the configuration");
                 std::process::exit(1);
@@ -216,7 +217,7 @@ fn run(options: NtpMetricsExporterOptions) -> Result<(), Box<dyn std::error::Err
 
 async fn handle_connection(
     stream: &mut (impl tokio::io::AsyncWrite + tokio::io::AsyncRead + Unpin),
-    observation_socket_path: &Path,
+    observation_socket_path: &SocketLocation,
 ) -> std::io::Result<()> {
     // Wait until a request was sent, dropping the bytes read when this scope ends
     // to ensure we don't accidentally use them afterwards
@@ -272,8 +273,11 @@ async fn handle_connection(
     Ok(())
 }
 
-async fn handler(buf: &mut String, observation_socket_path: &Path) -> std::io::Result<()> {
-    let mut stream = tokio::net::UnixStream::connect(observation_socket_path).await?;
+async fn handler(
+    buf: &mut String,
+    observation_socket_path: &SocketLocation,
+) -> std::io::Result<()> {
+    let mut stream = observation_socket_path.connect().await?;
     let mut msg = Vec::with_capacity(16 * 1024);
     let observable_state: ObservableState =
         crate::daemon::sockets::read_json(&mut stream, &mut msg).await?;
@@ -298,7 +302,7 @@ fn format_response(buf: &mut String, state: &ObservableState) -> std::fmt::Resul
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use std::{io::Cursor, path::Path};
 
     use super::*;
 
@@ -318,7 +322,11 @@ mod tests {
     async fn deny_non_get_request() {
         let mut example = b"POST / HTTP/1.1\r\n\r\n".to_vec();
         let mut cursor = Cursor::new(&mut example);
-        let res = handle_connection(&mut cursor, Path::new("/tmp/ntpd-rs.sock")).await;
+        let res = handle_connection(
+            &mut cursor,
+            &SocketLocation::Unix("/tmp/ntpd-rs.sock".into()),
+        )
+        .await;
         let err = res.unwrap_err();
         assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
         assert_eq!(err.to_string(), "Expected GET request");
@@ -328,7 +336,11 @@ mod tests {
     async fn does_not_accept_large_requests() {
         let mut example = [1u8; 4096].to_vec();
         let mut cursor = Cursor::new(&mut example);
-        let res = handle_connection(&mut cursor, Path::new("/tmp/ntpd-rs.sock")).await;
+        let res = handle_connection(
+            &mut cursor,
+            &SocketLocation::Unix("/tmp/ntpd-rs.sock".into()),
+        )
+        .await;
         let err = res.unwrap_err();
         assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
         assert_eq!(err.to_string(), "Request too long");
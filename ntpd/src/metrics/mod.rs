@@ -1,3 +1,4 @@
+#[cfg(feature = "metrics-exporter")]
 pub mod exporter;
 
 use ntp_proto::PollIntervalLimits;
@@ -43,14 +44,21 @@ impl MetricType {
     }
 }
 
+/// Output for metrics, with the labels that are added to every measurement
+struct MetricsWriter<'a, W> {
+    w: &'a mut W,
+    common_labels: Vec<(&'static str, String)>,
+}
+
 fn format_metric<T: std::fmt::Display>(
-    w: &mut impl std::fmt::Write,
+    out: &mut MetricsWriter<'_, impl std::fmt::Write>,
     name: &str,
     help: &str,
     metric_type: MetricType,
     unit: Option<Unit>,
     measurements: Vec<Measurement<T>>,
 ) -> std::fmt::Result {
+    let w = &mut *out.w;
     let name = if let Some(unit) = unit {
         format!("{}_{}", name, unit.as_str())
     } else {
@@ -71,16 +79,21 @@ fn format_metric<T: std::fmt::Display>(
     // write all the measurements
     for measurement in measurements {
         w.write_str(&name)?;
-        if !measurement.labels.is_empty() {
+        let labels: Vec<_> = out
+            .common_labels
+            .iter()
+            .chain(&measurement.labels)
+            .collect();
+        if !labels.is_empty() {
             w.write_str("{")?;
 
-            for (offset, (label, value)) in measurement.labels.iter().enumerate() {
+            for (offset, (label, value)) in labels.iter().enumerate() {
                 let value = value
                     .replace('\\', "\\\\")
                     .replace('"', "\\\"")
                     .replace('\n', "\\n");
                 write!(w, "{label}=\"{value}\"")?;
-                if offset < measurement.labels.len() - 1 {
+                if offset < labels.len() - 1 {
                     w.write_str(",")?;
                 }
             }
@@ -131,7 +144,10 @@ macro_rules! collect_servers {
     ($from: expr, |$ident: ident| $value: expr $(,)?) => {{
         let mut data = vec![];
         for $ident in &$from.servers {
-            let labels = vec![("listen_address", format!("{}", $ident.address))];
+            let mut labels = vec![("listen_address", format!("{}", $ident.address))];
+            if let Some(label) = &$ident.label {
+                labels.push(("listener", label.clone()));
+            }
             let value = $value;
             data.push(Measurement { labels, value })
         }
@@ -140,6 +156,16 @@ macro_rules! collect_servers {
 }
 
 pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> std::fmt::Result {
+    let w = &mut MetricsWriter {
+        w,
+        common_labels: state
+            .program
+            .instance_name
+            .iter()
+            .map(|name| ("instance_name", name.clone()))
+            .collect(),
+    };
+
     format_metric(
         w,
         "ntp_uptime",
@@ -151,11 +177,26 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
                 ("version", state.program.version.clone()),
                 ("build_commit", state.program.build_commit.clone()),
                 ("build_commit_date", state.program.build_commit_date.clone()),
+                ("features", state.program.features.join(",")),
+                ("algorithm", state.program.algorithm.clone()),
+                (
+                    "config_hash",
+                    state.program.config_hash.clone().unwrap_or_default(),
+                ),
             ],
             value: state.program.uptime_seconds,
         }],
     )?;
 
+    format_metric(
+        w,
+        "ntp_observation_epoch",
+        "Epoch of the daemon state these metrics were taken from",
+        MetricType::Gauge,
+        None,
+        Measurement::simple(state.epoch),
+    )?;
+
     format_metric(
         w,
         "ntp_system_poll_interval",
@@ -231,6 +272,24 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         ),
     )?;
 
+    format_metric(
+        w,
+        "ntp_system_holdover",
+        "Indicates that all sources were lost and the clock runs on its last frequency estimate",
+        MetricType::Gauge,
+        None,
+        Measurement::simple(state.system.holdover as i64),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_system_clock_class",
+        "Quality of our time as a PTP clock class, which degrades during long holdover",
+        MetricType::Gauge,
+        None,
+        Measurement::simple(state.system.clock_quality(state.program.now).clock_class()),
+    )?;
+
     format_metric(
         w,
         "ntp_system_stratum",
@@ -240,6 +299,15 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         Measurement::simple(state.system.stratum),
     )?;
 
+    format_metric(
+        w,
+        "ntp_system_source_diversity",
+        "How independent the sources steering the clock are, from 0 to 1",
+        MetricType::Gauge,
+        None,
+        Measurement::simple(state.diversity.score),
+    )?;
+
     format_metric(
         w,
         "ntp_source_poll_interval",
@@ -312,6 +380,42 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_sources!(state, |p| p.timedata.remote_uncertainty.to_seconds()),
     )?;
 
+    format_metric(
+        w,
+        "ntp_source_path_changes_total",
+        "Number of detected changes of the network path to the source",
+        MetricType::Counter,
+        None,
+        collect_sources!(state, |p| p.timedata.path_changes),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_spikes_total",
+        "Number of measurements of the source rejected as spikes in the offset",
+        MetricType::Counter,
+        None,
+        collect_sources!(state, |p| p.timedata.spikes),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_offset_correction",
+        "Configured correction added to the measured offsets of the source",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        collect_sources!(state, |p| p.timedata.offset_correction.to_seconds()),
+    )?;
+
+    format_metric(
+        w,
+        "ntp_source_delay_correction",
+        "Configured correction removed from the measured delays of the source",
+        MetricType::Gauge,
+        Some(Unit::Seconds),
+        collect_sources!(state, |p| p.timedata.delay_correction.to_seconds()),
+    )?;
+
     format_metric(
         w,
         "ntp_server_received_packets_total",
@@ -357,6 +461,15 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_servers!(state, |s| s.stats.rate_limited_packets.get()),
     )?;
 
+    format_metric(
+        w,
+        "ntp_server_rate_limit_kod_packets_total",
+        "Number of rate limited packets answered with a RATE kiss-o'-death",
+        MetricType::Counter,
+        None,
+        collect_servers!(state, |s| s.stats.rate_limit_kod_packets.get()),
+    )?;
+
     format_metric(
         w,
         "ntp_server_response_send_errors_total",
@@ -411,6 +524,40 @@ pub fn format_state(w: &mut impl std::fmt::Write, state: &ObservableState) -> st
         collect_servers!(state, |s| s.stats.nts_nak_packets.get()),
     )?;
 
-    w.write_str("# EOF\n")?;
+    w.w.write_str("# EOF\n")?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::{ObservableState, observer::ProgramData};
+
+    use super::*;
+
+    #[test]
+    fn instance_name_labels_all_metrics() {
+        let state = ObservableState {
+            program: ProgramData {
+                instance_name: Some("tenant-a".into()),
+                ..Default::default()
+            },
+            system: Default::default(),
+            sources: vec![],
+            servers: vec![],
+            epoch: 0,
+            disabled_sources: vec![],
+            leap_seconds: None,
+            diversity: Default::default(),
+            failed_sources: vec![],
+        };
+
+        let mut output = String::new();
+        format_state(&mut output, &state).unwrap();
+
+        let measurements: Vec<_> = output.lines().filter(|l| !l.starts_with('#')).collect();
+        assert!(!measurements.is_empty());
+        for line in measurements {
+            assert!(line.contains("instance_name=\"tenant-a\""), "{line}");
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use ntp_proto::{KalmanClockController, Replay, SimulatedClock};
+
+use crate::daemon::{Config, config::CliArg, recorder};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const USAGE_MSG: &str = "\
+usage: ntp-replay [-c PATH] [--ignore-leap-seconds] RECORDING
+       ntp-replay -h | ntp-replay -v";
+
+const DESCRIPTOR: &str = "ntp-replay - replay recorded measurements through the clock algorithm";
+
+const HELP_MSG: &str = "Options:
+  -c, --config=CONFIG                  take the synchronization settings from this
+                                       ntpd-rs configuration file (default: built-in
+                                       defaults)
+  -h, --help                           display this help text
+      --ignore-leap-seconds            do not apply leap seconds announced by the
+                                       sources to the simulated clock, as if the
+                                       kernel was not told about them
+  -v, --version                        display version information";
+
+pub fn long_help_message() -> String {
+    format!("{DESCRIPTOR}\n\n{USAGE_MSG}\n\n{HELP_MSG}")
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+enum ReplayAction {
+    #[default]
+    Help,
+    Version,
+    Replay,
+}
+
+#[derive(Debug, Default)]
+struct NtpReplayOptions {
+    config: Option<PathBuf>,
+    recording: Option<PathBuf>,
+    ignore_leap_seconds: bool,
+    help: bool,
+    version: bool,
+    action: ReplayAction,
+}
+
+impl NtpReplayOptions {
+    const TAKES_ARGUMENT: &'static [&'static str] = &["--config"];
+    const TAKES_ARGUMENT_SHORT: &'static [char] = &['c'];
+
+    /// parse an iterator over command line arguments
+    fn try_parse_from<I, T>(iter: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str> + Clone,
+    {
+        let mut options = NtpReplayOptions::default();
+
+        let arg_iter = CliArg::normalize_arguments(
+            Self::TAKES_ARGUMENT,
+            Self::TAKES_ARGUMENT_SHORT,
+            iter.into_iter().map(|x| x.as_ref().to_string()),
+        )?;
+
+        for arg in arg_iter {
+            match arg {
+                CliArg::Flag(flag) => match flag.as_str() {
+                    "-h" | "--help" => {
+                        options.help = true;
+                    }
+                    "-v" | "--version" => {
+                        options.version = true;
+                    }
+                    "--ignore-leap-seconds" => {
+                        options.ignore_leap_seconds = true;
+                    }
+                    option => {
+                        Err(format!("invalid option provided: {option}"))?;
+                    }
+                },
+                CliArg::Argument(option, value) => match option.as_str() {
+                    "-c" | "--config" => {
+                        options.config = Some(PathBuf::from(value));
+                    }
+                    option => {
+                        Err(format!("invalid option provided: {option}"))?;
+                    }
+                },
+                CliArg::Rest(rest) => {
+                    let [recording] = rest.as_slice() else {
+                        Err("expected a single recording")?
+                    };
+                    options.recording = Some(PathBuf::from(recording));
+                }
+            }
+        }
+
+        options.resolve_action();
+
+        Ok(options)
+    }
+
+    /// from the arguments resolve which action should be performed
+    fn resolve_action(&mut self) {
+        if self.help {
+            self.action = ReplayAction::Help;
+        } else if self.version {
+            self.action = ReplayAction::Version;
+        } else if self.recording.is_some() {
+            self.action = ReplayAction::Replay;
+        } else {
+            self.action = ReplayAction::Help;
+        }
+    }
+}
+
+pub fn main() -> std::io::Result<ExitCode> {
+    let options = match NtpReplayOptions::try_parse_from(std::env::args()) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{msg}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    match options.action {
+        ReplayAction::Help => {
+            println!("{}", long_help_message());
+            Ok(ExitCode::SUCCESS)
+        }
+        ReplayAction::Version => {
+            eprintln!("ntp-replay {VERSION}");
+            Ok(ExitCode::SUCCESS)
+        }
+        ReplayAction::Replay => replay(
+            options.config.as_deref(),
+            &options.recording.unwrap_or_default(),
+            options.ignore_leap_seconds,
+        ),
+    }
+}
+
+/// Replay the recording and write what the algorithm made of every
+/// measurement to stdout, one JSON object per line
+fn replay(
+    config: Option<&Path>,
+    recording: &Path,
+    ignore_leap_seconds: bool,
+) -> std::io::Result<ExitCode> {
+    let config = match config {
+        Some(path) => match Config::from_args(Some(path), vec![], vec![]) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error: Could not load configuration: {e}");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+        None => Config::default(),
+    };
+
+    let measurements = match recorder::load(recording) {
+        Ok(measurements) => measurements,
+        Err(e) => {
+            eprintln!("Error: Could not read {}: {e}", recording.display());
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let mut replay = Replay::<KalmanClockController<SimulatedClock, usize>>::new(
+        config.synchronization.synchronization_base,
+        config.synchronization.algorithm,
+        config.source_defaults,
+    );
+    if ignore_leap_seconds {
+        replay.ignore_leap_seconds();
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    for measurement in &measurements {
+        serde_json::to_writer(&mut stdout, &replay.measurement(measurement))?;
+        stdout.write_all(b"\n")?;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINARY: &str = "/usr/bin/ntp-replay";
+
+    #[test]
+    fn cli_replay() {
+        let arguments = &[BINARY, "-c", "ntp.toml", "recording.jsonl"];
+        let options = NtpReplayOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, ReplayAction::Replay);
+        assert_eq!(options.config.as_deref(), Some(Path::new("ntp.toml")));
+        assert_eq!(
+            options.recording.as_deref(),
+            Some(Path::new("recording.jsonl"))
+        );
+
+        assert!(!options.ignore_leap_seconds);
+
+        let arguments = &[BINARY, "--ignore-leap-seconds", "recording.jsonl"];
+        let options = NtpReplayOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, ReplayAction::Replay);
+        assert!(options.ignore_leap_seconds);
+
+        let options = NtpReplayOptions::try_parse_from([BINARY]).unwrap();
+        assert_eq!(options.action, ReplayAction::Help);
+
+        let arguments = &[BINARY, "a.jsonl", "b.jsonl"];
+        let err = NtpReplayOptions::try_parse_from(arguments).unwrap_err();
+        assert_eq!(err, "expected a single recording");
+    }
+}
@@ -0,0 +1,679 @@
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
+};
+
+use ntp_proto::{
+    KeySet, NoCipher, NtpClock, NtpDuration, NtpLeapIndicator, NtpPacket, NtpTimestamp, Server,
+    ServerAction, ServerReason, ServerResponse, ServerStatHandler, SystemSnapshot, TimeSnapshot,
+};
+use serde::Deserialize;
+use timestamped_socket::socket::{GeneralTimestampMode, RecvResult, open_ip};
+use tokio::runtime::Builder;
+use tracing::{debug, info, warn};
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::daemon::{
+    clock::NtpClockWrapper,
+    config::{CliArg, KeysetConfig, ServerConfig},
+    nts_key_provider,
+    tracing::{LogLevel, tracing_init},
+    util::convert_net_timestamp,
+};
+#[cfg(feature = "nts-server")]
+use crate::daemon::{
+    config::{CryptoPolicyConfig, NtsKeConfig},
+    keyexchange,
+};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Maximum size of udp packet we handle
+const MAX_PACKET_SIZE: usize = 1024;
+
+// Size of an NTPv3/v4 header, everything after it is extension fields or a MAC
+const HEADER_SIZE: usize = 48;
+
+const USAGE_MSG: &str = "\
+usage: ntp-test-server SCENARIO
+       ntp-test-server -h | ntp-test-server -v";
+
+const DESCRIPTOR: &str = "ntp-test-server - NTP server answering as scripted, for testing clients";
+
+const HELP_MSG: &str = "Options:
+  -h, --help                           display this help text
+  -v, --version                        display version information";
+
+pub fn long_help_message() -> String {
+    format!("{DESCRIPTOR}\n\n{USAGE_MSG}\n\n{HELP_MSG}")
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+enum TestServerAction {
+    #[default]
+    Help,
+    Version,
+    Serve,
+}
+
+#[derive(Debug, Default)]
+struct NtpTestServerOptions {
+    scenario: Option<PathBuf>,
+    help: bool,
+    version: bool,
+    action: TestServerAction,
+}
+
+impl NtpTestServerOptions {
+    const TAKES_ARGUMENT: &'static [&'static str] = &[];
+    const TAKES_ARGUMENT_SHORT: &'static [char] = &[];
+
+    /// parse an iterator over command line arguments
+    fn try_parse_from<I, T>(iter: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str> + Clone,
+    {
+        let mut options = NtpTestServerOptions::default();
+
+        let arg_iter = CliArg::normalize_arguments(
+            Self::TAKES_ARGUMENT,
+            Self::TAKES_ARGUMENT_SHORT,
+            iter.into_iter().map(|x| x.as_ref().to_string()),
+        )?;
+
+        for arg in arg_iter {
+            match arg {
+                CliArg::Flag(flag) => match flag.as_str() {
+                    "-h" | "--help" => {
+                        options.help = true;
+                    }
+                    "-v" | "--version" => {
+                        options.version = true;
+                    }
+                    option => {
+                        Err(format!("invalid option provided: {option}"))?;
+                    }
+                },
+                CliArg::Argument(option, _) => {
+                    Err(format!("invalid option provided: {option}"))?;
+                }
+                CliArg::Rest(rest) => {
+                    let [scenario] = rest.as_slice() else {
+                        Err("expected a single scenario")?
+                    };
+                    options.scenario = Some(PathBuf::from(scenario));
+                }
+            }
+        }
+
+        options.resolve_action();
+
+        Ok(options)
+    }
+
+    /// from the arguments resolve which action should be performed
+    fn resolve_action(&mut self) {
+        if self.help {
+            self.action = TestServerAction::Help;
+        } else if self.version {
+            self.action = TestServerAction::Version;
+        } else if self.scenario.is_some() {
+            self.action = TestServerAction::Serve;
+        } else {
+            self.action = TestServerAction::Help;
+        }
+    }
+}
+
+/// Script for the test server, read from a TOML file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Scenario {
+    #[serde(default = "default_listen")]
+    listen: SocketAddr,
+    /// Start over with the first response once the last one has answered its
+    /// count, instead of answering like the last one from then on
+    #[serde(default)]
+    repeat: bool,
+    #[serde(default, rename = "response")]
+    responses: Vec<ResponseConfig>,
+    #[cfg(feature = "nts-server")]
+    #[serde(default, rename = "nts-ke-server")]
+    nts_ke: Vec<NtsKeConfig>,
+}
+
+fn default_listen() -> SocketAddr {
+    "[::]:123".parse().unwrap()
+}
+
+/// How to answer a number of consecutive requests
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ResponseConfig {
+    /// Number of requests answered this way, forever when not given
+    #[serde(default)]
+    count: Option<u64>,
+    /// Seconds added to the time the server reports
+    #[serde(default)]
+    offset: f64,
+    /// Seconds to wait before sending the response
+    #[serde(default)]
+    delay: f64,
+    #[serde(default = "default_stratum")]
+    stratum: u8,
+    #[serde(default)]
+    leap: Leap,
+    /// Answer with this kiss-o'-death code instead of the time
+    #[serde(default, deserialize_with = "deserialize_kiss_code")]
+    kiss: Option<[u8; 4]>,
+    /// Do not answer at all
+    #[serde(default)]
+    drop: bool,
+    #[serde(default)]
+    broken: Vec<BrokenField>,
+    /// What to do with NTS requests
+    #[serde(default)]
+    nts: Option<NtsBehavior>,
+}
+
+impl Default for ResponseConfig {
+    fn default() -> Self {
+        ResponseConfig {
+            count: None,
+            offset: 0.0,
+            delay: 0.0,
+            stratum: default_stratum(),
+            leap: Leap::default(),
+            kiss: None,
+            drop: false,
+            broken: vec![],
+            nts: None,
+        }
+    }
+}
+
+fn default_stratum() -> u8 {
+    1
+}
+
+fn deserialize_kiss_code<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<[u8; 4]>, D::Error> {
+    let code = String::deserialize(deserializer)?;
+    let bytes: [u8; 4] = code.as_bytes().try_into().map_err(|_| {
+        serde::de::Error::custom(format!("kiss code {code} must be four ASCII characters"))
+    })?;
+    if !bytes.is_ascii() {
+        return Err(serde::de::Error::custom(format!(
+            "kiss code {code} must be four ASCII characters"
+        )));
+    }
+    Ok(Some(bytes))
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Leap {
+    #[default]
+    None,
+    Insert,
+    Delete,
+    Unsynchronized,
+}
+
+impl From<Leap> for NtpLeapIndicator {
+    fn from(value: Leap) -> Self {
+        match value {
+            Leap::None => NtpLeapIndicator::NoWarning,
+            Leap::Insert => NtpLeapIndicator::Leap61,
+            Leap::Delete => NtpLeapIndicator::Leap59,
+            Leap::Unsynchronized => NtpLeapIndicator::Unsynchronized,
+        }
+    }
+}
+
+/// Fields of the NTPv3/v4 header to damage in the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum BrokenField {
+    /// Origin timestamp that does not match the request
+    OriginTimestamp,
+    /// Transmit timestamp of zero
+    TransmitTimestamp,
+    /// Client instead of server mode
+    Mode,
+    /// Unsupported version number
+    Version,
+    /// Largest possible root delay and dispersion
+    RootDistance,
+    /// Response cut off in the middle of the header
+    Truncated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum NtsBehavior {
+    /// Answer with an NTS negative acknowledgment (NTSN kiss-o'-death)
+    Nak,
+    /// Damage the authenticator, so the response does not verify
+    CorruptAuthenticator,
+    /// Leave out all extension fields, sending an unauthenticated response
+    Unauthenticated,
+}
+
+/// Hands out the configured response for each request in turn
+#[derive(Debug)]
+struct Script {
+    responses: Vec<ResponseConfig>,
+    repeat: bool,
+    current: usize,
+    answered: u64,
+}
+
+impl Script {
+    fn new(mut responses: Vec<ResponseConfig>, repeat: bool) -> Self {
+        if responses.is_empty() {
+            responses.push(ResponseConfig::default());
+        }
+
+        Script {
+            responses,
+            repeat,
+            current: 0,
+            answered: 0,
+        }
+    }
+
+    /// The response for the next request, with its index in the scenario
+    fn next_response(&mut self) -> (usize, &ResponseConfig) {
+        if self.responses[self.current]
+            .count
+            .is_some_and(|count| self.answered >= count)
+        {
+            if self.current + 1 < self.responses.len() {
+                self.current += 1;
+                self.answered = 0;
+            } else if self.repeat {
+                self.current = 0;
+                self.answered = 0;
+            }
+        }
+
+        self.answered += 1;
+        (self.current, &self.responses[self.current])
+    }
+}
+
+/// Clock that is off by the configured offset
+#[derive(Debug, Clone)]
+struct OffsetClock<C> {
+    inner: C,
+    offset: NtpDuration,
+}
+
+impl<C: NtpClock> NtpClock for OffsetClock<C> {
+    type Error = C::Error;
+
+    fn now(&self) -> Result<NtpTimestamp, Self::Error> {
+        Ok(self.inner.now()? + self.offset)
+    }
+
+    fn set_frequency(&self, freq: f64) -> Result<NtpTimestamp, Self::Error> {
+        self.inner.set_frequency(freq)
+    }
+
+    fn get_frequency(&self) -> Result<f64, Self::Error> {
+        self.inner.get_frequency()
+    }
+
+    fn step_clock(&self, offset: NtpDuration) -> Result<NtpTimestamp, Self::Error> {
+        self.inner.step_clock(offset)
+    }
+
+    fn disable_ntp_algorithm(&self) -> Result<(), Self::Error> {
+        self.inner.disable_ntp_algorithm()
+    }
+
+    fn error_estimate_update(
+        &self,
+        est_error: NtpDuration,
+        max_error: NtpDuration,
+    ) -> Result<(), Self::Error> {
+        self.inner.error_estimate_update(est_error, max_error)
+    }
+
+    fn status_update(&self, leap_status: NtpLeapIndicator) -> Result<(), Self::Error> {
+        self.inner.status_update(leap_status)
+    }
+}
+
+struct IgnoreStats;
+
+impl ServerStatHandler for IgnoreStats {
+    fn register(
+        &mut self,
+        _version: u8,
+        _nts: bool,
+        _reason: ServerReason,
+        _response: ServerResponse,
+    ) {
+    }
+}
+
+impl ResponseConfig {
+    fn server(&self, keyset: Arc<KeySet>) -> Server<OffsetClock<NtpClockWrapper>> {
+        let system = SystemSnapshot {
+            stratum: self.stratum,
+            time_snapshot: TimeSnapshot {
+                leap_indicator: self.leap.into(),
+                ..TimeSnapshot::default()
+            },
+            ..SystemSnapshot::default()
+        };
+        let clock = OffsetClock {
+            inner: NtpClockWrapper::default(),
+            offset: NtpDuration::from_seconds(self.offset),
+        };
+        // the default configuration answers everyone without rate limiting
+        let config = ServerConfig::from(default_listen()).into();
+        Server::new(config, clock, system, keyset)
+    }
+
+    /// Build the response to a request, or `None` when it is not answered
+    fn respond(
+        &self,
+        server: &mut Server<OffsetClock<NtpClockWrapper>>,
+        keyset: &KeySet,
+        client: SocketAddr,
+        recv_timestamp: NtpTimestamp,
+        request: &[u8],
+    ) -> Option<Vec<u8>> {
+        if self.drop {
+            return None;
+        }
+
+        let is_nts = matches!(NtpPacket::deserialize(request, keyset), Ok((_, Some(_))));
+
+        let mut response = if is_nts && self.nts == Some(NtsBehavior::Nak) {
+            let (packet, _) = NtpPacket::deserialize(request, keyset).ok()?;
+            let mut buffer = [0u8; MAX_PACKET_SIZE];
+            let mut cursor = std::io::Cursor::new(buffer.as_mut_slice());
+            NtpPacket::nts_nak_response(packet)
+                .serialize(&mut cursor, &NoCipher, None)
+                .ok()?;
+            let length = cursor.position() as usize;
+            buffer[..length].to_vec()
+        } else {
+            let mut buffer = [0u8; MAX_PACKET_SIZE];
+            let recv_timestamp = recv_timestamp + NtpDuration::from_seconds(self.offset);
+            match server.handle(
+                client.ip(),
+                recv_timestamp,
+                request,
+                &mut buffer[..request.len()],
+                &mut IgnoreStats,
+            ) {
+                ServerAction::Ignore => return None,
+                ServerAction::Respond { message } => message.to_vec(),
+            }
+        };
+
+        if is_nts {
+            match self.nts {
+                Some(NtsBehavior::CorruptAuthenticator) if response.len() > HEADER_SIZE => {
+                    if let Some(last) = response.last_mut() {
+                        *last ^= 0xff;
+                    }
+                }
+                Some(NtsBehavior::Unauthenticated) => response.truncate(HEADER_SIZE),
+                _ => {}
+            }
+        }
+
+        self.damage(&mut response);
+
+        Some(response)
+    }
+
+    /// Apply the kiss code and broken fields to the header of a response
+    fn damage(&self, response: &mut Vec<u8>) {
+        if response.len() < HEADER_SIZE {
+            return;
+        }
+
+        if let Some(code) = self.kiss {
+            response[1] = 0;
+            response[12..16].copy_from_slice(&code);
+        }
+
+        for field in &self.broken {
+            match field {
+                BrokenField::OriginTimestamp => {
+                    let origin: [u8; 8] = rand::random();
+                    response[24..32].copy_from_slice(&origin);
+                }
+                BrokenField::TransmitTimestamp => response[40..48].fill(0),
+                BrokenField::Mode => response[0] = (response[0] & !0b111) | 3,
+                BrokenField::Version => response[0] |= 0b0011_1000,
+                BrokenField::RootDistance => response[4..12].fill(0xff),
+                BrokenField::Truncated => {}
+            }
+        }
+
+        // after the other fields, which would otherwise no longer be there
+        if self.broken.contains(&BrokenField::Truncated) {
+            response.truncate(HEADER_SIZE / 2);
+        }
+    }
+}
+
+pub fn main() -> std::io::Result<ExitCode> {
+    let options = match NtpTestServerOptions::try_parse_from(std::env::args()) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{msg}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    match options.action {
+        TestServerAction::Help => {
+            println!("{}", long_help_message());
+            Ok(ExitCode::SUCCESS)
+        }
+        TestServerAction::Version => {
+            eprintln!("ntp-test-server {VERSION}");
+            Ok(ExitCode::SUCCESS)
+        }
+        TestServerAction::Serve => {
+            let scenario = match load_scenario(&options.scenario.unwrap_or_default()) {
+                Ok(scenario) => scenario,
+                Err(e) => {
+                    eprintln!("Error: Could not load scenario: {e}");
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+
+            tracing_init(LogLevel::Info, true).init();
+
+            Builder::new_current_thread()
+                .enable_all()
+                .build()?
+                .block_on(serve(scenario))?;
+
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+fn load_scenario(path: &Path) -> std::io::Result<Scenario> {
+    let contents = std::fs::read_to_string(path)?;
+    toml::from_str(&contents).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+async fn serve(scenario: Scenario) -> std::io::Result<()> {
+    // keys for the cookies handed out by the key exchange servers
+    let (keyset, _key_rotation) = nts_key_provider::spawn(KeysetConfig::default()).await;
+
+    #[cfg(feature = "nts-server")]
+    for nts_ke_config in scenario.nts_ke {
+        let _join_handle = keyexchange::spawn(
+            nts_ke_config,
+            keyset.clone(),
+            CryptoPolicyConfig::default().aead_algorithms,
+        );
+    }
+
+    let mut socket = open_ip(scenario.listen, GeneralTimestampMode::SoftwareRecv)?;
+    info!(listen = ?scenario.listen, "serving scenario");
+
+    let mut script = Script::new(scenario.responses, scenario.repeat);
+    let mut current_server: Option<(usize, Server<_>)> = None;
+
+    let mut buf = [0u8; MAX_PACKET_SIZE];
+    loop {
+        let (length, client, recv_timestamp) = match socket.recv(&mut buf).await {
+            Ok(RecvResult {
+                bytes_read,
+                remote_addr,
+                timestamp: Some(timestamp),
+            }) => (bytes_read, remote_addr, convert_net_timestamp(timestamp)),
+            Ok(_) => {
+                debug!("received a packet without a timestamp");
+                continue;
+            }
+            Err(error) => {
+                warn!(?error, "could not receive packet");
+                continue;
+            }
+        };
+
+        let keys = keyset.borrow().clone();
+        let (index, response_config) = script.next_response();
+        // a fresh server for every configured response, so it serves the configured time
+        if current_server
+            .as_ref()
+            .is_none_or(|(current, _)| *current != index)
+        {
+            current_server = Some((index, response_config.server(keys.clone())));
+        }
+        let (_, server) = current_server.as_mut().expect("server was just created");
+        server.update_keyset(keys.clone());
+
+        let Some(response) =
+            response_config.respond(server, &keys, client, recv_timestamp, &buf[..length])
+        else {
+            debug!(?client, response = index, "not answering");
+            continue;
+        };
+
+        if response_config.delay > 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(response_config.delay)).await;
+        }
+
+        debug!(?client, response = index, "answering");
+        if let Err(error) = socket.send_to(&response, client).await {
+            warn!(?error, "could not send response");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINARY: &str = "/usr/bin/ntp-test-server";
+
+    #[test]
+    fn cli_scenario() {
+        let arguments = &[BINARY, "scenario.toml"];
+        let options = NtpTestServerOptions::try_parse_from(arguments).unwrap();
+        assert_eq!(options.action, TestServerAction::Serve);
+        assert_eq!(
+            options.scenario.as_deref(),
+            Some(Path::new("scenario.toml"))
+        );
+
+        let options = NtpTestServerOptions::try_parse_from([BINARY]).unwrap();
+        assert_eq!(options.action, TestServerAction::Help);
+    }
+
+    #[test]
+    fn test_scenario() {
+        let scenario: Scenario = toml::from_str(
+            r#"
+            listen = "127.0.0.1:1230"
+            repeat = true
+
+            [[response]]
+            count = 2
+            offset = 0.5
+
+            [[response]]
+            count = 1
+            kiss = "RATE"
+            broken = ["origin-timestamp", "truncated"]
+            nts = "nak"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.listen, "127.0.0.1:1230".parse().unwrap());
+        assert_eq!(scenario.responses[0].offset, 0.5);
+        assert_eq!(scenario.responses[1].kiss, Some(*b"RATE"));
+        assert_eq!(scenario.responses[1].nts, Some(NtsBehavior::Nak));
+
+        let mut script = Script::new(scenario.responses, scenario.repeat);
+        let order: Vec<_> = (0..7).map(|_| script.next_response().0).collect();
+        assert_eq!(order, [0, 0, 1, 0, 0, 1, 0]);
+
+        let err = toml::from_str::<Scenario>("[[response]]\nkiss = \"RATELIMIT\"").unwrap_err();
+        assert!(err.to_string().contains("four ASCII characters"));
+    }
+
+    #[test]
+    fn test_script_keeps_last_response() {
+        let mut script = Script::new(
+            vec![
+                ResponseConfig {
+                    count: Some(1),
+                    ..ResponseConfig::default()
+                },
+                ResponseConfig {
+                    count: Some(1),
+                    drop: true,
+                    ..ResponseConfig::default()
+                },
+            ],
+            false,
+        );
+        let order: Vec<_> = (0..4).map(|_| script.next_response().0).collect();
+        assert_eq!(order, [0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_damage() {
+        let mut response = vec![0b0010_0100; HEADER_SIZE];
+        ResponseConfig {
+            kiss: Some(*b"DENY"),
+            broken: vec![BrokenField::Mode, BrokenField::TransmitTimestamp],
+            ..ResponseConfig::default()
+        }
+        .damage(&mut response);
+
+        assert_eq!(response[0] & 0b111, 3);
+        assert_eq!(response[1], 0);
+        assert_eq!(&response[12..16], b"DENY");
+        assert_eq!(&response[40..48], &[0; 8]);
+
+        ResponseConfig {
+            broken: vec![BrokenField::Truncated],
+            ..ResponseConfig::default()
+        }
+        .damage(&mut response);
+        assert_eq!(response.len(), HEADER_SIZE / 2);
+    }
+}
@@ -88,6 +88,7 @@ count = 4
         &result.stdout,
         "+0.014375±0.005806(±0.034586)s".as_bytes()
     ));
+    assert!(contains_bytes(&result.stdout, b"Daemon: version 1.5.0"));
     assert_eq!(result.status.code(), Some(0));
 }
 
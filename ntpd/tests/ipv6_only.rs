@@ -0,0 +1,155 @@
+#![cfg(target_os = "linux")]
+
+use std::{
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const CARGO_TARGET_TMPDIR: &str = env!("CARGO_TARGET_TMPDIR");
+
+/// The port the server listens on, inside the namespace it is not in use
+const PORT: u16 = 12300;
+
+/// Stops the daemons when the test ends, also when it fails. Both run in a
+/// PID namespace of which the shell is the init process, so they are killed
+/// with it when `unshare` is.
+struct Namespace(Child);
+
+impl Drop for Namespace {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Whether network namespaces can be created by the user running the tests
+fn have_namespaces() -> bool {
+    Command::new("unshare")
+        .args(["-rn", "ip", "link", "set", "lo", "up"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn write_config(name: &str, contents: &str) -> String {
+    let path = format!("{CARGO_TARGET_TMPDIR}/ipv6_only_{name}_config");
+    std::fs::write(&path, contents.as_bytes()).unwrap();
+    path
+}
+
+/// The sources the daemon using `config` reports, as JSON
+fn sources(config: &str) -> Vec<serde_json::Value> {
+    let output = Command::new(env!("CARGO_BIN_EXE_ntp-ctl"))
+        .args(["status", "-f", "json", "-c", config])
+        .output()
+        .unwrap();
+    serde_json::from_slice::<serde_json::Value>(&output.stdout)
+        .ok()
+        .and_then(|state| state["sources"].as_array().cloned())
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_ipv6_only_network() {
+    if !have_namespaces() {
+        eprintln!("skipped, network namespaces are not available");
+        return;
+    }
+
+    let server_observation = format!("{CARGO_TARGET_TMPDIR}/ipv6_only_server_observation");
+    let client_observation = format!("{CARGO_TARGET_TMPDIR}/ipv6_only_client_observation");
+    let _ = std::fs::remove_file(&server_observation);
+    let _ = std::fs::remove_file(&client_observation);
+
+    let server_config = write_config(
+        "server",
+        &format!(
+            r#"[observability]
+observation-path = "{server_observation}"
+
+[synchronization]
+local-stratum = 1
+
+[[server]]
+listen = "[::]:{PORT}"
+"#
+        ),
+    );
+
+    // the IPv4 source is reached at the address embedding it in the NAT64
+    // prefix, which is given to the loopback interface instead of a gateway
+    let client_config = write_config(
+        "client",
+        &format!(
+            r#"[observability]
+observation-path = "{client_observation}"
+
+[synchronization]
+prober = true
+nat64-prefix = "64:ff9b::/96"
+
+[[source]]
+mode = "server"
+address = "[::1]:{PORT}"
+
+[[source]]
+mode = "server"
+address = "192.0.2.33:{PORT}"
+"#
+        ),
+    );
+
+    let daemon = env!("CARGO_BIN_EXE_ntp-daemon");
+    let script = format!(
+        "ip link set lo up \
+        && ip addr del 127.0.0.1/8 dev lo \
+        && ip addr add 64:ff9b::c000:221/128 dev lo \
+        && {{ {daemon} -c {server_config} & exec {daemon} -c {client_config}; }}"
+    );
+    let mut namespace = Namespace(
+        Command::new("unshare")
+            .args([
+                "-rn",
+                "--pid",
+                "--fork",
+                "--kill-child",
+                "sh",
+                "-c",
+                &script,
+            ])
+            .spawn()
+            .unwrap(),
+    );
+
+    // the observation sockets are in the file system, so they can be reached
+    // from outside the network namespace
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let expected = [
+        format!("[::1]:{PORT}"),
+        format!("[64:ff9b::c000:221]:{PORT}"),
+    ];
+    loop {
+        assert!(namespace.0.try_wait().unwrap().is_none(), "daemons stopped");
+
+        let reached: Vec<_> = if Path::new(&client_observation).exists() {
+            sources(&client_config)
+                .iter()
+                .filter(|source| source["reach"].as_u64().is_some_and(|reach| reach != 0))
+                .filter_map(|source| source["address"].as_str().map(String::from))
+                .collect()
+        } else {
+            vec![]
+        };
+        if expected.iter().all(|addr| reached.contains(addr)) {
+            break;
+        }
+
+        assert!(
+            Instant::now() < deadline,
+            "sources not reached in time, only {reached:?}"
+        );
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,102 @@
+#![cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+
+use std::{
+    net::UdpSocket,
+    path::Path,
+    process::{Child, Command},
+    time::{Duration, Instant},
+};
+
+const CARGO_TARGET_TMPDIR: &str = env!("CARGO_TARGET_TMPDIR");
+
+/// Stops the daemon when the test ends, also when it fails
+struct Daemon(Child);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+#[test]
+fn test_daemon_runs_with_syscall_filter() {
+    // the daemon can only switch to another user when started as root, so it
+    // runs as the user running the tests
+    let user = nix::unistd::User::from_uid(nix::unistd::geteuid())
+        .unwrap()
+        .unwrap()
+        .name;
+    let port = free_port();
+    let observation_path = format!("{CARGO_TARGET_TMPDIR}/sandbox_test_observation");
+    let _ = std::fs::remove_file(&observation_path);
+
+    let test_config_contents = format!(
+        r#"[observability]
+observation-path = "{observation_path}"
+
+[sandbox]
+level = "seccomp"
+user = "{user}"
+
+[[server]]
+listen = "127.0.0.1:{port}"
+"#
+    );
+    let test_config_path = format!("{CARGO_TARGET_TMPDIR}/sandbox_test_config");
+    std::fs::write(&test_config_path, test_config_contents.as_bytes()).unwrap();
+
+    let mut daemon = Daemon(
+        Command::new(env!("CARGO_BIN_EXE_ntp-daemon"))
+            .args(["-c", &test_config_path])
+            .spawn()
+            .unwrap(),
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !Path::new(&observation_path).exists() {
+        assert!(
+            daemon.0.try_wait().unwrap().is_none(),
+            "daemon stopped while starting"
+        );
+        assert!(Instant::now() < deadline, "daemon did not start in time");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    // the observation socket
+    let status = Command::new(env!("CARGO_BIN_EXE_ntp-ctl"))
+        .args(["status", "-c", &test_config_path])
+        .output()
+        .unwrap();
+    assert_eq!(status.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&status.stdout).contains("Daemon: version"));
+
+    // the server
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut request = [0u8; 48];
+    request[0] = 0x23; // version 4, client mode
+    request[40..].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+    client.send_to(&request, ("127.0.0.1", port)).unwrap();
+
+    let mut response = [0u8; 1024];
+    let (length, _) = client.recv_from(&mut response).unwrap();
+    assert_eq!(length, 48);
+    assert_eq!(response[0] & 0x7, 4); // server mode
+    assert_eq!(response[24..32], request[40..]);
+
+    assert!(daemon.0.try_wait().unwrap().is_none(), "daemon stopped");
+}